@@ -0,0 +1,339 @@
+use crate::builder::{AsmProgram, AsmSection, AsmSize, FReg, Reg};
+use crate::gas::data_value;
+
+// The generic registers are the AArch64 ones in encoding order, the last
+// one being the stack pointer
+pub const X0: Reg = Reg::R0;
+pub const X1: Reg = Reg::R1;
+pub const X2: Reg = Reg::R2;
+pub const X3: Reg = Reg::R3;
+pub const X4: Reg = Reg::R4;
+pub const X5: Reg = Reg::R5;
+pub const X6: Reg = Reg::R6;
+pub const X7: Reg = Reg::R7;
+pub const X8: Reg = Reg::R8;
+pub const X9: Reg = Reg::R9;
+pub const X10: Reg = Reg::R10;
+pub const X11: Reg = Reg::R11;
+pub const X12: Reg = Reg::R12;
+pub const X13: Reg = Reg::R13;
+pub const X14: Reg = Reg::R14;
+pub const X15: Reg = Reg::R15;
+pub const X16: Reg = Reg::R16;
+pub const X17: Reg = Reg::R17;
+pub const X18: Reg = Reg::R18;
+pub const X19: Reg = Reg::R19;
+pub const X20: Reg = Reg::R20;
+pub const X21: Reg = Reg::R21;
+pub const X22: Reg = Reg::R22;
+pub const X23: Reg = Reg::R23;
+pub const X24: Reg = Reg::R24;
+pub const X25: Reg = Reg::R25;
+pub const X26: Reg = Reg::R26;
+pub const X27: Reg = Reg::R27;
+pub const X28: Reg = Reg::R28;
+/// The frame pointer
+pub const FP: Reg = Reg::R29;
+/// The link register, holding the return address
+pub const LR: Reg = Reg::R30;
+pub const SP: Reg = Reg::R31;
+
+/// An instruction of the AArch64 subset the backends use.
+///
+/// The integer instructions work on the `x` registers when they are of a
+/// `Qword` and on the `w` ones otherwise, and the float ones on the `d`
+/// registers when they are of a `Qword` and on the `s` ones otherwise.
+/// `Ldr` and `Str` access as many bytes as their size, and `Ldrs`
+/// sign-extends them to 64 bits. The `Ldur` ones take an unscaled offset.
+/// `Sxt` sign-extends the low bytes of a register, of the size of the
+/// instruction, and `Fcvt` converts a single to a double.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Mov,
+    Movz,
+    Movn,
+    Movk,
+    Add,
+    Sub,
+    Mul,
+    Sxt,
+    Cmp,
+    Ldr,
+    Ldrs,
+    Ldur,
+    Ldurs,
+    Str,
+    Stur,
+    Ldp,
+    Stp,
+    Adrp,
+    B,
+    Beq,
+    Bne,
+    Bl,
+    Ret,
+    Brk,
+    Fmov,
+    Fadd,
+    Fsub,
+    Fcmp,
+    Fcvt,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Reg(Reg),
+    FReg(FReg),
+    /// The memory at a register plus an offset
+    Mem(Reg, i64),
+    /// The memory at a register plus another register
+    Index(Reg, Reg),
+    /// The memory at a register plus an offset, the register being set to
+    /// that address first
+    PreIndex(Reg, i64),
+    /// The memory at a register, which is offset afterwards
+    PostIndex(Reg, i64),
+    Imm(i64),
+    /// The immediate of a `fmov`
+    FImm(f64),
+    /// A left shift of the operand before
+    Lsl(u8),
+    /// The 4KB page a symbol is in, for `adrp`
+    Page(String),
+    /// The offset of a symbol in its page
+    PageOff(String),
+    Label(String),
+}
+
+/// An instruction. Its operands are in assembly order, the destination
+/// first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    pub(crate) op: Op,
+    pub(crate) operands: Vec<Operand>,
+    pub(crate) size: AsmSize,
+}
+
+impl Inst {
+    pub fn new(op: Op, operands: Vec<Operand>) -> Self {
+        Self::sized(op, AsmSize::Qword, operands)
+    }
+
+    pub fn sized(op: Op, size: AsmSize, operands: Vec<Operand>) -> Self {
+        Self { op, operands, size }
+    }
+}
+
+/// Whether `imm` is an immediate of `add`, `sub` and `cmp`: 12 bits,
+/// shifted left by 12 or not
+pub fn is_arith_imm(imm: i64) -> bool {
+    (0..4096).contains(&imm) || (imm & 0xfff == 0 && (0..4096).contains(&(imm >> 12)))
+}
+
+/// Whether `offset` is an offset of a `ldr` or a `str` of `size` bytes: 12
+/// unsigned bits, scaled by the size
+pub fn is_scaled_offset(offset: i64, size: u64) -> bool {
+    let size = size as i64;
+    offset >= 0 && offset % size == 0 && offset / size < 4096
+}
+
+/// Whether `offset` is an offset of a `ldur` or a `stur`: 9 signed bits
+pub fn is_unscaled_offset(offset: i64) -> bool {
+    (-256..256).contains(&offset)
+}
+
+/// Whether `v` is an immediate of `fmov`: a sign, 4 bits of fraction and
+/// 3 of exponent, from 0.125 to 31
+pub fn is_fmov_imm(v: f64) -> bool {
+    (-3..=4).any(|exp| {
+        let n = v.abs() * 16.0 / 2f64.powi(exp);
+        (16.0..=31.0).contains(&n) && n.fract() == 0.0
+    })
+}
+
+/// How the symbols and the sections of a program are written, for the
+/// objects of its system
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flavor {
+    /// ELF, on Linux and the other Unixes
+    Elf,
+    /// Mach-O, on macOS and iOS, where the C symbols take a leading
+    /// underscore
+    MachO,
+}
+
+/// The name `name` is written with. The local labels, starting with `.L`,
+/// start with `L` on Mach-O.
+pub fn symbol(name: &str, flavor: Flavor) -> String {
+    match flavor {
+        Flavor::Elf => name.to_string(),
+        Flavor::MachO => match name.strip_prefix(".L") {
+            Some(local) => format!("L{}", local),
+            None => format!("_{}", name),
+        },
+    }
+}
+
+/// The name of `reg` used with operands of `size`
+pub fn reg_name(reg: Reg, size: AsmSize) -> String {
+    match (reg, size) {
+        (SP, AsmSize::Qword) => "sp".to_string(),
+        (SP, _) => "wsp".to_string(),
+        (reg, AsmSize::Qword) => format!("x{}", reg as usize),
+        (reg, _) => format!("w{}", reg as usize),
+    }
+}
+
+pub fn freg_name(reg: FReg, size: AsmSize) -> String {
+    match size {
+        AsmSize::Qword => format!("d{}", reg as usize),
+        _ => format!("s{}", reg as usize),
+    }
+}
+
+fn operand(operand: &Operand, size: AsmSize, fsize: AsmSize, flavor: Flavor) -> String {
+    let base = |reg: &Reg| reg_name(*reg, AsmSize::Qword);
+    match operand {
+        Operand::Reg(reg) => reg_name(*reg, size),
+        Operand::FReg(reg) => freg_name(*reg, fsize),
+        Operand::Mem(reg, 0) => format!("[{}]", base(reg)),
+        Operand::Mem(reg, offset) => format!("[{}, #{}]", base(reg), offset),
+        Operand::Index(reg, index) => format!("[{}, {}]", base(reg), base(index)),
+        Operand::PreIndex(reg, offset) => format!("[{}, #{}]!", base(reg), offset),
+        Operand::PostIndex(reg, offset) => format!("[{}], #{}", base(reg), offset),
+        Operand::Imm(imm) => format!("#{}", imm),
+        Operand::FImm(v) => format!("#{:?}", v),
+        Operand::Lsl(shift) => format!("lsl #{}", shift),
+        Operand::Page(name) => match flavor {
+            Flavor::Elf => symbol(name, flavor),
+            Flavor::MachO => format!("{}@PAGE", symbol(name, flavor)),
+        },
+        Operand::PageOff(name) => match flavor {
+            Flavor::Elf => format!(":lo12:{}", symbol(name, flavor)),
+            Flavor::MachO => format!("{}@PAGEOFF", symbol(name, flavor)),
+        },
+        Operand::Label(name) => symbol(name, flavor),
+    }
+}
+
+/// The mnemonic of a load or a store of `size` bytes to a general purpose
+/// register, from the one of a whole register
+fn access(base: &str, size: AsmSize) -> String {
+    match size {
+        AsmSize::Byte => format!("{}b", base),
+        AsmSize::Word => format!("{}h", base),
+        AsmSize::Dword if base.ends_with('s') => format!("{}w", base),
+        _ => base.to_string(),
+    }
+}
+
+fn mnemonic(inst: &Inst) -> String {
+    let float = matches!(inst.operands.first(), Some(Operand::FReg(_)));
+    match inst.op {
+        Op::Mov => "mov".to_string(),
+        Op::Movz => "movz".to_string(),
+        Op::Movn => "movn".to_string(),
+        Op::Movk => "movk".to_string(),
+        Op::Add => "add".to_string(),
+        Op::Sub => "sub".to_string(),
+        Op::Mul => "mul".to_string(),
+        Op::Sxt => match inst.size {
+            AsmSize::Byte => "sxtb".to_string(),
+            AsmSize::Word => "sxth".to_string(),
+            _ => "sxtw".to_string(),
+        },
+        Op::Cmp => "cmp".to_string(),
+        // A float register is accessed whole
+        Op::Ldr if float => "ldr".to_string(),
+        Op::Ldur if float => "ldur".to_string(),
+        Op::Str if float => "str".to_string(),
+        Op::Stur if float => "stur".to_string(),
+        Op::Ldr => access("ldr", inst.size),
+        Op::Ldrs => access("ldrs", inst.size),
+        Op::Ldur => access("ldur", inst.size),
+        Op::Ldurs => access("ldurs", inst.size),
+        Op::Str => access("str", inst.size),
+        Op::Stur => access("stur", inst.size),
+        Op::Ldp => "ldp".to_string(),
+        Op::Stp => "stp".to_string(),
+        Op::Adrp => "adrp".to_string(),
+        Op::B => "b".to_string(),
+        Op::Beq => "b.eq".to_string(),
+        Op::Bne => "b.ne".to_string(),
+        Op::Bl => "bl".to_string(),
+        Op::Ret => "ret".to_string(),
+        Op::Brk => "brk".to_string(),
+        Op::Fmov => "fmov".to_string(),
+        Op::Fadd => "fadd".to_string(),
+        Op::Fsub => "fsub".to_string(),
+        Op::Fcmp => "fcmp".to_string(),
+        Op::Fcvt => "fcvt".to_string(),
+    }
+}
+
+/// One instruction in GNU syntax, without indentation
+pub fn print_inst(inst: &Inst, flavor: Flavor) -> String {
+    let operands: Vec<String> = inst
+        .operands
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            // The sizes of the general purpose and of the float registers
+            let (size, fsize) = match inst.op {
+                // A sign extension writes a whole register
+                Op::Ldrs | Op::Ldurs => (AsmSize::Qword, inst.size),
+                Op::Sxt if i == 0 => (AsmSize::Qword, inst.size),
+                Op::Sxt => (AsmSize::Dword, inst.size),
+                // Bytes and halves are loaded to and stored from a `w`
+                Op::Ldr | Op::Ldur | Op::Str | Op::Stur if inst.size != AsmSize::Qword => {
+                    (AsmSize::Dword, inst.size)
+                }
+                Op::Fcvt if i == 0 => (inst.size, AsmSize::Qword),
+                Op::Fcvt => (inst.size, AsmSize::Dword),
+                Op::Adrp => (AsmSize::Qword, inst.size),
+                _ => (inst.size, inst.size),
+            };
+            operand(arg, size, fsize, flavor)
+        })
+        .collect();
+    let mnemonic = mnemonic(inst);
+    if operands.is_empty() {
+        mnemonic
+    } else {
+        format!("{} {}", mnemonic, operands.join(", "))
+    }
+}
+
+/// The program as GNU assembler source, for objects of `flavor`
+pub fn print(program: &AsmProgram<Inst>, flavor: Flavor) -> String {
+    let mut out = String::from("\t.text\n");
+    for label in &program.labels {
+        let name = symbol(&label.label, flavor);
+        if label.global {
+            out.push_str(&format!("\t.globl {}\n", name));
+            if flavor == Flavor::Elf {
+                out.push_str(&format!("\t.type {}, %function\n", name));
+            }
+        }
+        out.push_str(&format!("{}:\n", name));
+        for inst in &label.asm {
+            out.push_str(&format!("\t{}\n", print_inst(inst, flavor)));
+        }
+    }
+    for data in &program.data {
+        match (data.section, flavor) {
+            (AsmSection::Data, _) => out.push_str("\t.data\n"),
+            (AsmSection::Rodata, Flavor::Elf) => out.push_str("\t.section .rodata\n"),
+            (AsmSection::Rodata, Flavor::MachO) => out.push_str("\t.section __TEXT,__const\n"),
+        }
+        out.push_str(&format!("\t.balign {}\n", data.align.max(1)));
+        out.push_str(&format!("{}:\n", symbol(&data.label, flavor)));
+        for line in data.data.iter().flat_map(data_value) {
+            out.push_str(&format!("\t{}\n", line));
+        }
+    }
+    if flavor == Flavor::Elf {
+        out.push_str("\t.section .note.GNU-stack,\"\",%progbits\n");
+    }
+    out
+}
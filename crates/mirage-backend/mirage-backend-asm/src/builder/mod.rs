@@ -1,32 +1,75 @@
 
+/// The section some data is put in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsmSection {
+    Data,
+    Rodata,
+}
 
 #[derive(Debug, Clone)]
 pub struct AsmData {
-    label: String,
-    data_type: AsmDataType,
-    data: AsmDataValue,
+    pub(crate) label: String,
+    pub(crate) section: AsmSection,
+    pub(crate) align: u64,
+    pub(crate) data: Vec<AsmDataValue>,
 }
 
-#[derive(Debug, Clone)]
-pub enum AsmDataType {
-    Byte,
-    Word,
-    Dword,
-    Qword,
-    String,
+impl AsmData {
+    pub fn new(label: String, section: AsmSection, align: u64, data: Vec<AsmDataValue>) -> Self {
+        Self {
+            label,
+            section,
+            align,
+            data,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AsmDataValue {
     Byte(u8),
+    /// A NUL terminated string
     String(String),
     Word(u16),
     Dword(u32),
     Qword(u64),
+    Bytes(Vec<u8>),
+}
+
+/// The size of the operands of an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsmSize {
+    Byte,
+    Word,
+    Dword,
+    Qword,
 }
 
+impl AsmSize {
+    pub fn from_bytes(bytes: u64) -> Self {
+        match bytes {
+            1 => AsmSize::Byte,
+            2 => AsmSize::Word,
+            4 => AsmSize::Dword,
+            _ => AsmSize::Qword,
+        }
+    }
 
-#[derive(Debug, Clone, Copy)]
+    pub fn bytes(&self) -> u64 {
+        match self {
+            AsmSize::Byte => 1,
+            AsmSize::Word => 2,
+            AsmSize::Dword => 4,
+            AsmSize::Qword => 8,
+        }
+    }
+}
+
+/// An instruction of the x86-64 subset the backends use. `Mul` and `Div`
+/// are signed, `Movsx` sign-extends its source, of the size of the
+/// instruction, to 64 bits, `Movzx` zero-extends a byte or a word, and the
+/// `ss`/`sd` ones work on the `FReg`s.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AsmCommand {
     Mov,
     Add,
@@ -34,34 +77,69 @@ pub enum AsmCommand {
     Mul,
     Div,
     Jmp,
-    Call
+    Call,
+    Movsx,
+    Movzx,
+    Lea,
+    Cmp,
+    Je,
+    Jne,
+    Jp,
+    Push,
+    Pop,
+    Leave,
+    Ret,
+    Ud2,
+    Movss,
+    Movsd,
+    Addss,
+    Addsd,
+    Subss,
+    Subsd,
+    Ucomiss,
+    Ucomisd,
+    Cvtss2sd,
 }
 
-#[derive(Debug, Clone)]
+/// An instruction. Its arguments are in Intel order, the destination
+/// first.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Asm {
-    asm_op: AsmCommand,
-    asm_arg: Vec<AsmArg>,
+    pub(crate) asm_op: AsmCommand,
+    pub(crate) asm_arg: Vec<AsmArg>,
+    pub(crate) size: AsmSize,
 }
 
 impl Asm {
     pub fn new(asm_op: AsmCommand, asm_arg: Vec<AsmArg>) -> Self {
+        Self::sized(asm_op, AsmSize::Qword, asm_arg)
+    }
+
+    pub fn sized(asm_op: AsmCommand, size: AsmSize, asm_arg: Vec<AsmArg>) -> Self {
         Self {
             asm_op,
             asm_arg,
+            size,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AsmArg {
     Reg(Reg),
+    FReg(FReg),
+    /// The memory at a register plus a displacement
     Mem(Reg, i64),
+    /// The memory at a symbol
+    Sym(String),
     Imm(i64),
     Label(String),
     None,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// A general purpose register. x86-64 has the first sixteen, AArch64 all
+/// of them, the last one being its stack pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(usize)]
 pub enum Reg {
     R0,
@@ -79,7 +157,23 @@ pub enum Reg {
     R12,
     R13,
     R14,
-    R15
+    R15,
+    R16,
+    R17,
+    R18,
+    R19,
+    R20,
+    R21,
+    R22,
+    R23,
+    R24,
+    R25,
+    R26,
+    R27,
+    R28,
+    R29,
+    R30,
+    R31,
 }
 
 impl Reg {
@@ -100,41 +194,171 @@ impl Reg {
             Reg::R12,
             Reg::R13,
             Reg::R14,
-            Reg::R15
+            Reg::R15,
+            Reg::R16,
+            Reg::R17,
+            Reg::R18,
+            Reg::R19,
+            Reg::R20,
+            Reg::R21,
+            Reg::R22,
+            Reg::R23,
+            Reg::R24,
+            Reg::R25,
+            Reg::R26,
+            Reg::R27,
+            Reg::R28,
+            Reg::R29,
+            Reg::R30,
+            Reg::R31,
+        ]
+    }
+}
+
+/// A floating point register. x86-64 has the first sixteen, AArch64 all of
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(usize)]
+pub enum FReg {
+    F0,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    F25,
+    F26,
+    F27,
+    F28,
+    F29,
+    F30,
+    F31,
+}
+
+impl FReg {
+    pub fn all() -> Vec<FReg> {
+        vec![
+            FReg::F0,
+            FReg::F1,
+            FReg::F2,
+            FReg::F3,
+            FReg::F4,
+            FReg::F5,
+            FReg::F6,
+            FReg::F7,
+            FReg::F8,
+            FReg::F9,
+            FReg::F10,
+            FReg::F11,
+            FReg::F12,
+            FReg::F13,
+            FReg::F14,
+            FReg::F15,
+            FReg::F16,
+            FReg::F17,
+            FReg::F18,
+            FReg::F19,
+            FReg::F20,
+            FReg::F21,
+            FReg::F22,
+            FReg::F23,
+            FReg::F24,
+            FReg::F25,
+            FReg::F26,
+            FReg::F27,
+            FReg::F28,
+            FReg::F29,
+            FReg::F30,
+            FReg::F31,
         ]
     }
 }
 
+/// A label and the instructions following it, of the x86-64 `Asm` unless
+/// another instruction set is given
 #[derive(Debug, Clone)]
-pub struct AsmLabel {
-    label: String,
-    asm: Vec<Asm>,
+pub struct AsmLabel<I = Asm> {
+    pub(crate) label: String,
+    pub(crate) asm: Vec<I>,
+    /// Whether the label is visible from other objects
+    pub(crate) global: bool,
 }
 
-impl AsmLabel {
+impl<I> AsmLabel<I> {
     pub fn new(label: String) -> Self {
         Self {
             label,
             asm: Vec::new(),
+            global: false,
         }
     }
 
-    pub fn builder(&self) -> AsmLabelBuilder {
-        AsmLabelBuilder::new(&self.label)
+    pub fn global(label: String) -> Self {
+        Self {
+            global: true,
+            ..Self::new(label)
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.label
     }
 
-    pub fn add_asm(&mut self, asm: Asm) {
+    pub fn is_global(&self) -> bool {
+        self.global
+    }
+
+    pub fn asm(&self) -> &[I] {
+        &self.asm
+    }
+
+    pub fn add_asm(&mut self, asm: I) {
         self.asm.push(asm);
     }
+
+    pub fn insert_asm(&mut self, index: usize, asm: I) {
+        self.asm.insert(index, asm);
+    }
+}
+
+impl AsmLabel {
+    pub fn builder(&self) -> AsmLabelBuilder {
+        AsmLabelBuilder::new(&self.label)
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct AsmProgram {
-    labels: Vec<AsmLabel>,
-    data: Vec<AsmData>,
+pub struct AsmProgram<I = Asm> {
+    pub(crate) labels: Vec<AsmLabel<I>>,
+    pub(crate) data: Vec<AsmData>,
 }
 
-impl AsmProgram {
+impl<I> Default for AsmProgram<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I> AsmProgram<I> {
     pub fn new() -> Self {
         Self {
             labels: Vec::new(),
@@ -142,7 +366,15 @@ impl AsmProgram {
         }
     }
 
-    pub fn add_label(&mut self, label: AsmLabel) {
+    pub fn labels(&self) -> &[AsmLabel<I>] {
+        &self.labels
+    }
+
+    pub fn data(&self) -> &[AsmData] {
+        &self.data
+    }
+
+    pub fn add_label(&mut self, label: AsmLabel<I>) {
         self.labels.push(label);
     }
 
@@ -152,22 +384,32 @@ impl AsmProgram {
 }
 
 #[derive(Debug, Clone)]
-pub struct AsmProgramBuilder {
-    program: AsmProgram,
+pub struct AsmProgramBuilder<I = Asm> {
+    program: AsmProgram<I>,
+}
+
+impl<I> Default for AsmProgramBuilder<I> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl AsmProgramBuilder {
+impl<I> AsmProgramBuilder<I> {
     pub fn new() -> Self {
         Self {
             program: AsmProgram::new()
         }
     }
 
-    pub fn build_label(&mut self, label: AsmLabel) {
+    pub fn build_label(&mut self, label: AsmLabel<I>) {
         self.program.add_label(label)
     }
 
-    pub fn build(self) -> AsmProgram {
+    pub fn build_data(&mut self, data: AsmData) {
+        self.program.add_data(data)
+    }
+
+    pub fn build(self) -> AsmProgram<I> {
         self.program
     }
 }
@@ -232,6 +474,10 @@ impl AsmLabelBuilder {
         );
     }
 
+    pub fn build_asm(&mut self, asm: Asm) {
+        self.label.add_asm(asm);
+    }
+
     pub fn build(self) -> AsmLabel {
         self.label.clone()
     }
@@ -0,0 +1,257 @@
+//! A writer of ELF64 relocatable objects for x86-64
+
+use std::collections::HashMap;
+
+use crate::object::{Object, RelocKind, RelocTarget, Section};
+
+const EM_X86_64: u16 = 62;
+const ET_REL: u16 = 1;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const R_X86_64_PC32: u64 = 2;
+const R_X86_64_PLT32: u64 = 4;
+
+/// The indices of the section headers
+const TEXT: u16 = 1;
+const DATA: u16 = 2;
+const RODATA: u16 = 3;
+const SYMTAB: u32 = 5;
+const STRTAB: u32 = 6;
+const SHSTRTAB: u16 = 7;
+const SECTIONS: u16 = 9;
+
+fn section_index(section: Section) -> u16 {
+    match section {
+        Section::Text => TEXT,
+        Section::Data => DATA,
+        Section::Rodata => RODATA,
+    }
+}
+
+/// A string table, which starts with an empty string
+struct StringTable(Vec<u8>);
+
+impl StringTable {
+    fn new() -> Self {
+        StringTable(vec![0])
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        let offset = self.0.len() as u32;
+        self.0.extend_from_slice(s.as_bytes());
+        self.0.push(0);
+        offset
+    }
+}
+
+struct SectionHeader {
+    name: u32,
+    ty: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+fn pad(out: &mut Vec<u8>, align: u64) {
+    while !(out.len() as u64).is_multiple_of(align.max(1)) {
+        out.push(0);
+    }
+}
+
+fn symbol(out: &mut Vec<u8>, name: u32, info: u8, shndx: u16, value: u64) {
+    out.extend_from_slice(&name.to_le_bytes());
+    out.push(info);
+    out.push(0);
+    out.extend_from_slice(&shndx.to_le_bytes());
+    out.extend_from_slice(&value.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+}
+
+/// `object` as an ELF relocatable file.
+///
+/// The symbols starting with `.L` are local to the assembly and left out,
+/// as GNU `as` does. The stack isn't executable.
+pub fn write(object: &Object) -> Vec<u8> {
+    let mut strtab = StringTable::new();
+    let mut symtab = Vec::new();
+    let mut indices: HashMap<&str, u32> = HashMap::new();
+    symbol(&mut symtab, 0, 0, 0, 0);
+    for section in [TEXT, DATA, RODATA] {
+        symbol(&mut symtab, 0, STB_LOCAL << 4 | STT_SECTION, section, 0);
+    }
+    let visible = object.symbols.iter().filter(|s| !s.name.starts_with(".L"));
+    // The local symbols come before the global ones
+    let (locals, globals): (Vec<_>, Vec<_>) = visible.partition(|s| !s.global);
+    let first_global = 4 + locals.len() as u32;
+    for (index, s) in (4..).zip(locals.into_iter().chain(globals)) {
+        let name = strtab.add(&s.name);
+        let bind = if s.global { STB_GLOBAL } else { STB_LOCAL };
+        let ty = if s.function { STT_FUNC } else { STT_NOTYPE };
+        let shndx = s.section.map_or(0, section_index);
+        symbol(&mut symtab, name, bind << 4 | ty, shndx, s.offset);
+        indices.insert(&s.name, index);
+    }
+
+    let mut rela = Vec::new();
+    for r in &object.relocations {
+        let sym = match &r.target {
+            RelocTarget::Section(section) => section_index(*section) as u32,
+            RelocTarget::Symbol(name) => indices[name.as_str()],
+        };
+        let ty = match r.kind {
+            RelocKind::Pc32 => R_X86_64_PC32,
+            RelocKind::Plt32 => R_X86_64_PLT32,
+        };
+        rela.extend_from_slice(&r.offset.to_le_bytes());
+        rela.extend_from_slice(&((sym as u64) << 32 | ty).to_le_bytes());
+        rela.extend_from_slice(&r.addend.to_le_bytes());
+    }
+
+    let mut shstrtab = StringTable::new();
+    let mut out = vec![0; 64];
+    let mut headers = Vec::new();
+    let mut add = |out: &mut Vec<u8>, name: &str, ty, flags, bytes: &[u8], align, extra| {
+        pad(out, align);
+        let (link, info, entsize) = extra;
+        headers.push(SectionHeader {
+            name: shstrtab.add(name),
+            ty,
+            flags,
+            offset: out.len() as u64,
+            size: bytes.len() as u64,
+            link,
+            info,
+            align,
+            entsize,
+        });
+        out.extend_from_slice(bytes);
+    };
+    let alloc = SHF_ALLOC;
+    add(
+        &mut out,
+        ".text",
+        SHT_PROGBITS,
+        alloc | SHF_EXECINSTR,
+        &object.text,
+        16,
+        (0, 0, 0),
+    );
+    add(
+        &mut out,
+        ".data",
+        SHT_PROGBITS,
+        alloc | SHF_WRITE,
+        &object.data,
+        object.data_align,
+        (0, 0, 0),
+    );
+    add(
+        &mut out,
+        ".rodata",
+        SHT_PROGBITS,
+        alloc,
+        &object.rodata,
+        object.rodata_align,
+        (0, 0, 0),
+    );
+    add(
+        &mut out,
+        ".rela.text",
+        SHT_RELA,
+        SHF_INFO_LINK,
+        &rela,
+        8,
+        (SYMTAB, TEXT as u32, 24),
+    );
+    add(
+        &mut out,
+        ".symtab",
+        SHT_SYMTAB,
+        0,
+        &symtab,
+        8,
+        (STRTAB, first_global, 24),
+    );
+    add(&mut out, ".strtab", SHT_STRTAB, 0, &strtab.0, 1, (0, 0, 0));
+    // The name of the section names is added before they are written
+    let shstrtab_name = shstrtab.add(".shstrtab");
+    let note_name = shstrtab.add(".note.GNU-stack");
+    pad(&mut out, 1);
+    headers.push(SectionHeader {
+        name: shstrtab_name,
+        ty: SHT_STRTAB,
+        flags: 0,
+        offset: out.len() as u64,
+        size: shstrtab.0.len() as u64,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+    out.extend_from_slice(&shstrtab.0);
+    headers.push(SectionHeader {
+        name: note_name,
+        ty: SHT_PROGBITS,
+        flags: 0,
+        offset: out.len() as u64,
+        size: 0,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+
+    pad(&mut out, 8);
+    let shoff = out.len() as u64;
+    out.extend_from_slice(&[0; 64]);
+    for h in &headers {
+        out.extend_from_slice(&h.name.to_le_bytes());
+        out.extend_from_slice(&h.ty.to_le_bytes());
+        out.extend_from_slice(&h.flags.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&h.offset.to_le_bytes());
+        out.extend_from_slice(&h.size.to_le_bytes());
+        out.extend_from_slice(&h.link.to_le_bytes());
+        out.extend_from_slice(&h.info.to_le_bytes());
+        out.extend_from_slice(&h.align.to_le_bytes());
+        out.extend_from_slice(&h.entsize.to_le_bytes());
+    }
+
+    let mut header = Vec::with_capacity(64);
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&ET_REL.to_le_bytes());
+    header.extend_from_slice(&EM_X86_64.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.extend_from_slice(&0u64.to_le_bytes()); // entry
+    header.extend_from_slice(&0u64.to_le_bytes()); // program headers
+    header.extend_from_slice(&shoff.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes()); // flags
+    header.extend_from_slice(&64u16.to_le_bytes()); // header size
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&64u16.to_le_bytes()); // section header size
+    header.extend_from_slice(&SECTIONS.to_le_bytes());
+    header.extend_from_slice(&SHSTRTAB.to_le_bytes());
+    out[..64].copy_from_slice(&header);
+    out
+}
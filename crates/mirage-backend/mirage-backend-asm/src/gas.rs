@@ -0,0 +1,32 @@
+use crate::builder::AsmDataValue;
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    out
+}
+
+/// The directives emitting `value`, one per line
+pub(crate) fn data_value(value: &AsmDataValue) -> Vec<String> {
+    match value {
+        AsmDataValue::Byte(b) => vec![format!(".byte {}", b)],
+        AsmDataValue::Word(w) => vec![format!(".short {}", w)],
+        AsmDataValue::Dword(d) => vec![format!(".long {}", d)],
+        AsmDataValue::Qword(q) => vec![format!(".quad {}", q)],
+        AsmDataValue::String(s) => vec![format!(".asciz \"{}\"", escape(s))],
+        AsmDataValue::Bytes(bytes) => bytes
+            .chunks(16)
+            .map(|chunk| {
+                let chunk: Vec<String> = chunk.iter().map(|b| b.to_string()).collect();
+                format!(".byte {}", chunk.join(", "))
+            })
+            .collect(),
+    }
+}
@@ -1 +1,10 @@
+#[cfg(test)]
+mod test;
+
+pub mod aarch64;
 pub mod builder;
+pub mod elf;
+mod gas;
+pub mod object;
+pub mod riscv64;
+pub mod x86_64;
@@ -0,0 +1,79 @@
+use crate::elf;
+
+/// A section of an object file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Section {
+    Text,
+    Data,
+    Rodata,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// The section the symbol is defined in, or `None` when another
+    /// object defines it
+    pub section: Option<Section>,
+    pub offset: u64,
+    pub global: bool,
+    pub function: bool,
+}
+
+/// How the linker computes a relocated value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    /// The 32-bit distance from the place to the symbol
+    Pc32,
+    /// The same, through the procedure linkage table when the symbol is a
+    /// function of a shared library
+    Plt32,
+}
+
+/// What a relocation refers to: a symbol of another object, or the start
+/// of a section of this one
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelocTarget {
+    Symbol(String),
+    Section(Section),
+}
+
+/// A place of `.text` the linker fills in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub offset: u64,
+    pub target: RelocTarget,
+    pub kind: RelocKind,
+    pub addend: i64,
+}
+
+/// An encoded program: the content of its sections, its symbols and what
+/// is left for the linker to resolve
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Object {
+    pub text: Vec<u8>,
+    pub data: Vec<u8>,
+    pub rodata: Vec<u8>,
+    pub data_align: u64,
+    pub rodata_align: u64,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Object {
+    pub fn section(&self, section: Section) -> &[u8] {
+        match section {
+            Section::Text => &self.text,
+            Section::Data => &self.data,
+            Section::Rodata => &self.rodata,
+        }
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// The object as an ELF relocatable file
+    pub fn to_elf(&self) -> Vec<u8> {
+        elf::write(self)
+    }
+}
@@ -0,0 +1,230 @@
+use crate::builder::{AsmProgram, AsmSection, AsmSize, FReg, Reg};
+use crate::gas::data_value;
+
+// The generic registers are the RISC-V ones in encoding order, named here
+// by their role in the psABI
+pub const ZERO: Reg = Reg::R0;
+/// The return address
+pub const RA: Reg = Reg::R1;
+pub const SP: Reg = Reg::R2;
+pub const GP: Reg = Reg::R3;
+pub const TP: Reg = Reg::R4;
+pub const T0: Reg = Reg::R5;
+pub const T1: Reg = Reg::R6;
+pub const T2: Reg = Reg::R7;
+/// The frame pointer
+pub const S0: Reg = Reg::R8;
+pub const S1: Reg = Reg::R9;
+pub const A0: Reg = Reg::R10;
+pub const A1: Reg = Reg::R11;
+pub const A2: Reg = Reg::R12;
+pub const A3: Reg = Reg::R13;
+pub const A4: Reg = Reg::R14;
+pub const A5: Reg = Reg::R15;
+pub const A6: Reg = Reg::R16;
+pub const A7: Reg = Reg::R17;
+pub const S2: Reg = Reg::R18;
+pub const S11: Reg = Reg::R27;
+pub const T3: Reg = Reg::R28;
+pub const T4: Reg = Reg::R29;
+pub const T5: Reg = Reg::R30;
+pub const T6: Reg = Reg::R31;
+
+/// An instruction of the RV64GC subset the backends use, with the pseudo
+/// instructions of the assembler: `Li` loads any 64-bit constant, `La`
+/// the address of a symbol and `Call` calls a function wherever it is.
+///
+/// `Load` and `Store` access as many bytes as their size, `Load`
+/// sign-extending them to 64 bits, and the float instructions work on
+/// doubles when they are of a `Qword` and on singles otherwise. `SextW`
+/// sign-extends the low 32 bits of a register. `Fmv` copies a float
+/// register to another, or the bits of a float register to a general
+/// purpose one or back, and `Fcvt` converts a single to a double.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Li,
+    La,
+    Mv,
+    Add,
+    Addi,
+    Sub,
+    Mul,
+    Slli,
+    Srai,
+    SextW,
+    Load,
+    Store,
+    Beq,
+    Bne,
+    J,
+    Call,
+    Ret,
+    Ebreak,
+    Fmv,
+    Fadd,
+    Fsub,
+    Feq,
+    Fcvt,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Reg(Reg),
+    FReg(FReg),
+    /// The memory at a register plus a 12-bit offset
+    Mem(Reg, i64),
+    Imm(i64),
+    Label(String),
+}
+
+/// An instruction. Its operands are in assembly order, the destination
+/// first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    pub(crate) op: Op,
+    pub(crate) operands: Vec<Operand>,
+    pub(crate) size: AsmSize,
+}
+
+impl Inst {
+    pub fn new(op: Op, operands: Vec<Operand>) -> Self {
+        Self::sized(op, AsmSize::Qword, operands)
+    }
+
+    pub fn sized(op: Op, size: AsmSize, operands: Vec<Operand>) -> Self {
+        Self { op, operands, size }
+    }
+}
+
+/// Whether `imm` is an immediate of `addi`, or an offset of a load or a
+/// store: 12 signed bits
+pub fn is_imm12(imm: i64) -> bool {
+    (-2048..2048).contains(&imm)
+}
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+const FREG_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// The psABI name of `reg`
+pub fn reg_name(reg: Reg) -> &'static str {
+    REG_NAMES[reg as usize]
+}
+
+pub fn freg_name(reg: FReg) -> &'static str {
+    FREG_NAMES[reg as usize]
+}
+
+fn operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Reg(reg) => reg_name(*reg).to_string(),
+        Operand::FReg(reg) => freg_name(*reg).to_string(),
+        Operand::Mem(reg, offset) => format!("{}({})", offset, reg_name(*reg)),
+        Operand::Imm(imm) => imm.to_string(),
+        Operand::Label(name) => name.clone(),
+    }
+}
+
+/// The suffix of the float instructions of `size`
+fn precision(size: AsmSize) -> &'static str {
+    match size {
+        AsmSize::Qword => "d",
+        _ => "s",
+    }
+}
+
+/// The letter of the memory accesses and of the moves of bits of `size`
+fn width(size: AsmSize) -> &'static str {
+    match size {
+        AsmSize::Byte => "b",
+        AsmSize::Word => "h",
+        AsmSize::Dword => "w",
+        AsmSize::Qword => "d",
+    }
+}
+
+fn mnemonic(inst: &Inst) -> String {
+    let float = |i: usize| matches!(inst.operands.get(i), Some(Operand::FReg(_)));
+    let (p, w) = (precision(inst.size), width(inst.size));
+    match inst.op {
+        Op::Li => "li".to_string(),
+        Op::La => "lla".to_string(),
+        Op::Mv => "mv".to_string(),
+        Op::Add => "add".to_string(),
+        Op::Addi => "addi".to_string(),
+        Op::Sub => "sub".to_string(),
+        Op::Mul => "mul".to_string(),
+        Op::Slli => "slli".to_string(),
+        Op::Srai => "srai".to_string(),
+        Op::SextW => "sext.w".to_string(),
+        // A float register is loaded and stored as the word or the double
+        // it holds
+        Op::Load if float(0) => format!("fl{}", w),
+        Op::Store if float(0) => format!("fs{}", w),
+        Op::Load => format!("l{}", w),
+        Op::Store => format!("s{}", w),
+        Op::Beq => "beq".to_string(),
+        Op::Bne => "bne".to_string(),
+        Op::J => "j".to_string(),
+        Op::Call => "call".to_string(),
+        Op::Ret => "ret".to_string(),
+        Op::Ebreak => "ebreak".to_string(),
+        // The general purpose side of a move of bits is an `x`
+        Op::Fmv => match (float(0), float(1)) {
+            (true, true) => format!("fmv.{}", p),
+            (false, _) => format!("fmv.x.{}", w),
+            (true, false) => format!("fmv.{}.x", w),
+        },
+        Op::Fadd => format!("fadd.{}", p),
+        Op::Fsub => format!("fsub.{}", p),
+        Op::Feq => format!("feq.{}", p),
+        Op::Fcvt => "fcvt.d.s".to_string(),
+    }
+}
+
+/// One instruction in GNU syntax, without indentation
+pub fn print_inst(inst: &Inst) -> String {
+    let operands: Vec<String> = inst.operands.iter().map(operand).collect();
+    let mnemonic = mnemonic(inst);
+    if operands.is_empty() {
+        mnemonic
+    } else {
+        format!("{} {}", mnemonic, operands.join(", "))
+    }
+}
+
+/// The program as GNU assembler source, for an ELF object
+pub fn print(program: &AsmProgram<Inst>) -> String {
+    let mut out = String::from("\t.text\n");
+    for label in &program.labels {
+        if label.global {
+            out.push_str(&format!("\t.globl {}\n", label.label));
+            out.push_str(&format!("\t.type {}, @function\n", label.label));
+        }
+        out.push_str(&format!("{}:\n", label.label));
+        for inst in &label.asm {
+            out.push_str(&format!("\t{}\n", print_inst(inst)));
+        }
+    }
+    for data in &program.data {
+        match data.section {
+            AsmSection::Data => out.push_str("\t.data\n"),
+            AsmSection::Rodata => out.push_str("\t.section .rodata\n"),
+        }
+        out.push_str(&format!("\t.balign {}\n", data.align.max(1)));
+        out.push_str(&format!("{}:\n", data.label));
+        for line in data.data.iter().flat_map(data_value) {
+            out.push_str(&format!("\t{}\n", line));
+        }
+    }
+    out.push_str("\t.section .note.GNU-stack,\"\",@progbits\n");
+    out
+}
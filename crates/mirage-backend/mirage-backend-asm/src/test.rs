@@ -0,0 +1,776 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::aarch64::{
+    self, is_arith_imm, is_fmov_imm, is_scaled_offset, is_unscaled_offset, print_inst, Flavor,
+    Inst, Op, Operand, FP, LR, SP, X0, X1, X16, X9,
+};
+use crate::builder::{
+    Asm, AsmArg, AsmCommand, AsmData, AsmDataValue, AsmLabel, AsmProgram, AsmSection, AsmSize,
+    FReg, Reg,
+};
+use crate::object::{RelocKind, RelocTarget, Section};
+use crate::riscv64::{self, A0, A1, RA, S0, SP as RV_SP, T0, T4, ZERO};
+use crate::x86_64::encoder::{encode, encode_asm, EncodeError};
+use crate::x86_64::{
+    print_att, print_att_asm, print_intel, print_intel_asm, R11, R12, R13, R15, R8, R9, RAX, RBP,
+    RCX, RDI, RDX, RSI, RSP,
+};
+
+fn asm(op: AsmCommand, args: Vec<AsmArg>) -> Asm {
+    Asm::new(op, args)
+}
+
+fn sized(op: AsmCommand, size: AsmSize, args: Vec<AsmArg>) -> Asm {
+    Asm::sized(op, size, args)
+}
+
+fn reg(r: Reg) -> AsmArg {
+    AsmArg::Reg(r)
+}
+
+fn freg(i: usize) -> AsmArg {
+    AsmArg::FReg(FReg::all()[i])
+}
+
+fn mem(base: Reg, disp: i64) -> AsmArg {
+    AsmArg::Mem(base, disp)
+}
+
+fn sym(name: &str) -> AsmArg {
+    AsmArg::Sym(name.to_string())
+}
+
+fn label(name: &str) -> AsmArg {
+    AsmArg::Label(name.to_string())
+}
+
+fn imm(imm: i64) -> AsmArg {
+    AsmArg::Imm(imm)
+}
+
+/// Instructions of every form the encoder knows, without local labels
+fn instructions() -> Vec<Asm> {
+    use AsmCommand::*;
+    use AsmSize::*;
+    vec![
+        asm(Mov, vec![reg(RAX), reg(RCX)]),
+        asm(Mov, vec![reg(R12), reg(RSP)]),
+        sized(Mov, Dword, vec![reg(RDX), reg(R9)]),
+        sized(Mov, Byte, vec![mem(RBP, -1), reg(RSI)]),
+        sized(Mov, Word, vec![mem(R12, 8), reg(RAX)]),
+        asm(Mov, vec![reg(RAX), mem(RSP, 16)]),
+        asm(Mov, vec![reg(R13), mem(R13, 0)]),
+        asm(Mov, vec![reg(RAX), imm(1)]),
+        asm(Mov, vec![reg(R8), imm(-1)]),
+        asm(Mov, vec![reg(R11), imm(1 << 40)]),
+        sized(Mov, Dword, vec![reg(RAX), imm(5)]),
+        sized(Mov, Byte, vec![reg(RDI), imm(5)]),
+        asm(Mov, vec![mem(RBP, -8), imm(7)]),
+        sized(Mov, Dword, vec![mem(RBP, -300), imm(-2)]),
+        asm(Mov, vec![reg(RAX), sym("g")]),
+        sized(Mov, Dword, vec![sym("g"), reg(R9)]),
+        sized(Mov, Word, vec![sym("g"), imm(3)]),
+        asm(Add, vec![reg(RAX), reg(RCX)]),
+        asm(Sub, vec![reg(RSP), imm(32)]),
+        asm(Add, vec![reg(RAX), imm(1000)]),
+        asm(Sub, vec![reg(R11), imm(1000)]),
+        asm(Cmp, vec![reg(RAX), reg(RCX)]),
+        asm(Cmp, vec![reg(RAX), imm(0)]),
+        sized(Cmp, Byte, vec![mem(RAX, 0), imm(1)]),
+        sized(Add, Dword, vec![reg(R8), mem(RBP, -4)]),
+        sized(Add, Byte, vec![reg(RAX), imm(100)]),
+        sized(Sub, Word, vec![reg(RCX), imm(300)]),
+        asm(Mul, vec![reg(RCX), imm(8)]),
+        asm(Mul, vec![reg(RCX), imm(1000)]),
+        asm(Mul, vec![reg(RAX), reg(RCX)]),
+        sized(Mul, Dword, vec![reg(R9), mem(RSP, 0)]),
+        asm(Div, vec![reg(RCX)]),
+        sized(Div, Dword, vec![mem(RBP, -8)]),
+        asm(Lea, vec![reg(RAX), mem(RBP, -16)]),
+        asm(Lea, vec![reg(R11), sym("g")]),
+        sized(Movsx, Byte, vec![reg(RAX), mem(RBP, -1)]),
+        sized(Movsx, Word, vec![reg(RDX), reg(RAX)]),
+        sized(Movsx, Dword, vec![reg(RSI), reg(RAX)]),
+        sized(Movsx, Byte, vec![reg(RAX), reg(RSI)]),
+        sized(Movzx, Byte, vec![reg(RAX), mem(RBP, -1)]),
+        sized(Movzx, Word, vec![reg(R9), reg(RCX)]),
+        sized(Movzx, Byte, vec![reg(RSI), reg(RDI)]),
+        asm(Push, vec![reg(RBP)]),
+        asm(Push, vec![reg(R12)]),
+        asm(Pop, vec![reg(R15)]),
+        asm(Leave, vec![]),
+        asm(Ret, vec![]),
+        asm(Ud2, vec![]),
+        asm(Movss, vec![freg(0), sym("g")]),
+        asm(Movss, vec![mem(RBP, -4), freg(1)]),
+        asm(Movsd, vec![freg(8), freg(1)]),
+        asm(Movsd, vec![freg(2), freg(9)]),
+        asm(Addss, vec![freg(0), freg(1)]),
+        asm(Addsd, vec![freg(0), mem(RBP, -8)]),
+        asm(Subss, vec![freg(3), freg(4)]),
+        asm(Subsd, vec![freg(15), freg(14)]),
+        asm(Ucomiss, vec![freg(0), freg(1)]),
+        asm(Ucomisd, vec![freg(9), freg(10)]),
+        asm(Cvtss2sd, vec![freg(0), freg(0)]),
+        asm(Call, vec![label("printf")]),
+        asm(Jmp, vec![label("elsewhere")]),
+        asm(Je, vec![label("elsewhere")]),
+        asm(Jne, vec![label("elsewhere")]),
+        asm(Jp, vec![label("elsewhere")]),
+    ]
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mirage-as-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(cmd: &mut Command) {
+    let out = cmd.output().unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+/// `.text` of `source` as assembled by the system `as`
+fn gnu_text(name: &str, source: &str) -> Vec<u8> {
+    let dir = scratch_dir(name);
+    let (s, o, bin) = (dir.join("a.s"), dir.join("a.o"), dir.join("a.bin"));
+    std::fs::write(&s, source).unwrap();
+    run(Command::new("as").arg(&s).arg("-o").arg(&o));
+    run(Command::new("objcopy")
+        .args(["-O", "binary", "--only-section=.text"])
+        .arg(&o)
+        .arg(&bin));
+    let text = std::fs::read(&bin).unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+    text
+}
+
+fn program(labels: Vec<AsmLabel>, data: Vec<AsmData>) -> AsmProgram {
+    let mut program = AsmProgram::new();
+    for l in labels {
+        program.add_label(l);
+    }
+    for d in data {
+        program.add_data(d);
+    }
+    program
+}
+
+fn function(name: &str, global: bool, body: Vec<Asm>) -> AsmLabel {
+    let mut label = if global {
+        AsmLabel::global(name.to_string())
+    } else {
+        AsmLabel::new(name.to_string())
+    };
+    for asm in body {
+        label.add_asm(asm);
+    }
+    label
+}
+
+#[test]
+fn test_intel_printer() {
+    use AsmCommand::*;
+    let cases = [
+        (
+            asm(Mov, vec![mem(RBP, -8), reg(RAX)]),
+            "mov QWORD PTR [rbp-8], rax",
+        ),
+        (
+            sized(Movsx, AsmSize::Dword, vec![reg(RAX), sym("g")]),
+            "movsxd rax, DWORD PTR [rip+g]",
+        ),
+        (
+            sized(Movsx, AsmSize::Byte, vec![reg(RAX), reg(RSI)]),
+            "movsx rax, sil",
+        ),
+        (asm(Mul, vec![reg(RCX), imm(8)]), "imul rcx, rcx, 8"),
+        (
+            asm(Mov, vec![reg(RAX), imm(1 << 40)]),
+            "movabs rax, 1099511627776",
+        ),
+        (asm(Lea, vec![reg(RAX), mem(RSP, 16)]), "lea rax, [rsp+16]"),
+        (
+            asm(Movss, vec![freg(0), mem(RBP, 0)]),
+            "movss xmm0, DWORD PTR [rbp]",
+        ),
+        (asm(Call, vec![label("printf")]), "call printf"),
+        (asm(Leave, vec![]), "leave"),
+    ];
+    for (asm, intel) in cases {
+        assert_eq!(print_intel_asm(&asm), intel);
+    }
+}
+
+#[test]
+fn test_syntaxes_assemble_alike() {
+    let program = program(vec![function("f", true, instructions())], vec![]);
+    let att = gnu_text("att", &print_att(&program));
+    let intel = gnu_text("intel", &print_intel(&program));
+    assert!(!att.is_empty());
+    assert_eq!(att, intel);
+}
+
+#[test]
+fn test_encoding() {
+    // Each instruction on its own, so `as` can't pick shorter jumps
+    for asm in instructions() {
+        let source = format!("\t{}\n", print_att_asm(&asm));
+        assert_eq!(
+            encode_asm(&asm).unwrap(),
+            gnu_text("one", &source),
+            "{}",
+            print_att_asm(&asm)
+        );
+    }
+    assert_eq!(
+        encode_asm(&asm(AsmCommand::Lea, vec![reg(RAX), imm(1)])),
+        Err(EncodeError::InvalidOperands("leaq $1, %rax".to_string()))
+    );
+}
+
+#[test]
+fn test_labels_and_relocations() {
+    use AsmCommand::*;
+    let message = AsmData::new(
+        "message".to_string(),
+        AsmSection::Rodata,
+        1,
+        vec![AsmDataValue::String("hi".to_string())],
+    );
+    let counter = AsmData::new(
+        "counter".to_string(),
+        AsmSection::Data,
+        8,
+        vec![AsmDataValue::Qword(0)],
+    );
+    let main = function(
+        "main",
+        true,
+        vec![asm(Jmp, vec![label(".Lnext")]), asm(Ud2, vec![])],
+    );
+    let next = function(
+        ".Lnext",
+        false,
+        vec![
+            asm(Lea, vec![reg(RDI), sym("message")]),
+            asm(Call, vec![label("puts")]),
+            asm(Mov, vec![sym("counter"), imm(1)]),
+            asm(Ret, vec![]),
+        ],
+    );
+    let object = encode(&program(vec![main, next], vec![message, counter])).unwrap();
+    // The jump over `ud2` is resolved
+    assert_eq!(&object.text[..7], &[0xe9, 2, 0, 0, 0, 0x0f, 0x0b]);
+    assert_eq!(object.symbol(".Lnext").unwrap().offset, 7);
+    assert_eq!(object.rodata, b"hi\0");
+    assert_eq!(object.data_align, 8);
+    assert!(object.symbol("main").unwrap().function);
+
+    let targets: Vec<_> = object
+        .relocations
+        .iter()
+        .map(|r| (r.target.clone(), r.kind, r.addend))
+        .collect();
+    assert_eq!(
+        targets,
+        vec![
+            (RelocTarget::Section(Section::Rodata), RelocKind::Pc32, -4),
+            (
+                RelocTarget::Symbol("puts".to_string()),
+                RelocKind::Plt32,
+                -4
+            ),
+            // The immediate follows the displacement
+            (RelocTarget::Section(Section::Data), RelocKind::Pc32, -8),
+        ]
+    );
+    assert_eq!(object.symbol("puts").unwrap().section, None);
+
+    let twice = vec![function("f", false, vec![]), function("f", false, vec![])];
+    assert_eq!(
+        encode(&program(twice, vec![])),
+        Err(EncodeError::DuplicateLabel("f".to_string()))
+    );
+    let missing = vec![function(
+        "f",
+        false,
+        vec![asm(Jmp, vec![label(".Lnowhere")])],
+    )];
+    assert_eq!(
+        encode(&program(missing, vec![])),
+        Err(EncodeError::UnknownLabel(".Lnowhere".to_string()))
+    );
+}
+
+#[test]
+fn test_elf_object() {
+    use AsmCommand::*;
+    // Prints a string three times, counting in a global, and exits with
+    // the count
+    let message = AsmData::new(
+        "message".to_string(),
+        AsmSection::Rodata,
+        1,
+        vec![AsmDataValue::String("hello".to_string())],
+    );
+    let count = AsmData::new(
+        "count".to_string(),
+        AsmSection::Data,
+        8,
+        vec![AsmDataValue::Qword(0)],
+    );
+    let main = function(
+        "main",
+        true,
+        vec![
+            asm(Push, vec![reg(RBP)]),
+            asm(Mov, vec![reg(RBP), reg(RSP)]),
+        ],
+    );
+    let looping = function(
+        ".Lloop",
+        false,
+        vec![
+            asm(Lea, vec![reg(RDI), sym("message")]),
+            asm(Call, vec![label("puts")]),
+            asm(Call, vec![label("bump")]),
+            asm(Cmp, vec![reg(RAX), imm(3)]),
+            asm(Jne, vec![label(".Lloop")]),
+            asm(Leave, vec![]),
+            asm(Ret, vec![]),
+        ],
+    );
+    let bump = function(
+        "bump",
+        false,
+        vec![
+            asm(Mov, vec![reg(RAX), sym("count")]),
+            asm(Add, vec![reg(RAX), imm(1)]),
+            asm(Mov, vec![sym("count"), reg(RAX)]),
+            asm(Ret, vec![]),
+        ],
+    );
+    let program = program(vec![main, looping, bump], vec![message, count]);
+    let elf = encode(&program).unwrap().to_elf();
+    assert_eq!(&elf[..4], b"\x7fELF");
+
+    let dir = scratch_dir("elf");
+    let (o, exe) = (dir.join("a.o"), dir.join("a"));
+    std::fs::write(&o, elf).unwrap();
+    run(Command::new("cc").arg(&o).arg("-o").arg(&exe));
+    let out = Command::new(&exe).output().unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "hello\nhello\nhello\n"
+    );
+}
+
+fn a64(op: Op, operands: Vec<Operand>) -> Inst {
+    Inst::new(op, operands)
+}
+
+fn a64_sized(op: Op, size: AsmSize, operands: Vec<Operand>) -> Inst {
+    Inst::sized(op, size, operands)
+}
+
+#[test]
+fn test_aarch64_printer() {
+    let cases = [
+        (
+            a64(
+                Op::Stp,
+                vec![
+                    Operand::Reg(FP),
+                    Operand::Reg(LR),
+                    Operand::PreIndex(SP, -16),
+                ],
+            ),
+            "stp x29, x30, [sp, #-16]!",
+        ),
+        (
+            a64(
+                Op::Ldp,
+                vec![
+                    Operand::Reg(FP),
+                    Operand::Reg(LR),
+                    Operand::PostIndex(SP, 16),
+                ],
+            ),
+            "ldp x29, x30, [sp], #16",
+        ),
+        (
+            a64_sized(
+                Op::Ldrs,
+                AsmSize::Word,
+                vec![Operand::Reg(X0), Operand::Mem(X1, 6)],
+            ),
+            "ldrsh x0, [x1, #6]",
+        ),
+        (
+            a64_sized(
+                Op::Stur,
+                AsmSize::Byte,
+                vec![Operand::Reg(X9), Operand::Mem(FP, -1)],
+            ),
+            "sturb w9, [x29, #-1]",
+        ),
+        (
+            a64_sized(
+                Op::Sxt,
+                AsmSize::Dword,
+                vec![Operand::Reg(X0), Operand::Reg(X9)],
+            ),
+            "sxtw x0, w9",
+        ),
+        (
+            a64(Op::Ldr, vec![Operand::Reg(X0), Operand::Index(FP, X16)]),
+            "ldr x0, [x29, x16]",
+        ),
+        (
+            a64_sized(
+                Op::Fcvt,
+                AsmSize::Qword,
+                vec![Operand::FReg(FReg::F0), Operand::FReg(FReg::F0)],
+            ),
+            "fcvt d0, s0",
+        ),
+        (
+            a64_sized(
+                Op::Fmov,
+                AsmSize::Dword,
+                vec![Operand::FReg(FReg::F1), Operand::FImm(-0.5)],
+            ),
+            "fmov s1, #-0.5",
+        ),
+        (
+            a64(
+                Op::Add,
+                vec![
+                    Operand::Reg(SP),
+                    Operand::Reg(SP),
+                    Operand::Imm(1),
+                    Operand::Lsl(12),
+                ],
+            ),
+            "add sp, sp, #1, lsl #12",
+        ),
+        (
+            a64(Op::Beq, vec![Operand::Label(".L1".to_string())]),
+            "b.eq .L1",
+        ),
+    ];
+    for (inst, text) in &cases {
+        assert_eq!(print_inst(inst, Flavor::Elf), *text);
+    }
+
+    let page = a64(
+        Op::Adrp,
+        vec![Operand::Reg(X16), Operand::Page("g".to_string())],
+    );
+    let off = a64(
+        Op::Add,
+        vec![
+            Operand::Reg(X16),
+            Operand::Reg(X16),
+            Operand::PageOff(".LC0".to_string()),
+        ],
+    );
+    assert_eq!(print_inst(&page, Flavor::Elf), "adrp x16, g");
+    assert_eq!(print_inst(&off, Flavor::Elf), "add x16, x16, :lo12:.LC0");
+    assert_eq!(print_inst(&page, Flavor::MachO), "adrp x16, _g@PAGE");
+    assert_eq!(print_inst(&off, Flavor::MachO), "add x16, x16, LC0@PAGEOFF");
+}
+
+#[test]
+fn test_aarch64_limits() {
+    assert!(is_arith_imm(4095) && is_arith_imm(4096) && is_arith_imm(0xfff000));
+    assert!(!is_arith_imm(4097) && !is_arith_imm(-1) && !is_arith_imm(0x1000000));
+    assert!(is_scaled_offset(32760, 8) && !is_scaled_offset(32768, 8));
+    assert!(!is_scaled_offset(4, 8) && !is_scaled_offset(-8, 8));
+    assert!(is_unscaled_offset(-256) && !is_unscaled_offset(256));
+    assert!(is_fmov_imm(1.0) && is_fmov_imm(-31.0) && is_fmov_imm(0.125));
+    assert!(!is_fmov_imm(0.0) && !is_fmov_imm(0.1) && !is_fmov_imm(32.0));
+}
+
+/// A function and some data, assembled for both flavors by LLVM's
+/// assembler, when there is one
+#[test]
+fn test_aarch64_program_assembles() {
+    let mut f = AsmLabel::global("f".to_string());
+    for inst in [
+        a64(
+            Op::Stp,
+            vec![
+                Operand::Reg(FP),
+                Operand::Reg(LR),
+                Operand::PreIndex(SP, -16),
+            ],
+        ),
+        a64(Op::Mov, vec![Operand::Reg(FP), Operand::Reg(SP)]),
+        a64(
+            Op::Adrp,
+            vec![Operand::Reg(X16), Operand::Page("g".to_string())],
+        ),
+        a64(
+            Op::Add,
+            vec![
+                Operand::Reg(X16),
+                Operand::Reg(X16),
+                Operand::PageOff("g".to_string()),
+            ],
+        ),
+        a64_sized(
+            Op::Ldrs,
+            AsmSize::Dword,
+            vec![Operand::Reg(X0), Operand::Mem(X16, 0)],
+        ),
+        a64(
+            Op::Movz,
+            vec![Operand::Reg(X9), Operand::Imm(1), Operand::Lsl(16)],
+        ),
+        a64(Op::Movk, vec![Operand::Reg(X9), Operand::Imm(2)]),
+        a64(Op::Cmp, vec![Operand::Reg(X0), Operand::Reg(X9)]),
+        a64(Op::Bne, vec![Operand::Label(".L1".to_string())]),
+        a64(Op::Bl, vec![Operand::Label("g".to_string())]),
+    ] {
+        f.add_asm(inst);
+    }
+    let mut end = AsmLabel::new(".L1".to_string());
+    end.add_asm(a64(
+        Op::Ldp,
+        vec![
+            Operand::Reg(FP),
+            Operand::Reg(LR),
+            Operand::PostIndex(SP, 16),
+        ],
+    ));
+    end.add_asm(a64(Op::Ret, vec![]));
+    let mut program = AsmProgram::new();
+    program.add_label(f);
+    program.add_label(end);
+    program.add_data(AsmData::new(
+        "g".to_string(),
+        AsmSection::Data,
+        4,
+        vec![AsmDataValue::Dword(7)],
+    ));
+
+    let elf = aarch64::print(&program, Flavor::Elf);
+    assert!(elf.contains("\t.globl f\n\t.type f, %function\nf:\n"));
+    assert!(elf.ends_with("\t.section .note.GNU-stack,\"\",%progbits\n"));
+    let macho = aarch64::print(&program, Flavor::MachO);
+    assert!(macho.contains("\t.globl _f\n_f:\n"));
+    assert!(macho.contains("\nL1:\n"));
+
+    for (name, triple, source) in [
+        ("a64-elf", "aarch64-linux-gnu", elf),
+        ("a64-macho", "arm64-apple-macos", macho),
+    ] {
+        let dir = scratch_dir(name);
+        let (s, o) = (dir.join("a.s"), dir.join("a.o"));
+        std::fs::write(&s, source).unwrap();
+        let mut cmd = Command::new("llvm-mc");
+        cmd.arg(format!("-triple={}", triple))
+            .arg("-filetype=obj")
+            .arg(&s)
+            .arg("-o")
+            .arg(&o);
+        if cmd.output().is_ok() {
+            run(&mut cmd);
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+fn rv(op: riscv64::Op, operands: Vec<riscv64::Operand>) -> riscv64::Inst {
+    riscv64::Inst::new(op, operands)
+}
+
+fn rv_sized(op: riscv64::Op, size: AsmSize, operands: Vec<riscv64::Operand>) -> riscv64::Inst {
+    riscv64::Inst::sized(op, size, operands)
+}
+
+#[test]
+fn test_riscv64_printer() {
+    use riscv64::{Op, Operand};
+    let cases = [
+        (
+            rv(Op::Store, vec![Operand::Reg(RA), Operand::Mem(RV_SP, 8)]),
+            "sd ra, 8(sp)",
+        ),
+        (
+            rv_sized(
+                Op::Load,
+                AsmSize::Byte,
+                vec![Operand::Reg(T0), Operand::Mem(S0, -17)],
+            ),
+            "lb t0, -17(s0)",
+        ),
+        (
+            rv_sized(
+                Op::Store,
+                AsmSize::Word,
+                vec![Operand::Reg(A1), Operand::Mem(T4, 0)],
+            ),
+            "sh a1, 0(t4)",
+        ),
+        (
+            rv_sized(
+                Op::Load,
+                AsmSize::Dword,
+                vec![Operand::FReg(FReg::F10), Operand::Mem(T4, 0)],
+            ),
+            "flw fa0, 0(t4)",
+        ),
+        (
+            rv(
+                Op::Store,
+                vec![Operand::FReg(FReg::F8), Operand::Mem(S0, -24)],
+            ),
+            "fsd fs0, -24(s0)",
+        ),
+        (
+            rv(Op::Li, vec![Operand::Reg(A0), Operand::Imm(-1 << 40)]),
+            "li a0, -1099511627776",
+        ),
+        (
+            rv(
+                Op::La,
+                vec![Operand::Reg(T4), Operand::Label(".LC0".to_string())],
+            ),
+            "lla t4, .LC0",
+        ),
+        (
+            rv(
+                Op::Bne,
+                vec![
+                    Operand::Reg(T0),
+                    Operand::Reg(ZERO),
+                    Operand::Label(".L1".to_string()),
+                ],
+            ),
+            "bne t0, zero, .L1",
+        ),
+        (
+            rv(
+                Op::Fmv,
+                vec![Operand::FReg(FReg::F0), Operand::FReg(FReg::F28)],
+            ),
+            "fmv.d ft0, ft8",
+        ),
+        (
+            rv_sized(
+                Op::Fmv,
+                AsmSize::Dword,
+                vec![Operand::Reg(A0), Operand::FReg(FReg::F0)],
+            ),
+            "fmv.x.w a0, ft0",
+        ),
+        (
+            rv(Op::Fmv, vec![Operand::FReg(FReg::F0), Operand::Reg(A1)]),
+            "fmv.d.x ft0, a1",
+        ),
+        (
+            rv_sized(
+                Op::Feq,
+                AsmSize::Dword,
+                vec![
+                    Operand::Reg(T0),
+                    Operand::FReg(FReg::F0),
+                    Operand::FReg(FReg::F1),
+                ],
+            ),
+            "feq.s t0, ft0, ft1",
+        ),
+        (
+            rv(Op::Call, vec![Operand::Label("puts".to_string())]),
+            "call puts",
+        ),
+        (rv(Op::Ret, vec![]), "ret"),
+    ];
+    for (inst, text) in &cases {
+        assert_eq!(riscv64::print_inst(inst), *text);
+    }
+}
+
+/// A function and some data, assembled for RV64GC by LLVM's assembler,
+/// when there is one
+#[test]
+fn test_riscv64_program_assembles() {
+    use riscv64::{Op, Operand};
+    let mut f = AsmLabel::global("f".to_string());
+    for inst in [
+        rv(
+            Op::Addi,
+            vec![Operand::Reg(RV_SP), Operand::Reg(RV_SP), Operand::Imm(-16)],
+        ),
+        rv(Op::Store, vec![Operand::Reg(RA), Operand::Mem(RV_SP, 8)]),
+        rv(
+            Op::La,
+            vec![Operand::Reg(T4), Operand::Label("g".to_string())],
+        ),
+        rv_sized(
+            Op::Load,
+            AsmSize::Dword,
+            vec![Operand::Reg(A0), Operand::Mem(T4, 0)],
+        ),
+        rv(
+            Op::Li,
+            vec![Operand::Reg(T0), Operand::Imm(0x1234_5678_9abc)],
+        ),
+        rv(
+            Op::Bne,
+            vec![
+                Operand::Reg(A0),
+                Operand::Reg(T0),
+                Operand::Label(".L1".to_string()),
+            ],
+        ),
+        rv(Op::Call, vec![Operand::Label("g".to_string())]),
+    ] {
+        f.add_asm(inst);
+    }
+    let mut end = AsmLabel::new(".L1".to_string());
+    end.add_asm(rv(Op::Load, vec![Operand::Reg(RA), Operand::Mem(RV_SP, 8)]));
+    end.add_asm(rv(
+        Op::Addi,
+        vec![Operand::Reg(RV_SP), Operand::Reg(RV_SP), Operand::Imm(16)],
+    ));
+    end.add_asm(rv(Op::Ret, vec![]));
+    let mut program = AsmProgram::new();
+    program.add_label(f);
+    program.add_label(end);
+    program.add_data(AsmData::new(
+        "g".to_string(),
+        AsmSection::Data,
+        4,
+        vec![AsmDataValue::Dword(7)],
+    ));
+
+    let source = riscv64::print(&program);
+    assert!(source.contains("\t.globl f\n\t.type f, @function\nf:\n"));
+    assert!(source.ends_with("\t.section .note.GNU-stack,\"\",@progbits\n"));
+
+    let dir = scratch_dir("rv64");
+    let (s, o) = (dir.join("a.s"), dir.join("a.o"));
+    std::fs::write(&s, source).unwrap();
+    let mut cmd = Command::new("llvm-mc");
+    cmd.args(["-triple=riscv64-unknown-linux-gnu", "-mattr=+m,+a,+f,+d,+c"])
+        .arg("-filetype=obj")
+        .arg(&s)
+        .arg("-o")
+        .arg(&o);
+    if cmd.output().is_ok() {
+        run(&mut cmd);
+    }
+    let _ = std::fs::remove_dir_all(dir);
+}
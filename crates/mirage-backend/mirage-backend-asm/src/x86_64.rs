@@ -0,0 +1,306 @@
+pub mod encoder;
+
+use crate::builder::{Asm, AsmArg, AsmCommand, AsmProgram, AsmSection, AsmSize, FReg, Reg};
+use crate::gas::data_value;
+
+// The generic registers are the x86-64 ones in encoding order
+pub const RAX: Reg = Reg::R0;
+pub const RCX: Reg = Reg::R1;
+pub const RDX: Reg = Reg::R2;
+pub const RBX: Reg = Reg::R3;
+pub const RSP: Reg = Reg::R4;
+pub const RBP: Reg = Reg::R5;
+pub const RSI: Reg = Reg::R6;
+pub const RDI: Reg = Reg::R7;
+pub const R8: Reg = Reg::R8;
+pub const R9: Reg = Reg::R9;
+pub const R10: Reg = Reg::R10;
+pub const R11: Reg = Reg::R11;
+pub const R12: Reg = Reg::R12;
+pub const R13: Reg = Reg::R13;
+pub const R14: Reg = Reg::R14;
+pub const R15: Reg = Reg::R15;
+
+const REG_NAMES: [[&str; 4]; 16] = [
+    ["al", "ax", "eax", "rax"],
+    ["cl", "cx", "ecx", "rcx"],
+    ["dl", "dx", "edx", "rdx"],
+    ["bl", "bx", "ebx", "rbx"],
+    ["spl", "sp", "esp", "rsp"],
+    ["bpl", "bp", "ebp", "rbp"],
+    ["sil", "si", "esi", "rsi"],
+    ["dil", "di", "edi", "rdi"],
+    ["r8b", "r8w", "r8d", "r8"],
+    ["r9b", "r9w", "r9d", "r9"],
+    ["r10b", "r10w", "r10d", "r10"],
+    ["r11b", "r11w", "r11d", "r11"],
+    ["r12b", "r12w", "r12d", "r12"],
+    ["r13b", "r13w", "r13d", "r13"],
+    ["r14b", "r14w", "r14d", "r14"],
+    ["r15b", "r15w", "r15d", "r15"],
+];
+
+/// The name of `reg` used with operands of `size`
+pub fn reg_name(reg: Reg, size: AsmSize) -> &'static str {
+    let i = match size {
+        AsmSize::Byte => 0,
+        AsmSize::Word => 1,
+        AsmSize::Dword => 2,
+        AsmSize::Qword => 3,
+    };
+    REG_NAMES[reg as usize][i]
+}
+
+pub fn freg_name(reg: FReg) -> String {
+    format!("xmm{}", reg as usize)
+}
+
+fn suffix(size: AsmSize) -> &'static str {
+    match size {
+        AsmSize::Byte => "b",
+        AsmSize::Word => "w",
+        AsmSize::Dword => "l",
+        AsmSize::Qword => "q",
+    }
+}
+
+fn fits_i32(imm: i64) -> bool {
+    i32::try_from(imm).is_ok()
+}
+
+fn att_arg(arg: &AsmArg, size: AsmSize) -> String {
+    match arg {
+        AsmArg::Reg(reg) => format!("%{}", reg_name(*reg, size)),
+        AsmArg::FReg(reg) => format!("%{}", freg_name(*reg)),
+        AsmArg::Mem(base, 0) => format!("(%{})", reg_name(*base, AsmSize::Qword)),
+        AsmArg::Mem(base, disp) => format!("{}(%{})", disp, reg_name(*base, AsmSize::Qword)),
+        AsmArg::Sym(name) => format!("{}(%rip)", name),
+        AsmArg::Imm(imm) => format!("${}", imm),
+        AsmArg::Label(name) => name.clone(),
+        AsmArg::None => String::new(),
+    }
+}
+
+/// The AT&T mnemonic of `asm`
+fn att_mnemonic(asm: &Asm) -> String {
+    let s = suffix(asm.size);
+    match asm.asm_op {
+        AsmCommand::Mov => match asm.asm_arg.get(1) {
+            Some(AsmArg::Imm(imm)) if !fits_i32(*imm) => "movabsq".to_string(),
+            _ => format!("mov{}", s),
+        },
+        AsmCommand::Add => format!("add{}", s),
+        AsmCommand::Sub => format!("sub{}", s),
+        AsmCommand::Mul => format!("imul{}", s),
+        AsmCommand::Div => format!("idiv{}", s),
+        AsmCommand::Cmp => format!("cmp{}", s),
+        AsmCommand::Lea => "leaq".to_string(),
+        AsmCommand::Movsx => format!("movs{}q", s),
+        AsmCommand::Movzx => format!("movz{}q", s),
+        AsmCommand::Push => "pushq".to_string(),
+        AsmCommand::Pop => "popq".to_string(),
+        AsmCommand::Jmp => "jmp".to_string(),
+        AsmCommand::Je => "je".to_string(),
+        AsmCommand::Jne => "jne".to_string(),
+        AsmCommand::Jp => "jp".to_string(),
+        AsmCommand::Call => "call".to_string(),
+        AsmCommand::Leave => "leave".to_string(),
+        AsmCommand::Ret => "ret".to_string(),
+        AsmCommand::Ud2 => "ud2".to_string(),
+        AsmCommand::Movss => "movss".to_string(),
+        AsmCommand::Movsd => "movsd".to_string(),
+        AsmCommand::Addss => "addss".to_string(),
+        AsmCommand::Addsd => "addsd".to_string(),
+        AsmCommand::Subss => "subss".to_string(),
+        AsmCommand::Subsd => "subsd".to_string(),
+        AsmCommand::Ucomiss => "ucomiss".to_string(),
+        AsmCommand::Ucomisd => "ucomisd".to_string(),
+        AsmCommand::Cvtss2sd => "cvtss2sd".to_string(),
+    }
+}
+
+/// One instruction in AT&T syntax, without indentation
+pub fn print_att_asm(asm: &Asm) -> String {
+    let mnemonic = att_mnemonic(asm);
+    let args: Vec<String> = asm
+        .asm_arg
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, arg)| **arg != AsmArg::None)
+        .map(|(i, arg)| {
+            // The destination of a sign extension or of a `lea` is a
+            // whole register
+            let size = match asm.asm_op {
+                AsmCommand::Movsx | AsmCommand::Movzx | AsmCommand::Lea if i == 0 => AsmSize::Qword,
+                AsmCommand::Push | AsmCommand::Pop => AsmSize::Qword,
+                _ => asm.size,
+            };
+            att_arg(arg, size)
+        })
+        .collect();
+    if args.is_empty() {
+        mnemonic
+    } else {
+        format!("{} {}", mnemonic, args.join(", "))
+    }
+}
+
+fn ptr(size: AsmSize) -> &'static str {
+    match size {
+        AsmSize::Byte => "BYTE PTR",
+        AsmSize::Word => "WORD PTR",
+        AsmSize::Dword => "DWORD PTR",
+        AsmSize::Qword => "QWORD PTR",
+    }
+}
+
+/// `arg` in Intel syntax. A memory operand is prefixed with the size it
+/// is accessed with, if any.
+fn intel_arg(arg: &AsmArg, size: AsmSize, access: Option<AsmSize>) -> String {
+    let mem = |address: String| match access {
+        Some(access) => format!("{} [{}]", ptr(access), address),
+        None => format!("[{}]", address),
+    };
+    match arg {
+        AsmArg::Reg(reg) => reg_name(*reg, size).to_string(),
+        AsmArg::FReg(reg) => freg_name(*reg),
+        AsmArg::Mem(base, 0) => mem(reg_name(*base, AsmSize::Qword).to_string()),
+        AsmArg::Mem(base, disp) if *disp < 0 => {
+            mem(format!("{}-{}", reg_name(*base, AsmSize::Qword), disp.unsigned_abs()))
+        }
+        AsmArg::Mem(base, disp) => mem(format!("{}+{}", reg_name(*base, AsmSize::Qword), disp)),
+        AsmArg::Sym(name) => mem(format!("rip+{}", name)),
+        AsmArg::Imm(imm) => imm.to_string(),
+        AsmArg::Label(name) => name.clone(),
+        AsmArg::None => String::new(),
+    }
+}
+
+/// The Intel mnemonic of `asm`
+fn intel_mnemonic(asm: &Asm) -> &'static str {
+    match asm.asm_op {
+        AsmCommand::Mov => match asm.asm_arg.get(1) {
+            Some(AsmArg::Imm(imm)) if !fits_i32(*imm) => "movabs",
+            _ => "mov",
+        },
+        AsmCommand::Add => "add",
+        AsmCommand::Sub => "sub",
+        AsmCommand::Mul => "imul",
+        AsmCommand::Div => "idiv",
+        AsmCommand::Cmp => "cmp",
+        AsmCommand::Lea => "lea",
+        AsmCommand::Movsx if asm.size == AsmSize::Dword => "movsxd",
+        AsmCommand::Movsx => "movsx",
+        AsmCommand::Movzx => "movzx",
+        AsmCommand::Push => "push",
+        AsmCommand::Pop => "pop",
+        AsmCommand::Jmp => "jmp",
+        AsmCommand::Je => "je",
+        AsmCommand::Jne => "jne",
+        AsmCommand::Jp => "jp",
+        AsmCommand::Call => "call",
+        AsmCommand::Leave => "leave",
+        AsmCommand::Ret => "ret",
+        AsmCommand::Ud2 => "ud2",
+        AsmCommand::Movss => "movss",
+        AsmCommand::Movsd => "movsd",
+        AsmCommand::Addss => "addss",
+        AsmCommand::Addsd => "addsd",
+        AsmCommand::Subss => "subss",
+        AsmCommand::Subsd => "subsd",
+        AsmCommand::Ucomiss => "ucomiss",
+        AsmCommand::Ucomisd => "ucomisd",
+        AsmCommand::Cvtss2sd => "cvtss2sd",
+    }
+}
+
+/// One instruction in Intel syntax, as GNU `as` reads it with
+/// `.intel_syntax noprefix`, without indentation
+pub fn print_intel_asm(asm: &Asm) -> String {
+    let mnemonic = intel_mnemonic(asm);
+    let mut args: Vec<&AsmArg> = asm
+        .asm_arg
+        .iter()
+        .filter(|arg| **arg != AsmArg::None)
+        .collect();
+    // `imul` by an immediate takes its destination twice
+    if let (AsmCommand::Mul, [dst @ AsmArg::Reg(_), AsmArg::Imm(_)]) = (asm.asm_op, &args[..]) {
+        args.insert(1, dst);
+    }
+    let args: Vec<String> = args
+        .into_iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            let (size, access) = match asm.asm_op {
+                AsmCommand::Movsx | AsmCommand::Movzx | AsmCommand::Lea if i == 0 => (AsmSize::Qword, None),
+                AsmCommand::Lea => (AsmSize::Qword, None),
+                AsmCommand::Push | AsmCommand::Pop => (AsmSize::Qword, Some(AsmSize::Qword)),
+                AsmCommand::Movss
+                | AsmCommand::Addss
+                | AsmCommand::Subss
+                | AsmCommand::Ucomiss
+                | AsmCommand::Cvtss2sd => (asm.size, Some(AsmSize::Dword)),
+                AsmCommand::Movsd | AsmCommand::Addsd | AsmCommand::Subsd | AsmCommand::Ucomisd => {
+                    (asm.size, Some(AsmSize::Qword))
+                }
+                _ => (asm.size, Some(asm.size)),
+            };
+            intel_arg(arg, size, access)
+        })
+        .collect();
+    if args.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{} {}", mnemonic, args.join(", "))
+    }
+}
+
+/// The syntax the instructions are printed in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Att,
+    Intel,
+}
+
+/// The program as GNU assembler source in AT&T syntax
+pub fn print_att(program: &AsmProgram) -> String {
+    print(program, Syntax::Att)
+}
+
+/// The program as GNU assembler source in Intel syntax
+pub fn print_intel(program: &AsmProgram) -> String {
+    print(program, Syntax::Intel)
+}
+
+/// The program as GNU assembler source in `syntax`
+pub fn print(program: &AsmProgram, syntax: Syntax) -> String {
+    let (mut out, print_asm): (String, fn(&Asm) -> String) = match syntax {
+        Syntax::Att => (String::new(), print_att_asm),
+        Syntax::Intel => (".intel_syntax noprefix\n".to_string(), print_intel_asm),
+    };
+    out.push_str("\t.text\n");
+    for label in &program.labels {
+        if label.global {
+            out.push_str(&format!("\t.globl {}\n", label.label));
+            out.push_str(&format!("\t.type {}, @function\n", label.label));
+        }
+        out.push_str(&format!("{}:\n", label.label));
+        for asm in &label.asm {
+            out.push_str(&format!("\t{}\n", print_asm(asm)));
+        }
+    }
+    for data in &program.data {
+        match data.section {
+            AsmSection::Data => out.push_str("\t.data\n"),
+            AsmSection::Rodata => out.push_str("\t.section .rodata\n"),
+        }
+        out.push_str(&format!("\t.balign {}\n", data.align.max(1)));
+        out.push_str(&format!("{}:\n", data.label));
+        for line in data.data.iter().flat_map(data_value) {
+            out.push_str(&format!("\t{}\n", line));
+        }
+    }
+    out.push_str("\t.section .note.GNU-stack,\"\",@progbits\n");
+    out
+}
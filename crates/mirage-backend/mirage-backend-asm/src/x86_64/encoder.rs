@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+
+use crate::builder::{Asm, AsmArg, AsmCommand, AsmDataValue, AsmProgram, AsmSection, AsmSize};
+use crate::object::{Object, RelocKind, RelocTarget, Relocation, Section, Symbol};
+use crate::x86_64::print_att_asm;
+
+/// An error encoding a program
+/// # Variants
+/// * `InvalidOperands` - An instruction whose operands have no encoding
+/// * `DuplicateLabel` - A label defined twice
+/// * `UnknownLabel` - A local label, starting with `.L`, which isn't
+///   defined
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncodeError {
+    InvalidOperands(String),
+    DuplicateLabel(String),
+    UnknownLabel(String),
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncodeError::InvalidOperands(instr) => {
+                write!(f, "no encoding for the operands of `{}`", instr)
+            }
+            EncodeError::DuplicateLabel(label) => write!(f, "label `{}` is defined twice", label),
+            EncodeError::UnknownLabel(label) => write!(f, "unknown label `{}`", label),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+pub type EncodeResult<T> = Result<T, EncodeError>;
+
+/// A register/memory operand
+#[derive(Debug, Clone, Copy)]
+enum Rm<'a> {
+    Reg(u8),
+    /// A base register plus a displacement
+    Base(u8, i64),
+    /// The memory at a symbol, relative to the instruction pointer
+    Rip(&'a str),
+}
+
+impl Rm<'_> {
+    fn base(&self) -> u8 {
+        match self {
+            Rm::Reg(r) | Rm::Base(r, _) => *r,
+            Rm::Rip(_) => 0,
+        }
+    }
+}
+
+/// A 32-bit field to fill in once the labels are placed: `offset` gets the
+/// address of `target`, plus `addend`, minus its own address
+#[derive(Debug, Clone)]
+struct Fixup {
+    offset: usize,
+    target: String,
+    /// Whether the field is the target of a jump or of a call
+    branch: bool,
+    addend: i64,
+}
+
+fn fits_i8(imm: i64) -> bool {
+    i8::try_from(imm).is_ok()
+}
+
+fn fits_i32(imm: i64) -> bool {
+    i32::try_from(imm).is_ok()
+}
+
+/// Whether `imm` can be an immediate of `size`, signed or not
+fn fits(imm: i64, size: AsmSize) -> bool {
+    match size {
+        AsmSize::Byte => (-128..=255).contains(&imm),
+        AsmSize::Word => (-32768..=65535).contains(&imm),
+        AsmSize::Dword => (i32::MIN as i64..=u32::MAX as i64).contains(&imm),
+        AsmSize::Qword => true,
+    }
+}
+
+fn rm(arg: &AsmArg) -> Option<Rm<'_>> {
+    match arg {
+        AsmArg::Reg(r) => Some(Rm::Reg(*r as u8)),
+        AsmArg::Mem(base, disp) => Some(Rm::Base(*base as u8, *disp)),
+        AsmArg::Sym(name) => Some(Rm::Rip(name)),
+        _ => None,
+    }
+}
+
+fn mem(arg: &AsmArg) -> Option<Rm<'_>> {
+    rm(arg).filter(|rm| !matches!(rm, Rm::Reg(_)))
+}
+
+/// A float register, or memory
+fn xmm(arg: &AsmArg) -> Option<Rm<'_>> {
+    match arg {
+        AsmArg::FReg(r) => Some(Rm::Reg(*r as u8)),
+        arg => mem(arg),
+    }
+}
+
+/// The `reg` field of the ModRM byte of the ALU instructions taking an
+/// immediate, and the first of their opcodes
+fn alu(op: AsmCommand) -> Option<(u8, u8)> {
+    match op {
+        AsmCommand::Add => Some((0, 0x00)),
+        AsmCommand::Sub => Some((5, 0x28)),
+        AsmCommand::Cmp => Some((7, 0x38)),
+        _ => None,
+    }
+}
+
+/// The mandatory prefix, the opcode after `0f`, and the opcode storing to
+/// memory, of the SSE instructions
+fn sse(op: AsmCommand) -> Option<(Option<u8>, u8, Option<u8>)> {
+    match op {
+        AsmCommand::Movss => Some((Some(0xf3), 0x10, Some(0x11))),
+        AsmCommand::Movsd => Some((Some(0xf2), 0x10, Some(0x11))),
+        AsmCommand::Addss => Some((Some(0xf3), 0x58, None)),
+        AsmCommand::Addsd => Some((Some(0xf2), 0x58, None)),
+        AsmCommand::Subss => Some((Some(0xf3), 0x5c, None)),
+        AsmCommand::Subsd => Some((Some(0xf2), 0x5c, None)),
+        AsmCommand::Ucomiss => Some((None, 0x2e, None)),
+        AsmCommand::Ucomisd => Some((Some(0x66), 0x2e, None)),
+        AsmCommand::Cvtss2sd => Some((Some(0xf3), 0x5a, None)),
+        _ => None,
+    }
+}
+
+/// The opcode of a jump or a call to a label
+fn branch(op: AsmCommand) -> Option<&'static [u8]> {
+    match op {
+        AsmCommand::Jmp => Some(&[0xe9]),
+        AsmCommand::Call => Some(&[0xe8]),
+        AsmCommand::Je => Some(&[0x0f, 0x84]),
+        AsmCommand::Jne => Some(&[0x0f, 0x85]),
+        AsmCommand::Jp => Some(&[0x0f, 0x8a]),
+        _ => None,
+    }
+}
+
+/// Encodes instructions to the `.text` of an object
+#[derive(Debug, Default)]
+struct Encoder {
+    code: Vec<u8>,
+    fixups: Vec<Fixup>,
+}
+
+impl Encoder {
+    fn push(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn imm(&mut self, imm: i64, size: AsmSize) {
+        self.push(&imm.to_le_bytes()[..size.bytes() as usize]);
+    }
+
+    /// The REX prefix, if the instruction needs one. A byte operand in
+    /// `spl`, `bpl`, `sil` or `dil` needs it to not be `ah`, `ch`, `dh` or
+    /// `bh`.
+    fn rex(&mut self, w: bool, reg: u8, rm: Rm, force: bool) {
+        let rex = 0x40 | (w as u8) << 3 | (reg >> 3 & 1) << 2 | (rm.base() >> 3 & 1);
+        if rex != 0x40 || force {
+            self.push(&[rex]);
+        }
+    }
+
+    /// The ModRM byte, and the SIB byte and displacement it needs.
+    /// `trailing` is the size of the immediate after it.
+    fn modrm(&mut self, reg: u8, rm: Rm, trailing: usize) -> Option<()> {
+        let reg = (reg & 7) << 3;
+        match rm {
+            Rm::Reg(r) => self.push(&[0xc0 | reg | (r & 7)]),
+            Rm::Base(base, disp) => {
+                let b = base & 7;
+                let mode = if disp == 0 && b != 5 {
+                    0x00
+                } else if fits_i8(disp) {
+                    0x40
+                } else if fits_i32(disp) {
+                    0x80
+                } else {
+                    return None;
+                };
+                self.push(&[mode | reg | b]);
+                if b == 4 {
+                    // `rsp` and `r12` as a base go through a SIB byte
+                    self.push(&[0x24]);
+                }
+                match mode {
+                    0x40 => self.imm(disp, AsmSize::Byte),
+                    0x80 => self.imm(disp, AsmSize::Dword),
+                    _ => {}
+                }
+            }
+            Rm::Rip(target) => {
+                self.push(&[reg | 5]);
+                self.fixups.push(Fixup {
+                    offset: self.code.len(),
+                    target: target.to_string(),
+                    branch: false,
+                    addend: -4 - trailing as i64,
+                });
+                self.push(&[0; 4]);
+            }
+        }
+        Some(())
+    }
+
+    /// An instruction with a ModRM byte: its prefixes, `opcode` and the
+    /// operands of the ModRM byte
+    fn inst(
+        &mut self,
+        prefix: Option<u8>,
+        (w, force_rex): (bool, bool),
+        opcode: &[u8],
+        (reg, rm): (u8, Rm),
+        trailing: usize,
+    ) -> Option<()> {
+        if let Some(prefix) = prefix {
+            self.push(&[prefix]);
+        }
+        self.rex(w, reg, rm, force_rex);
+        self.push(opcode);
+        self.modrm(reg, rm, trailing)
+    }
+
+    /// An instruction on integers of `size`, picking the byte or the full
+    /// size form of `opcode`. `reg` is a register, or an extension of the
+    /// opcode when `digit` is set.
+    fn sized(
+        &mut self,
+        size: AsmSize,
+        opcode: u8,
+        (reg, digit): (u8, bool),
+        rm: Rm,
+        trailing: usize,
+    ) -> Option<()> {
+        let byte_reg = |r: u8| (4..8).contains(&r);
+        let (opcode, force) = match size {
+            AsmSize::Byte => {
+                let force = (!digit && byte_reg(reg)) || matches!(rm, Rm::Reg(r) if byte_reg(r));
+                (opcode, force)
+            }
+            _ => (opcode + 1, false),
+        };
+        let prefix = (size == AsmSize::Word).then_some(0x66);
+        self.inst(
+            prefix,
+            (size == AsmSize::Qword, force),
+            &[opcode],
+            (reg, rm),
+            trailing,
+        )
+    }
+
+    fn encode(&mut self, asm: &Asm) -> EncodeResult<()> {
+        let args: Vec<&AsmArg> = asm.asm_arg.iter().filter(|a| **a != AsmArg::None).collect();
+        // Only the first sixteen registers of each class exist on x86-64
+        let exists = args.iter().all(|arg| match arg {
+            AsmArg::Reg(r) | AsmArg::Mem(r, _) => (*r as usize) < 16,
+            AsmArg::FReg(r) => (*r as usize) < 16,
+            _ => true,
+        });
+        if !exists {
+            return Err(EncodeError::InvalidOperands(format!("{:?}", asm)));
+        }
+        self.encode_args(asm, &args)
+            .ok_or_else(|| EncodeError::InvalidOperands(print_att_asm(asm)))
+    }
+
+    fn encode_args(&mut self, asm: &Asm, args: &[&AsmArg]) -> Option<()> {
+        let size = asm.size;
+        let word = (size == AsmSize::Word).then_some(0x66);
+        let qword = size == AsmSize::Qword;
+        // The immediates are at most 32 bits, sign-extended for a qword
+        let imm_size = match size {
+            AsmSize::Qword => AsmSize::Dword,
+            size => size,
+        };
+        let op = asm.asm_op;
+        match (op, args) {
+            (AsmCommand::Leave, []) => self.push(&[0xc9]),
+            (AsmCommand::Ret, []) => self.push(&[0xc3]),
+            (AsmCommand::Ud2, []) => self.push(&[0x0f, 0x0b]),
+            (AsmCommand::Push | AsmCommand::Pop, [AsmArg::Reg(r)]) => {
+                let r = *r as u8;
+                self.rex(false, 0, Rm::Reg(r), false);
+                let base = if op == AsmCommand::Push { 0x50 } else { 0x58 };
+                self.push(&[base + (r & 7)]);
+            }
+            (_, [AsmArg::Label(target)]) => {
+                let opcode = branch(op)?;
+                self.push(opcode);
+                self.fixups.push(Fixup {
+                    offset: self.code.len(),
+                    target: target.clone(),
+                    branch: true,
+                    addend: -4,
+                });
+                self.push(&[0; 4]);
+            }
+            (AsmCommand::Mov, [AsmArg::Reg(d), AsmArg::Imm(imm)]) => {
+                let d = *d as u8;
+                if qword && fits_i32(*imm) {
+                    self.inst(None, (true, false), &[0xc7], (0, Rm::Reg(d)), 4)?;
+                    self.imm(*imm, AsmSize::Dword);
+                } else if fits(*imm, size) {
+                    // `mov` to a register has the register in its opcode, and
+                    // an immediate of the full size
+                    if let Some(prefix) = word {
+                        self.push(&[prefix]);
+                    }
+                    let byte = size == AsmSize::Byte;
+                    self.rex(qword, 0, Rm::Reg(d), byte && (4..8).contains(&d));
+                    self.push(&[if byte { 0xb0 } else { 0xb8 } + (d & 7)]);
+                    self.imm(*imm, size);
+                } else {
+                    return None;
+                }
+            }
+            (AsmCommand::Mov, [dst, AsmArg::Imm(imm)]) => {
+                let dst = mem(dst)?;
+                if !fits(*imm, imm_size) || (qword && !fits_i32(*imm)) {
+                    return None;
+                }
+                self.sized(size, 0xc6, (0, true), dst, imm_size.bytes() as usize)?;
+                self.imm(*imm, imm_size);
+            }
+            (AsmCommand::Mov, [dst, AsmArg::Reg(s)]) => {
+                self.sized(size, 0x88, (*s as u8, false), rm(dst)?, 0)?;
+            }
+            (AsmCommand::Mov, [AsmArg::Reg(d), src]) => {
+                self.sized(size, 0x8a, (*d as u8, false), mem(src)?, 0)?;
+            }
+            (AsmCommand::Add | AsmCommand::Sub | AsmCommand::Cmp, [dst, AsmArg::Imm(imm)]) => {
+                let (digit, base) = alu(op)?;
+                let dst = rm(dst)?;
+                if !fits(*imm, imm_size) || (qword && !fits_i32(*imm)) {
+                    return None;
+                }
+                let accumulator = matches!(dst, Rm::Reg(0));
+                if size != AsmSize::Byte && fits_i8(*imm) {
+                    self.inst(word, (qword, false), &[0x83], (digit, dst), 1)?;
+                    self.imm(*imm, AsmSize::Byte);
+                } else if accumulator {
+                    // The short form on `al`, `ax`, `eax` or `rax`
+                    if let Some(prefix) = word {
+                        self.push(&[prefix]);
+                    }
+                    self.rex(qword, 0, dst, false);
+                    let opcode = if size == AsmSize::Byte {
+                        base + 4
+                    } else {
+                        base + 5
+                    };
+                    self.push(&[opcode]);
+                    self.imm(*imm, imm_size);
+                } else {
+                    self.sized(size, 0x80, (digit, true), dst, imm_size.bytes() as usize)?;
+                    self.imm(*imm, imm_size);
+                }
+            }
+            (AsmCommand::Add | AsmCommand::Sub | AsmCommand::Cmp, [dst, AsmArg::Reg(s)]) => {
+                let (_, base) = alu(op)?;
+                self.sized(size, base, (*s as u8, false), rm(dst)?, 0)?;
+            }
+            (AsmCommand::Add | AsmCommand::Sub | AsmCommand::Cmp, [AsmArg::Reg(d), src]) => {
+                let (_, base) = alu(op)?;
+                self.sized(size, base + 2, (*d as u8, false), mem(src)?, 0)?;
+            }
+            (AsmCommand::Mul, [AsmArg::Reg(d), AsmArg::Imm(imm)]) if size != AsmSize::Byte => {
+                let d = *d as u8;
+                if fits_i8(*imm) {
+                    self.inst(word, (qword, false), &[0x6b], (d, Rm::Reg(d)), 1)?;
+                    self.imm(*imm, AsmSize::Byte);
+                } else if fits(*imm, imm_size) && (!qword || fits_i32(*imm)) {
+                    self.inst(
+                        word,
+                        (qword, false),
+                        &[0x69],
+                        (d, Rm::Reg(d)),
+                        imm_size.bytes() as usize,
+                    )?;
+                    self.imm(*imm, imm_size);
+                } else {
+                    return None;
+                }
+            }
+            (AsmCommand::Mul, [AsmArg::Reg(d), src]) if size != AsmSize::Byte => {
+                self.inst(word, (qword, false), &[0x0f, 0xaf], (*d as u8, rm(src)?), 0)?;
+            }
+            (AsmCommand::Div, [src]) => {
+                self.sized(size, 0xf6, (7, true), rm(src)?, 0)?;
+            }
+            (AsmCommand::Lea, [AsmArg::Reg(d), src]) => {
+                self.inst(None, (true, false), &[0x8d], (*d as u8, mem(src)?), 0)?;
+            }
+            (AsmCommand::Movsx, [AsmArg::Reg(d), src]) => {
+                let opcode: &[u8] = match size {
+                    AsmSize::Byte => &[0x0f, 0xbe],
+                    AsmSize::Word => &[0x0f, 0xbf],
+                    AsmSize::Dword => &[0x63],
+                    AsmSize::Qword => return None,
+                };
+                self.inst(None, (true, false), opcode, (*d as u8, rm(src)?), 0)?;
+            }
+            (AsmCommand::Movzx, [AsmArg::Reg(d), src]) => {
+                let opcode: &[u8] = match size {
+                    AsmSize::Byte => &[0x0f, 0xb6],
+                    AsmSize::Word => &[0x0f, 0xb7],
+                    _ => return None,
+                };
+                self.inst(None, (true, false), opcode, (*d as u8, rm(src)?), 0)?;
+            }
+            (_, [dst, src]) => {
+                let (prefix, load, store) = sse(op)?;
+                match (dst, src) {
+                    (AsmArg::FReg(d), src) => self.inst(
+                        prefix,
+                        (false, false),
+                        &[0x0f, load],
+                        (*d as u8, xmm(src)?),
+                        0,
+                    )?,
+                    (dst, AsmArg::FReg(s)) => self.inst(
+                        prefix,
+                        (false, false),
+                        &[0x0f, store?],
+                        (*s as u8, mem(dst)?),
+                        0,
+                    )?,
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+fn data_bytes(value: &AsmDataValue, out: &mut Vec<u8>) {
+    match value {
+        AsmDataValue::Byte(b) => out.push(*b),
+        AsmDataValue::Word(w) => out.extend_from_slice(&w.to_le_bytes()),
+        AsmDataValue::Dword(d) => out.extend_from_slice(&d.to_le_bytes()),
+        AsmDataValue::Qword(q) => out.extend_from_slice(&q.to_le_bytes()),
+        AsmDataValue::String(s) => {
+            out.extend_from_slice(s.as_bytes());
+            out.push(0);
+        }
+        AsmDataValue::Bytes(bytes) => out.extend_from_slice(bytes),
+    }
+}
+
+/// Encode `program` to machine code.
+///
+/// The labels of `.text` are in the order of the program, and every jump
+/// and call takes a 32-bit displacement. The references to the labels of
+/// the program are resolved, the ones to the data through a relocation
+/// against its section, and the other symbols are left to the linker.
+pub fn encode(program: &AsmProgram) -> EncodeResult<Object> {
+    let mut object = Object {
+        data_align: 1,
+        rodata_align: 1,
+        ..Object::default()
+    };
+    let mut defined: HashMap<String, (Section, u64)> = HashMap::new();
+    let mut define = |object: &mut Object, name: &str, section, offset, global| {
+        if defined
+            .insert(name.to_string(), (section, offset))
+            .is_some()
+        {
+            return Err(EncodeError::DuplicateLabel(name.to_string()));
+        }
+        object.symbols.push(Symbol {
+            name: name.to_string(),
+            section: Some(section),
+            offset,
+            global,
+            function: global && section == Section::Text,
+        });
+        Ok(())
+    };
+
+    for data in &program.data {
+        let align = data.align.max(1);
+        let (bytes, max_align, section) = match data.section {
+            AsmSection::Data => (&mut object.data, &mut object.data_align, Section::Data),
+            AsmSection::Rodata => (
+                &mut object.rodata,
+                &mut object.rodata_align,
+                Section::Rodata,
+            ),
+        };
+        *max_align = (*max_align).max(align);
+        while !(bytes.len() as u64).is_multiple_of(align) {
+            bytes.push(0);
+        }
+        let offset = bytes.len() as u64;
+        for value in &data.data {
+            data_bytes(value, bytes);
+        }
+        define(&mut object, &data.label, section, offset, false)?;
+    }
+
+    let mut encoder = Encoder::default();
+    for label in &program.labels {
+        let offset = encoder.code.len() as u64;
+        define(
+            &mut object,
+            &label.label,
+            Section::Text,
+            offset,
+            label.global,
+        )?;
+        for asm in &label.asm {
+            encoder.encode(asm)?;
+        }
+    }
+
+    let mut text = encoder.code;
+    let mut externs: Vec<String> = Vec::new();
+    for fixup in encoder.fixups {
+        let place = fixup.offset as u64;
+        match defined.get(&fixup.target) {
+            Some((Section::Text, offset)) => {
+                let value = *offset as i64 + fixup.addend - place as i64;
+                let value = i32::try_from(value)
+                    .map_err(|_| EncodeError::InvalidOperands(fixup.target.clone()))?;
+                text[fixup.offset..fixup.offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            Some((section, offset)) => object.relocations.push(Relocation {
+                offset: place,
+                target: RelocTarget::Section(*section),
+                kind: RelocKind::Pc32,
+                addend: *offset as i64 + fixup.addend,
+            }),
+            None if fixup.target.starts_with(".L") => {
+                return Err(EncodeError::UnknownLabel(fixup.target));
+            }
+            None => {
+                if !externs.contains(&fixup.target) {
+                    externs.push(fixup.target.clone());
+                }
+                object.relocations.push(Relocation {
+                    offset: place,
+                    target: RelocTarget::Symbol(fixup.target),
+                    kind: if fixup.branch {
+                        RelocKind::Plt32
+                    } else {
+                        RelocKind::Pc32
+                    },
+                    addend: fixup.addend,
+                });
+            }
+        }
+    }
+    object.text = text;
+    object
+        .symbols
+        .extend(externs.into_iter().map(|name| Symbol {
+            name,
+            section: None,
+            offset: 0,
+            global: true,
+            function: false,
+        }));
+    Ok(object)
+}
+
+/// Encode one instruction on its own. A reference to a label is left as
+/// zeros.
+pub fn encode_asm(asm: &Asm) -> EncodeResult<Vec<u8>> {
+    let mut encoder = Encoder::default();
+    encoder.encode(asm)?;
+    Ok(encoder.code)
+}
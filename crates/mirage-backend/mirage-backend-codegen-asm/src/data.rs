@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use mirage_backend_asm::builder::{AsmData, AsmDataValue, AsmSection};
+use mirage_frontend::object::util::to_string_with_special_char;
+use mirage_frontend::object::MirageValueEnum;
+
+use crate::layout::{align_of, size_of, struct_layout};
+use crate::{CodeGenError, CodeGenResult};
+
+/// The data of a program besides its code: the globals, the string
+/// constants and the float constants, the last two in `.rodata`
+#[derive(Debug, Clone, Default)]
+pub struct StaticData {
+    items: Vec<AsmData>,
+    strings: HashMap<String, String>,
+    floats: HashMap<(u64, u64), String>,
+}
+
+impl StaticData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_items(self) -> Vec<AsmData> {
+        self.items
+    }
+
+    fn constant(&mut self, align: u64, value: AsmDataValue) -> String {
+        let label = format!(".LC{}", self.items.len());
+        self.items.push(AsmData::new(
+            label.clone(),
+            AsmSection::Rodata,
+            align,
+            vec![value],
+        ));
+        label
+    }
+
+    /// The label of a constant string, NUL terminated and with its escapes
+    /// replaced. Equal strings share their label.
+    pub fn string(&mut self, s: &str) -> String {
+        if let Some(label) = self.strings.get(s) {
+            return label.clone();
+        }
+        let value = AsmDataValue::String(to_string_with_special_char(s));
+        let label = self.constant(1, value);
+        self.strings.insert(s.to_string(), label.clone());
+        label
+    }
+
+    /// The label of a float constant of `size` bytes with the bits `bits`
+    fn float(&mut self, size: u64, bits: u64) -> String {
+        if let Some(label) = self.floats.get(&(size, bits)) {
+            return label.clone();
+        }
+        let value = match size {
+            4 => AsmDataValue::Dword(bits as u32),
+            _ => AsmDataValue::Qword(bits),
+        };
+        let label = self.constant(size, value);
+        self.floats.insert((size, bits), label.clone());
+        label
+    }
+
+    pub fn float32(&mut self, v: f32) -> String {
+        self.float(4, v.to_bits() as u64)
+    }
+
+    pub fn float64(&mut self, v: f64) -> String {
+        self.float(8, v.to_bits())
+    }
+
+    /// Lay out a global named `name` initialized to `value`, in `.rodata`
+    /// if it is never written. A string global is NUL terminated, like a
+    /// string constant.
+    pub fn global(
+        &mut self,
+        name: &str,
+        value: &MirageValueEnum,
+        read_only: bool,
+    ) -> CodeGenResult<()> {
+        let section = if read_only {
+            AsmSection::Rodata
+        } else {
+            AsmSection::Data
+        };
+        let ty = value.get_type();
+        let data = match value.try_to_rust_string() {
+            Some(s) => AsmDataValue::String(to_string_with_special_char(&s)),
+            None => {
+                let mut bytes = vec![0; size_of(&ty) as usize];
+                write(&mut bytes, 0, value)?;
+                AsmDataValue::Bytes(bytes)
+            }
+        };
+        self.items.push(AsmData::new(
+            name.to_string(),
+            section,
+            align_of(&ty),
+            vec![data],
+        ));
+        Ok(())
+    }
+}
+
+fn write(bytes: &mut [u8], offset: u64, value: &MirageValueEnum) -> CodeGenResult<()> {
+    let mut put = |b: &[u8]| {
+        let start = offset as usize;
+        bytes[start..start + b.len()].copy_from_slice(b);
+    };
+    match value {
+        MirageValueEnum::Int8(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Int16(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Int32(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Int64(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::UInt8(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::UInt16(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::UInt32(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::UInt64(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Float32(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Float64(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Array(a) => {
+            let size = size_of(&a.ty.element_ty());
+            for (i, v) in a.values.iter().enumerate() {
+                write(bytes, offset + i as u64 * size, v)?;
+            }
+        }
+        MirageValueEnum::Struct(s) => {
+            let (_, offsets) = struct_layout(&s.ty.fields);
+            for (v, field) in s.values.iter().zip(offsets) {
+                write(bytes, offset + field, v)?;
+            }
+        }
+        MirageValueEnum::Pointer(_) | MirageValueEnum::Register(_) => {
+            return Err(CodeGenError::Unsupported(value.print_to_string()))
+        }
+    }
+    Ok(())
+}
@@ -1,18 +1,22 @@
 use std::collections::HashMap;
 
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::MirageTypeEnum;
 
-
-
-#[derive(Debug, Clone)]
+/// What the functions of a program are compiled against
+#[derive(Debug, Clone, Default)]
 pub struct Environement {
+    /// The assembly label of every label of the function being compiled
     labels: HashMap<String, String>,
+    functions: HashMap<String, FunctionType>,
+    /// The symbol and the type of every global, by index
+    globals: Vec<(String, MirageTypeEnum)>,
+    structs: HashMap<String, Vec<MirageTypeEnum>>,
 }
 
 impl Environement {
     pub fn new() -> Self {
-        Self {
-            labels: HashMap::new(),
-        }
+        Self::default()
     }
 
     pub fn add_label(&mut self, name: String, label: String) {
@@ -22,4 +26,32 @@ impl Environement {
     pub fn get_label(&self, name: &String) -> Option<&String> {
         self.labels.get(name)
     }
+
+    pub fn clear_labels(&mut self) {
+        self.labels.clear();
+    }
+
+    pub fn add_function(&mut self, name: String, ty: FunctionType) {
+        self.functions.insert(name, ty);
+    }
+
+    pub fn get_function(&self, name: &str) -> Option<&FunctionType> {
+        self.functions.get(name)
+    }
+
+    pub fn add_global(&mut self, symbol: String, ty: MirageTypeEnum) {
+        self.globals.push((symbol, ty));
+    }
+
+    pub fn get_global(&self, index: usize) -> Option<&(String, MirageTypeEnum)> {
+        self.globals.get(index)
+    }
+
+    pub fn add_struct(&mut self, name: String, fields: Vec<MirageTypeEnum>) {
+        self.structs.insert(name, fields);
+    }
+
+    pub fn get_struct(&self, name: &str) -> Option<&Vec<MirageTypeEnum>> {
+        self.structs.get(name)
+    }
 }
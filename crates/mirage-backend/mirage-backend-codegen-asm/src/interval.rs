@@ -0,0 +1,187 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_backend_opti::analysis::cfg::Cfg;
+use mirage_backend_opti::ir::{
+    for_each_use, is_phi, phi_incoming, reg_key, value_register, RegKey,
+};
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::LabelBodyInstr;
+use mirage_frontend::object::{RegisterType, RegisterValue};
+
+fn is_local(reg: &RegisterValue) -> bool {
+    reg.register_type != RegisterType::Global
+}
+
+fn is_call(instr: &LabelBodyInstr) -> bool {
+    match instr {
+        LabelBodyInstr::Assign(_, instr) => is_call(instr),
+        LabelBodyInstr::Call(..) => true,
+        LabelBodyInstr::Command(_) => false,
+    }
+}
+
+/// The registers read by the copies to the phis of `to` coming from the
+/// label `from`, and the phis they are copied to
+fn edge_copies(
+    func: &FunctionValue,
+    from: &str,
+    to: usize,
+    defs: &mut HashSet<RegKey>,
+    uses: &mut HashSet<RegKey>,
+) {
+    for instr in func.get_labels()[to].body.iter().take_while(|i| is_phi(i)) {
+        let (LabelBodyInstr::Assign(r, _), Some(incoming)) = (instr, phi_incoming(instr)) else {
+            continue;
+        };
+        for (label, value) in incoming {
+            if label != from {
+                continue;
+            }
+            defs.insert(reg_key(r));
+            if let Some(reg) = value_register(value).filter(|reg| is_local(reg)) {
+                uses.insert(reg_key(reg));
+            }
+        }
+    }
+}
+
+/// The live intervals of the local registers of a function: the first and
+/// the last position each one is live at.
+///
+/// The instructions are numbered in the order of the labels, and the phis
+/// are assigned on the edges, as the code generator does: between the
+/// last instruction of the block they come from and the next one. The
+/// arguments are assigned at position 0, before the first label.
+#[derive(Debug, Clone, Default)]
+pub struct LiveIntervals {
+    ranges: HashMap<RegKey, (usize, usize)>,
+    /// The positions of the calls
+    calls: Vec<usize>,
+}
+
+impl LiveIntervals {
+    pub fn new(func: &FunctionValue) -> Self {
+        let labels = func.get_labels();
+        let cfg = Cfg::new(func);
+        let mut offsets = Vec::with_capacity(labels.len());
+        let mut offset = 0;
+        for label in labels {
+            offsets.push(offset);
+            offset += 2 * label.body.len() + 2;
+        }
+        // Instruction `i` of label `l`, then the copies on the edges of the
+        // block ending before instruction `end`
+        let at = |l: usize, i: usize| offsets[l] + 2 * i + 1;
+        let edge = |l: usize, end: usize| offsets[l] + 2 * end;
+
+        // The phis a block assigns, and the registers it reads for them
+        let mut edge_defs = vec![HashSet::new(); cfg.len()];
+        let mut edge_uses = vec![HashSet::new(); cfg.len()];
+        for b in 0..cfg.len() {
+            let from = &labels[cfg.blocks[b].label].name;
+            for s in &cfg.succs[b] {
+                let block = cfg.blocks[*s];
+                if block.start == 0 {
+                    edge_copies(
+                        func,
+                        from,
+                        block.label,
+                        &mut edge_defs[b],
+                        &mut edge_uses[b],
+                    );
+                }
+            }
+        }
+
+        let step = |live: &mut HashSet<RegKey>, instr: &LabelBodyInstr| {
+            if let LabelBodyInstr::Assign(r, _) = instr {
+                live.remove(&reg_key(r));
+            }
+            for_each_use(instr, &mut |reg, _| {
+                if is_local(reg) {
+                    live.insert(reg_key(reg));
+                }
+            });
+        };
+
+        // Liveness, iterated to a fixed point
+        let mut live_in: Vec<HashSet<RegKey>> = vec![HashSet::new(); cfg.len()];
+        let mut live_out: Vec<HashSet<RegKey>> = vec![HashSet::new(); cfg.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in (0..cfg.len()).rev() {
+                let out: HashSet<RegKey> = cfg.succs[b]
+                    .iter()
+                    .flat_map(|s| live_in[*s].iter().copied())
+                    .collect();
+                let mut live = out.clone();
+                live.retain(|k| !edge_defs[b].contains(k));
+                live.extend(edge_uses[b].iter().copied());
+                for instr in cfg.instrs(func, b).iter().rev() {
+                    if !is_phi(instr) {
+                        step(&mut live, instr);
+                    }
+                }
+                if live != live_in[b] || out != live_out[b] {
+                    live_in[b] = live;
+                    live_out[b] = out;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut intervals = LiveIntervals::default();
+        for i in 0..func.get_type().get_args().len() {
+            intervals.extend((RegisterType::Argument, i), 0);
+        }
+        for b in 0..cfg.len() {
+            let block = cfg.blocks[b];
+            for key in &live_in[b] {
+                intervals.extend(*key, edge(block.label, block.start));
+            }
+            for key in live_out[b].iter().chain(&edge_defs[b]).chain(&edge_uses[b]) {
+                intervals.extend(*key, edge(block.label, block.end));
+            }
+            let body = cfg.instrs(func, b);
+            for (i, instr) in body.iter().enumerate() {
+                if is_phi(instr) {
+                    continue;
+                }
+                let pos = at(block.label, block.start + i);
+                if is_call(instr) {
+                    intervals.calls.push(pos);
+                }
+                if let LabelBodyInstr::Assign(r, _) = instr {
+                    intervals.extend(reg_key(r), pos);
+                }
+                for_each_use(instr, &mut |reg, _| {
+                    if is_local(reg) {
+                        intervals.extend(reg_key(reg), pos);
+                    }
+                });
+            }
+        }
+        intervals
+    }
+
+    fn extend(&mut self, key: RegKey, pos: usize) {
+        let range = self.ranges.entry(key).or_insert((pos, pos));
+        range.0 = range.0.min(pos);
+        range.1 = range.1.max(pos);
+    }
+
+    /// The first and the last position `key` is live at
+    pub fn range(&self, key: &RegKey) -> Option<(usize, usize)> {
+        self.ranges.get(key).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&RegKey, &(usize, usize))> {
+        self.ranges.iter()
+    }
+
+    /// Whether a register live from `start` to `end` has to survive a call
+    pub fn crosses_call(&self, start: usize, end: usize) -> bool {
+        self.calls.iter().any(|c| start < *c && *c < end)
+    }
+}
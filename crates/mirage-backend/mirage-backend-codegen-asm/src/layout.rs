@@ -0,0 +1,48 @@
+use mirage_backend_opti::layout::Layout;
+pub use mirage_backend_opti::layout::{align_to, is_aggregate};
+use mirage_frontend::object::MirageTypeEnum;
+
+/// The size of an address on the 64-bit targets
+pub const POINTER_SIZE: u64 = 8;
+
+/// The layout of the values in memory
+const LAYOUT: Layout = Layout::new(POINTER_SIZE);
+
+/// The number of bytes taken by a value of `ty` in memory
+pub fn size_of(ty: &MirageTypeEnum) -> u64 {
+    LAYOUT.size_of(ty)
+}
+
+pub fn align_of(ty: &MirageTypeEnum) -> u64 {
+    LAYOUT.align_of(ty)
+}
+
+/// The size of a struct with `fields` and the offset of every field
+pub fn struct_layout(fields: &[MirageTypeEnum]) -> (u64, Vec<u64>) {
+    LAYOUT.struct_layout(fields)
+}
+
+/// How a value is held in registers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    /// An integer or an address of the given size in bytes, sign-extended
+    /// to 64 bits in a register, whether it is signed or not
+    Int(u64),
+    F32,
+    F64,
+}
+
+impl Class {
+    pub fn is_float(&self) -> bool {
+        matches!(self, Class::F32 | Class::F64)
+    }
+}
+
+pub fn class_of(ty: &MirageTypeEnum) -> Class {
+    match ty {
+        MirageTypeEnum::Float32(_) => Class::F32,
+        MirageTypeEnum::Float64(_) => Class::F64,
+        MirageTypeEnum::Array(_) | MirageTypeEnum::Struct(_) => Class::Int(POINTER_SIZE),
+        _ => Class::Int(size_of(ty)),
+    }
+}
@@ -1,30 +1,102 @@
 #![allow(dead_code)]
+#[cfg(test)]
+mod test;
+
+mod aarch64;
+mod data;
 mod register;
 mod environement;
+mod interval;
+mod layout;
+mod moves;
+mod operand;
+mod riscv64;
+mod x86_64;
+
+use std::path::Path;
 
+use data::StaticData;
 use environement::Environement;
-use mirage_backend_asm::builder::{AsmLabel, AsmLabelBuilder, AsmProgram, AsmProgramBuilder, Reg};
+use mirage_backend_asm::aarch64::{self as asm_aarch64, Flavor};
+use mirage_backend_asm::builder::{AsmLabel, AsmProgram, AsmProgramBuilder};
+use mirage_backend_asm::riscv64 as asm_riscv64;
+use mirage_backend_asm::x86_64::encoder::{encode, EncodeError};
+use mirage_backend_asm::x86_64::{print, Syntax};
 use mirage_frontend::{
     module::Module,
-    object::{
-        function::FunctionValue, label::{Command, Label}, statements::Statement
-    }
+    object::function::FunctionValue,
+    object::statements::{Arch, Os, Statement},
 };
-use register::RegisterAllocator;
 
-#[derive(Debug, Clone)]
+/// A code generation error
+/// # Variants
+/// * `UnknownFunction` - A call to a function which isn't declared
+/// * `UnknownType` - A `new` of a type which isn't defined
+/// * `UnknownGlobal` - A global register without its global
+/// * `UnknownLabel` - A jump to a label which isn't in the function
+/// * `ArgumentCount` - A call with too few arguments
+/// * `UnsupportedArch` - A target whose architecture has no backend
+/// * `Unsupported` - Something the backend can't express, such as a global
+///   holding a pointer
+/// * `Encode` - The compiled program couldn't be encoded to machine code
+#[derive(Debug, Clone, PartialEq)]
 pub enum CodeGenError {
-    InvalidStatement
+    UnknownFunction(String),
+    UnknownType(String),
+    UnknownGlobal(usize),
+    UnknownLabel(String),
+    ArgumentCount(String),
+    UnsupportedArch(Arch),
+    Unsupported(String),
+    Encode(EncodeError),
+}
+
+impl std::fmt::Display for CodeGenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodeGenError::UnknownFunction(name) => write!(f, "call to unknown function `{}`", name),
+            CodeGenError::UnknownType(name) => write!(f, "unknown type `{}`", name),
+            CodeGenError::UnknownGlobal(index) => write!(f, "unknown global g{}", index),
+            CodeGenError::UnknownLabel(label) => write!(f, "unknown label `{}`", label),
+            CodeGenError::ArgumentCount(name) => {
+                write!(f, "too few arguments in a call to `{}`", name)
+            }
+            CodeGenError::UnsupportedArch(arch) => {
+                write!(f, "no native backend for {}", arch.to_str())
+            }
+            CodeGenError::Unsupported(what) => write!(f, "unsupported: `{}`", what),
+            CodeGenError::Encode(err) => write!(f, "{}", err),
+        }
+    }
 }
 
-type CodeGenResult<T> = Result<T, CodeGenError>;
+impl std::error::Error for CodeGenError {}
+
+pub type CodeGenResult<T> = Result<T, CodeGenError>;
+
+/// A compiled program, in the instructions of its architecture
+#[derive(Debug, Clone)]
+pub enum NativeProgram {
+    X86_64(AsmProgram),
+    Aarch64(AsmProgram<asm_aarch64::Inst>),
+    RiscV64(AsmProgram<asm_riscv64::Inst>),
+}
 
+/// The native code generator, which emits x86-64 assembly for the System V
+/// ABI, AArch64 assembly for the AAPCS64 when the target is `arm64` or
+/// `aarch64`, or RV64GC assembly for the RISC-V psABI when it is
+/// `riscv64`. Without a target, the program is compiled to x86-64.
+///
+/// Every function gets a global symbol, unless it is `#internal`, and the
+/// externs are left for the linker to resolve. The globals are in `.data`,
+/// or in `.rodata` when they are `#const`, under their name.
 #[derive(Debug, Clone)]
 pub struct CodeGen {
     stmts: Vec<Statement>,
-    code: AsmProgram,
-    builder: AsmProgramBuilder,
-    reg_alloc: RegisterAllocator,
+    code: NativeProgram,
+    arch: Arch,
+    /// The object format the AArch64 assembly is written for
+    flavor: Flavor,
     module: Module,
     env: Environement,
     lid: usize
@@ -32,53 +104,135 @@ pub struct CodeGen {
 
 impl CodeGen {
     pub fn new(stmts: Vec<Statement>, module: Module) -> Self {
-        let mut reg_alloc = RegisterAllocator::all();
-        reg_alloc.make_reserved(Reg::R1); // R1 is reserved for the stack pointer
-        reg_alloc.make_reserved(Reg::R2); // R2 is reserved for the frame pointer
-        reg_alloc.make_reserved(Reg::R3); // R3 is reserved for the return address
-
         Self {
             stmts,
-            code: AsmProgram::new(),
-            builder: AsmProgramBuilder::new(),
-            reg_alloc,
+            code: NativeProgram::X86_64(AsmProgram::new()),
+            arch: Arch::X86_64,
+            flavor: Flavor::Elf,
             module,
             env: Environement::new(),
             lid: 0,
         }
     }
 
-    pub fn compile(&mut self) -> Self {
-        for stmt in self.stmts.clone().iter() {
-            self.compile_stmt(stmt);
+    pub fn compile(&mut self) -> CodeGenResult<()> {
+        let mut data = StaticData::new();
+        for stmt in &self.stmts {
+            match stmt {
+                Statement::Target(t) => {
+                    self.arch = match t.0.arch {
+                        Arch::X86_64 | Arch::Unknown => Arch::X86_64,
+                        Arch::Arm64 => Arch::Arm64,
+                        Arch::RiscV64 => Arch::RiscV64,
+                        arch => return Err(CodeGenError::UnsupportedArch(arch)),
+                    };
+                    self.flavor = match t.0.os {
+                        Os::MacOs | Os::Ios => Flavor::MachO,
+                        _ => Flavor::Elf,
+                    };
+                }
+                Statement::Typedef(t) => {
+                    self.env.add_struct(t.name.clone(), t.ty.clone().into_vec());
+                }
+                Statement::Global(global) => {
+                    data.global(&global.name, global.value.get_value_ref(), global.is_const())?;
+                    self.env.add_global(global.name.clone(), global.value.get_type());
+                }
+                Statement::External(e) => {
+                    self.env.add_function(e.name.clone(), e.ty.clone());
+                }
+                Statement::Function(f) => {
+                    self.env.add_function(f.get_name().clone(), f.get_type().clone());
+                }
+                Statement::Module(_) => {}
+            }
         }
-        self.clone()
+
+        let flavor = self.flavor;
+        self.code = match self.arch {
+            Arch::Arm64 => NativeProgram::Aarch64(self.functions(data, |env, data, f, lid| {
+                let alloc = aarch64::register_allocator();
+                aarch64::FunctionCompiler::new(env, data, f, flavor, lid, alloc).compile()
+            })?),
+            Arch::RiscV64 => NativeProgram::RiscV64(self.functions(data, |env, data, f, lid| {
+                let alloc = riscv64::register_allocator();
+                riscv64::FunctionCompiler::new(env, data, f, lid, alloc).compile()
+            })?),
+            _ => NativeProgram::X86_64(self.functions(data, |env, data, f, lid| {
+                let alloc = x86_64::register_allocator();
+                x86_64::FunctionCompiler::new(env, data, f, lid, alloc).compile()
+            })?),
+        };
+        Ok(())
     }
 
-    pub fn compile_stmt(&mut self, stmt: &Statement) {
-        match stmt.clone() {
-            Statement::Function(f) => {
-                self.compile_function(f);
-            },
-            _ => {}
+    /// Compile every function with `compile`, then add the static data
+    fn functions<I>(
+        &mut self,
+        mut data: StaticData,
+        compile: impl Fn(
+            &Environement,
+            &mut StaticData,
+            &FunctionValue,
+            &mut usize,
+        ) -> CodeGenResult<Vec<AsmLabel<I>>>,
+    ) -> CodeGenResult<AsmProgram<I>> {
+        let mut builder = AsmProgramBuilder::new();
+        for stmt in &self.stmts {
+            let Statement::Function(f) = stmt else {
+                continue;
+            };
+            self.env.clear_labels();
+            for label in f.get_labels() {
+                self.env.add_label(
+                    label.name.clone(),
+                    format!(".L.{}.{}", f.get_name(), label.name),
+                );
+            }
+            for label in compile(&self.env, &mut data, f, &mut self.lid)? {
+                builder.build_label(label);
+            }
+        }
+        for item in data.into_items() {
+            builder.build_data(item);
         }
+        Ok(builder.build())
     }
 
-    pub fn compile_function(&mut self, func: FunctionValue) {
-        let label = AsmLabel::new(func.get_name().clone());
-        let builder = label.builder();
+    pub fn program(&self) -> &NativeProgram {
+        &self.code
     }
 
-    pub fn compile_label(&mut self, label: Label) -> AsmLabel {
-        let asmlabel = AsmLabel::new(label.name);
-        let builder = asmlabel.builder();
-        todo!()
+    /// The compiled program, as GNU assembler source in AT&T syntax
+    pub fn emit_asm(&self) -> String {
+        self.emit_asm_with(Syntax::Att)
     }
-    pub fn compile_command(&mut self, builder: &mut AsmLabelBuilder, command: Command) {
-        todo!()
+
+    /// The compiled program, as GNU assembler source in `syntax`. The
+    /// syntax only matters on x86-64.
+    pub fn emit_asm_with(&self, syntax: Syntax) -> String {
+        match &self.code {
+            NativeProgram::X86_64(code) => print(code, syntax),
+            NativeProgram::Aarch64(code) => asm_aarch64::print(code, self.flavor),
+            NativeProgram::RiscV64(code) => asm_riscv64::print(code),
+        }
+    }
+
+    /// The compiled program, as an ELF relocatable object, encoded without
+    /// an assembler. Only x86-64 has an encoder.
+    pub fn emit_object(&self) -> CodeGenResult<Vec<u8>> {
+        match &self.code {
+            NativeProgram::X86_64(code) => {
+                let object = encode(code).map_err(CodeGenError::Encode)?;
+                Ok(object.to_elf())
+            }
+            NativeProgram::Aarch64(_) => Err(CodeGenError::UnsupportedArch(Arch::Arm64)),
+            NativeProgram::RiscV64(_) => Err(CodeGenError::UnsupportedArch(Arch::RiscV64)),
+        }
     }
 
-    pub fn compile_value() {
-        todo!()
+    /// Write the compiled program to a `.s` file
+    pub fn write_to(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.emit_asm())
     }
 }
@@ -0,0 +1,135 @@
+use mirage_backend_asm::builder::{FReg, Reg};
+use mirage_frontend::object::label::Value;
+
+use crate::layout::Class;
+use crate::CodeGenResult;
+
+/// Where the value of a register is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Loc {
+    Reg(Reg),
+    FReg(FReg),
+    /// A slot at an offset from the frame pointer
+    Slot(i64),
+}
+
+/// What a copy of a parallel move reads: a register of some class, where
+/// it is, or any other value
+#[derive(Debug, Clone, Copy)]
+pub enum Src<'v> {
+    Loc(Loc, Class),
+    Value(&'v Value),
+}
+
+/// A copy of a parallel move: `dst`, of `class`, gets `src`
+#[derive(Debug, Clone, Copy)]
+pub struct Move<'v> {
+    pub dst: Loc,
+    pub class: Class,
+    pub src: Src<'v>,
+}
+
+/// A function compiler moving values between the locations of the
+/// registers, for the arguments and the phis
+pub trait ParallelMove {
+    /// The registers a copy between two slots goes through, and a value
+    /// to a slot is loaded to
+    const TEMP: Reg;
+    const FTEMP: FReg;
+    /// The registers a location is saved to, to break a cycle of copies
+    const CYCLE: Reg;
+    const FCYCLE: FReg;
+
+    /// Load a value of `class` from `loc` to `to`, or to `fto` if it is a
+    /// float
+    fn load_loc(&mut self, class: Class, loc: Loc, to: Reg, fto: FReg);
+
+    /// Store a value of `class` from `from`, or from `ffrom` if it is a
+    /// float, to `loc`. An integer kept in a register is sign-extended.
+    fn store_loc(&mut self, class: Class, loc: Loc, from: Reg, ffrom: FReg);
+
+    /// Load `value` to `to`, or to `fto` if it is a float
+    fn value(&mut self, value: &Value, to: Reg, fto: FReg) -> CodeGenResult<Class>;
+
+    /// Do `moves` as if they all read their source before any of them
+    /// writes its destination. The copies between registers are ordered so
+    /// that a location is read before it is overwritten, and a cycle is
+    /// broken by saving one of its locations to a scratch register. The
+    /// constants are loaded last.
+    fn parallel_move(&mut self, moves: Vec<Move>) -> CodeGenResult<()> {
+        let (mut pending, values): (Vec<_>, Vec<_>) = moves
+            .into_iter()
+            .filter(|m| !matches!(m.src, Src::Loc(loc, _) if loc == m.dst))
+            .partition(|m| matches!(m.src, Src::Loc(..)));
+        while !pending.is_empty() {
+            let read = |pending: &[Move], loc: Loc| {
+                pending.iter().find_map(|m| match m.src {
+                    Src::Loc(l, class) if l == loc => Some(class),
+                    _ => None,
+                })
+            };
+            match (0..pending.len()).find(|i| read(&pending, pending[*i].dst).is_none()) {
+                Some(i) => {
+                    let m = pending.remove(i);
+                    let Src::Loc(src, class) = m.src else {
+                        unreachable!("only the copies of registers are pending")
+                    };
+                    match (src, m.dst) {
+                        (Loc::Reg(r), _) if !m.class.is_float() => {
+                            self.store_loc(m.class, m.dst, r, Self::FTEMP)
+                        }
+                        (Loc::FReg(r), _) if m.class.is_float() => {
+                            self.store_loc(m.class, m.dst, Self::TEMP, r)
+                        }
+                        // The float temporary may be the destination of a
+                        // copy already done, so a float goes straight to its
+                        // register
+                        (Loc::Slot(_), Loc::FReg(to)) if m.class.is_float() => {
+                            self.load_loc(class, src, Self::TEMP, to)
+                        }
+                        _ => {
+                            self.load_loc(class, src, Self::TEMP, Self::FTEMP);
+                            self.store_loc(m.class, m.dst, Self::TEMP, Self::FTEMP);
+                        }
+                    }
+                }
+                None => {
+                    // Every destination is read by another copy
+                    let saved = pending[0].dst;
+                    let class = read(&pending, saved).expect("the destination is read");
+                    let tmp = if class.is_float() {
+                        Loc::FReg(Self::FCYCLE)
+                    } else {
+                        Loc::Reg(Self::CYCLE)
+                    };
+                    self.load_loc(class, saved, Self::CYCLE, Self::FCYCLE);
+                    for m in &mut pending {
+                        if let Src::Loc(loc, _) = &mut m.src {
+                            if *loc == saved {
+                                *loc = tmp;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // A value is loaded straight to a register of its class, since the
+        // temporary registers may be destinations
+        for m in values {
+            let Src::Value(value) = m.src else { continue };
+            match m.dst {
+                Loc::Reg(r) if !m.class.is_float() => {
+                    self.value(value, r, Self::FTEMP)?;
+                }
+                Loc::FReg(r) => {
+                    self.value(value, Self::TEMP, r)?;
+                }
+                _ => {
+                    self.value(value, Self::TEMP, Self::FTEMP)?;
+                    self.store_loc(m.class, m.dst, Self::TEMP, Self::FTEMP);
+                }
+            }
+        }
+        Ok(())
+    }
+}
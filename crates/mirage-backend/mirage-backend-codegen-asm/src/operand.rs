@@ -0,0 +1,30 @@
+use mirage_backend_opti::ir::value_register;
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::{MirageValueEnum, RegisterValue};
+
+/// The constant integer `value` holds, if it is one
+pub fn const_index(value: &Value) -> Option<i64> {
+    let Value::ConstValue(obj) = value else {
+        return None;
+    };
+    match obj.get_value_ref() {
+        MirageValueEnum::Int8(v) => Some(v.value as i64),
+        MirageValueEnum::Int16(v) => Some(v.value as i64),
+        MirageValueEnum::Int32(v) => Some(v.value as i64),
+        MirageValueEnum::Int64(v) => Some(v.value),
+        MirageValueEnum::UInt8(v) => Some(v.value as i64),
+        MirageValueEnum::UInt16(v) => Some(v.value as i64),
+        MirageValueEnum::UInt32(v) => Some(v.value as i64),
+        MirageValueEnum::UInt64(v) => Some(v.value as i64),
+        _ => None,
+    }
+}
+
+/// The register whose address `instr` takes, if any
+pub fn addressed(instr: &LabelBodyInstr) -> Option<&RegisterValue> {
+    match instr {
+        LabelBodyInstr::Assign(_, instr) => addressed(instr),
+        LabelBodyInstr::Command(Command::Ref(value)) => value_register(value),
+        _ => None,
+    }
+}
@@ -1,63 +1,282 @@
-use mirage_backend_asm::builder::Reg;
+use std::collections::{HashMap, HashSet};
 
+use mirage_backend_asm::builder::{FReg, Reg};
+use mirage_backend_opti::ir::{reg_key, RegKey};
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr};
+use mirage_frontend::object::RegisterType;
+
+use crate::interval::LiveIntervals;
+use crate::layout::{class_of, is_aggregate, Class};
+use crate::moves::Loc;
+use crate::operand::addressed;
+
+/// A machine register, general purpose or floating point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PhysReg {
+    Int(Reg),
+    Float(FReg),
+}
+
+impl PhysReg {
+    pub fn is_float(&self) -> bool {
+        matches!(self, PhysReg::Float(_))
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            PhysReg::Int(reg) => *reg as usize,
+            PhysReg::Float(reg) => Reg::all().len() + *reg as usize,
+        }
+    }
+}
+
+impl From<Reg> for PhysReg {
+    fn from(reg: Reg) -> Self {
+        PhysReg::Int(reg)
+    }
+}
+
+impl From<FReg> for PhysReg {
+    fn from(reg: FReg) -> Self {
+        PhysReg::Float(reg)
+    }
+}
+
+/// The live range of a register of the IR, from the position it is
+/// assigned at to the last one it is read at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interval {
+    pub key: RegKey,
+    pub start: usize,
+    pub end: usize,
+    pub float: bool,
+    /// Whether the value has to survive a call, which only the
+    /// callee-saved registers do
+    pub across_call: bool,
+    /// The register the value should rather be in, such as the one an
+    /// argument comes in
+    pub hint: Option<PhysReg>,
+}
 
 #[derive(Debug, Clone)]
 pub struct RegisterState {
+    /// Whether a live value is in the register
     is_used: bool,
+    /// Whether the register has been given to a value, so a callee-saved
+    /// one has to be saved
     is_dirty: bool,
-    is_live: bool,
+    /// Whether the register is never given, like the stack pointer or the
+    /// scratch registers
     is_reserved: bool,
-    reg: Reg,
+    /// Whether a call preserves the register
+    is_callee_saved: bool,
+    reg: PhysReg,
 }
 
+/// A linear scan register allocator.
+///
+/// The intervals are visited by their start, and each one takes a free
+/// register of its class, its hint first. When there is none, the value
+/// whose interval ends the last, the new one or one already in a register
+/// it could have, is spilled: it gets no register and lives in the frame.
 #[derive(Debug, Clone)]
 pub struct RegisterAllocator {
     regs: Vec<RegisterState>,
 }
 
 impl RegisterAllocator {
-    pub fn all() -> Self {
+    /// The allocator of a target with the first `ints` general purpose and
+    /// the first `floats` float registers, the others being reserved
+    pub fn new(ints: usize, floats: usize) -> Self {
+        let ints = Reg::all()
+            .into_iter()
+            .map(|reg| (PhysReg::Int(reg), reg as usize >= ints));
+        let floats = FReg::all()
+            .into_iter()
+            .map(|reg| (PhysReg::Float(reg), reg as usize >= floats));
         Self {
-            regs: Reg
-                ::all()
-                .iter()
-                .map(|reg| RegisterState {
+            regs: ints
+                .chain(floats)
+                .map(|(reg, missing)| RegisterState {
                     is_used: false,
                     is_dirty: false,
-                    is_live: false,
-                    is_reserved: false,
-                    reg: *reg,
-                }).collect(),
+                    is_reserved: missing,
+                    is_callee_saved: false,
+                    reg,
+                })
+                .collect(),
         }
     }
 
-    pub fn make_dirty(&mut self, reg: Reg) {
-        self.regs[reg as usize].is_dirty = true;
+    fn state(&mut self, reg: impl Into<PhysReg>) -> &mut RegisterState {
+        &mut self.regs[reg.into().index()]
+    }
+
+    pub fn make_dirty(&mut self, reg: impl Into<PhysReg>) {
+        self.state(reg).is_dirty = true;
     }
 
-    pub fn make_live(&mut self, reg: Reg) {
-        self.regs[reg as usize].is_live = true;
+    pub fn make_reserved(&mut self, reg: impl Into<PhysReg>) {
+        self.state(reg).is_reserved = true;
     }
 
-    pub fn make_dead(&mut self, reg: Reg) {
-        self.regs[reg as usize].is_live = false;
+    pub fn make_callee_saved(&mut self, reg: impl Into<PhysReg>) {
+        self.state(reg).is_callee_saved = true;
     }
 
-    pub fn make_reserved(&mut self, reg: Reg) {
-        self.regs[reg as usize].is_reserved = true;
+    pub fn is_used(&self, reg: impl Into<PhysReg>) -> bool {
+        self.regs[reg.into().index()].is_used
     }
 
-    pub fn get(&mut self) -> Option<Reg> {
-        for reg in self.regs.iter_mut() {
-            if !reg.is_used && !reg.is_reserved {
-                reg.is_used = true;
-                return Some(reg.reg);
+    pub fn is_callee_saved(&self, reg: impl Into<PhysReg>) -> bool {
+        self.regs[reg.into().index()].is_callee_saved
+    }
+
+    /// Whether the value of `interval` can be in `reg`
+    fn fits(&self, reg: PhysReg, interval: &Interval) -> bool {
+        let state = &self.regs[reg.index()];
+        !state.is_reserved
+            && reg.is_float() == interval.float
+            && (state.is_callee_saved || !interval.across_call)
+    }
+
+    /// Take a free register for `interval`. A value which doesn't survive a
+    /// call goes to a caller-saved register first, so fewer registers have
+    /// to be saved.
+    pub fn get(&mut self, interval: &Interval) -> Option<PhysReg> {
+        let free = |this: &Self, reg: PhysReg| !this.is_used(reg) && this.fits(reg, interval);
+        let reg = interval.hint.filter(|hint| free(self, *hint)).or_else(|| {
+            let mut regs: Vec<_> = self.regs.iter().map(|s| s.reg).collect();
+            regs.sort_by_key(|reg| self.is_callee_saved(*reg));
+            regs.into_iter().find(|reg| free(self, *reg))
+        })?;
+        let state = self.state(reg);
+        state.is_used = true;
+        state.is_dirty = true;
+        Some(reg)
+    }
+
+    pub fn free(&mut self, reg: PhysReg) {
+        self.state(reg).is_used = false;
+    }
+
+    /// The callee-saved registers given to some value, which a function
+    /// has to save
+    pub fn dirty_callee_saved(&self) -> Vec<PhysReg> {
+        self.regs
+            .iter()
+            .filter(|s| s.is_dirty && s.is_callee_saved)
+            .map(|s| s.reg)
+            .collect()
+    }
+
+    /// Give registers to the values of `intervals`. The values left out
+    /// are spilled.
+    pub fn allocate(&mut self, mut intervals: Vec<Interval>) -> HashMap<RegKey, PhysReg> {
+        // The values with a hint first, so they get it before another
+        // value starting with them takes it
+        intervals.sort_by_key(|i| (i.start, i.hint.is_none(), i.end, i.key.0 as u8, i.key.1));
+        let mut given = HashMap::new();
+        let mut active: Vec<(Interval, PhysReg)> = Vec::new();
+        for interval in intervals {
+            // Two values live at the same position never share a register,
+            // even when one of them is only read there
+            let (expired, live) = active
+                .into_iter()
+                .partition(|(a, _)| a.end < interval.start);
+            active = live;
+            for (_, reg) in expired {
+                self.free(reg);
+            }
+
+            if let Some(reg) = self.get(&interval) {
+                given.insert(interval.key, reg);
+                active.push((interval, reg));
+                continue;
+            }
+            let victim = active
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, reg))| self.fits(*reg, &interval))
+                .max_by_key(|(_, (a, _))| a.end)
+                .filter(|(_, (a, _))| a.end > interval.end)
+                .map(|(i, _)| i);
+            if let Some(i) = victim {
+                let (spilled, reg) = active.swap_remove(i);
+                given.remove(&spilled.key);
+                given.insert(interval.key, reg);
+                active.push((interval, reg));
             }
         }
-        None
+        given
     }
+}
 
-    pub fn is_used(&self, reg: Reg) -> bool {
-        self.regs[reg as usize].is_used
+/// The class of the registers of `func`, from their assignment
+pub fn register_classes(func: &FunctionValue) -> HashMap<RegKey, Class> {
+    let mut classes = HashMap::new();
+    for (i, ty) in func.get_type().get_args().iter().enumerate() {
+        classes.insert((RegisterType::Argument, i), class_of(ty));
     }
+    for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+        if let LabelBodyInstr::Assign(reg, value) = instr {
+            // A `getelementptr` or a `ref` gives an address, whatever the
+            // type of the register
+            let class = match **value {
+                LabelBodyInstr::Command(Command::GetElementPtr(..) | Command::Ref(_)) => {
+                    Class::Int(8)
+                }
+                _ => class_of(&reg.ty),
+            };
+            classes
+                .entry((reg.register_type, reg.index))
+                .or_insert(class);
+        }
+    }
+    classes
+}
+
+/// Give machine registers to the registers of `func`, whose arguments
+/// come in `incoming`. The registers whose address is taken stay in the
+/// frame, and an argument coming in a register would rather stay there.
+pub fn allocate_registers(
+    func: &FunctionValue,
+    classes: &HashMap<RegKey, Class>,
+    incoming: &[Loc],
+    alloc: &mut RegisterAllocator,
+) -> HashMap<RegKey, PhysReg> {
+    let in_frame: HashSet<RegKey> = func
+        .get_labels()
+        .iter()
+        .flat_map(|l| l.body.iter())
+        .filter_map(addressed)
+        .filter(|r| !is_aggregate(&r.ty))
+        .map(reg_key)
+        .collect();
+    let hints: HashMap<RegKey, PhysReg> = incoming
+        .iter()
+        .enumerate()
+        .filter_map(|(i, loc)| match loc {
+            Loc::Reg(r) => Some(((RegisterType::Argument, i), (*r).into())),
+            Loc::FReg(r) => Some(((RegisterType::Argument, i), (*r).into())),
+            Loc::Slot(_) => None,
+        })
+        .collect();
+    let live = LiveIntervals::new(func);
+    let intervals = live
+        .iter()
+        .filter(|(key, _)| !in_frame.contains(key))
+        .filter_map(|(key, (start, end))| {
+            Some(Interval {
+                key: *key,
+                start: *start,
+                end: *end,
+                float: classes.get(key)?.is_float(),
+                across_call: live.crosses_call(*start, *end),
+                hint: hints.get(key).copied(),
+            })
+        })
+        .collect();
+    alloc.allocate(intervals)
 }
@@ -0,0 +1,1021 @@
+use std::collections::HashMap;
+
+use mirage_backend_asm::builder::{AsmLabel, AsmSize, FReg, Reg};
+use mirage_backend_asm::riscv64::{
+    is_imm12, Inst, Op, Operand, A0, A1, A2, A3, A4, A5, A6, A7, GP, RA, S0, S1, S11, S2, SP, T0,
+    T1, T2, T3, T4, TP, ZERO,
+};
+use mirage_backend_opti::ir::{has_attribute, is_phi, reg_key, value_register, RegKey};
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::Flag;
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::object::{MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue};
+
+use crate::data::StaticData;
+use crate::environement::Environement;
+use crate::layout::{align_of, align_to, class_of, is_aggregate, size_of, struct_layout, Class};
+use crate::moves::{Loc, Move, ParallelMove, Src};
+use crate::operand::const_index;
+use crate::register::{allocate_registers, register_classes, PhysReg, RegisterAllocator};
+use crate::{CodeGenError, CodeGenResult};
+
+/// The registers of the integer arguments, in order
+const INT_ARGS: [Reg; 8] = [A0, A1, A2, A3, A4, A5, A6, A7];
+
+/// The float registers of the arguments, in order
+const FLOAT_ARGS: [FReg; 8] = [
+    FReg::F10,
+    FReg::F11,
+    FReg::F12,
+    FReg::F13,
+    FReg::F14,
+    FReg::F15,
+    FReg::F16,
+    FReg::F17,
+];
+
+/// The frame pointer
+const FP: Reg = S0;
+
+/// The registers the instructions work in
+const ACC: Reg = T0;
+const ACC2: Reg = T1;
+const FACC: FReg = FReg::F0;
+const FACC2: FReg = FReg::F1;
+
+/// The register holding the address an instruction reads or writes
+const BASE: Reg = T2;
+
+/// The scratch registers used to fill and copy memory
+const SCRATCH: Reg = T3;
+const FSCRATCH: FReg = FReg::F2;
+
+/// The register an offset or an immediate too large for an instruction is
+/// built in
+const IP: Reg = T4;
+
+/// The registers the values are given, and the classes of the others: the
+/// zero register, the return address, the stack, global, thread and frame
+/// pointers, the registers the instructions work in and the scratch ones
+/// are never given. `s1` to `s11` and `fs0` to `fs11` are callee-saved.
+pub fn register_allocator() -> RegisterAllocator {
+    let mut alloc = RegisterAllocator::new(32, 32);
+    for reg in [ZERO, RA, SP, GP, TP, FP, ACC, ACC2, BASE, SCRATCH, IP] {
+        alloc.make_reserved(reg);
+    }
+    for reg in [FACC, FACC2, FSCRATCH] {
+        alloc.make_reserved(reg);
+    }
+    alloc.make_callee_saved(S1);
+    for reg in &Reg::all()[S2 as usize..=S11 as usize] {
+        alloc.make_callee_saved(*reg);
+    }
+    let fregs = FReg::all();
+    for reg in fregs[8..10].iter().chain(&fregs[18..28]) {
+        alloc.make_callee_saved(*reg);
+    }
+    alloc
+}
+
+fn reg(reg: Reg) -> Operand {
+    Operand::Reg(reg)
+}
+
+fn freg(reg: FReg) -> Operand {
+    Operand::FReg(reg)
+}
+
+/// The size the float instructions on values of `class` are of
+fn float_size(class: Class) -> AsmSize {
+    match class {
+        Class::F32 => AsmSize::Dword,
+        _ => AsmSize::Qword,
+    }
+}
+
+/// `to` gets `from` plus `imm`, with an `addi` when it fits in 12 bits.
+/// A larger one is built in `t4` first.
+pub fn add_imm(to: Reg, from: Reg, imm: i64) -> Vec<Inst> {
+    if imm == 0 && to == from {
+        return Vec::new();
+    }
+    if is_imm12(imm) {
+        return vec![Inst::new(
+            Op::Addi,
+            vec![reg(to), reg(from), Operand::Imm(imm)],
+        )];
+    }
+    vec![
+        Inst::new(Op::Li, vec![reg(IP), Operand::Imm(imm)]),
+        Inst::new(Op::Add, vec![reg(to), reg(from), reg(IP)]),
+    ]
+}
+
+/// A load or a store, `op` being `Load` or `Store`, of `size` bytes between
+/// `data` and the memory at `base` plus `offset`. An offset which doesn't
+/// fit in 12 bits is added to the base in `t4` first.
+pub fn access(op: Op, size: AsmSize, data: Operand, base: Reg, offset: i64) -> Vec<Inst> {
+    if is_imm12(offset) {
+        return vec![Inst::sized(
+            op,
+            size,
+            vec![data, Operand::Mem(base, offset)],
+        )];
+    }
+    let mut insts = add_imm(IP, base, offset);
+    insts.push(Inst::sized(op, size, vec![data, Operand::Mem(IP, 0)]));
+    insts
+}
+
+/// Where the arguments of `func` come: the integers in `a0` to `a7`, the
+/// floats in `fa0` to `fa7` then in the integer registers left, and the
+/// others on the stack, at the frame pointer
+fn incoming(func: &FunctionValue) -> Vec<Loc> {
+    let (mut ints, mut floats, mut stack) = (0, 0, 0);
+    let mut locs = Vec::new();
+    for ty in func.get_type().get_args() {
+        let class = class_of(ty);
+        if class.is_float() && floats < FLOAT_ARGS.len() {
+            locs.push(Loc::FReg(FLOAT_ARGS[floats]));
+            floats += 1;
+        } else if ints < INT_ARGS.len() {
+            locs.push(Loc::Reg(INT_ARGS[ints]));
+            ints += 1;
+        } else {
+            locs.push(Loc::Slot(stack));
+            stack += 8;
+        }
+    }
+    locs
+}
+
+/// Compiles a function to RV64GC, for the LP64D calling convention of the
+/// RISC-V psABI.
+///
+/// The frame pointer `s0` is the stack pointer at the entry, with the
+/// return address and the caller's frame pointer right below it and the
+/// slots below them. The registers are given machine registers by a
+/// linear scan over their live intervals, and the ones which don't get
+/// one, or whose address is taken, live in an 8-byte slot of the frame.
+/// They are loaded to `t0`, `t1` or `ft0`, `ft1` by the instructions using
+/// them. A value kept in a register is sign-extended to 64 bits. Structs
+/// and arrays are handled through their address, and the ones a function
+/// creates are in its frame.
+pub struct FunctionCompiler<'a> {
+    env: &'a Environement,
+    data: &'a mut StaticData,
+    func: &'a FunctionValue,
+    /// The counter of the local labels of the program
+    lid: &'a mut usize,
+    labels: Vec<AsmLabel<Inst>>,
+    /// The index of the label being compiled
+    current: usize,
+    /// The class of the registers, from their assignment
+    classes: HashMap<RegKey, Class>,
+    /// The machine register of the registers which have one
+    regs: HashMap<RegKey, PhysReg>,
+    /// The offset from the frame pointer of the slot of the other ones
+    slots: HashMap<RegKey, i64>,
+    /// The callee-saved registers the function uses, and where they are
+    /// saved
+    saved: Vec<(PhysReg, i64)>,
+    /// The size of the frame, with the return address and the frame
+    /// pointer
+    frame_size: u64,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    pub fn new(
+        env: &'a Environement,
+        data: &'a mut StaticData,
+        func: &'a FunctionValue,
+        lid: &'a mut usize,
+        mut alloc: RegisterAllocator,
+    ) -> Self {
+        let classes = register_classes(func);
+        let regs = allocate_registers(func, &classes, &incoming(func), &mut alloc);
+        let name = func.get_name().clone();
+        let entry = if has_attribute(func, &Flag::internal()) {
+            AsmLabel::new(name)
+        } else {
+            AsmLabel::global(name)
+        };
+        Self {
+            env,
+            data,
+            func,
+            lid,
+            labels: vec![entry],
+            current: 0,
+            classes,
+            regs,
+            slots: HashMap::new(),
+            saved: Vec::new(),
+            frame_size: 16,
+        }
+        .save(alloc.dirty_callee_saved())
+    }
+
+    /// Give a slot to each callee-saved register the function uses
+    fn save(mut self, regs: Vec<PhysReg>) -> Self {
+        for r in regs {
+            let slot = self.alloc(8, 8);
+            self.saved.push((r, slot));
+        }
+        self
+    }
+
+    /// Compile the function to its labels: its symbol, then the labels of
+    /// its body
+    pub fn compile(mut self) -> CodeGenResult<Vec<AsmLabel<Inst>>> {
+        self.arguments()?;
+        let func = self.func;
+        let labels = func.get_labels();
+        for (i, label) in labels.iter().enumerate() {
+            self.current = i;
+            self.labels.push(AsmLabel::new(self.label(&label.name)?));
+            for instr in &label.body {
+                if !is_phi(instr) {
+                    self.statement(instr)?;
+                }
+            }
+            let ends = matches!(
+                label.body.last(),
+                Some(LabelBodyInstr::Command(Command::Jump(_) | Command::Ret(_)))
+            );
+            if !ends {
+                // The label falls through to the next one
+                match labels.get(i + 1) {
+                    Some(next) => self.phis(&next.name)?,
+                    None => self.emit(Op::Ebreak, vec![]),
+                }
+            }
+        }
+        if labels.is_empty() {
+            self.emit(Op::Ebreak, vec![]);
+        }
+        self.prologue();
+        Ok(self.labels)
+    }
+
+    fn emit(&mut self, op: Op, operands: Vec<Operand>) {
+        self.emit_sized(op, AsmSize::Qword, operands);
+    }
+
+    fn emit_sized(&mut self, op: Op, size: AsmSize, operands: Vec<Operand>) {
+        self.emit_all(vec![Inst::sized(op, size, operands)]);
+    }
+
+    fn emit_all(&mut self, insts: Vec<Inst>) {
+        let label = self.labels.last_mut().expect("the function has its symbol");
+        for inst in insts {
+            label.add_asm(inst);
+        }
+    }
+
+    /// A load or a store of a callee-saved register to its slot
+    fn save_access(op: Op, r: PhysReg, slot: i64) -> Vec<Inst> {
+        let data = match r {
+            PhysReg::Int(r) => reg(r),
+            PhysReg::Float(r) => freg(r),
+        };
+        access(op, AsmSize::Qword, data, FP, slot)
+    }
+
+    /// Save the return address and the frame pointer, set the frame
+    /// pointer, reserve the frame and save the callee-saved registers, at
+    /// the start of the function
+    fn prologue(&mut self) {
+        let size = align_to(self.frame_size, 16) as i64;
+        let mut prologue = vec![
+            Inst::new(Op::Addi, vec![reg(SP), reg(SP), Operand::Imm(-16)]),
+            Inst::new(Op::Store, vec![reg(RA), Operand::Mem(SP, 8)]),
+            Inst::new(Op::Store, vec![reg(FP), Operand::Mem(SP, 0)]),
+            Inst::new(Op::Addi, vec![reg(FP), reg(SP), Operand::Imm(16)]),
+        ];
+        prologue.extend(add_imm(SP, SP, 16 - size));
+        for (r, slot) in &self.saved {
+            prologue.extend(Self::save_access(Op::Store, *r, *slot));
+        }
+        for (i, inst) in prologue.into_iter().enumerate() {
+            self.labels[0].insert_asm(i, inst);
+        }
+    }
+
+    /// Restore the callee-saved registers, the stack pointer, the return
+    /// address and the frame pointer, and return
+    fn epilogue(&mut self) {
+        for (r, slot) in self.saved.clone() {
+            self.emit_all(Self::save_access(Op::Load, r, slot));
+        }
+        self.emit(Op::Addi, vec![reg(SP), reg(FP), Operand::Imm(-16)]);
+        self.emit(Op::Load, vec![reg(RA), Operand::Mem(SP, 8)]);
+        self.emit(Op::Load, vec![reg(FP), Operand::Mem(SP, 0)]);
+        self.emit(Op::Addi, vec![reg(SP), reg(SP), Operand::Imm(16)]);
+        self.emit(Op::Ret, vec![]);
+    }
+
+    fn label(&self, name: &String) -> CodeGenResult<String> {
+        self.env
+            .get_label(name)
+            .cloned()
+            .ok_or_else(|| CodeGenError::UnknownLabel(name.clone()))
+    }
+
+    fn local_label(&mut self) -> String {
+        *self.lid += 1;
+        format!(".L{}", self.lid)
+    }
+
+    /// Reserve `size` bytes in the frame and return their offset from the
+    /// frame pointer
+    fn alloc(&mut self, size: u64, align: u64) -> i64 {
+        self.frame_size = align_to(self.frame_size + size, align.max(1));
+        -(self.frame_size as i64)
+    }
+
+    fn slot(&mut self, key: RegKey) -> i64 {
+        if let Some(slot) = self.slots.get(&key) {
+            return *slot;
+        }
+        let slot = self.alloc(8, 8);
+        self.slots.insert(key, slot);
+        slot
+    }
+
+    fn class(&self, reg: &RegisterValue) -> Class {
+        self.classes
+            .get(&(reg.register_type, reg.index))
+            .copied()
+            .unwrap_or_else(|| class_of(&reg.ty))
+    }
+
+    /// Where the register or the slot of a register is
+    fn loc(&mut self, key: RegKey) -> Loc {
+        match self.regs.get(&key) {
+            Some(PhysReg::Int(r)) => Loc::Reg(*r),
+            Some(PhysReg::Float(r)) => Loc::FReg(*r),
+            None => Loc::Slot(self.slot(key)),
+        }
+    }
+
+    /// Move the arguments from where they come to where they are kept
+    fn arguments(&mut self) -> CodeGenResult<()> {
+        let moves = incoming(self.func)
+            .into_iter()
+            .zip(self.func.get_type().get_args())
+            .enumerate()
+            .map(|(i, (from, ty))| Move {
+                dst: self.loc((RegisterType::Argument, i)),
+                class: class_of(ty),
+                src: Src::Loc(from, class_of(ty)),
+            })
+            .collect();
+        self.parallel_move(moves)
+    }
+
+    /// What a copy reads `value` from
+    fn source<'v>(&mut self, value: &'v Value) -> Src<'v> {
+        match value_register(value) {
+            Some(r) if r.register_type != RegisterType::Global => {
+                Src::Loc(self.loc(reg_key(r)), self.class(r))
+            }
+            _ => Src::Value(value),
+        }
+    }
+
+    /// Load a value of `class` from `base` plus `offset` to `to`, or to
+    /// `fto` if it is a float
+    fn load(&mut self, class: Class, base: Reg, offset: i64, to: Reg, fto: FReg) {
+        let insts = match class {
+            Class::Int(size) => access(Op::Load, AsmSize::from_bytes(size), reg(to), base, offset),
+            _ => access(Op::Load, float_size(class), freg(fto), base, offset),
+        };
+        self.emit_all(insts);
+    }
+
+    /// Store a value of `class` from `from`, or from `ffrom` if it is a
+    /// float, to `base` plus `offset`
+    fn store(&mut self, class: Class, base: Reg, offset: i64, from: Reg, ffrom: FReg) {
+        let insts = match class {
+            Class::Int(size) => access(
+                Op::Store,
+                AsmSize::from_bytes(size),
+                reg(from),
+                base,
+                offset,
+            ),
+            _ => access(Op::Store, float_size(class), freg(ffrom), base, offset),
+        };
+        self.emit_all(insts);
+    }
+
+    /// Copy `size` bytes from `src` plus `src_off` to `dst` plus `dst_off`
+    fn copy(&mut self, dst: Reg, dst_off: i64, src: Reg, src_off: i64, size: u64) {
+        let mut done = 0;
+        for chunk in [8, 4, 2, 1] {
+            while size - done >= chunk {
+                let class = Class::Int(chunk);
+                self.load(class, src, src_off + done as i64, SCRATCH, FSCRATCH);
+                self.store(class, dst, dst_off + done as i64, SCRATCH, FSCRATCH);
+                done += chunk;
+            }
+        }
+    }
+
+    /// Leave the address of the symbol `name` in `to`, relative to the
+    /// program counter
+    fn symbol_address(&mut self, to: Reg, name: String) {
+        self.emit(Op::La, vec![reg(to), Operand::Label(name)]);
+    }
+
+    fn global(&self, index: usize) -> CodeGenResult<(String, MirageTypeEnum)> {
+        self.env
+            .get_global(index)
+            .cloned()
+            .ok_or(CodeGenError::UnknownGlobal(index))
+    }
+
+    /// Load `reg` to `to`, or to `fto` if it is a float
+    fn read(&mut self, r: &RegisterValue, to: Reg, fto: FReg) -> CodeGenResult<Class> {
+        if r.register_type == RegisterType::Global {
+            let (symbol, ty) = self.global(r.index)?;
+            let class = class_of(&ty);
+            if is_aggregate(&ty) {
+                self.symbol_address(to, symbol);
+            } else {
+                self.symbol_address(IP, symbol);
+                self.load(class, IP, 0, to, fto);
+            }
+            return Ok(class);
+        }
+        let class = self.class(r);
+        let loc = self.loc(reg_key(r));
+        self.load_loc(class, loc, to, fto);
+        Ok(class)
+    }
+
+    /// Assign the value in `t0` or `ft0` to `reg`
+    fn write(&mut self, r: &RegisterValue) -> CodeGenResult<()> {
+        if r.register_type == RegisterType::Global {
+            let (symbol, ty) = self.global(r.index)?;
+            if is_aggregate(&ty) {
+                self.symbol_address(BASE, symbol);
+                self.copy(BASE, 0, ACC, 0, size_of(&ty));
+            } else {
+                self.symbol_address(IP, symbol);
+                self.store(class_of(&ty), IP, 0, ACC, FACC);
+            }
+            return Ok(());
+        }
+        let class = self.class(r);
+        let loc = self.loc(reg_key(r));
+        self.store_loc(class, loc, ACC, FACC);
+        Ok(())
+    }
+
+    fn value_type(value: &Value) -> CodeGenResult<MirageTypeEnum> {
+        match value {
+            Value::List(_) => Err(CodeGenError::Unsupported(value.to_string())),
+            _ => Ok(value.get_type()),
+        }
+    }
+
+    /// Load a float constant to `fto`, from the read-only data
+    fn float(&mut self, class: Class, v: f64, fto: FReg) {
+        let label = match class {
+            Class::F32 => self.data.float32(v as f32),
+            _ => self.data.float64(v),
+        };
+        self.symbol_address(IP, label);
+        self.load(class, IP, 0, ACC, fto);
+    }
+
+    fn object(&mut self, value: &MirageValueEnum, to: Reg, fto: FReg) -> CodeGenResult<Class> {
+        let imm = match value {
+            MirageValueEnum::Register(r) => return self.read(r, to, fto),
+            MirageValueEnum::Int8(v) => v.value as i64,
+            MirageValueEnum::Int16(v) => v.value as i64,
+            MirageValueEnum::Int32(v) => v.value as i64,
+            MirageValueEnum::Int64(v) => v.value,
+            MirageValueEnum::UInt8(v) => v.value as i8 as i64,
+            MirageValueEnum::UInt16(v) => v.value as i16 as i64,
+            MirageValueEnum::UInt32(v) => v.value as i32 as i64,
+            MirageValueEnum::UInt64(v) => v.value as i64,
+            MirageValueEnum::Float32(v) => {
+                self.float(Class::F32, v.value as f64, fto);
+                return Ok(Class::F32);
+            }
+            MirageValueEnum::Float64(v) => {
+                self.float(Class::F64, v.value, fto);
+                return Ok(Class::F64);
+            }
+            MirageValueEnum::Array(_) | MirageValueEnum::Struct(_) => {
+                if let Some(s) = value.try_to_rust_string() {
+                    let label = self.data.string(&s);
+                    self.symbol_address(to, label);
+                    return Ok(Class::Int(8));
+                }
+                let ty = value.get_type();
+                let slot = self.alloc(size_of(&ty), align_of(&ty));
+                self.fill(slot, value)?;
+                self.emit_all(add_imm(to, FP, slot));
+                return Ok(Class::Int(8));
+            }
+            MirageValueEnum::Pointer(_) => {
+                return Err(CodeGenError::Unsupported(value.print_to_string()))
+            }
+        };
+        self.emit(Op::Li, vec![reg(to), Operand::Imm(imm)]);
+        Ok(class_of(&value.get_type()))
+    }
+
+    /// Write a constant struct or array to the frame, at `offset`
+    fn fill(&mut self, offset: i64, value: &MirageValueEnum) -> CodeGenResult<()> {
+        match value {
+            MirageValueEnum::Array(a) => {
+                let size = size_of(&a.ty.element_ty()) as i64;
+                for (i, v) in a.values.iter().enumerate() {
+                    self.fill(offset + i as i64 * size, v)?;
+                }
+            }
+            MirageValueEnum::Struct(s) => {
+                let (_, offsets) = struct_layout(&s.ty.fields);
+                for (v, field) in s.values.iter().zip(offsets) {
+                    self.fill(offset + field as i64, v)?;
+                }
+            }
+            _ => {
+                let class = self.object(value, SCRATCH, FSCRATCH)?;
+                self.store(class, FP, offset, SCRATCH, FSCRATCH);
+            }
+        }
+        Ok(())
+    }
+
+    /// Store `value`, of type `ty`, at `dst` plus `offset`. A struct or an
+    /// array is copied.
+    fn store_value(
+        &mut self,
+        dst: Reg,
+        offset: i64,
+        ty: &MirageTypeEnum,
+        value: &Value,
+    ) -> CodeGenResult<()> {
+        if is_aggregate(ty) {
+            self.value(value, ACC, FACC)?;
+            self.copy(dst, offset, ACC, 0, size_of(ty));
+        } else {
+            let class = self.value(value, ACC, FACC)?;
+            self.store(class, dst, offset, ACC, FACC);
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, instr: &LabelBodyInstr) -> CodeGenResult<()> {
+        match instr {
+            LabelBodyInstr::Assign(r, value) => {
+                let has_value = match &**value {
+                    LabelBodyInstr::Call(name, args) => self.call(name, args).map(|_| true)?,
+                    LabelBodyInstr::Command(cmd) => self.command(cmd)?,
+                    LabelBodyInstr::Assign(..) => false,
+                };
+                if !has_value {
+                    return Err(CodeGenError::Unsupported(instr.to_string()));
+                }
+                self.write(r)
+            }
+            LabelBodyInstr::Call(name, args) => self.call(name, args),
+            LabelBodyInstr::Command(cmd) => self.command(cmd).map(|_| ()),
+        }
+    }
+
+    /// Call `name`. The result is left in `t0` or `ft0`.
+    fn call(&mut self, name: &str, args: &[Value]) -> CodeGenResult<()> {
+        let env = self.env;
+        let ty = env
+            .get_function(name)
+            .ok_or_else(|| CodeGenError::UnknownFunction(name.to_string()))?;
+        let fixed = ty.get_args().len();
+        if args.len() < fixed {
+            return Err(CodeGenError::ArgumentCount(name.to_string()));
+        }
+
+        // The variadic arguments are passed as the fixed integers are, a
+        // `float` being promoted to a `double`
+        let (mut ints, mut floats) = (0, 0);
+        let (mut moves, mut stack) = (Vec::new(), Vec::new());
+        for (i, arg) in args.iter().enumerate() {
+            let class = match value_register(arg) {
+                Some(r) if r.register_type != RegisterType::Global => self.class(r),
+                _ => class_of(&Self::value_type(arg)?),
+            };
+            let variadic = i >= fixed;
+            let promote = variadic && class == Class::F32;
+            let dst = if class.is_float() && !variadic && floats < FLOAT_ARGS.len() {
+                floats += 1;
+                Loc::FReg(FLOAT_ARGS[floats - 1])
+            } else if ints < INT_ARGS.len() {
+                ints += 1;
+                Loc::Reg(INT_ARGS[ints - 1])
+            } else {
+                stack.push((arg, promote, 8 * stack.len() as i64));
+                continue;
+            };
+            let src = if promote {
+                // Converted to a slot of its own first, since the move to
+                // an integer register copies the bits
+                self.value(arg, ACC, FACC)?;
+                self.emit(Op::Fcvt, vec![freg(FACC), freg(FACC)]);
+                let slot = self.alloc(8, 8);
+                self.store(Class::F64, FP, slot, ACC, FACC);
+                Src::Loc(Loc::Slot(slot), Class::F64)
+            } else {
+                self.source(arg)
+            };
+            let class = if promote { Class::F64 } else { class };
+            moves.push(Move { dst, class, src });
+        }
+
+        // The arguments on the stack first, since loading them goes through
+        // `t0` and `ft0`
+        let area = align_to(8 * stack.len() as u64, 16) as i64;
+        self.emit_all(add_imm(SP, SP, -area));
+        for (arg, promote, at) in stack {
+            let class = self.value(arg, ACC, FACC)?;
+            if promote {
+                self.emit(Op::Fcvt, vec![freg(FACC), freg(FACC)]);
+                self.store(Class::F64, SP, at, ACC, FACC);
+            } else if class.is_float() {
+                self.store(class, SP, at, ACC, FACC);
+            } else {
+                self.store(Class::Int(8), SP, at, ACC, FACC);
+            }
+        }
+        // The registers of the arguments may hold the values of others
+        self.parallel_move(moves)?;
+        self.emit(Op::Call, vec![Operand::Label(name.to_string())]);
+        self.emit_all(add_imm(SP, SP, area));
+        match class_of(ty.get_ret()) {
+            Class::Int(_) => self.emit(Op::Mv, vec![reg(ACC), reg(A0)]),
+            class => self.emit_sized(
+                Op::Fmv,
+                float_size(class),
+                vec![freg(FACC), freg(FLOAT_ARGS[0])],
+            ),
+        }
+        Ok(())
+    }
+
+    /// Emit `cmd`, returning whether it leaves a value in `t0` or `ft0`
+    fn command(&mut self, cmd: &Command) -> CodeGenResult<bool> {
+        match cmd {
+            Command::Store(r, value) => {
+                let ty = Self::value_type(value)?;
+                self.read(r, BASE, FACC)?;
+                self.store_value(BASE, 0, &ty, value)?;
+                Ok(false)
+            }
+            Command::New(name, args) => {
+                let env = self.env;
+                let fields = env
+                    .get_struct(name)
+                    .ok_or_else(|| CodeGenError::UnknownType(name.clone()))?;
+                let (size, offsets) = struct_layout(fields);
+                let align = fields.iter().map(align_of).max().unwrap_or(1);
+                let slot = self.alloc(size, align);
+                for ((arg, field), offset) in args.iter().zip(fields).zip(offsets) {
+                    self.store_value(FP, slot + offset as i64, field, arg)?;
+                }
+                self.emit_all(add_imm(ACC, FP, slot));
+                Ok(true)
+            }
+            Command::Get(r, index) => {
+                let fields = match &r.ty {
+                    MirageTypeEnum::Struct(s) => &s.fields,
+                    MirageTypeEnum::Pointer(p) => match &*p.element_ty {
+                        MirageTypeEnum::Struct(s) => &s.fields,
+                        _ => return Err(CodeGenError::Unsupported(cmd.to_string())),
+                    },
+                    _ => return Err(CodeGenError::Unsupported(cmd.to_string())),
+                };
+                let (_, offsets) = struct_layout(fields);
+                let Some((ty, offset)) = fields.get(*index).zip(offsets.get(*index)) else {
+                    return Err(CodeGenError::Unsupported(cmd.to_string()));
+                };
+                self.read(r, BASE, FACC)?;
+                if is_aggregate(ty) {
+                    self.emit_all(add_imm(ACC, BASE, *offset as i64));
+                } else {
+                    self.load(class_of(ty), BASE, *offset as i64, ACC, FACC);
+                }
+                Ok(true)
+            }
+            Command::Const(obj) => {
+                self.object(obj.get_value_ref(), ACC, FACC)?;
+                Ok(true)
+            }
+            // The frame is given back on return, and there is no heap
+            Command::Free(_) => Ok(false),
+            Command::Ret(value) => {
+                self.value(value, A0, FLOAT_ARGS[0])?;
+                self.epilogue();
+                Ok(false)
+            }
+            Command::Jump(label) => {
+                self.phis(label)?;
+                let target = self.label(label)?;
+                self.emit(Op::J, vec![Operand::Label(target)]);
+                Ok(false)
+            }
+            Command::Jeq(label, lhs, rhs) => {
+                self.jeq(label, lhs, rhs)?;
+                Ok(false)
+            }
+            Command::Phi(_) => Ok(false),
+            Command::IncrInt8(r)
+            | Command::IncrInt16(r)
+            | Command::IncrInt32(r)
+            | Command::IncrInt64(r) => {
+                self.read(r, ACC, FACC)?;
+                self.emit_all(add_imm(ACC, ACC, 1));
+                Ok(true)
+            }
+            Command::IncrFloat32(r) | Command::IncrFloat64(r) => {
+                let class = self.read(r, ACC, FACC)?;
+                self.float(class, 1.0, FACC2);
+                self.emit_sized(
+                    Op::Fadd,
+                    float_size(class),
+                    vec![freg(FACC), freg(FACC), freg(FACC2)],
+                );
+                Ok(true)
+            }
+            Command::AddInt8(lhs, rhs)
+            | Command::AddInt16(lhs, rhs)
+            | Command::AddInt32(lhs, rhs)
+            | Command::AddInt64(lhs, rhs) => self.arith(lhs, rhs, Op::Add),
+            Command::SubInt8(lhs, rhs)
+            | Command::SubInt16(lhs, rhs)
+            | Command::SubInt32(lhs, rhs)
+            | Command::SubInt64(lhs, rhs) => self.arith(lhs, rhs, Op::Sub),
+            Command::AddFloat32(lhs, rhs) | Command::AddFloat64(lhs, rhs) => {
+                self.arith(lhs, rhs, Op::Fadd)
+            }
+            Command::SubFloat32(lhs, rhs) | Command::SubFloat64(lhs, rhs) => {
+                self.arith(lhs, rhs, Op::Fsub)
+            }
+            Command::Ref(value) => {
+                match value_register(value) {
+                    Some(r) => self.address_of(r)?,
+                    None => {
+                        // A constant gets a slot of its own
+                        let ty = Self::value_type(value)?;
+                        if is_aggregate(&ty) {
+                            self.value(value, ACC, FACC)?;
+                        } else {
+                            let slot = self.alloc(size_of(&ty), align_of(&ty));
+                            self.store_value(FP, slot, &ty, value)?;
+                            self.emit_all(add_imm(ACC, FP, slot));
+                        }
+                    }
+                }
+                Ok(true)
+            }
+            Command::Load(ty, value) => {
+                self.value(value, BASE, FACC)?;
+                if is_aggregate(ty) {
+                    // Loading a struct or an array copies it
+                    let slot = self.alloc(size_of(ty), align_of(ty));
+                    self.copy(FP, slot, BASE, 0, size_of(ty));
+                    self.emit_all(add_imm(ACC, FP, slot));
+                } else {
+                    self.load(class_of(ty), BASE, 0, ACC, FACC);
+                }
+                Ok(true)
+            }
+            Command::GetElementPtr(ty, base, indices) => {
+                self.value(base, ACC, FACC)?;
+                self.element_ptr(ty, indices)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// `lhs op rhs`. The integers are added on 64 bits, and truncated when
+    /// they are stored. A constant right operand which fits is added as an
+    /// immediate.
+    fn arith(&mut self, lhs: &Value, rhs: &Value, op: Op) -> CodeGenResult<bool> {
+        let class = self.value(lhs, ACC, FACC)?;
+        if let Some(c) = const_index(rhs) {
+            let imm = if op == Op::Sub {
+                c.checked_neg()
+            } else {
+                Some(c)
+            };
+            if let Some(imm) = imm.filter(|imm| is_imm12(*imm)) {
+                self.emit_all(add_imm(ACC, ACC, imm));
+                return Ok(true);
+            }
+        }
+        self.value(rhs, ACC2, FACC2)?;
+        match op {
+            Op::Add | Op::Sub => self.emit(op, vec![reg(ACC), reg(ACC), reg(ACC2)]),
+            _ => self.emit_sized(
+                op,
+                float_size(class),
+                vec![freg(FACC), freg(FACC), freg(FACC2)],
+            ),
+        }
+        Ok(true)
+    }
+
+    /// Branch to `label` if `lhs` equals `rhs`. A conditional branch only
+    /// reaches 4KB away, so it skips over the copies to the phis of `label`
+    /// and a jump there when the operands differ. Unordered floats, a NaN
+    /// among them, don't compare equal.
+    fn jeq(&mut self, label: &String, lhs: &Value, rhs: &Value) -> CodeGenResult<()> {
+        let class = self.value(lhs, ACC, FACC)?;
+        let skip = self.local_label();
+        let skip_op = Operand::Label(skip.clone());
+        match (class, const_index(rhs)) {
+            (Class::Int(_), Some(0)) => {
+                self.emit(Op::Bne, vec![reg(ACC), reg(ZERO), skip_op]);
+            }
+            (Class::Int(_), _) => {
+                self.value(rhs, ACC2, FACC2)?;
+                self.emit(Op::Bne, vec![reg(ACC), reg(ACC2), skip_op]);
+            }
+            _ => {
+                self.value(rhs, ACC2, FACC2)?;
+                self.emit_sized(
+                    Op::Feq,
+                    float_size(class),
+                    vec![reg(ACC), freg(FACC), freg(FACC2)],
+                );
+                self.emit(Op::Beq, vec![reg(ACC), reg(ZERO), skip_op]);
+            }
+        }
+        self.phis(label)?;
+        let target = self.label(label)?;
+        self.emit(Op::J, vec![Operand::Label(target)]);
+        self.labels.push(AsmLabel::new(skip));
+        Ok(())
+    }
+
+    /// The phis of `to` and the values they take coming from the label
+    /// being compiled
+    fn phi_copies(&self, to: &String) -> Vec<(&'a RegisterValue, &'a Value)> {
+        let labels: &'a Vec<_> = self.func.get_labels();
+        let from = &labels[self.current].name;
+        let Some(to) = labels.iter().find(|l| &l.name == to) else {
+            return Vec::new();
+        };
+        to.body
+            .iter()
+            .map_while(|instr| match instr {
+                LabelBodyInstr::Assign(r, value) => match &**value {
+                    LabelBodyInstr::Command(Command::Phi(incoming)) => Some((r, incoming)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .filter_map(|(r, incoming)| {
+                incoming
+                    .iter()
+                    .find(|(label, _)| label == from)
+                    .map(|(_, value)| (r, value))
+            })
+            .collect()
+    }
+
+    /// Assign the phis of `to` the values coming from the label being
+    /// compiled. The phis read their operands before any of them is
+    /// assigned.
+    fn phis(&mut self, to: &String) -> CodeGenResult<()> {
+        let mut moves = Vec::new();
+        for (r, value) in self.phi_copies(to) {
+            if r.register_type == RegisterType::Global {
+                return Err(CodeGenError::Unsupported(format!(
+                    "phi to {}",
+                    r.print_to_string()
+                )));
+            }
+            let dst = self.loc(reg_key(r));
+            let class = self.class(r);
+            let src = self.source(value);
+            moves.push(Move { dst, class, src });
+        }
+        self.parallel_move(moves)
+    }
+
+    /// Leave in `t0` the address `ref reg` gives
+    fn address_of(&mut self, r: &RegisterValue) -> CodeGenResult<()> {
+        if r.register_type == RegisterType::Global {
+            let (symbol, _) = self.global(r.index)?;
+            self.symbol_address(ACC, symbol);
+        } else if is_aggregate(&r.ty) {
+            self.read(r, ACC, FACC)?;
+        } else {
+            let slot = self.slot((r.register_type, r.index));
+            self.emit_all(add_imm(ACC, FP, slot));
+        }
+        Ok(())
+    }
+
+    /// Offset the address in `t0` as `getelementptr` does: the first index
+    /// steps over whole values of `ty`, the next ones go into it
+    fn element_ptr(&mut self, ty: &MirageTypeEnum, indices: &[Value]) -> CodeGenResult<()> {
+        let mut ty = ty.clone();
+        for (i, index) in indices.iter().enumerate() {
+            if i > 0 {
+                match ty {
+                    MirageTypeEnum::Struct(s) => {
+                        let field = const_index(index)
+                            .and_then(|f| usize::try_from(f).ok())
+                            .filter(|f| *f < s.fields.len())
+                            .ok_or_else(|| CodeGenError::Unsupported(index.to_string()))?;
+                        let (_, offsets) = struct_layout(&s.fields);
+                        self.emit_all(add_imm(ACC, ACC, offsets[field] as i64));
+                        ty = s.fields[field].clone();
+                        continue;
+                    }
+                    MirageTypeEnum::Array(a) => ty = a.element_ty(),
+                    _ => {
+                        return Err(CodeGenError::Unsupported(format!(
+                            "getelementptr into {}",
+                            ty.print_to_string()
+                        )))
+                    }
+                }
+            }
+            let stride = size_of(&ty);
+            match const_index(index) {
+                Some(c) => self.emit_all(add_imm(ACC, ACC, c.wrapping_mul(stride as i64))),
+                None => {
+                    self.value(index, ACC2, FACC2)?;
+                    if stride.is_power_of_two() {
+                        let shift = Operand::Imm(stride.trailing_zeros() as i64);
+                        self.emit(Op::Slli, vec![reg(ACC2), reg(ACC2), shift]);
+                    } else {
+                        self.emit(Op::Li, vec![reg(IP), Operand::Imm(stride as i64)]);
+                        self.emit(Op::Mul, vec![reg(ACC2), reg(ACC2), reg(IP)]);
+                    }
+                    self.emit(Op::Add, vec![reg(ACC), reg(ACC), reg(ACC2)]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ParallelMove for FunctionCompiler<'_> {
+    const TEMP: Reg = ACC;
+    const FTEMP: FReg = FACC;
+    const CYCLE: Reg = BASE;
+    const FCYCLE: FReg = FSCRATCH;
+
+    /// A float in a general purpose register, passed there for want of
+    /// float registers, is moved bit for bit
+    fn load_loc(&mut self, class: Class, loc: Loc, to: Reg, fto: FReg) {
+        let size = float_size(class);
+        match loc {
+            Loc::Slot(slot) => self.load(class, FP, slot, to, fto),
+            Loc::Reg(r) if class.is_float() => {
+                self.emit_sized(Op::Fmv, size, vec![freg(fto), reg(r)])
+            }
+            Loc::Reg(r) if r != to => self.emit(Op::Mv, vec![reg(to), reg(r)]),
+            Loc::FReg(r) if r != fto => self.emit_sized(Op::Fmv, size, vec![freg(fto), freg(r)]),
+            _ => {}
+        }
+    }
+
+    fn store_loc(&mut self, class: Class, loc: Loc, from: Reg, ffrom: FReg) {
+        match (loc, class) {
+            (Loc::Slot(slot), _) => self.store(class, FP, slot, from, ffrom),
+            (Loc::Reg(r), Class::F32 | Class::F64) => {
+                self.emit_sized(Op::Fmv, float_size(class), vec![reg(r), freg(ffrom)])
+            }
+            (Loc::Reg(r), Class::Int(4)) => self.emit(Op::SextW, vec![reg(r), reg(from)]),
+            (Loc::Reg(r), Class::Int(size)) if size < 8 => {
+                let shift = Operand::Imm(64 - 8 * size as i64);
+                self.emit(Op::Slli, vec![reg(r), reg(from), shift.clone()]);
+                self.emit(Op::Srai, vec![reg(r), reg(r), shift]);
+            }
+            (Loc::Reg(r), _) if r != from => self.emit(Op::Mv, vec![reg(r), reg(from)]),
+            (Loc::FReg(r), _) if r != ffrom => {
+                self.emit_sized(Op::Fmv, float_size(class), vec![freg(r), freg(ffrom)])
+            }
+            _ => {}
+        }
+    }
+
+    fn value(&mut self, value: &Value, to: Reg, fto: FReg) -> CodeGenResult<Class> {
+        match value {
+            Value::ConstValue(obj) => self.object(obj.get_value_ref(), to, fto),
+            Value::Register(r) => self.read(r, to, fto),
+            Value::List(_) => Err(CodeGenError::Unsupported(value.to_string())),
+        }
+    }
+}
@@ -0,0 +1,937 @@
+use std::path::PathBuf;
+use std::process::Command as Process;
+
+use mirage_backend_asm::aarch64::{print_inst, Flavor, X0};
+use mirage_backend_asm::builder::{Asm, AsmArg, AsmCommand, AsmSize};
+use mirage_backend_asm::x86_64::{print_att_asm, Syntax, RAX, RBP, RDI};
+use mirage_frontend::module::Module;
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::{Flag, Flags};
+use mirage_frontend::object::statements::{
+    Arch, Compiler, External, Global, Os, Statement, Target, TypeDef,
+};
+use mirage_frontend::object::util::List;
+use mirage_frontend::object::{
+    MirageObject, MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue,
+};
+
+use crate::aarch64::{add_imm, mov_imm};
+use crate::layout::struct_layout;
+use crate::register::{Interval, PhysReg};
+use crate::x86_64::register_allocator;
+use crate::{CodeGen, CodeGenError};
+
+fn i32_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int32().into()
+}
+
+fn i64_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int64().into()
+}
+
+fn f64_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_float64().into()
+}
+
+fn reg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Register, ty)
+}
+
+fn arg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Argument, ty)
+}
+
+fn val(reg: &RegisterValue) -> Value {
+    Value::Register(reg.clone())
+}
+
+fn i32_obj(v: i32) -> MirageObject {
+    MirageObject::from(MirageTypeEnum::type_int32().const_value(v).to_value_enum())
+}
+
+fn i32_val(v: i32) -> Value {
+    Value::ConstValue(i32_obj(v))
+}
+
+fn i64_val(v: i64) -> Value {
+    Value::ConstValue(MirageObject::from(
+        MirageTypeEnum::type_int64().const_value(v).to_value_enum(),
+    ))
+}
+
+fn f64_val(v: f64) -> Value {
+    Value::ConstValue(MirageObject::from(
+        MirageTypeEnum::type_float64()
+            .const_value(v)
+            .to_value_enum(),
+    ))
+}
+
+fn assign(reg: &RegisterValue, cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(reg.clone(), Box::new(LabelBodyInstr::Command(cmd)))
+}
+
+fn assign_call(reg: &RegisterValue, name: &str, args: Vec<Value>) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(
+        reg.clone(),
+        Box::new(LabelBodyInstr::Call(name.to_string(), args)),
+    )
+}
+
+fn cmd(cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Command(cmd)
+}
+
+fn label(name: &str, body: Vec<LabelBodyInstr>) -> Label {
+    Label::new(name.to_string(), Flags::new(vec![]), body)
+}
+
+fn function(name: &str, args: Vec<MirageTypeEnum>, labels: Vec<Label>) -> Statement {
+    let mut f = FunctionType::new(args, i32_ty(), false).fn_value(name.to_string());
+    for l in labels {
+        f.add_label(l);
+    }
+    Statement::Function(f)
+}
+
+fn string(s: &str) -> MirageValueEnum {
+    let ty = MirageTypeEnum::type_array(MirageTypeEnum::type_int8().into(), s.len());
+    MirageValueEnum::Array(
+        ty.const_value(
+            s.bytes()
+                .map(|c| {
+                    MirageTypeEnum::type_int8()
+                        .const_value(c as i8)
+                        .to_value_enum()
+                })
+                .collect(),
+        ),
+    )
+}
+
+fn printf() -> Statement {
+    let ty = FunctionType::new(
+        vec![MirageTypeEnum::type_ptr(MirageTypeEnum::type_int8().into()).into()],
+        i32_ty(),
+        true,
+    );
+    Statement::External(External::new("printf".to_string(), ty))
+}
+
+/// `main`, returning the result of `name` called with `args`
+fn main_calling(name: &str, args: Vec<Value>) -> Statement {
+    function(
+        "main",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign_call(&reg(0, i32_ty()), name, args),
+                cmd(Command::Ret(val(&reg(0, i32_ty())))),
+            ],
+        )],
+    )
+}
+
+fn f32_val(v: f32) -> Value {
+    Value::ConstValue(MirageObject::from(
+        MirageTypeEnum::type_float32()
+            .const_value(v)
+            .to_value_enum(),
+    ))
+}
+
+fn compile(stmts: Vec<Statement>) -> String {
+    let mut codegen = CodeGen::new(stmts, Module::new("test".to_string()));
+    codegen.compile().unwrap();
+    codegen.emit_asm()
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mirage-asm-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Assemble `asm` with the system `as` and return the object file
+fn assemble(name: &str, asm: &str) -> PathBuf {
+    let dir = scratch_dir(name);
+    let source = dir.join(format!("{}.s", name));
+    let object = dir.join(format!("{}.o", name));
+    std::fs::write(&source, asm).unwrap();
+    let out = Process::new("as")
+        .arg(&source)
+        .arg("-o")
+        .arg(&object)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}\n{}",
+        String::from_utf8_lossy(&out.stderr),
+        asm
+    );
+    object
+}
+
+/// Compile `stmts`, assemble and link them with the C library, and run
+/// them. Gives the exit status and the output.
+fn run(name: &str, stmts: Vec<Statement>) -> (i32, String) {
+    link_and_run(assemble(name, &compile(stmts)))
+}
+
+/// Like `run`, with the object encoded by the backend instead of `as`
+fn run_object(name: &str, stmts: Vec<Statement>) -> (i32, String) {
+    let mut codegen = CodeGen::new(stmts, Module::new("test".to_string()));
+    codegen.compile().unwrap();
+    let object = scratch_dir(name).join(format!("{}.o", name));
+    std::fs::write(&object, codegen.emit_object().unwrap()).unwrap();
+    link_and_run(object)
+}
+
+fn link_and_run(object: PathBuf) -> (i32, String) {
+    let exe = object.with_extension("");
+    let out = Process::new("cc")
+        .arg(&object)
+        .arg("-o")
+        .arg(&exe)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let out = Process::new(&exe).output().unwrap();
+    let _ = std::fs::remove_dir_all(exe.parent().unwrap());
+    (
+        out.status.code().unwrap(),
+        String::from_utf8(out.stdout).unwrap(),
+    )
+}
+
+fn counter_loop() -> Statement {
+    let a = arg(0, i32_ty());
+    let r = |i| reg(i, i32_ty());
+    function(
+        "count",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    assign(&r(0), Command::Const(i32_obj(0))),
+                    assign(&r(1), Command::Ref(val(&r(0)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(&r(2), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Jeq("end".to_string(), val(&r(2)), val(&a))),
+                    assign(&r(3), Command::AddInt32(val(&r(2)), i32_val(1))),
+                    cmd(Command::Store(r(1), val(&r(3)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "end",
+                vec![
+                    assign(&r(4), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    )
+}
+
+#[test]
+fn test_printer() {
+    let mov = Asm::sized(
+        AsmCommand::Mov,
+        AsmSize::Dword,
+        vec![AsmArg::Mem(RBP, -8), AsmArg::Reg(RAX)],
+    );
+    assert_eq!(print_att_asm(&mov), "movl %eax, -8(%rbp)");
+    let movabs = Asm::new(
+        AsmCommand::Mov,
+        vec![AsmArg::Reg(RAX), AsmArg::Imm(1 << 40)],
+    );
+    assert_eq!(print_att_asm(&movabs), "movabsq $1099511627776, %rax");
+    let movsx = Asm::sized(
+        AsmCommand::Movsx,
+        AsmSize::Byte,
+        vec![AsmArg::Reg(RAX), AsmArg::Sym("g".to_string())],
+    );
+    assert_eq!(print_att_asm(&movsx), "movsbq g(%rip), %rax");
+}
+
+#[test]
+fn test_printf_and_globals() {
+    let mut helper = label("entry", vec![cmd(Command::Ret(val(&arg(0, i32_ty()))))]);
+    helper.flags.push(Flag::internal());
+    let g = RegisterValue::new(0, RegisterType::Global, string("%d %s %.1f\\n").get_type());
+    let stmts = vec![
+        printf(),
+        Statement::Global(Global::with_flags(
+            "fmt".to_string(),
+            Flags::new(vec![Flag::constant()]),
+            MirageObject::from(string("%d %s %.1f\\n")),
+        )),
+        function("helper", vec![i32_ty()], vec![helper]),
+        function(
+            "main",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign_call(&reg(0, i32_ty()), "helper", vec![i32_val(42)]),
+                    LabelBodyInstr::Call(
+                        "printf".to_string(),
+                        vec![
+                            val(&g),
+                            val(&reg(0, i32_ty())),
+                            Value::ConstValue(MirageObject::from(string("ok"))),
+                            f64_val(2.5),
+                        ],
+                    ),
+                    cmd(Command::Ret(i32_val(3))),
+                ],
+            )],
+        ),
+    ];
+    let asm = compile(stmts.clone());
+    assert!(asm.contains("\t.globl main\n"));
+    assert!(!asm.contains(".globl helper"));
+    assert!(asm.contains("\t.section .rodata\n\t.balign 1\nfmt:\n"));
+    assert_eq!(run("printf", stmts.clone()), (3, "42 ok 2.5\n".to_string()));
+    assert_eq!(
+        run_object("printf-object", stmts),
+        (3, "42 ok 2.5\n".to_string())
+    );
+}
+
+#[test]
+fn test_loop() {
+    let stmts = vec![counter_loop(), main_calling("count", vec![i32_val(5)])];
+    assert_eq!(run("loop", stmts.clone()).0, 5);
+    assert_eq!(run_object("loop-object", stmts.clone()).0, 5);
+
+    let mut codegen = CodeGen::new(stmts, Module::new("test".to_string()));
+    codegen.compile().unwrap();
+    let intel = codegen.emit_asm_with(Syntax::Intel);
+    assert!(intel.starts_with(".intel_syntax noprefix\n"));
+    assert_eq!(link_and_run(assemble("loop-intel", &intel)).0, 5);
+}
+
+/// Swaps two phis around a loop, as many times as its argument says, and
+/// returns 10 if the first one ends up 1, 20 otherwise
+fn swap() -> Statement {
+    let a = arg(0, i64_ty());
+    let r = |i| reg(i, i64_ty());
+    function(
+        "swap",
+        vec![i64_ty()],
+        vec![
+            label("entry", vec![cmd(Command::Jump("loop".to_string()))]),
+            label(
+                "loop",
+                vec![
+                    assign(
+                        &r(0),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(1)),
+                            ("loop".to_string(), val(&r(1))),
+                        ]),
+                    ),
+                    assign(
+                        &r(1),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(2)),
+                            ("loop".to_string(), val(&r(0))),
+                        ]),
+                    ),
+                    assign(
+                        &r(2),
+                        Command::Phi(vec![
+                            ("entry".to_string(), val(&a)),
+                            ("loop".to_string(), val(&r(3))),
+                        ]),
+                    ),
+                    assign(&r(3), Command::SubInt64(val(&r(2)), i64_val(1))),
+                    cmd(Command::Jeq("end".to_string(), val(&r(2)), i64_val(0))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "end",
+                vec![
+                    assign(&reg(4, i32_ty()), Command::Const(i32_obj(10))),
+                    cmd(Command::Jeq("one".to_string(), val(&r(0)), i64_val(1))),
+                    assign(&reg(4, i32_ty()), Command::Const(i32_obj(20))),
+                    cmd(Command::Ret(val(&reg(4, i32_ty())))),
+                ],
+            ),
+            label("one", vec![cmd(Command::Ret(val(&reg(4, i32_ty()))))]),
+        ],
+    )
+}
+
+#[test]
+fn test_phis() {
+    let swap = swap();
+    let even = vec![swap.clone(), main_calling("swap", vec![i64_val(4)])];
+    assert_eq!(run("phis-even", even).0, 10);
+    let odd = vec![swap, main_calling("swap", vec![i64_val(3)])];
+    assert_eq!(run("phis-odd", odd.clone()).0, 20);
+    assert_eq!(run_object("phis-object", odd).0, 20);
+}
+
+/// `second`, building a struct of its argument and 40 and returning twice
+/// the 40 less the argument, and `main` calling it with 3
+fn structs() -> Vec<Statement> {
+    let fields = vec![i32_ty(), i64_ty()];
+    let point: MirageTypeEnum = MirageTypeEnum::type_struct(fields.clone()).into();
+    let p = reg(0, point.clone());
+    let q = reg(2, MirageTypeEnum::type_ptr(i64_ty()).into());
+    vec![
+        Statement::Typedef(TypeDef::new("point".to_string(), List::from_vec(fields))),
+        function(
+            "second",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &p,
+                        Command::New(
+                            "point".to_string(),
+                            List::from_vec(vec![val(&arg(0, i32_ty())), i64_val(40)]),
+                        ),
+                    ),
+                    assign(&reg(1, i64_ty()), Command::Get(p.clone(), 1)),
+                    assign(
+                        &q,
+                        Command::GetElementPtr(point, val(&p), vec![i32_val(0), i32_val(1)]),
+                    ),
+                    assign(&reg(3, i64_ty()), Command::Load(i64_ty(), val(&q))),
+                    assign(
+                        &reg(4, i64_ty()),
+                        Command::AddInt64(val(&reg(1, i64_ty())), val(&reg(3, i64_ty()))),
+                    ),
+                    assign(&reg(5, i32_ty()), Command::Get(p.clone(), 0)),
+                    assign(
+                        &reg(6, i32_ty()),
+                        Command::SubInt32(val(&reg(4, i64_ty())), val(&reg(5, i32_ty()))),
+                    ),
+                    cmd(Command::Ret(val(&reg(6, i32_ty())))),
+                ],
+            )],
+        ),
+        main_calling("second", vec![i32_val(3)]),
+    ]
+}
+
+#[test]
+fn test_structs() {
+    assert_eq!(struct_layout(&[i32_ty(), i64_ty()]), (16, vec![0, 8]));
+    assert_eq!(run("structs", structs()).0, 77);
+
+    let p = reg(0, MirageTypeEnum::type_struct(vec![]).into());
+
+    let mut codegen = CodeGen::new(
+        vec![function(
+            "f",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign(&p, Command::New("line".to_string(), List::from_vec(vec![]))),
+                    cmd(Command::Ret(i32_val(0))),
+                ],
+            )],
+        )],
+        Module::new("test".to_string()),
+    );
+    assert_eq!(
+        codegen.compile(),
+        Err(CodeGenError::UnknownType("line".to_string()))
+    );
+}
+
+/// `f`, taking eight integers and nine doubles, the last two of each being
+/// passed on the stack, and `main` calling it
+fn stack_arguments() -> Vec<Statement> {
+    let mut args = vec![i64_ty(); 8];
+    args.extend(vec![f64_ty(); 9]);
+    let mut body = Vec::new();
+    let mut last = val(&arg(0, i64_ty()));
+    for i in 1..8 {
+        body.push(assign(
+            &reg(i, i64_ty()),
+            Command::SubInt64(last, val(&arg(i, i64_ty()))),
+        ));
+        last = val(&reg(i, i64_ty()));
+    }
+    let mut flast = val(&arg(8, f64_ty()));
+    for i in 9..17 {
+        body.push(assign(
+            &reg(i, f64_ty()),
+            Command::AddFloat64(flast, val(&arg(i, f64_ty()))),
+        ));
+        flast = val(&reg(i, f64_ty()));
+    }
+    body.push(cmd(Command::Jeq("sum".to_string(), flast, f64_val(22.5))));
+    body.push(cmd(Command::Ret(i32_val(1))));
+    let mut f = FunctionType::new(args, i32_ty(), false).fn_value("f".to_string());
+    f.add_label(label("entry", body));
+    f.add_label(label(
+        "sum",
+        vec![
+            assign(&reg(17, i32_ty()), Command::AddInt32(last, i32_val(0))),
+            cmd(Command::Ret(val(&reg(17, i32_ty())))),
+        ],
+    ));
+
+    let mut call_args: Vec<Value> = (1..=8).map(|i| i64_val(i * 10)).collect();
+    call_args.extend((1..=9).map(|i| f64_val(i as f64 * 0.5)));
+    vec![Statement::Function(f), main_calling("f", call_args)]
+}
+
+#[test]
+fn test_stack_arguments() {
+    let stmts = stack_arguments();
+    // 10 - 20 - ... - 80 = -340, truncated to the exit status
+    assert_eq!(run("stack", stmts).0, (-340i32).rem_euclid(256));
+}
+
+#[test]
+fn test_unsupported_arch() {
+    let mut codegen = CodeGen::new(
+        vec![Statement::Target(Target::new(
+            Os::Linux,
+            Arch::X86,
+            Compiler::Gcc,
+        ))],
+        Module::new("test".to_string()),
+    );
+    assert_eq!(
+        codegen.compile(),
+        Err(CodeGenError::UnsupportedArch(Arch::X86))
+    );
+}
+
+#[test]
+fn test_register_allocation() {
+    let interval = |i, start, end, across_call| Interval {
+        key: (RegisterType::Register, i),
+        start,
+        end,
+        float: false,
+        across_call,
+        hint: None,
+    };
+
+    // Ten registers are given out, so the two values living the longest
+    // of twelve are spilled
+    let mut alloc = register_allocator();
+    let given = alloc.allocate((0..12).map(|i| interval(i, i, 100 + i, false)).collect());
+    assert_eq!(given.len(), 10);
+    assert!(!given.contains_key(&(RegisterType::Register, 10)));
+    assert!(!given.contains_key(&(RegisterType::Register, 11)));
+    // The caller-saved ones are given first
+    let first = given[&(RegisterType::Register, 0)];
+    assert!(!alloc.is_callee_saved(first));
+
+    // A value living across a call gets a callee-saved register, which the
+    // function has to save, and a float is spilled
+    let mut alloc = register_allocator();
+    let float = Interval {
+        key: (RegisterType::Register, 1),
+        float: true,
+        ..interval(1, 0, 10, true)
+    };
+    let hinted = Interval {
+        hint: Some(RDI.into()),
+        ..interval(2, 0, 4, false)
+    };
+    let given = alloc.allocate(vec![interval(0, 0, 10, true), float, hinted]);
+    let across = given[&(RegisterType::Register, 0)];
+    assert!(alloc.is_callee_saved(across));
+    assert_eq!(alloc.dirty_callee_saved(), vec![across]);
+    assert!(!given.contains_key(&(RegisterType::Register, 1)));
+    assert_eq!(given[&(RegisterType::Register, 2)], PhysReg::Int(RDI));
+
+    // Values don't share a register when one ends where the other starts
+    let mut alloc = register_allocator();
+    let given = alloc.allocate(vec![interval(0, 0, 4, false), interval(1, 4, 8, false)]);
+    assert_ne!(
+        given[&(RegisterType::Register, 0)],
+        given[&(RegisterType::Register, 1)]
+    );
+}
+
+#[test]
+fn test_register_pressure() {
+    // Fourteen integers and a double live across a call, more than there
+    // are registers to keep them
+    let a = arg(0, i64_ty());
+    let r = |i| reg(i, i64_ty());
+    let f = |i| reg(i, f64_ty());
+    let mut body = Vec::new();
+    for i in 0..14 {
+        body.push(assign(&r(i), Command::AddInt64(val(&a), i64_val(i as i64))));
+    }
+    body.push(assign(
+        &f(14),
+        Command::AddFloat64(f64_val(1.25), f64_val(1.75)),
+    ));
+    body.push(assign_call(&reg(15, i32_ty()), "id", vec![val(&a)]));
+    let mut last = val(&reg(15, i32_ty()));
+    for i in 0..14 {
+        body.push(assign(&r(16 + i), Command::AddInt64(last, val(&r(i)))));
+        last = val(&r(16 + i));
+    }
+    body.push(cmd(Command::Jeq(
+        "done".to_string(),
+        val(&f(14)),
+        f64_val(3.0),
+    )));
+    body.push(cmd(Command::Ret(i32_val(0))));
+
+    let mut id = label("entry", vec![cmd(Command::Ret(val(&a)))]);
+    id.flags.push(Flag::internal());
+    let stmts = vec![
+        function("id", vec![i64_ty()], vec![id]),
+        function(
+            "pressure",
+            vec![i64_ty()],
+            vec![
+                label("entry", body),
+                label("done", vec![cmd(Command::Ret(last))]),
+            ],
+        ),
+        main_calling("pressure", vec![i64_val(2)]),
+    ];
+    let asm = compile(stmts.clone());
+    // The callee-saved registers are saved and restored
+    assert!(asm.contains("\tmovq %rbx, -8(%rbp)\n"));
+    assert!(asm.contains("\tmovq -8(%rbp), %rbx\n"));
+    // 2 + (2 + 0) + ... + (2 + 13)
+    assert_eq!(run("pressure", stmts).0, 2 + 14 * 2 + 91);
+}
+
+/// Compile `stmts` for `arch` on `os`
+fn compile_for(os: Os, arch: Arch, stmts: Vec<Statement>) -> String {
+    let mut target = vec![Statement::Target(Target::new(os, arch, Compiler::Clang))];
+    target.extend(stmts);
+    compile(target)
+}
+
+/// Compile `stmts` for AArch64 on `os`
+fn compile_aarch64(os: Os, stmts: Vec<Statement>) -> String {
+    compile_for(os, Arch::Arm64, stmts)
+}
+
+/// Assemble `asm` for `triple`, with the features `attrs`, with LLVM's
+/// assembler. There is no machine of the other architectures to run the
+/// objects on, so the tests stop there, and are skipped without `llvm-mc`.
+fn assemble_llvm(name: &str, triple: &str, attrs: &str, asm: &str) {
+    let dir = scratch_dir(name);
+    let source = dir.join(format!("{}.s", name));
+    std::fs::write(&source, asm).unwrap();
+    let mut cmd = Process::new("llvm-mc");
+    cmd.arg(format!("-triple={}", triple));
+    if !attrs.is_empty() {
+        cmd.arg(format!("-mattr={}", attrs));
+    }
+    let out = cmd
+        .arg("-filetype=obj")
+        .arg(&source)
+        .arg("-o")
+        .arg(dir.join(format!("{}.o", name)))
+        .output();
+    let _ = std::fs::remove_dir_all(&dir);
+    let Ok(out) = out else {
+        eprintln!("llvm-mc not found, {} is not assembled", name);
+        return;
+    };
+    assert!(
+        out.status.success(),
+        "{}\n{}",
+        String::from_utf8_lossy(&out.stderr),
+        asm
+    );
+}
+
+/// Compile `stmts` for Linux and macOS on AArch64, assemble both, and
+/// return the Linux assembly
+fn check_aarch64(name: &str, stmts: Vec<Statement>) -> String {
+    let elf = compile_aarch64(Os::Linux, stmts.clone());
+    assemble_llvm(name, "aarch64-linux-gnu", "", &elf);
+    let macho = compile_aarch64(Os::MacOs, stmts);
+    assemble_llvm(&format!("{}-macho", name), "arm64-apple-macos", "", &macho);
+    elf
+}
+
+fn print_all(insts: Vec<mirage_backend_asm::aarch64::Inst>) -> Vec<String> {
+    insts.iter().map(|i| print_inst(i, Flavor::Elf)).collect()
+}
+
+#[test]
+fn test_aarch64_immediates() {
+    assert_eq!(print_all(mov_imm(X0, 0)), ["movz x0, #0"]);
+    assert_eq!(print_all(mov_imm(X0, -1)), ["movn x0, #0"]);
+    assert_eq!(
+        print_all(mov_imm(X0, 0x1234_0000_5678)),
+        ["movz x0, #22136", "movk x0, #4660, lsl #32"]
+    );
+    assert_eq!(print_all(mov_imm(X0, -0x10000)), ["movn x0, #65535"]);
+    assert_eq!(print_all(add_imm(X0, X0, 0)), Vec::<String>::new());
+    assert_eq!(print_all(add_imm(X0, X0, -16)), ["sub x0, x0, #16"]);
+    assert_eq!(
+        print_all(add_imm(X0, X0, 0x3000)),
+        ["add x0, x0, #3, lsl #12"]
+    );
+    assert_eq!(
+        print_all(add_imm(X0, X0, 0x3008)),
+        ["add x0, x0, #3, lsl #12", "add x0, x0, #8"]
+    );
+    assert_eq!(
+        print_all(add_imm(X0, X0, 1 << 24)),
+        ["movz x16, #256, lsl #16", "add x0, x0, x16"]
+    );
+}
+
+#[test]
+fn test_aarch64_programs() {
+    let asm = check_aarch64(
+        "a64-loop",
+        vec![counter_loop(), main_calling("count", vec![i32_val(5)])],
+    );
+    assert!(asm.contains("\t.type count, %function\ncount:\n\tstp x29, x30, [sp, #-16]!\n"));
+    assert!(asm.contains("\tb.eq .L.count.end\n"));
+
+    // The swap of the phis is a cycle, broken with `x11`
+    let asm = check_aarch64(
+        "a64-phis",
+        vec![swap(), main_calling("swap", vec![i64_val(3)])],
+    );
+    assert!(asm.contains("\tmov x11, x3\n\tmov x3, x1\n\tmov x1, x11\n\tb .L.swap.loop\n"));
+    check_aarch64("a64-structs", structs());
+    check_aarch64("a64-stack", stack_arguments());
+}
+
+#[test]
+fn test_aarch64_variadic() {
+    let fmt = RegisterValue::new(
+        0,
+        RegisterType::Global,
+        string("%s %d %.1f %.1f\\n").get_type(),
+    );
+    let stmts = vec![
+        printf(),
+        Statement::Global(Global::with_flags(
+            "fmt".to_string(),
+            Flags::new(vec![Flag::constant()]),
+            MirageObject::from(string("%s %d %.1f %.1f\\n")),
+        )),
+        function(
+            "main",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    LabelBodyInstr::Call(
+                        "printf".to_string(),
+                        vec![
+                            val(&fmt),
+                            Value::ConstValue(MirageObject::from(string("ok"))),
+                            i32_val(42),
+                            f32_val(2.5),
+                            f64_val(0.1),
+                        ],
+                    ),
+                    cmd(Command::Ret(i32_val(0))),
+                ],
+            )],
+        ),
+    ];
+    let elf = check_aarch64("a64-printf", stmts.clone());
+    // The variadic arguments are in registers, the float promoted
+    assert!(elf.contains("\tadrp x0, fmt\n\tadd x0, x0, :lo12:fmt\n"));
+    assert!(elf.contains("\tfmov s0, #2.5\n"));
+    assert!(elf.contains("\tldr d1, [x16]\n\tfcvt d0, s0\n\tbl printf\n"));
+    // and on the stack on macOS, in 8-byte slots
+    let macho = compile_aarch64(Os::MacOs, stmts);
+    assert!(macho.contains("\tadrp x0, _fmt@PAGE\n\tadd x0, x0, _fmt@PAGEOFF\n"));
+    assert!(macho.contains("\tsub sp, sp, #32\n"));
+    assert!(macho.contains("\tstr x9, [sp, #8]\n"));
+    assert!(macho.contains("\tfcvt d16, s16\n\tstr d16, [sp, #16]\n"));
+}
+
+#[test]
+fn test_aarch64_large_frame() {
+    // A 4800-byte array in the frame: most of its elements are too far
+    // from the frame pointer for the offsets of the loads and stores
+    let values = (0..600)
+        .map(|i| MirageTypeEnum::type_int64().const_value(i).to_value_enum())
+        .collect();
+    let array = MirageTypeEnum::type_array(i64_ty(), 600).const_value(values);
+    let ptr = reg(1, MirageTypeEnum::type_ptr(i64_ty()).into());
+    let stmts = vec![function(
+        "main",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign(
+                    &reg(0, MirageTypeEnum::type_ptr(i64_ty()).into()),
+                    Command::Const(MirageObject::from(MirageValueEnum::Array(array))),
+                ),
+                assign(
+                    &ptr,
+                    Command::GetElementPtr(i64_ty(), val(&reg(0, i64_ty())), vec![i32_val(599)]),
+                ),
+                assign(&reg(2, i64_ty()), Command::Load(i64_ty(), val(&ptr))),
+                cmd(Command::Ret(val(&reg(2, i64_ty())))),
+            ],
+        )],
+    )];
+    let asm = check_aarch64("a64-frame", stmts);
+    assert!(asm.contains("\tsub sp, sp, #1, lsl #12\n\tsub sp, sp, #704\n"));
+    assert!(asm.contains("\tstur x12, [x29, #-256]\n"));
+    assert!(asm.contains("\tsub x9, x29, #1, lsl #12\n\tsub x9, x9, #704\n"));
+    assert!(asm.contains("\tmovn x16, #4799\n\tstr x12, [x29, x16]\n"));
+    assert!(asm.contains("\tadd x9, x9, #1, lsl #12\n\tadd x9, x9, #696\n"));
+}
+
+/// Compile `stmts` for RISC-V, assemble them for RV64GC, and return the
+/// assembly
+fn check_riscv64(name: &str, stmts: Vec<Statement>) -> String {
+    let asm = compile_for(Os::Linux, Arch::RiscV64, stmts);
+    assemble_llvm(name, "riscv64-unknown-linux-gnu", "+m,+a,+f,+d,+c", &asm);
+    asm
+}
+
+#[test]
+fn test_riscv64_programs() {
+    let asm = check_riscv64(
+        "rv-loop",
+        vec![counter_loop(), main_calling("count", vec![i32_val(5)])],
+    );
+    assert!(asm.contains(
+        "count:\n\taddi sp, sp, -16\n\tsd ra, 8(sp)\n\tsd s0, 0(sp)\n\taddi s0, sp, 16\n"
+    ));
+    // The conditional branch skips over a jump, which reaches further
+    assert!(asm.contains("\tbne t0, t1, .L1\n\tj .L.count.end\n.L1:\n"));
+    assert!(asm.contains("\tsext.w a0, t0\n\taddi sp, s0, -16\n"));
+
+    let asm = check_riscv64(
+        "rv-phis",
+        vec![swap(), main_calling("swap", vec![i64_val(3)])],
+    );
+    assert!(asm.contains("\tbne t0, zero, .L"));
+    check_riscv64("rv-structs", structs());
+    let asm = check_riscv64("rv-stack", stack_arguments());
+    // The ninth double is on the stack, the integer registers being taken
+    assert!(asm
+        .lines()
+        .any(|line| line.starts_with("\tfld ") && line.ends_with(", 0(s0)")));
+}
+
+#[test]
+fn test_riscv64_float_arguments() {
+    // Ten doubles: the last two are passed in `a0` and `a1`
+    let args = vec![f64_ty(); 10];
+    let mut f = FunctionType::new(args, i32_ty(), false).fn_value("f".to_string());
+    f.add_label(label(
+        "entry",
+        vec![
+            assign(
+                &reg(0, f64_ty()),
+                Command::AddFloat64(val(&arg(8, f64_ty())), val(&arg(9, f64_ty()))),
+            ),
+            cmd(Command::Jeq(
+                "one".to_string(),
+                val(&reg(0, f64_ty())),
+                f64_val(1.5),
+            )),
+            cmd(Command::Ret(i32_val(0))),
+        ],
+    ));
+    f.add_label(label("one", vec![cmd(Command::Ret(i32_val(1)))]));
+    let fmt = RegisterValue::new(0, RegisterType::Global, string("%.1f\\n").get_type());
+    let stmts = vec![
+        printf(),
+        Statement::Global(Global::with_flags(
+            "fmt".to_string(),
+            Flags::new(vec![Flag::constant()]),
+            MirageObject::from(string("%.1f\\n")),
+        )),
+        Statement::Function(f),
+        function(
+            "main",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    LabelBodyInstr::Call("printf".to_string(), vec![val(&fmt), f32_val(2.5)]),
+                    assign_call(
+                        &reg(0, i32_ty()),
+                        "f",
+                        (0..10).map(|i| f64_val(i as f64 * 0.25)).collect(),
+                    ),
+                    cmd(Command::Ret(val(&reg(0, i32_ty())))),
+                ],
+            )],
+        ),
+    ];
+    let asm = check_riscv64("rv-floats", stmts);
+    assert!(asm.contains("\tfmv.d.x ft0, a0\n"));
+    assert!(asm.contains("\tfmv.x.d a1, ft0\n"));
+    // A variadic float is promoted, and passed in an integer register
+    assert!(asm.contains("\tfcvt.d.s ft0, ft0\n\tfsd ft0, -24(s0)\n"));
+    assert!(asm.contains("\tfld ft0, -24(s0)\n\tfmv.x.d a1, ft0\n"));
+    assert!(asm.contains("\tfeq.d t0, ft0, ft1\n\tbeq t0, zero, .L"));
+}
+
+#[test]
+fn test_riscv64_large_frame() {
+    // A 4800-byte array: most of its elements are further from the frame
+    // pointer than the 12 bits of the offsets reach
+    let values = (0..600)
+        .map(|i| MirageTypeEnum::type_int64().const_value(i).to_value_enum())
+        .collect();
+    let array = MirageTypeEnum::type_array(i64_ty(), 600).const_value(values);
+    let ptr: MirageTypeEnum = MirageTypeEnum::type_ptr(i64_ty()).into();
+    let stmts = vec![function(
+        "main",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign(
+                    &reg(0, ptr.clone()),
+                    Command::Const(MirageObject::from(MirageValueEnum::Array(array))),
+                ),
+                assign(
+                    &reg(1, ptr.clone()),
+                    Command::GetElementPtr(i64_ty(), val(&reg(0, ptr)), vec![i32_val(599)]),
+                ),
+                assign(
+                    &reg(2, i64_ty()),
+                    Command::Load(i64_ty(), val(&reg(1, i64_ty()))),
+                ),
+                cmd(Command::Ret(val(&reg(2, i64_ty())))),
+            ],
+        )],
+    )];
+    let asm = check_riscv64("rv-frame", stmts);
+    assert!(asm.contains("\taddi s0, sp, 16\n\tli t4, -4800\n\tadd sp, sp, t4\n"));
+    assert!(asm.contains("\tli t4, -4816\n\tadd t4, s0, t4\n\tsd t3, 0(t4)\n"));
+    assert!(asm.contains("\tsd t3, -24(s0)\n"));
+    assert!(asm.contains("\tli t4, 4792\n\tadd t0, t0, t4\n"));
+}
@@ -0,0 +1,931 @@
+use std::collections::HashMap;
+
+use mirage_backend_asm::builder::{Asm, AsmArg, AsmCommand, AsmLabel, AsmSize, FReg, Reg};
+use mirage_backend_asm::x86_64::{
+    R10, R11, R12, R13, R14, R15, R8, R9, RAX, RBP, RBX, RCX, RDI, RDX, RSI, RSP,
+};
+use mirage_backend_opti::ir::{has_attribute, is_phi, reg_key, value_register, RegKey};
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::Flag;
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::object::{MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue};
+
+use crate::data::StaticData;
+use crate::environement::Environement;
+use crate::layout::{align_of, align_to, class_of, is_aggregate, size_of, struct_layout, Class};
+use crate::moves::{Loc, Move, ParallelMove, Src};
+use crate::operand::const_index;
+use crate::register::{allocate_registers, register_classes, PhysReg, RegisterAllocator};
+use crate::{CodeGenError, CodeGenResult};
+
+/// The registers of the integer arguments, in order
+const INT_ARGS: [Reg; 6] = [RDI, RSI, RDX, RCX, R8, R9];
+
+/// The number of float arguments passed in registers, from `xmm0`
+const FLOAT_ARGS: usize = 8;
+
+/// The scratch registers used to fill and copy memory, which no value is
+/// loaded to
+const SCRATCH: Reg = R10;
+const FSCRATCH: FReg = FReg::F15;
+
+/// The registers the values are given, and the classes of the others:
+/// the stack and the frame pointers, `rax`, `rcx`, `xmm0` and `xmm1`, which
+/// the instructions work in and the results come back in, and the scratch
+/// registers are never given. `r11` is the scratch register of the parallel
+/// moves.
+pub fn register_allocator() -> RegisterAllocator {
+    let mut alloc = RegisterAllocator::new(16, 16);
+    for reg in [RSP, RBP, RAX, RCX, SCRATCH, R11] {
+        alloc.make_reserved(reg);
+    }
+    for reg in [FReg::F0, FReg::F1, FSCRATCH] {
+        alloc.make_reserved(reg);
+    }
+    for reg in [RBX, R12, R13, R14, R15] {
+        alloc.make_callee_saved(reg);
+    }
+    alloc
+}
+
+fn fits_i32(imm: i64) -> bool {
+    i32::try_from(imm).is_ok()
+}
+
+fn reg(reg: Reg) -> AsmArg {
+    AsmArg::Reg(reg)
+}
+
+fn freg(reg: FReg) -> AsmArg {
+    AsmArg::FReg(reg)
+}
+
+fn mem(base: Reg, disp: i64) -> AsmArg {
+    AsmArg::Mem(base, disp)
+}
+
+fn float_arg(i: usize) -> FReg {
+    FReg::all()[i]
+}
+
+/// Where the arguments of `func` come: the first ones in registers, the
+/// others on the stack above the return address
+fn incoming(func: &FunctionValue) -> Vec<Loc> {
+    let (mut ints, mut floats, mut stack) = (0, 0, 0);
+    let mut locs = Vec::new();
+    for ty in func.get_type().get_args() {
+        let class = class_of(ty);
+        if class.is_float() && floats < FLOAT_ARGS {
+            locs.push(Loc::FReg(float_arg(floats)));
+            floats += 1;
+        } else if !class.is_float() && ints < INT_ARGS.len() {
+            locs.push(Loc::Reg(INT_ARGS[ints]));
+            ints += 1;
+        } else {
+            locs.push(Loc::Slot(16 + 8 * stack));
+            stack += 1;
+        }
+    }
+    locs
+}
+
+/// Compiles a function to x86-64, for the System V ABI.
+///
+/// The registers are given machine registers by a linear scan over their
+/// live intervals, and the ones which don't get one, or whose address is
+/// taken, live in an 8-byte slot of the frame. They are loaded to `rax`,
+/// `rcx` or `xmm0`, `xmm1` by the instructions using them. A value kept in
+/// a register is sign-extended to 64 bits. Structs and arrays are handled
+/// through their address, and the ones a function creates are in its
+/// frame.
+pub struct FunctionCompiler<'a> {
+    env: &'a Environement,
+    data: &'a mut StaticData,
+    func: &'a FunctionValue,
+    /// The counter of the local labels of the program
+    lid: &'a mut usize,
+    labels: Vec<AsmLabel>,
+    /// The index of the label being compiled
+    current: usize,
+    /// The class of the registers, from their assignment
+    classes: HashMap<RegKey, Class>,
+    /// The machine register of the registers which have one
+    regs: HashMap<RegKey, PhysReg>,
+    /// The offset from the frame pointer of the slot of the other ones
+    slots: HashMap<RegKey, i64>,
+    /// The callee-saved registers the function uses, and where they are
+    /// saved
+    saved: Vec<(Reg, i64)>,
+    frame_size: u64,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    pub fn new(
+        env: &'a Environement,
+        data: &'a mut StaticData,
+        func: &'a FunctionValue,
+        lid: &'a mut usize,
+        mut alloc: RegisterAllocator,
+    ) -> Self {
+        let classes = register_classes(func);
+        let regs = allocate_registers(func, &classes, &incoming(func), &mut alloc);
+        let internal = has_attribute(func, &Flag::internal());
+        let name = func.get_name().clone();
+        let entry = if internal {
+            AsmLabel::new(name)
+        } else {
+            AsmLabel::global(name)
+        };
+        Self {
+            env,
+            data,
+            func,
+            lid,
+            labels: vec![entry],
+            current: 0,
+            classes,
+            regs,
+            slots: HashMap::new(),
+            saved: Vec::new(),
+            frame_size: 0,
+        }
+        .save(alloc.dirty_callee_saved())
+    }
+
+    /// Give a slot to each callee-saved register the function uses
+    fn save(mut self, regs: Vec<PhysReg>) -> Self {
+        for r in regs {
+            if let PhysReg::Int(r) = r {
+                let slot = self.alloc(8, 8);
+                self.saved.push((r, slot));
+            }
+        }
+        self
+    }
+
+    /// Compile the function to its labels: its symbol, then the labels of
+    /// its body
+    pub fn compile(mut self) -> CodeGenResult<Vec<AsmLabel>> {
+        self.arguments()?;
+        let func = self.func;
+        let labels = func.get_labels();
+        for (i, label) in labels.iter().enumerate() {
+            self.current = i;
+            self.labels.push(AsmLabel::new(self.label(&label.name)?));
+            for instr in &label.body {
+                if !is_phi(instr) {
+                    self.statement(instr)?;
+                }
+            }
+            let ends = matches!(
+                label.body.last(),
+                Some(LabelBodyInstr::Command(Command::Jump(_) | Command::Ret(_)))
+            );
+            if !ends {
+                // The label falls through to the next one
+                match labels.get(i + 1) {
+                    Some(next) => self.phis(&next.name)?,
+                    None => self.emit(AsmCommand::Ud2, vec![]),
+                }
+            }
+        }
+        if labels.is_empty() {
+            self.emit(AsmCommand::Ud2, vec![]);
+        }
+        self.prologue();
+        Ok(self.labels)
+    }
+
+    fn emit(&mut self, op: AsmCommand, args: Vec<AsmArg>) {
+        self.emit_sized(op, AsmSize::Qword, args);
+    }
+
+    fn emit_sized(&mut self, op: AsmCommand, size: AsmSize, args: Vec<AsmArg>) {
+        self.labels
+            .last_mut()
+            .expect("the function has its symbol")
+            .add_asm(Asm::sized(op, size, args));
+    }
+
+    /// Save the frame pointer, reserve the frame and save the callee-saved
+    /// registers, at the start of the function
+    fn prologue(&mut self) {
+        let size = align_to(self.frame_size, 16) as i64;
+        let mut prologue = vec![
+            Asm::new(AsmCommand::Push, vec![reg(RBP)]),
+            Asm::new(AsmCommand::Mov, vec![reg(RBP), reg(RSP)]),
+        ];
+        if size > 0 {
+            prologue.push(Asm::new(AsmCommand::Sub, vec![reg(RSP), AsmArg::Imm(size)]));
+        }
+        for (r, slot) in &self.saved {
+            prologue.push(Asm::new(AsmCommand::Mov, vec![mem(RBP, *slot), reg(*r)]));
+        }
+        for (i, asm) in prologue.into_iter().enumerate() {
+            self.labels[0].insert_asm(i, asm);
+        }
+    }
+
+    fn label(&self, name: &String) -> CodeGenResult<String> {
+        self.env
+            .get_label(name)
+            .cloned()
+            .ok_or_else(|| CodeGenError::UnknownLabel(name.clone()))
+    }
+
+    fn local_label(&mut self) -> String {
+        *self.lid += 1;
+        format!(".L{}", self.lid)
+    }
+
+    /// Reserve `size` bytes in the frame and return their offset from the
+    /// frame pointer
+    fn alloc(&mut self, size: u64, align: u64) -> i64 {
+        self.frame_size = align_to(self.frame_size + size, align.max(1));
+        -(self.frame_size as i64)
+    }
+
+    /// Restore the callee-saved registers and return
+    fn epilogue(&mut self) {
+        for (r, slot) in self.saved.clone() {
+            self.emit(AsmCommand::Mov, vec![reg(r), mem(RBP, slot)]);
+        }
+        self.emit(AsmCommand::Leave, vec![]);
+        self.emit(AsmCommand::Ret, vec![]);
+    }
+
+    fn slot(&mut self, key: RegKey) -> i64 {
+        if let Some(slot) = self.slots.get(&key) {
+            return *slot;
+        }
+        let slot = self.alloc(8, 8);
+        self.slots.insert(key, slot);
+        slot
+    }
+
+    fn class(&self, reg: &RegisterValue) -> Class {
+        self.classes
+            .get(&(reg.register_type, reg.index))
+            .copied()
+            .unwrap_or_else(|| class_of(&reg.ty))
+    }
+
+    /// Where the register or the slot of a register is
+    fn loc(&mut self, key: RegKey) -> Loc {
+        match self.regs.get(&key) {
+            Some(PhysReg::Int(r)) => Loc::Reg(*r),
+            Some(PhysReg::Float(r)) => Loc::FReg(*r),
+            None => Loc::Slot(self.slot(key)),
+        }
+    }
+
+    /// Move the arguments from where they come to where they are kept
+    fn arguments(&mut self) -> CodeGenResult<()> {
+        let moves = incoming(self.func)
+            .into_iter()
+            .zip(self.func.get_type().get_args())
+            .enumerate()
+            .map(|(i, (from, ty))| Move {
+                dst: self.loc((RegisterType::Argument, i)),
+                class: class_of(ty),
+                src: Src::Loc(from, class_of(ty)),
+            })
+            .collect();
+        self.parallel_move(moves)
+    }
+
+    /// What a copy reads `value` from
+    fn source<'v>(&mut self, value: &'v Value) -> Src<'v> {
+        match value_register(value) {
+            Some(r) if r.register_type != RegisterType::Global => {
+                Src::Loc(self.loc(reg_key(r)), self.class(r))
+            }
+            _ => Src::Value(value),
+        }
+    }
+
+    /// Load a value of `class` from `src` to `to`, or to `fto` if it is a
+    /// float
+    fn load(&mut self, class: Class, src: AsmArg, to: Reg, fto: FReg) {
+        match class {
+            Class::Int(8) => self.emit(AsmCommand::Mov, vec![reg(to), src]),
+            Class::Int(size) => self.emit_sized(
+                AsmCommand::Movsx,
+                AsmSize::from_bytes(size),
+                vec![reg(to), src],
+            ),
+            Class::F32 => self.emit(AsmCommand::Movss, vec![freg(fto), src]),
+            Class::F64 => self.emit(AsmCommand::Movsd, vec![freg(fto), src]),
+        }
+    }
+
+    /// Store a value of `class` from `from`, or from `ffrom` if it is a
+    /// float, to `dst`
+    fn store(&mut self, class: Class, dst: AsmArg, from: Reg, ffrom: FReg) {
+        match class {
+            Class::Int(size) => self.emit_sized(
+                AsmCommand::Mov,
+                AsmSize::from_bytes(size),
+                vec![dst, reg(from)],
+            ),
+            Class::F32 => self.emit(AsmCommand::Movss, vec![dst, freg(ffrom)]),
+            Class::F64 => self.emit(AsmCommand::Movsd, vec![dst, freg(ffrom)]),
+        }
+    }
+
+    /// Copy `size` bytes from `src` plus `src_off` to `dst` plus `dst_off`
+    fn copy(&mut self, dst: Reg, dst_off: i64, src: Reg, src_off: i64, size: u64) {
+        let mut done = 0;
+        for chunk in [8, 4, 2, 1] {
+            while size - done >= chunk {
+                let class = Class::Int(chunk);
+                self.load(class, mem(src, src_off + done as i64), SCRATCH, FSCRATCH);
+                self.store(class, mem(dst, dst_off + done as i64), SCRATCH, FSCRATCH);
+                done += chunk;
+            }
+        }
+    }
+
+    fn global(&self, index: usize) -> CodeGenResult<(String, MirageTypeEnum)> {
+        self.env
+            .get_global(index)
+            .cloned()
+            .ok_or(CodeGenError::UnknownGlobal(index))
+    }
+
+    /// Load `reg` to `to`, or to `fto` if it is a float
+    fn read(&mut self, r: &RegisterValue, to: Reg, fto: FReg) -> CodeGenResult<Class> {
+        if r.register_type == RegisterType::Global {
+            let (symbol, ty) = self.global(r.index)?;
+            let class = class_of(&ty);
+            if is_aggregate(&ty) {
+                self.emit(AsmCommand::Lea, vec![reg(to), AsmArg::Sym(symbol)]);
+            } else {
+                self.load(class, AsmArg::Sym(symbol), to, fto);
+            }
+            return Ok(class);
+        }
+        let class = self.class(r);
+        let loc = self.loc(reg_key(r));
+        self.load_loc(class, loc, to, fto);
+        Ok(class)
+    }
+
+    /// Assign the value in `rax` or `xmm0` to `reg`
+    fn write(&mut self, r: &RegisterValue) -> CodeGenResult<()> {
+        if r.register_type == RegisterType::Global {
+            let (symbol, ty) = self.global(r.index)?;
+            if is_aggregate(&ty) {
+                self.emit(AsmCommand::Lea, vec![reg(R11), AsmArg::Sym(symbol)]);
+                self.copy(R11, 0, RAX, 0, size_of(&ty));
+            } else {
+                self.store(class_of(&ty), AsmArg::Sym(symbol), RAX, FReg::F0);
+            }
+            return Ok(());
+        }
+        let class = self.class(r);
+        let loc = self.loc(reg_key(r));
+        self.store_loc(class, loc, RAX, FReg::F0);
+        Ok(())
+    }
+
+    fn value_type(value: &Value) -> CodeGenResult<MirageTypeEnum> {
+        match value {
+            Value::List(_) => Err(CodeGenError::Unsupported(value.to_string())),
+            _ => Ok(value.get_type()),
+        }
+    }
+
+    fn object(&mut self, value: &MirageValueEnum, to: Reg, fto: FReg) -> CodeGenResult<Class> {
+        let imm = match value {
+            MirageValueEnum::Register(r) => return self.read(r, to, fto),
+            MirageValueEnum::Int8(v) => v.value as i64,
+            MirageValueEnum::Int16(v) => v.value as i64,
+            MirageValueEnum::Int32(v) => v.value as i64,
+            MirageValueEnum::Int64(v) => v.value,
+            MirageValueEnum::UInt8(v) => v.value as i8 as i64,
+            MirageValueEnum::UInt16(v) => v.value as i16 as i64,
+            MirageValueEnum::UInt32(v) => v.value as i32 as i64,
+            MirageValueEnum::UInt64(v) => v.value as i64,
+            MirageValueEnum::Float32(v) => {
+                let label = self.data.float32(v.value);
+                self.emit(AsmCommand::Movss, vec![freg(fto), AsmArg::Sym(label)]);
+                return Ok(Class::F32);
+            }
+            MirageValueEnum::Float64(v) => {
+                let label = self.data.float64(v.value);
+                self.emit(AsmCommand::Movsd, vec![freg(fto), AsmArg::Sym(label)]);
+                return Ok(Class::F64);
+            }
+            MirageValueEnum::Array(_) | MirageValueEnum::Struct(_) => {
+                if let Some(s) = value.try_to_rust_string() {
+                    let label = self.data.string(&s);
+                    self.emit(AsmCommand::Lea, vec![reg(to), AsmArg::Sym(label)]);
+                    return Ok(Class::Int(8));
+                }
+                let ty = value.get_type();
+                let slot = self.alloc(size_of(&ty), align_of(&ty));
+                self.fill(slot, value)?;
+                self.emit(AsmCommand::Lea, vec![reg(to), mem(RBP, slot)]);
+                return Ok(Class::Int(8));
+            }
+            MirageValueEnum::Pointer(_) => {
+                return Err(CodeGenError::Unsupported(value.print_to_string()))
+            }
+        };
+        self.emit(AsmCommand::Mov, vec![reg(to), AsmArg::Imm(imm)]);
+        Ok(class_of(&value.get_type()))
+    }
+
+    /// Write a constant struct or array to the frame, at `offset`
+    fn fill(&mut self, offset: i64, value: &MirageValueEnum) -> CodeGenResult<()> {
+        match value {
+            MirageValueEnum::Array(a) => {
+                let size = size_of(&a.ty.element_ty()) as i64;
+                for (i, v) in a.values.iter().enumerate() {
+                    self.fill(offset + i as i64 * size, v)?;
+                }
+            }
+            MirageValueEnum::Struct(s) => {
+                let (_, offsets) = struct_layout(&s.ty.fields);
+                for (v, field) in s.values.iter().zip(offsets) {
+                    self.fill(offset + field as i64, v)?;
+                }
+            }
+            _ => {
+                let class = self.object(value, SCRATCH, FSCRATCH)?;
+                self.store(class, mem(RBP, offset), SCRATCH, FSCRATCH);
+            }
+        }
+        Ok(())
+    }
+
+    /// Store `value`, of type `ty`, at `dst` plus `offset`. A struct or an
+    /// array is copied.
+    fn store_value(
+        &mut self,
+        dst: Reg,
+        offset: i64,
+        ty: &MirageTypeEnum,
+        value: &Value,
+    ) -> CodeGenResult<()> {
+        if is_aggregate(ty) {
+            self.value(value, RAX, FReg::F0)?;
+            self.copy(dst, offset, RAX, 0, size_of(ty));
+        } else {
+            let class = self.value(value, RAX, FReg::F0)?;
+            self.store(class, mem(dst, offset), RAX, FReg::F0);
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, instr: &LabelBodyInstr) -> CodeGenResult<()> {
+        match instr {
+            LabelBodyInstr::Assign(r, value) => {
+                let has_value = match &**value {
+                    LabelBodyInstr::Call(name, args) => self.call(name, args).map(|_| true)?,
+                    LabelBodyInstr::Command(cmd) => self.command(cmd)?,
+                    LabelBodyInstr::Assign(..) => false,
+                };
+                if !has_value {
+                    return Err(CodeGenError::Unsupported(instr.to_string()));
+                }
+                self.write(r)
+            }
+            LabelBodyInstr::Call(name, args) => self.call(name, args),
+            LabelBodyInstr::Command(cmd) => self.command(cmd).map(|_| ()),
+        }
+    }
+
+    /// Call `name`. The result is left in `rax` or `xmm0`.
+    fn call(&mut self, name: &str, args: &[Value]) -> CodeGenResult<()> {
+        let env = self.env;
+        let ty = env
+            .get_function(name)
+            .ok_or_else(|| CodeGenError::UnknownFunction(name.to_string()))?;
+        let fixed = ty.get_args().len();
+        if args.len() < fixed {
+            return Err(CodeGenError::ArgumentCount(name.to_string()));
+        }
+
+        // Classify the arguments: a `float` passed to the variadic part is
+        // promoted to a `double`, and an unsigned narrow integer, which is
+        // held sign-extended, is zero-extended
+        let mut classes = Vec::with_capacity(args.len());
+        for (i, arg) in args.iter().enumerate() {
+            let class = match value_register(arg) {
+                Some(r) if r.register_type != RegisterType::Global => self.class(r),
+                _ => class_of(&Self::value_type(arg)?),
+            };
+            let promote = i >= fixed && class == Class::F32;
+            let zero_extend = match Self::value_type(arg)? {
+                MirageTypeEnum::UInt8(_) if i >= fixed => Some(AsmSize::Byte),
+                MirageTypeEnum::UInt16(_) if i >= fixed => Some(AsmSize::Word),
+                _ => None,
+            };
+            classes.push((class, promote, zero_extend));
+        }
+        let (mut ints, mut floats, mut extends) = (0, Vec::new(), Vec::new());
+        let (mut moves, mut stack) = (Vec::new(), Vec::new());
+        for (arg, (class, promote, zero_extend)) in args.iter().zip(&classes) {
+            let dst = if class.is_float() && floats.len() < FLOAT_ARGS {
+                let dst = float_arg(floats.len());
+                floats.push((dst, *promote));
+                Loc::FReg(dst)
+            } else if !class.is_float() && ints < INT_ARGS.len() {
+                ints += 1;
+                if let Some(size) = zero_extend {
+                    extends.push((INT_ARGS[ints - 1], *size));
+                }
+                Loc::Reg(INT_ARGS[ints - 1])
+            } else {
+                stack.push((arg, *class, *promote, *zero_extend));
+                continue;
+            };
+            let src = self.source(arg);
+            moves.push(Move {
+                dst,
+                class: *class,
+                src,
+            });
+        }
+
+        // The arguments on the stack first, since loading them goes through
+        // `rax` and `xmm0`
+        let area = align_to(8 * stack.len() as u64, 16) as i64;
+        if area > 0 {
+            self.emit(AsmCommand::Sub, vec![reg(RSP), AsmArg::Imm(area)]);
+        }
+        for (i, (arg, _, promote, zero_extend)) in stack.into_iter().enumerate() {
+            let class = self.value(arg, RAX, FReg::F0)?;
+            let at = mem(RSP, 8 * i as i64);
+            if let Some(size) = zero_extend {
+                self.emit_sized(AsmCommand::Movzx, size, vec![reg(RAX), reg(RAX)]);
+            }
+            if promote {
+                self.emit(AsmCommand::Cvtss2sd, vec![freg(FReg::F0), freg(FReg::F0)]);
+                self.store(Class::F64, at, RAX, FReg::F0);
+            } else if class.is_float() {
+                self.store(class, at, RAX, FReg::F0);
+            } else {
+                self.store(Class::Int(8), at, RAX, FReg::F0);
+            }
+        }
+        // The registers of the arguments may hold the values of others
+        self.parallel_move(moves)?;
+        for (r, size) in extends {
+            self.emit_sized(AsmCommand::Movzx, size, vec![reg(r), reg(r)]);
+        }
+        let used = floats.len();
+        for (to, promote) in floats {
+            if promote {
+                self.emit(AsmCommand::Cvtss2sd, vec![freg(to), freg(to)]);
+            }
+        }
+        if ty.is_var_arg() {
+            // A variadic callee is told how many vector registers are used
+            self.emit_sized(
+                AsmCommand::Mov,
+                AsmSize::Dword,
+                vec![reg(RAX), AsmArg::Imm(used as i64)],
+            );
+        }
+        self.emit(AsmCommand::Call, vec![AsmArg::Label(name.to_string())]);
+        if area > 0 {
+            self.emit(AsmCommand::Add, vec![reg(RSP), AsmArg::Imm(area)]);
+        }
+        Ok(())
+    }
+
+    /// Emit `cmd`, returning whether it leaves a value in `rax` or `xmm0`
+    fn command(&mut self, cmd: &Command) -> CodeGenResult<bool> {
+        match cmd {
+            Command::Store(r, value) => {
+                let ty = Self::value_type(value)?;
+                self.read(r, R11, FReg::F0)?;
+                self.store_value(R11, 0, &ty, value)?;
+                Ok(false)
+            }
+            Command::New(name, args) => {
+                let env = self.env;
+                let fields = env
+                    .get_struct(name)
+                    .ok_or_else(|| CodeGenError::UnknownType(name.clone()))?;
+                let (size, offsets) = struct_layout(fields);
+                let align = fields.iter().map(align_of).max().unwrap_or(1);
+                let slot = self.alloc(size, align);
+                for ((arg, field), offset) in args.iter().zip(fields).zip(offsets) {
+                    self.store_value(RBP, slot + offset as i64, field, arg)?;
+                }
+                self.emit(AsmCommand::Lea, vec![reg(RAX), mem(RBP, slot)]);
+                Ok(true)
+            }
+            Command::Get(r, index) => {
+                let fields = match &r.ty {
+                    MirageTypeEnum::Struct(s) => &s.fields,
+                    MirageTypeEnum::Pointer(p) => match &*p.element_ty {
+                        MirageTypeEnum::Struct(s) => &s.fields,
+                        _ => return Err(CodeGenError::Unsupported(cmd.to_string())),
+                    },
+                    _ => return Err(CodeGenError::Unsupported(cmd.to_string())),
+                };
+                let (_, offsets) = struct_layout(fields);
+                let Some((ty, offset)) = fields.get(*index).zip(offsets.get(*index)) else {
+                    return Err(CodeGenError::Unsupported(cmd.to_string()));
+                };
+                self.read(r, R11, FReg::F0)?;
+                if is_aggregate(ty) {
+                    self.emit(AsmCommand::Lea, vec![reg(RAX), mem(R11, *offset as i64)]);
+                } else {
+                    self.load(class_of(ty), mem(R11, *offset as i64), RAX, FReg::F0);
+                }
+                Ok(true)
+            }
+            Command::Const(obj) => {
+                self.object(obj.get_value_ref(), RAX, FReg::F0)?;
+                Ok(true)
+            }
+            // The frame is given back on return, and there is no heap
+            Command::Free(_) => Ok(false),
+            Command::Ret(value) => {
+                self.value(value, RAX, FReg::F0)?;
+                self.epilogue();
+                Ok(false)
+            }
+            Command::Jump(label) => {
+                self.phis(label)?;
+                let target = self.label(label)?;
+                self.emit(AsmCommand::Jmp, vec![AsmArg::Label(target)]);
+                Ok(false)
+            }
+            Command::Jeq(label, lhs, rhs) => {
+                self.jeq(label, lhs, rhs)?;
+                Ok(false)
+            }
+            Command::Phi(_) => Ok(false),
+            Command::IncrInt8(r)
+            | Command::IncrInt16(r)
+            | Command::IncrInt32(r)
+            | Command::IncrInt64(r) => {
+                self.read(r, RAX, FReg::F0)?;
+                self.emit(AsmCommand::Add, vec![reg(RAX), AsmArg::Imm(1)]);
+                Ok(true)
+            }
+            Command::IncrFloat32(r) => {
+                self.read(r, RAX, FReg::F0)?;
+                let one = self.data.float32(1.0);
+                self.emit(AsmCommand::Addss, vec![freg(FReg::F0), AsmArg::Sym(one)]);
+                Ok(true)
+            }
+            Command::IncrFloat64(r) => {
+                self.read(r, RAX, FReg::F0)?;
+                let one = self.data.float64(1.0);
+                self.emit(AsmCommand::Addsd, vec![freg(FReg::F0), AsmArg::Sym(one)]);
+                Ok(true)
+            }
+            Command::AddInt8(lhs, rhs)
+            | Command::AddInt16(lhs, rhs)
+            | Command::AddInt32(lhs, rhs)
+            | Command::AddInt64(lhs, rhs) => self.arith(lhs, rhs, AsmCommand::Add),
+            Command::AddFloat32(lhs, rhs) => self.arith(lhs, rhs, AsmCommand::Addss),
+            Command::AddFloat64(lhs, rhs) => self.arith(lhs, rhs, AsmCommand::Addsd),
+            Command::SubInt8(lhs, rhs)
+            | Command::SubInt16(lhs, rhs)
+            | Command::SubInt32(lhs, rhs)
+            | Command::SubInt64(lhs, rhs) => self.arith(lhs, rhs, AsmCommand::Sub),
+            Command::SubFloat32(lhs, rhs) => self.arith(lhs, rhs, AsmCommand::Subss),
+            Command::SubFloat64(lhs, rhs) => self.arith(lhs, rhs, AsmCommand::Subsd),
+            Command::Ref(value) => {
+                match value_register(value) {
+                    Some(r) => self.address_of(r)?,
+                    None => {
+                        // A constant gets a slot of its own
+                        let ty = Self::value_type(value)?;
+                        if is_aggregate(&ty) {
+                            self.value(value, RAX, FReg::F0)?;
+                        } else {
+                            let slot = self.alloc(size_of(&ty), align_of(&ty));
+                            self.store_value(RBP, slot, &ty, value)?;
+                            self.emit(AsmCommand::Lea, vec![reg(RAX), mem(RBP, slot)]);
+                        }
+                    }
+                }
+                Ok(true)
+            }
+            Command::Load(ty, value) => {
+                self.value(value, R11, FReg::F0)?;
+                if is_aggregate(ty) {
+                    // Loading a struct or an array copies it
+                    let slot = self.alloc(size_of(ty), align_of(ty));
+                    self.copy(RBP, slot, R11, 0, size_of(ty));
+                    self.emit(AsmCommand::Lea, vec![reg(RAX), mem(RBP, slot)]);
+                } else {
+                    self.load(class_of(ty), mem(R11, 0), RAX, FReg::F0);
+                }
+                Ok(true)
+            }
+            Command::GetElementPtr(ty, base, indices) => {
+                self.value(base, RAX, FReg::F0)?;
+                self.element_ptr(ty, indices)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// `lhs op rhs`. The integers are added on 64 bits, and truncated when
+    /// they are stored.
+    fn arith(&mut self, lhs: &Value, rhs: &Value, op: AsmCommand) -> CodeGenResult<bool> {
+        self.value(lhs, RAX, FReg::F0)?;
+        self.value(rhs, RCX, FReg::F1)?;
+        match op {
+            AsmCommand::Add | AsmCommand::Sub => self.emit(op, vec![reg(RAX), reg(RCX)]),
+            _ => self.emit(op, vec![freg(FReg::F0), freg(FReg::F1)]),
+        }
+        Ok(true)
+    }
+
+    /// Branch to `label` if `lhs` equals `rhs`. The branch goes around the
+    /// copies to the phis of `label` when there are some.
+    fn jeq(&mut self, label: &String, lhs: &Value, rhs: &Value) -> CodeGenResult<()> {
+        let class = self.value(lhs, RAX, FReg::F0)?;
+        self.value(rhs, RCX, FReg::F1)?;
+        match class {
+            Class::F32 => self.emit(AsmCommand::Ucomiss, vec![freg(FReg::F0), freg(FReg::F1)]),
+            Class::F64 => self.emit(AsmCommand::Ucomisd, vec![freg(FReg::F0), freg(FReg::F1)]),
+            Class::Int(_) => self.emit(AsmCommand::Cmp, vec![reg(RAX), reg(RCX)]),
+        }
+        let target = self.label(label)?;
+        if !class.is_float() && self.phi_copies(label).is_empty() {
+            self.emit(AsmCommand::Je, vec![AsmArg::Label(target)]);
+            return Ok(());
+        }
+        let skip = self.local_label();
+        if class.is_float() {
+            // Unordered operands, a NaN among them, aren't equal
+            self.emit(AsmCommand::Jp, vec![AsmArg::Label(skip.clone())]);
+        }
+        self.emit(AsmCommand::Jne, vec![AsmArg::Label(skip.clone())]);
+        self.phis(label)?;
+        self.emit(AsmCommand::Jmp, vec![AsmArg::Label(target)]);
+        self.labels.push(AsmLabel::new(skip));
+        Ok(())
+    }
+
+    /// The phis of `to` and the values they take coming from the label
+    /// being compiled
+    fn phi_copies(&self, to: &String) -> Vec<(&'a RegisterValue, &'a Value)> {
+        let labels: &'a Vec<_> = self.func.get_labels();
+        let from = &labels[self.current].name;
+        let Some(to) = labels.iter().find(|l| &l.name == to) else {
+            return Vec::new();
+        };
+        to.body
+            .iter()
+            .map_while(|instr| match instr {
+                LabelBodyInstr::Assign(r, value) => match &**value {
+                    LabelBodyInstr::Command(Command::Phi(incoming)) => Some((r, incoming)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .filter_map(|(r, incoming)| {
+                incoming
+                    .iter()
+                    .find(|(label, _)| label == from)
+                    .map(|(_, value)| (r, value))
+            })
+            .collect()
+    }
+
+    /// Assign the phis of `to` the values coming from the label being
+    /// compiled. The phis read their operands before any of them is
+    /// assigned.
+    fn phis(&mut self, to: &String) -> CodeGenResult<()> {
+        let mut moves = Vec::new();
+        for (r, value) in self.phi_copies(to) {
+            if r.register_type == RegisterType::Global {
+                return Err(CodeGenError::Unsupported(format!(
+                    "phi to {}",
+                    r.print_to_string()
+                )));
+            }
+            let dst = self.loc(reg_key(r));
+            let class = self.class(r);
+            let src = self.source(value);
+            moves.push(Move { dst, class, src });
+        }
+        self.parallel_move(moves)
+    }
+
+    /// Leave in `rax` the address `ref reg` gives
+    fn address_of(&mut self, r: &RegisterValue) -> CodeGenResult<()> {
+        if r.register_type == RegisterType::Global {
+            let (symbol, _) = self.global(r.index)?;
+            self.emit(AsmCommand::Lea, vec![reg(RAX), AsmArg::Sym(symbol)]);
+        } else if is_aggregate(&r.ty) {
+            self.read(r, RAX, FReg::F0)?;
+        } else {
+            let slot = self.slot((r.register_type, r.index));
+            self.emit(AsmCommand::Lea, vec![reg(RAX), mem(RBP, slot)]);
+        }
+        Ok(())
+    }
+
+    /// Offset the address in `rax` as `getelementptr` does: the first index
+    /// steps over whole values of `ty`, the next ones go into it
+    fn element_ptr(&mut self, ty: &MirageTypeEnum, indices: &[Value]) -> CodeGenResult<()> {
+        let mut ty = ty.clone();
+        for (i, index) in indices.iter().enumerate() {
+            if i > 0 {
+                match ty {
+                    MirageTypeEnum::Struct(s) => {
+                        let field = const_index(index)
+                            .and_then(|f| usize::try_from(f).ok())
+                            .filter(|f| *f < s.fields.len())
+                            .ok_or_else(|| CodeGenError::Unsupported(index.to_string()))?;
+                        let (_, offsets) = struct_layout(&s.fields);
+                        self.add_offset(offsets[field] as i64);
+                        ty = s.fields[field].clone();
+                        continue;
+                    }
+                    MirageTypeEnum::Array(a) => ty = a.element_ty(),
+                    _ => {
+                        return Err(CodeGenError::Unsupported(format!(
+                            "getelementptr into {}",
+                            ty.print_to_string()
+                        )))
+                    }
+                }
+            }
+            let stride = size_of(&ty) as i64;
+            match const_index(index) {
+                Some(c) => self.add_offset(c.wrapping_mul(stride)),
+                None => {
+                    self.value(index, RCX, FReg::F1)?;
+                    self.emit(AsmCommand::Mul, vec![reg(RCX), AsmArg::Imm(stride)]);
+                    self.emit(AsmCommand::Add, vec![reg(RAX), reg(RCX)]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_offset(&mut self, offset: i64) {
+        if offset == 0 {
+            return;
+        }
+        if fits_i32(offset) {
+            self.emit(AsmCommand::Add, vec![reg(RAX), AsmArg::Imm(offset)]);
+        } else {
+            self.emit(AsmCommand::Mov, vec![reg(RCX), AsmArg::Imm(offset)]);
+            self.emit(AsmCommand::Add, vec![reg(RAX), reg(RCX)]);
+        }
+    }
+}
+
+impl ParallelMove for FunctionCompiler<'_> {
+    const TEMP: Reg = RAX;
+    const FTEMP: FReg = FReg::F0;
+    const CYCLE: Reg = R11;
+    const FCYCLE: FReg = FSCRATCH;
+
+    fn load_loc(&mut self, class: Class, loc: Loc, to: Reg, fto: FReg) {
+        match (loc, class) {
+            (Loc::Slot(slot), _) => self.load(class, mem(RBP, slot), to, fto),
+            (Loc::Reg(r), _) if r != to => self.emit(AsmCommand::Mov, vec![reg(to), reg(r)]),
+            (Loc::FReg(r), Class::F32) if r != fto => {
+                self.emit(AsmCommand::Movss, vec![freg(fto), freg(r)])
+            }
+            (Loc::FReg(r), _) if r != fto => self.emit(AsmCommand::Movsd, vec![freg(fto), freg(r)]),
+            _ => {}
+        }
+    }
+
+    fn store_loc(&mut self, class: Class, loc: Loc, from: Reg, ffrom: FReg) {
+        match (loc, class) {
+            (Loc::Slot(slot), _) => self.store(class, mem(RBP, slot), from, ffrom),
+            (Loc::Reg(r), Class::Int(size)) if size < 8 => self.emit_sized(
+                AsmCommand::Movsx,
+                AsmSize::from_bytes(size),
+                vec![reg(r), reg(from)],
+            ),
+            (Loc::Reg(r), _) if r != from => self.emit(AsmCommand::Mov, vec![reg(r), reg(from)]),
+            (Loc::FReg(r), Class::F32) if r != ffrom => {
+                self.emit(AsmCommand::Movss, vec![freg(r), freg(ffrom)])
+            }
+            (Loc::FReg(r), _) if r != ffrom => {
+                self.emit(AsmCommand::Movsd, vec![freg(r), freg(ffrom)])
+            }
+            _ => {}
+        }
+    }
+
+    fn value(&mut self, value: &Value, to: Reg, fto: FReg) -> CodeGenResult<Class> {
+        match value {
+            Value::ConstValue(obj) => self.object(obj.get_value_ref(), to, fto),
+            Value::Register(r) => self.read(r, to, fto),
+            Value::List(_) => Err(CodeGenError::Unsupported(value.to_string())),
+        }
+    }
+}
@@ -0,0 +1,680 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_backend_opti::ir::{
+    for_each_use, has_attribute, is_phi, reg_key, value_register, RegKey,
+};
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::Flag;
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::object::{MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue};
+
+use crate::literal::{initializer, literal};
+use crate::string::c_string;
+use crate::types::{CType, Expr, Types};
+use crate::{signature, CodeGenError, CodeGenResult, Env};
+
+/// Whether values of `ty` live in memory and are handled through their
+/// address: arrays, strings included, and structs
+fn is_aggregate(ty: &MirageTypeEnum) -> bool {
+    matches!(ty, MirageTypeEnum::Array(_) | MirageTypeEnum::Struct(_))
+}
+
+/// The constant integer `value` holds, if it is one
+fn const_index(value: &Value) -> Option<i64> {
+    let Value::ConstValue(obj) = value else {
+        return None;
+    };
+    match obj.get_value_ref() {
+        MirageValueEnum::Int8(v) => Some(v.value as i64),
+        MirageValueEnum::Int16(v) => Some(v.value as i64),
+        MirageValueEnum::Int32(v) => Some(v.value as i64),
+        MirageValueEnum::Int64(v) => Some(v.value),
+        MirageValueEnum::UInt8(v) => Some(v.value as i64),
+        MirageValueEnum::UInt16(v) => Some(v.value as i64),
+        MirageValueEnum::UInt32(v) => Some(v.value as i64),
+        MirageValueEnum::UInt64(v) => Some(v.value as i64),
+        _ => None,
+    }
+}
+
+/// A pointer to values of the C type `name`
+fn pointer_to(name: &str) -> String {
+    if name.ends_with('*') {
+        format!("{}*", name)
+    } else {
+        format!("{} *", name)
+    }
+}
+
+/// The C label of the label `name`
+fn label_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("L_{}", name)
+}
+
+/// Compiles a function to a C function.
+///
+/// The registers are locals named `r0`, `v0` and the arguments `a0` and so
+/// on, of the C type of their first assignment. The structs and arrays the
+/// function creates, and the constants whose address is taken, are locals
+/// named `s0`, `s1` and so on, one for every instruction creating one. The
+/// phis are assigned by the branches to their label, through temporaries
+/// when there are several of them.
+pub struct FunctionCompiler<'a> {
+    env: &'a Env,
+    types: &'a mut Types,
+    /// The static objects holding the constant structs and arrays of the
+    /// program
+    constants: &'a mut Vec<String>,
+    func: &'a FunctionValue,
+    /// The C type of the registers
+    regs: HashMap<RegKey, CType>,
+    /// The declarations of the locals holding structs, arrays and constants
+    slots: Vec<String>,
+    /// The labels some instruction jumps to
+    targets: HashSet<String>,
+    /// The label being compiled
+    current: String,
+    lines: Vec<String>,
+    indent: usize,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    pub fn new(
+        env: &'a Env,
+        types: &'a mut Types,
+        constants: &'a mut Vec<String>,
+        func: &'a FunctionValue,
+    ) -> Self {
+        let mut regs = HashMap::new();
+        let mut targets = HashSet::new();
+        for (i, ty) in func.get_type().get_args().iter().enumerate() {
+            regs.insert((RegisterType::Argument, i), CType::of(ty));
+        }
+        for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+            match instr {
+                LabelBodyInstr::Assign(reg, value) => {
+                    // A `getelementptr`, a `ref` or a `new` gives an
+                    // address, whatever the type of the register
+                    let ty = match **value {
+                        LabelBodyInstr::Command(
+                            Command::GetElementPtr(..) | Command::Ref(_) | Command::New(..),
+                        ) => CType::Ptr,
+                        _ => CType::of(&reg.ty),
+                    };
+                    regs.entry(reg_key(reg)).or_insert(ty);
+                }
+                LabelBodyInstr::Command(Command::Jump(label) | Command::Jeq(label, ..)) => {
+                    targets.insert(label.clone());
+                }
+                _ => {}
+            }
+            for_each_use(instr, &mut |reg, _| {
+                regs.entry(reg_key(reg)).or_insert(CType::of(&reg.ty));
+            });
+        }
+        Self {
+            env,
+            types,
+            constants,
+            func,
+            regs,
+            slots: Vec::new(),
+            targets,
+            current: String::new(),
+            lines: Vec::new(),
+            indent: 1,
+        }
+    }
+
+    pub fn compile(mut self) -> CodeGenResult<String> {
+        let func = self.func;
+        let labels = func.get_labels();
+        for (i, label) in labels.iter().enumerate() {
+            self.current = label.name.clone();
+            if self.targets.contains(&label.name) {
+                self.lines.push(format!("{}:;", label_name(&label.name)));
+            }
+            for instr in &label.body {
+                if !is_phi(instr) {
+                    self.statement(instr)?;
+                }
+            }
+            let falls_through = !matches!(
+                label.body.last(),
+                Some(LabelBodyInstr::Command(Command::Jump(_) | Command::Ret(_)))
+            );
+            if falls_through {
+                match labels.get(i + 1) {
+                    Some(next) => self.phis(&next.name)?,
+                    None => self.line("MIRAGE_TRAP();"),
+                }
+            }
+        }
+        if labels.is_empty() {
+            self.line("MIRAGE_TRAP();");
+        }
+
+        let static_ = if has_attribute(func, &Flag::internal()) {
+            "static "
+        } else {
+            ""
+        };
+        let mut code = format!(
+            "{}{} {{\n",
+            static_,
+            signature(func.get_name(), func.get_type(), false)?
+        );
+        let mut regs: Vec<(&RegKey, &CType)> = self
+            .regs
+            .iter()
+            .filter(|(key, _)| !matches!(key.0, RegisterType::Argument | RegisterType::Global))
+            .collect();
+        regs.sort_by_key(|(key, _)| (register_prefix(key.0), key.1));
+        for (key, ty) in regs {
+            code.push_str(&format!("    {};\n", ty.declare(&register_name(*key))));
+        }
+        for slot in &self.slots {
+            code.push_str(&format!("    {};\n", slot));
+        }
+        for line in &self.lines {
+            code.push_str(line);
+            code.push('\n');
+        }
+        code.push_str("}\n");
+        Ok(code)
+    }
+
+    fn line(&mut self, line: impl AsRef<str>) {
+        let indent = "    ".repeat(self.indent);
+        self.lines.push(format!("{}{}", indent, line.as_ref()));
+    }
+
+    fn statement(&mut self, instr: &LabelBodyInstr) -> CodeGenResult<()> {
+        match instr {
+            LabelBodyInstr::Assign(reg, value) => {
+                let value = match &**value {
+                    LabelBodyInstr::Call(name, args) => self.call(name, args)?,
+                    LabelBodyInstr::Command(cmd) => self.command(cmd)?,
+                    LabelBodyInstr::Assign(..) => {
+                        return Err(CodeGenError::Unsupported(instr.to_string()))
+                    }
+                };
+                self.write(reg, &value)
+            }
+            LabelBodyInstr::Call(name, args) => {
+                let call = self.call(name, args)?;
+                self.line(format!("{};", call.code));
+                Ok(())
+            }
+            LabelBodyInstr::Command(cmd) => match cmd {
+                Command::Store(reg, value) => self.store(reg, value),
+                Command::Ret(value) => {
+                    let value = self.value(value)?;
+                    let ty = CType::of(self.func.get_type().get_ret());
+                    self.line(format!("return {};", value.to(ty)));
+                    Ok(())
+                }
+                Command::Jump(label) => self.jump(label),
+                Command::Jeq(label, lhs, rhs) => {
+                    let lhs = self.value(lhs)?;
+                    let rhs = self.value(rhs)?;
+                    self.line(format!("if ({} == {}) {{", lhs.code, rhs.to(lhs.ty)));
+                    self.indent += 1;
+                    self.jump(label)?;
+                    self.indent -= 1;
+                    self.line("}");
+                    Ok(())
+                }
+                // The locals are given back on return, and there is no heap
+                Command::Free(_) | Command::Phi(_) => Ok(()),
+                // The value isn't used, but making it may have effects
+                _ => self.command(cmd).map(|_| ()),
+            },
+        }
+    }
+
+    /// Assign the phis of `label` and go to it
+    fn jump(&mut self, label: &str) -> CodeGenResult<()> {
+        self.phis(label)?;
+        self.line(format!("goto {};", label_name(label)));
+        Ok(())
+    }
+
+    /// Assign the phis of `label` the values coming from the label being
+    /// compiled
+    fn phis(&mut self, label: &str) -> CodeGenResult<()> {
+        let func = self.func;
+        let target = func
+            .get_labels()
+            .iter()
+            .find(|l| l.name == label)
+            .ok_or_else(|| CodeGenError::UnknownLabel(label.to_string()))?;
+        let copies: Vec<(&RegisterValue, &Value)> = target
+            .body
+            .iter()
+            .map_while(|instr| match instr {
+                LabelBodyInstr::Assign(reg, value) => match &**value {
+                    LabelBodyInstr::Command(Command::Phi(incoming)) => Some((reg, incoming)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .filter_map(|(reg, incoming)| {
+                incoming
+                    .iter()
+                    .find(|(label, _)| *label == self.current)
+                    .map(|(_, value)| (reg, value))
+            })
+            .collect();
+        if let [(reg, value)] = copies[..] {
+            let value = self.value(value)?;
+            return self.write(reg, &value);
+        }
+        if copies.is_empty() {
+            return Ok(());
+        }
+
+        // The phis read their operands before any of them is assigned
+        self.line("{");
+        self.indent += 1;
+        let mut temps = Vec::with_capacity(copies.len());
+        for (i, (reg, value)) in copies.iter().enumerate() {
+            let ty = self.reg_type(reg);
+            let value = self.value(value)?;
+            let temp = format!("t{}", i);
+            self.line(format!("{} = {};", ty.declare(&temp), value.to(ty)));
+            temps.push(Expr::new(temp, ty));
+        }
+        for ((reg, _), temp) in copies.iter().zip(temps) {
+            self.write(reg, &temp)?;
+        }
+        self.indent -= 1;
+        self.line("}");
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, args: &[Value]) -> CodeGenResult<Expr> {
+        let env = self.env;
+        let ty = env
+            .functions
+            .get(name)
+            .ok_or_else(|| CodeGenError::UnknownFunction(name.to_string()))?;
+        let fixed = ty.get_args();
+        if args.len() < fixed.len() {
+            return Err(CodeGenError::ArgumentCount(name.to_string()));
+        }
+        let count = if ty.is_var_arg() {
+            args.len()
+        } else {
+            fixed.len()
+        };
+        let mut values = Vec::with_capacity(count);
+        for (i, arg) in args[..count].iter().enumerate() {
+            let value = self.value(arg)?;
+            // The variadic arguments are promoted by C
+            values.push(match fixed.get(i) {
+                Some(ty) => value.to(CType::of(ty)),
+                None => value.code,
+            });
+        }
+        Ok(Expr::new(
+            format!("{}({})", name, values.join(", ")),
+            CType::of(ty.get_ret()),
+        ))
+    }
+
+    /// The value of a command which makes one
+    fn command(&mut self, cmd: &Command) -> CodeGenResult<Expr> {
+        use CType::*;
+        match cmd {
+            Command::New(name, args) => {
+                let env = self.env;
+                let fields = env
+                    .structs
+                    .get(name)
+                    .ok_or_else(|| CodeGenError::UnknownType(name.clone()))?;
+                let slot = self.slot(&MirageTypeEnum::type_struct(fields.clone()).into())?;
+                for (i, (arg, field)) in args.iter().zip(fields).enumerate() {
+                    let value = self.value(arg)?;
+                    let field_name = format!("{}.f{}", slot, i);
+                    self.assign(&field_name, field, &value)?;
+                }
+                Ok(Expr::new(format!("(void *)&{}", slot), Ptr))
+            }
+            Command::Get(reg, index) => {
+                let ty = match &reg.ty {
+                    MirageTypeEnum::Pointer(p) => &*p.element_ty,
+                    ty => ty,
+                };
+                let MirageTypeEnum::Struct(s) = ty else {
+                    return Err(CodeGenError::Unsupported(cmd.to_string()));
+                };
+                let Some(field) = s.fields.get(*index) else {
+                    return Err(CodeGenError::Unsupported(cmd.to_string()));
+                };
+                let name = self.types.name(ty)?;
+                let address = self.read(reg)?.to(Ptr);
+                let code = format!("(({}){})->f{}", pointer_to(&name), address, index);
+                Ok(self.lvalue(code, field))
+            }
+            Command::Const(obj) => self.object(obj.get_value_ref()),
+            Command::Ref(value) => self.address_of(value),
+            Command::Load(ty, value) => {
+                let address = self.value(value)?.to(Ptr);
+                if is_aggregate(ty) {
+                    // Loading a struct or an array copies it
+                    let slot = self.slot(ty)?;
+                    self.line(format!(
+                        "mirage_copy(&{}, {}, sizeof {});",
+                        slot, address, slot
+                    ));
+                    return Ok(Expr::new(format!("(void *)&{}", slot), Ptr));
+                }
+                let name = self.types.name(ty)?;
+                Ok(Expr::new(
+                    format!("*({}){}", pointer_to(&name), address),
+                    CType::of(ty),
+                ))
+            }
+            Command::GetElementPtr(ty, base, indices) => self.element_ptr(ty, base, indices),
+            Command::IncrInt8(reg) => self.incr(reg, I8, U8),
+            Command::IncrInt16(reg) => self.incr(reg, I16, U16),
+            Command::IncrInt32(reg) => self.incr(reg, I32, U32),
+            Command::IncrInt64(reg) => self.incr(reg, I64, U64),
+            Command::IncrFloat32(reg) => {
+                let value = self.read(reg)?;
+                Ok(Expr::new(format!("({} + 1.0f)", value.to(F32)), F32))
+            }
+            Command::IncrFloat64(reg) => {
+                let value = self.read(reg)?;
+                Ok(Expr::new(format!("({} + 1.0)", value.to(F64)), F64))
+            }
+            Command::AddInt8(lhs, rhs) => self.arith(lhs, rhs, "+", I8, U8),
+            Command::AddInt16(lhs, rhs) => self.arith(lhs, rhs, "+", I16, U16),
+            Command::AddInt32(lhs, rhs) => self.arith(lhs, rhs, "+", I32, U32),
+            Command::AddInt64(lhs, rhs) => self.arith(lhs, rhs, "+", I64, U64),
+            Command::AddFloat32(lhs, rhs) => self.float(lhs, rhs, "+", F32),
+            Command::AddFloat64(lhs, rhs) => self.float(lhs, rhs, "+", F64),
+            Command::SubInt8(lhs, rhs) => self.arith(lhs, rhs, "-", I8, U8),
+            Command::SubInt16(lhs, rhs) => self.arith(lhs, rhs, "-", I16, U16),
+            Command::SubInt32(lhs, rhs) => self.arith(lhs, rhs, "-", I32, U32),
+            Command::SubInt64(lhs, rhs) => self.arith(lhs, rhs, "-", I64, U64),
+            Command::SubFloat32(lhs, rhs) => self.float(lhs, rhs, "-", F32),
+            Command::SubFloat64(lhs, rhs) => self.float(lhs, rhs, "-", F64),
+            Command::Store(..)
+            | Command::Free(_)
+            | Command::Ret(_)
+            | Command::Jump(_)
+            | Command::Jeq(..)
+            | Command::Phi(_) => Err(CodeGenError::Unsupported(cmd.to_string())),
+        }
+    }
+
+    /// `reg + 1`, computed on the unsigned type of its width so that it
+    /// wraps around
+    fn incr(&mut self, reg: &RegisterValue, ty: CType, unsigned: CType) -> CodeGenResult<Expr> {
+        let value = self.read(reg)?;
+        Ok(Expr::new(
+            format!("({})({} + 1)", ty.name(), value.to(unsigned)),
+            ty,
+        ))
+    }
+
+    /// `lhs op rhs`, computed on the unsigned type of its width so that it
+    /// wraps around
+    fn arith(
+        &mut self,
+        lhs: &Value,
+        rhs: &Value,
+        op: &str,
+        ty: CType,
+        unsigned: CType,
+    ) -> CodeGenResult<Expr> {
+        let lhs = self.value(lhs)?;
+        let rhs = self.value(rhs)?;
+        Ok(Expr::new(
+            format!(
+                "({})({} {} {})",
+                ty.name(),
+                lhs.to(unsigned),
+                op,
+                rhs.to(unsigned)
+            ),
+            ty,
+        ))
+    }
+
+    fn float(&mut self, lhs: &Value, rhs: &Value, op: &str, ty: CType) -> CodeGenResult<Expr> {
+        let lhs = self.value(lhs)?;
+        let rhs = self.value(rhs)?;
+        Ok(Expr::new(
+            format!("({} {} {})", lhs.to(ty), op, rhs.to(ty)),
+            ty,
+        ))
+    }
+
+    /// The address `getelementptr` gives: the first index steps over whole
+    /// values of `ty`, the next ones go into it
+    fn element_ptr(
+        &mut self,
+        ty: &MirageTypeEnum,
+        base: &Value,
+        indices: &[Value],
+    ) -> CodeGenResult<Expr> {
+        let base = self.value(base)?;
+        if indices.is_empty() {
+            return Ok(Expr::new(base.to(CType::Ptr), CType::Ptr));
+        }
+        let name = self.types.name(ty)?;
+        let mut code = format!("(({}){})", pointer_to(&name), base.to(CType::Ptr));
+        let mut ty = ty.clone();
+        for (i, index) in indices.iter().enumerate() {
+            if i > 0 {
+                match ty {
+                    MirageTypeEnum::Struct(s) => {
+                        let field = const_index(index)
+                            .and_then(|f| usize::try_from(f).ok())
+                            .filter(|f| *f < s.fields.len())
+                            .ok_or_else(|| CodeGenError::Unsupported(index.to_string()))?;
+                        code = format!("{}.f{}", code, field);
+                        ty = s.fields[field].clone();
+                        continue;
+                    }
+                    MirageTypeEnum::Array(a) => ty = *a.element_ty,
+                    _ => {
+                        return Err(CodeGenError::Unsupported(format!(
+                            "getelementptr into {}",
+                            ty.print_to_string()
+                        )))
+                    }
+                }
+            }
+            let index = self.value(index)?;
+            let index = match index.ty {
+                CType::Ptr => index.to(CType::I64),
+                _ => index.code,
+            };
+            code = format!("{}[{}]", code, index);
+        }
+        Ok(Expr::new(format!("(void *)&{}", code), CType::Ptr))
+    }
+
+    /// Store `value` where `reg` points
+    fn store(&mut self, reg: &RegisterValue, value: &Value) -> CodeGenResult<()> {
+        let ty = match value {
+            Value::List(_) => return Err(CodeGenError::Unsupported(value.to_string())),
+            _ => value.get_type(),
+        };
+        let address = self.read(reg)?.to(CType::Ptr);
+        let value = self.value(value)?;
+        if is_aggregate(&ty) {
+            let name = self.types.name(&ty)?;
+            self.line(format!(
+                "mirage_copy({}, {}, sizeof({}));",
+                address,
+                value.to(CType::Ptr),
+                name
+            ));
+        } else {
+            self.line(format!(
+                "*({}){} = {};",
+                pointer_to(value.ty.name()),
+                address,
+                value.code
+            ));
+        }
+        Ok(())
+    }
+
+    /// Assign `value` to the object `lvalue` of type `ty`. A struct or an
+    /// array is copied from the address `value` holds.
+    fn assign(&mut self, lvalue: &str, ty: &MirageTypeEnum, value: &Expr) -> CodeGenResult<()> {
+        if is_aggregate(ty) {
+            self.line(format!(
+                "mirage_copy(&{}, {}, sizeof {});",
+                lvalue,
+                value.to(CType::Ptr),
+                lvalue
+            ));
+        } else {
+            self.line(format!("{} = {};", lvalue, value.to(CType::of(ty))));
+        }
+        Ok(())
+    }
+
+    /// The value of the object `lvalue` of type `ty`, its address for a
+    /// struct or an array
+    fn lvalue(&self, lvalue: String, ty: &MirageTypeEnum) -> Expr {
+        if is_aggregate(ty) {
+            Expr::new(format!("(void *)&{}", lvalue), CType::Ptr)
+        } else {
+            Expr::new(lvalue, CType::of(ty))
+        }
+    }
+
+    /// Declare a local of `ty` for an instruction and return its name
+    fn slot(&mut self, ty: &MirageTypeEnum) -> CodeGenResult<String> {
+        let name = format!("s{}", self.slots.len());
+        let declaration = self.types.declare(ty, &name)?;
+        self.slots.push(declaration);
+        Ok(name)
+    }
+
+    fn global(&self, index: usize) -> CodeGenResult<&'a (String, MirageTypeEnum)> {
+        let env = self.env;
+        env.globals
+            .get(index)
+            .ok_or(CodeGenError::UnknownGlobal(index))
+    }
+
+    fn reg_type(&self, reg: &RegisterValue) -> CType {
+        self.regs
+            .get(&reg_key(reg))
+            .copied()
+            .unwrap_or_else(|| CType::of(&reg.ty))
+    }
+
+    fn read(&self, reg: &RegisterValue) -> CodeGenResult<Expr> {
+        if reg.register_type == RegisterType::Global {
+            let (name, ty) = self.global(reg.index)?;
+            return Ok(self.lvalue(name.clone(), ty));
+        }
+        Ok(Expr::new(register_name(reg_key(reg)), self.reg_type(reg)))
+    }
+
+    fn write(&mut self, reg: &RegisterValue, value: &Expr) -> CodeGenResult<()> {
+        if reg.register_type == RegisterType::Global {
+            let (name, ty) = self.global(reg.index)?;
+            return self.assign(name, ty, value);
+        }
+        let ty = self.reg_type(reg);
+        self.line(format!(
+            "{} = {};",
+            register_name(reg_key(reg)),
+            value.to(ty)
+        ));
+        Ok(())
+    }
+
+    /// The address `ref value` gives. A constant gets a local of its own.
+    fn address_of(&mut self, value: &Value) -> CodeGenResult<Expr> {
+        if let Some(reg) = value_register(value) {
+            if reg.register_type != RegisterType::Global && is_aggregate(&reg.ty) {
+                return self.read(reg);
+            }
+            let name = match reg.register_type {
+                RegisterType::Global => self.global(reg.index)?.0.clone(),
+                _ => register_name(reg_key(reg)),
+            };
+            return Ok(Expr::new(format!("(void *)&{}", name), CType::Ptr));
+        }
+        let ty = match value {
+            Value::List(_) => return Err(CodeGenError::Unsupported(value.to_string())),
+            _ => value.get_type(),
+        };
+        let value = self.value(value)?;
+        if is_aggregate(&ty) {
+            return Ok(value);
+        }
+        let slot = self.slot(&ty)?;
+        self.assign(&slot, &ty, &value)?;
+        Ok(Expr::new(format!("(void *)&{}", slot), CType::Ptr))
+    }
+
+    fn value(&mut self, value: &Value) -> CodeGenResult<Expr> {
+        match value {
+            Value::ConstValue(obj) => self.object(obj.get_value_ref()),
+            Value::Register(reg) => self.read(reg),
+            Value::List(_) => Err(CodeGenError::Unsupported(value.to_string())),
+        }
+    }
+
+    /// The value of a constant. A string is a string literal, and another
+    /// struct or array is copied to a local from a static object.
+    fn object(&mut self, value: &MirageValueEnum) -> CodeGenResult<Expr> {
+        if let MirageValueEnum::Register(reg) = value {
+            return self.read(reg);
+        }
+        if let Some(literal) = literal(value) {
+            return Ok(literal);
+        }
+        if let Some(s) = value.try_to_rust_string() {
+            return Ok(Expr::new(format!("(void *){}", c_string(&s)), CType::Ptr));
+        }
+        let ty = value.get_type();
+        if !is_aggregate(&ty) {
+            return Err(CodeGenError::Unsupported(value.print_to_string()));
+        }
+        let constant = format!("mirage_c{}", self.constants.len());
+        let definition = format!(
+            "static const {} = {};",
+            self.types.declare(&ty, &constant)?,
+            initializer(value)?
+        );
+        self.constants.push(definition);
+        let slot = self.slot(&ty)?;
+        self.line(format!(
+            "mirage_copy(&{}, &{}, sizeof {});",
+            slot, constant, slot
+        ));
+        Ok(Expr::new(format!("(void *)&{}", slot), CType::Ptr))
+    }
+}
+
+fn register_prefix(ty: RegisterType) -> char {
+    match ty {
+        RegisterType::Register => 'r',
+        RegisterType::Variable => 'v',
+        RegisterType::Argument => 'a',
+        RegisterType::Global => 'g',
+    }
+}
+
+fn register_name(key: RegKey) -> String {
+    format!("{}{}", register_prefix(key.0), key.1)
+}
@@ -0,0 +1,240 @@
+#[cfg(test)]
+mod test;
+
+mod function;
+mod literal;
+mod string;
+mod types;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use function::FunctionCompiler;
+use literal::initializer;
+use mirage_backend_opti::ir::has_attribute;
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::meta::Flag;
+use mirage_frontend::object::statements::{Global, Statement};
+use mirage_frontend::object::MirageTypeEnum;
+use string::c_string;
+use types::{declare, CType, Types};
+
+/// A code generation error
+/// # Variants
+/// * `UnknownFunction` - A call to a function which isn't declared
+/// * `UnknownType` - A `new` of a type which isn't defined
+/// * `UnknownGlobal` - A global register without its global
+/// * `UnknownLabel` - A jump to a label which isn't in the function
+/// * `ArgumentCount` - A call with too few arguments
+/// * `Unsupported` - Something C can't express, such as an empty struct
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodeGenError {
+    UnknownFunction(String),
+    UnknownType(String),
+    UnknownGlobal(usize),
+    UnknownLabel(String),
+    ArgumentCount(String),
+    Unsupported(String),
+}
+
+pub type CodeGenResult<T> = Result<T, CodeGenError>;
+
+/// What the functions of a program are compiled against
+#[derive(Debug, Clone, Default)]
+struct Env {
+    functions: HashMap<String, FunctionType>,
+    /// The name and the type of every global, by index
+    globals: Vec<(String, MirageTypeEnum)>,
+    structs: HashMap<String, Vec<MirageTypeEnum>>,
+}
+
+/// What every translation unit starts with: the fixed-width integer types,
+/// a trap for the paths which don't return, and a copy which doesn't need
+/// `<string.h>`, whose declarations could clash with the externs
+const PRELUDE: &str = "#include <stddef.h>
+#include <stdint.h>
+
+#if defined(__GNUC__)
+#define MIRAGE_TRAP() __builtin_trap()
+#else
+#define MIRAGE_TRAP() for (;;)
+#endif
+
+static inline void mirage_copy(void *to, const void *from, size_t size) {
+    unsigned char *d = to;
+    const unsigned char *s = from;
+    while (size--) {
+        *d++ = *s++;
+    }
+}
+";
+
+/// The C code generator, which translates a program to a C11 translation
+/// unit.
+///
+/// The typedefs become structs and the globals static objects, `const`
+/// when they are `#const`. The externs become prototypes, and the
+/// functions C functions, `static` when they are `#internal`, whose
+/// registers are locals and whose labels are the targets of `goto`s.
+/// Pointers are `void *`, and so are structs and arrays, which are handled
+/// through their address. The ones a function creates are its locals.
+///
+/// The integer arithmetic is done on the unsigned type of its width, so
+/// that it wraps around as in the other backends.
+#[derive(Debug, Clone)]
+pub struct CodeGen {
+    stmts: Vec<Statement>,
+    code: String,
+}
+
+impl CodeGen {
+    pub fn new(stmts: Vec<Statement>) -> Self {
+        Self {
+            stmts,
+            code: String::new(),
+        }
+    }
+
+    pub fn compile(&mut self) -> CodeGenResult<()> {
+        let mut env = Env::default();
+        let mut types = Types::new();
+        let mut prototypes = Vec::new();
+        let mut globals = Vec::new();
+        for stmt in &self.stmts {
+            match stmt {
+                Statement::Typedef(t) => {
+                    let fields = t.ty.clone().into_vec();
+                    types.typedef(&t.name, fields.clone())?;
+                    env.structs.insert(t.name.clone(), fields);
+                }
+                Statement::Global(global) => {
+                    globals.push(define_global(&mut types, global)?);
+                    env.globals
+                        .push((global.name.clone(), global.value.get_type()));
+                }
+                Statement::External(e) => {
+                    prototypes.push(format!("{};", signature(&e.name, &e.ty, true)?));
+                    env.functions.insert(e.name.clone(), e.ty.clone());
+                }
+                Statement::Function(f) => {
+                    let static_ = if has_attribute(f, &Flag::internal()) {
+                        "static "
+                    } else {
+                        ""
+                    };
+                    let signature = signature(f.get_name(), f.get_type(), false)?;
+                    prototypes.push(format!("{}{};", static_, signature));
+                    env.functions
+                        .insert(f.get_name().clone(), f.get_type().clone());
+                }
+                _ => {}
+            }
+        }
+
+        let mut constants = Vec::new();
+        let mut functions = Vec::new();
+        for stmt in &self.stmts {
+            if let Statement::Function(f) = stmt {
+                functions
+                    .push(FunctionCompiler::new(&env, &mut types, &mut constants, f).compile()?);
+            }
+        }
+
+        let mut code = String::from(PRELUDE);
+        for section in [types.definitions(), &prototypes, &globals, &constants] {
+            if !section.is_empty() {
+                code.push('\n');
+            }
+            for item in section {
+                code.push_str(item);
+                code.push('\n');
+            }
+        }
+        for function in functions {
+            code.push('\n');
+            code.push_str(&function);
+        }
+        self.code = code;
+        Ok(())
+    }
+
+    /// The compiled program, as C source
+    pub fn emit_c(&self) -> String {
+        self.code.clone()
+    }
+
+    /// Write the compiled program to a `.c` file
+    pub fn write_to(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, &self.code)
+    }
+}
+
+/// The declarator of a function `name` of type `ty`, with its parameters
+/// named `a0`, `a1` and so on. The parameters of an extern aren't named,
+/// and its pointers to bytes are `char *`, as the C library declares its
+/// strings.
+fn signature(name: &str, ty: &FunctionType, external: bool) -> CodeGenResult<String> {
+    let c_type = |ty: &MirageTypeEnum| match ty {
+        MirageTypeEnum::Pointer(p)
+            if external
+                && matches!(
+                    *p.element_ty,
+                    MirageTypeEnum::Int8(_) | MirageTypeEnum::UInt8(_)
+                ) =>
+        {
+            "char *"
+        }
+        _ => CType::of(ty).name(),
+    };
+    let mut params: Vec<String> = ty
+        .get_args()
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| match external {
+            true => c_type(arg).to_string(),
+            false => declare(c_type(arg), &format!("a{}", i)),
+        })
+        .collect();
+    if ty.is_var_arg() {
+        // C wants a named parameter before the variadic ones
+        if params.is_empty() {
+            return Err(CodeGenError::Unsupported(format!(
+                "{} without a fixed argument",
+                name
+            )));
+        }
+        params.push("...".to_string());
+    }
+    if params.is_empty() {
+        params.push("void".to_string());
+    }
+    Ok(declare(
+        c_type(ty.get_ret()),
+        &format!("{}({})", name, params.join(", ")),
+    ))
+}
+
+/// The definition of a global. A string global is NUL terminated, like a
+/// string constant.
+fn define_global(types: &mut Types, global: &Global) -> CodeGenResult<String> {
+    let qualifier = if global.is_const() {
+        "static const"
+    } else {
+        "static"
+    };
+    let value = global.value.get_value_ref();
+    if let Some(s) = value.try_to_rust_string() {
+        return Ok(format!(
+            "{} int8_t {}[] = {};",
+            qualifier,
+            global.name,
+            c_string(&s)
+        ));
+    }
+    Ok(format!(
+        "{} {} = {};",
+        qualifier,
+        types.declare(&value.get_type(), &global.name)?,
+        initializer(value)?
+    ))
+}
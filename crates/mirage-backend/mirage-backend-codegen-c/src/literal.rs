@@ -0,0 +1,63 @@
+use mirage_frontend::object::MirageValueEnum;
+
+use crate::types::{CType, Expr};
+use crate::{CodeGenError, CodeGenResult};
+
+/// A float constant. The values C has no literal for are divisions, which
+/// are constant expressions.
+fn float(v: f64) -> String {
+    if v.is_nan() {
+        "(0.0 / 0.0)".to_string()
+    } else if v.is_infinite() {
+        let sign = if v < 0.0 { "-" } else { "" };
+        format!("({}1.0 / 0.0)", sign)
+    } else {
+        format!("{:?}", v)
+    }
+}
+
+/// The C constant of an integer or a float value
+pub fn literal(value: &MirageValueEnum) -> Option<Expr> {
+    let (code, ty) = match value {
+        MirageValueEnum::Int8(v) => (v.value.to_string(), CType::I8),
+        MirageValueEnum::Int16(v) => (v.value.to_string(), CType::I16),
+        MirageValueEnum::Int32(v) if v.value == i32::MIN => {
+            ("(-2147483647 - 1)".to_string(), CType::I32)
+        }
+        MirageValueEnum::Int32(v) => (v.value.to_string(), CType::I32),
+        MirageValueEnum::Int64(v) if v.value == i64::MIN => (
+            "(-INT64_C(9223372036854775807) - 1)".to_string(),
+            CType::I64,
+        ),
+        MirageValueEnum::Int64(v) => (format!("INT64_C({})", v.value), CType::I64),
+        MirageValueEnum::UInt8(v) => (v.value.to_string(), CType::U8),
+        MirageValueEnum::UInt16(v) => (v.value.to_string(), CType::U16),
+        MirageValueEnum::UInt32(v) => (format!("{}u", v.value), CType::U32),
+        MirageValueEnum::UInt64(v) => (format!("UINT64_C({})", v.value), CType::U64),
+        MirageValueEnum::Float32(v) if !v.value.is_finite() => {
+            (format!("(float){}", float(v.value as f64)), CType::F32)
+        }
+        MirageValueEnum::Float32(v) => (format!("{:?}f", v.value), CType::F32),
+        MirageValueEnum::Float64(v) => (float(v.value), CType::F64),
+        _ => return None,
+    };
+    Some(Expr::new(code, ty))
+}
+
+/// The initializer of a static object holding `value`
+pub fn initializer(value: &MirageValueEnum) -> CodeGenResult<String> {
+    let fields = match value {
+        MirageValueEnum::Array(a) => &a.values,
+        MirageValueEnum::Struct(s) => &s.values,
+        _ => {
+            return literal(value)
+                .map(|e| e.code)
+                .ok_or_else(|| CodeGenError::Unsupported(value.print_to_string()))
+        }
+    };
+    let fields: Vec<String> = fields
+        .iter()
+        .map(initializer)
+        .collect::<CodeGenResult<_>>()?;
+    Ok(format!("{{{}}}", fields.join(", ")))
+}
@@ -0,0 +1,20 @@
+use mirage_frontend::object::util::to_string_with_special_char;
+
+/// A C string literal of `s`, whose escapes are replaced first. The bytes
+/// which aren't printable are written as octal escapes, and so is `?`,
+/// which could start a trigraph.
+pub fn c_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for b in to_string_with_special_char(s).bytes() {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            b' '..=b'~' if b != b'?' => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,485 @@
+use std::path::PathBuf;
+use std::process::Command as Process;
+
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::{Flag, Flags};
+use mirage_frontend::object::statements::{External, Global, Statement, TypeDef};
+use mirage_frontend::object::util::List;
+use mirage_frontend::object::{
+    MirageObject, MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue,
+};
+
+use crate::string::c_string;
+use crate::{CodeGen, CodeGenError};
+
+fn i8_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int8().into()
+}
+
+fn i32_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int32().into()
+}
+
+fn i64_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int64().into()
+}
+
+fn reg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Register, ty)
+}
+
+fn arg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Argument, ty)
+}
+
+fn val(reg: &RegisterValue) -> Value {
+    Value::Register(reg.clone())
+}
+
+fn obj(value: MirageValueEnum) -> MirageObject {
+    MirageObject::from(value)
+}
+
+fn i32_obj(v: i32) -> MirageObject {
+    obj(MirageTypeEnum::type_int32().const_value(v).to_value_enum())
+}
+
+fn i32_val(v: i32) -> Value {
+    Value::ConstValue(i32_obj(v))
+}
+
+fn i8_val(v: i8) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_int8()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn i64_val(v: i64) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_int64()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn f64_val(v: f64) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_float64()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn assign(reg: &RegisterValue, cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(reg.clone(), Box::new(LabelBodyInstr::Command(cmd)))
+}
+
+fn assign_call(reg: &RegisterValue, name: &str, args: Vec<Value>) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(
+        reg.clone(),
+        Box::new(LabelBodyInstr::Call(name.to_string(), args)),
+    )
+}
+
+fn cmd(cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Command(cmd)
+}
+
+fn label(name: &str, body: Vec<LabelBodyInstr>) -> Label {
+    Label::new(name.to_string(), Flags::new(vec![]), body)
+}
+
+fn function(name: &str, args: Vec<MirageTypeEnum>, labels: Vec<Label>) -> Statement {
+    let mut f = FunctionType::new(args, i32_ty(), false).fn_value(name.to_string());
+    for l in labels {
+        f.add_label(l);
+    }
+    Statement::Function(f)
+}
+
+fn string(s: &str) -> MirageValueEnum {
+    let ty = MirageTypeEnum::type_array(i8_ty(), s.len());
+    MirageValueEnum::Array(
+        ty.const_value(
+            s.bytes()
+                .map(|c| {
+                    MirageTypeEnum::type_int8()
+                        .const_value(c as i8)
+                        .to_value_enum()
+                })
+                .collect(),
+        ),
+    )
+}
+
+fn printf() -> Statement {
+    let ty = FunctionType::new(
+        vec![MirageTypeEnum::type_ptr(i8_ty()).into()],
+        i32_ty(),
+        true,
+    );
+    Statement::External(External::new("printf".to_string(), ty))
+}
+
+/// `main`, returning the result of `name` called with `args`
+fn main_calling(name: &str, args: Vec<Value>) -> Statement {
+    function(
+        "main",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign_call(&reg(0, i32_ty()), name, args),
+                cmd(Command::Ret(val(&reg(0, i32_ty())))),
+            ],
+        )],
+    )
+}
+
+fn compile(stmts: Vec<Statement>) -> String {
+    let mut codegen = CodeGen::new(stmts);
+    codegen.compile().unwrap();
+    codegen.emit_c()
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mirage-c-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Compile `stmts` to C, build it with the system `cc` and run it. Gives
+/// the exit status and the output.
+fn run(name: &str, stmts: Vec<Statement>) -> (i32, String) {
+    let source = compile(stmts);
+    let dir = scratch_dir(name);
+    let (c, exe) = (dir.join(format!("{}.c", name)), dir.join(name));
+    std::fs::write(&c, &source).unwrap();
+    let out = Process::new("cc")
+        .args(["-std=c11", "-O2", "-Wall"])
+        .arg(&c)
+        .arg("-o")
+        .arg(&exe)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}\n{}",
+        String::from_utf8_lossy(&out.stderr),
+        source
+    );
+    let out = Process::new(&exe).output().unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+    (
+        out.status.code().unwrap(),
+        String::from_utf8(out.stdout).unwrap(),
+    )
+}
+
+fn counter_loop() -> Statement {
+    let a = arg(0, i32_ty());
+    let r = |i| reg(i, i32_ty());
+    function(
+        "count",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    assign(&r(0), Command::Const(i32_obj(0))),
+                    assign(&r(1), Command::Ref(val(&r(0)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(&r(2), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Jeq("end".to_string(), val(&r(2)), val(&a))),
+                    assign(&r(3), Command::AddInt32(val(&r(2)), i32_val(1))),
+                    cmd(Command::Store(r(1), val(&r(3)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "end",
+                vec![
+                    assign(&r(4), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    )
+}
+
+#[test]
+fn test_c_string() {
+    assert_eq!(c_string("a\\n\"b\"?"), "\"a\\012\\\"b\\\"\\077\"");
+}
+
+#[test]
+fn test_printf_and_globals() {
+    let mut helper = label("entry", vec![cmd(Command::Ret(val(&arg(0, i32_ty()))))]);
+    helper.flags.push(Flag::internal());
+    let g = RegisterValue::new(0, RegisterType::Global, string("%d %s %.1f\\n").get_type());
+    let stmts = vec![
+        printf(),
+        Statement::Global(Global::with_flags(
+            "fmt".to_string(),
+            Flags::new(vec![Flag::constant()]),
+            obj(string("%d %s %.1f\\n")),
+        )),
+        function("helper", vec![i32_ty()], vec![helper]),
+        function(
+            "main",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign_call(&reg(0, i32_ty()), "helper", vec![i32_val(42)]),
+                    LabelBodyInstr::Call(
+                        "printf".to_string(),
+                        vec![
+                            val(&g),
+                            val(&reg(0, i32_ty())),
+                            Value::ConstValue(obj(string("ok"))),
+                            f64_val(2.5),
+                        ],
+                    ),
+                    cmd(Command::Ret(i32_val(3))),
+                ],
+            )],
+        ),
+    ];
+    let c = compile(stmts.clone());
+    assert!(c.contains("\nint32_t printf(char *, ...);\n"));
+    assert!(c.contains("\nstatic int32_t helper(int32_t a0);\n"));
+    assert!(c.contains("\nstatic const int8_t fmt[] = \"%d %s %.1f\\012\";\n"));
+    assert_eq!(run("printf", stmts), (3, "42 ok 2.5\n".to_string()));
+}
+
+#[test]
+fn test_loop() {
+    let stmts = vec![counter_loop(), main_calling("count", vec![i32_val(5)])];
+    let c = compile(stmts.clone());
+    assert!(c.contains("\nL_loop:;\n"));
+    assert!(!c.contains("L_entry"));
+    assert_eq!(run("loop", stmts).0, 5);
+}
+
+/// Swaps two phis around a loop, as many times as its argument says, and
+/// returns 10 if the first one ends up 1, 20 otherwise
+fn swap() -> Statement {
+    let a = arg(0, i64_ty());
+    let r = |i| reg(i, i64_ty());
+    function(
+        "swap",
+        vec![i64_ty()],
+        vec![
+            label("entry", vec![cmd(Command::Jump("loop".to_string()))]),
+            label(
+                "loop",
+                vec![
+                    assign(
+                        &r(0),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(1)),
+                            ("loop".to_string(), val(&r(1))),
+                        ]),
+                    ),
+                    assign(
+                        &r(1),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(2)),
+                            ("loop".to_string(), val(&r(0))),
+                        ]),
+                    ),
+                    assign(
+                        &r(2),
+                        Command::Phi(vec![
+                            ("entry".to_string(), val(&a)),
+                            ("loop".to_string(), val(&r(3))),
+                        ]),
+                    ),
+                    assign(&r(3), Command::SubInt64(val(&r(2)), i64_val(1))),
+                    cmd(Command::Jeq("end".to_string(), val(&r(2)), i64_val(0))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "end",
+                vec![
+                    assign(&reg(4, i32_ty()), Command::Const(i32_obj(10))),
+                    cmd(Command::Jeq("one".to_string(), val(&r(0)), i64_val(1))),
+                    assign(&reg(4, i32_ty()), Command::Const(i32_obj(20))),
+                    cmd(Command::Ret(val(&reg(4, i32_ty())))),
+                ],
+            ),
+            label("one", vec![cmd(Command::Ret(val(&reg(4, i32_ty()))))]),
+        ],
+    )
+}
+
+#[test]
+fn test_phis() {
+    let swap = swap();
+    let even = vec![swap.clone(), main_calling("swap", vec![i64_val(4)])];
+    assert_eq!(run("phis-even", even).0, 10);
+    let odd = vec![swap, main_calling("swap", vec![i64_val(3)])];
+    assert_eq!(run("phis-odd", odd).0, 20);
+}
+
+/// `second`, building a struct of its argument and 40 and returning twice
+/// the 40 less the argument, and `main` calling it with 3
+fn structs() -> Vec<Statement> {
+    let fields = vec![i32_ty(), i64_ty()];
+    let point: MirageTypeEnum = MirageTypeEnum::type_struct(fields.clone()).into();
+    let p = reg(0, point.clone());
+    let q = reg(2, MirageTypeEnum::type_ptr(i64_ty()).into());
+    vec![
+        Statement::Typedef(TypeDef::new("point".to_string(), List::from_vec(fields))),
+        function(
+            "second",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &p,
+                        Command::New(
+                            "point".to_string(),
+                            List::from_vec(vec![val(&arg(0, i32_ty())), i64_val(40)]),
+                        ),
+                    ),
+                    assign(&reg(1, i64_ty()), Command::Get(p.clone(), 1)),
+                    assign(
+                        &q,
+                        Command::GetElementPtr(point, val(&p), vec![i32_val(0), i32_val(1)]),
+                    ),
+                    assign(&reg(3, i64_ty()), Command::Load(i64_ty(), val(&q))),
+                    assign(
+                        &reg(4, i64_ty()),
+                        Command::AddInt64(val(&reg(1, i64_ty())), val(&reg(3, i64_ty()))),
+                    ),
+                    assign(&reg(5, i32_ty()), Command::Get(p.clone(), 0)),
+                    assign(
+                        &reg(6, i32_ty()),
+                        Command::SubInt32(val(&reg(4, i64_ty())), val(&reg(5, i32_ty()))),
+                    ),
+                    cmd(Command::Ret(val(&reg(6, i32_ty())))),
+                ],
+            )],
+        ),
+        main_calling("second", vec![i32_val(3)]),
+    ]
+}
+
+#[test]
+fn test_structs() {
+    let c = compile(structs());
+    assert!(c.contains("\nstruct point {\n    int32_t f0;\n    int64_t f1;\n};\n"));
+    assert!(c.contains("(void *)&((struct point *)r0)[0].f1"));
+    assert_eq!(run("structs", structs()).0, 77);
+
+    let p = reg(0, MirageTypeEnum::type_struct(vec![]).into());
+    let mut codegen = CodeGen::new(vec![function(
+        "f",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign(&p, Command::New("line".to_string(), List::from_vec(vec![]))),
+                cmd(Command::Ret(i32_val(0))),
+            ],
+        )],
+    )]);
+    assert_eq!(
+        codegen.compile(),
+        Err(CodeGenError::UnknownType("line".to_string()))
+    );
+}
+
+/// The arithmetic wraps around at the width of its instruction
+#[test]
+fn test_wraparound() {
+    let b = reg(0, i8_ty());
+    let w = reg(1, i32_ty());
+    let l = reg(2, i64_ty());
+    let stmts = vec![
+        printf(),
+        function(
+            "main",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign(&b, Command::AddInt8(i8_val(127), i8_val(1))),
+                    assign(&w, Command::AddInt32(i32_val(i32::MAX), i32_val(1))),
+                    assign(&l, Command::SubInt64(i64_val(i64::MIN), i64_val(1))),
+                    LabelBodyInstr::Call(
+                        "printf".to_string(),
+                        vec![
+                            Value::ConstValue(obj(string("%d %d %lld\\n"))),
+                            val(&b),
+                            val(&w),
+                            val(&l),
+                        ],
+                    ),
+                    assign(&reg(3, i8_ty()), Command::IncrInt8(b.clone())),
+                    cmd(Command::Ret(val(&reg(3, i8_ty())))),
+                ],
+            )],
+        ),
+    ];
+    let c = compile(stmts.clone());
+    assert!(c.contains("r0 = (int8_t)((uint8_t)127 + (uint8_t)1);"));
+    assert!(c.contains("(-INT64_C(9223372036854775807) - 1)"));
+    assert_eq!(
+        run("wraparound", stmts),
+        (0x81, "-128 -2147483648 9223372036854775807\n".to_string())
+    );
+}
+
+/// `main` summing the elements of a constant array through `getelementptr`
+#[test]
+fn test_arrays() {
+    let array: MirageTypeEnum = MirageTypeEnum::type_array(i32_ty(), 3).into();
+    let values = MirageValueEnum::Array(
+        MirageTypeEnum::type_array(i32_ty(), 3).const_value(
+            [5, 7, 9]
+                .iter()
+                .map(|v| MirageTypeEnum::type_int32().const_value(*v).to_value_enum())
+                .collect(),
+        ),
+    );
+    let a = reg(0, array.clone());
+    let ptr: MirageTypeEnum = MirageTypeEnum::type_ptr(i32_ty()).into();
+    let mut body = vec![assign(&a, Command::Const(obj(values)))];
+    let mut sum = i32_val(0);
+    for i in 0..3 {
+        let p = reg(1 + 3 * i, ptr.clone());
+        let v = reg(2 + 3 * i, i32_ty());
+        let s = reg(3 + 3 * i, i32_ty());
+        body.push(assign(
+            &p,
+            Command::GetElementPtr(array.clone(), val(&a), vec![i32_val(0), i32_val(i as i32)]),
+        ));
+        body.push(assign(&v, Command::Load(i32_ty(), val(&p))));
+        body.push(assign(&s, Command::AddInt32(sum, val(&v))));
+        sum = val(&s);
+    }
+    body.push(cmd(Command::Jeq(
+        "done".to_string(),
+        sum.clone(),
+        i32_val(21),
+    )));
+    let stmts = vec![function(
+        "main",
+        vec![],
+        vec![
+            label("entry", body),
+            label("done", vec![cmd(Command::Ret(sum))]),
+        ],
+    )];
+    let c = compile(stmts.clone());
+    assert!(c.contains("\ntypedef int32_t mirage_a0[3];\n"));
+    assert!(c.contains("\nstatic const mirage_a0 mirage_c0 = {5, 7, 9};\n"));
+    assert_eq!(run("arrays", stmts).0, 21);
+}
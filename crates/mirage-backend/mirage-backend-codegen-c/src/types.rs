@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use mirage_frontend::object::MirageTypeEnum;
+
+use crate::{CodeGenError, CodeGenResult};
+
+/// The C type a value is held in. Pointers are `void *`, and so are structs
+/// and arrays, which are handled through their address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    Ptr,
+}
+
+impl CType {
+    pub fn of(ty: &MirageTypeEnum) -> Self {
+        match ty {
+            MirageTypeEnum::Int8(_) => CType::I8,
+            MirageTypeEnum::Int16(_) => CType::I16,
+            MirageTypeEnum::Int32(_) => CType::I32,
+            MirageTypeEnum::Int64(_) => CType::I64,
+            MirageTypeEnum::UInt8(_) => CType::U8,
+            MirageTypeEnum::UInt16(_) => CType::U16,
+            MirageTypeEnum::UInt32(_) => CType::U32,
+            MirageTypeEnum::UInt64(_) => CType::U64,
+            MirageTypeEnum::Float32(_) => CType::F32,
+            MirageTypeEnum::Float64(_) => CType::F64,
+            _ => CType::Ptr,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CType::I8 => "int8_t",
+            CType::I16 => "int16_t",
+            CType::I32 => "int32_t",
+            CType::I64 => "int64_t",
+            CType::U8 => "uint8_t",
+            CType::U16 => "uint16_t",
+            CType::U32 => "uint32_t",
+            CType::U64 => "uint64_t",
+            CType::F32 => "float",
+            CType::F64 => "double",
+            CType::Ptr => "void *",
+        }
+    }
+
+    /// `name` declared as a value of this type
+    pub fn declare(&self, name: &str) -> String {
+        declare(self.name(), name)
+    }
+}
+
+/// A C expression and the type of its value. The code is always a cast
+/// expression, so that it can be cast or be an operand without more
+/// parentheses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub code: String,
+    pub ty: CType,
+}
+
+impl Expr {
+    pub fn new(code: impl Into<String>, ty: CType) -> Self {
+        Self {
+            code: code.into(),
+            ty,
+        }
+    }
+
+    /// The expression converted to `ty`. An address and an integer are
+    /// converted through `uintptr_t`.
+    pub fn to(&self, ty: CType) -> String {
+        if self.ty == ty {
+            self.code.clone()
+        } else if self.ty == CType::Ptr || ty == CType::Ptr {
+            format!("({})(uintptr_t){}", ty.name(), self.code)
+        } else {
+            format!("({}){}", ty.name(), self.code)
+        }
+    }
+}
+
+/// The names of the structs and arrays of a translation unit, and their
+/// definitions in an order C accepts.
+///
+/// A struct declared by a typedef is named after it, and its fields are
+/// `f0`, `f1` and so on. The other structs are named `mirage_s0`,
+/// `mirage_s1` and so on, and every array type is given a typedef,
+/// `mirage_a0` and so on, so that any type is a single name in a
+/// declaration.
+#[derive(Debug, Clone, Default)]
+pub struct Types {
+    names: HashMap<MirageTypeEnum, String>,
+    definitions: Vec<String>,
+    structs: usize,
+    arrays: usize,
+}
+
+impl Types {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn definitions(&self) -> &[String] {
+        &self.definitions
+    }
+
+    /// Define the struct `name` of a typedef. A struct whose fields were
+    /// named before is given a typedef.
+    pub fn typedef(&mut self, name: &str, fields: Vec<MirageTypeEnum>) -> CodeGenResult<()> {
+        let ty = MirageTypeEnum::type_struct(fields).into();
+        match self.names.get(&ty) {
+            Some(first) => {
+                let first = first.clone();
+                self.definitions
+                    .push(format!("typedef {} {};", first, name));
+            }
+            None => self.define_struct(&ty, format!("struct {}", name))?,
+        }
+        Ok(())
+    }
+
+    /// The C type a value of `ty` is stored in memory as
+    pub fn name(&mut self, ty: &MirageTypeEnum) -> CodeGenResult<String> {
+        if let Some(name) = self.names.get(ty) {
+            return Ok(name.clone());
+        }
+        match ty {
+            MirageTypeEnum::Struct(_) => {
+                let name = format!("struct mirage_s{}", self.structs);
+                self.structs += 1;
+                self.define_struct(ty, name.clone())?;
+                Ok(name)
+            }
+            MirageTypeEnum::Array(a) => {
+                if a.length == 0 {
+                    return Err(CodeGenError::Unsupported(ty.print_to_string()));
+                }
+                let element = self.name(&a.element_ty)?;
+                let name = format!("mirage_a{}", self.arrays);
+                self.arrays += 1;
+                self.definitions.push(format!(
+                    "typedef {}[{}];",
+                    declare(&element, &name),
+                    a.length
+                ));
+                self.names.insert(ty.clone(), name.clone());
+                Ok(name)
+            }
+            _ => Ok(CType::of(ty).name().to_string()),
+        }
+    }
+
+    /// `name` declared as a value of `ty` in memory
+    pub fn declare(&mut self, ty: &MirageTypeEnum, name: &str) -> CodeGenResult<String> {
+        Ok(declare(&self.name(ty)?, name))
+    }
+
+    /// Define a struct of `ty` named `name`, once its fields are defined
+    fn define_struct(&mut self, ty: &MirageTypeEnum, name: String) -> CodeGenResult<()> {
+        let MirageTypeEnum::Struct(s) = ty else {
+            unreachable!("only structs have fields");
+        };
+        if s.fields.is_empty() {
+            return Err(CodeGenError::Unsupported(ty.print_to_string()));
+        }
+        let mut definition = format!("{} {{\n", name);
+        for (i, field) in s.fields.iter().enumerate() {
+            definition.push_str(&format!(
+                "    {};\n",
+                self.declare(field, &format!("f{}", i))?
+            ));
+        }
+        definition.push_str("};");
+        self.definitions.push(definition);
+        self.names.insert(ty.clone(), name);
+        Ok(())
+    }
+}
+
+/// `name` declared with the C type `ty`
+pub fn declare(ty: &str, name: &str) -> String {
+    if ty.ends_with('*') {
+        format!("{}{}", ty, name)
+    } else {
+        format!("{} {}", ty, name)
+    }
+}
@@ -0,0 +1,851 @@
+use std::collections::{HashMap, HashSet};
+
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, FuncRef, Function, GlobalValue, InstBuilder, MemFlags, StackSlot,
+    StackSlotData, StackSlotKind, TrapCode, Value as ClifValue,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_module::{DataDescription, DataId, FuncId, Module};
+use mirage_backend_opti::ir::{for_each_use, is_phi, reg_key, value_register, RegKey};
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::object::{MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue};
+
+use crate::data::string_bytes;
+use crate::layout::{
+    align_of, is_aggregate, size_of, struct_layout, Kind, POINTER_SIZE, POINTER_TYPE,
+};
+use crate::{backend, CompilerError, CompilerResult, Env};
+
+/// The trap of the paths which don't return
+const MISSING_RETURN: TrapCode = TrapCode::unwrap_user(1);
+
+/// The constant integer `value` holds, if it is one
+fn const_index(value: &Value) -> Option<i64> {
+    let Value::ConstValue(obj) = value else {
+        return None;
+    };
+    match obj.get_value_ref() {
+        MirageValueEnum::Int8(v) => Some(v.value as i64),
+        MirageValueEnum::Int16(v) => Some(v.value as i64),
+        MirageValueEnum::Int32(v) => Some(v.value as i64),
+        MirageValueEnum::Int64(v) => Some(v.value),
+        MirageValueEnum::UInt8(v) => Some(v.value as i64),
+        MirageValueEnum::UInt16(v) => Some(v.value as i64),
+        MirageValueEnum::UInt32(v) => Some(v.value as i64),
+        MirageValueEnum::UInt64(v) => Some(v.value as i64),
+        _ => None,
+    }
+}
+
+/// A Cranelift value and what it holds
+#[derive(Debug, Clone, Copy)]
+struct Operand {
+    value: ClifValue,
+    kind: Kind,
+}
+
+impl Operand {
+    fn new(value: ClifValue, kind: Kind) -> Self {
+        Self { value, kind }
+    }
+}
+
+/// Compiles a function to Cranelift IR.
+///
+/// Registers are Cranelift variables, of the kind of their first
+/// assignment, except the ones whose address is taken, which live in a
+/// stack slot. The structs and arrays the function creates get a stack
+/// slot for every instruction creating one. A label is a block, and the
+/// phis are assigned by the branches to it.
+pub struct FunctionCompiler<'a, M: Module> {
+    env: &'a Env,
+    module: &'a mut M,
+    /// The data objects holding the string constants of the module
+    strings: &'a mut HashMap<String, DataId>,
+    func: &'a FunctionValue,
+    b: FunctionBuilder<'a>,
+    kinds: HashMap<RegKey, Kind>,
+    vars: HashMap<RegKey, Variable>,
+    slots: HashMap<RegKey, StackSlot>,
+    blocks: HashMap<String, Block>,
+    /// The label being compiled
+    current: String,
+    /// Whether the block being emitted to has ended with a branch
+    terminated: bool,
+    func_refs: HashMap<FuncId, FuncRef>,
+    data_refs: HashMap<DataId, GlobalValue>,
+}
+
+impl<'a, M: Module> FunctionCompiler<'a, M> {
+    pub fn new(
+        env: &'a Env,
+        module: &'a mut M,
+        strings: &'a mut HashMap<String, DataId>,
+        function: &'a mut Function,
+        builder_ctx: &'a mut FunctionBuilderContext,
+        func: &'a FunctionValue,
+    ) -> Self {
+        let mut kinds = HashMap::new();
+        for (i, ty) in func.get_type().get_args().iter().enumerate() {
+            kinds.insert((RegisterType::Argument, i), Kind::of(ty));
+        }
+        for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+            if let LabelBodyInstr::Assign(reg, value) = instr {
+                // A `getelementptr`, a `ref` or a `new` gives an address,
+                // whatever the type of the register
+                let kind = match **value {
+                    LabelBodyInstr::Command(
+                        Command::GetElementPtr(..) | Command::Ref(_) | Command::New(..),
+                    ) => Kind::Ptr,
+                    _ => Kind::of(&reg.ty),
+                };
+                kinds.entry(reg_key(reg)).or_insert(kind);
+            }
+            for_each_use(instr, &mut |reg, _| {
+                kinds.entry(reg_key(reg)).or_insert(Kind::of(&reg.ty));
+            });
+        }
+        Self {
+            env,
+            module,
+            strings,
+            func,
+            b: FunctionBuilder::new(function, builder_ctx),
+            kinds,
+            vars: HashMap::new(),
+            slots: HashMap::new(),
+            blocks: HashMap::new(),
+            current: String::new(),
+            terminated: false,
+            func_refs: HashMap::new(),
+            data_refs: HashMap::new(),
+        }
+    }
+
+    pub fn compile(mut self) -> CompilerResult<()> {
+        let func = self.func;
+        let entry = self.b.create_block();
+        self.b.append_block_params_for_function_params(entry);
+        self.b.switch_to_block(entry);
+
+        // The registers whose address is taken live in a stack slot
+        let mut referenced = HashSet::new();
+        for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+            let value = match instr {
+                LabelBodyInstr::Assign(_, value) => value,
+                _ => instr,
+            };
+            if let LabelBodyInstr::Command(Command::Ref(value)) = value {
+                if let Some(reg) = value_register(value) {
+                    if reg.register_type != RegisterType::Global && !is_aggregate(&reg.ty) {
+                        referenced.insert(reg_key(reg));
+                    }
+                }
+            }
+        }
+        for key in referenced {
+            let size = self.kinds[&key].clif().bytes();
+            let slot = self.stack_slot(size, size);
+            self.slots.insert(key, slot);
+        }
+
+        let params = self.b.block_params(entry).to_vec();
+        for (i, (param, ty)) in params.iter().zip(func.get_type().get_args()).enumerate() {
+            let arg = RegisterValue::new(i, RegisterType::Argument, ty.clone());
+            self.write(&arg, Operand::new(*param, Kind::of(ty)))?;
+        }
+
+        let labels = func.get_labels();
+        for label in labels {
+            let block = self.b.create_block();
+            self.blocks.insert(label.name.clone(), block);
+        }
+        match labels.first() {
+            Some(first) => {
+                let block = self.blocks[&first.name];
+                self.b.ins().jump(block, &[]);
+            }
+            None => {
+                self.b.ins().trap(MISSING_RETURN);
+            }
+        }
+
+        for (i, label) in labels.iter().enumerate() {
+            self.current = label.name.clone();
+            self.b.switch_to_block(self.blocks[&label.name]);
+            self.terminated = false;
+            for instr in &label.body {
+                if is_phi(instr) {
+                    continue;
+                }
+                if self.terminated {
+                    // What follows a branch is unreachable, but still
+                    // compiled, in a block of its own
+                    let block = self.b.create_block();
+                    self.b.switch_to_block(block);
+                    self.terminated = false;
+                }
+                self.statement(instr)?;
+            }
+            if !self.terminated {
+                match labels.get(i + 1) {
+                    Some(next) => self.jump(&next.name)?,
+                    None => {
+                        self.b.ins().trap(MISSING_RETURN);
+                    }
+                }
+            }
+        }
+
+        self.b.seal_all_blocks();
+        self.b.finalize();
+        Ok(())
+    }
+
+    fn statement(&mut self, instr: &LabelBodyInstr) -> CompilerResult<()> {
+        match instr {
+            LabelBodyInstr::Assign(reg, value) => {
+                let value = match &**value {
+                    LabelBodyInstr::Call(name, args) => self.call(name, args)?,
+                    LabelBodyInstr::Command(cmd) => self.command(cmd)?,
+                    LabelBodyInstr::Assign(..) => {
+                        return Err(CompilerError::Unsupported(instr.to_string()))
+                    }
+                };
+                self.write(reg, value)
+            }
+            LabelBodyInstr::Call(name, args) => self.call(name, args).map(|_| ()),
+            LabelBodyInstr::Command(cmd) => match cmd {
+                Command::Store(reg, value) => {
+                    let ty = match value {
+                        Value::List(_) => {
+                            return Err(CompilerError::Unsupported(value.to_string()))
+                        }
+                        _ => value.get_type(),
+                    };
+                    let address = self.read(reg)?;
+                    let address = self.convert(address, Kind::Ptr);
+                    let value = self.value(value)?;
+                    self.store(address, 0, &ty, value);
+                    Ok(())
+                }
+                Command::Ret(value) => {
+                    let value = self.value(value)?;
+                    let value = self.convert(value, Kind::of(self.func.get_type().get_ret()));
+                    self.b.ins().return_(&[value]);
+                    self.terminated = true;
+                    Ok(())
+                }
+                Command::Jump(label) => self.jump(label),
+                Command::Jeq(label, lhs, rhs) => self.jeq(label, lhs, rhs),
+                // The frame is given back on return, and there is no heap
+                Command::Free(_) | Command::Phi(_) => Ok(()),
+                // The value isn't used, but making it may have effects
+                _ => self.command(cmd).map(|_| ()),
+            },
+        }
+    }
+
+    fn block(&self, label: &str) -> CompilerResult<Block> {
+        self.blocks
+            .get(label)
+            .copied()
+            .ok_or_else(|| CompilerError::UnknownLabel(label.to_string()))
+    }
+
+    /// Assign the phis of `label` and branch to it
+    fn jump(&mut self, label: &str) -> CompilerResult<()> {
+        let block = self.block(label)?;
+        self.phis(label)?;
+        self.b.ins().jump(block, &[]);
+        self.terminated = true;
+        Ok(())
+    }
+
+    /// Branch to `label` when `lhs == rhs`, through a block assigning its
+    /// phis if it has some, and go on in a new block otherwise
+    fn jeq(&mut self, label: &str, lhs: &Value, rhs: &Value) -> CompilerResult<()> {
+        let target = self.block(label)?;
+        let lhs = self.value(lhs)?;
+        let rhs = self.value(rhs)?;
+        let rhs = self.convert(rhs, lhs.kind);
+        let cond = if lhs.kind.is_float() {
+            self.b.ins().fcmp(FloatCC::Equal, lhs.value, rhs)
+        } else {
+            self.b.ins().icmp(IntCC::Equal, lhs.value, rhs)
+        };
+        let next = self.b.create_block();
+        if self.incoming(label)?.is_empty() {
+            self.b.ins().brif(cond, target, &[], next, &[]);
+        } else {
+            let edge = self.b.create_block();
+            self.b.ins().brif(cond, edge, &[], next, &[]);
+            self.b.switch_to_block(edge);
+            self.jump(label)?;
+        }
+        self.b.switch_to_block(next);
+        self.terminated = false;
+        Ok(())
+    }
+
+    /// The phis of `label` and their value coming from the label being
+    /// compiled
+    fn incoming(&self, label: &str) -> CompilerResult<Vec<(&'a RegisterValue, &'a Value)>> {
+        let func = self.func;
+        let target = func
+            .get_labels()
+            .iter()
+            .find(|l| l.name == label)
+            .ok_or_else(|| CompilerError::UnknownLabel(label.to_string()))?;
+        Ok(target
+            .body
+            .iter()
+            .map_while(|instr| match instr {
+                LabelBodyInstr::Assign(reg, value) => match &**value {
+                    LabelBodyInstr::Command(Command::Phi(incoming)) => Some((reg, incoming)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .filter_map(|(reg, incoming)| {
+                incoming
+                    .iter()
+                    .find(|(label, _)| *label == self.current)
+                    .map(|(_, value)| (reg, value))
+            })
+            .collect())
+    }
+
+    /// Assign the phis of `label` the values coming from the label being
+    /// compiled. They read their operands before any of them is assigned.
+    fn phis(&mut self, label: &str) -> CompilerResult<()> {
+        let copies = self.incoming(label)?;
+        let mut values = Vec::with_capacity(copies.len());
+        for (_, value) in &copies {
+            values.push(self.value(value)?);
+        }
+        for ((reg, _), value) in copies.into_iter().zip(values) {
+            self.write(reg, value)?;
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, args: &[Value]) -> CompilerResult<Operand> {
+        let env = self.env;
+        let callee = env
+            .functions
+            .get(name)
+            .ok_or_else(|| CompilerError::UnknownFunction(name.to_string()))?;
+        let fixed = callee.ty.get_args();
+        if args.len() < fixed.len() {
+            return Err(CompilerError::ArgumentCount(name.to_string()));
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for (arg, ty) in args.iter().zip(fixed) {
+            let value = self.value(arg)?;
+            values.push(self.convert(value, Kind::of(ty)));
+        }
+        let func_ref = self.func_ref(callee.id);
+        let ret = Kind::of(callee.ty.get_ret());
+        if !callee.ty.is_var_arg() {
+            let call = self.b.ins().call(func_ref, &values);
+            return Ok(Operand::new(self.b.inst_results(call)[0], ret));
+        }
+
+        // The variadic arguments are promoted as in C, and passed with a
+        // signature made for the call
+        let mut sig = crate::signature(env.call_conv, &callee.ty);
+        for arg in &args[fixed.len()..] {
+            let value = self.value(arg)?;
+            let kind = match value.kind {
+                Kind::F32 | Kind::F64 if !env.float_var_args => {
+                    return Err(CompilerError::Unsupported(format!(
+                        "float variadic argument to {}",
+                        name
+                    )))
+                }
+                Kind::F32 => Kind::F64,
+                Kind::I8 | Kind::I16 => Kind::I32,
+                Kind::U8 | Kind::U16 => Kind::U32,
+                kind => kind,
+            };
+            sig.params.push(AbiParam::new(kind.clif()));
+            values.push(self.convert(value, kind));
+        }
+        let sig = self.b.import_signature(sig);
+        let address = self.b.ins().func_addr(POINTER_TYPE, func_ref);
+        let call = self.b.ins().call_indirect(sig, address, &values);
+        Ok(Operand::new(self.b.inst_results(call)[0], ret))
+    }
+
+    /// The value of a command which makes one
+    fn command(&mut self, cmd: &Command) -> CompilerResult<Operand> {
+        match cmd {
+            Command::New(name, args) => {
+                let env = self.env;
+                let fields = env
+                    .structs
+                    .get(name)
+                    .ok_or_else(|| CompilerError::UnknownType(name.clone()))?;
+                let (size, offsets) = struct_layout(fields);
+                let align = fields.iter().map(align_of).max().unwrap_or(1);
+                let slot = self.stack_slot(size, align);
+                let address = self.b.ins().stack_addr(POINTER_TYPE, slot, 0);
+                for ((arg, field), offset) in args.iter().zip(fields).zip(offsets) {
+                    let value = self.value(arg)?;
+                    self.store(address, offset, field, value);
+                }
+                Ok(Operand::new(address, Kind::Ptr))
+            }
+            Command::Get(reg, index) => {
+                let fields = match &reg.ty {
+                    MirageTypeEnum::Struct(s) => &s.fields,
+                    MirageTypeEnum::Pointer(p) => match &*p.element_ty {
+                        MirageTypeEnum::Struct(s) => &s.fields,
+                        _ => return Err(CompilerError::Unsupported(cmd.to_string())),
+                    },
+                    _ => return Err(CompilerError::Unsupported(cmd.to_string())),
+                };
+                let (_, offsets) = struct_layout(fields);
+                let Some((ty, offset)) = fields.get(*index).zip(offsets.get(*index)) else {
+                    return Err(CompilerError::Unsupported(cmd.to_string()));
+                };
+                let address = self.read(reg)?;
+                let address = self.convert(address, Kind::Ptr);
+                Ok(self.load(address, *offset, ty))
+            }
+            Command::Const(obj) => self.object(obj.get_value_ref()),
+            Command::Ref(value) => self.address_of(value),
+            Command::Load(ty, value) => {
+                let address = self.value(value)?;
+                let address = self.convert(address, Kind::Ptr);
+                if is_aggregate(ty) {
+                    // Loading a struct or an array copies it
+                    let slot = self.stack_slot(size_of(ty), align_of(ty));
+                    let copy = self.b.ins().stack_addr(POINTER_TYPE, slot, 0);
+                    self.copy(copy, address, ty);
+                    return Ok(Operand::new(copy, Kind::Ptr));
+                }
+                Ok(self.load(address, 0, ty))
+            }
+            Command::GetElementPtr(ty, base, indices) => self.element_ptr(ty, base, indices),
+            Command::IncrInt8(reg) => self.incr(reg, Kind::I8),
+            Command::IncrInt16(reg) => self.incr(reg, Kind::I16),
+            Command::IncrInt32(reg) => self.incr(reg, Kind::I32),
+            Command::IncrInt64(reg) => self.incr(reg, Kind::I64),
+            Command::IncrFloat32(reg) => self.incr(reg, Kind::F32),
+            Command::IncrFloat64(reg) => self.incr(reg, Kind::F64),
+            Command::AddInt8(lhs, rhs) => self.arith(lhs, rhs, Kind::I8, false),
+            Command::AddInt16(lhs, rhs) => self.arith(lhs, rhs, Kind::I16, false),
+            Command::AddInt32(lhs, rhs) => self.arith(lhs, rhs, Kind::I32, false),
+            Command::AddInt64(lhs, rhs) => self.arith(lhs, rhs, Kind::I64, false),
+            Command::AddFloat32(lhs, rhs) => self.arith(lhs, rhs, Kind::F32, false),
+            Command::AddFloat64(lhs, rhs) => self.arith(lhs, rhs, Kind::F64, false),
+            Command::SubInt8(lhs, rhs) => self.arith(lhs, rhs, Kind::I8, true),
+            Command::SubInt16(lhs, rhs) => self.arith(lhs, rhs, Kind::I16, true),
+            Command::SubInt32(lhs, rhs) => self.arith(lhs, rhs, Kind::I32, true),
+            Command::SubInt64(lhs, rhs) => self.arith(lhs, rhs, Kind::I64, true),
+            Command::SubFloat32(lhs, rhs) => self.arith(lhs, rhs, Kind::F32, true),
+            Command::SubFloat64(lhs, rhs) => self.arith(lhs, rhs, Kind::F64, true),
+            Command::Store(..)
+            | Command::Free(_)
+            | Command::Ret(_)
+            | Command::Jump(_)
+            | Command::Jeq(..)
+            | Command::Phi(_) => Err(CompilerError::Unsupported(cmd.to_string())),
+        }
+    }
+
+    /// `reg + 1`, which wraps around at the width of `kind`
+    fn incr(&mut self, reg: &RegisterValue, kind: Kind) -> CompilerResult<Operand> {
+        let value = self.read(reg)?;
+        let value = self.convert(value, kind);
+        let value = match kind {
+            Kind::F32 => {
+                let one = self.b.ins().f32const(1.0);
+                self.b.ins().fadd(value, one)
+            }
+            Kind::F64 => {
+                let one = self.b.ins().f64const(1.0);
+                self.b.ins().fadd(value, one)
+            }
+            _ => self.b.ins().iadd_imm(value, 1),
+        };
+        Ok(Operand::new(value, kind))
+    }
+
+    /// `lhs + rhs`, or `lhs - rhs`, which wraps around at the width of
+    /// `kind`
+    fn arith(
+        &mut self,
+        lhs: &Value,
+        rhs: &Value,
+        kind: Kind,
+        sub: bool,
+    ) -> CompilerResult<Operand> {
+        let lhs = self.value(lhs)?;
+        let lhs = self.convert(lhs, kind);
+        let rhs = self.value(rhs)?;
+        let rhs = self.convert(rhs, kind);
+        let value = match (kind.is_float(), sub) {
+            (false, false) => self.b.ins().iadd(lhs, rhs),
+            (false, true) => self.b.ins().isub(lhs, rhs),
+            (true, false) => self.b.ins().fadd(lhs, rhs),
+            (true, true) => self.b.ins().fsub(lhs, rhs),
+        };
+        Ok(Operand::new(value, kind))
+    }
+
+    /// The address `getelementptr` gives: the first index steps over whole
+    /// values of `ty`, the next ones go into it
+    fn element_ptr(
+        &mut self,
+        ty: &MirageTypeEnum,
+        base: &Value,
+        indices: &[Value],
+    ) -> CompilerResult<Operand> {
+        let base = self.value(base)?;
+        let mut address = self.convert(base, Kind::Ptr);
+        let mut ty = ty.clone();
+        for (i, index) in indices.iter().enumerate() {
+            if i > 0 {
+                match ty {
+                    MirageTypeEnum::Struct(s) => {
+                        let field = const_index(index)
+                            .and_then(|f| usize::try_from(f).ok())
+                            .filter(|f| *f < s.fields.len())
+                            .ok_or_else(|| CompilerError::Unsupported(index.to_string()))?;
+                        let (_, offsets) = struct_layout(&s.fields);
+                        address = self.b.ins().iadd_imm(address, offsets[field] as i64);
+                        ty = s.fields[field].clone();
+                        continue;
+                    }
+                    MirageTypeEnum::Array(a) => ty = a.element_ty(),
+                    _ => {
+                        return Err(CompilerError::Unsupported(format!(
+                            "getelementptr into {}",
+                            ty.print_to_string()
+                        )))
+                    }
+                }
+            }
+            let stride = size_of(&ty) as i64;
+            address = match const_index(index) {
+                Some(c) => self.b.ins().iadd_imm(address, c.wrapping_mul(stride)),
+                None => {
+                    let index = self.value(index)?;
+                    let kind = if index.kind.is_signed() {
+                        Kind::I64
+                    } else {
+                        Kind::U64
+                    };
+                    let index = self.convert(index, kind);
+                    let offset = self.b.ins().imul_imm(index, stride);
+                    self.b.ins().iadd(address, offset)
+                }
+            };
+        }
+        Ok(Operand::new(address, Kind::Ptr))
+    }
+
+    /// `value` converted to `kind`, as C converts between arithmetic
+    /// types. An address converts as an unsigned integer.
+    fn convert(&mut self, value: Operand, kind: Kind) -> ClifValue {
+        let (from, to) = (value.kind.clif(), kind.clif());
+        let v = value.value;
+        match (value.kind.is_float(), kind.is_float()) {
+            (false, false) if from.bits() < to.bits() => match value.kind.is_signed() {
+                true => self.b.ins().sextend(to, v),
+                false => self.b.ins().uextend(to, v),
+            },
+            (false, false) if from.bits() > to.bits() => self.b.ins().ireduce(to, v),
+            (false, false) => v,
+            (false, true) => {
+                // Cranelift converts from 32 and 64-bit integers only
+                let signed = value.kind.is_signed();
+                let v = match (from.bits() < 32, signed) {
+                    (true, true) => self.convert(value, Kind::I32),
+                    (true, false) => self.convert(value, Kind::U32),
+                    (false, _) => v,
+                };
+                match signed {
+                    true => self.b.ins().fcvt_from_sint(to, v),
+                    false => self.b.ins().fcvt_from_uint(to, v),
+                }
+            }
+            (true, false) => {
+                let wide = if to.bits() < 32 { types::I32 } else { to };
+                let v = match kind.is_signed() {
+                    true => self.b.ins().fcvt_to_sint_sat(wide, v),
+                    false => self.b.ins().fcvt_to_uint_sat(wide, v),
+                };
+                match wide == to {
+                    true => v,
+                    false => self.b.ins().ireduce(to, v),
+                }
+            }
+            (true, true) if from.bits() < to.bits() => self.b.ins().fpromote(to, v),
+            (true, true) if from.bits() > to.bits() => self.b.ins().fdemote(to, v),
+            (true, true) => v,
+        }
+    }
+
+    /// Load a value of `ty` from `address` plus `offset`. A struct or an
+    /// array isn't loaded: its address is the value.
+    fn load(&mut self, address: ClifValue, offset: u32, ty: &MirageTypeEnum) -> Operand {
+        if is_aggregate(ty) {
+            let address = self.b.ins().iadd_imm(address, offset as i64);
+            return Operand::new(address, Kind::Ptr);
+        }
+        let kind = Kind::of(ty);
+        let value = self
+            .b
+            .ins()
+            .load(kind.clif(), MemFlags::new(), address, offset as i32);
+        Operand::new(value, kind)
+    }
+
+    /// Store `value`, of type `ty`, at `address` plus `offset`. A struct or
+    /// an array is copied from the address `value` holds.
+    fn store(&mut self, address: ClifValue, offset: u32, ty: &MirageTypeEnum, value: Operand) {
+        if is_aggregate(ty) {
+            let to = self.b.ins().iadd_imm(address, offset as i64);
+            let from = self.convert(value, Kind::Ptr);
+            self.copy(to, from, ty);
+            return;
+        }
+        let value = self.convert(value, Kind::of(ty));
+        self.b
+            .ins()
+            .store(MemFlags::new(), value, address, offset as i32);
+    }
+
+    /// Copy a struct or an array of `ty` from `from` to `to`
+    fn copy(&mut self, to: ClifValue, from: ClifValue, ty: &MirageTypeEnum) {
+        let config = self.module.target_config();
+        let align = align_of(ty).min(POINTER_SIZE) as u8;
+        self.b.emit_small_memory_copy(
+            config,
+            to,
+            from,
+            size_of(ty) as u64,
+            align,
+            align,
+            true,
+            MemFlags::new(),
+        );
+    }
+
+    fn stack_slot(&mut self, size: u32, align: u32) -> StackSlot {
+        let align_shift = align.max(1).trailing_zeros() as u8;
+        self.b.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            size,
+            align_shift,
+        ))
+    }
+
+    fn func_ref(&mut self, id: FuncId) -> FuncRef {
+        if let Some(func_ref) = self.func_refs.get(&id) {
+            return *func_ref;
+        }
+        let func_ref = self.module.declare_func_in_func(id, self.b.func);
+        self.func_refs.insert(id, func_ref);
+        func_ref
+    }
+
+    /// The address of the data object `id`
+    fn data_address(&mut self, id: DataId) -> ClifValue {
+        let global = match self.data_refs.get(&id) {
+            Some(global) => *global,
+            None => {
+                let global = self.module.declare_data_in_func(id, self.b.func);
+                self.data_refs.insert(id, global);
+                global
+            }
+        };
+        self.b.ins().symbol_value(POINTER_TYPE, global)
+    }
+
+    /// The address of a constant string, NUL terminated and with its
+    /// escapes replaced. Equal strings share their data object.
+    fn string(&mut self, s: &str) -> CompilerResult<ClifValue> {
+        let id = match self.strings.get(s) {
+            Some(id) => *id,
+            None => {
+                let id = self
+                    .module
+                    .declare_anonymous_data(false, false)
+                    .map_err(backend)?;
+                let mut data = DataDescription::new();
+                data.define(string_bytes(s).into_boxed_slice());
+                self.module.define_data(id, &data).map_err(backend)?;
+                self.strings.insert(s.to_string(), id);
+                id
+            }
+        };
+        Ok(self.data_address(id))
+    }
+
+    fn global(&self, index: usize) -> CompilerResult<(DataId, MirageTypeEnum)> {
+        self.env
+            .globals
+            .get(index)
+            .cloned()
+            .ok_or(CompilerError::UnknownGlobal(index))
+    }
+
+    fn reg_kind(&self, reg: &RegisterValue) -> Kind {
+        self.kinds
+            .get(&reg_key(reg))
+            .copied()
+            .unwrap_or_else(|| Kind::of(&reg.ty))
+    }
+
+    fn var(&mut self, reg: &RegisterValue) -> Variable {
+        let key = reg_key(reg);
+        if let Some(var) = self.vars.get(&key) {
+            return *var;
+        }
+        let var = Variable::new(self.vars.len());
+        self.b.declare_var(var, self.reg_kind(reg).clif());
+        self.vars.insert(key, var);
+        var
+    }
+
+    fn read(&mut self, reg: &RegisterValue) -> CompilerResult<Operand> {
+        if reg.register_type == RegisterType::Global {
+            let (id, ty) = self.global(reg.index)?;
+            let address = self.data_address(id);
+            return Ok(self.load(address, 0, &ty));
+        }
+        let kind = self.reg_kind(reg);
+        if let Some(slot) = self.slots.get(&reg_key(reg)).copied() {
+            let value = self.b.ins().stack_load(kind.clif(), slot, 0);
+            return Ok(Operand::new(value, kind));
+        }
+        let var = self.var(reg);
+        Ok(Operand::new(self.b.use_var(var), kind))
+    }
+
+    fn write(&mut self, reg: &RegisterValue, value: Operand) -> CompilerResult<()> {
+        if reg.register_type == RegisterType::Global {
+            let (id, ty) = self.global(reg.index)?;
+            let address = self.data_address(id);
+            self.store(address, 0, &ty, value);
+            return Ok(());
+        }
+        let value = self.convert(value, self.reg_kind(reg));
+        if let Some(slot) = self.slots.get(&reg_key(reg)).copied() {
+            self.b.ins().stack_store(value, slot, 0);
+            return Ok(());
+        }
+        let var = self.var(reg);
+        self.b.def_var(var, value);
+        Ok(())
+    }
+
+    /// The address `ref value` gives. A constant gets a slot of its own.
+    fn address_of(&mut self, value: &Value) -> CompilerResult<Operand> {
+        if let Some(reg) = value_register(value) {
+            if reg.register_type == RegisterType::Global {
+                let (id, _) = self.global(reg.index)?;
+                return Ok(Operand::new(self.data_address(id), Kind::Ptr));
+            }
+            if is_aggregate(&reg.ty) {
+                return self.read(reg);
+            }
+            let slot = self.slots[&reg_key(reg)];
+            let address = self.b.ins().stack_addr(POINTER_TYPE, slot, 0);
+            return Ok(Operand::new(address, Kind::Ptr));
+        }
+        let ty = match value {
+            Value::List(_) => return Err(CompilerError::Unsupported(value.to_string())),
+            _ => value.get_type(),
+        };
+        let value = self.value(value)?;
+        if is_aggregate(&ty) {
+            return Ok(value);
+        }
+        let slot = self.stack_slot(size_of(&ty), align_of(&ty));
+        let address = self.b.ins().stack_addr(POINTER_TYPE, slot, 0);
+        self.store(address, 0, &ty, value);
+        Ok(Operand::new(address, Kind::Ptr))
+    }
+
+    fn value(&mut self, value: &Value) -> CompilerResult<Operand> {
+        match value {
+            Value::ConstValue(obj) => self.object(obj.get_value_ref()),
+            Value::Register(reg) => self.read(reg),
+            Value::List(_) => Err(CompilerError::Unsupported(value.to_string())),
+        }
+    }
+
+    /// The value of a constant. A string is the address of its data
+    /// object, and another struct or array is written to a stack slot.
+    fn object(&mut self, value: &MirageValueEnum) -> CompilerResult<Operand> {
+        let int = |b: &mut FunctionBuilder, kind: Kind, v: i64| {
+            Operand::new(b.ins().iconst(kind.clif(), v), kind)
+        };
+        Ok(match value {
+            MirageValueEnum::Register(reg) => return self.read(reg),
+            MirageValueEnum::Int8(v) => int(&mut self.b, Kind::I8, v.value as u8 as i64),
+            MirageValueEnum::Int16(v) => int(&mut self.b, Kind::I16, v.value as u16 as i64),
+            MirageValueEnum::Int32(v) => int(&mut self.b, Kind::I32, v.value as u32 as i64),
+            MirageValueEnum::Int64(v) => int(&mut self.b, Kind::I64, v.value),
+            MirageValueEnum::UInt8(v) => int(&mut self.b, Kind::U8, v.value as i64),
+            MirageValueEnum::UInt16(v) => int(&mut self.b, Kind::U16, v.value as i64),
+            MirageValueEnum::UInt32(v) => int(&mut self.b, Kind::U32, v.value as i64),
+            MirageValueEnum::UInt64(v) => int(&mut self.b, Kind::U64, v.value as i64),
+            MirageValueEnum::Float32(v) => Operand::new(self.b.ins().f32const(v.value), Kind::F32),
+            MirageValueEnum::Float64(v) => Operand::new(self.b.ins().f64const(v.value), Kind::F64),
+            MirageValueEnum::Array(_) | MirageValueEnum::Struct(_) => {
+                if let Some(s) = value.try_to_rust_string() {
+                    return Ok(Operand::new(self.string(&s)?, Kind::Ptr));
+                }
+                let ty = value.get_type();
+                let slot = self.stack_slot(size_of(&ty), align_of(&ty));
+                let address = self.b.ins().stack_addr(POINTER_TYPE, slot, 0);
+                self.fill(address, 0, value)?;
+                Operand::new(address, Kind::Ptr)
+            }
+            MirageValueEnum::Pointer(_) => {
+                return Err(CompilerError::Unsupported(value.print_to_string()))
+            }
+        })
+    }
+
+    /// Write a constant struct or array at `address` plus `offset`
+    fn fill(
+        &mut self,
+        address: ClifValue,
+        offset: u32,
+        value: &MirageValueEnum,
+    ) -> CompilerResult<()> {
+        match value {
+            MirageValueEnum::Array(a) => {
+                let size = size_of(&a.ty.element_ty());
+                for (i, v) in a.values.iter().enumerate() {
+                    self.fill(address, offset + i as u32 * size, v)?;
+                }
+                Ok(())
+            }
+            MirageValueEnum::Struct(s) => {
+                let (_, offsets) = struct_layout(&s.ty.fields);
+                for (v, field) in s.values.iter().zip(offsets) {
+                    self.fill(address, offset + field, v)?;
+                }
+                Ok(())
+            }
+            _ => {
+                let v = self.object(value)?;
+                self.store(address, offset, &value.get_type(), v);
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{Compiler, CompilerResult};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId};
+use mirage_backend_output::jit::{JitError, JitResult, JitSignature};
+use mirage_backend_output::ExecutionEngineOutput;
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::statements::Statement;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Whether the process defines a symbol named `name`, which is what an
+/// extern resolves to when no host function is registered for it
+#[cfg(unix)]
+fn process_symbol(name: &str) -> bool {
+    let Ok(name) = std::ffi::CString::new(name) else {
+        return false;
+    };
+    !unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) }.is_null()
+}
+
+#[cfg(not(unix))]
+fn process_symbol(_name: &str) -> bool {
+    true
+}
+
+/// A JIT session over a program compiled in memory with `cranelift-jit`.
+///
+/// The functions are compiled when the session is created, and linked by
+/// the first `get_function`. An extern resolves to the host function
+/// registered for it, or to the symbol of the process with its name, such
+/// as `printf`, so host functions must be registered before.
+pub struct JitSession {
+    module: JITModule,
+    functions: HashMap<String, (FuncId, FunctionType)>,
+    externs: HashMap<String, FunctionType>,
+    /// The address of the registered host functions, shared with the
+    /// symbol lookup of the module
+    symbols: Arc<Mutex<HashMap<String, u64>>>,
+    finalized: bool,
+}
+
+impl JitSession {
+    /// Create a new session
+    /// # Arguments
+    /// * `compiler` - The compiler of the program, which is compiled again
+    ///   for the host
+    pub fn new(compiler: &Compiler) -> CompilerResult<Self> {
+        let isa = compiler.isa(None, false)?;
+        let symbols: Arc<Mutex<HashMap<String, u64>>> = Arc::default();
+        let lookup = symbols.clone();
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol_lookup_fn(Box::new(move |name| {
+            let symbols = lookup.lock().unwrap();
+            symbols
+                .get(name)
+                .map(|address| *address as usize as *const u8)
+        }));
+        let mut module = JITModule::new(builder);
+        let env = compiler.define(&mut module)?;
+
+        let mut functions = HashMap::new();
+        let mut externs = HashMap::new();
+        for stmt in &compiler.stmts {
+            match stmt {
+                Statement::Function(f) => {
+                    let id = env.functions[f.get_name()].id;
+                    functions.insert(f.get_name().clone(), (id, f.get_type().clone()));
+                }
+                Statement::External(e) => {
+                    externs.insert(e.name.clone(), e.ty.clone());
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            module,
+            functions,
+            externs,
+            symbols,
+            finalized: false,
+        })
+    }
+
+    /// Whether the program defines a function named `name`
+    pub fn contains_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// The Mirage type of the function named `name`
+    pub fn function_type(&self, name: &str) -> Option<&FunctionType> {
+        self.functions.get(name).map(|(_, ty)| ty)
+    }
+
+    /// Link the functions, once every extern can be resolved
+    fn finalize(&mut self) -> JitResult<()> {
+        if self.finalized {
+            return Ok(());
+        }
+        let symbols = self.symbols.lock().unwrap();
+        if let Some(name) = self
+            .externs
+            .keys()
+            .find(|name| !symbols.contains_key(*name) && !process_symbol(name))
+        {
+            return Err(JitError::UnknownExtern(name.clone()));
+        }
+        drop(symbols);
+        self.module
+            .finalize_definitions()
+            .map_err(|err| JitError::Backend(err.to_string()))?;
+        self.finalized = true;
+        Ok(())
+    }
+}
+
+impl ExecutionEngineOutput for JitSession {
+    unsafe fn get_function<T: JitSignature>(&mut self, name: &str) -> JitResult<T> {
+        let (id, ty) = self
+            .functions
+            .get(name)
+            .ok_or_else(|| JitError::UnknownFunction(name.to_string()))?;
+        T::check(name, ty)?;
+        let id = *id;
+
+        self.finalize()?;
+        let address = self.module.get_finalized_function(id) as u64;
+        Ok(unsafe { T::from_address(address) })
+    }
+
+    fn register_symbol<T: JitSignature>(&mut self, name: &str, f: T) -> JitResult<()> {
+        let ty = self
+            .externs
+            .get(name)
+            .ok_or_else(|| JitError::UnknownExtern(name.to_string()))?;
+        T::check(name, ty)?;
+        if self.finalized {
+            return Err(JitError::AlreadyFinalized(name.to_string()));
+        }
+
+        self.symbols
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), f.address());
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use cranelift_codegen::ir::{types, AbiParam, Type};
+pub use mirage_backend_opti::layout::is_aggregate;
+use mirage_backend_opti::layout::Layout;
+use mirage_frontend::object::MirageTypeEnum;
+
+/// The size of an address. Cranelift only targets 64-bit machines.
+pub const POINTER_SIZE: u32 = 8;
+
+/// The type of an address
+pub const POINTER_TYPE: Type = types::I64;
+
+/// The layout of the values in memory
+const LAYOUT: Layout = Layout::new(POINTER_SIZE as u64);
+
+/// The number of bytes taken by a value of `ty` in memory
+pub fn size_of(ty: &MirageTypeEnum) -> u32 {
+    LAYOUT.size_of(ty) as u32
+}
+
+pub fn align_of(ty: &MirageTypeEnum) -> u32 {
+    LAYOUT.align_of(ty) as u32
+}
+
+/// The size of a struct with `fields` and the offset of every field
+pub fn struct_layout(fields: &[MirageTypeEnum]) -> (u32, Vec<u32>) {
+    let (size, offsets) = LAYOUT.struct_layout(fields);
+    (
+        size as u32,
+        offsets.into_iter().map(|offset| offset as u32).collect(),
+    )
+}
+
+/// What a value is held as: an integer of its width and signedness, a
+/// float, or an address. Structs and arrays are handled through their
+/// address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    Ptr,
+}
+
+impl Kind {
+    pub fn of(ty: &MirageTypeEnum) -> Self {
+        match ty {
+            MirageTypeEnum::Int8(_) => Kind::I8,
+            MirageTypeEnum::Int16(_) => Kind::I16,
+            MirageTypeEnum::Int32(_) => Kind::I32,
+            MirageTypeEnum::Int64(_) => Kind::I64,
+            MirageTypeEnum::UInt8(_) => Kind::U8,
+            MirageTypeEnum::UInt16(_) => Kind::U16,
+            MirageTypeEnum::UInt32(_) => Kind::U32,
+            MirageTypeEnum::UInt64(_) => Kind::U64,
+            MirageTypeEnum::Float32(_) => Kind::F32,
+            MirageTypeEnum::Float64(_) => Kind::F64,
+            _ => Kind::Ptr,
+        }
+    }
+
+    /// The Cranelift type of the value
+    pub fn clif(self) -> Type {
+        match self {
+            Kind::I8 | Kind::U8 => types::I8,
+            Kind::I16 | Kind::U16 => types::I16,
+            Kind::I32 | Kind::U32 => types::I32,
+            Kind::I64 | Kind::U64 => types::I64,
+            Kind::F32 => types::F32,
+            Kind::F64 => types::F64,
+            Kind::Ptr => POINTER_TYPE,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, Kind::I8 | Kind::I16 | Kind::I32 | Kind::I64)
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Kind::F32 | Kind::F64)
+    }
+
+    /// The parameter or the return value of a function, extended to 32
+    /// bits or more as C does when it is a narrower integer
+    pub fn abi_param(self) -> AbiParam {
+        let param = AbiParam::new(self.clif());
+        match self {
+            Kind::I8 | Kind::I16 => param.sext(),
+            Kind::U8 | Kind::U16 => param.uext(),
+            _ => param,
+        }
+    }
+}
@@ -0,0 +1,295 @@
+#[cfg(test)]
+mod test;
+
+mod data;
+mod function;
+mod jit;
+mod layout;
+
+pub use jit::JitSession;
+
+use cranelift_codegen::ir::Signature;
+use cranelift_codegen::isa::{CallConv, OwnedTargetIsa};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::FunctionBuilderContext;
+use cranelift_module::{default_libcall_names, DataDescription, DataId, FuncId, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use data::global_data;
+use function::FunctionCompiler;
+use layout::Kind;
+use mirage_backend_opti::ir::has_attribute;
+use mirage_backend_opti::OptiLevel;
+use mirage_backend_output::object::File;
+use mirage_backend_output::{CompilerOutput, ExecutionEngineOutput, ObjectOutput};
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::meta::Flag;
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::MirageTypeEnum;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use target_lexicon::Triple;
+
+/// A compiler error
+/// # Variants
+/// * `UnknownFunction` - A call to a function which isn't declared
+/// * `UnknownType` - A `new` of a type which isn't defined
+/// * `UnknownGlobal` - A global register without its global
+/// * `UnknownLabel` - A jump to a label which isn't in the function
+/// * `ArgumentCount` - A call with too few arguments
+/// * `Unsupported` - Something the backend can't express, such as a global
+///   holding a pointer
+/// * `Target` - The target isn't known to Cranelift or its settings are
+///   invalid
+/// * `Backend` - Cranelift failed to compile a function or to emit the
+///   module
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompilerError {
+    UnknownFunction(String),
+    UnknownType(String),
+    UnknownGlobal(usize),
+    UnknownLabel(String),
+    ArgumentCount(String),
+    Unsupported(String),
+    Target(String),
+    Backend(String),
+}
+
+pub type CompilerResult<T> = Result<T, CompilerError>;
+
+fn backend(err: impl std::fmt::Display) -> CompilerError {
+    CompilerError::Backend(err.to_string())
+}
+
+/// A function of the module, defined or imported
+#[derive(Debug, Clone)]
+struct Callee {
+    id: FuncId,
+    ty: FunctionType,
+}
+
+/// What the functions of a module are compiled against
+#[derive(Debug, Clone)]
+struct Env {
+    functions: HashMap<String, Callee>,
+    /// The data object and the type of every global, by index
+    globals: Vec<(DataId, MirageTypeEnum)>,
+    structs: HashMap<String, Vec<MirageTypeEnum>>,
+    call_conv: CallConv,
+    /// Whether a float can be passed as a variadic argument. On x86-64 the
+    /// caller sets `%al` to the number of vector registers used, which
+    /// Cranelift can't express.
+    float_var_args: bool,
+}
+
+/// The Cranelift signature of a function of type `ty`. The variadic
+/// arguments aren't part of it: every call gives them a signature of its
+/// own.
+fn signature(call_conv: CallConv, ty: &FunctionType) -> Signature {
+    let mut sig = Signature::new(call_conv);
+    sig.params
+        .extend(ty.get_args().iter().map(|arg| Kind::of(arg).abi_param()));
+    sig.returns.push(Kind::of(ty.get_ret()).abi_param());
+    sig
+}
+
+/// The Cranelift Compiler struct.
+///
+/// The functions become functions of a Cranelift module, local to it when
+/// they are `#internal`, and the externs are imported. The globals are
+/// data objects, read-only when they are `#const`. Pointers are 64-bit
+/// addresses, and so are structs and arrays, which are handled through
+/// their address. The ones a function creates live in its stack frame.
+///
+/// `compile` emits an object file for the target, the host unless one is
+/// set, and `jit` compiles the program again in memory for the host.
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    stmts: Vec<Statement>,
+    opti_level: OptiLevel,
+    target: Option<String>,
+    object: Vec<u8>,
+}
+
+impl Compiler {
+    pub fn new(stmts: Vec<Statement>) -> Self {
+        Self {
+            stmts,
+            opti_level: OptiLevel::O0,
+            target: None,
+            object: Vec::new(),
+        }
+    }
+
+    pub fn set_opti_level(&mut self, level: OptiLevel) {
+        self.opti_level = level;
+    }
+
+    pub fn get_opti_level(&self) -> OptiLevel {
+        self.opti_level
+    }
+
+    /// Emit the object file for the target triple `triple` rather than for
+    /// the host
+    pub fn set_target(&mut self, triple: &str) {
+        self.target = Some(triple.to_string());
+    }
+
+    /// The target to compile for. Position independent code is needed to
+    /// link an object file into an executable, and isn't by the JIT.
+    fn isa(&self, target: Option<&str>, pic: bool) -> CompilerResult<OwnedTargetIsa> {
+        let mut flags = settings::builder();
+        let opt_level = match self.opti_level {
+            OptiLevel::O0 => "none",
+            OptiLevel::O1 | OptiLevel::Os | OptiLevel::Oz => "speed_and_size",
+            OptiLevel::O2 | OptiLevel::O3 => "speed",
+        };
+        let target_err = |err: settings::SetError| CompilerError::Target(err.to_string());
+        flags.set("opt_level", opt_level).map_err(target_err)?;
+        flags
+            .set("is_pic", if pic { "true" } else { "false" })
+            .map_err(target_err)?;
+        let isa = match target {
+            Some(triple) => {
+                let err = |err: &dyn std::fmt::Display| {
+                    CompilerError::Target(format!("{}: {}", triple, err))
+                };
+                let parsed = Triple::from_str(triple).map_err(|e| err(&e))?;
+                cranelift_codegen::isa::lookup(parsed).map_err(|e| err(&e))?
+            }
+            None => {
+                cranelift_native::builder().map_err(|err| CompilerError::Target(err.to_string()))?
+            }
+        };
+        isa.finish(settings::Flags::new(flags))
+            .map_err(|err| CompilerError::Target(err.to_string()))
+    }
+
+    pub fn compile(&mut self) -> CompilerResult<()> {
+        let isa = self.isa(self.target.as_deref(), true)?;
+        let builder =
+            ObjectBuilder::new(isa, "mirage", default_libcall_names()).map_err(backend)?;
+        let mut module = ObjectModule::new(builder);
+        self.define(&mut module)?;
+        self.object = module.finish().emit().map_err(backend)?;
+        Ok(())
+    }
+
+    /// Declare and define the program in `module`, and return what its
+    /// functions were compiled against
+    fn define<M: Module>(&self, module: &mut M) -> CompilerResult<Env> {
+        let isa = module.isa();
+        let mut env = Env {
+            functions: HashMap::new(),
+            globals: Vec::new(),
+            structs: HashMap::new(),
+            call_conv: isa.default_call_conv(),
+            float_var_args: isa.name() != "x64",
+        };
+        let mut functions = Vec::new();
+        for stmt in &self.stmts {
+            match stmt {
+                Statement::Typedef(t) => {
+                    env.structs.insert(t.name.clone(), t.ty.clone().into_vec());
+                }
+                Statement::Global(global) => {
+                    let id = module
+                        .declare_data(&global.name, Linkage::Local, !global.is_const(), false)
+                        .map_err(backend)?;
+                    let (bytes, align) = global_data(global.value.get_value_ref())?;
+                    let mut data = DataDescription::new();
+                    data.define(bytes.into_boxed_slice());
+                    data.set_align(align as u64);
+                    module.define_data(id, &data).map_err(backend)?;
+                    env.globals.push((id, global.value.get_type()));
+                }
+                Statement::External(e) => {
+                    let sig = signature(env.call_conv, &e.ty);
+                    let id = module
+                        .declare_function(&e.name, Linkage::Import, &sig)
+                        .map_err(backend)?;
+                    env.functions.insert(
+                        e.name.clone(),
+                        Callee {
+                            id,
+                            ty: e.ty.clone(),
+                        },
+                    );
+                }
+                Statement::Function(f) => {
+                    // The bodies are compiled once every function is
+                    // declared, so that they can call each other
+                    let linkage = if has_attribute(f, &Flag::internal()) {
+                        Linkage::Local
+                    } else {
+                        Linkage::Export
+                    };
+                    let sig = signature(env.call_conv, f.get_type());
+                    let id = module
+                        .declare_function(f.get_name(), linkage, &sig)
+                        .map_err(backend)?;
+                    env.functions.insert(
+                        f.get_name().clone(),
+                        Callee {
+                            id,
+                            ty: f.get_type().clone(),
+                        },
+                    );
+                    functions.push((f, id, sig));
+                }
+                _ => {}
+            }
+        }
+
+        let mut strings = HashMap::new();
+        let mut ctx = module.make_context();
+        let mut builder_ctx = FunctionBuilderContext::new();
+        for (f, id, sig) in functions {
+            ctx.func.signature = sig;
+            FunctionCompiler::new(
+                &env,
+                module,
+                &mut strings,
+                &mut ctx.func,
+                &mut builder_ctx,
+                f,
+            )
+            .compile()?;
+            module
+                .define_function(id, &mut ctx)
+                .map_err(|err| CompilerError::Backend(format!("{}: {:?}", f.get_name(), err)))?;
+            module.clear_context(&mut ctx);
+        }
+        Ok(env)
+    }
+
+    /// The compiled program, as the bytes of an object file
+    pub fn emit_object(&self) -> Vec<u8> {
+        self.object.clone()
+    }
+
+    /// Write the compiled program to an object file
+    pub fn write_to(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, &self.object)
+    }
+
+    /// Compile the program in memory, to run it on the host
+    pub fn jit(&self) -> CompilerResult<JitSession> {
+        JitSession::new(self)
+    }
+}
+
+impl CompilerOutput for Compiler {
+    /// The object file `compile` emitted. The output borrows its bytes for
+    /// any lifetime, so they are leaked.
+    fn object<'a>(&mut self) -> ObjectOutput<'a> {
+        let bytes: &'a [u8] = Box::leak(self.object.clone().into_boxed_slice());
+        let file = File::parse(bytes).expect("`compile` emits the object file");
+        ObjectOutput::new(Arc::new(file))
+    }
+
+    fn execution_engine(&mut self) -> impl ExecutionEngineOutput {
+        self.jit().expect("the program compiles for the host")
+    }
+}
@@ -0,0 +1,598 @@
+use std::path::PathBuf;
+use std::process::Command as Process;
+
+use mirage_backend_opti::OptiLevel;
+use mirage_backend_output::jit::JitError;
+use mirage_backend_output::object::{Object, ObjectSection, ObjectSymbol};
+use mirage_backend_output::{CompilerOutput, ExecutionEngineOutput};
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::{Flag, Flags};
+use mirage_frontend::object::statements::{External, Global, Statement, TypeDef};
+use mirage_frontend::object::util::List;
+use mirage_frontend::object::{
+    MirageObject, MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue,
+};
+
+use crate::layout::struct_layout;
+use crate::{Compiler, CompilerError, JitSession};
+
+fn i8_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int8().into()
+}
+
+fn u8_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_uint8().into()
+}
+
+fn i32_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int32().into()
+}
+
+fn i64_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int64().into()
+}
+
+fn reg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Register, ty)
+}
+
+fn arg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Argument, ty)
+}
+
+fn global(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Global, ty)
+}
+
+fn val(reg: &RegisterValue) -> Value {
+    Value::Register(reg.clone())
+}
+
+fn obj(value: MirageValueEnum) -> MirageObject {
+    MirageObject::from(value)
+}
+
+fn i32_obj(v: i32) -> MirageObject {
+    obj(MirageTypeEnum::type_int32().const_value(v).to_value_enum())
+}
+
+fn i32_val(v: i32) -> Value {
+    Value::ConstValue(i32_obj(v))
+}
+
+fn i8_val(v: i8) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_int8()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn u8_val(v: u8) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_uint8()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn i64_val(v: i64) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_int64()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn assign(reg: &RegisterValue, cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(reg.clone(), Box::new(LabelBodyInstr::Command(cmd)))
+}
+
+fn assign_call(reg: &RegisterValue, name: &str, args: Vec<Value>) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(
+        reg.clone(),
+        Box::new(LabelBodyInstr::Call(name.to_string(), args)),
+    )
+}
+
+fn cmd(cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Command(cmd)
+}
+
+fn label(name: &str, body: Vec<LabelBodyInstr>) -> Label {
+    Label::new(name.to_string(), Flags::new(vec![]), body)
+}
+
+fn function_returning(
+    name: &str,
+    args: Vec<MirageTypeEnum>,
+    ret: MirageTypeEnum,
+    labels: Vec<Label>,
+) -> Statement {
+    let mut f = FunctionType::new(args, ret, false).fn_value(name.to_string());
+    for l in labels {
+        f.add_label(l);
+    }
+    Statement::Function(f)
+}
+
+fn function(name: &str, args: Vec<MirageTypeEnum>, labels: Vec<Label>) -> Statement {
+    function_returning(name, args, i32_ty(), labels)
+}
+
+fn string(s: &str) -> MirageValueEnum {
+    let ty = MirageTypeEnum::type_array(i8_ty(), s.len());
+    MirageValueEnum::Array(
+        ty.const_value(
+            s.bytes()
+                .map(|c| {
+                    MirageTypeEnum::type_int8()
+                        .const_value(c as i8)
+                        .to_value_enum()
+                })
+                .collect(),
+        ),
+    )
+}
+
+fn printf() -> Statement {
+    let ty = FunctionType::new(
+        vec![MirageTypeEnum::type_ptr(i8_ty()).into()],
+        i32_ty(),
+        true,
+    );
+    Statement::External(External::new("printf".to_string(), ty))
+}
+
+fn jit(stmts: Vec<Statement>) -> JitSession {
+    Compiler::new(stmts).jit().unwrap()
+}
+
+fn counter_loop() -> Statement {
+    let a = arg(0, i32_ty());
+    let r = |i| reg(i, i32_ty());
+    function(
+        "count",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    assign(&r(0), Command::Const(i32_obj(0))),
+                    assign(&r(1), Command::Ref(val(&r(0)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(&r(2), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Jeq("end".to_string(), val(&r(2)), val(&a))),
+                    assign(&r(3), Command::AddInt32(val(&r(2)), i32_val(1))),
+                    cmd(Command::Store(r(1), val(&r(3)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "end",
+                vec![
+                    assign(&r(4), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    )
+}
+
+/// `sum(n)`, adding `n` down to 1 with phis, one of them on the edge
+/// taken by a `jeq`
+fn sum_loop() -> Statement {
+    let n = arg(0, i64_ty());
+    let r = |i| reg(i, i64_ty());
+    function_returning(
+        "sum",
+        vec![i64_ty()],
+        i64_ty(),
+        vec![
+            label(
+                "entry",
+                vec![cmd(Command::Jeq("done".to_string(), val(&n), i64_val(0)))],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(
+                        &r(0),
+                        Command::Phi(vec![
+                            ("entry".to_string(), val(&n)),
+                            ("loop".to_string(), val(&r(2))),
+                        ]),
+                    ),
+                    assign(
+                        &r(1),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(0)),
+                            ("loop".to_string(), val(&r(3))),
+                        ]),
+                    ),
+                    assign(&r(2), Command::SubInt64(val(&r(0)), i64_val(1))),
+                    assign(&r(3), Command::AddInt64(val(&r(1)), val(&r(0)))),
+                    cmd(Command::Jeq("done".to_string(), val(&r(2)), i64_val(0))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "done",
+                vec![
+                    assign(
+                        &r(4),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(0)),
+                            ("loop".to_string(), val(&r(3))),
+                        ]),
+                    ),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    )
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("mirage-cranelift-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Call `main` through the traits every backend implements
+fn run_main<C: CompilerOutput>(compiler: &mut C) -> i32 {
+    let mut engine = compiler.execution_engine();
+    let main: extern "C" fn() -> i32 = unsafe { engine.get_function("main") }.unwrap();
+    main()
+}
+
+#[test]
+fn test_loops() {
+    let mut session = jit(vec![counter_loop(), sum_loop()]);
+    let count: extern "C" fn(i32) -> i32 = unsafe { session.get_function("count") }.unwrap();
+    assert_eq!(count(5), 5);
+    assert_eq!(count(0), 0);
+    let sum: extern "C" fn(i64) -> i64 = unsafe { session.get_function("sum") }.unwrap();
+    assert_eq!(sum(0), 0);
+    assert_eq!(sum(1), 1);
+    assert_eq!(sum(100), 5050);
+
+    for level in [OptiLevel::O0, OptiLevel::O2, OptiLevel::Os] {
+        let mut compiler = Compiler::new(vec![sum_loop()]);
+        compiler.set_opti_level(level);
+        let mut session = compiler.jit().unwrap();
+        let sum: extern "C" fn(i64) -> i64 = unsafe { session.get_function("sum") }.unwrap();
+        assert_eq!(sum(10), 55);
+    }
+}
+
+#[test]
+fn test_wraparound() {
+    let mut session = jit(vec![
+        function_returning(
+            "inc8",
+            vec![i8_ty()],
+            i8_ty(),
+            vec![label(
+                "entry",
+                vec![
+                    assign(&reg(0, i8_ty()), Command::IncrInt8(arg(0, i8_ty()))),
+                    cmd(Command::Ret(val(&reg(0, i8_ty())))),
+                ],
+            )],
+        ),
+        function_returning(
+            "sub8",
+            vec![],
+            u8_ty(),
+            vec![label(
+                "entry",
+                vec![
+                    assign(&reg(0, u8_ty()), Command::SubInt8(u8_val(0), u8_val(1))),
+                    cmd(Command::Ret(val(&reg(0, u8_ty())))),
+                ],
+            )],
+        ),
+        function_returning(
+            "widen",
+            vec![i8_ty()],
+            i64_ty(),
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &reg(0, i8_ty()),
+                        Command::AddInt8(val(&arg(0, i8_ty())), i8_val(1)),
+                    ),
+                    cmd(Command::Ret(val(&reg(0, i8_ty())))),
+                ],
+            )],
+        ),
+    ]);
+    let inc8: extern "C" fn(i8) -> i8 = unsafe { session.get_function("inc8") }.unwrap();
+    assert_eq!(inc8(127), -128);
+    assert_eq!(inc8(-1), 0);
+    let sub8: extern "C" fn() -> u8 = unsafe { session.get_function("sub8") }.unwrap();
+    assert_eq!(sub8(), 255);
+    let widen: extern "C" fn(i8) -> i64 = unsafe { session.get_function("widen") }.unwrap();
+    assert_eq!(widen(-3), -2);
+    assert_eq!(widen(127), -128);
+}
+
+#[test]
+fn test_structs_and_arrays() {
+    let fields = vec![i32_ty(), i64_ty()];
+    assert_eq!(struct_layout(&fields), (16, vec![0, 8]));
+    let point: MirageTypeEnum = MirageTypeEnum::type_struct(fields.clone()).into();
+    let p = reg(0, point);
+    let array: MirageTypeEnum = MirageTypeEnum::type_array(i32_ty(), 4).into();
+    let table = MirageValueEnum::Array(
+        MirageTypeEnum::type_array(i32_ty(), 4).const_value(
+            [10, 20, 30, 40]
+                .into_iter()
+                .map(|v| MirageTypeEnum::type_int32().const_value(v).to_value_enum())
+                .collect(),
+        ),
+    );
+    let ptr = |i| reg(i, MirageTypeEnum::type_ptr(i32_ty()).into());
+    let mut session = jit(vec![
+        Statement::Typedef(TypeDef::new("point".to_string(), List::from_vec(fields))),
+        Statement::Global(Global::new("table".to_string(), obj(table))),
+        function(
+            "first",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &p,
+                        Command::New(
+                            "point".to_string(),
+                            List::from_vec(vec![val(&arg(0, i32_ty())), i64_val(0)]),
+                        ),
+                    ),
+                    assign(&reg(1, i32_ty()), Command::Get(p.clone(), 0)),
+                    cmd(Command::Ret(val(&reg(1, i32_ty())))),
+                ],
+            )],
+        ),
+        function(
+            "at",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &ptr(0),
+                        Command::GetElementPtr(
+                            array.clone(),
+                            val(&global(0, array.clone())),
+                            vec![i32_val(0), val(&arg(0, i32_ty()))],
+                        ),
+                    ),
+                    assign(&reg(1, i32_ty()), Command::Load(i32_ty(), val(&ptr(0)))),
+                    cmd(Command::Store(ptr(0), i32_val(0))),
+                    cmd(Command::Ret(val(&reg(1, i32_ty())))),
+                ],
+            )],
+        ),
+    ]);
+    let first: extern "C" fn(i32) -> i32 = unsafe { session.get_function("first") }.unwrap();
+    assert_eq!(first(9), 9);
+    let at: extern "C" fn(i32) -> i32 = unsafe { session.get_function("at") }.unwrap();
+    assert_eq!(at(2), 30);
+    // The global is writable, so the store is seen by the next call
+    assert_eq!(at(2), 0);
+    assert_eq!(at(3), 40);
+
+    let mut compiler = Compiler::new(vec![function(
+        "f",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign(&p, Command::New("line".to_string(), List::from_vec(vec![]))),
+                cmd(Command::Ret(i32_val(0))),
+            ],
+        )],
+    )]);
+    assert_eq!(
+        compiler.compile(),
+        Err(CompilerError::UnknownType("line".to_string()))
+    );
+}
+
+extern "C" fn host_mul(a: i32, b: i32) -> i32 {
+    a * b
+}
+
+extern "C" fn host_mul_wide(a: i32, b: i32) -> i64 {
+    a as i64 * b as i64
+}
+
+#[test]
+fn test_register_symbol() {
+    let ty = FunctionType::new(vec![i32_ty(), i32_ty()], i32_ty(), false);
+    let stmts = vec![
+        Statement::External(External::new("host_mul".to_string(), ty)),
+        function(
+            "square",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    assign_call(
+                        &reg(0, i32_ty()),
+                        "host_mul",
+                        vec![val(&arg(0, i32_ty())), val(&arg(0, i32_ty()))],
+                    ),
+                    cmd(Command::Ret(val(&reg(0, i32_ty())))),
+                ],
+            )],
+        ),
+    ];
+
+    let mut session = jit(stmts.clone());
+    assert!(session.contains_function("square"));
+    assert!(!session.contains_function("host_mul"));
+    assert_eq!(
+        unsafe { session.get_function::<extern "C" fn(i32) -> i32>("square") },
+        Err(JitError::UnknownExtern("host_mul".to_string()))
+    );
+    assert!(matches!(
+        session.register_symbol("host_mul", host_mul_wide as extern "C" fn(i32, i32) -> i64),
+        Err(JitError::SignatureMismatch { .. })
+    ));
+    assert_eq!(
+        session.register_symbol("mul", host_mul as extern "C" fn(i32, i32) -> i32),
+        Err(JitError::UnknownExtern("mul".to_string()))
+    );
+    session
+        .register_symbol("host_mul", host_mul as extern "C" fn(i32, i32) -> i32)
+        .unwrap();
+    let square: extern "C" fn(i32) -> i32 = unsafe { session.get_function("square") }.unwrap();
+    assert_eq!(square(7), 49);
+    assert_eq!(
+        session.register_symbol("host_mul", host_mul as extern "C" fn(i32, i32) -> i32),
+        Err(JitError::AlreadyFinalized("host_mul".to_string()))
+    );
+    assert!(matches!(
+        unsafe { session.get_function::<extern "C" fn(i64) -> i32>("square") },
+        Err(JitError::SignatureMismatch { .. })
+    ));
+    assert_eq!(
+        unsafe { session.get_function::<extern "C" fn() -> i32>("cube") },
+        Err(JitError::UnknownFunction("cube".to_string()))
+    );
+}
+
+#[test]
+fn test_object() {
+    let g = global(0, string("%d\n").get_type());
+    let mut helper = label("entry", vec![cmd(Command::Ret(val(&arg(0, i32_ty()))))]);
+    helper.flags.push(Flag::internal());
+    let stmts = vec![
+        printf(),
+        Statement::Global(Global::new("fmt".to_string(), obj(string("%d\\n")))),
+        function("helper", vec![i32_ty()], vec![helper]),
+        function(
+            "main",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign_call(&reg(0, i32_ty()), "helper", vec![i32_val(42)]),
+                    LabelBodyInstr::Call(
+                        "printf".to_string(),
+                        vec![val(&g), val(&reg(0, i32_ty()))],
+                    ),
+                    cmd(Command::Ret(i32_val(3))),
+                ],
+            )],
+        ),
+    ];
+    let mut compiler = Compiler::new(stmts);
+    compiler.set_opti_level(OptiLevel::O2);
+    compiler.compile().unwrap();
+
+    let output = compiler.object();
+    assert!(output
+        .get_sections()
+        .iter()
+        .any(|s| s.name() == Ok(".text")));
+    let bytes = compiler.emit_object();
+    let file = mirage_backend_output::object::File::parse(&*bytes).unwrap();
+    let symbol = |name: &str| file.symbols().find(|s| s.name() == Ok(name)).unwrap();
+    assert!(symbol("main").is_global());
+    assert!(symbol("helper").is_local());
+    assert!(symbol("printf").is_undefined());
+
+    let dir = scratch_dir("object");
+    let (o, exe) = (dir.join("main.o"), dir.join("main"));
+    compiler.write_to(&o).unwrap();
+    let out = Process::new("cc")
+        .arg(&o)
+        .arg("-o")
+        .arg(&exe)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let out = Process::new(&exe).output().unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "42\n");
+
+    // The JIT resolves `printf` in the process
+    assert_eq!(run_main(&mut compiler), 3);
+}
+
+#[test]
+fn test_targets() {
+    let stmts = vec![counter_loop()];
+    for triple in [
+        "x86_64-unknown-linux-gnu",
+        "aarch64-unknown-linux-gnu",
+        "riscv64gc-unknown-linux-gnu",
+    ] {
+        let mut compiler = Compiler::new(stmts.clone());
+        compiler.set_target(triple);
+        compiler.compile().unwrap();
+        let bytes = compiler.emit_object();
+        let file = mirage_backend_output::object::File::parse(&*bytes).unwrap();
+        assert!(file.symbols().any(|s| s.name() == Ok("count")));
+    }
+
+    let mut compiler = Compiler::new(stmts);
+    compiler.set_target("pdp11");
+    assert!(matches!(compiler.compile(), Err(CompilerError::Target(_))));
+}
+
+#[test]
+fn test_labels() {
+    let mut compiler = Compiler::new(vec![
+        function(
+            "f",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![cmd(Command::Jeq(
+                    "missing".to_string(),
+                    val(&arg(0, i32_ty())),
+                    i32_val(0),
+                ))],
+            )],
+        ),
+        function("empty", vec![], vec![]),
+    ]);
+    assert_eq!(
+        compiler.compile(),
+        Err(CompilerError::UnknownLabel("missing".to_string()))
+    );
+
+    let mut session = jit(vec![
+        function(
+            "f",
+            vec![i32_ty()],
+            vec![
+                label(
+                    "entry",
+                    vec![cmd(Command::Jeq(
+                        "end".to_string(),
+                        val(&arg(0, i32_ty())),
+                        i32_val(0),
+                    ))],
+                ),
+                label("end", vec![cmd(Command::Ret(i32_val(1)))]),
+            ],
+        ),
+        function("empty", vec![], vec![]),
+    ]);
+    let f: extern "C" fn(i32) -> i32 = unsafe { session.get_function("f") }.unwrap();
+    assert_eq!(f(0), 1);
+    assert_eq!(f(5), 1);
+    assert!(session.contains_function("empty"));
+}
@@ -0,0 +1,94 @@
+use crate::Compiler;
+use mirage_backend_llvm::execution_engine::ExecutionEngine;
+use mirage_backend_output::jit::{JitError, JitResult, JitSignature};
+use mirage_backend_output::ExecutionEngineOutput;
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::statements::Statement;
+use std::collections::HashMap;
+
+/// A JIT session over a compiled module.
+///
+/// The session owns its execution engine and a copy of the module, so it
+/// stays valid independently of the `Compiler` it was created from.
+/// Host functions for `extern`s must be registered before the first
+/// `get_function`, which emits the code.
+pub struct JitSession {
+    engine: ExecutionEngine,
+    functions: HashMap<String, FunctionType>,
+    externs: HashMap<String, FunctionType>,
+    finalized: bool,
+}
+
+impl JitSession {
+    /// Create a new session
+    /// # Arguments
+    /// * `compiler` - A compiler on which `compile` has been called
+    pub fn new(compiler: &Compiler) -> Self {
+        let mut functions = HashMap::new();
+        let mut externs = HashMap::new();
+        for stmt in &compiler.stmts {
+            match stmt {
+                Statement::Function(f) => {
+                    functions.insert(f.get_name().clone(), f.get_type().clone());
+                }
+                Statement::External(e) => {
+                    externs.insert(e.name.clone(), e.ty.clone());
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            engine: ExecutionEngine::new_with_module(&compiler.module.clone_module()),
+            functions,
+            externs,
+            finalized: false,
+        }
+    }
+
+    /// Whether the module defines a function named `name`
+    pub fn contains_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// The Mirage type of the function named `name`
+    pub fn function_type(&self, name: &str) -> Option<&FunctionType> {
+        self.functions.get(name)
+    }
+}
+
+impl ExecutionEngineOutput for JitSession {
+    unsafe fn get_function<T: JitSignature>(&mut self, name: &str) -> JitResult<T> {
+        let ty = self
+            .functions
+            .get(name)
+            .ok_or_else(|| JitError::UnknownFunction(name.to_string()))?;
+        T::check(name, ty)?;
+
+        self.finalized = true;
+        let address = self
+            .engine
+            .try_get_function_address(name)
+            .ok_or_else(|| JitError::UnknownFunction(name.to_string()))?;
+
+        Ok(unsafe { T::from_address(address) })
+    }
+
+    fn register_symbol<T: JitSignature>(&mut self, name: &str, f: T) -> JitResult<()> {
+        let ty = self
+            .externs
+            .get(name)
+            .ok_or_else(|| JitError::UnknownExtern(name.to_string()))?;
+        T::check(name, ty)?;
+        if self.finalized {
+            return Err(JitError::AlreadyFinalized(name.to_string()));
+        }
+
+        let function = self
+            .engine
+            .find_function(name)
+            .ok_or_else(|| JitError::UnknownExtern(name.to_string()))?;
+        self.engine.add_global_mapping(&function, f.address());
+        Ok(())
+    }
+}
@@ -1,25 +1,43 @@
-mod string;
+#[cfg(test)]
+mod test;
 
+mod jit;
+mod orc;
+
+pub use jit::JitSession;
+pub use orc::OrcSession;
+
+use mirage_backend_llvm::attribute::Attribute;
+use mirage_backend_llvm::basic_block::BasicBlock;
 use mirage_backend_llvm::builder::{Builder, MathOpType};
 use mirage_backend_llvm::context::Context;
-use mirage_backend_llvm::execution_engine::ExecutionEngine;
 use mirage_backend_llvm::module::Module;
+use mirage_backend_llvm::pass_manager::PassManager;
 use mirage_backend_llvm::target::{
-    CodeGenFileType, CodeModel, OptimizationLevel, RelocMode, Target,
+    CodeGenFileType, CodeModel, OptimizationLevel, RelocMode, Target, TargetMachine,
 };
+use mirage_backend_opti::analysis::call_graph::CallGraph;
+use mirage_backend_opti::analysis::ssa::SsaRegisters;
+use mirage_backend_opti::analysis::AnalysisManager;
+use mirage_backend_opti::OptiLevel;
+use mirage_backend_llvm::types::function_types::FunctionType as LLVMFunctionType;
 use mirage_backend_llvm::types::struct_type::StructType;
 use mirage_backend_llvm::types::{Type, TypeBuilder, TypeEnum};
 use mirage_backend_llvm::value::function_value::FunctionValue as LLVMFunctionValue;
-use mirage_backend_llvm::value::ValueEnum;
+use mirage_backend_llvm::value::{Linkage, ValueEnum};
 use mirage_backend_output::{CompilerOutput, ExecutionEngineOutput, ObjectOutput};
 use mirage_frontend::object::function::FunctionValue;
 use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
 use mirage_frontend::object::meta::Flag;
-use mirage_frontend::object::statements::{External, Statement, TypeDef};
+use mirage_frontend::object::statements::{
+    Arch, Compiler as Toolchain, External, Os, Statement, TargetType, TypeDef,
+};
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::object::util::to_string_with_special_char;
 use mirage_frontend::object::{
     MirageObject, MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::sync::Arc;
@@ -28,16 +46,32 @@ use std::sync::Arc;
 /// # Variants
 /// * `InvalidStatement` - Invalid statement
 /// * `ModuleDeclMissing` - Module declaration missing
-/// * `TargetMissing` - Target missing
+/// * `TargetMissing` - LLVM has no backend for the target
+/// * `PassPipeline` - The LLVM pass pipeline failed to parse or run
+/// * `Unsupported` - The command can't be lowered to LLVM yet
 #[derive(Debug)]
 pub enum CompilerError {
     InvalidStatement,
     ModuleDeclMissing,
     TargetMissing,
+    PassPipeline(String),
+    Unsupported(String),
 }
 
 type CompilerResult<T> = Result<T, CompilerError>;
 
+/// How the globals of a module are emitted when it is split into several
+/// LLVM modules, as the lazy ORC session does
+#[derive(Debug, Clone, PartialEq)]
+enum Globals {
+    /// Defined in the module, private to it
+    Private,
+    /// Defined in the module as `<prefix>.<name>`, visible to the others
+    Exported(String),
+    /// Only declared, and defined by the module exporting them with `prefix`
+    Imported(String),
+}
+
 /// The LLVM Compiler struct
 #[derive(Debug, Clone)]
 pub struct Compiler {
@@ -46,13 +80,51 @@ pub struct Compiler {
     builder: Builder,
     stmts: Vec<Statement>,
     env: HashMap<RegisterValue, ValueEnum>,
+    /// The registers of the current function which are kept as plain
+    /// values instead of stack slots
+    ssa: HashSet<(RegisterType, usize)>,
+    values: HashMap<(RegisterType, usize), ValueEnum>,
     fn_env: HashMap<String, LLVMFunctionValue>,
+    /// The function being compiled, its block for each label and the
+    /// phis opening each label
+    function: Option<LLVMFunctionValue>,
+    blocks: HashMap<String, BasicBlock>,
+    phis: HashMap<String, Vec<(RegisterValue, ValueEnum, Vec<(String, Value)>)>>,
+    /// The label being compiled
+    label: String,
     struct_env: HashMap<String, StructType>,
     index_g: usize,
     no_store: bool,
     debug: bool,
     no_load: bool,
     is_argument: bool,
+    opti_level: OptiLevel,
+    passes: Vec<String>,
+    globals: Globals,
+    /// The LLVM triple to compile for, the host's by default
+    triple: Option<String>,
+}
+
+/// The LLVM triple of a Mirage target
+fn llvm_triple(target: &TargetType) -> String {
+    let arch = match target.arch {
+        Arch::X86 => "i686",
+        Arch::X86_64 => "x86_64",
+        Arch::Arm => "arm",
+        Arch::Arm64 => "aarch64",
+        Arch::RiscV64 => "riscv64",
+        Arch::Unknown => "unknown",
+    };
+    let system = match target.os {
+        Os::Linux => "unknown-linux-gnu",
+        Os::Android => "unknown-linux-android",
+        Os::Windows if target.compiler == Toolchain::Gcc => "pc-windows-gnu",
+        Os::Windows => "pc-windows-msvc",
+        Os::MacOs => "apple-macosx",
+        Os::Ios => "apple-ios",
+        Os::Unknown => "unknown-unknown",
+    };
+    format!("{}-{}", arch, system)
 }
 
 impl Compiler {
@@ -75,7 +147,7 @@ impl Compiler {
             MirageTypeEnum::Int64(_) | MirageTypeEnum::UInt64(_) => {
                 self.context.i64_type().to_type_enum()
             }
-            MirageTypeEnum::Float32(_) => self.context.float_type().to_type_enum(),
+            MirageTypeEnum::Float32(_) => self.context.f32_type().to_type_enum(),
             MirageTypeEnum::Float64(_) => self.context.float_type().to_type_enum(),
             MirageTypeEnum::Array(t) => {
                 let ta: MirageTypeEnum = t.clone().into();
@@ -125,7 +197,15 @@ impl Compiler {
 
     /// Create a new compiler
     pub fn new(stmts: Vec<Statement>, debug: bool) -> CompilerResult<Self> {
-        let context = Context::create();
+        Self::new_in_context(stmts, debug, Context::create())
+    }
+
+    /// Create a new compiler whose module lives in `context`
+    pub fn new_in_context(
+        stmts: Vec<Statement>,
+        debug: bool,
+        context: Context,
+    ) -> CompilerResult<Self> {
         if stmts.is_empty() {
             return Err(CompilerError::ModuleDeclMissing);
         }
@@ -144,27 +224,146 @@ impl Compiler {
             builder,
             stmts,
             env: HashMap::new(),
+            ssa: HashSet::new(),
+            values: HashMap::new(),
             struct_env: HashMap::new(),
             fn_env: HashMap::new(),
+            function: None,
+            blocks: HashMap::new(),
+            phis: HashMap::new(),
+            label: String::new(),
             index_g: 0,
             no_store: false,
             debug,
             no_load: false,
             is_argument: false,
+            opti_level: OptiLevel::O0,
+            passes: Vec::new(),
+            globals: Globals::Private,
+            triple: None,
         })
     }
 
-    /// Compile the module
-    pub fn compile(&mut self) {
+    /// Set the optimization level used for the LLVM pipeline and the target machine
+    pub fn set_opti_level(&mut self, level: OptiLevel) {
+        self.opti_level = level;
+    }
+
+    pub fn get_opti_level(&self) -> OptiLevel {
+        self.opti_level
+    }
+
+    /// Add a custom pass, in `opt -passes=...` syntax, run after the level's pipeline
+    pub fn add_pass(&mut self, pass: &str) {
+        self.passes.push(pass.to_string());
+    }
+
+    /// Compile for `target` instead of the host
+    pub fn set_target(&mut self, target: &TargetType) {
+        self.triple = Some(llvm_triple(target));
+    }
+
+    /// Create a target machine for the target at the chosen optimization level
+    pub fn target_machine(&self) -> CompilerResult<TargetMachine> {
+        Target::init();
+        let level = match self.opti_level {
+            OptiLevel::O0 => OptimizationLevel::None,
+            OptiLevel::O1 => OptimizationLevel::Less,
+            OptiLevel::O2 | OptiLevel::Os | OptiLevel::Oz => OptimizationLevel::Default,
+            OptiLevel::O3 => OptimizationLevel::Aggressive,
+        };
+        let triple = self
+            .triple
+            .clone()
+            .unwrap_or_else(Target::get_default_target_triple);
+        let target = Target::from_triple(&triple).map_err(|_| CompilerError::TargetMissing)?;
+        Ok(target.create_target_machine(
+            &triple,
+            "generic",
+            "",
+            level,
+            RelocMode::Default,
+            CodeModel::Default,
+        ))
+    }
+
+    /// Compile the module, then run the LLVM pass pipeline
+    pub fn compile(&mut self) -> CompilerResult<()> {
         for stmt in self.stmts.clone().iter() {
-            self.compile_stmt(stmt);
+            self.compile_stmt(stmt)?;
         }
+        self.add_function_attributes();
+        self.run_passes()
     }
 
-    fn compile_stmt(&mut self, stmt: &Statement) {
+    /// Emit what the call graph infers about every function as LLVM
+    /// attributes: `memory(none)` or `memory(read)`, `nofree` and
+    /// `norecurse`
+    fn add_function_attributes(&self) {
+        let graph = CallGraph::new(&self.stmts);
+        for stmt in &self.stmts {
+            let (name, defined) = match stmt {
+                Statement::Function(f) => (f.get_name(), true),
+                Statement::External(e) => (&e.name, false),
+                _ => continue,
+            };
+            let Some(f) = self.fn_env.get(name) else {
+                continue;
+            };
+            let effects = graph.effects(name);
+            let mut attributes = Vec::new();
+            // The access of the argument, inaccessible and other memory,
+            // 2 bits each
+            if effects.is_pure() {
+                attributes.push(("memory", 0));
+            } else if effects.is_readonly() {
+                attributes.push(("memory", 0b01_01_01));
+            }
+            if !effects.may_free() {
+                attributes.push(("nofree", 0));
+            }
+            if defined && !graph.is_recursive(name) && !graph.calls_unknown(name) {
+                attributes.push(("norecurse", 0));
+            }
+            for (kind, value) in attributes {
+                f.add_attribute(
+                    self.context
+                        .create_enum_attribute(Attribute::kind_for_name(kind), value),
+                );
+            }
+        }
+    }
+
+    /// Run the `default<level>` pipeline and the custom passes over the module.
+    /// Nothing is run at `O0` without custom passes, but the triple of a
+    /// target set with `set_target` is still recorded in the module.
+    pub fn run_passes(&self) -> CompilerResult<()> {
+        let mut pm = if self.opti_level == OptiLevel::O0 {
+            PassManager::create()
+        } else {
+            PassManager::default_pipeline(self.opti_level.as_str())
+        };
+        for pass in &self.passes {
+            pm.add_pass(pass);
+        }
+        if pm.is_empty() && self.triple.is_none() {
+            return Ok(());
+        }
+
+        let tm = self.target_machine()?;
+        self.module.set_target_triple(&tm.get_target_triple());
+        self.module.set_target_data(&tm.create_data_layout());
+        if pm.is_empty() {
+            return Ok(());
+        }
+        pm.run(&self.module, Some(&tm))
+            .map_err(CompilerError::PassPipeline)
+    }
+
+    fn compile_stmt(&mut self, stmt: &Statement) -> CompilerResult<()> {
         match stmt.clone() {
             Statement::Function(f) => {
-                self.compile_function(f);
+                self.compile_function(f)?;
             }
             Statement::External(e) => {
                 self.compile_external(e);
@@ -178,14 +377,29 @@ impl Compiler {
                     panic!("Global value who arent string doesn't work right now")
                 }
                 let s = global.value.get_value().try_to_rust_string().unwrap();
-                let s = string::to_string_with_special_char(&s);
-                let s = self.builder.build_global_string(&global.name, &s);
+                let s = to_string_with_special_char(&s);
+                let s = match &self.globals {
+                    Globals::Private => self.builder.build_global_string(&global.name, &s),
+                    Globals::Exported(prefix) => {
+                        let name = format!("{}.{}", prefix, global.name);
+                        let s = self.builder.build_global_string(&name, &s);
+                        s.as_raw().set_linkage(Linkage::External);
+                        s
+                    }
+                    Globals::Imported(prefix) => {
+                        // The string and its NUL terminator
+                        let ty = self.context.i8_type().array(s.len() as u64 + 1);
+                        let name = format!("{}.{}", prefix, global.name);
+                        self.module.add_global(ty.to_type_enum(), &name)
+                    }
+                };
                 let reg = RegisterValue::new(self.index_g, RegisterType::Global, obj.get_type());
                 self.index_g += 1;
                 self.env.insert(reg, s);
             }
             _ => {}
         }
+        Ok(())
     }
 
     fn compile_typedef(&mut self, t: TypeDef) {
@@ -212,7 +426,7 @@ impl Compiler {
         self.fn_env.insert(external.name, f);
     }
 
-    fn compile_function(&mut self, func: FunctionValue) {
+    fn compile_function(&mut self, func: FunctionValue) -> CompilerResult<()> {
         let args_ty: Vec<_> = func
             .get_type()
             .get_args()
@@ -226,11 +440,47 @@ impl Compiler {
         let fn_value = self.module.add_function(func.get_name(), fn_ty);
 
         self.fn_env.insert(func.get_name().clone(), fn_value);
-        let mut is_first = true;
-        for label in func.get_labels() {
-            let bb = self.context.append_basic_block(&label.name, fn_value);
+        self.ssa = AnalysisManager::new()
+            .get::<SsaRegisters>(&func)
+            .iter()
+            .copied()
+            .collect();
+        self.values.clear();
+        self.function = Some(fn_value);
+
+        // Every block exists before the first branch, and every phi before
+        // the first edge adds its incoming value
+        let labels = func.get_labels();
+        self.blocks = labels
+            .iter()
+            .map(|label| {
+                let bb = self.context.append_basic_block(&label.name, fn_value);
+                (label.name.clone(), bb)
+            })
+            .collect();
+        self.phis.clear();
+        for label in labels {
+            self.builder.position_at_end(self.blocks[&label.name]);
+            let mut phis = Vec::new();
+            for instr in &label.body {
+                let LabelBodyInstr::Assign(r, value) = instr else {
+                    break;
+                };
+                let LabelBodyInstr::Command(Command::Phi(incoming)) = value.as_ref() else {
+                    break;
+                };
+                let ty = self.mirage_ty_to_llvm_ty(r.get_type());
+                let phi = self.builder.build_phi(ty, "");
+                phis.push((r.clone(), phi, incoming.clone()));
+            }
+            self.phis.insert(label.name.clone(), phis);
+        }
+
+        for (i, label) in labels.iter().enumerate() {
+            let bb = self.blocks[&label.name];
             self.builder.position_at_end(bb);
-            if is_first {
+            self.label = label.name.clone();
+            if i == 0 {
                 for (i, (llvm_arg, mirage_arg)) in fn_value
                     .get_all_params()
                     .iter()
@@ -248,33 +498,108 @@ impl Compiler {
                     self.builder.build_store(*llvm_arg, ptr);
                     self.env.insert(reg, ptr.to_value_enum());
                 }
-                is_first = false
             }
-            for stmt in &label.body {
-                self.compile_instr(bb, stmt);
+            let phis = self.phis[&label.name].clone();
+            for (r, phi, _) in &phis {
+                self.assign(r, *phi);
+            }
+            for instr in &label.body[phis.len()..] {
+                // The instructions after a branch are only reached by the
+                // branches to the following label
+                if self.is_terminated() {
+                    let next = self.continuation_block();
+                    self.builder.position_at_end(next);
+                }
+                self.compile_instr(bb, instr)?;
+            }
+            if !self.is_terminated() {
+                match labels.get(i + 1) {
+                    Some(next) => self.build_jump(&next.name)?,
+                    // Running past the last label is a missing return
+                    None => self.builder.build_unreachable(),
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Keep `val` in `r`, as a plain value or in a stack slot
+    fn assign(&mut self, r: &RegisterValue, val: ValueEnum) {
+        if self.is_ssa(r) {
+            self.values.insert((r.register_type, r.index), val);
+            return;
+        }
+        let ty = self.mirage_ty_to_llvm_ty(r.get_type());
+        let ptr = self.builder.build_alloca(ty, "");
+        self.builder.build_store(val, ptr);
+        self.env.insert(r.clone(), ptr.to_value_enum());
+    }
+
+    /// Whether the block being filled already ends with a branch or a return
+    fn is_terminated(&self) -> bool {
+        self.builder
+            .get_insert_block()
+            .is_some_and(|bb| bb.get_terminator().is_some())
+    }
+
+    /// A new block for the code following a branch in the middle of a
+    /// label, placed before the block of the next label
+    fn continuation_block(&self) -> BasicBlock {
+        let next = self
+            .builder
+            .get_insert_block()
+            .and_then(|bb| bb.get_next_basic_block());
+        match next {
+            Some(next) => self.context.insert_basic_block(next, ""),
+            None => self
+                .context
+                .append_basic_block("", self.function.expect("no function is compiled")),
+        }
+    }
+
+    /// The block of `label`, after adding the values its phis take when
+    /// coming from the block being filled
+    fn edge_to(&mut self, label: &str) -> CompilerResult<BasicBlock> {
+        let Some(&target) = self.blocks.get(label) else {
+            return Err(CompilerError::Unsupported(format!("jump {}", label)));
+        };
+        let from = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned in a block");
+        for (r, phi, incoming) in self.phis[label].clone() {
+            let value = match incoming.iter().find(|(pred, _)| *pred == self.label) {
+                Some((_, value)) => self.compile_value(value),
+                None => self.mirage_ty_to_llvm_ty(r.get_type()).get_undef(),
+            };
+            self.builder.add_incoming(phi, &[(value, from)]);
+        }
+        Ok(target)
+    }
+
+    fn build_jump(&mut self, label: &str) -> CompilerResult<()> {
+        let target = self.edge_to(label)?;
+        self.builder.build_br(target);
+        Ok(())
     }
 
     fn compile_instr(
         &mut self,
-        bb: mirage_backend_llvm::basic_block::BasicBlock,
+        bb: BasicBlock,
         instr: &LabelBodyInstr,
-    ) -> Option<ValueEnum> {
-        match instr {
-            LabelBodyInstr::Command(c) => self.compile_command(c.clone()),
+    ) -> CompilerResult<Option<ValueEnum>> {
+        Ok(match instr {
+            LabelBodyInstr::Command(c) => self.compile_command(c.clone())?,
             LabelBodyInstr::Assign(r, value) => {
-                let val = self.compile_instr(bb, value)?;
+                let Some(val) = self.compile_instr(bb, value)? else {
+                    return Ok(None);
+                };
                 if self.no_store {
                     self.no_store = false;
                     self.env.insert(r.clone(), val);
-                    return Some(val);
+                    return Ok(Some(val));
                 }
-                let ty = self.mirage_ty_to_llvm_ty(r.get_type());
-                let ptr = self.builder.build_alloca(ty, "");
-                self.builder.build_store(val, ptr);
-
-                self.env.insert(r.clone(), ptr.to_value_enum());
+                self.assign(r, val);
                 None
             }
             LabelBodyInstr::Call(f, args) => {
@@ -288,15 +613,25 @@ impl Compiler {
                     .collect();
                 self.builder.build_call(fn_value, &args, "")
             }
-            _ => None,
-        }
+        })
     }
 
-    fn compile_command(&mut self, cmd: Command) -> Option<ValueEnum> {
-        match cmd {
+    fn compile_command(&mut self, cmd: Command) -> CompilerResult<Option<ValueEnum>> {
+        Ok(match cmd {
             Command::New(s, args) => {
                 let struct_ty = *self.struct_env.get(&s).unwrap();
-                let ptr = self.builder.build_alloca(struct_ty.to_type_enum(), "");
+                // The struct lives until it is freed
+                let ptr_ty = self.context.i8_type().ptr().to_type_enum();
+                let malloc = self.libc_function(
+                    "malloc",
+                    ptr_ty.func(vec![self.context.i64_type().to_type_enum()], false),
+                );
+                let size = struct_ty.to_type_enum().size_of();
+                let ptr = self
+                    .builder
+                    .build_call(malloc, &[size], "")
+                    .expect("malloc returns a pointer")
+                    .into_ptr_value();
 
                 for (i, arg) in args.iter().enumerate() {
                     let val = self.compile_value(arg);
@@ -309,8 +644,25 @@ impl Compiler {
                     self.builder.build_store(val, gep);
                 }
 
+                self.no_store = true;
                 Some(ptr.to_value_enum())
             }
+            Command::Free(regs) => {
+                let ptr_ty = self.context.i8_type().ptr().to_type_enum();
+                let free =
+                    self.libc_function("free", self.context.void_type().func(vec![ptr_ty], false));
+                for reg in regs {
+                    let value = self.compile_register_value(reg.clone());
+                    if !matches!(value, ValueEnum::PointerValue(_)) {
+                        return Err(CompilerError::Unsupported(format!(
+                            "free {}",
+                            reg.print_to_string()
+                        )));
+                    }
+                    self.builder.build_call(free, &[value], "");
+                }
+                None
+            }
 
             Command::Store(r, v) => {
                 let r = self.compile_register_value(r);
@@ -412,7 +764,7 @@ impl Compiler {
                 let v = self.compile_register_value(v).into_float_value();
                 Some(
                     self.builder
-                        .build_float_add(v, self.context.float_type().float(1.0), ""),
+                        .build_float_add(v, self.context.f32_type().float(1.0), ""),
                 )
             }
             Command::IncrFloat64(v) => {
@@ -452,8 +804,27 @@ impl Compiler {
                         .build_get_element_ptr(ty, ptr.into_ptr_value(), &indices, ""),
                 )
             }
-            _ => todo!(),
-        }
+            Command::Jump(label) => {
+                self.build_jump(&label)?;
+                None
+            }
+            Command::Jeq(label, v1, v2) => {
+                let is_float = v1.get_type().is_float();
+                let v1 = self.compile_value(&v1);
+                let v2 = self.compile_value(&v2);
+                let cond = if is_float {
+                    self.builder.build_float_eq(v1, v2, "")
+                } else {
+                    self.builder.build_int_eq(v1, v2, "")
+                };
+                let target = self.edge_to(&label)?;
+                let next = self.continuation_block();
+                self.builder.build_cond_br(cond, target, next);
+                self.builder.position_at_end(next);
+                None
+            }
+            other => return Err(CompilerError::Unsupported(other.to_string())),
+        })
     }
 
     fn compile_value(&mut self, val: &Value) -> ValueEnum {
@@ -508,7 +879,7 @@ impl Compiler {
                 .to_value_enum(),
             MirageValueEnum::Float32(v) => self
                 .context
-                .float_type()
+                .f32_type()
                 .float(v.value as f64)
                 .to_value_enum(),
             MirageValueEnum::Float64(v) => self.context.float_type().float(v.value).to_value_enum(),
@@ -546,7 +917,30 @@ impl Compiler {
         }
     }
 
+    /// The C library function `name`, declared as `ty` unless the module
+    /// already declares it
+    fn libc_function(&mut self, name: &str, ty: LLVMFunctionType) -> LLVMFunctionValue {
+        if let Some(f) = self.fn_env.get(name) {
+            return *f;
+        }
+        let f = self.module.add_function(name, ty);
+        self.fn_env.insert(name.to_string(), f);
+        f
+    }
+
+    /// Whether `reg` can skip its stack slot: it is assigned once and its
+    /// value is used directly rather than through a pointer
+    fn is_ssa(&self, reg: &RegisterValue) -> bool {
+        self.ssa.contains(&(reg.register_type, reg.index))
+            && !reg.ty.is_string()
+            && !reg.contains_flag(&Flag::not_loadable())
+            && !matches!(reg.ty, MirageTypeEnum::Struct(_))
+    }
+
     fn compile_register_value(&mut self, val: RegisterValue) -> ValueEnum {
+        if let Some(value) = self.values.get(&(val.register_type, val.index)) {
+            return *value;
+        }
         let ty = self.mirage_ty_to_llvm_ty(val.get_type());
         let ptr = self
             .env
@@ -578,14 +972,6 @@ impl CompilerOutput for Compiler {
     }
 
     fn execution_engine(&mut self) -> impl ExecutionEngineOutput {
-        self.clone()
-    }
-}
-
-impl ExecutionEngineOutput for Compiler {
-    fn get_function<T: Copy + Sized>(&mut self, name: &str) -> T {
-        let execution_engine = ExecutionEngine::new_with_module(&self.module);
-
-        execution_engine.get_function(name)
+        JitSession::new(self)
     }
 }
@@ -0,0 +1,350 @@
+use crate::{Compiler, Globals};
+use mirage_backend_llvm::orc::{JitDylib, LLJit, OrcError, ResourceTracker, ThreadSafeContext};
+use mirage_backend_output::jit::{JitError, JitResult, JitSignature};
+use mirage_backend_output::ExecutionEngineOutput;
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::statements::{External, ModuleDecl, Statement};
+use std::collections::HashMap;
+
+impl From<OrcError> for JitError {
+    fn from(err: OrcError) -> Self {
+        JitError::Backend(err.0)
+    }
+}
+
+struct OrcModule {
+    tracker: ResourceTracker,
+    dylib: JitDylib,
+    functions: Vec<String>,
+    externs: Vec<String>,
+}
+
+/// An incremental JIT session on top of ORC `LLJIT`.
+///
+/// Mirage modules are added, removed and replaced by their module name.
+/// A module can call functions of previously added modules of the same
+/// dylib by declaring them as `extern`. Functions and externs are looked up
+/// by dylib and name, so each dylib can define the same names.
+pub struct OrcSession {
+    // Trackers must be released before the JIT is disposed, so they are
+    // declared (and dropped) first.
+    modules: HashMap<String, OrcModule>,
+    symbols: Vec<ResourceTracker>,
+    functions: HashMap<(JitDylib, String), FunctionType>,
+    externs: HashMap<(JitDylib, String), FunctionType>,
+    jit: LLJit,
+    context: ThreadSafeContext,
+    debug: bool,
+}
+
+impl OrcSession {
+    pub fn new(debug: bool) -> JitResult<Self> {
+        Ok(Self {
+            modules: HashMap::new(),
+            symbols: Vec::new(),
+            functions: HashMap::new(),
+            externs: HashMap::new(),
+            jit: LLJit::new()?,
+            context: ThreadSafeContext::new(),
+            debug,
+        })
+    }
+
+    /// The dylib modules are added to by default
+    pub fn main_dylib(&self) -> JitDylib {
+        self.jit.main_dylib()
+    }
+
+    /// Create a new dylib. Its symbols are isolated from the other dylibs.
+    pub fn create_dylib(&mut self, name: &str) -> JitResult<JitDylib> {
+        Ok(self.jit.create_dylib(name)?)
+    }
+
+    pub fn get_dylib(&self, name: &str) -> Option<JitDylib> {
+        self.jit.get_dylib(name)
+    }
+
+    pub fn contains_module(&self, name: &str) -> bool {
+        self.modules.contains_key(name)
+    }
+
+    fn compile(&self, stmts: Vec<Statement>, globals: Globals) -> JitResult<Compiler> {
+        let mut compiler =
+            Compiler::new_in_context(stmts, self.debug, self.context.get_context())
+                .map_err(|e| JitError::Backend(format!("{:?}", e)))?;
+        compiler.globals = globals;
+        compiler
+            .compile()
+            .map_err(|e| JitError::Backend(format!("{:?}", e)))?;
+        compiler.module.set_target_triple(&self.jit.get_triple());
+        compiler
+            .module
+            .set_data_layout_str(&self.jit.get_data_layout_str());
+        Ok(compiler)
+    }
+
+    /// Add a module to `dylib`. It is compiled when one of its functions
+    /// is first looked up.
+    /// # Arguments
+    /// * `dylib` - The dylib which will hold the module's symbols
+    /// * `stmts` - The module, starting with its module declaration
+    pub fn add_module(&mut self, dylib: JitDylib, stmts: Vec<Statement>) -> JitResult<()> {
+        let name = self.reserve(&stmts)?;
+        let compiler = self.compile(stmts.clone(), Globals::Private)?;
+        let tracker = self.jit.create_tracker(dylib);
+        if let Err(e) = self
+            .jit
+            .add_module(&tracker, compiler.module, &self.context)
+        {
+            let _ = tracker.remove();
+            return Err(e.into());
+        }
+        self.register(dylib, name, tracker, &stmts);
+        Ok(())
+    }
+
+    /// Add a module to `dylib`, compiling each function on its first call.
+    /// # Arguments
+    /// * `dylib` - The dylib which will hold the module's symbols
+    /// * `stmts` - The module, starting with its module declaration
+    pub fn add_module_lazy(&mut self, dylib: JitDylib, stmts: Vec<Statement>) -> JitResult<()> {
+        let name = self.reserve(&stmts)?;
+
+        // Each function goes in its own LLVM module so that calling one
+        // doesn't compile its neighbours. The globals are defined once, in
+        // a module of their own, and only declared by the others.
+        let functions = stmts
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Function(f) => Some(f.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let shared = stmts
+            .iter()
+            .skip(1)
+            .filter(|s| !matches!(s, Statement::Function(_)))
+            .cloned()
+            .collect::<Vec<_>>();
+        let (defined, globals) = if shared.iter().any(|s| matches!(s, Statement::Global(_))) {
+            let mut part = vec![Statement::Module(ModuleDecl::new(format!(
+                "{}.globals",
+                name
+            )))];
+            part.extend(
+                shared
+                    .iter()
+                    .filter(|s| !matches!(s, Statement::External(_)))
+                    .cloned(),
+            );
+            let module = self.compile(part, Globals::Exported(name.clone()))?.module;
+            (Some(module), Globals::Imported(name.clone()))
+        } else {
+            (None, Globals::Private)
+        };
+        let mut parts = Vec::new();
+        for f in &functions {
+            let mut part = vec![Statement::Module(ModuleDecl::new(format!(
+                "{}.{}",
+                name,
+                f.get_name()
+            )))];
+            part.extend(shared.iter().cloned());
+            part.extend(
+                functions
+                    .iter()
+                    .filter(|other| other.get_name() != f.get_name())
+                    .map(|other| {
+                        Statement::External(External::new(
+                            other.get_name().clone(),
+                            other.get_type().clone(),
+                        ))
+                    }),
+            );
+            part.push(Statement::Function(f.clone()));
+            parts.push(self.compile(part, globals.clone())?.module);
+        }
+
+        let tracker = self.jit.create_tracker(dylib);
+        if let Some(module) = defined {
+            if let Err(e) = self.jit.add_module(&tracker, module, &self.context) {
+                let _ = tracker.remove();
+                return Err(e.into());
+            }
+        }
+        for module in parts {
+            if let Err(e) = self
+                .jit
+                .add_module_lazy(dylib, &tracker, module, &self.context)
+            {
+                let _ = tracker.remove();
+                return Err(e.into());
+            }
+        }
+        self.register(dylib, name, tracker, &stmts);
+        Ok(())
+    }
+
+    /// Remove a module. Function pointers obtained from it become dangling.
+    pub fn remove_module(&mut self, name: &str) -> JitResult<()> {
+        let module = self
+            .modules
+            .remove(name)
+            .ok_or_else(|| JitError::UnknownModule(name.to_string()))?;
+        for f in module.functions {
+            self.functions.remove(&(module.dylib, f));
+        }
+        // An extern stays known while another module of the dylib declares it
+        for e in module.externs {
+            let declared = self
+                .modules
+                .values()
+                .any(|other| other.dylib == module.dylib && other.externs.contains(&e));
+            if !declared {
+                self.externs.remove(&(module.dylib, e));
+            }
+        }
+        Ok(module.tracker.remove()?)
+    }
+
+    /// Look up a function compiled in `dylib`, checking `T` against its
+    /// Mirage type
+    /// # Safety
+    /// The returned pointer must not be called once the session is dropped,
+    /// or once its module is removed or replaced
+    pub unsafe fn get_function_in<T: JitSignature>(
+        &mut self,
+        dylib: JitDylib,
+        name: &str,
+    ) -> JitResult<T> {
+        let ty = self
+            .functions
+            .get(&(dylib, name.to_string()))
+            .ok_or_else(|| JitError::UnknownFunction(name.to_string()))?;
+        T::check(name, ty)?;
+        let address = self.jit.lookup_in(dylib, name)?;
+        Ok(unsafe { T::from_address(address) })
+    }
+
+    /// Provide the implementation of an `extern` of the modules of `dylib`
+    pub fn register_symbol_in<T: JitSignature>(
+        &mut self,
+        dylib: JitDylib,
+        name: &str,
+        f: T,
+    ) -> JitResult<()> {
+        let ty = self
+            .externs
+            .get(&(dylib, name.to_string()))
+            .ok_or_else(|| JitError::UnknownExtern(name.to_string()))?;
+        T::check(name, ty)?;
+        let tracker = self.jit.create_tracker(dylib);
+        self.jit.define_symbol(dylib, &tracker, name, f.address())?;
+        self.symbols.push(tracker);
+        Ok(())
+    }
+
+    /// The dylib holding `name`: the main dylib if it does, otherwise the
+    /// only other dylib which does
+    fn find_dylib(
+        &self,
+        symbols: &HashMap<(JitDylib, String), FunctionType>,
+        name: &str,
+    ) -> JitResult<Option<JitDylib>> {
+        let main = self.main_dylib();
+        if symbols.contains_key(&(main, name.to_string())) {
+            return Ok(Some(main));
+        }
+        let mut dylibs = symbols.keys().filter(|(_, n)| n == name).map(|(d, _)| *d);
+        match (dylibs.next(), dylibs.next()) {
+            (Some(_), Some(_)) => Err(JitError::AmbiguousSymbol(name.to_string())),
+            (dylib, _) => Ok(dylib),
+        }
+    }
+
+    /// Remove the module with the same name as `stmts`, if any, and add `stmts`
+    pub fn replace_module(
+        &mut self,
+        dylib: JitDylib,
+        stmts: Vec<Statement>,
+        lazy: bool,
+    ) -> JitResult<()> {
+        if let Some(Statement::Module(m)) = stmts.first() {
+            if self.modules.contains_key(&m.name) {
+                self.remove_module(&m.name)?;
+            }
+        }
+        if lazy {
+            self.add_module_lazy(dylib, stmts)
+        } else {
+            self.add_module(dylib, stmts)
+        }
+    }
+
+    fn reserve(&self, stmts: &[Statement]) -> JitResult<String> {
+        let name = match stmts.first() {
+            Some(Statement::Module(m)) => m.name.clone(),
+            _ => return Err(JitError::Backend("module declaration missing".to_string())),
+        };
+        if self.modules.contains_key(&name) {
+            return Err(JitError::Backend(format!(
+                "module `{}` was already added",
+                name
+            )));
+        }
+        Ok(name)
+    }
+
+    fn register(
+        &mut self,
+        dylib: JitDylib,
+        name: String,
+        tracker: ResourceTracker,
+        stmts: &[Statement],
+    ) {
+        let mut functions = Vec::new();
+        let mut externs = Vec::new();
+        for stmt in stmts {
+            match stmt {
+                Statement::Function(f) => {
+                    functions.push(f.get_name().clone());
+                    self.functions
+                        .insert((dylib, f.get_name().clone()), f.get_type().clone());
+                }
+                Statement::External(e) => {
+                    externs.push(e.name.clone());
+                    self.externs.insert((dylib, e.name.clone()), e.ty.clone());
+                }
+                _ => {}
+            }
+        }
+        self.modules.insert(
+            name,
+            OrcModule {
+                tracker,
+                dylib,
+                functions,
+                externs,
+            },
+        );
+    }
+}
+
+/// Names are looked up in the main dylib first, then in the only other
+/// dylib defining them; use `get_function_in` and `register_symbol_in` to
+/// pick the dylib
+impl ExecutionEngineOutput for OrcSession {
+    unsafe fn get_function<T: JitSignature>(&mut self, name: &str) -> JitResult<T> {
+        let dylib = self
+            .find_dylib(&self.functions, name)?
+            .ok_or_else(|| JitError::UnknownFunction(name.to_string()))?;
+        self.get_function_in(dylib, name)
+    }
+
+    fn register_symbol<T: JitSignature>(&mut self, name: &str, f: T) -> JitResult<()> {
+        let dylib = self
+            .find_dylib(&self.externs, name)?
+            .ok_or_else(|| JitError::UnknownExtern(name.to_string()))?;
+        self.register_symbol_in(dylib, name, f)
+    }
+}
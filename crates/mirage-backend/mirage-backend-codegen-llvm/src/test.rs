@@ -0,0 +1,435 @@
+use crate::{Compiler, CompilerError, JitSession, OrcSession};
+use mirage_backend_codegen_wasm::WasmSession;
+use mirage_backend_opti::OptiLevel;
+use mirage_backend_output::jit::JitError;
+use mirage_backend_output::ExecutionEngineOutput;
+use mirage_frontend::builder::Builder;
+use mirage_frontend::module::Module;
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::statements::{Statement, TargetType};
+use mirage_frontend::object::{IntValue, MirageTypeEnum};
+use mirage_frontend::parser::parse;
+
+fn binary_i32() -> FunctionType {
+    FunctionType::new(
+        vec![
+            MirageTypeEnum::type_int32().into(),
+            MirageTypeEnum::type_int32().into(),
+        ],
+        MirageTypeEnum::type_int32().into(),
+        false,
+    )
+}
+
+fn compile(builder: Builder) -> Compiler {
+    let mut compiler = Compiler::new(builder.asts, false).unwrap();
+    compiler.compile().unwrap();
+    compiler
+}
+
+fn add_module() -> Compiler {
+    compile(add_builder("test", "add"))
+}
+
+fn add_builder(module: &str, name: &str) -> Builder {
+    let mut builder = Builder::new(Module::new(module.to_string()));
+    let mut add = binary_i32().fn_value(name.to_string());
+    let mut entry = builder.new_basic_block("entry");
+    let a = add.get_nth_arg(0).unwrap();
+    let b = add.get_nth_arg(1).unwrap();
+    let r = entry
+        .build_int_add(a.expect_int_value().unwrap(), b.expect_int_value().unwrap())
+        .unwrap();
+    entry.build_ret(r).unwrap();
+    add.add_label(entry.build());
+    builder.build_function(add);
+    builder
+}
+
+#[test]
+fn test_jit_call() {
+    let mut jit = JitSession::new(&add_module());
+    let add: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("add") }.unwrap();
+    assert_eq!(add(40, 2), 42);
+}
+
+#[test]
+fn test_jit_loop_with_phi() {
+    let stmts = parse(
+        "module test;\n\
+         count(@int32) -> @int32\n\
+         entry: \n\
+         \tjump head\n\
+         head: \n\
+         \tr0 = phi [@int32 0, entry], [r1, body]\n\
+         \tjeq exit, r0, arg0\n\
+         body: \n\
+         \tr1 = incr_i32 r0\n\
+         \tjump head\n\
+         exit: \n\
+         \tret r0",
+    )
+    .unwrap();
+    let mut compiler = Compiler::new(stmts, false).unwrap();
+    compiler.compile().unwrap();
+    let mut jit = JitSession::new(&compiler);
+    let count: extern "C" fn(i32) -> i32 = unsafe { jit.get_function("count") }.unwrap();
+    assert_eq!(count(0), 0);
+    assert_eq!(count(7), 7);
+}
+
+#[test]
+fn test_unsupported_command() {
+    let stmts = parse(
+        "module test;\n\
+         f() -> @float64\n\
+         entry: \n\
+         \tr0 = sub_f64 @float64 1.5, @float64 0.5\n\
+         \tret r0",
+    )
+    .unwrap();
+    let mut compiler = Compiler::new(stmts, false).unwrap();
+    assert!(matches!(
+        compiler.compile(),
+        Err(CompilerError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_new_and_free() {
+    let stmts = parse(
+        "module test;\n\
+         type pair = {@int32,@float64};\n\
+         f() -> @int32\n\
+         entry: \n\
+         \tv0 = new pair, {@int32 4,@float64 -1.5}\n\
+         \tfree v0\n\
+         \tret @int32 4",
+    )
+    .unwrap();
+    let mut compiler = Compiler::new(stmts, false).unwrap();
+    compiler.compile().unwrap();
+    let ir = compiler.print_to_string();
+    assert!(ir.contains("call ptr @malloc"));
+    assert!(ir.contains("call void @free"));
+    let mut jit = JitSession::new(&compiler);
+    let f: extern "C" fn() -> i32 = unsafe { jit.get_function("f") }.unwrap();
+    assert_eq!(f(), 4);
+}
+
+#[test]
+fn test_jit_signature_mismatch() {
+    let mut jit = JitSession::new(&add_module());
+    let res = unsafe { jit.get_function::<extern "C" fn(i64, i32) -> i32>("add") };
+    assert!(matches!(res, Err(JitError::SignatureMismatch { .. })));
+    let res = unsafe { jit.get_function::<extern "C" fn(i32) -> i32>("add") };
+    assert!(matches!(res, Err(JitError::SignatureMismatch { .. })));
+}
+
+#[test]
+fn test_jit_unknown_function() {
+    let mut jit = JitSession::new(&add_module());
+    let res = unsafe { jit.get_function::<extern "C" fn(i32, i32) -> i32>("sub") };
+    assert_eq!(res.err(), Some(JitError::UnknownFunction("sub".to_string())));
+}
+
+extern "C" fn host_mul(a: i32, b: i32) -> i32 {
+    a * b
+}
+
+#[test]
+fn test_jit_register_symbol() {
+    let mut builder = Builder::new(Module::new("test".to_string()));
+    builder.build_extern("host_mul".to_string(), binary_i32());
+    let mut square = FunctionType::new(
+        vec![MirageTypeEnum::type_int32().into()],
+        MirageTypeEnum::type_int32().into(),
+        false,
+    )
+    .fn_value("square".to_string());
+    let mut entry = builder.new_basic_block("entry");
+    let a = square.get_nth_arg(0).unwrap();
+    let r = entry
+        .build_call("host_mul".to_string(), vec![a.clone(), a])
+        .unwrap();
+    entry.build_ret(r).unwrap();
+    square.add_label(entry.build());
+    builder.build_function(square);
+
+    let mut jit = JitSession::new(&compile(builder));
+    assert!(matches!(
+        jit.register_symbol("host_mul", host_mul as extern "C" fn(i32, i32) -> i64),
+        Err(JitError::SignatureMismatch { .. })
+    ));
+    jit.register_symbol("host_mul", host_mul as extern "C" fn(i32, i32) -> i32)
+        .unwrap();
+    let square: extern "C" fn(i32) -> i32 = unsafe { jit.get_function("square") }.unwrap();
+    assert_eq!(square(7), 49);
+    assert_eq!(
+        jit.register_symbol("host_mul", host_mul as extern "C" fn(i32, i32) -> i32),
+        Err(JitError::AlreadyFinalized("host_mul".to_string()))
+    );
+}
+
+fn call_builder(module: &str, name: &str, callee: &str) -> Builder {
+    let mut builder = Builder::new(Module::new(module.to_string()));
+    builder.build_extern(callee.to_string(), binary_i32());
+    let mut f = binary_i32().fn_value(name.to_string());
+    let mut entry = builder.new_basic_block("entry");
+    let r = entry
+        .build_call(
+            callee.to_string(),
+            vec![f.get_nth_arg(0).unwrap(), f.get_nth_arg(1).unwrap()],
+        )
+        .unwrap();
+    entry.build_ret(r).unwrap();
+    f.add_label(entry.build());
+    builder.build_function(f);
+    builder
+}
+
+#[test]
+fn test_orc_incremental_modules() {
+    let mut jit = OrcSession::new(false).unwrap();
+    let main = jit.main_dylib();
+    jit.add_module(main, add_builder("m1", "add").asts).unwrap();
+    jit.add_module(main, call_builder("m2", "call_add", "add").asts)
+        .unwrap();
+
+    let call_add: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("call_add") }.unwrap();
+    assert_eq!(call_add(1, 2), 3);
+}
+
+#[test]
+fn test_orc_lazy() {
+    let mut jit = OrcSession::new(false).unwrap();
+    let main = jit.main_dylib();
+    let mut builder = add_builder("m1", "add");
+    let call = call_builder("unused", "call_add", "add");
+    builder.asts.extend(
+        call.asts
+            .into_iter()
+            .filter(|s| matches!(s, Statement::Function(_))),
+    );
+    jit.add_module_lazy(main, builder.asts).unwrap();
+
+    let call_add: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("call_add") }.unwrap();
+    assert_eq!(call_add(20, 22), 42);
+}
+
+extern "C" fn host_addr(s: *const i8) -> i64 {
+    s as i64
+}
+
+#[test]
+fn test_orc_lazy_globals() {
+    let stmts = parse(
+        "module m1;\n\
+         extern host_addr : (@int8*) -> @int64;\n\
+         global s = \"hi\"\n\
+         first() -> @int64\n\
+         entry: \n\
+         \tr0 = host_addr { g0 }\n\
+         \tret r0\n\
+         second() -> @int64\n\
+         entry: \n\
+         \tr0 = host_addr { g0 }\n\
+         \tret r0",
+    )
+    .unwrap();
+    let mut jit = OrcSession::new(false).unwrap();
+    let main = jit.main_dylib();
+    jit.add_module_lazy(main, stmts).unwrap();
+    jit.register_symbol("host_addr", host_addr as extern "C" fn(*const i8) -> i64)
+        .unwrap();
+
+    // Both functions see the one definition of the string
+    let first: extern "C" fn() -> i64 = unsafe { jit.get_function("first") }.unwrap();
+    let second: extern "C" fn() -> i64 = unsafe { jit.get_function("second") }.unwrap();
+    assert_eq!(first(), second());
+    let s = unsafe { std::ffi::CStr::from_ptr(first() as *const std::ffi::c_char) };
+    assert_eq!(s.to_str(), Ok("hi"));
+}
+
+#[test]
+fn test_orc_remove_and_replace() {
+    let mut jit = OrcSession::new(false).unwrap();
+    let main = jit.main_dylib();
+    jit.add_module(main, add_builder("m1", "add").asts).unwrap();
+    jit.remove_module("m1").unwrap();
+    assert!(matches!(
+        unsafe { jit.get_function::<extern "C" fn(i32, i32) -> i32>("add") },
+        Err(JitError::UnknownFunction(_))
+    ));
+    assert!(matches!(
+        jit.remove_module("m1"),
+        Err(JitError::UnknownModule(_))
+    ));
+
+    jit.add_module(main, call_builder("m1", "add", "host_mul").asts)
+        .unwrap();
+    jit.register_symbol("host_mul", host_mul as extern "C" fn(i32, i32) -> i32)
+        .unwrap();
+    let mul: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("add") }.unwrap();
+    assert_eq!(mul(6, 7), 42);
+
+    jit.replace_module(main, add_builder("m1", "add").asts, true)
+        .unwrap();
+    let add: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("add") }.unwrap();
+    assert_eq!(add(6, 7), 13);
+}
+
+#[test]
+fn test_orc_dylibs() {
+    let mut jit = OrcSession::new(false).unwrap();
+    let main = jit.main_dylib();
+    let other = jit.create_dylib("other").unwrap();
+    assert_eq!(jit.get_dylib("other"), Some(other));
+
+    jit.add_module(main, add_builder("m1", "add").asts).unwrap();
+    jit.add_module(other, add_builder("m2", "add2").asts).unwrap();
+    let add2: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("add2") }.unwrap();
+    assert_eq!(add2(2, 2), 4);
+}
+
+#[test]
+fn test_orc_same_name_in_dylibs() {
+    let mut jit = OrcSession::new(false).unwrap();
+    let main = jit.main_dylib();
+    let first = jit.create_dylib("first").unwrap();
+    let second = jit.create_dylib("second").unwrap();
+
+    jit.add_module(main, add_builder("m1", "add").asts).unwrap();
+    jit.add_module(first, call_builder("m2", "add", "host_mul").asts)
+        .unwrap();
+    jit.register_symbol_in(
+        first,
+        "host_mul",
+        host_mul as extern "C" fn(i32, i32) -> i32,
+    )
+    .unwrap();
+    let add: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function_in(main, "add") }.unwrap();
+    let mul: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function_in(first, "add") }.unwrap();
+    assert_eq!(add(6, 7), 13);
+    assert_eq!(mul(6, 7), 42);
+    // The main dylib wins over the others
+    let add: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("add") }.unwrap();
+    assert_eq!(add(6, 7), 13);
+
+    // Removing a module only forgets the functions of its dylib
+    jit.remove_module("m1").unwrap();
+    assert_eq!(
+        unsafe { jit.get_function_in::<extern "C" fn(i32, i32) -> i32>(main, "add") },
+        Err(JitError::UnknownFunction("add".to_string()))
+    );
+    let mul: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("add") }.unwrap();
+    assert_eq!(mul(6, 7), 42);
+
+    jit.add_module(second, add_builder("m3", "add").asts)
+        .unwrap();
+    assert_eq!(
+        unsafe { jit.get_function::<extern "C" fn(i32, i32) -> i32>("add") },
+        Err(JitError::AmbiguousSymbol("add".to_string()))
+    );
+    jit.remove_module("m2").unwrap();
+    assert_eq!(
+        jit.register_symbol_in(
+            first,
+            "host_mul",
+            host_mul as extern "C" fn(i32, i32) -> i32
+        ),
+        Err(JitError::UnknownExtern("host_mul".to_string()))
+    );
+    let add: extern "C" fn(i32, i32) -> i32 =
+        unsafe { jit.get_function_in(second, "add") }.unwrap();
+    assert_eq!(add(6, 7), 13);
+}
+
+fn add_module_at(level: OptiLevel, passes: &[&str]) -> Compiler {
+    let mut compiler = Compiler::new(add_builder("test", "add").asts, false).unwrap();
+    compiler.set_opti_level(level);
+    for pass in passes {
+        compiler.add_pass(pass);
+    }
+    compiler.compile().unwrap();
+    compiler
+}
+
+#[test]
+fn test_pipeline_levels() {
+    let o0 = add_module_at(OptiLevel::O0, &[]).print_to_string();
+    assert!(o0.contains("alloca"));
+
+    for level in [OptiLevel::O1, OptiLevel::O2, OptiLevel::O3, OptiLevel::Os, OptiLevel::Oz] {
+        let ir = add_module_at(level, &[]).print_to_string();
+        assert!(!ir.contains("alloca"), "{}:\n{}", level.as_str(), ir);
+        assert!(ir.contains("add i32"), "{}:\n{}", level.as_str(), ir);
+    }
+}
+
+#[test]
+fn test_set_target() {
+    let mut compiler = Compiler::new(add_builder("test", "add").asts, false).unwrap();
+    compiler.set_target(&TargetType::parse("linux-arm64-gcc"));
+    compiler.compile().unwrap();
+    let ir = compiler.print_to_string();
+    assert!(ir.contains("target triple = \"aarch64-unknown-linux-gnu\""), "{}", ir);
+}
+
+#[test]
+fn test_custom_passes() {
+    let ir = add_module_at(OptiLevel::O0, &["mem2reg"]).print_to_string();
+    assert!(!ir.contains("alloca"));
+
+    let mut compiler = Compiler::new(add_builder("test", "add").asts, false).unwrap();
+    compiler.add_pass("not-a-pass");
+    assert!(matches!(
+        compiler.compile(),
+        Err(CompilerError::PassPipeline(_))
+    ));
+}
+
+#[test]
+fn test_function_attributes() {
+    let ir = add_module().print_to_string();
+    assert!(ir.contains("memory(none)"), "{}", ir);
+    assert!(ir.contains("nofree") && ir.contains("norecurse"), "{}", ir);
+}
+
+/// `add`, and `mix(a, b) = add(a, b) - 3`
+fn mix_builder() -> Builder {
+    let mut builder = add_builder("test", "add");
+    let mut mix = binary_i32().fn_value("mix".to_string());
+    let mut entry = builder.new_basic_block("entry");
+    let sum = entry
+        .build_call(
+            "add".to_string(),
+            vec![mix.get_nth_arg(0).unwrap(), mix.get_nth_arg(1).unwrap()],
+        )
+        .unwrap();
+    let r = entry
+        .build_int_sub(
+            sum.expect_int_value().unwrap(),
+            IntValue::Int32(MirageTypeEnum::type_int32().const_value(3)),
+        )
+        .unwrap();
+    entry.build_ret(r).unwrap();
+    mix.add_label(entry.build());
+    builder.build_function(mix);
+    builder
+}
+
+#[test]
+fn test_jit_and_wasm_agree() {
+    let builder = mix_builder();
+    let mut wasm = mirage_backend_codegen_wasm::Compiler::new(builder.asts.clone());
+    wasm.compile().unwrap();
+    let mut session = WasmSession::new(&wasm.emit_wasm()).unwrap();
+    let mut jit = JitSession::new(&compile(builder));
+    let add: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("add") }.unwrap();
+    let mix: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get_function("mix") }.unwrap();
+    for (a, b) in [(0, 0), (40, 2), (-7, 3), (i32::MAX, 1)] {
+        assert_eq!(session.call::<(i32, i32), i32>("add", (a, b)), Ok(add(a, b)));
+        assert_eq!(session.call::<(i32, i32), i32>("mix", (a, b)), Ok(mix(a, b)));
+    }
+}
@@ -0,0 +1,990 @@
+use std::collections::HashMap;
+
+use mirage_backend_opti::analysis::cfg::Cfg;
+use mirage_backend_opti::analysis::dominators::Dominators;
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::object::{MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue};
+use walrus::ir::{
+    BinaryOp, Block, IfElse, InstrSeqId, Loop, MemArg, StoreKind, UnaryOp, Value as WasmValue,
+};
+use walrus::{FunctionBuilder, InstrSeqBuilder, LocalId, ModuleLocals, ValType};
+
+use crate::layout::{
+    align_of, align_to, is_aggregate, load_kind, size_of, store_kind, struct_layout, val_type,
+};
+use crate::memory::StaticData;
+use crate::stackify::{stackify, Branch, Edge, Exit, Shape};
+use crate::{CompilerError, CompilerResult, Env};
+
+type RegKey = (RegisterType, usize);
+
+/// What a `br` can go to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    /// The end of the block followed by this block
+    Block(usize),
+    /// The start of the loop headed by this block
+    Loop(usize),
+    /// The start of the dispatch loop
+    Dispatch,
+}
+
+/// What a memory access is relative to
+#[derive(Clone, Copy)]
+enum Base<'r> {
+    /// The frame of the function
+    Frame,
+    /// Address 0
+    Absolute,
+    /// The address held by a register
+    Register(&'r RegisterValue),
+}
+
+/// The register `value` designates, if it is one
+fn register(value: &Value) -> Option<&RegisterValue> {
+    match value {
+        Value::Register(reg) => Some(reg),
+        Value::ConstValue(obj) => match obj.get_value_ref() {
+            MirageValueEnum::Register(reg) => Some(reg),
+            _ => None,
+        },
+        Value::List(_) => None,
+    }
+}
+
+/// The constant integer `value` holds, if it is one
+fn const_index(value: &Value) -> Option<i64> {
+    let Value::ConstValue(obj) = value else {
+        return None;
+    };
+    match obj.get_value_ref() {
+        MirageValueEnum::Int8(v) => Some(v.value as i64),
+        MirageValueEnum::Int16(v) => Some(v.value as i64),
+        MirageValueEnum::Int32(v) => Some(v.value as i64),
+        MirageValueEnum::Int64(v) => Some(v.value),
+        MirageValueEnum::UInt8(v) => Some(v.value as i64),
+        MirageValueEnum::UInt16(v) => Some(v.value as i64),
+        MirageValueEnum::UInt32(v) => Some(v.value as i64),
+        MirageValueEnum::UInt64(v) => Some(v.value as i64),
+        _ => None,
+    }
+}
+
+fn is_phi(instr: &LabelBodyInstr) -> bool {
+    matches!(instr, LabelBodyInstr::Assign(_, value)
+        if matches!(**value, LabelBodyInstr::Command(Command::Phi(_))))
+}
+
+fn eq_op(ty: ValType) -> BinaryOp {
+    match ty {
+        ValType::I64 => BinaryOp::I64Eq,
+        ValType::F32 => BinaryOp::F32Eq,
+        ValType::F64 => BinaryOp::F64Eq,
+        _ => BinaryOp::I32Eq,
+    }
+}
+
+/// Compiles the body of a function.
+///
+/// Registers are wasm locals, except the ones whose address is taken,
+/// which live in the frame of the function. The frame is carved out of the
+/// stack in linear memory, and holds the structs and arrays created by the
+/// function, which are handled through their address.
+pub struct FunctionCompiler<'a> {
+    env: &'a Env,
+    data: &'a mut StaticData,
+    locals: &'a mut ModuleLocals,
+    builder: &'a mut FunctionBuilder,
+    func: &'a FunctionValue,
+    cfg: Cfg,
+    /// The instruction sequence being emitted to
+    seq: InstrSeqId,
+    registers: HashMap<RegKey, LocalId>,
+    /// The wasm type of the registers, from their assignment
+    types: HashMap<RegKey, ValType>,
+    /// The offset in the frame of the registers whose address is taken
+    slots: HashMap<RegKey, u32>,
+    frame_size: u32,
+    frame: LocalId,
+    /// The stack pointer of the caller, restored before returning
+    saved: LocalId,
+    /// Where the stack pointer is restored
+    epilogues: Vec<(InstrSeqId, usize)>,
+    targets: Vec<(Target, InstrSeqId)>,
+    /// The local holding the next block to run, and the index of every
+    /// block in the dispatch loop
+    dispatch: Option<(LocalId, HashMap<usize, i32>)>,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    pub fn new(
+        env: &'a Env,
+        data: &'a mut StaticData,
+        locals: &'a mut ModuleLocals,
+        builder: &'a mut FunctionBuilder,
+        func: &'a FunctionValue,
+        args: &[LocalId],
+    ) -> Self {
+        let mut registers = HashMap::new();
+        let mut types = HashMap::new();
+        for (i, (local, ty)) in args.iter().zip(func.get_type().get_args()).enumerate() {
+            registers.insert((RegisterType::Argument, i), *local);
+            types.insert((RegisterType::Argument, i), val_type(ty));
+        }
+        for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+            if let LabelBodyInstr::Assign(reg, value) = instr {
+                // A `getelementptr` gives an address, whatever the type of
+                // the register
+                let ty = match **value {
+                    LabelBodyInstr::Command(Command::GetElementPtr(..)) => ValType::I32,
+                    _ => val_type(&reg.ty),
+                };
+                types.entry((reg.register_type, reg.index)).or_insert(ty);
+            }
+        }
+        let frame = locals.add(ValType::I32);
+        let saved = locals.add(ValType::I32);
+        Self {
+            env,
+            data,
+            locals,
+            seq: builder.func_body_id(),
+            builder,
+            func,
+            cfg: Cfg::new(func),
+            registers,
+            types,
+            slots: HashMap::new(),
+            frame_size: 0,
+            frame,
+            saved,
+            epilogues: Vec::new(),
+            targets: Vec::new(),
+            dispatch: None,
+        }
+    }
+
+    fn b(&mut self) -> InstrSeqBuilder<'_> {
+        self.builder.instr_seq(self.seq)
+    }
+
+    pub fn compile(mut self) -> CompilerResult<()> {
+        if self.cfg.is_empty() {
+            self.b().unreachable();
+            return Ok(());
+        }
+
+        let func = self.func;
+        for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+            let value = match instr {
+                LabelBodyInstr::Assign(_, value) => value,
+                _ => instr,
+            };
+            let LabelBodyInstr::Command(Command::Ref(value)) = value else {
+                continue;
+            };
+            let Some(reg) = register(value) else {
+                continue;
+            };
+            let key = (reg.register_type, reg.index);
+            if reg.register_type != RegisterType::Global
+                && !is_aggregate(&reg.ty)
+                && !self.slots.contains_key(&key)
+            {
+                let slot = self.alloc(size_of(&reg.ty), align_of(&reg.ty));
+                self.slots.insert(key, slot);
+            }
+        }
+        for (i, ty) in func.get_type().get_args().iter().enumerate() {
+            let Some(slot) = self.slots.get(&(RegisterType::Argument, i)).copied() else {
+                continue;
+            };
+            let local = self.registers[&(RegisterType::Argument, i)];
+            self.store(Base::Frame, slot, ty, |s| {
+                s.b().local_get(local);
+                Ok(())
+            })?;
+        }
+
+        let dom = Dominators::new(&self.cfg);
+        let shape = stackify(&self.cfg, &dom);
+        self.shape(&shape)?;
+        self.b().unreachable();
+        self.finish_frame();
+        Ok(())
+    }
+
+    /// Reserve `size` bytes in the frame and return their offset
+    fn alloc(&mut self, size: u32, align: u32) -> u32 {
+        let offset = align_to(self.frame_size, align);
+        self.frame_size = offset + size;
+        offset
+    }
+
+    /// Move the stack pointer below the frame on entry, or drop the
+    /// epilogues when there is no frame
+    fn finish_frame(&mut self) {
+        if self.frame_size == 0 {
+            for (seq, at) in self.epilogues.iter().rev() {
+                self.builder
+                    .instr_seq(*seq)
+                    .instrs_mut()
+                    .drain(*at..*at + 2);
+            }
+            return;
+        }
+        let size = align_to(self.frame_size, 16) as i32;
+        let (sp, saved, frame) = (self.env.stack_pointer, self.saved, self.frame);
+        let entry = self.builder.func_body_id();
+        let mut body = self.builder.instr_seq(entry);
+        body.instr_at(0, walrus::ir::GlobalGet { global: sp })
+            .instr_at(1, walrus::ir::LocalTee { local: saved })
+            .instr_at(
+                2,
+                walrus::ir::Const {
+                    value: WasmValue::I32(size),
+                },
+            )
+            .instr_at(
+                3,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32Sub,
+                },
+            )
+            .instr_at(4, walrus::ir::LocalTee { local: frame })
+            .instr_at(5, walrus::ir::GlobalSet { global: sp });
+    }
+
+    /// Emit the instructions `f` emits in a new sequence, which `br`
+    /// reaches as `target`
+    fn nested(
+        &mut self,
+        target: Option<Target>,
+        f: impl FnOnce(&mut Self) -> CompilerResult<()>,
+    ) -> CompilerResult<InstrSeqId> {
+        let seq = self.builder.dangling_instr_seq(None).id();
+        let outer = std::mem::replace(&mut self.seq, seq);
+        if let Some(target) = target {
+            self.targets.push((target, seq));
+        }
+        let res = f(self);
+        if target.is_some() {
+            self.targets.pop();
+        }
+        self.seq = outer;
+        res.map(|_| seq)
+    }
+
+    fn target(&self, target: Target) -> InstrSeqId {
+        self.targets
+            .iter()
+            .rev()
+            .find(|(t, _)| *t == target)
+            .map(|(_, seq)| *seq)
+            .expect("the stackifier nests every branch in its target")
+    }
+
+    fn shape(&mut self, shape: &Shape) -> CompilerResult<()> {
+        match shape {
+            Shape::Code { block, exit } => {
+                self.code(*block)?;
+                self.exit(*block, exit)
+            }
+            Shape::Loop { header, body } => {
+                let seq = self.nested(Some(Target::Loop(*header)), |s| s.shape(body))?;
+                self.b().instr(Loop { seq });
+                Ok(())
+            }
+            Shape::Block { follow, body, next } => {
+                let seq = self.nested(Some(Target::Block(*follow)), |s| s.shape(body))?;
+                self.b().instr(Block { seq });
+                self.shape(next)
+            }
+            Shape::Dispatch { blocks } => self.dispatch(blocks),
+        }
+    }
+
+    /// `loop { block { ... block { br_table } code 0 ... } code n }`, where
+    /// the branch to a block sets its index and continues the loop
+    fn dispatch(&mut self, blocks: &[(usize, Exit)]) -> CompilerResult<()> {
+        let next = self.locals.add(ValType::I32);
+        let index = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, (b, _))| (*b, i as i32))
+            .collect();
+        self.dispatch = Some((next, index));
+        self.b().i32_const(0).local_set(next);
+        let seq = self.nested(Some(Target::Dispatch), |s| {
+            let body = s.seq;
+            let seqs: Vec<InstrSeqId> = blocks
+                .iter()
+                .map(|_| s.builder.dangling_instr_seq(None).id())
+                .collect();
+            s.builder
+                .instr_seq(seqs[0])
+                .local_get(next)
+                .br_table(seqs.clone().into_boxed_slice(), seqs[seqs.len() - 1]);
+            for (i, (b, exit)) in blocks.iter().enumerate() {
+                s.seq = seqs.get(i + 1).copied().unwrap_or(body);
+                s.b().instr(Block { seq: seqs[i] });
+                s.code(*b)?;
+                s.exit(*b, exit)?;
+            }
+            Ok(())
+        })?;
+        self.b().instr(Loop { seq });
+        Ok(())
+    }
+
+    /// The instructions of block `b`. The phis are done by the branches
+    /// to its label, and the branches by its exit.
+    fn code(&mut self, b: usize) -> CompilerResult<()> {
+        let func = self.func;
+        for instr in self.cfg.instrs(func, b) {
+            if is_phi(instr)
+                || matches!(
+                    instr,
+                    LabelBodyInstr::Command(Command::Jump(_) | Command::Jeq(..))
+                )
+            {
+                continue;
+            }
+            self.statement(instr)?;
+        }
+        Ok(())
+    }
+
+    fn exit(&mut self, b: usize, exit: &Exit) -> CompilerResult<()> {
+        let func = self.func;
+        let last = self.cfg.instrs(func, b).last();
+        match exit {
+            Exit::None => {
+                if !matches!(last, Some(LabelBodyInstr::Command(Command::Ret(_)))) {
+                    self.b().unreachable();
+                }
+                Ok(())
+            }
+            Exit::Jump(edge) => self.edge(b, edge),
+            Exit::Cond { taken, fallthrough } => {
+                let Some(LabelBodyInstr::Command(Command::Jeq(_, lhs, rhs))) = last else {
+                    unreachable!("only a jeq has two successors");
+                };
+                let op = eq_op(self.value_type(lhs));
+                self.value(lhs)?;
+                self.value(rhs)?;
+                self.b().binop(op);
+                let consequent = self.nested(None, |s| s.edge(b, taken))?;
+                let alternative = self.nested(None, |s| s.edge(b, fallthrough))?;
+                self.b().instr(IfElse {
+                    consequent,
+                    alternative,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn edge(&mut self, from: usize, edge: &Edge) -> CompilerResult<()> {
+        self.phis(from, edge.target)?;
+        match &edge.branch {
+            Branch::Break => {
+                let seq = self.target(Target::Block(edge.target));
+                self.b().br(seq);
+            }
+            Branch::Continue => {
+                let seq = self.target(Target::Loop(edge.target));
+                self.b().br(seq);
+            }
+            Branch::Inline(shape) => return self.shape(shape),
+            Branch::Dispatch => {
+                let (next, index) = self.dispatch.as_ref().expect("inside the dispatch loop");
+                let (next, index) = (*next, index[&edge.target]);
+                let seq = self.target(Target::Dispatch);
+                self.b().i32_const(index).local_set(next).br(seq);
+            }
+        }
+        Ok(())
+    }
+
+    /// Assign the phis of `to` the values coming from the label of `from`
+    fn phis(&mut self, from: usize, to: usize) -> CompilerResult<()> {
+        let block = self.cfg.blocks[to];
+        if block.start != 0 {
+            return Ok(());
+        }
+        let labels = self.func.get_labels();
+        let pred = &labels[self.cfg.blocks[from].label].name;
+        let copies: Vec<(&RegisterValue, &Value)> = labels[block.label]
+            .body
+            .iter()
+            .map_while(|instr| match instr {
+                LabelBodyInstr::Assign(reg, value) => match &**value {
+                    LabelBodyInstr::Command(Command::Phi(incoming)) => Some((reg, incoming)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .filter_map(|(reg, incoming)| {
+                incoming
+                    .iter()
+                    .find(|(label, _)| label == pred)
+                    .map(|(_, value)| (reg, value))
+            })
+            .collect();
+        if let [(reg, value)] = copies[..] {
+            return self.write(reg, |s| s.value(value));
+        }
+
+        // The phis read their operands before any of them is assigned
+        let mut temps = Vec::with_capacity(copies.len());
+        for (reg, value) in &copies {
+            let temp = self.locals.add(self.reg_type(reg));
+            self.value(value)?;
+            self.b().local_set(temp);
+            temps.push(temp);
+        }
+        for ((reg, _), temp) in copies.iter().zip(temps) {
+            self.write(reg, |s| {
+                s.b().local_get(temp);
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, instr: &LabelBodyInstr) -> CompilerResult<()> {
+        match instr {
+            LabelBodyInstr::Assign(reg, value) => self.write(reg, |s| {
+                if s.instr(value)? {
+                    Ok(())
+                } else {
+                    Err(CompilerError::Unsupported(instr.to_string()))
+                }
+            }),
+            _ => {
+                if self.instr(instr)? {
+                    self.b().drop();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Emit `instr`, returning whether it leaves a value
+    fn instr(&mut self, instr: &LabelBodyInstr) -> CompilerResult<bool> {
+        match instr {
+            LabelBodyInstr::Assign(..) => Err(CompilerError::Unsupported(instr.to_string())),
+            LabelBodyInstr::Call(name, args) => self.call(name, args).map(|_| true),
+            LabelBodyInstr::Command(cmd) => self.command(cmd),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Value]) -> CompilerResult<()> {
+        let env = self.env;
+        let callee = env
+            .functions
+            .get(name)
+            .ok_or_else(|| CompilerError::UnknownFunction(name.to_string()))?;
+        let fixed = callee.ty.get_args().len();
+        if args.len() < fixed {
+            return Err(CompilerError::ArgumentCount(name.to_string()));
+        }
+        for arg in &args[..fixed] {
+            self.value(arg)?;
+        }
+        if callee.ty.is_var_arg() {
+            self.var_args(&args[fixed..])?;
+        }
+        self.b().call(callee.id);
+        Ok(())
+    }
+
+    /// Write the variadic arguments of a call to a buffer in the frame and
+    /// push its address, as clang does for wasm32: each argument is aligned
+    /// to its size, narrow integers take 4 bytes, extended according to
+    /// their signedness, and `float`s are promoted to `double`s
+    fn var_args(&mut self, args: &[Value]) -> CompilerResult<()> {
+        let mut layout = Vec::with_capacity(args.len());
+        let mut size = 0;
+        for arg in args {
+            let (kind, width) = match self.value_type(arg) {
+                ValType::I64 => (StoreKind::I64 { atomic: false }, 8),
+                ValType::F32 | ValType::F64 => (StoreKind::F64, 8),
+                _ => (StoreKind::I32 { atomic: false }, 4),
+            };
+            size = align_to(size, width);
+            layout.push((kind, width, size));
+            size += width;
+        }
+        if args.is_empty() {
+            self.b().i32_const(0);
+            return Ok(());
+        }
+        let buffer = self.alloc(size, 8);
+        let memory = self.env.memory;
+        for (arg, (kind, width, offset)) in args.iter().zip(layout) {
+            let frame = self.frame;
+            self.b().local_get(frame);
+            self.value(arg)?;
+            if self.value_type(arg) == ValType::F32 {
+                self.b().unop(UnaryOp::F64PromoteF32);
+            }
+            // Narrow integers are held sign-extended
+            let mask = match arg {
+                Value::List(_) => None,
+                _ => match arg.get_type() {
+                    MirageTypeEnum::UInt8(_) => Some(0xff),
+                    MirageTypeEnum::UInt16(_) => Some(0xffff),
+                    _ => None,
+                },
+            };
+            if let Some(mask) = mask {
+                self.b().i32_const(mask).binop(BinaryOp::I32And);
+            }
+            self.b().store(
+                memory,
+                kind,
+                MemArg {
+                    align: width,
+                    offset: buffer + offset,
+                },
+            );
+        }
+        self.slot_address(buffer);
+        Ok(())
+    }
+
+    fn command(&mut self, cmd: &Command) -> CompilerResult<bool> {
+        match cmd {
+            Command::Store(reg, value) => {
+                let ty = match value {
+                    Value::List(_) => return Err(CompilerError::Unsupported(value.to_string())),
+                    _ => value.get_type(),
+                };
+                self.store(Base::Register(reg), 0, &ty, |s| s.value(value))?;
+                Ok(false)
+            }
+            Command::New(name, args) => {
+                let env = self.env;
+                let fields = env
+                    .structs
+                    .get(name)
+                    .ok_or_else(|| CompilerError::UnknownType(name.clone()))?;
+                let (size, offsets) = struct_layout(fields);
+                let align = fields.iter().map(align_of).max().unwrap_or(1);
+                let slot = self.alloc(size, align);
+                for ((arg, field), offset) in args.iter().zip(fields).zip(offsets) {
+                    self.store(Base::Frame, slot + offset, field, |s| s.value(arg))?;
+                }
+                self.slot_address(slot);
+                Ok(true)
+            }
+            Command::Get(reg, index) => {
+                let fields = match &reg.ty {
+                    MirageTypeEnum::Struct(s) => &s.fields,
+                    MirageTypeEnum::Pointer(p) => match &*p.element_ty {
+                        MirageTypeEnum::Struct(s) => &s.fields,
+                        _ => return Err(CompilerError::Unsupported(cmd.to_string())),
+                    },
+                    _ => return Err(CompilerError::Unsupported(cmd.to_string())),
+                };
+                let (_, offsets) = struct_layout(fields);
+                let Some((ty, offset)) = fields.get(*index).zip(offsets.get(*index)) else {
+                    return Err(CompilerError::Unsupported(cmd.to_string()));
+                };
+                self.read(reg)?;
+                self.load(ty, *offset);
+                Ok(true)
+            }
+            Command::Const(obj) => {
+                self.object(obj.get_value_ref())?;
+                Ok(true)
+            }
+            // The frame is given back on return, and there is no heap
+            Command::Free(_) => Ok(false),
+            Command::Ret(value) => {
+                self.value(value)?;
+                let at = self.builder.instr_seq(self.seq).instrs().len();
+                self.epilogues.push((self.seq, at));
+                let (sp, saved) = (self.env.stack_pointer, self.saved);
+                self.b().local_get(saved).global_set(sp).return_();
+                Ok(false)
+            }
+            Command::Jump(_) | Command::Jeq(..) | Command::Phi(_) => Ok(false),
+            Command::IncrInt8(reg) => self.incr(
+                reg,
+                WasmValue::I32(1),
+                BinaryOp::I32Add,
+                Some(UnaryOp::I32Extend8S),
+            ),
+            Command::IncrInt16(reg) => self.incr(
+                reg,
+                WasmValue::I32(1),
+                BinaryOp::I32Add,
+                Some(UnaryOp::I32Extend16S),
+            ),
+            Command::IncrInt32(reg) => self.incr(reg, WasmValue::I32(1), BinaryOp::I32Add, None),
+            Command::IncrInt64(reg) => self.incr(reg, WasmValue::I64(1), BinaryOp::I64Add, None),
+            Command::IncrFloat32(reg) => {
+                self.incr(reg, WasmValue::F32(1.0), BinaryOp::F32Add, None)
+            }
+            Command::IncrFloat64(reg) => {
+                self.incr(reg, WasmValue::F64(1.0), BinaryOp::F64Add, None)
+            }
+            Command::AddInt8(lhs, rhs) => {
+                self.arith(lhs, rhs, BinaryOp::I32Add, Some(UnaryOp::I32Extend8S))
+            }
+            Command::AddInt16(lhs, rhs) => {
+                self.arith(lhs, rhs, BinaryOp::I32Add, Some(UnaryOp::I32Extend16S))
+            }
+            Command::AddInt32(lhs, rhs) => self.arith(lhs, rhs, BinaryOp::I32Add, None),
+            Command::AddInt64(lhs, rhs) => self.arith(lhs, rhs, BinaryOp::I64Add, None),
+            Command::AddFloat32(lhs, rhs) => self.arith(lhs, rhs, BinaryOp::F32Add, None),
+            Command::AddFloat64(lhs, rhs) => self.arith(lhs, rhs, BinaryOp::F64Add, None),
+            Command::SubInt8(lhs, rhs) => {
+                self.arith(lhs, rhs, BinaryOp::I32Sub, Some(UnaryOp::I32Extend8S))
+            }
+            Command::SubInt16(lhs, rhs) => {
+                self.arith(lhs, rhs, BinaryOp::I32Sub, Some(UnaryOp::I32Extend16S))
+            }
+            Command::SubInt32(lhs, rhs) => self.arith(lhs, rhs, BinaryOp::I32Sub, None),
+            Command::SubInt64(lhs, rhs) => self.arith(lhs, rhs, BinaryOp::I64Sub, None),
+            Command::SubFloat32(lhs, rhs) => self.arith(lhs, rhs, BinaryOp::F32Sub, None),
+            Command::SubFloat64(lhs, rhs) => self.arith(lhs, rhs, BinaryOp::F64Sub, None),
+            Command::Ref(value) => {
+                match register(value) {
+                    Some(reg) => self.address_of(reg)?,
+                    None => {
+                        // A constant gets a slot of its own
+                        let ty = match value {
+                            Value::List(_) => {
+                                return Err(CompilerError::Unsupported(value.to_string()))
+                            }
+                            _ => value.get_type(),
+                        };
+                        if is_aggregate(&ty) {
+                            self.value(value)?;
+                        } else {
+                            let slot = self.alloc(size_of(&ty), align_of(&ty));
+                            self.store(Base::Frame, slot, &ty, |s| s.value(value))?;
+                            self.slot_address(slot);
+                        }
+                    }
+                }
+                Ok(true)
+            }
+            Command::Load(ty, value) => {
+                if is_aggregate(ty) {
+                    // Loading a struct or an array copies it
+                    let slot = self.alloc(size_of(ty), align_of(ty));
+                    self.store(Base::Frame, slot, ty, |s| s.value(value))?;
+                    self.slot_address(slot);
+                } else {
+                    self.value(value)?;
+                    self.load(ty, 0);
+                }
+                Ok(true)
+            }
+            Command::GetElementPtr(ty, base, indices) => {
+                self.value(base)?;
+                self.element_ptr(ty, indices)?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn incr(
+        &mut self,
+        reg: &RegisterValue,
+        one: WasmValue,
+        op: BinaryOp,
+        extend: Option<UnaryOp>,
+    ) -> CompilerResult<bool> {
+        self.read(reg)?;
+        self.b().const_(one).binop(op);
+        if let Some(extend) = extend {
+            self.b().unop(extend);
+        }
+        Ok(true)
+    }
+
+    /// `lhs op rhs`, sign-extended back from 8 or 16 bits by `extend`
+    fn arith(
+        &mut self,
+        lhs: &Value,
+        rhs: &Value,
+        op: BinaryOp,
+        extend: Option<UnaryOp>,
+    ) -> CompilerResult<bool> {
+        self.value(lhs)?;
+        self.value(rhs)?;
+        self.b().binop(op);
+        if let Some(extend) = extend {
+            self.b().unop(extend);
+        }
+        Ok(true)
+    }
+
+    /// Offset the address on the stack as `getelementptr` does: the first
+    /// index steps over whole values of `ty`, the next ones go into it
+    fn element_ptr(&mut self, ty: &MirageTypeEnum, indices: &[Value]) -> CompilerResult<()> {
+        let mut ty = ty.clone();
+        for (i, index) in indices.iter().enumerate() {
+            if i > 0 {
+                match ty {
+                    MirageTypeEnum::Struct(s) => {
+                        let field = const_index(index)
+                            .and_then(|f| usize::try_from(f).ok())
+                            .filter(|f| *f < s.fields.len())
+                            .ok_or_else(|| CompilerError::Unsupported(index.to_string()))?;
+                        let (_, offsets) = struct_layout(&s.fields);
+                        self.add_offset(offsets[field] as i32);
+                        ty = s.fields[field].clone();
+                        continue;
+                    }
+                    MirageTypeEnum::Array(a) => ty = a.element_ty(),
+                    _ => {
+                        return Err(CompilerError::Unsupported(format!(
+                            "getelementptr into {}",
+                            ty.print_to_string()
+                        )))
+                    }
+                }
+            }
+            let stride = size_of(&ty) as i32;
+            match const_index(index) {
+                Some(c) => self.add_offset((c as i32).wrapping_mul(stride)),
+                None => {
+                    self.value(index)?;
+                    if self.value_type(index) == ValType::I64 {
+                        self.b().unop(UnaryOp::I32WrapI64);
+                    }
+                    self.b()
+                        .i32_const(stride)
+                        .binop(BinaryOp::I32Mul)
+                        .binop(BinaryOp::I32Add);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_offset(&mut self, offset: i32) {
+        if offset != 0 {
+            self.b().i32_const(offset).binop(BinaryOp::I32Add);
+        }
+    }
+
+    /// Push the address of a slot of the frame
+    fn slot_address(&mut self, offset: u32) {
+        let frame = self.frame;
+        self.b().local_get(frame);
+        self.add_offset(offset as i32);
+    }
+
+    /// Load a value of `ty` from the address on the stack plus `offset`.
+    /// A struct or an array isn't loaded: its address is the value.
+    fn load(&mut self, ty: &MirageTypeEnum, offset: u32) {
+        if is_aggregate(ty) {
+            self.add_offset(offset as i32);
+            return;
+        }
+        let memory = self.env.memory;
+        self.b().load(
+            memory,
+            load_kind(ty),
+            MemArg {
+                align: align_of(ty),
+                offset,
+            },
+        );
+    }
+
+    /// Store the value `value` pushes, of type `ty`, at `offset` from
+    /// `base`. A struct or an array is copied.
+    fn store(
+        &mut self,
+        base: Base,
+        offset: u32,
+        ty: &MirageTypeEnum,
+        value: impl FnOnce(&mut Self) -> CompilerResult<()>,
+    ) -> CompilerResult<()> {
+        match base {
+            Base::Frame => {
+                let frame = self.frame;
+                self.b().local_get(frame);
+            }
+            Base::Absolute => {
+                self.b().i32_const(0);
+            }
+            Base::Register(reg) => self.read(reg)?,
+        }
+        let memory = self.env.memory;
+        if is_aggregate(ty) {
+            self.add_offset(offset as i32);
+            value(self)?;
+            self.b()
+                .i32_const(size_of(ty) as i32)
+                .memory_copy(memory, memory);
+        } else {
+            value(self)?;
+            self.b().store(
+                memory,
+                store_kind(ty),
+                MemArg {
+                    align: align_of(ty),
+                    offset,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn global(&self, index: usize) -> CompilerResult<(u32, MirageTypeEnum)> {
+        self.env
+            .globals
+            .get(index)
+            .cloned()
+            .ok_or(CompilerError::UnknownGlobal(index))
+    }
+
+    fn local(&mut self, reg: &RegisterValue) -> LocalId {
+        let key = (reg.register_type, reg.index);
+        if let Some(local) = self.registers.get(&key) {
+            return *local;
+        }
+        let local = self.locals.add(self.reg_type(reg));
+        self.registers.insert(key, local);
+        local
+    }
+
+    fn reg_type(&self, reg: &RegisterValue) -> ValType {
+        self.types
+            .get(&(reg.register_type, reg.index))
+            .copied()
+            .unwrap_or_else(|| val_type(&reg.ty))
+    }
+
+    fn value_type(&self, value: &Value) -> ValType {
+        match (register(value), value) {
+            (Some(reg), _) => self.reg_type(reg),
+            (None, Value::ConstValue(obj)) => val_type(&obj.get_type()),
+            (None, _) => ValType::I32,
+        }
+    }
+
+    fn read(&mut self, reg: &RegisterValue) -> CompilerResult<()> {
+        if reg.register_type == RegisterType::Global {
+            let (address, ty) = self.global(reg.index)?;
+            self.b().i32_const(0);
+            self.load(&ty, address);
+            return Ok(());
+        }
+        if let Some(slot) = self.slots.get(&(reg.register_type, reg.index)).copied() {
+            let frame = self.frame;
+            self.b().local_get(frame);
+            self.load(&reg.ty, slot);
+            return Ok(());
+        }
+        let local = self.local(reg);
+        self.b().local_get(local);
+        Ok(())
+    }
+
+    /// Assign the value `value` pushes to `reg`
+    fn write(
+        &mut self,
+        reg: &RegisterValue,
+        value: impl FnOnce(&mut Self) -> CompilerResult<()>,
+    ) -> CompilerResult<()> {
+        if reg.register_type == RegisterType::Global {
+            let (address, ty) = self.global(reg.index)?;
+            return self.store(Base::Absolute, address, &ty, value);
+        }
+        if let Some(slot) = self.slots.get(&(reg.register_type, reg.index)).copied() {
+            return self.store(Base::Frame, slot, &reg.ty, value);
+        }
+        value(self)?;
+        let local = self.local(reg);
+        self.b().local_set(local);
+        Ok(())
+    }
+
+    /// Push the address `ref reg` gives
+    fn address_of(&mut self, reg: &RegisterValue) -> CompilerResult<()> {
+        if reg.register_type == RegisterType::Global {
+            let (address, _) = self.global(reg.index)?;
+            self.b().i32_const(address as i32);
+        } else if is_aggregate(&reg.ty) {
+            self.read(reg)?;
+        } else {
+            let slot = self.slots[&(reg.register_type, reg.index)];
+            self.slot_address(slot);
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, value: &Value) -> CompilerResult<()> {
+        match value {
+            Value::ConstValue(obj) => self.object(obj.get_value_ref()),
+            Value::Register(reg) => self.read(reg),
+            Value::List(_) => Err(CompilerError::Unsupported(value.to_string())),
+        }
+    }
+
+    fn object(&mut self, value: &MirageValueEnum) -> CompilerResult<()> {
+        match value {
+            MirageValueEnum::Register(reg) => return self.read(reg),
+            MirageValueEnum::Int8(v) => self.b().i32_const(v.value as i32),
+            MirageValueEnum::Int16(v) => self.b().i32_const(v.value as i32),
+            MirageValueEnum::Int32(v) => self.b().i32_const(v.value),
+            MirageValueEnum::Int64(v) => self.b().i64_const(v.value),
+            MirageValueEnum::UInt8(v) => self.b().i32_const(v.value as i8 as i32),
+            MirageValueEnum::UInt16(v) => self.b().i32_const(v.value as i16 as i32),
+            MirageValueEnum::UInt32(v) => self.b().i32_const(v.value as i32),
+            MirageValueEnum::UInt64(v) => self.b().i64_const(v.value as i64),
+            MirageValueEnum::Float32(v) => self.b().f32_const(v.value),
+            MirageValueEnum::Float64(v) => self.b().f64_const(v.value),
+            MirageValueEnum::Array(_) | MirageValueEnum::Struct(_) => {
+                if let Some(s) = value.try_to_rust_string() {
+                    let address = self.data.string(&s);
+                    self.b().i32_const(address as i32);
+                    return Ok(());
+                }
+                let ty = value.get_type();
+                let slot = self.alloc(size_of(&ty), align_of(&ty));
+                self.fill(slot, value)?;
+                self.slot_address(slot);
+                return Ok(());
+            }
+            MirageValueEnum::Pointer(_) => {
+                return Err(CompilerError::Unsupported(value.print_to_string()))
+            }
+        };
+        Ok(())
+    }
+
+    /// Write a constant struct or array to the frame, at `offset`
+    fn fill(&mut self, offset: u32, value: &MirageValueEnum) -> CompilerResult<()> {
+        match value {
+            MirageValueEnum::Array(a) => {
+                let size = size_of(&a.ty.element_ty());
+                for (i, v) in a.values.iter().enumerate() {
+                    self.fill(offset + i as u32 * size, v)?;
+                }
+                Ok(())
+            }
+            MirageValueEnum::Struct(s) => {
+                let (_, offsets) = struct_layout(&s.ty.fields);
+                for (v, field) in s.values.iter().zip(offsets) {
+                    self.fill(offset + field, v)?;
+                }
+                Ok(())
+            }
+            _ => self.store(Base::Frame, offset, &value.get_type(), |s| s.object(value)),
+        }
+    }
+}
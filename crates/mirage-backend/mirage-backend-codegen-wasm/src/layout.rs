@@ -0,0 +1,72 @@
+pub use mirage_backend_opti::layout::is_aggregate;
+use mirage_backend_opti::layout::{self, Layout};
+use mirage_frontend::object::MirageTypeEnum;
+use walrus::ir::{ExtendedLoad, LoadKind, StoreKind};
+use walrus::ValType;
+
+/// The size of an address in wasm32
+pub const POINTER_SIZE: u32 = 4;
+
+/// The layout of the values in memory
+const LAYOUT: Layout = Layout::new(POINTER_SIZE as u64);
+
+/// Round `offset` up to a multiple of `align`
+pub fn align_to(offset: u32, align: u32) -> u32 {
+    layout::align_to(offset.into(), align.into()) as u32
+}
+
+/// The number of bytes taken by a value of `ty` in memory
+pub fn size_of(ty: &MirageTypeEnum) -> u32 {
+    LAYOUT.size_of(ty) as u32
+}
+
+pub fn align_of(ty: &MirageTypeEnum) -> u32 {
+    LAYOUT.align_of(ty) as u32
+}
+
+/// The size of a struct with `fields` and the offset of every field
+pub fn struct_layout(fields: &[MirageTypeEnum]) -> (u32, Vec<u32>) {
+    let (size, offsets) = LAYOUT.struct_layout(fields);
+    (
+        size as u32,
+        offsets.into_iter().map(|offset| offset as u32).collect(),
+    )
+}
+
+/// The wasm type holding a value of `ty`. Integers narrower than 32 bits
+/// are kept sign-extended in an `i32`, whether they are signed or not.
+pub fn val_type(ty: &MirageTypeEnum) -> ValType {
+    match ty {
+        MirageTypeEnum::Int64(_) | MirageTypeEnum::UInt64(_) => ValType::I64,
+        MirageTypeEnum::Float32(_) => ValType::F32,
+        MirageTypeEnum::Float64(_) => ValType::F64,
+        _ => ValType::I32,
+    }
+}
+
+/// The load reading a value of `ty`, or its address for an aggregate
+pub fn load_kind(ty: &MirageTypeEnum) -> LoadKind {
+    match ty {
+        MirageTypeEnum::Int8(_) | MirageTypeEnum::UInt8(_) => LoadKind::I32_8 {
+            kind: ExtendedLoad::SignExtend,
+        },
+        MirageTypeEnum::Int16(_) | MirageTypeEnum::UInt16(_) => LoadKind::I32_16 {
+            kind: ExtendedLoad::SignExtend,
+        },
+        MirageTypeEnum::Int64(_) | MirageTypeEnum::UInt64(_) => LoadKind::I64 { atomic: false },
+        MirageTypeEnum::Float32(_) => LoadKind::F32,
+        MirageTypeEnum::Float64(_) => LoadKind::F64,
+        _ => LoadKind::I32 { atomic: false },
+    }
+}
+
+pub fn store_kind(ty: &MirageTypeEnum) -> StoreKind {
+    match ty {
+        MirageTypeEnum::Int8(_) | MirageTypeEnum::UInt8(_) => StoreKind::I32_8 { atomic: false },
+        MirageTypeEnum::Int16(_) | MirageTypeEnum::UInt16(_) => StoreKind::I32_16 { atomic: false },
+        MirageTypeEnum::Int64(_) | MirageTypeEnum::UInt64(_) => StoreKind::I64 { atomic: false },
+        MirageTypeEnum::Float32(_) => StoreKind::F32,
+        MirageTypeEnum::Float64(_) => StoreKind::F64,
+        _ => StoreKind::I32 { atomic: false },
+    }
+}
@@ -1,76 +1,225 @@
-use mirage_frontend::object::function::FunctionValue;
-use mirage_frontend::object::label::Label;
+#[cfg(test)]
+mod test;
+
+mod function;
+mod layout;
+mod memory;
+#[cfg(feature = "runtime")]
+mod runtime;
+mod stackify;
+
+#[cfg(feature = "runtime")]
+pub use runtime::WasmSession;
+
+use function::FunctionCompiler;
+use memory::{StaticData, DATA_START};
+use mirage_frontend::object::function::{FunctionType, FunctionValue};
+use mirage_frontend::object::meta::Flag;
 use mirage_frontend::object::statements::Statement;
 use mirage_frontend::object::MirageTypeEnum;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::collections::HashMap;
+use std::path::Path;
+use walrus::ir::Value;
+use walrus::{
+    ActiveData, ActiveDataLocation, DataKind, FunctionBuilder, FunctionId, FunctionKind, GlobalId,
+    InitExpr, LocalId, MemoryId, ValType,
+};
+
+/// The size of a wasm page
+const PAGE_SIZE: u32 = 64 * 1024;
+
+/// The size of the stack, below the top of linear memory
+const STACK_SIZE: u32 = 64 * 1024;
+
+/// A compiler error
+/// # Variants
+/// * `UnknownFunction` - A call to a function which isn't declared
+/// * `UnknownType` - A `new` of a type which isn't defined
+/// * `UnknownGlobal` - A global register without its global
+/// * `ArgumentCount` - A call with too few arguments
+/// * `Unsupported` - Something wasm32 can't express, such as a global
+///   holding a pointer
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompilerError {
+    UnknownFunction(String),
+    UnknownType(String),
+    UnknownGlobal(usize),
+    ArgumentCount(String),
+    Unsupported(String),
+}
+
+impl std::fmt::Display for CompilerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompilerError::UnknownFunction(name) => {
+                write!(f, "call to unknown function `{}`", name)
+            }
+            CompilerError::UnknownType(name) => write!(f, "unknown type `{}`", name),
+            CompilerError::UnknownGlobal(index) => write!(f, "unknown global g{}", index),
+            CompilerError::ArgumentCount(name) => {
+                write!(f, "too few arguments in a call to `{}`", name)
+            }
+            CompilerError::Unsupported(what) => write!(f, "unsupported on wasm32: `{}`", what),
+        }
+    }
+}
+
+impl std::error::Error for CompilerError {}
+
+pub type CompilerResult<T> = Result<T, CompilerError>;
+
+/// A function of the module, defined or imported
+struct Callee {
+    id: FunctionId,
+    ty: FunctionType,
+}
+
+/// What the functions of a module are compiled against
+struct Env {
+    memory: MemoryId,
+    stack_pointer: GlobalId,
+    functions: HashMap<String, Callee>,
+    /// The address and type of every global, by index
+    globals: Vec<(u32, MirageTypeEnum)>,
+    structs: HashMap<String, Vec<MirageTypeEnum>>,
+}
 
-#[derive(Debug, Clone)]
+/// The WebAssembly Compiler struct.
+///
+/// Every function is exported under its name, unless it is `#internal`,
+/// and every extern is imported from the `env` module. A variadic extern
+/// takes its variadic arguments through a buffer in linear memory, whose
+/// address is its last parameter. Linear memory, exported as `memory`,
+/// holds the globals and the string constants from address 1024, and a
+/// stack growing down from its end.
+#[derive(Debug)]
 pub struct Compiler {
     stmts: Vec<Statement>,
-    module: Rc<RefCell<walrus::Module>>,
-    fn_builder: Option<Rc<walrus::FunctionBuilder>>,
+    module: walrus::Module,
 }
 
 impl Compiler {
     pub fn new(stmts: Vec<Statement>) -> Self {
         let config = walrus::ModuleConfig::new();
         let module = walrus::Module::with_config(config);
-        Self {
-            stmts,
-            module: Rc::new(RefCell::new(module)),
-            fn_builder: None,
-        }
+        Self { stmts, module }
     }
 
-    pub fn mirage_ty_to_wasm_ty(&self, ty: MirageTypeEnum) -> walrus::ValType {
-        match ty {
-            MirageTypeEnum::Int8(..)
-            | MirageTypeEnum::UInt8(_)
-            | MirageTypeEnum::Int16(..)
-            | MirageTypeEnum::UInt16(_)
-            | MirageTypeEnum::Int32(..)
-            | MirageTypeEnum::UInt32(_) => walrus::ValType::I32,
-            MirageTypeEnum::Int64(..) | MirageTypeEnum::UInt64(_) => walrus::ValType::I64,
-            MirageTypeEnum::Float32(..) => walrus::ValType::F32,
-            MirageTypeEnum::Float64(..) => walrus::ValType::F64,
-            MirageTypeEnum::Array(..) => todo!(),
-            MirageTypeEnum::Pointer(..) => todo!(),
-            MirageTypeEnum::Struct(..) => todo!(),
+    /// The wasm type of a value of `ty`. Structs, arrays and pointers are
+    /// addresses in linear memory.
+    pub fn mirage_ty_to_wasm_ty(&self, ty: MirageTypeEnum) -> ValType {
+        layout::val_type(&ty)
+    }
+
+    /// The wasm signature of a function of type `ty`
+    fn signature(&self, ty: &FunctionType) -> (Vec<ValType>, Vec<ValType>) {
+        let mut params: Vec<ValType> = ty.get_args().iter().map(layout::val_type).collect();
+        if ty.is_var_arg() {
+            params.push(ValType::I32);
         }
+        (params, vec![layout::val_type(ty.get_ret())])
     }
 
-    pub fn compile(&mut self) {
-        for stmt in self.stmts.clone() {
+    pub fn compile(&mut self) -> CompilerResult<()> {
+        let memory = self.module.memories.add_local(false, 1, None);
+        self.module.exports.add("memory", memory);
+        let stack_pointer =
+            self.module
+                .globals
+                .add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)));
+
+        let mut data = StaticData::new();
+        let mut env = Env {
+            memory,
+            stack_pointer,
+            functions: HashMap::new(),
+            globals: Vec::new(),
+            structs: HashMap::new(),
+        };
+        let mut functions: Vec<(&FunctionValue, FunctionId, Vec<LocalId>)> = Vec::new();
+        for stmt in &self.stmts {
             match stmt {
-                Statement::Function(ref f) => {
-                    self.compile_function(f);
+                Statement::Typedef(t) => {
+                    env.structs.insert(t.name.clone(), t.ty.clone().into_vec());
+                }
+                Statement::Global(global) => {
+                    let address = data.global(global.value.get_value_ref())?;
+                    env.globals.push((address, global.value.get_type()));
+                }
+                Statement::External(e) => {
+                    let (params, results) = self.signature(&e.ty);
+                    let ty = self.module.types.add(&params, &results);
+                    let (id, _) = self.module.add_import_func("env", &e.name, ty);
+                    env.functions.insert(
+                        e.name.clone(),
+                        Callee {
+                            id,
+                            ty: e.ty.clone(),
+                        },
+                    );
+                }
+                Statement::Function(f) => {
+                    // The bodies are compiled once every function is
+                    // declared, so that they can call each other
+                    let (params, results) = self.signature(f.get_type());
+                    let args: Vec<LocalId> = params
+                        .iter()
+                        .map(|ty| self.module.locals.add(*ty))
+                        .collect();
+                    let mut builder =
+                        FunctionBuilder::new(&mut self.module.types, &params, &results);
+                    builder.name(f.get_name().clone());
+                    let id = builder.finish(args.clone(), &mut self.module.funcs);
+                    env.functions.insert(
+                        f.get_name().clone(),
+                        Callee {
+                            id,
+                            ty: f.get_type().clone(),
+                        },
+                    );
+                    functions.push((f, id, args));
                 }
                 _ => {}
             }
         }
-    }
 
-    pub fn compile_function(&mut self, f: &FunctionValue) {
-        let fn_ty = f.get_type();
-        let ret_ty = self.mirage_ty_to_wasm_ty(fn_ty.get_ret().clone());
-        let args_ty = fn_ty
-            .get_args()
-            .iter()
-            .map(|x| self.mirage_ty_to_wasm_ty(x.clone()))
-            .collect::<Vec<_>>();
-
-        let fn_builder =
-            walrus::FunctionBuilder::new(&mut self.module.borrow_mut().types, &args_ty, &[ret_ty]);
-        self.fn_builder = Some(Rc::new(fn_builder));
-
-        for label in f.get_labels() {
-            self.compile_label(label.clone());
+        for (f, id, args) in &functions {
+            let walrus::Module { funcs, locals, .. } = &mut self.module;
+            let FunctionKind::Local(local) = &mut funcs.get_mut(*id).kind else {
+                unreachable!("the functions are defined locally");
+            };
+            FunctionCompiler::new(&env, &mut data, locals, local.builder_mut(), f, args)
+                .compile()?;
+            let internal = f
+                .get_labels()
+                .first()
+                .is_some_and(|l| l.flags.contains(&Flag::internal()));
+            if !internal {
+                self.module.exports.add(f.get_name(), *id);
+            }
         }
+
+        self.module.data.add(
+            DataKind::Active(ActiveData {
+                memory,
+                location: ActiveDataLocation::Absolute(DATA_START),
+            }),
+            data.bytes().to_vec(),
+        );
+        let pages = (data.end() + STACK_SIZE).div_ceil(PAGE_SIZE);
+        self.module.memories.get_mut(memory).initial = pages;
+        self.module.globals.get_mut(stack_pointer).kind =
+            walrus::GlobalKind::Local(InitExpr::Value(Value::I32((pages * PAGE_SIZE) as i32)));
+        Ok(())
     }
 
-    pub fn compile_label(&mut self, label: Label) {
-        todo!()
+    /// The compiled module, as the bytes of a `.wasm` file
+    pub fn emit_wasm(&mut self) -> Vec<u8> {
+        self.module.emit_wasm()
     }
-}
 
+    /// Write the compiled module to a `.wasm` file
+    pub fn write_to(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.emit_wasm())
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use mirage_frontend::object::util::to_string_with_special_char;
+use mirage_frontend::object::MirageValueEnum;
+
+use crate::layout::{align_of, align_to, size_of, struct_layout};
+use crate::{CompilerError, CompilerResult};
+
+/// Where the static data starts in linear memory. The addresses below are
+/// left unused, so that 0 is never a valid address.
+pub const DATA_START: u32 = 1024;
+
+/// The static data of a module, put in linear memory by a single data
+/// segment: the globals, then the string constants
+#[derive(Debug, Clone)]
+pub struct StaticData {
+    bytes: Vec<u8>,
+    strings: HashMap<String, u32>,
+}
+
+impl StaticData {
+    pub fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            strings: HashMap::new(),
+        }
+    }
+
+    /// The first address after the data
+    pub fn end(&self) -> u32 {
+        DATA_START + self.bytes.len() as u32
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn alloc(&mut self, size: u32, align: u32) -> u32 {
+        let address = align_to(self.end(), align);
+        self.bytes.resize((address + size - DATA_START) as usize, 0);
+        address
+    }
+
+    fn put(&mut self, address: u32, bytes: &[u8]) {
+        let start = (address - DATA_START) as usize;
+        self.bytes[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn put_string(&mut self, s: &str) -> u32 {
+        let bytes = to_string_with_special_char(s).into_bytes();
+        let address = self.alloc(bytes.len() as u32 + 1, 1);
+        self.put(address, &bytes);
+        address
+    }
+
+    /// The address of a constant string, NUL terminated and with its
+    /// escapes replaced. Equal strings share their address.
+    pub fn string(&mut self, s: &str) -> u32 {
+        if let Some(address) = self.strings.get(s) {
+            return *address;
+        }
+        let address = self.put_string(s);
+        self.strings.insert(s.to_string(), address);
+        address
+    }
+
+    /// Lay out a global initialized to `value` and return its address.
+    /// A string global is NUL terminated, like a string constant.
+    pub fn global(&mut self, value: &MirageValueEnum) -> CompilerResult<u32> {
+        if let Some(s) = value.try_to_rust_string() {
+            return Ok(self.put_string(&s));
+        }
+        let ty = value.get_type();
+        let address = self.alloc(size_of(&ty), align_of(&ty));
+        self.write(address, value)?;
+        Ok(address)
+    }
+
+    fn write(&mut self, address: u32, value: &MirageValueEnum) -> CompilerResult<()> {
+        match value {
+            MirageValueEnum::Int8(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::Int16(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::Int32(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::Int64(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::UInt8(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::UInt16(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::UInt32(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::UInt64(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::Float32(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::Float64(v) => self.put(address, &v.value.to_le_bytes()),
+            MirageValueEnum::Array(a) => {
+                let size = size_of(&a.ty.element_ty());
+                for (i, v) in a.values.iter().enumerate() {
+                    self.write(address + i as u32 * size, v)?;
+                }
+            }
+            MirageValueEnum::Struct(s) => {
+                let (_, offsets) = struct_layout(&s.ty.fields);
+                for (v, offset) in s.values.iter().zip(offsets) {
+                    self.write(address + offset, v)?;
+                }
+            }
+            MirageValueEnum::Pointer(_) | MirageValueEnum::Register(_) => {
+                return Err(CompilerError::Unsupported(value.print_to_string()))
+            }
+        }
+        Ok(())
+    }
+}
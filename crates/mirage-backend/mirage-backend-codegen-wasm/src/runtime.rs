@@ -0,0 +1,414 @@
+use mirage_backend_output::jit::{JitError, JitResult};
+use wasmi::{
+    Caller, Engine, Error, Extern, Instance, Linker, Module, Store, WasmParams, WasmResults,
+};
+
+use crate::layout::align_to;
+
+/// The externs the host provides, in the `env` module
+const HOST_FUNCTIONS: [&str; 3] = ["printf", "puts", "putchar"];
+
+/// The state of the host functions
+#[derive(Debug, Default)]
+struct Host {
+    stdout: Vec<u8>,
+}
+
+fn backend(err: impl std::fmt::Display) -> JitError {
+    JitError::Backend(err.to_string())
+}
+
+/// A session running a module compiled by the `Compiler`, with the wasmi
+/// interpreter.
+///
+/// The host provides `printf`, `puts` and `putchar`, which write to a
+/// buffer read back with `stdout`, so that tests can check what a program
+/// prints. `printf` follows the calling convention of the compiler for
+/// variadic externs, and reads a `%l` argument as 64 bits, as on the
+/// targets of the LLVM backend.
+pub struct WasmSession {
+    store: Store<Host>,
+    instance: Instance,
+}
+
+impl WasmSession {
+    /// Instantiate the bytes of a compiled module
+    pub fn new(wasm: &[u8]) -> JitResult<Self> {
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm).map_err(backend)?;
+        if let Some(import) = module
+            .imports()
+            .find(|i| i.module() != "env" || !HOST_FUNCTIONS.contains(&i.name()))
+        {
+            return Err(JitError::UnknownExtern(import.name().to_string()));
+        }
+
+        let mut linker = Linker::<Host>::new(&engine);
+        linker
+            .func_wrap("env", "printf", printf)
+            .and_then(|l| l.func_wrap("env", "puts", puts))
+            .and_then(|l| l.func_wrap("env", "putchar", putchar))
+            .map_err(backend)?;
+        let mut store = Store::new(&engine, Host::default());
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(backend)?;
+        Ok(Self { store, instance })
+    }
+
+    /// Whether the module exports a function named `name`
+    pub fn contains_function(&self, name: &str) -> bool {
+        self.instance.get_func(&self.store, name).is_some()
+    }
+
+    /// Call the exported function `name`, whose wasm signature must be
+    /// `P -> R`
+    pub fn call<P: WasmParams, R: WasmResults>(&mut self, name: &str, params: P) -> JitResult<R> {
+        let func = self
+            .instance
+            .get_func(&self.store, name)
+            .ok_or_else(|| JitError::UnknownFunction(name.to_string()))?;
+        let typed = func
+            .typed::<P, R>(&self.store)
+            .map_err(|_| JitError::SignatureMismatch {
+                name: name.to_string(),
+                expected: format!("{:?}", func.ty(&self.store)),
+                found: format!(
+                    "{} -> {}",
+                    std::any::type_name::<P>(),
+                    std::any::type_name::<R>()
+                ),
+            })?;
+        typed.call(&mut self.store, params).map_err(backend)
+    }
+
+    /// What the program has printed so far
+    pub fn stdout(&self) -> String {
+        String::from_utf8_lossy(&self.store.data().stdout).into_owned()
+    }
+}
+
+fn memory<'a>(caller: &'a Caller<'_, Host>) -> Result<&'a [u8], Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Error::new("the module doesn't export its memory"))?;
+    Ok(memory.data(caller))
+}
+
+/// The NUL terminated string at `address`
+fn c_string(memory: &[u8], address: i32) -> Result<&[u8], Error> {
+    let s = memory
+        .get(address as u32 as usize..)
+        .ok_or_else(|| Error::new("string out of bounds"))?;
+    let len = s
+        .iter()
+        .position(|c| *c == 0)
+        .ok_or_else(|| Error::new("string without a NUL"))?;
+    Ok(&s[..len])
+}
+
+fn printf(mut caller: Caller<'_, Host>, format: i32, args: i32) -> Result<i32, Error> {
+    let memory = memory(&caller)?;
+    let mut args = VarArgs {
+        memory,
+        next: args as u32 as usize,
+    };
+    let out = format_printf(c_string(memory, format)?, &mut args)?;
+    let len = out.len() as i32;
+    caller.data_mut().stdout.extend(out);
+    Ok(len)
+}
+
+fn puts(mut caller: Caller<'_, Host>, s: i32) -> Result<i32, Error> {
+    let s = c_string(memory(&caller)?, s)?.to_vec();
+    let stdout = &mut caller.data_mut().stdout;
+    stdout.extend(s);
+    stdout.push(b'\n');
+    Ok(0)
+}
+
+fn putchar(mut caller: Caller<'_, Host>, c: i32) -> i32 {
+    caller.data_mut().stdout.push(c as u8);
+    c
+}
+
+/// The variadic arguments of a call, read from the buffer the compiler
+/// writes them to
+pub(crate) struct VarArgs<'a> {
+    pub memory: &'a [u8],
+    pub next: usize,
+}
+
+impl VarArgs<'_> {
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let at = align_to(self.next as u32, N as u32) as usize;
+        let bytes = self
+            .memory
+            .get(at..at + N)
+            .ok_or_else(|| Error::new("variadic argument out of bounds"))?;
+        self.next = at + N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn int(&mut self, size: Size) -> Result<i64, Error> {
+        Ok(match size {
+            Size::Char => i32::from_le_bytes(self.read()?) as i8 as i64,
+            Size::Short => i32::from_le_bytes(self.read()?) as i16 as i64,
+            Size::Int => i32::from_le_bytes(self.read()?) as i64,
+            Size::Long => i64::from_le_bytes(self.read()?),
+        })
+    }
+
+    fn uint(&mut self, size: Size) -> Result<u64, Error> {
+        Ok(match size {
+            Size::Char => i32::from_le_bytes(self.read()?) as u8 as u64,
+            Size::Short => i32::from_le_bytes(self.read()?) as u16 as u64,
+            Size::Int => u32::from_le_bytes(self.read()?) as u64,
+            Size::Long => u64::from_le_bytes(self.read()?),
+        })
+    }
+
+    fn float(&mut self) -> Result<f64, Error> {
+        Ok(f64::from_le_bytes(self.read()?))
+    }
+}
+
+/// The size of an integer argument, from its length modifier
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Size {
+    Char,
+    Short,
+    Int,
+    Long,
+}
+
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// A width or a precision: digits, or `*` for an `int` argument
+fn number(format: &[u8], i: &mut usize, args: &mut VarArgs) -> Result<usize, Error> {
+    if format.get(*i) == Some(&b'*') {
+        *i += 1;
+        return Ok(args.int(Size::Int)?.max(0) as usize);
+    }
+    let mut n = 0;
+    while let Some(d) = format.get(*i).filter(|d| d.is_ascii_digit()) {
+        n = n * 10 + (d - b'0') as usize;
+        *i += 1;
+    }
+    Ok(n)
+}
+
+/// Format `%e` as C does, with at least two digits of exponent
+fn exponent(v: f64, precision: usize, upper: bool) -> String {
+    let s = format!("{:.*e}", precision, v);
+    let (mantissa, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let sign = if exp < 0 { '-' } else { '+' };
+    let e = if upper { 'E' } else { 'e' };
+    format!("{}{}{}{:02}", mantissa, e, sign, exp.abs())
+}
+
+fn strip_zeros(s: String) -> String {
+    if !s.contains('.') {
+        return s;
+    }
+    let (mantissa, exp) = match s.find(['e', 'E']) {
+        Some(at) => s.split_at(at),
+        None => (s.as_str(), ""),
+    };
+    format!(
+        "{}{}",
+        mantissa.trim_end_matches('0').trim_end_matches('.'),
+        exp
+    )
+}
+
+/// Format a float conversion of `v`, whose sign is handled by the caller
+fn float(v: f64, conv: u8, spec: &Spec) -> String {
+    let upper = conv.is_ascii_uppercase();
+    if !v.is_finite() {
+        let s = if v.is_nan() { "nan" } else { "inf" };
+        return if upper {
+            s.to_uppercase()
+        } else {
+            s.to_string()
+        };
+    }
+    let precision = spec.precision.unwrap_or(6);
+    match conv.to_ascii_lowercase() {
+        b'f' => format!("{:.*}", precision, v),
+        b'e' => exponent(v, precision, upper),
+        _ => {
+            let p = precision.max(1);
+            let exp: i32 = format!("{:.*e}", p - 1, v)
+                .split_once('e')
+                .unwrap()
+                .1
+                .parse()
+                .unwrap();
+            let s = if exp < -4 || exp >= p as i32 {
+                exponent(v, p - 1, upper)
+            } else {
+                format!("{:.*}", (p as i32 - 1 - exp) as usize, v)
+            };
+            if spec.alternate {
+                s
+            } else {
+                strip_zeros(s)
+            }
+        }
+    }
+}
+
+fn pad(out: &mut Vec<u8>, spec: &Spec, sign: &str, body: &[u8], numeric: bool) {
+    let len = sign.len() + body.len();
+    let fill = spec.width.saturating_sub(len);
+    if spec.left {
+        out.extend(sign.bytes());
+        out.extend(body);
+        out.extend(std::iter::repeat_n(b' ', fill));
+    } else if spec.zero && numeric {
+        out.extend(sign.bytes());
+        out.extend(std::iter::repeat_n(b'0', fill));
+        out.extend(body);
+    } else {
+        out.extend(std::iter::repeat_n(b' ', fill));
+        out.extend(sign.bytes());
+        out.extend(body);
+    }
+}
+
+/// What `printf(format, args...)` prints
+pub(crate) fn format_printf(format: &[u8], args: &mut VarArgs) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while let Some(c) = format.get(i) {
+        i += 1;
+        if *c != b'%' {
+            out.push(*c);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(flag) = format.get(i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        spec.width = number(format, &mut i, args)?;
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = Some(number(format, &mut i, args)?);
+        }
+        let mut size = Size::Int;
+        while let Some(modifier) = format.get(i) {
+            size = match modifier {
+                b'h' if size == Size::Short => Size::Char,
+                b'h' => Size::Short,
+                b'l' | b'j' | b'q' => Size::Long,
+                b'z' | b't' => Size::Int,
+                _ => break,
+            };
+            i += 1;
+        }
+        let conv = *format
+            .get(i)
+            .ok_or_else(|| Error::new("incomplete printf conversion"))?;
+        i += 1;
+
+        let sign = |negative: bool| match negative {
+            true => "-",
+            false if spec.plus => "+",
+            false if spec.space => " ",
+            false => "",
+        };
+        // The minimum number of digits of an integer
+        let digits = |s: String| {
+            let min = spec.precision.unwrap_or(1);
+            let mut body = "0".repeat(min.saturating_sub(s.len())).into_bytes();
+            if min > 0 || s != "0" {
+                body.extend(s.bytes());
+            }
+            body
+        };
+        match conv {
+            b'%' => out.push(b'%'),
+            b'd' | b'i' => {
+                let v = args.int(size)?;
+                let body = digits(v.unsigned_abs().to_string());
+                pad(
+                    &mut out,
+                    &spec,
+                    sign(v < 0),
+                    &body,
+                    spec.precision.is_none(),
+                );
+            }
+            b'u' | b'x' | b'X' | b'o' => {
+                let v = args.uint(size)?;
+                let s = match conv {
+                    b'u' => v.to_string(),
+                    b'x' => format!("{:x}", v),
+                    b'X' => format!("{:X}", v),
+                    _ => format!("{:o}", v),
+                };
+                let prefix = match conv {
+                    b'x' if spec.alternate && v != 0 => "0x",
+                    b'X' if spec.alternate && v != 0 => "0X",
+                    _ => "",
+                };
+                let body = digits(s);
+                pad(&mut out, &spec, prefix, &body, spec.precision.is_none());
+            }
+            b'p' => {
+                let v = args.uint(Size::Int)?;
+                pad(&mut out, &spec, "0x", format!("{:x}", v).as_bytes(), false);
+            }
+            b'c' => {
+                let c = args.int(Size::Int)? as u8;
+                pad(&mut out, &spec, "", &[c], false);
+            }
+            b's' => {
+                let address = args.int(Size::Int)? as i32;
+                let s = c_string(args.memory, address)?;
+                let s = &s[..spec.precision.unwrap_or(s.len()).min(s.len())];
+                pad(&mut out, &spec, "", s, false);
+            }
+            b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+                let v = args.float()?;
+                let body = float(v.abs(), conv, &spec);
+                pad(
+                    &mut out,
+                    &spec,
+                    sign(v.is_sign_negative() && !v.is_nan()),
+                    body.as_bytes(),
+                    v.is_finite(),
+                );
+            }
+            _ => {
+                return Err(Error::new(format!(
+                    "unsupported printf conversion `%{}`",
+                    conv as char
+                )))
+            }
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,188 @@
+use mirage_backend_opti::analysis::cfg::Cfg;
+use mirage_backend_opti::analysis::dominators::Dominators;
+
+/// How a branch reaches its target in the structured code
+#[derive(Debug, Clone, PartialEq)]
+pub enum Branch {
+    /// `br` out of the `block` which the target follows
+    Break,
+    /// `br` back to the `loop` the target heads
+    Continue,
+    /// The target is only entered from here, and its code is placed inline
+    Inline(Box<Shape>),
+    /// Set the next block of the enclosing dispatch loop and `br` to it
+    Dispatch,
+}
+
+/// A control flow edge of the CFG
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub target: usize,
+    pub branch: Branch,
+}
+
+/// How the code of a block is left
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exit {
+    /// No successor: the block returns, or the function falls off its end
+    None,
+    /// A `jump`, a `jeq` whose two targets are the same, or falling
+    /// through to the next label
+    Jump(Edge),
+    /// A `jeq`: `taken` when its operands are equal, else `fallthrough`
+    Cond { taken: Edge, fallthrough: Edge },
+}
+
+/// The structured control flow of a function, as wasm nests it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    /// The instructions of `block`, then how it is left
+    Code { block: usize, exit: Exit },
+    /// `loop { body }`, continued by the branches back to `header`
+    Loop { header: usize, body: Box<Shape> },
+    /// `block { body }` then `next`, the code of `follow`: the branches
+    /// to `follow` from `body` break out of the block
+    Block {
+        follow: usize,
+        body: Box<Shape>,
+        next: Box<Shape>,
+    },
+    /// A loop around a `br_table` over `blocks`, used when the control
+    /// flow is irreducible. The first block is entered first.
+    Dispatch { blocks: Vec<(usize, Exit)> },
+}
+
+struct Stackifier<'a> {
+    cfg: &'a Cfg,
+    dom: &'a Dominators,
+    /// The position of each block in reverse post-order
+    order: Vec<usize>,
+}
+
+impl Stackifier<'_> {
+    fn is_backward(&self, from: usize, to: usize) -> bool {
+        self.order[to] <= self.order[from]
+    }
+
+    fn reachable_preds(&self, b: usize) -> impl Iterator<Item = usize> + '_ {
+        self.cfg.preds[b]
+            .iter()
+            .copied()
+            .filter(|p| self.order[*p] != usize::MAX)
+    }
+
+    /// Whether `b` is entered by more than one forward edge
+    fn is_merge(&self, b: usize) -> bool {
+        self.reachable_preds(b)
+            .filter(|p| !self.is_backward(*p, b))
+            .count()
+            > 1
+    }
+
+    fn is_loop_header(&self, b: usize) -> bool {
+        self.reachable_preds(b).any(|p| self.is_backward(p, b))
+    }
+
+    /// Whether every backward edge goes to a block dominating its source,
+    /// so that each loop has a single entry
+    fn is_reducible(&self) -> bool {
+        (0..self.cfg.len())
+            .filter(|b| self.order[*b] != usize::MAX)
+            .all(|b| {
+                self.cfg.succs[b]
+                    .iter()
+                    .all(|s| !self.is_backward(b, *s) || self.dom.dominates(*s, b))
+            })
+    }
+
+    /// The code of `b` and of the blocks it dominates
+    fn tree(&self, b: usize) -> Shape {
+        let mut merges: Vec<usize> = self
+            .dom
+            .children(b)
+            .iter()
+            .copied()
+            .filter(|c| self.is_merge(*c))
+            .collect();
+        merges.sort_unstable_by_key(|m| self.order[*m]);
+        let shape = self.within(b, &merges);
+        if self.is_loop_header(b) {
+            Shape::Loop {
+                header: b,
+                body: Box::new(shape),
+            }
+        } else {
+            shape
+        }
+    }
+
+    /// The code of `b` inside one block per merge node it dominates, the
+    /// last one outermost
+    fn within(&self, b: usize, merges: &[usize]) -> Shape {
+        match merges.split_last() {
+            None => Shape::Code {
+                block: b,
+                exit: self.exit(b, |to| self.edge(b, to)),
+            },
+            Some((follow, inner)) => Shape::Block {
+                follow: *follow,
+                body: Box::new(self.within(b, inner)),
+                next: Box::new(self.tree(*follow)),
+            },
+        }
+    }
+
+    fn edge(&self, from: usize, to: usize) -> Edge {
+        let branch = if self.is_backward(from, to) {
+            Branch::Continue
+        } else if self.is_merge(to) {
+            Branch::Break
+        } else {
+            Branch::Inline(Box::new(self.tree(to)))
+        };
+        Edge { target: to, branch }
+    }
+
+    fn exit(&self, b: usize, edge: impl Fn(usize) -> Edge) -> Exit {
+        // A `jeq` has its target first, then the next block
+        match self.cfg.succs[b][..] {
+            [] => Exit::None,
+            [to] => Exit::Jump(edge(to)),
+            [taken, fallthrough, ..] => Exit::Cond {
+                taken: edge(taken),
+                fallthrough: edge(fallthrough),
+            },
+        }
+    }
+}
+
+/// Recover structured control flow from the CFG of a function, with the
+/// algorithm of Ramsey's "Beyond Relooper".
+///
+/// Every block is placed in the subtree of its immediate dominator. A
+/// block entered by several forward edges follows a wasm `block` which
+/// the branches to it break out of, and a loop header starts a wasm
+/// `loop`. Control flow with a loop entered in more than one place has
+/// no such nesting, and is run by a dispatch loop instead.
+pub fn stackify(cfg: &Cfg, dom: &Dominators) -> Shape {
+    let rpo = cfg.reverse_post_order();
+    let mut order = vec![usize::MAX; cfg.len()];
+    for (i, b) in rpo.iter().enumerate() {
+        order[*b] = i;
+    }
+    let stackifier = Stackifier { cfg, dom, order };
+    if stackifier.is_reducible() {
+        return stackifier.tree(0);
+    }
+    let blocks = rpo
+        .iter()
+        .map(|b| {
+            let exit = stackifier.exit(*b, |to| Edge {
+                target: to,
+                branch: Branch::Dispatch,
+            });
+            (*b, exit)
+        })
+        .collect();
+    Shape::Dispatch { blocks }
+}
@@ -0,0 +1,404 @@
+use mirage_backend_opti::analysis::cfg::Cfg;
+use mirage_backend_opti::analysis::dominators::Dominators;
+use mirage_backend_output::jit::JitError;
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::{Flag, Flags};
+use mirage_frontend::object::statements::{External, Global, Statement, TypeDef};
+use mirage_frontend::object::util::List;
+use mirage_frontend::object::{
+    MirageObject, MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue,
+};
+use walrus::{ExportItem, ValType};
+
+use crate::layout::struct_layout;
+use crate::runtime::{format_printf, VarArgs};
+use crate::stackify::{stackify, Shape};
+use crate::{Compiler, CompilerError, WasmSession};
+
+fn i32_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int32().into()
+}
+
+fn i64_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int64().into()
+}
+
+fn reg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Register, ty)
+}
+
+fn arg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Argument, ty)
+}
+
+fn val(reg: &RegisterValue) -> Value {
+    Value::Register(reg.clone())
+}
+
+fn i32_obj(v: i32) -> MirageObject {
+    MirageObject::from(MirageTypeEnum::type_int32().const_value(v).to_value_enum())
+}
+
+fn i32_val(v: i32) -> Value {
+    Value::ConstValue(i32_obj(v))
+}
+
+fn assign(reg: &RegisterValue, cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(reg.clone(), Box::new(LabelBodyInstr::Command(cmd)))
+}
+
+fn cmd(cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Command(cmd)
+}
+
+fn label(name: &str, body: Vec<LabelBodyInstr>) -> Label {
+    Label::new(name.to_string(), Flags::new(vec![]), body)
+}
+
+fn function(name: &str, args: Vec<MirageTypeEnum>, labels: Vec<Label>) -> Statement {
+    let mut f = FunctionType::new(args, i32_ty(), false).fn_value(name.to_string());
+    for l in labels {
+        f.add_label(l);
+    }
+    Statement::Function(f)
+}
+
+fn string(s: &str) -> MirageValueEnum {
+    let ty = MirageTypeEnum::type_array(MirageTypeEnum::type_int8().into(), s.len());
+    MirageValueEnum::Array(
+        ty.const_value(
+            s.bytes()
+                .map(|c| {
+                    MirageTypeEnum::type_int8()
+                        .const_value(c as i8)
+                        .to_value_enum()
+                })
+                .collect(),
+        ),
+    )
+}
+
+/// Compile `stmts`, parse the wasm back, which validates it, and
+/// instantiate it
+fn compile(stmts: Vec<Statement>) -> (walrus::Module, WasmSession) {
+    let mut compiler = Compiler::new(stmts);
+    compiler.compile().unwrap();
+    let wasm = compiler.emit_wasm();
+    (
+        walrus::Module::from_buffer(&wasm).unwrap(),
+        WasmSession::new(&wasm).unwrap(),
+    )
+}
+
+fn exports(module: &walrus::Module) -> Vec<&str> {
+    let mut names: Vec<&str> = module.exports.iter().map(|e| e.name.as_str()).collect();
+    names.sort_unstable();
+    names
+}
+
+fn shape(stmt: &Statement) -> Shape {
+    let Statement::Function(f) = stmt else {
+        panic!("not a function");
+    };
+    let cfg = Cfg::new(f);
+    stackify(&cfg, &Dominators::new(&cfg))
+}
+
+/// Whether `shape` nests a shape matching `f`
+fn contains(shape: &Shape, f: &impl Fn(&Shape) -> bool) -> bool {
+    use crate::stackify::{Branch, Exit};
+    let exit = |exit: &Exit| match exit {
+        Exit::None => false,
+        Exit::Jump(e) => matches!(&e.branch, Branch::Inline(s) if contains(s, f)),
+        Exit::Cond { taken, fallthrough } => [taken, fallthrough]
+            .iter()
+            .any(|e| matches!(&e.branch, Branch::Inline(s) if contains(s, f))),
+    };
+    f(shape)
+        || match shape {
+            Shape::Code { exit: e, .. } => exit(e),
+            Shape::Loop { body, .. } => contains(body, f),
+            Shape::Block { body, next, .. } => contains(body, f) || contains(next, f),
+            Shape::Dispatch { blocks } => blocks.iter().any(|(_, e)| exit(e)),
+        }
+}
+
+fn counter_loop() -> Statement {
+    let a = arg(0, i32_ty());
+    let r = |i| reg(i, i32_ty());
+    function(
+        "count",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    assign(&r(0), Command::Const(i32_obj(0))),
+                    assign(&r(1), Command::Ref(val(&r(0)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(&r(2), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Jeq("end".to_string(), val(&r(2)), val(&a))),
+                    assign(&r(3), Command::AddInt32(val(&r(2)), i32_val(1))),
+                    cmd(Command::Store(r(1), val(&r(3)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "end",
+                vec![
+                    assign(&r(4), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    )
+}
+
+#[test]
+fn test_exports_and_imports() {
+    let printf_ty = FunctionType::new(
+        vec![MirageTypeEnum::type_ptr(MirageTypeEnum::type_int8().into()).into()],
+        i32_ty(),
+        true,
+    );
+    let mut helper = label("entry", vec![cmd(Command::Ret(val(&arg(0, i32_ty()))))]);
+    helper.flags.push(Flag::internal());
+    let g = RegisterValue::new(0, RegisterType::Global, string("%d\n").get_type());
+    let (module, mut session) = compile(vec![
+        Statement::External(External::new("printf".to_string(), printf_ty)),
+        Statement::Global(Global::new(
+            "g0".to_string(),
+            MirageObject::from(string("%d\n")),
+        )),
+        function("helper", vec![i32_ty()], vec![helper]),
+        function(
+            "main",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    LabelBodyInstr::Assign(
+                        reg(0, i32_ty()),
+                        Box::new(LabelBodyInstr::Call(
+                            "helper".to_string(),
+                            vec![i32_val(42)],
+                        )),
+                    ),
+                    LabelBodyInstr::Call(
+                        "printf".to_string(),
+                        vec![val(&g), val(&reg(0, i32_ty()))],
+                    ),
+                    cmd(Command::Ret(i32_val(0))),
+                ],
+            )],
+        ),
+    ]);
+
+    assert_eq!(exports(&module), ["main", "memory"]);
+    let import = module.imports.iter().next().unwrap();
+    assert_eq!(
+        (import.module.as_str(), import.name.as_str()),
+        ("env", "printf")
+    );
+    let walrus::ImportKind::Function(printf) = import.kind else {
+        panic!("printf isn't imported as a function");
+    };
+    let ty = module.types.get(module.funcs.get(printf).ty());
+    assert_eq!(ty.params(), [ValType::I32, ValType::I32]);
+    assert_eq!(ty.results(), [ValType::I32]);
+
+    let data = module.data.iter().next().unwrap();
+    assert!(data.value.starts_with(b"%d\n\0"));
+    let memory = module
+        .exports
+        .iter()
+        .find_map(|e| match e.item {
+            ExportItem::Memory(m) => Some(m),
+            _ => None,
+        })
+        .unwrap();
+    assert!(module.memories.get(memory).initial >= 2);
+
+    assert!(!session.contains_function("helper"));
+    assert_eq!(session.call::<(), i32>("main", ()), Ok(0));
+    assert_eq!(session.stdout(), "42\n");
+    assert!(matches!(
+        session.call::<i32, i32>("main", 1),
+        Err(JitError::SignatureMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_loop() {
+    let count = counter_loop();
+    assert!(contains(&shape(&count), &|s| matches!(
+        s,
+        Shape::Loop { header: 1, .. }
+    )));
+    let (module, mut session) = compile(vec![count]);
+    assert_eq!(exports(&module), ["count", "memory"]);
+    assert_eq!(session.call::<i32, i32>("count", 5), Ok(5));
+}
+
+#[test]
+fn test_diamond_with_phis() {
+    let a = arg(0, i64_ty());
+    let r = |i| reg(i, i64_ty());
+    let i64_val = |v: i64| {
+        Value::ConstValue(MirageObject::from(
+            MirageTypeEnum::type_int64().const_value(v).to_value_enum(),
+        ))
+    };
+    let max = function(
+        "max0",
+        vec![i64_ty()],
+        vec![
+            label(
+                "entry",
+                vec![cmd(Command::Jeq("zero".to_string(), val(&a), i64_val(0)))],
+            ),
+            label(
+                "other",
+                vec![
+                    assign(&r(0), Command::SubInt64(val(&a), i64_val(1))),
+                    cmd(Command::Jump("join".to_string())),
+                ],
+            ),
+            label("zero", vec![cmd(Command::Jump("join".to_string()))]),
+            label(
+                "join",
+                vec![
+                    assign(
+                        &r(1),
+                        Command::Phi(vec![
+                            ("other".to_string(), val(&r(0))),
+                            ("zero".to_string(), i64_val(7)),
+                        ]),
+                    ),
+                    assign(&reg(2, i32_ty()), Command::Const(i32_obj(1))),
+                    cmd(Command::Ret(val(&reg(2, i32_ty())))),
+                ],
+            ),
+        ],
+    );
+    assert!(contains(&shape(&max), &|s| matches!(
+        s,
+        Shape::Block { follow: 3, .. }
+    )));
+    let (_, mut session) = compile(vec![max]);
+    assert_eq!(session.call::<i64, i32>("max0", 0), Ok(1));
+    assert_eq!(session.call::<i64, i32>("max0", 5), Ok(1));
+}
+
+#[test]
+fn test_irreducible() {
+    let a = arg(0, i32_ty());
+    let f = function(
+        "f",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![cmd(Command::Jeq("b".to_string(), val(&a), i32_val(0)))],
+            ),
+            label(
+                "c",
+                vec![cmd(Command::Jeq("end".to_string(), val(&a), i32_val(1)))],
+            ),
+            label("b", vec![cmd(Command::Jump("c".to_string()))]),
+            label("end", vec![cmd(Command::Ret(val(&a)))]),
+        ],
+    );
+    let Shape::Dispatch { blocks } = shape(&f) else {
+        panic!("an irreducible graph needs a dispatch loop");
+    };
+    assert_eq!(blocks[0].0, 0);
+    assert_eq!(blocks.len(), 4);
+    let (_, mut session) = compile(vec![f]);
+    assert_eq!(session.call::<i32, i32>("f", 1), Ok(1));
+}
+
+#[test]
+fn test_structs() {
+    let fields = vec![i32_ty(), i64_ty()];
+    assert_eq!(struct_layout(&fields), (16, vec![0, 8]));
+    let point: MirageTypeEnum = MirageTypeEnum::type_struct(fields.clone()).into();
+    let p = reg(0, point);
+    let (module, mut session) = compile(vec![
+        Statement::Typedef(TypeDef::new("point".to_string(), List::from_vec(fields))),
+        function(
+            "first",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &p,
+                        Command::New(
+                            "point".to_string(),
+                            List::from_vec(vec![
+                                val(&arg(0, i32_ty())),
+                                Value::ConstValue(MirageObject::from(
+                                    MirageTypeEnum::type_int64().const_value(0).to_value_enum(),
+                                )),
+                            ]),
+                        ),
+                    ),
+                    assign(&reg(1, i32_ty()), Command::Get(p.clone(), 0)),
+                    cmd(Command::Ret(val(&reg(1, i32_ty())))),
+                ],
+            )],
+        ),
+    ]);
+    assert_eq!(exports(&module), ["first", "memory"]);
+    assert_eq!(session.call::<i32, i32>("first", 9), Ok(9));
+
+    let mut compiler = Compiler::new(vec![function(
+        "f",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign(&p, Command::New("line".to_string(), List::from_vec(vec![]))),
+                cmd(Command::Ret(i32_val(0))),
+            ],
+        )],
+    )]);
+    assert_eq!(
+        compiler.compile(),
+        Err(CompilerError::UnknownType("line".to_string()))
+    );
+}
+
+#[test]
+fn test_printf() {
+    let mut memory = b"hi\0".to_vec();
+    memory.resize(8, 0);
+    for arg in [
+        (-42i32).to_le_bytes(),
+        255i32.to_le_bytes(),
+        0i32.to_le_bytes(),
+    ] {
+        memory.extend(arg);
+    }
+    memory.extend([0; 4]);
+    memory.extend((1i64 << 40).to_le_bytes());
+    memory.extend(1.5f64.to_le_bytes());
+    memory.extend(0.0001f64.to_le_bytes());
+    let mut args = VarArgs {
+        memory: &memory,
+        next: 8,
+    };
+    let out = format_printf(b"%5d|%-4x|%s!|%ld|%.2f %g%%", &mut args).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "  -42|ff  |hi!|1099511627776|1.50 0.0001%"
+    );
+    assert!(format_printf(b"%k", &mut args).is_err());
+}
@@ -0,0 +1,307 @@
+use mirage_backend_interpreter::{Interpreter, Kind, Val};
+use mirage_backend_opti::OptiLevel;
+use mirage_backend_output::jit::JitError;
+use mirage_frontend::object::statements::Statement;
+
+use crate::host::register_stdio;
+use crate::native::{cc_level, has_cc, link_and_run, Scratch};
+use crate::{Outcome, FUEL};
+
+/// Why a backend didn't give an outcome
+/// # Variants
+/// * `Unsupported` - The backend can't compile the program, which it says
+///   so: the run is skipped
+/// * `Failed` - The backend failed to compile the program, or the program
+///   failed to link or to run
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunError {
+    Unsupported(String),
+    Failed(String),
+}
+
+pub type RunResult<T> = Result<T, RunError>;
+
+/// A way to run a program, which the harness compares with the interpreter
+pub trait Backend {
+    /// The name of the backend in the reports
+    fn name(&self) -> &str;
+
+    /// Compile `stmts`, optimized for `level`, and call `entry`, which takes
+    /// no argument and returns a value of `ret`
+    fn run(
+        &self,
+        stmts: &[Statement],
+        entry: &str,
+        ret: Kind,
+        level: OptiLevel,
+    ) -> RunResult<Outcome>;
+}
+
+/// Every backend which can run on this machine. The backends linking with
+/// the system `cc` are left out when it isn't installed.
+pub fn available_backends() -> Vec<Box<dyn Backend>> {
+    let mut backends: Vec<Box<dyn Backend>> = vec![Box::new(Interpreted), Box::new(Wasm)];
+    if has_cc() {
+        backends.push(Box::new(CBackend));
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        backends.push(Box::new(Asm));
+        #[cfg(feature = "cranelift")]
+        backends.push(Box::new(Cranelift));
+    }
+    #[cfg(feature = "llvm")]
+    backends.push(Box::new(Llvm));
+    backends
+}
+
+/// The native backends link with a driver defining `main`
+fn check_no_main(stmts: &[Statement]) -> RunResult<()> {
+    let defines_main = stmts
+        .iter()
+        .any(|stmt| matches!(stmt, Statement::Function(f) if f.get_name() == "main"));
+    match defines_main {
+        true => Err(RunError::Unsupported(
+            "the program defines `main`".to_string(),
+        )),
+        false => Ok(()),
+    }
+}
+
+/// The interpreter, run on the optimized program
+pub struct Interpreted;
+
+impl Backend for Interpreted {
+    fn name(&self) -> &str {
+        "interpreter"
+    }
+
+    fn run(&self, stmts: &[Statement], entry: &str, _: Kind, _: OptiLevel) -> RunResult<Outcome> {
+        let mut interpreter = Interpreter::new(stmts.to_vec());
+        interpreter.set_fuel(Some(FUEL));
+        let stdout = register_stdio(&mut interpreter);
+        let value = interpreter
+            .call(entry, &[])
+            .map_err(|trap| RunError::Failed(trap.to_string()))?;
+        let stdout = String::from_utf8_lossy(&stdout.borrow()).into_owned();
+        Ok(Outcome { value, stdout })
+    }
+}
+
+/// The C backend, built with the system `cc` at the same level
+pub struct CBackend;
+
+impl Backend for CBackend {
+    fn name(&self) -> &str {
+        "c"
+    }
+
+    fn run(
+        &self,
+        stmts: &[Statement],
+        entry: &str,
+        ret: Kind,
+        level: OptiLevel,
+    ) -> RunResult<Outcome> {
+        use mirage_backend_codegen_c::{CodeGen, CodeGenError};
+
+        check_no_main(stmts)?;
+        let mut codegen = CodeGen::new(stmts.to_vec());
+        codegen.compile().map_err(|e| match e {
+            CodeGenError::Unsupported(what) => RunError::Unsupported(what),
+            e => RunError::Failed(format!("{:?}", e)),
+        })?;
+        let scratch = Scratch::new()?;
+        let source = scratch.write("program.c", codegen.emit_c())?;
+        link_and_run(
+            &scratch,
+            &[&source],
+            &["-std=c11", cc_level(level)],
+            entry,
+            ret,
+        )
+    }
+}
+
+/// The native code generator, encoding an x86-64 object without an
+/// assembler
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub struct Asm;
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+impl Backend for Asm {
+    fn name(&self) -> &str {
+        "asm"
+    }
+
+    fn run(&self, stmts: &[Statement], entry: &str, ret: Kind, _: OptiLevel) -> RunResult<Outcome> {
+        use mirage_backend_codegen_asm::{CodeGen, CodeGenError};
+        use mirage_frontend::module::Module;
+
+        check_no_main(stmts)?;
+        let unsupported = |e| match e {
+            CodeGenError::Unsupported(what) => RunError::Unsupported(what),
+            e => RunError::Failed(format!("{:?}", e)),
+        };
+        let mut codegen = CodeGen::new(stmts.to_vec(), Module::new("difftest".to_string()));
+        codegen.compile().map_err(unsupported)?;
+        let object = codegen.emit_object().map_err(unsupported)?;
+        let scratch = Scratch::new()?;
+        let object = scratch.write("program.o", object)?;
+        link_and_run(&scratch, &[&object], &[], entry, ret)
+    }
+}
+
+/// The wasm backend, run with the wasmi interpreter
+pub struct Wasm;
+
+impl Backend for Wasm {
+    fn name(&self) -> &str {
+        "wasm"
+    }
+
+    fn run(&self, stmts: &[Statement], entry: &str, ret: Kind, _: OptiLevel) -> RunResult<Outcome> {
+        use mirage_backend_codegen_wasm::{Compiler, CompilerError, WasmSession};
+
+        let mut compiler = Compiler::new(stmts.to_vec());
+        compiler.compile().map_err(|e| match e {
+            CompilerError::Unsupported(what) => RunError::Unsupported(what),
+            e => RunError::Failed(format!("{:?}", e)),
+        })?;
+        let mut session = WasmSession::new(&compiler.emit_wasm()).map_err(|e| match e {
+            JitError::UnknownExtern(name) => {
+                RunError::Unsupported(format!("the host has no `{}`", name))
+            }
+            e => RunError::Failed(e.to_string()),
+        })?;
+        let failed = |e: JitError| RunError::Failed(e.to_string());
+        // The integers narrower than 32 bits are returned as an `i32`
+        let value = match ret {
+            Kind::I8 | Kind::I16 | Kind::I32 | Kind::U8 | Kind::U16 | Kind::U32 => {
+                Val::I32(session.call::<(), i32>(entry, ()).map_err(failed)?).convert(ret)
+            }
+            Kind::I64 | Kind::U64 => {
+                Val::I64(session.call::<(), i64>(entry, ()).map_err(failed)?).convert(ret)
+            }
+            Kind::F32 => Val::F32(session.call::<(), f32>(entry, ()).map_err(failed)?),
+            Kind::F64 => Val::F64(session.call::<(), f64>(entry, ()).map_err(failed)?),
+            Kind::Ptr => return Err(RunError::Unsupported("a pointer result".to_string())),
+        };
+        Ok(Outcome {
+            value,
+            stdout: session.stdout(),
+        })
+    }
+}
+
+/// The Cranelift backend, its object linked with the system `cc`
+#[cfg(feature = "cranelift")]
+pub struct Cranelift;
+
+#[cfg(feature = "cranelift")]
+impl Backend for Cranelift {
+    fn name(&self) -> &str {
+        "cranelift"
+    }
+
+    fn run(
+        &self,
+        stmts: &[Statement],
+        entry: &str,
+        ret: Kind,
+        level: OptiLevel,
+    ) -> RunResult<Outcome> {
+        use mirage_backend_codegen_cranelift::{Compiler, CompilerError};
+
+        check_no_main(stmts)?;
+        let mut compiler = Compiler::new(stmts.to_vec());
+        compiler.set_opti_level(level);
+        compiler.compile().map_err(|e| match e {
+            CompilerError::Unsupported(what) => RunError::Unsupported(what),
+            e => RunError::Failed(format!("{:?}", e)),
+        })?;
+        let scratch = Scratch::new()?;
+        let object = scratch.write("program.o", compiler.emit_object())?;
+        link_and_run(&scratch, &[&object], &[], entry, ret)
+    }
+}
+
+/// The LLVM backend, run in this process by its JIT. The host provides
+/// `puts` and `putchar`, but not the variadic `printf`.
+#[cfg(feature = "llvm")]
+pub struct Llvm;
+
+#[cfg(feature = "llvm")]
+impl Backend for Llvm {
+    fn name(&self) -> &str {
+        "llvm"
+    }
+
+    fn run(
+        &self,
+        stmts: &[Statement],
+        entry: &str,
+        ret: Kind,
+        level: OptiLevel,
+    ) -> RunResult<Outcome> {
+        use crate::host::{jit_putchar, jit_puts, take_jit_stdout};
+        use mirage_backend_codegen_llvm::{Compiler, CompilerError, JitSession};
+        use mirage_backend_output::ExecutionEngineOutput;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        // What the compiler can't lower yet is reported as an error, but a
+        // few paths still panic
+        let compiled = catch_unwind(AssertUnwindSafe(|| -> Result<_, CompilerError> {
+            let mut compiler = Compiler::new(stmts.to_vec(), false)?;
+            compiler.set_opti_level(level);
+            compiler.compile()?;
+            Ok(compiler)
+        }));
+        let compiler = match compiled {
+            Ok(Ok(compiler)) => compiler,
+            Ok(Err(CompilerError::Unsupported(what))) => return Err(RunError::Unsupported(what)),
+            Ok(Err(e)) => return Err(RunError::Failed(format!("{:?}", e))),
+            Err(_) => return Err(RunError::Failed("the compiler panicked".to_string())),
+        };
+
+        let mut jit = JitSession::new(&compiler);
+        let failed = |e: JitError| RunError::Failed(e.to_string());
+        for stmt in stmts {
+            let Statement::External(e) = stmt else {
+                continue;
+            };
+            let registered = match e.name.as_str() {
+                "puts" => jit.register_symbol("puts", jit_puts as extern "C" fn(*const u8) -> i32),
+                "putchar" => {
+                    jit.register_symbol("putchar", jit_putchar as extern "C" fn(i32) -> i32)
+                }
+                name => return Err(RunError::Unsupported(format!("the host has no `{}`", name))),
+            };
+            registered.map_err(failed)?;
+        }
+
+        macro_rules! call {
+            ($t:ty, $variant:ident) => {{
+                let f: extern "C" fn() -> $t =
+                    unsafe { jit.get_function(entry) }.map_err(failed)?;
+                Val::$variant(f())
+            }};
+        }
+        take_jit_stdout();
+        let value = match ret {
+            Kind::I8 => call!(i8, I8),
+            Kind::I16 => call!(i16, I16),
+            Kind::I32 => call!(i32, I32),
+            Kind::I64 => call!(i64, I64),
+            Kind::U8 => call!(u8, U8),
+            Kind::U16 => call!(u16, U16),
+            Kind::U32 => call!(u32, U32),
+            Kind::U64 => call!(u64, U64),
+            Kind::F32 => call!(f32, F32),
+            Kind::F64 => call!(f64, F64),
+            Kind::Ptr => return Err(RunError::Unsupported("a pointer result".to_string())),
+        };
+        Ok(Outcome {
+            value,
+            stdout: take_jit_stdout(),
+        })
+    }
+}
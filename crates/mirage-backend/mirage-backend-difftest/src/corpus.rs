@@ -0,0 +1,805 @@
+//! The programs every backend is checked on. Each one exercises a part of
+//! the IR the backends tend to get wrong: the widths of integers and their
+//! wraparound, phis, memory, structs and arrays, globals, calls and
+//! variadic externs.
+
+use mirage_frontend::builder::Builder;
+use mirage_frontend::module::Module;
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::{Flag, Flags};
+use mirage_frontend::object::statements::{External, Global, Statement, TypeDef};
+use mirage_frontend::object::util::List;
+use mirage_frontend::object::{
+    MirageObject, MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue,
+};
+
+use crate::Case;
+
+fn i8_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int8().into()
+}
+
+fn i16_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int16().into()
+}
+
+fn i32_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int32().into()
+}
+
+fn i64_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int64().into()
+}
+
+fn u8_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_uint8().into()
+}
+
+fn u16_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_uint16().into()
+}
+
+fn u32_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_uint32().into()
+}
+
+fn u64_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_uint64().into()
+}
+
+fn f64_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_float64().into()
+}
+
+fn ptr_ty(ty: MirageTypeEnum) -> MirageTypeEnum {
+    MirageTypeEnum::type_ptr(ty).into()
+}
+
+fn reg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Register, ty)
+}
+
+fn arg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Argument, ty)
+}
+
+fn global(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Global, ty)
+}
+
+fn val(reg: &RegisterValue) -> Value {
+    Value::Register(reg.clone())
+}
+
+fn obj(value: MirageValueEnum) -> MirageObject {
+    MirageObject::from(value)
+}
+
+/// The integer `v`, truncated to `ty`
+fn int_obj(ty: &MirageTypeEnum, v: i64) -> MirageObject {
+    let value = match ty {
+        MirageTypeEnum::Int8(t) => t.const_value(v as i8).to_value_enum(),
+        MirageTypeEnum::Int16(t) => t.const_value(v as i16).to_value_enum(),
+        MirageTypeEnum::Int32(t) => t.const_value(v as i32).to_value_enum(),
+        MirageTypeEnum::Int64(t) => t.const_value(v).to_value_enum(),
+        MirageTypeEnum::UInt8(t) => t.const_value(v as u8).to_value_enum(),
+        MirageTypeEnum::UInt16(t) => t.const_value(v as u16).to_value_enum(),
+        MirageTypeEnum::UInt32(t) => t.const_value(v as u32).to_value_enum(),
+        MirageTypeEnum::UInt64(t) => t.const_value(v as u64).to_value_enum(),
+        _ => unreachable!("an integer type"),
+    };
+    obj(value)
+}
+
+fn int_val(ty: &MirageTypeEnum, v: i64) -> Value {
+    Value::ConstValue(int_obj(ty, v))
+}
+
+fn i32_val(v: i32) -> Value {
+    int_val(&i32_ty(), v as i64)
+}
+
+fn i64_val(v: i64) -> Value {
+    int_val(&i64_ty(), v)
+}
+
+fn f64_val(v: f64) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_float64()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn assign(reg: &RegisterValue, cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(reg.clone(), Box::new(LabelBodyInstr::Command(cmd)))
+}
+
+fn assign_call(reg: &RegisterValue, name: &str, args: Vec<Value>) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(
+        reg.clone(),
+        Box::new(LabelBodyInstr::Call(name.to_string(), args)),
+    )
+}
+
+fn call(name: &str, args: Vec<Value>) -> LabelBodyInstr {
+    LabelBodyInstr::Call(name.to_string(), args)
+}
+
+fn cmd(cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Command(cmd)
+}
+
+fn label(name: &str, body: Vec<LabelBodyInstr>) -> Label {
+    Label::new(name.to_string(), Flags::new(vec![]), body)
+}
+
+fn function(
+    name: &str,
+    args: Vec<MirageTypeEnum>,
+    ret: MirageTypeEnum,
+    labels: Vec<Label>,
+) -> Statement {
+    let mut f = FunctionType::new(args, ret, false).fn_value(name.to_string());
+    for l in labels {
+        f.add_label(l);
+    }
+    Statement::Function(f)
+}
+
+/// A NUL terminated string constant, with the escapes of the IR
+fn string(s: &str) -> MirageValueEnum {
+    let ty = MirageTypeEnum::type_array(i8_ty(), s.len());
+    MirageValueEnum::Array(
+        ty.const_value(
+            s.bytes()
+                .map(|c| {
+                    MirageTypeEnum::type_int8()
+                        .const_value(c as i8)
+                        .to_value_enum()
+                })
+                .collect(),
+        ),
+    )
+}
+
+fn string_val(s: &str) -> Value {
+    Value::ConstValue(obj(string(s)))
+}
+
+fn printf() -> Statement {
+    let ty = FunctionType::new(vec![ptr_ty(i8_ty())], i32_ty(), true);
+    Statement::External(External::new("printf".to_string(), ty))
+}
+
+fn putchar() -> Statement {
+    let ty = FunctionType::new(vec![i32_ty()], i32_ty(), false);
+    Statement::External(External::new("putchar".to_string(), ty))
+}
+
+fn puts() -> Statement {
+    let ty = FunctionType::new(vec![ptr_ty(i8_ty())], i32_ty(), false);
+    Statement::External(External::new("puts".to_string(), ty))
+}
+
+/// `add(a, b)` and an entry calling it, built with the builder
+fn builder_add() -> Case {
+    let mut builder = Builder::new(Module::new("builder_add".to_string()));
+    let fn_type = FunctionType::new(vec![i32_ty(), i32_ty()], i32_ty(), false);
+
+    let mut add = fn_type.fn_value("add".to_string());
+    let mut bb = builder.new_basic_block("entry");
+    let a = RegisterValue::new(0, RegisterType::Argument, i32_ty()).to_mirage_value();
+    let b = RegisterValue::new(1, RegisterType::Argument, i32_ty()).to_mirage_value();
+    let sum = bb
+        .build_int_add(a.expect_int_value().unwrap(), b.expect_int_value().unwrap())
+        .unwrap();
+    bb.build_ret(sum).unwrap();
+    add.add_label(bb.build());
+    builder.build_function(add);
+
+    let mut entry = FunctionType::new(vec![], i32_ty(), false).fn_value("run".to_string());
+    let mut bb = builder.new_basic_block("entry");
+    let forty = bb
+        .build_const(MirageTypeEnum::type_int32().const_value(40).to_value_enum())
+        .unwrap();
+    let minus_two = bb
+        .build_const(MirageTypeEnum::type_int32().const_value(-2).to_value_enum())
+        .unwrap();
+    let r = bb
+        .build_call("add".to_string(), vec![forty, minus_two])
+        .unwrap();
+    let r = bb
+        .build_int_sub(r.expect_int_value().unwrap(), r.expect_int_value().unwrap())
+        .unwrap();
+    let r = bb
+        .build_call("add".to_string(), vec![r, forty_two()])
+        .unwrap();
+    bb.build_ret(r).unwrap();
+    entry.add_label(bb.build());
+    builder.build_function(entry);
+
+    Case::new("builder_add", builder.asts, "run")
+}
+
+fn forty_two() -> MirageValueEnum {
+    MirageTypeEnum::type_int32().const_value(42).to_value_enum()
+}
+
+/// `count(n)`, counting to `n` in a variable whose address is taken
+fn counter_loop() -> Case {
+    let n = arg(0, i32_ty());
+    let r = |i| reg(i, i32_ty());
+    let count = function(
+        "count",
+        vec![i32_ty()],
+        i32_ty(),
+        vec![
+            label(
+                "entry",
+                vec![
+                    assign(&r(0), Command::Const(obj(forty_two()))),
+                    assign(&r(1), Command::Ref(val(&r(0)))),
+                    cmd(Command::Store(r(1), i32_val(0))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(&r(2), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Jeq("end".to_string(), val(&r(2)), val(&n))),
+                    assign(&r(3), Command::AddInt32(val(&r(2)), i32_val(1))),
+                    cmd(Command::Store(r(1), val(&r(3)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "end",
+                vec![
+                    assign(&r(4), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    );
+    let run = function(
+        "run",
+        vec![],
+        i32_ty(),
+        vec![label(
+            "entry",
+            vec![
+                assign_call(&r(0), "count", vec![i32_val(1000)]),
+                cmd(Command::Ret(val(&r(0)))),
+            ],
+        )],
+    );
+    Case::new("counter_loop", vec![count, run], "run")
+}
+
+/// `sum(n)`, adding `n` down to 1 with phis, one of them on the edge taken
+/// by a `jeq`
+fn sum_phis() -> Case {
+    let n = arg(0, i64_ty());
+    let r = |i| reg(i, i64_ty());
+    let sum = function(
+        "sum",
+        vec![i64_ty()],
+        i64_ty(),
+        vec![
+            label(
+                "entry",
+                vec![cmd(Command::Jeq("done".to_string(), val(&n), i64_val(0)))],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(
+                        &r(0),
+                        Command::Phi(vec![
+                            ("entry".to_string(), val(&n)),
+                            ("loop".to_string(), val(&r(2))),
+                        ]),
+                    ),
+                    assign(
+                        &r(1),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(0)),
+                            ("loop".to_string(), val(&r(3))),
+                        ]),
+                    ),
+                    assign(&r(2), Command::SubInt64(val(&r(0)), i64_val(1))),
+                    assign(&r(3), Command::AddInt64(val(&r(1)), val(&r(0)))),
+                    cmd(Command::Jeq("done".to_string(), val(&r(2)), i64_val(0))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "done",
+                vec![
+                    assign(
+                        &r(4),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(0)),
+                            ("loop".to_string(), val(&r(3))),
+                        ]),
+                    ),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    );
+    let run = function(
+        "run",
+        vec![],
+        i64_ty(),
+        vec![label(
+            "entry",
+            vec![
+                assign_call(&r(0), "sum", vec![i64_val(100_000)]),
+                assign_call(&r(1), "sum", vec![i64_val(0)]),
+                assign(&r(2), Command::AddInt64(val(&r(0)), val(&r(1)))),
+                cmd(Command::Ret(val(&r(2)))),
+            ],
+        )],
+    );
+    Case::new("sum_phis", vec![sum, run], "run")
+}
+
+/// `fib(n)`, recursively
+fn fib() -> Case {
+    let n = arg(0, i64_ty());
+    let r = |i| reg(i, i64_ty());
+    let fib = function(
+        "fib",
+        vec![i64_ty()],
+        i64_ty(),
+        vec![
+            label(
+                "entry",
+                vec![
+                    cmd(Command::Jeq("base".to_string(), val(&n), i64_val(0))),
+                    cmd(Command::Jeq("base".to_string(), val(&n), i64_val(1))),
+                    assign(&r(0), Command::SubInt64(val(&n), i64_val(1))),
+                    assign_call(&r(1), "fib", vec![val(&r(0))]),
+                    assign(&r(2), Command::SubInt64(val(&n), i64_val(2))),
+                    assign_call(&r(3), "fib", vec![val(&r(2))]),
+                    assign(&r(4), Command::AddInt64(val(&r(1)), val(&r(3)))),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+            label("base", vec![cmd(Command::Ret(val(&n)))]),
+        ],
+    );
+    let run = function(
+        "run",
+        vec![],
+        i64_ty(),
+        vec![label(
+            "entry",
+            vec![
+                assign_call(&r(0), "fib", vec![i64_val(20)]),
+                cmd(Command::Ret(val(&r(0)))),
+            ],
+        )],
+    );
+    Case::new("fib", vec![fib, run], "run")
+}
+
+type Binary = fn(Value, Value) -> Command;
+type Unary = fn(RegisterValue) -> Command;
+
+/// The add, the sub and the increment of `ty`, each wrapping around, and
+/// printed with `format`
+fn wrap_width(ty: MirageTypeEnum, format: &str, first: usize) -> Vec<LabelBodyInstr> {
+    let (add, sub, incr): (Binary, Binary, Unary) = match ty.size().size() {
+        1 => (Command::AddInt8, Command::SubInt8, Command::IncrInt8),
+        2 => (Command::AddInt16, Command::SubInt16, Command::IncrInt16),
+        4 => (Command::AddInt32, Command::SubInt32, Command::IncrInt32),
+        _ => (Command::AddInt64, Command::SubInt64, Command::IncrInt64),
+    };
+    let bits = ty.size().size() * 8;
+    let signed = matches!(
+        ty,
+        MirageTypeEnum::Int8(_)
+            | MirageTypeEnum::Int16(_)
+            | MirageTypeEnum::Int32(_)
+            | MirageTypeEnum::Int64(_)
+    );
+    // The largest value of `ty`, as an `i64` holding its bits
+    let max = match (signed, bits) {
+        (true, 64) => i64::MAX,
+        (false, 64) => -1,
+        (true, _) => (1 << (bits - 1)) - 1,
+        (false, _) => (1 << bits) - 1,
+    };
+    let r = |i| reg(first + i, ty.clone());
+    let format = format!("{}\\n", format);
+    vec![
+        assign(&r(0), Command::Const(int_obj(&ty, max))),
+        assign(&r(1), add(val(&r(0)), int_val(&ty, 1))),
+        assign(&r(2), sub(val(&r(1)), int_val(&ty, 1))),
+        assign(&r(3), incr(r(2))),
+        assign(&r(4), sub(int_val(&ty, 0), int_val(&ty, 3))),
+        call(
+            "printf",
+            vec![
+                string_val(&format),
+                val(&r(1)),
+                val(&r(2)),
+                val(&r(3)),
+                val(&r(4)),
+            ],
+        ),
+    ]
+}
+
+/// Wraparound at every width, signed and unsigned, and the promotion of
+/// the narrow integers passed to `printf`
+fn wraparound() -> Case {
+    let widths = [
+        (i8_ty(), "%d"),
+        (u8_ty(), "%u"),
+        (i16_ty(), "%d"),
+        (u16_ty(), "%u"),
+        (i32_ty(), "%d"),
+        (u32_ty(), "%u"),
+        (i64_ty(), "%lld"),
+        (u64_ty(), "%llu"),
+    ];
+    let mut body = Vec::new();
+    for (i, (ty, format)) in widths.into_iter().enumerate() {
+        let format = [format; 4].join(" ");
+        body.extend(wrap_width(ty, &format, i * 5));
+    }
+    // The int8 `max + 1`
+    body.push(cmd(Command::Ret(val(&reg(1, i8_ty())))));
+    let run = function("run", vec![], i8_ty(), vec![label("entry", body)]);
+    Case::new("wraparound", vec![printf(), run], "run")
+}
+
+/// A struct made by `new`, read with `get`, and an array in a global,
+/// walked with `getelementptr` and written back
+fn structs_and_arrays() -> Case {
+    let fields = vec![i32_ty(), i64_ty(), u8_ty()];
+    let point: MirageTypeEnum = MirageTypeEnum::type_struct(fields.clone()).into();
+    let array: MirageTypeEnum = MirageTypeEnum::type_array(i64_ty(), 4).into();
+    let table = MirageValueEnum::Array(
+        MirageTypeEnum::type_array(i64_ty(), 4).const_value(
+            [3, -5, 8, 1 << 40]
+                .into_iter()
+                .map(|v: i64| MirageTypeEnum::type_int64().const_value(v).to_value_enum())
+                .collect(),
+        ),
+    );
+    let p = reg(0, point.clone());
+    let ptr = |i| reg(i, ptr_ty(i64_ty()));
+    let i = |i| reg(i, i64_ty());
+    let mut body = vec![
+        assign(
+            &p,
+            Command::New(
+                "point".to_string(),
+                List::from_vec(vec![i32_val(7), i64_val(-9), int_val(&u8_ty(), 200)]),
+            ),
+        ),
+        assign(&reg(1, i32_ty()), Command::Get(p.clone(), 0)),
+        assign(&i(2), Command::Get(p.clone(), 1)),
+        assign(&reg(3, u8_ty()), Command::Get(p.clone(), 2)),
+        call(
+            "printf",
+            vec![
+                string_val("%d %lld %u\\n"),
+                val(&reg(1, i32_ty())),
+                val(&i(2)),
+                val(&reg(3, u8_ty())),
+            ],
+        ),
+    ];
+    // table[k] += table[k + 1], for k in 0..3, then the sum of the table
+    let mut next = 4;
+    for k in 0..3 {
+        let at = |index: i64| {
+            Command::GetElementPtr(
+                array.clone(),
+                val(&global(0, array.clone())),
+                vec![i64_val(0), i64_val(index)],
+            )
+        };
+        body.extend([
+            assign(&ptr(next), at(k)),
+            assign(&ptr(next + 1), at(k + 1)),
+            assign(&i(next + 2), Command::Load(i64_ty(), val(&ptr(next)))),
+            assign(&i(next + 3), Command::Load(i64_ty(), val(&ptr(next + 1)))),
+            assign(
+                &i(next + 4),
+                Command::AddInt64(val(&i(next + 2)), val(&i(next + 3))),
+            ),
+            cmd(Command::Store(ptr(next), val(&i(next + 4)))),
+        ]);
+        next += 5;
+    }
+    let mut sum = i(2);
+    for k in 0..4 {
+        body.extend([
+            assign(
+                &ptr(next),
+                Command::GetElementPtr(
+                    array.clone(),
+                    val(&global(0, array.clone())),
+                    vec![i64_val(0), i64_val(k)],
+                ),
+            ),
+            assign(&i(next + 1), Command::Load(i64_ty(), val(&ptr(next)))),
+            assign(
+                &i(next + 2),
+                Command::AddInt64(val(&sum), val(&i(next + 1))),
+            ),
+        ]);
+        sum = i(next + 2);
+        next += 3;
+    }
+    body.push(cmd(Command::Ret(val(&sum))));
+    let stmts = vec![
+        printf(),
+        Statement::Typedef(TypeDef::new("point".to_string(), List::from_vec(fields))),
+        Statement::Global(Global::new("table".to_string(), obj(table))),
+        function("run", vec![], i64_ty(), vec![label("entry", body)]),
+    ];
+    Case::new("structs_and_arrays", stmts, "run")
+}
+
+/// A global counter bumped by a helper, which a `#const` global limits
+fn globals() -> Case {
+    let counter = global(0, i32_ty());
+    let limit = global(1, i32_ty());
+    let r = |i| reg(i, i32_ty());
+    let p = |i| reg(i, ptr_ty(i32_ty()));
+    let mut bump_entry = label(
+        "entry",
+        vec![
+            assign(&p(0), Command::Ref(val(&counter))),
+            assign(&r(1), Command::Load(i32_ty(), val(&p(0)))),
+            assign(&r(2), Command::AddInt32(val(&r(1)), val(&arg(0, i32_ty())))),
+            cmd(Command::Store(p(0), val(&r(2)))),
+            cmd(Command::Ret(val(&r(2)))),
+        ],
+    );
+    bump_entry.flags.push(Flag::internal());
+    let bump = function("bump", vec![i32_ty()], i32_ty(), vec![bump_entry]);
+    let run = function(
+        "run",
+        vec![],
+        i32_ty(),
+        vec![
+            label("entry", vec![cmd(Command::Jump("loop".to_string()))]),
+            label(
+                "loop",
+                vec![
+                    assign_call(&r(1), "bump", vec![i32_val(3)]),
+                    call("printf", vec![string_val("counter %d\\n"), val(&r(1))]),
+                    cmd(Command::Jeq("done".to_string(), val(&r(1)), val(&limit))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "done",
+                vec![
+                    assign(&p(2), Command::Ref(val(&counter))),
+                    assign(&r(3), Command::Load(i32_ty(), val(&p(2)))),
+                    cmd(Command::Ret(val(&r(3)))),
+                ],
+            ),
+        ],
+    );
+    let stmts = vec![
+        printf(),
+        Statement::Global(Global::new(
+            "counter".to_string(),
+            obj(MirageTypeEnum::type_int32().const_value(-6).to_value_enum()),
+        )),
+        Statement::Global(Global::with_flags(
+            "limit".to_string(),
+            Flags::new(vec![Flag::constant()]),
+            obj(MirageTypeEnum::type_int32().const_value(9).to_value_enum()),
+        )),
+        bump,
+        run,
+    ];
+    Case::new("globals", stmts, "run")
+}
+
+/// `putchar` on every character of a string, walked with `getelementptr`,
+/// and `puts`
+fn strings() -> Case {
+    let text = "mirage\\tok";
+    let greeting = string(text);
+    let array = greeting.get_type();
+    let s = global(0, array.clone());
+    let c = |i| reg(i, i8_ty());
+    let p = |i| reg(i, ptr_ty(i8_ty()));
+    let n = |i| reg(i, i64_ty());
+    let run = function(
+        "run",
+        vec![],
+        i64_ty(),
+        vec![
+            label("entry", vec![cmd(Command::Jump("loop".to_string()))]),
+            label(
+                "loop",
+                vec![
+                    assign(
+                        &n(0),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(0)),
+                            ("loop".to_string(), val(&n(1))),
+                        ]),
+                    ),
+                    assign(
+                        &p(2),
+                        Command::GetElementPtr(
+                            array.clone(),
+                            val(&s),
+                            vec![i64_val(0), val(&n(0))],
+                        ),
+                    ),
+                    assign(&c(3), Command::Load(i8_ty(), val(&p(2)))),
+                    cmd(Command::Jeq(
+                        "done".to_string(),
+                        val(&c(3)),
+                        int_val(&i8_ty(), 0),
+                    )),
+                    call("putchar", vec![val(&c(3))]),
+                    assign(&n(1), Command::AddInt64(val(&n(0)), i64_val(1))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "done",
+                vec![
+                    call("puts", vec![string_val("")]),
+                    call("puts", vec![string_val("done")]),
+                    cmd(Command::Ret(val(&n(0)))),
+                ],
+            ),
+        ],
+    );
+    let stmts = vec![
+        putchar(),
+        puts(),
+        Statement::Global(Global::with_flags(
+            "text".to_string(),
+            Flags::new(vec![Flag::constant()]),
+            obj(greeting),
+        )),
+        run,
+    ];
+    Case::new("strings", stmts, "run")
+}
+
+/// Calls passing and returning narrow integers, which the callee must see
+/// extended according to their signedness
+fn narrow_calls() -> Case {
+    let widen = |name: &str, ty: MirageTypeEnum| {
+        let r = |i| reg(i, ty.clone());
+        function(
+            name,
+            vec![ty.clone(), ty.clone()],
+            ty.clone(),
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &r(0),
+                        match ty.size().size() {
+                            1 => {
+                                Command::SubInt8(val(&arg(0, ty.clone())), val(&arg(1, ty.clone())))
+                            }
+                            _ => Command::SubInt16(
+                                val(&arg(0, ty.clone())),
+                                val(&arg(1, ty.clone())),
+                            ),
+                        },
+                    ),
+                    cmd(Command::Ret(val(&r(0)))),
+                ],
+            )],
+        )
+    };
+    let run = function(
+        "run",
+        vec![],
+        u16_ty(),
+        vec![label(
+            "entry",
+            vec![
+                assign_call(
+                    &reg(0, i8_ty()),
+                    "sub_i8",
+                    vec![int_val(&i8_ty(), -100), int_val(&i8_ty(), 100)],
+                ),
+                assign_call(
+                    &reg(1, u8_ty()),
+                    "sub_u8",
+                    vec![int_val(&u8_ty(), 10), int_val(&u8_ty(), 20)],
+                ),
+                assign_call(
+                    &reg(2, i16_ty()),
+                    "sub_i16",
+                    vec![int_val(&i16_ty(), -30000), int_val(&i16_ty(), 10000)],
+                ),
+                assign_call(
+                    &reg(3, u16_ty()),
+                    "sub_u16",
+                    vec![int_val(&u16_ty(), 1), int_val(&u16_ty(), 2)],
+                ),
+                call(
+                    "printf",
+                    vec![
+                        string_val("%d %u %d %u\\n"),
+                        val(&reg(0, i8_ty())),
+                        val(&reg(1, u8_ty())),
+                        val(&reg(2, i16_ty())),
+                        val(&reg(3, u16_ty())),
+                    ],
+                ),
+                cmd(Command::Ret(val(&reg(3, u16_ty())))),
+            ],
+        )],
+    );
+    let stmts = vec![
+        printf(),
+        widen("sub_i8", i8_ty()),
+        widen("sub_u8", u8_ty()),
+        widen("sub_i16", i16_ty()),
+        widen("sub_u16", u16_ty()),
+        run,
+    ];
+    Case::new("narrow_calls", stmts, "run")
+}
+
+/// Float arithmetic, printed with every float conversion of `printf`
+fn floats() -> Case {
+    let r = |i| reg(i, f64_ty());
+    let run = function(
+        "run",
+        vec![],
+        f64_ty(),
+        vec![label(
+            "entry",
+            vec![
+                assign(&r(0), Command::AddFloat64(f64_val(0.1), f64_val(0.2))),
+                assign(&r(1), Command::SubFloat64(val(&r(0)), f64_val(1e-3))),
+                assign(&r(2), Command::IncrFloat64(r(1))),
+                call(
+                    "printf",
+                    vec![
+                        string_val("%f %.3e %g %8.2f\\n"),
+                        val(&r(0)),
+                        val(&r(1)),
+                        val(&r(2)),
+                        f64_val(-2.5),
+                    ],
+                ),
+                cmd(Command::Ret(val(&r(2)))),
+            ],
+        )],
+    );
+    Case::new("floats", vec![printf(), run], "run")
+}
+
+/// Every program of the corpus
+pub fn corpus() -> Vec<Case> {
+    vec![
+        builder_add(),
+        counter_loop(),
+        sum_phis(),
+        fib(),
+        wraparound(),
+        structs_and_arrays(),
+        globals(),
+        strings(),
+        narrow_calls(),
+        floats(),
+    ]
+}
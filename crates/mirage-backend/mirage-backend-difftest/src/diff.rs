@@ -0,0 +1,132 @@
+/// The lines of context kept around a change
+const CONTEXT: usize = 3;
+
+/// A line of a diff
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit<'a> {
+    Keep(&'a str),
+    Remove(&'a str),
+    Add(&'a str),
+}
+
+/// The shortest edit from `before` to `after`, through their longest
+/// common subsequence. The common start and end are taken out first, since
+/// a pass usually changes a few lines of a long program.
+fn edits<'a>(before: &[&'a str], after: &[&'a str]) -> Vec<Edit<'a>> {
+    let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &before[prefix..before.len() - suffix],
+        &after[prefix..after.len() - suffix],
+    );
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..]
+    // and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = match a[i] == b[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut out: Vec<Edit> = before[..prefix].iter().map(|l| Edit::Keep(l)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push(Edit::Keep(a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(Edit::Remove(a[i]));
+            i += 1;
+        } else {
+            out.push(Edit::Add(b[j]));
+            j += 1;
+        }
+    }
+    out.extend(
+        before[before.len() - suffix..]
+            .iter()
+            .map(|l| Edit::Keep(l)),
+    );
+    out
+}
+
+/// A unified diff of `before` and `after`, named `old` and `new` in its
+/// header, with only the changed lines and a few lines around them. It is
+/// empty when they are the same.
+pub fn unified_diff(before: &str, after: &str, old: &str, new: &str) -> String {
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+    let edits = edits(&before, &after);
+    let changed: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| !matches!(e, Edit::Keep(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // The ranges of edits shown, the changes closer than twice the context
+    // sharing a hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for i in changed {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old, new);
+    // The line numbers of the next edit, in `before` and in `after`
+    let (mut line_old, mut line_new) = (1, 1);
+    let mut at = 0;
+    for (start, end) in hunks {
+        for e in &edits[at..start] {
+            match e {
+                Edit::Keep(_) => {
+                    line_old += 1;
+                    line_new += 1;
+                }
+                Edit::Remove(_) => line_old += 1,
+                Edit::Add(_) => line_new += 1,
+            }
+        }
+        let hunk = &edits[start..end];
+        let len_old = hunk.iter().filter(|e| !matches!(e, Edit::Add(_))).count();
+        let len_new = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Remove(_)))
+            .count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            line_old, len_old, line_new, len_new
+        ));
+        for e in hunk {
+            let (sign, line) = match e {
+                Edit::Keep(l) => (' ', l),
+                Edit::Remove(l) => ('-', l),
+                Edit::Add(l) => ('+', l),
+            };
+            out.push(sign);
+            out.push_str(line);
+            out.push('\n');
+        }
+        line_old += len_old;
+        line_new += len_new;
+        at = end;
+    }
+    out
+}
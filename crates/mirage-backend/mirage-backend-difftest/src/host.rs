@@ -0,0 +1,359 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mirage_backend_interpreter::{Interpreter, Kind, Memory, Val};
+
+/// Resolve `printf`, `puts` and `putchar` in `interpreter` to functions
+/// which print as the C library does, to the buffer returned, so that what
+/// the program prints can be compared with what a compiled program prints
+pub fn register_stdio(interpreter: &mut Interpreter) -> Rc<RefCell<Vec<u8>>> {
+    let stdout = Rc::new(RefCell::new(Vec::new()));
+
+    let out = stdout.clone();
+    interpreter.register_extern("printf", move |memory, args| {
+        let (format, args) = args.split_first().ok_or_else(|| "no format".to_string())?;
+        let format = memory
+            .read_c_string(format.address())
+            .map_err(|e| e.to_string())?;
+        let printed = format_printf(memory, &format, args)?;
+        let len = printed.len();
+        out.borrow_mut().extend(printed);
+        Ok(Val::I32(len as i32))
+    });
+
+    let out = stdout.clone();
+    interpreter.register_extern("puts", move |memory, args| {
+        let s = args.first().ok_or_else(|| "no string".to_string())?;
+        let s = memory
+            .read_c_string(s.address())
+            .map_err(|e| e.to_string())?;
+        let mut out = out.borrow_mut();
+        out.extend(s);
+        out.push(b'\n');
+        Ok(Val::I32(0))
+    });
+
+    let out = stdout.clone();
+    interpreter.register_extern("putchar", move |_, args| {
+        let c = args.first().ok_or_else(|| "no character".to_string())?;
+        let c = c.convert(Kind::U8);
+        out.borrow_mut().extend(c.to_bytes());
+        Ok(c.convert(Kind::I32))
+    });
+
+    stdout
+}
+
+#[cfg(feature = "llvm")]
+thread_local! {
+    /// What the program run by the LLVM JIT printed
+    static JIT_STDOUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// `puts` for the program run by the LLVM JIT
+#[cfg(feature = "llvm")]
+pub(crate) extern "C" fn jit_puts(s: *const u8) -> i32 {
+    let s = unsafe { std::ffi::CStr::from_ptr(s as *const std::ffi::c_char) };
+    JIT_STDOUT.with(|out| {
+        let mut out = out.borrow_mut();
+        out.extend(s.to_bytes());
+        out.push(b'\n');
+    });
+    0
+}
+
+/// `putchar` for the program run by the LLVM JIT
+#[cfg(feature = "llvm")]
+pub(crate) extern "C" fn jit_putchar(c: i32) -> i32 {
+    JIT_STDOUT.with(|out| out.borrow_mut().push(c as u8));
+    c as u8 as i32
+}
+
+/// Take what the program run by the LLVM JIT printed so far
+#[cfg(feature = "llvm")]
+pub(crate) fn take_jit_stdout() -> String {
+    let out = JIT_STDOUT.with(|out| std::mem::take(&mut *out.borrow_mut()));
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The integer type a conversion reads, from its length modifier
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Size {
+    Char,
+    Short,
+    Int,
+    Long,
+}
+
+impl Size {
+    fn kind(self, signed: bool) -> Kind {
+        match (self, signed) {
+            (Size::Char, true) => Kind::I8,
+            (Size::Short, true) => Kind::I16,
+            (Size::Int, true) => Kind::I32,
+            (Size::Long, true) => Kind::I64,
+            (Size::Char, false) => Kind::U8,
+            (Size::Short, false) => Kind::U16,
+            (Size::Int, false) => Kind::U32,
+            (Size::Long, false) => Kind::U64,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// The arguments of a `printf`, read one after the other
+struct Args<'a> {
+    args: std::slice::Iter<'a, Val>,
+}
+
+impl Args<'_> {
+    fn next(&mut self) -> Result<Val, String> {
+        self.args
+            .next()
+            .copied()
+            .ok_or_else(|| "too few arguments".to_string())
+    }
+
+    fn int(&mut self, size: Size) -> Result<i64, String> {
+        match self.next()?.convert(size.kind(true)).convert(Kind::I64) {
+            Val::I64(v) => Ok(v),
+            _ => unreachable!("the value is converted to `int64`"),
+        }
+    }
+
+    fn uint(&mut self, size: Size) -> Result<u64, String> {
+        match self.next()?.convert(size.kind(false)).convert(Kind::U64) {
+            Val::U64(v) => Ok(v),
+            _ => unreachable!("the value is converted to `uint64`"),
+        }
+    }
+
+    fn float(&mut self) -> Result<f64, String> {
+        match self.next()?.convert(Kind::F64) {
+            Val::F64(v) => Ok(v),
+            _ => unreachable!("the value is converted to `float64`"),
+        }
+    }
+}
+
+/// A width or a precision: digits, or `*` for an `int` argument
+fn number(format: &[u8], i: &mut usize, args: &mut Args) -> Result<usize, String> {
+    if format.get(*i) == Some(&b'*') {
+        *i += 1;
+        return Ok(args.int(Size::Int)?.max(0) as usize);
+    }
+    let mut n = 0;
+    while let Some(d) = format.get(*i).filter(|d| d.is_ascii_digit()) {
+        n = n * 10 + (d - b'0') as usize;
+        *i += 1;
+    }
+    Ok(n)
+}
+
+/// Format `%e` as C does, with at least two digits of exponent
+fn exponent(v: f64, precision: usize, upper: bool) -> String {
+    let s = format!("{:.*e}", precision, v);
+    let (mantissa, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let sign = if exp < 0 { '-' } else { '+' };
+    let e = if upper { 'E' } else { 'e' };
+    format!("{}{}{}{:02}", mantissa, e, sign, exp.abs())
+}
+
+fn strip_zeros(s: String) -> String {
+    if !s.contains('.') {
+        return s;
+    }
+    let (mantissa, exp) = match s.find(['e', 'E']) {
+        Some(at) => s.split_at(at),
+        None => (s.as_str(), ""),
+    };
+    format!(
+        "{}{}",
+        mantissa.trim_end_matches('0').trim_end_matches('.'),
+        exp
+    )
+}
+
+/// Format a float conversion of `v`, whose sign is handled by the caller
+fn float(v: f64, conv: u8, spec: &Spec) -> String {
+    let upper = conv.is_ascii_uppercase();
+    if !v.is_finite() {
+        let s = if v.is_nan() { "nan" } else { "inf" };
+        return match upper {
+            true => s.to_uppercase(),
+            false => s.to_string(),
+        };
+    }
+    let precision = spec.precision.unwrap_or(6);
+    match conv.to_ascii_lowercase() {
+        b'f' => format!("{:.*}", precision, v),
+        b'e' => exponent(v, precision, upper),
+        _ => {
+            let p = precision.max(1);
+            let exp: i32 = format!("{:.*e}", p - 1, v)
+                .split_once('e')
+                .unwrap()
+                .1
+                .parse()
+                .unwrap();
+            let s = if exp < -4 || exp >= p as i32 {
+                exponent(v, p - 1, upper)
+            } else {
+                format!("{:.*}", (p as i32 - 1 - exp) as usize, v)
+            };
+            match spec.alternate {
+                true => s,
+                false => strip_zeros(s),
+            }
+        }
+    }
+}
+
+fn pad(out: &mut Vec<u8>, spec: &Spec, sign: &str, body: &[u8], numeric: bool) {
+    let fill = spec.width.saturating_sub(sign.len() + body.len());
+    if spec.left {
+        out.extend(sign.bytes());
+        out.extend(body);
+        out.extend(std::iter::repeat_n(b' ', fill));
+    } else if spec.zero && numeric {
+        out.extend(sign.bytes());
+        out.extend(std::iter::repeat_n(b'0', fill));
+        out.extend(body);
+    } else {
+        out.extend(std::iter::repeat_n(b' ', fill));
+        out.extend(sign.bytes());
+        out.extend(body);
+    }
+}
+
+/// What `printf(format, args...)` prints. `%p` isn't supported, since an
+/// address of the interpreter means nothing to a compiled program.
+pub(crate) fn format_printf(
+    memory: &Memory,
+    format: &[u8],
+    args: &[Val],
+) -> Result<Vec<u8>, String> {
+    let mut args = Args { args: args.iter() };
+    let mut out = Vec::new();
+    let mut i = 0;
+    while let Some(c) = format.get(i) {
+        i += 1;
+        if *c != b'%' {
+            out.push(*c);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(flag) = format.get(i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        spec.width = number(format, &mut i, &mut args)?;
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = Some(number(format, &mut i, &mut args)?);
+        }
+        let mut size = Size::Int;
+        while let Some(modifier) = format.get(i) {
+            size = match modifier {
+                b'h' if size == Size::Short => Size::Char,
+                b'h' => Size::Short,
+                b'l' | b'j' | b'z' | b't' => Size::Long,
+                _ => break,
+            };
+            i += 1;
+        }
+        let conv = *format
+            .get(i)
+            .ok_or_else(|| "incomplete conversion".to_string())?;
+        i += 1;
+
+        let sign = |negative: bool| match negative {
+            true => "-",
+            false if spec.plus => "+",
+            false if spec.space => " ",
+            false => "",
+        };
+        // The minimum number of digits of an integer
+        let digits = |s: String| {
+            let min = spec.precision.unwrap_or(1);
+            let mut body = "0".repeat(min.saturating_sub(s.len())).into_bytes();
+            if min > 0 || s != "0" {
+                body.extend(s.bytes());
+            }
+            body
+        };
+        match conv {
+            b'%' => out.push(b'%'),
+            b'd' | b'i' => {
+                let v = args.int(size)?;
+                let body = digits(v.unsigned_abs().to_string());
+                pad(
+                    &mut out,
+                    &spec,
+                    sign(v < 0),
+                    &body,
+                    spec.precision.is_none(),
+                );
+            }
+            b'u' | b'x' | b'X' | b'o' => {
+                let v = args.uint(size)?;
+                let s = match conv {
+                    b'u' => v.to_string(),
+                    b'x' => format!("{:x}", v),
+                    b'X' => format!("{:X}", v),
+                    _ => format!("{:o}", v),
+                };
+                let prefix = match conv {
+                    b'x' if spec.alternate && v != 0 => "0x",
+                    b'X' if spec.alternate && v != 0 => "0X",
+                    _ => "",
+                };
+                let body = digits(s);
+                pad(&mut out, &spec, prefix, &body, spec.precision.is_none());
+            }
+            b'c' => {
+                let c = args.int(Size::Int)? as u8;
+                pad(&mut out, &spec, "", &[c], false);
+            }
+            b's' => {
+                let s = memory
+                    .read_c_string(args.next()?.address())
+                    .map_err(|e| e.to_string())?;
+                let s = &s[..spec.precision.unwrap_or(s.len()).min(s.len())];
+                pad(&mut out, &spec, "", s, false);
+            }
+            b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+                let v = args.float()?;
+                let body = float(v.abs(), conv, &spec);
+                pad(
+                    &mut out,
+                    &spec,
+                    sign(v.is_sign_negative() && !v.is_nan()),
+                    body.as_bytes(),
+                    v.is_finite(),
+                );
+            }
+            _ => return Err(format!("unsupported conversion `%{}`", conv as char)),
+        }
+    }
+    Ok(out)
+}
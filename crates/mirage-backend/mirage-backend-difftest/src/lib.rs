@@ -0,0 +1,474 @@
+#[cfg(test)]
+mod test;
+
+mod backend;
+pub mod corpus;
+mod diff;
+mod host;
+mod native;
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub use backend::Asm;
+#[cfg(feature = "cranelift")]
+pub use backend::Cranelift;
+#[cfg(feature = "llvm")]
+pub use backend::Llvm;
+pub use backend::{available_backends, Backend, CBackend, Interpreted, RunError, RunResult, Wasm};
+pub use diff::unified_diff;
+pub use host::register_stdio;
+
+use mirage_backend_interpreter::{Interpreter, Kind, Trap, Val};
+use mirage_backend_opti::ir::has_attribute;
+use mirage_backend_opti::verify::verify_module;
+use mirage_backend_opti::{optimize, OptiLevel};
+use mirage_frontend::object::meta::Flag;
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::stringify::Stringify;
+
+/// Every optimization level, in the order they are checked
+pub const LEVELS: [OptiLevel; 6] = [
+    OptiLevel::O0,
+    OptiLevel::O1,
+    OptiLevel::O2,
+    OptiLevel::O3,
+    OptiLevel::Os,
+    OptiLevel::Oz,
+];
+
+/// The instructions the interpreter runs before taking the program to
+/// loop forever
+pub const FUEL: u64 = 50_000_000;
+
+/// A program of the corpus, and the function the harness calls. The entry
+/// takes no argument and returns an integer or a float, and it mustn't be
+/// named `main`, which the native backends' driver defines.
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub name: String,
+    pub stmts: Vec<Statement>,
+    pub entry: String,
+}
+
+impl Case {
+    pub fn new(name: &str, stmts: Vec<Statement>, entry: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            stmts,
+            entry: entry.to_string(),
+        }
+    }
+
+    /// The kind of value the entry returns, or why the case can't be run
+    fn entry_kind(&self) -> Result<Kind, String> {
+        if self.entry == "main" {
+            return Err("the entry is named `main`".to_string());
+        }
+        let func = self
+            .stmts
+            .iter()
+            .find_map(|stmt| match stmt {
+                Statement::Function(f) if *f.get_name() == self.entry => Some(f),
+                _ => None,
+            })
+            .ok_or_else(|| format!("there is no function `{}`", self.entry))?;
+        if !func.get_type().get_args().is_empty() {
+            return Err(format!("`{}` takes arguments", self.entry));
+        }
+        if has_attribute(func, &Flag::internal()) {
+            return Err(format!("`{}` is #internal", self.entry));
+        }
+        match Kind::of(func.get_type().get_ret()) {
+            Kind::Ptr => Err(format!(
+                "`{}` doesn't return an integer or a float",
+                self.entry
+            )),
+            kind => Ok(kind),
+        }
+    }
+}
+
+/// What a run of the entry gave: the value it returned, and what the
+/// program printed through `printf`, `puts` and `putchar`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub value: Val,
+    pub stdout: String,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "returned {}, printed {:?}", self.value, self.stdout)
+    }
+}
+
+/// A case the harness found wrong
+/// # Variants
+/// * `Invalid` - The case can't be checked: it doesn't verify, or its entry
+///   isn't one the harness can call
+/// * `Oracle` - The interpreter trapped running the unoptimized program, so
+///   there is no value to compare with
+/// * `Verify` - The optimizations of a level made the program malformed
+/// * `Mismatch` - A backend gave another outcome than the interpreter, or
+///   failed to compile or to run the program
+#[derive(Debug, Clone)]
+pub enum Failure {
+    Invalid {
+        case: String,
+        errors: Vec<String>,
+    },
+    Oracle {
+        case: String,
+        trap: Trap,
+    },
+    Verify {
+        case: String,
+        level: OptiLevel,
+        errors: Vec<String>,
+        diff: String,
+    },
+    Mismatch {
+        case: String,
+        backend: String,
+        level: OptiLevel,
+        expected: Outcome,
+        found: Result<Outcome, String>,
+        diff: String,
+    },
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Invalid { case, errors } => {
+                writeln!(f, "{}: invalid case", case)?;
+                for e in errors {
+                    writeln!(f, "  {}", e)?;
+                }
+                Ok(())
+            }
+            Failure::Oracle { case, trap } => {
+                writeln!(f, "{}: the interpreter trapped: {}", case, trap)
+            }
+            Failure::Verify {
+                case,
+                level,
+                errors,
+                diff,
+            } => {
+                writeln!(
+                    f,
+                    "{} at {}: the optimized program is malformed",
+                    case,
+                    level.as_str()
+                )?;
+                for e in errors {
+                    writeln!(f, "  {}", e)?;
+                }
+                write!(f, "{}", diff)
+            }
+            Failure::Mismatch {
+                case,
+                backend,
+                level,
+                expected,
+                found,
+                diff,
+            } => {
+                writeln!(f, "{} on {} at {}:", case, backend, level.as_str())?;
+                writeln!(f, "  expected: {}", expected)?;
+                match found {
+                    Ok(found) => writeln!(f, "  found:    {}", found)?,
+                    Err(e) => writeln!(f, "  failed:   {}", e)?,
+                }
+                write!(f, "{}", diff)
+            }
+        }
+    }
+}
+
+/// What checking cases gave
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// The number of runs compared with the interpreter
+    pub runs: usize,
+    /// The backend, the level and the case of the runs skipped because the
+    /// backend can't compile the program, and why
+    pub skipped: Vec<String>,
+    pub failures: Vec<Failure>,
+}
+
+impl Report {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn merge(&mut self, other: Report) {
+        self.runs += other.runs;
+        self.skipped.extend(other.skipped);
+        self.failures.extend(other.failures);
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} runs, {} skipped, {} failures",
+            self.runs,
+            self.skipped.len(),
+            self.failures.len()
+        )?;
+        for failure in &self.failures {
+            write!(f, "\n{}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// What the optimizations of `level` changed from `before` to `after`, or
+/// the whole of `after` when they changed nothing
+fn level_diff(before: &[Statement], after: &[Statement], level: OptiLevel) -> String {
+    let after = module_ir(after);
+    match unified_diff(&module_ir(before), &after, "O0", level.as_str()) {
+        d if d.is_empty() => after,
+        d => d,
+    }
+}
+
+/// Where a piece of a program can be cut
+/// # Variants
+/// * `Statement` - The statement at this index
+/// * `Label` - A label of the function statement at this index
+/// * `Instr` - An instruction of a label of a function statement
+#[derive(Debug, Clone, Copy)]
+enum Cut {
+    Statement(usize),
+    Label(usize, usize),
+    Instr(usize, usize, usize),
+}
+
+impl Cut {
+    /// Every cut of `stmts`, the last pieces first, so that making a cut
+    /// leaves the indices of the ones following it valid
+    fn all(stmts: &[Statement]) -> Vec<Cut> {
+        let mut cuts = Vec::new();
+        for (s, stmt) in stmts.iter().enumerate().rev() {
+            if let Statement::Function(f) = stmt {
+                for (l, label) in f.get_labels().iter().enumerate().rev() {
+                    cuts.extend((0..label.body.len()).rev().map(|i| Cut::Instr(s, l, i)));
+                    cuts.push(Cut::Label(s, l));
+                }
+            }
+            cuts.push(Cut::Statement(s));
+        }
+        cuts
+    }
+
+    fn apply(self, stmts: &mut Vec<Statement>) {
+        let (s, l, i) = match self {
+            Cut::Statement(s) => {
+                stmts.remove(s);
+                return;
+            }
+            Cut::Label(s, l) => (s, l, None),
+            Cut::Instr(s, l, i) => (s, l, Some(i)),
+        };
+        let Statement::Function(f) = &mut stmts[s] else {
+            unreachable!("only the statements of functions have labels")
+        };
+        match i {
+            Some(i) => {
+                f.get_labels_mut()[l].body.remove(i);
+            }
+            None => {
+                f.get_labels_mut().remove(l);
+            }
+        }
+    }
+}
+
+/// Drop the pieces of `case` one at a time for as long as `fails` holds of
+/// what is left, until no piece can be dropped
+fn shrink(case: &Case, fails: impl Fn(&Case) -> bool) -> Case {
+    let mut case = case.clone();
+    loop {
+        let mut shrunk = false;
+        for cut in Cut::all(&case.stmts) {
+            let mut smaller = case.clone();
+            cut.apply(&mut smaller.stmts);
+            if fails(&smaller) {
+                case = smaller;
+                shrunk = true;
+            }
+        }
+        if !shrunk {
+            return case;
+        }
+    }
+}
+
+/// The printed IR of a program, one statement after the other
+pub fn module_ir(stmts: &[Statement]) -> String {
+    let mut ir = stmts
+        .iter()
+        .map(|stmt| stmt.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    ir.push('\n');
+    ir
+}
+
+/// The differential tester: it runs every case on the interpreter without
+/// optimizations, then optimizes it at every level and runs it on every
+/// backend, and reports where the outcomes differ.
+///
+/// A failing case is first shrunk: its statements, labels and instructions
+/// are dropped one at a time for as long as it still fails the same way.
+/// The failure then shows what the optimizations of its level changed in
+/// what is left, as a diff of the printed IR with the unoptimized program,
+/// so that a bug of a pass is found in the few lines it touched. At `O0`,
+/// where nothing changes, it is the IR of the whole shrunk program.
+pub struct Harness {
+    backends: Vec<Box<dyn Backend>>,
+    levels: Vec<OptiLevel>,
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Harness {
+    /// A harness running every backend available on this machine, at every
+    /// level
+    pub fn new() -> Self {
+        Self::with_backends(available_backends())
+    }
+
+    pub fn with_backends(backends: Vec<Box<dyn Backend>>) -> Self {
+        Self {
+            backends,
+            levels: LEVELS.to_vec(),
+        }
+    }
+
+    pub fn set_levels(&mut self, levels: &[OptiLevel]) {
+        self.levels = levels.to_vec();
+    }
+
+    /// The names of the backends the harness runs
+    pub fn backends(&self) -> Vec<&str> {
+        self.backends.iter().map(|b| b.name()).collect()
+    }
+
+    /// What the interpreter gives for the unoptimized case, the outcome
+    /// every backend is compared with
+    pub fn oracle(case: &Case) -> Result<Outcome, Box<Failure>> {
+        let invalid = |errors| {
+            Box::new(Failure::Invalid {
+                case: case.name.clone(),
+                errors,
+            })
+        };
+        verify_module(&case.stmts)
+            .map_err(|errors| invalid(errors.iter().map(|e| e.to_string()).collect()))?;
+        case.entry_kind().map_err(|e| invalid(vec![e]))?;
+        let mut interpreter = Interpreter::new(case.stmts.clone());
+        interpreter.set_fuel(Some(FUEL));
+        let stdout = register_stdio(&mut interpreter);
+        let value = interpreter.call(&case.entry, &[]).map_err(|trap| {
+            Box::new(Failure::Oracle {
+                case: case.name.clone(),
+                trap,
+            })
+        })?;
+        let stdout = String::from_utf8_lossy(&stdout.borrow()).into_owned();
+        Ok(Outcome { value, stdout })
+    }
+
+    /// Check one case on every backend and at every level
+    pub fn check(&self, case: &Case) -> Report {
+        let mut report = Report::default();
+        let expected = match Self::oracle(case) {
+            Ok(outcome) => outcome,
+            Err(failure) => {
+                report.failures.push(*failure);
+                return report;
+            }
+        };
+        // Checked by the oracle
+        let ret = case.entry_kind().unwrap();
+
+        for level in &self.levels {
+            let stmts = optimize(*level, case.stmts.clone());
+            if verify_module(&stmts).is_err() {
+                let case = shrink(case, |c| {
+                    Self::oracle(c).is_ok()
+                        && verify_module(&optimize(*level, c.stmts.clone())).is_err()
+                });
+                let stmts = optimize(*level, case.stmts.clone());
+                let errors = verify_module(&stmts).unwrap_err();
+                report.failures.push(Failure::Verify {
+                    case: case.name.clone(),
+                    level: *level,
+                    errors: errors.iter().map(|e| e.to_string()).collect(),
+                    diff: level_diff(&case.stmts, &stmts, *level),
+                });
+                continue;
+            }
+            for backend in &self.backends {
+                match backend.run(&stmts, &case.entry, ret, *level) {
+                    Ok(found) if found == expected => {
+                        report.runs += 1;
+                        continue;
+                    }
+                    Err(RunError::Unsupported(why)) => {
+                        report.skipped.push(format!(
+                            "{} on {} at {}: {}",
+                            case.name,
+                            backend.name(),
+                            level.as_str(),
+                            why
+                        ));
+                        continue;
+                    }
+                    _ => {}
+                }
+                report.runs += 1;
+                let run = |c: &Case| {
+                    let expected = Self::oracle(c).ok()?;
+                    let stmts = optimize(*level, c.stmts.clone());
+                    verify_module(&stmts).ok()?;
+                    let found = match backend.run(&stmts, &c.entry, ret, *level) {
+                        Ok(found) if found == expected => return None,
+                        Ok(found) => Ok(found),
+                        Err(RunError::Unsupported(_)) => return None,
+                        Err(RunError::Failed(why)) => Err(why),
+                    };
+                    Some((expected, found, level_diff(&c.stmts, &stmts, *level)))
+                };
+                let case = shrink(case, |c| run(c).is_some());
+                // The shrunk case still fails, and its outcomes are reported
+                let (expected, found, diff) = run(&case).unwrap();
+                report.failures.push(Failure::Mismatch {
+                    case: case.name.clone(),
+                    backend: backend.name().to_string(),
+                    level: *level,
+                    expected,
+                    found,
+                    diff,
+                });
+            }
+        }
+        report
+    }
+
+    pub fn check_all(&self, cases: &[Case]) -> Report {
+        let mut report = Report::default();
+        for case in cases {
+            report.merge(self.check(case));
+        }
+        report
+    }
+}
@@ -0,0 +1,201 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command as Process, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use mirage_backend_interpreter::{Kind, Val};
+use mirage_backend_opti::OptiLevel;
+
+use crate::backend::{RunError, RunResult};
+use crate::Outcome;
+
+/// How long a compiled program may run before it is taken to loop forever
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// What the driver prints before the bits of the value the entry returned,
+/// on a line of its own
+const MARKER: &str = "mirage-difftest-result:";
+
+/// Whether a C compiler is installed as `cc`, which the native backends
+/// need to link the driver
+pub(crate) fn has_cc() -> bool {
+    static HAS_CC: OnceLock<bool> = OnceLock::new();
+    *HAS_CC.get_or_init(|| {
+        Process::new("cc")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    })
+}
+
+/// The flag of `cc` for `level`
+pub(crate) fn cc_level(level: OptiLevel) -> &'static str {
+    match level {
+        OptiLevel::O0 => "-O0",
+        OptiLevel::O1 => "-O1",
+        OptiLevel::O2 => "-O2",
+        OptiLevel::O3 => "-O3",
+        OptiLevel::Os => "-Os",
+        OptiLevel::Oz => "-Os",
+    }
+}
+
+/// A directory of its own for the files of a run, removed when dropped
+pub(crate) struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    pub fn new() -> RunResult<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "mirage-difftest-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).map_err(|e| RunError::Failed(e.to_string()))?;
+        Ok(Self { dir })
+    }
+
+    /// Write `bytes` to the file `name` of the directory, and return its
+    /// path
+    pub fn write(&self, name: &str, bytes: impl AsRef<[u8]>) -> RunResult<PathBuf> {
+        let path = self.path(name);
+        std::fs::write(&path, bytes).map_err(|e| RunError::Failed(e.to_string()))?;
+        Ok(path)
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn c_type(kind: Kind) -> &'static str {
+    match kind {
+        Kind::I8 => "int8_t",
+        Kind::I16 => "int16_t",
+        Kind::I32 => "int32_t",
+        Kind::I64 => "int64_t",
+        Kind::U8 => "uint8_t",
+        Kind::U16 => "uint16_t",
+        Kind::U32 => "uint32_t",
+        Kind::U64 => "uint64_t",
+        Kind::F32 => "float",
+        Kind::F64 => "double",
+        Kind::Ptr => "void *",
+    }
+}
+
+/// The C program which calls `entry` and prints the bits of its value
+/// after what the program printed
+fn driver(entry: &str, ret: Kind) -> String {
+    format!(
+        "#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+
+{ty} {entry}(void);
+
+int main(void) {{
+    {ty} value = {entry}();
+    unsigned long long bits = 0;
+    memcpy(&bits, &value, sizeof value);
+    printf(\"\\n{marker}%llx\\n\", bits);
+    return 0;
+}}
+",
+        ty = c_type(ret),
+        entry = entry,
+        marker = MARKER,
+    )
+}
+
+/// Run `cmd`, killing it after the timeout. Gives what it printed.
+fn run_with_timeout(cmd: &mut Process) -> Result<String, String> {
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    // Read the output on a thread, so that the program doesn't block on a
+    // full pipe while it is waited for
+    let mut pipe = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut out = Vec::new();
+        let _ = pipe.read_to_end(&mut out);
+        out
+    });
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) => break status,
+            None if start.elapsed() > TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("timed out after {}s", TIMEOUT.as_secs()));
+            }
+            None => std::thread::sleep(Duration::from_millis(5)),
+        }
+    };
+    let out = reader.join().unwrap_or_default();
+    if !status.success() {
+        return Err(match status.code() {
+            Some(code) => format!("exited with {}", code),
+            None => "killed by a signal".to_string(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// Link the driver with `inputs`, sources or objects, with the system `cc`
+/// and `flags`, then run it
+pub(crate) fn link_and_run(
+    scratch: &Scratch,
+    inputs: &[&Path],
+    flags: &[&str],
+    entry: &str,
+    ret: Kind,
+) -> RunResult<Outcome> {
+    let driver = scratch.write("driver.c", driver(entry, ret))?;
+    let exe = scratch.path("program");
+    let out = Process::new("cc")
+        .args(flags)
+        .arg("-w")
+        .arg(&driver)
+        .args(inputs)
+        .arg("-o")
+        .arg(&exe)
+        .output()
+        .map_err(|e| RunError::Failed(format!("cc: {}", e)))?;
+    if !out.status.success() {
+        return Err(RunError::Failed(format!(
+            "cc failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    let stdout = run_with_timeout(&mut Process::new(&exe)).map_err(RunError::Failed)?;
+    parse(&stdout, ret)
+}
+
+/// Split what the driver printed into what the program printed and the
+/// value it returned
+fn parse(stdout: &str, ret: Kind) -> RunResult<Outcome> {
+    let missing = || RunError::Failed(format!("no result in the output {:?}", stdout));
+    let at = stdout.rfind(&format!("\n{}", MARKER)).ok_or_else(missing)?;
+    let bits = stdout[at + 1 + MARKER.len()..].trim_end();
+    let bits = u64::from_str_radix(bits, 16).map_err(|_| missing())?;
+    Ok(Outcome {
+        value: Val::from_bytes(ret, &bits.to_le_bytes()),
+        stdout: stdout[..at].to_string(),
+    })
+}
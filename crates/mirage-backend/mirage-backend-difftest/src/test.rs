@@ -0,0 +1,143 @@
+use mirage_backend_interpreter::{Kind, Memory, Region, Val};
+use mirage_backend_opti::OptiLevel;
+use mirage_frontend::object::statements::Statement;
+
+use crate::corpus::corpus;
+use crate::host::format_printf;
+use crate::{unified_diff, Backend, Failure, Harness, Interpreted, Outcome, RunResult};
+
+#[test]
+fn test_corpus() {
+    let harness = Harness::new();
+    let report = harness.check_all(&corpus());
+    for skipped in &report.skipped {
+        eprintln!("skipped: {}", skipped);
+    }
+    assert!(report.is_success(), "{}", report);
+    assert!(report.runs > 0);
+}
+
+/// A backend giving the wrong value once the program is optimized
+struct OffByOne;
+
+impl Backend for OffByOne {
+    fn name(&self) -> &str {
+        "off-by-one"
+    }
+
+    fn run(
+        &self,
+        stmts: &[Statement],
+        entry: &str,
+        ret: Kind,
+        level: OptiLevel,
+    ) -> RunResult<Outcome> {
+        let mut outcome = Interpreted.run(stmts, entry, ret, level)?;
+        if level != OptiLevel::O0 {
+            outcome.value = outcome.value.arith(&Val::I32(1), false);
+        }
+        Ok(outcome)
+    }
+}
+
+#[test]
+fn test_mismatch() {
+    let mut harness = Harness::with_backends(vec![Box::new(OffByOne)]);
+    harness.set_levels(&[OptiLevel::O0, OptiLevel::O2]);
+    let case = corpus().into_iter().find(|c| c.name == "sum_phis").unwrap();
+    let report = harness.check(&case);
+    assert_eq!(report.runs, 2);
+    match report.failures.as_slice() {
+        [Failure::Mismatch {
+            backend,
+            level: OptiLevel::O2,
+            expected,
+            found: Ok(found),
+            diff,
+            ..
+        }] => {
+            assert_eq!(backend, "off-by-one");
+            assert_eq!(found.value, expected.value.arith(&Val::I32(1), false));
+            // The case is shrunk before it is reported: what is left no
+            // longer adds the numbers up
+            assert_ne!(expected.value, Val::I64(5_000_050_000));
+            assert!(diff.starts_with("--- O0\n+++ O2\n@@ -"), "{}", diff);
+        }
+        failures => panic!("{:?}", failures),
+    }
+}
+
+#[test]
+fn test_unified_diff() {
+    let before = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n";
+    let after = "a\nb\nc\nD\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+    assert_eq!(unified_diff(before, before, "old", "new"), "");
+    assert_eq!(
+        unified_diff(before, after, "old", "new"),
+        "--- old\n+++ new\n\
+         @@ -1,7 +1,7 @@\n a\n b\n c\n-d\n+D\n e\n f\n g\n\
+         @@ -10,3 +10,4 @@\n j\n k\n l\n+m\n"
+    );
+    // Changes closer than twice the context share a hunk
+    assert_eq!(
+        unified_diff("1\n2\n3\n4\n5\n", "1\n3\n4\n6\n", "old", "new"),
+        "--- old\n+++ new\n@@ -1,5 +1,4 @@\n 1\n-2\n 3\n 4\n-5\n+6\n"
+    );
+}
+
+#[test]
+fn test_printf() {
+    let mut memory = Memory::new();
+    let s = memory.allocate_with(b"ok\0".to_vec(), Region::Constant);
+    let printf = |format: &str, args: &[Val]| {
+        String::from_utf8(format_printf(&memory, format.as_bytes(), args).unwrap()).unwrap()
+    };
+    assert_eq!(
+        printf(
+            "%d %5u|%-4x|%04X %o%%",
+            &[
+                Val::I32(-42),
+                Val::U32(7),
+                Val::U32(255),
+                Val::U32(171),
+                Val::U32(8)
+            ]
+        ),
+        "-42     7|ff  |00AB 10%"
+    );
+    // The length modifiers truncate the promoted arguments
+    assert_eq!(
+        printf(
+            "%hhd %hu %lld %llu",
+            &[
+                Val::I32(200),
+                Val::U32(65537),
+                Val::I64(i64::MIN),
+                Val::U64(u64::MAX)
+            ]
+        ),
+        "-56 1 -9223372036854775808 18446744073709551615"
+    );
+    assert_eq!(
+        printf(
+            "[%s] [%.1s] [%c]",
+            &[Val::Ptr(s), Val::Ptr(s), Val::I32(65)]
+        ),
+        "[ok] [o] [A]"
+    );
+    assert_eq!(
+        printf(
+            "%f %.2e %g %g %+.1f",
+            &[
+                Val::F64(0.5),
+                Val::F64(12345.678),
+                Val::F64(0.0001),
+                Val::F64(1e20),
+                Val::F64(2.25)
+            ]
+        ),
+        "0.500000 1.23e+04 0.0001 1e+20 +2.2"
+    );
+    assert!(format_printf(&memory, b"%p", &[Val::Ptr(s)]).is_err());
+    assert!(format_printf(&memory, b"%d", &[]).is_err());
+}
@@ -0,0 +1,49 @@
+//! Optimizes the generated modules at every level and compiles them with
+//! the code generators, failing on a panic, on a module the optimizer
+//! leaves unverified, or on an error other than an unsupported feature.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mirage_backend_difftest::LEVELS;
+use mirage_backend_fuzz::GeneratedModule;
+use mirage_backend_opti::optimize;
+use mirage_backend_opti::verify::verify_module;
+use mirage_frontend::module::Module;
+
+fuzz_target!(|module: GeneratedModule| {
+    for level in LEVELS {
+        let stmts = optimize(level, module.stmts.clone());
+        if let Err(e) = verify_module(&stmts) {
+            panic!("{:?} at {:?}\n{:?}", e, level, module);
+        }
+
+        let mut c = mirage_backend_codegen_c::CodeGen::new(stmts.clone());
+        match c.compile() {
+            Ok(()) => {
+                c.emit_c();
+            }
+            Err(mirage_backend_codegen_c::CodeGenError::Unsupported(_)) => {}
+            Err(e) => panic!("c: {:?} at {:?}", e, level),
+        }
+
+        let mut asm = mirage_backend_codegen_asm::CodeGen::new(
+            stmts.clone(),
+            Module::new("fuzz".to_string()),
+        );
+        match asm.compile().and_then(|()| asm.emit_object()) {
+            Ok(_) => {}
+            Err(mirage_backend_codegen_asm::CodeGenError::Unsupported(_)) => {}
+            Err(e) => panic!("asm: {:?} at {:?}", e, level),
+        }
+
+        let mut wasm = mirage_backend_codegen_wasm::Compiler::new(stmts);
+        match wasm.compile() {
+            Ok(()) => {
+                wasm.emit_wasm();
+            }
+            Err(mirage_backend_codegen_wasm::CompilerError::Unsupported(_)) => {}
+            Err(e) => panic!("wasm: {:?} at {:?}", e, level),
+        }
+    }
+});
@@ -0,0 +1,14 @@
+//! Runs the generated modules with the interpreter and the wasm backend at
+//! every level, and fails when they disagree.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mirage_backend_difftest::{Case, Harness, Interpreted, Wasm};
+use mirage_backend_fuzz::{GeneratedModule, ENTRY};
+
+fuzz_target!(|module: GeneratedModule| {
+    let harness = Harness::with_backends(vec![Box::new(Interpreted), Box::new(Wasm)]);
+    let report = harness.check(&Case::new("fuzz", module.stmts, ENTRY));
+    assert!(report.is_success(), "{}", report);
+});
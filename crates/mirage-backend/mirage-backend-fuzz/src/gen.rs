@@ -0,0 +1,920 @@
+//! The generator of random modules. It takes its choices from the bytes of
+//! an `Unstructured`, so that a fuzzer mutating the bytes explores the
+//! modules, and builds the functions from structured pieces, branches and
+//! counted loops, so that every module verifies and runs to its end
+//! without trapping.
+
+use arbitrary::{Result, Unstructured};
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::{Flag, Flags};
+use mirage_frontend::object::statements::{External, Global, Statement, TypeDef};
+use mirage_frontend::object::util::List;
+use mirage_frontend::object::{
+    MirageObject, MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue,
+};
+
+/// The name of the function a generated module is run from. It takes no
+/// argument and returns a scalar.
+pub const ENTRY: &str = "run";
+
+/// The limits of the generated modules
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The most struct typedefs
+    pub max_typedefs: usize,
+    /// The most globals, scalars and arrays
+    pub max_globals: usize,
+    /// The most functions besides the entry
+    pub max_functions: usize,
+    pub max_args: usize,
+    /// The most instructions, branches and loops in a block
+    pub max_block_len: usize,
+    /// How deep the branches and loops nest
+    pub max_depth: usize,
+    /// The most iterations of a loop
+    pub max_trips: u32,
+    /// The most instructions a call of a function may run, counting the
+    /// iterations of its loops and what its calls run
+    pub max_cost: u64,
+    /// Whether the functions print values with `printf`
+    pub printf: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_typedefs: 2,
+            max_globals: 4,
+            max_functions: 4,
+            max_args: 4,
+            max_block_len: 8,
+            max_depth: 2,
+            max_trips: 6,
+            max_cost: 20_000,
+            printf: true,
+        }
+    }
+}
+
+/// The types of the values the generated code computes with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Scalar {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+const SCALARS: [Scalar; 10] = [
+    Scalar::I8,
+    Scalar::I16,
+    Scalar::I32,
+    Scalar::I64,
+    Scalar::U8,
+    Scalar::U16,
+    Scalar::U32,
+    Scalar::U64,
+    Scalar::F32,
+    Scalar::F64,
+];
+
+/// The elements of the arrays. An array of `i8` is a string, whose
+/// escapes are replaced, so it isn't one.
+const ELEMENTS: [Scalar; 7] = [
+    Scalar::I16,
+    Scalar::I32,
+    Scalar::I64,
+    Scalar::U8,
+    Scalar::U16,
+    Scalar::U32,
+    Scalar::U64,
+];
+
+impl Scalar {
+    pub fn of(ty: &MirageTypeEnum) -> Option<Scalar> {
+        Some(match ty {
+            MirageTypeEnum::Int8(_) => Scalar::I8,
+            MirageTypeEnum::Int16(_) => Scalar::I16,
+            MirageTypeEnum::Int32(_) => Scalar::I32,
+            MirageTypeEnum::Int64(_) => Scalar::I64,
+            MirageTypeEnum::UInt8(_) => Scalar::U8,
+            MirageTypeEnum::UInt16(_) => Scalar::U16,
+            MirageTypeEnum::UInt32(_) => Scalar::U32,
+            MirageTypeEnum::UInt64(_) => Scalar::U64,
+            MirageTypeEnum::Float32(_) => Scalar::F32,
+            MirageTypeEnum::Float64(_) => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    pub fn ty(self) -> MirageTypeEnum {
+        match self {
+            Scalar::I8 => MirageTypeEnum::type_int8().into(),
+            Scalar::I16 => MirageTypeEnum::type_int16().into(),
+            Scalar::I32 => MirageTypeEnum::type_int32().into(),
+            Scalar::I64 => MirageTypeEnum::type_int64().into(),
+            Scalar::U8 => MirageTypeEnum::type_uint8().into(),
+            Scalar::U16 => MirageTypeEnum::type_uint16().into(),
+            Scalar::U32 => MirageTypeEnum::type_uint32().into(),
+            Scalar::U64 => MirageTypeEnum::type_uint64().into(),
+            Scalar::F32 => MirageTypeEnum::type_float32().into(),
+            Scalar::F64 => MirageTypeEnum::type_float64().into(),
+        }
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, Scalar::F32 | Scalar::F64)
+    }
+
+    /// The constant `v`, truncated to the type. A float is `v / 4`, which
+    /// the float types hold exactly.
+    pub fn constant(self, v: i64) -> MirageObject {
+        let value = match self {
+            Scalar::I8 => MirageTypeEnum::type_int8()
+                .const_value(v as i8)
+                .to_value_enum(),
+            Scalar::I16 => MirageTypeEnum::type_int16()
+                .const_value(v as i16)
+                .to_value_enum(),
+            Scalar::I32 => MirageTypeEnum::type_int32()
+                .const_value(v as i32)
+                .to_value_enum(),
+            Scalar::I64 => MirageTypeEnum::type_int64().const_value(v).to_value_enum(),
+            Scalar::U8 => MirageTypeEnum::type_uint8()
+                .const_value(v as u8)
+                .to_value_enum(),
+            Scalar::U16 => MirageTypeEnum::type_uint16()
+                .const_value(v as u16)
+                .to_value_enum(),
+            Scalar::U32 => MirageTypeEnum::type_uint32()
+                .const_value(v as u32)
+                .to_value_enum(),
+            Scalar::U64 => MirageTypeEnum::type_uint64()
+                .const_value(v as u64)
+                .to_value_enum(),
+            Scalar::F32 => MirageTypeEnum::type_float32()
+                .const_value(v as f32 / 4.0)
+                .to_value_enum(),
+            Scalar::F64 => MirageTypeEnum::type_float64()
+                .const_value(v as f64 / 4.0)
+                .to_value_enum(),
+        };
+        MirageObject::from(value)
+    }
+
+    fn add(self, lhs: Value, rhs: Value) -> Command {
+        match self {
+            Scalar::I8 | Scalar::U8 => Command::AddInt8(lhs, rhs),
+            Scalar::I16 | Scalar::U16 => Command::AddInt16(lhs, rhs),
+            Scalar::I32 | Scalar::U32 => Command::AddInt32(lhs, rhs),
+            Scalar::I64 | Scalar::U64 => Command::AddInt64(lhs, rhs),
+            Scalar::F32 => Command::AddFloat32(lhs, rhs),
+            Scalar::F64 => Command::AddFloat64(lhs, rhs),
+        }
+    }
+
+    fn sub(self, lhs: Value, rhs: Value) -> Command {
+        match self {
+            Scalar::I8 | Scalar::U8 => Command::SubInt8(lhs, rhs),
+            Scalar::I16 | Scalar::U16 => Command::SubInt16(lhs, rhs),
+            Scalar::I32 | Scalar::U32 => Command::SubInt32(lhs, rhs),
+            Scalar::I64 | Scalar::U64 => Command::SubInt64(lhs, rhs),
+            Scalar::F32 => Command::SubFloat32(lhs, rhs),
+            Scalar::F64 => Command::SubFloat64(lhs, rhs),
+        }
+    }
+
+    fn incr(self, reg: RegisterValue) -> Command {
+        match self {
+            Scalar::I8 | Scalar::U8 => Command::IncrInt8(reg),
+            Scalar::I16 | Scalar::U16 => Command::IncrInt16(reg),
+            Scalar::I32 | Scalar::U32 => Command::IncrInt32(reg),
+            Scalar::I64 | Scalar::U64 => Command::IncrInt64(reg),
+            Scalar::F32 => Command::IncrFloat32(reg),
+            Scalar::F64 => Command::IncrFloat64(reg),
+        }
+    }
+
+    /// The conversion of `printf` for the type, which has none for a
+    /// `float`
+    fn format(self) -> Option<&'static str> {
+        match self {
+            Scalar::I8 | Scalar::I16 | Scalar::I32 => Some("%d"),
+            Scalar::U8 | Scalar::U16 | Scalar::U32 => Some("%u"),
+            Scalar::I64 => Some("%lld"),
+            Scalar::U64 => Some("%llu"),
+            Scalar::F32 => None,
+            Scalar::F64 => Some("%g"),
+        }
+    }
+}
+
+fn ptr_ty(ty: MirageTypeEnum) -> MirageTypeEnum {
+    MirageTypeEnum::type_ptr(ty).into()
+}
+
+fn val(reg: &RegisterValue) -> Value {
+    Value::Register(reg.clone())
+}
+
+fn i64_val(v: i64) -> Value {
+    Value::ConstValue(Scalar::I64.constant(v))
+}
+
+fn assign(reg: &RegisterValue, instr: LabelBodyInstr) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(reg.clone(), Box::new(instr))
+}
+
+fn cmd(cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Command(cmd)
+}
+
+/// A NUL terminated string constant, with the escapes of the IR
+fn string(s: &str) -> Value {
+    let ty = MirageTypeEnum::type_array(MirageTypeEnum::type_int8().into(), s.len());
+    let chars = s
+        .bytes()
+        .map(|c| {
+            MirageTypeEnum::type_int8()
+                .const_value(c as i8)
+                .to_value_enum()
+        })
+        .collect();
+    Value::ConstValue(MirageObject::from(MirageValueEnum::Array(
+        ty.const_value(chars),
+    )))
+}
+
+/// An integer constant: small, at the edge of the type or any bits
+fn int_bits(u: &mut Unstructured) -> Result<i64> {
+    Ok(match u.int_in_range(0..=3)? {
+        0 => u.int_in_range(-8..=8)?,
+        1 => *u.choose(&[i64::MIN, i64::MAX, -1, 0x7f, 0x80, 0xff, 0x7fff, 0xffff])?,
+        _ => u.arbitrary()?,
+    })
+}
+
+fn constant(u: &mut Unstructured, scalar: Scalar) -> Result<MirageObject> {
+    let v = match scalar.is_float() {
+        true => u.int_in_range(-32..=32)?,
+        false => int_bits(u)?,
+    };
+    Ok(scalar.constant(v))
+}
+
+enum GlobalKind {
+    Scalar {
+        scalar: Scalar,
+        constant: bool,
+    },
+    Array {
+        element: Scalar,
+        len: usize,
+        constant: bool,
+    },
+}
+
+struct Signature {
+    name: String,
+    args: Vec<Scalar>,
+    ret: Scalar,
+    /// The most instructions a call runs
+    cost: u64,
+}
+
+/// What the functions of the module can refer to
+struct ModuleCx {
+    typedefs: Vec<(String, Vec<Scalar>)>,
+    globals: Vec<GlobalKind>,
+    functions: Vec<Signature>,
+    printf: bool,
+}
+
+impl ModuleCx {
+    fn global(&self, index: usize) -> RegisterValue {
+        let ty = match &self.globals[index] {
+            GlobalKind::Scalar { scalar, .. } => scalar.ty(),
+            GlobalKind::Array { element, len, .. } => {
+                MirageTypeEnum::type_array(element.ty(), *len).into()
+            }
+        };
+        RegisterValue::new(index, RegisterType::Global, ty)
+    }
+}
+
+/// A value which can be read where the code is generated
+#[derive(Debug, Clone)]
+struct Var {
+    reg: RegisterValue,
+    scalar: Scalar,
+    /// A float which is only a constant, so that adding it to another keeps
+    /// the values bounded
+    small: bool,
+}
+
+/// What the code generated at some point can use: the values defined in a
+/// label which dominates it
+#[derive(Debug, Clone, Default)]
+struct Env {
+    vars: Vec<Var>,
+    /// Pointers to a scalar
+    slots: Vec<(RegisterValue, Scalar)>,
+    /// The structs made by `new`, with their typedef
+    structs: Vec<(RegisterValue, usize)>,
+    /// The counters of the enclosing loops, with their number of
+    /// iterations
+    counters: Vec<(RegisterValue, u32)>,
+}
+
+impl Env {
+    /// A value of `scalar`: a variable, or else a constant. The float
+    /// operand which is added is small.
+    fn pick(&self, u: &mut Unstructured, scalar: Scalar, small: bool) -> Result<Value> {
+        let vars: Vec<_> = self
+            .vars
+            .iter()
+            .filter(|v| v.scalar == scalar && (v.small || !small || !scalar.is_float()))
+            .collect();
+        if vars.is_empty() || u.ratio(1, 4)? {
+            return Ok(Value::ConstValue(constant(u, scalar)?));
+        }
+        Ok(val(&u.choose(&vars)?.reg))
+    }
+}
+
+/// Generate one function, then its signature
+struct FnGen<'u, 'a> {
+    u: &'u mut Unstructured<'a>,
+    config: &'u Config,
+    module: &'u ModuleCx,
+    labels: Vec<Label>,
+    entry_flags: Flags,
+    /// The label being filled
+    name: String,
+    body: Vec<LabelBodyInstr>,
+    next_reg: usize,
+    next_label: usize,
+    env: Env,
+    depth: usize,
+    /// How many times the current block runs in a call
+    trips: u64,
+    cost: u64,
+}
+
+impl<'u, 'a> FnGen<'u, 'a> {
+    fn new(u: &'u mut Unstructured<'a>, config: &'u Config, module: &'u ModuleCx) -> Self {
+        Self {
+            u,
+            config,
+            module,
+            labels: Vec::new(),
+            entry_flags: Flags::new(vec![]),
+            name: "entry".to_string(),
+            body: Vec::new(),
+            next_reg: 0,
+            next_label: 0,
+            env: Env::default(),
+            depth: 0,
+            trips: 1,
+            cost: 0,
+        }
+    }
+
+    fn reg(&mut self, ty: MirageTypeEnum) -> RegisterValue {
+        self.next_reg += 1;
+        RegisterValue::new(self.next_reg - 1, RegisterType::Register, ty)
+    }
+
+    fn fresh_label(&mut self, prefix: &str) -> String {
+        self.next_label += 1;
+        format!("{}{}", prefix, self.next_label - 1)
+    }
+
+    fn emit(&mut self, instr: LabelBodyInstr) {
+        self.cost += self.trips;
+        self.body.push(instr);
+    }
+
+    fn define(&mut self, scalar: Scalar, instr: LabelBodyInstr, small: bool) {
+        let reg = self.reg(scalar.ty());
+        self.emit(assign(&reg, instr));
+        self.env.vars.push(Var { reg, scalar, small });
+    }
+
+    /// End the label being filled, and start `next`
+    fn start_label(&mut self, next: String) {
+        let flags = match self.labels.is_empty() {
+            true => self.entry_flags.clone(),
+            false => Flags::new(vec![]),
+        };
+        let name = std::mem::replace(&mut self.name, next);
+        let body = std::mem::take(&mut self.body);
+        self.labels.push(Label::new(name, flags, body));
+    }
+
+    fn pick(&mut self, scalar: Scalar, small: bool) -> Result<Value> {
+        self.env.pick(self.u, scalar, small)
+    }
+
+    fn scalar(&mut self) -> Result<Scalar> {
+        Ok(*self.u.choose(&SCALARS)?)
+    }
+
+    /// The body of the function, which returns a value of `ret`
+    fn function(mut self, args: &[Scalar], ret: Scalar) -> Result<(Vec<Label>, u64)> {
+        for (i, scalar) in args.iter().enumerate() {
+            self.env.vars.push(Var {
+                reg: RegisterValue::new(i, RegisterType::Argument, scalar.ty()),
+                scalar: *scalar,
+                small: false,
+            });
+        }
+        for (i, global) in self.module.globals.iter().enumerate() {
+            if let GlobalKind::Scalar { scalar, constant } = global {
+                self.env.vars.push(Var {
+                    reg: self.module.global(i),
+                    scalar: *scalar,
+                    small: *constant,
+                });
+            }
+        }
+        self.block()?;
+        let value = self.pick(ret, false)?;
+        self.emit(cmd(Command::Ret(value)));
+        self.start_label(String::new());
+        Ok((self.labels, self.cost))
+    }
+
+    fn block(&mut self) -> Result<()> {
+        let len = self.u.int_in_range(0..=self.config.max_block_len)?;
+        for _ in 0..len {
+            if self.cost >= self.config.max_cost {
+                break;
+            }
+            let nest = self.depth < self.config.max_depth && self.u.ratio(1, 6)?;
+            match nest {
+                true if self.u.arbitrary()? => self.branch()?,
+                true => self.counted_loop()?,
+                false => self.instruction()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// `jeq` to a label, the code in between being the other branch, and
+    /// phis where they join
+    fn branch(&mut self) -> Result<()> {
+        let scalar = self.scalar()?;
+        let lhs = self.pick(scalar, false)?;
+        let rhs = self.pick(scalar, false)?;
+        let then = self.fresh_label("then");
+        let other = self.fresh_label("else");
+        let join = self.fresh_label("join");
+        self.emit(cmd(Command::Jeq(then.clone(), lhs, rhs)));
+        let outer = self.env.clone();
+
+        self.depth += 1;
+        self.start_label(other);
+        self.block()?;
+        let other_env = std::mem::replace(&mut self.env, outer.clone());
+        let other_end = self.name.clone();
+        self.emit(cmd(Command::Jump(join.clone())));
+
+        self.start_label(then);
+        self.block()?;
+        let then_env = std::mem::replace(&mut self.env, outer);
+        let then_end = self.name.clone();
+        // The branch taken by the `jeq` falls through to the join, or
+        // jumps there
+        if self.u.arbitrary()? {
+            self.emit(cmd(Command::Jump(join.clone())));
+        }
+        self.depth -= 1;
+
+        self.start_label(join);
+        for _ in 0..self.u.int_in_range(0..=2)? {
+            let scalar = self.scalar()?;
+            let from_other = other_env.pick(self.u, scalar, false)?;
+            let from_then = then_env.pick(self.u, scalar, false)?;
+            let phi = Command::Phi(vec![
+                (other_end.clone(), from_other),
+                (then_end.clone(), from_then),
+            ]);
+            self.define(scalar, cmd(phi), false);
+        }
+        Ok(())
+    }
+
+    /// A loop running a constant number of times, counted by a phi, with
+    /// other phis carrying values from an iteration to the next
+    fn counted_loop(&mut self) -> Result<()> {
+        let trips = self.u.int_in_range(0..=self.config.max_trips)?;
+        let head = self.fresh_label("head");
+        let body = self.fresh_label("body");
+        let exit = self.fresh_label("exit");
+        // The preheader falls through to the head, or jumps there
+        if self.u.arbitrary()? {
+            self.emit(cmd(Command::Jump(head.clone())));
+        }
+        let pre = self.name.clone();
+        self.start_label(head.clone());
+
+        let i64_ty = Scalar::I64.ty();
+        let counter = self.reg(i64_ty.clone());
+        let next = self.reg(i64_ty);
+        // The latch and the values of the next iteration are known once
+        // the body is made
+        self.emit(assign(&counter, cmd(Command::Phi(vec![]))));
+        let mut carried = Vec::new();
+        for _ in 0..self.u.int_in_range(0..=2)? {
+            let scalar = self.scalar()?;
+            let init = self.pick(scalar, false)?;
+            let reg = self.reg(scalar.ty());
+            self.emit(assign(&reg, cmd(Command::Phi(vec![]))));
+            carried.push((reg, scalar, init));
+        }
+        self.emit(cmd(Command::Jeq(
+            exit.clone(),
+            val(&counter),
+            i64_val(trips as i64),
+        )));
+        let head_at = self.labels.len();
+        for (reg, scalar, _) in &carried {
+            self.env.vars.push(Var {
+                reg: reg.clone(),
+                scalar: *scalar,
+                small: false,
+            });
+        }
+        self.env.vars.push(Var {
+            reg: counter.clone(),
+            scalar: Scalar::I64,
+            small: false,
+        });
+        let outer = self.env.clone();
+
+        self.start_label(body);
+        self.env.counters.push((counter.clone(), trips));
+        self.depth += 1;
+        let trips_outside = self.trips;
+        self.trips *= trips.max(1) as u64;
+        self.block()?;
+        self.emit(assign(
+            &next,
+            cmd(Command::AddInt64(val(&counter), i64_val(1))),
+        ));
+        let mut phis = vec![vec![(pre.clone(), i64_val(0)), (String::new(), val(&next))]];
+        for (_, scalar, init) in carried {
+            let value = self.pick(scalar, false)?;
+            phis.push(vec![(pre.clone(), init), (String::new(), value)]);
+        }
+        self.emit(cmd(Command::Jump(head)));
+        self.trips = trips_outside;
+        self.depth -= 1;
+        let latch = self.name.clone();
+        for (instr, mut incoming) in self.labels[head_at].body.iter_mut().zip(phis) {
+            incoming[1].0 = latch.clone();
+            if let LabelBodyInstr::Assign(_, phi) = instr {
+                **phi = cmd(Command::Phi(incoming));
+            }
+        }
+
+        self.start_label(exit);
+        self.env = outer;
+        Ok(())
+    }
+
+    fn instruction(&mut self) -> Result<()> {
+        match self.u.int_in_range(0..=11)? {
+            0 => {
+                let scalar = self.scalar()?;
+                let value = constant(self.u, scalar)?;
+                self.define(scalar, cmd(Command::Const(value)), true);
+            }
+            1 | 2 => {
+                let scalar = self.scalar()?;
+                let lhs = self.pick(scalar, false)?;
+                // A float only gets a small value added, so that the values
+                // grow slowly enough never to overflow
+                let rhs = self.pick(scalar, true)?;
+                let op = match self.u.arbitrary()? {
+                    true => scalar.add(lhs, rhs),
+                    false => scalar.sub(lhs, rhs),
+                };
+                self.define(scalar, cmd(op), false);
+            }
+            3 => {
+                if self.env.vars.is_empty() {
+                    return Ok(());
+                }
+                let var = self.u.choose(&self.env.vars)?.clone();
+                self.define(var.scalar, cmd(var.scalar.incr(var.reg)), false);
+            }
+            4 => self.slot()?,
+            5 => self.memory()?,
+            6 => self.array()?,
+            7 => self.structs()?,
+            8 | 9 => self.call()?,
+            10 => self.print()?,
+            _ => self.global_store()?,
+        }
+        Ok(())
+    }
+
+    /// A register whose address is taken, used as memory
+    fn slot(&mut self) -> Result<()> {
+        let scalar = self.scalar()?;
+        let init = self.pick(scalar, false)?;
+        let init = match init {
+            Value::ConstValue(obj) => obj,
+            Value::Register(reg) => MirageObject::new(MirageValueEnum::Register(reg), scalar.ty()),
+            Value::List(_) => unreachable!("a scalar isn't a list"),
+        };
+        let var = self.reg(scalar.ty());
+        let ptr = self.reg(ptr_ty(scalar.ty()));
+        self.emit(assign(&var, cmd(Command::Const(init))));
+        self.emit(assign(&ptr, cmd(Command::Ref(val(&var)))));
+        self.env.slots.push((ptr, scalar));
+        Ok(())
+    }
+
+    /// A load from a slot, or a store to it
+    fn memory(&mut self) -> Result<()> {
+        if self.env.slots.is_empty() {
+            return self.slot();
+        }
+        let (ptr, scalar) = self.u.choose(&self.env.slots)?.clone();
+        match self.u.arbitrary()? {
+            true => self.define(scalar, cmd(Command::Load(scalar.ty(), val(&ptr))), false),
+            false => {
+                let value = self.pick(scalar, false)?;
+                self.emit(cmd(Command::Store(ptr, value)));
+            }
+        }
+        Ok(())
+    }
+
+    /// A store to a global which isn't `#const`, through its address
+    fn global_store(&mut self) -> Result<()> {
+        let module = self.module;
+        let writable: Vec<_> = (module.globals.iter().enumerate())
+            .filter_map(|(i, g)| match g {
+                GlobalKind::Scalar {
+                    scalar,
+                    constant: false,
+                } => Some((i, *scalar)),
+                _ => None,
+            })
+            .collect();
+        if writable.is_empty() {
+            return Ok(());
+        }
+        let (index, scalar) = *self.u.choose(&writable)?;
+        let value = self.pick(scalar, false)?;
+        let ptr = self.reg(ptr_ty(scalar.ty()));
+        let global = module.global(index);
+        self.emit(assign(&ptr, cmd(Command::Ref(val(&global)))));
+        self.emit(cmd(Command::Store(ptr, value)));
+        Ok(())
+    }
+
+    /// A load from an element of a global array, or a store to it. The
+    /// index is a constant, or the counter of a loop running no more times
+    /// than the array is long.
+    fn array(&mut self) -> Result<()> {
+        let module = self.module;
+        let arrays: Vec<_> = (module.globals.iter().enumerate())
+            .filter_map(|(i, g)| match g {
+                GlobalKind::Array {
+                    element,
+                    len,
+                    constant,
+                } => Some((i, *element, *len, *constant)),
+                _ => None,
+            })
+            .collect();
+        if arrays.is_empty() {
+            return Ok(());
+        }
+        let (index, element, len, constant) = *self.u.choose(&arrays)?;
+        let counters: Vec<_> = (self.env.counters.iter())
+            .filter(|(_, trips)| *trips as usize <= len)
+            .map(|(counter, _)| counter.clone())
+            .collect();
+        let at = match counters.is_empty() || self.u.arbitrary()? {
+            true => i64_val(self.u.int_in_range(0..=len as i64 - 1)?),
+            false => val(self.u.choose(&counters)?),
+        };
+        let global = module.global(index);
+        let ptr = self.reg(ptr_ty(element.ty()));
+        self.emit(assign(
+            &ptr,
+            cmd(Command::GetElementPtr(
+                global.ty.clone(),
+                val(&global),
+                vec![i64_val(0), at],
+            )),
+        ));
+        match constant || self.u.arbitrary()? {
+            true => self.define(element, cmd(Command::Load(element.ty(), val(&ptr))), false),
+            false => {
+                let value = self.pick(element, false)?;
+                self.emit(cmd(Command::Store(ptr, value)));
+            }
+        }
+        Ok(())
+    }
+
+    /// A struct made by `new`, or a field read with `get`
+    fn structs(&mut self) -> Result<()> {
+        let module = self.module;
+        if module.typedefs.is_empty() {
+            return Ok(());
+        }
+        if !self.env.structs.is_empty() && self.u.arbitrary()? {
+            let (reg, typedef) = self.u.choose(&self.env.structs)?.clone();
+            let fields = &module.typedefs[typedef].1;
+            let index = self.u.choose_index(fields.len())?;
+            let scalar = fields[index];
+            self.define(scalar, cmd(Command::Get(reg, index)), false);
+            return Ok(());
+        }
+        let typedef = self.u.choose_index(module.typedefs.len())?;
+        let (name, fields) = &module.typedefs[typedef];
+        let mut values = Vec::new();
+        for scalar in fields {
+            values.push(self.pick(*scalar, false)?);
+        }
+        let ty = MirageTypeEnum::type_struct(fields.iter().map(|s| s.ty()).collect()).into();
+        let reg = self.reg(ty);
+        self.emit(assign(
+            &reg,
+            cmd(Command::New(name.clone(), List::from_vec(values))),
+        ));
+        self.env.structs.push((reg, typedef));
+        Ok(())
+    }
+
+    /// A call of a function made before this one, unless it would run too
+    /// long
+    fn call(&mut self) -> Result<()> {
+        let module = self.module;
+        let callees: Vec<_> = (module.functions.iter())
+            .filter(|f| self.cost + self.trips * f.cost <= self.config.max_cost)
+            .collect();
+        if callees.is_empty() {
+            return Ok(());
+        }
+        let callee = *self.u.choose(&callees)?;
+        let mut args = Vec::new();
+        for scalar in &callee.args {
+            args.push(self.pick(*scalar, false)?);
+        }
+        let call = LabelBodyInstr::Call(callee.name.clone(), args);
+        self.cost += self.trips * callee.cost;
+        match self.u.ratio(1, 4)? {
+            true => self.emit(call),
+            false => self.define(callee.ret, call, false),
+        }
+        Ok(())
+    }
+
+    fn print(&mut self) -> Result<()> {
+        if !self.module.printf || self.env.vars.is_empty() {
+            return Ok(());
+        }
+        let var = self.u.choose(&self.env.vars)?.clone();
+        let Some(format) = var.scalar.format() else {
+            return Ok(());
+        };
+        let format = string(&format!("{}\\n", format));
+        self.emit(LabelBodyInstr::Call(
+            "printf".to_string(),
+            vec![format, val(&var.reg)],
+        ));
+        Ok(())
+    }
+}
+
+/// A random module which verifies, and whose entry, `run`, returns
+/// without trapping after running at most about `max_cost` instructions
+pub fn generate(u: &mut Unstructured, config: &Config) -> Result<Vec<Statement>> {
+    let mut stmts = Vec::new();
+    let mut module = ModuleCx {
+        typedefs: Vec::new(),
+        globals: Vec::new(),
+        functions: Vec::new(),
+        printf: config.printf,
+    };
+    if config.printf {
+        let i8_ptr = ptr_ty(Scalar::I8.ty());
+        let ty = FunctionType::new(vec![i8_ptr], Scalar::I32.ty(), true);
+        stmts.push(Statement::External(External::new("printf".to_string(), ty)));
+    }
+
+    for i in 0..u.int_in_range(0..=config.max_typedefs)? {
+        let mut fields = Vec::new();
+        for _ in 0..u.int_in_range(1..=4)? {
+            fields.push(*u.choose(&SCALARS)?);
+        }
+        let name = format!("s{}", i);
+        let types = fields.iter().map(|s| s.ty()).collect();
+        stmts.push(Statement::Typedef(TypeDef::new(
+            name.clone(),
+            List::from_vec(types),
+        )));
+        module.typedefs.push((name, fields));
+    }
+
+    for i in 0..u.int_in_range(0..=config.max_globals)? {
+        let read_only = u.ratio(1, 3)?;
+        let flags = match read_only {
+            true => Flags::new(vec![Flag::constant()]),
+            false => Flags::new(vec![]),
+        };
+        let (kind, value) = match u.ratio(1, 3)? {
+            true => {
+                let element = *u.choose(&ELEMENTS)?;
+                let len = u.int_in_range(1..=6)?;
+                let mut values = Vec::new();
+                for _ in 0..len {
+                    values.push(constant_value(u, element)?);
+                }
+                let ty = MirageTypeEnum::type_array(element.ty(), len);
+                let value = MirageValueEnum::Array(ty.const_value(values));
+                (
+                    GlobalKind::Array {
+                        element,
+                        len,
+                        constant: read_only,
+                    },
+                    MirageObject::from(value),
+                )
+            }
+            false => {
+                let scalar = *u.choose(&SCALARS)?;
+                let value = constant(u, scalar)?;
+                (
+                    GlobalKind::Scalar {
+                        scalar,
+                        constant: read_only,
+                    },
+                    value,
+                )
+            }
+        };
+        stmts.push(Statement::Global(Global::with_flags(
+            format!("g{}", i),
+            flags,
+            value,
+        )));
+        module.globals.push(kind);
+    }
+
+    let functions = u.int_in_range(0..=config.max_functions)?;
+    for i in 0..=functions {
+        let entry = i == functions;
+        let mut args = Vec::new();
+        if !entry {
+            for _ in 0..u.int_in_range(0..=config.max_args)? {
+                args.push(*u.choose(&SCALARS)?);
+            }
+        }
+        let ret = *u.choose(&SCALARS)?;
+        let name = match entry {
+            true => ENTRY.to_string(),
+            false => format!("f{}", i),
+        };
+        let mut gen = FnGen::new(u, config, &module);
+        if !entry {
+            let flags = [Flag::internal(), Flag::inline(), Flag::noinline()];
+            let flag = gen.u.choose_index(flags.len() + 1)?;
+            gen.entry_flags = Flags::new(flags.into_iter().skip(flag).take(1).collect());
+        }
+        let (labels, cost) = gen.function(&args, ret)?;
+        let ty = FunctionType::new(args.iter().map(|s| s.ty()).collect(), ret.ty(), false);
+        let mut function = ty.fn_value(name.clone());
+        for label in labels {
+            function.add_label(label);
+        }
+        stmts.push(Statement::Function(function));
+        module.functions.push(Signature {
+            name,
+            args,
+            ret,
+            cost,
+        });
+    }
+    Ok(stmts)
+}
+
+fn constant_value(u: &mut Unstructured, scalar: Scalar) -> Result<MirageValueEnum> {
+    Ok(constant(u, scalar)?.get_value_ref().clone())
+}
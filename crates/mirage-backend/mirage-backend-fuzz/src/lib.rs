@@ -0,0 +1,40 @@
+#[cfg(test)]
+mod test;
+
+mod gen;
+mod shrink;
+mod strategy;
+
+pub use gen::{generate, Config, ENTRY};
+pub use shrink::{shrink, Candidates};
+pub use strategy::{modules, ModuleStrategy, ModuleTree};
+
+use arbitrary::{Arbitrary, Unstructured};
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::stringify::Stringify;
+
+/// A module made by the generator with the default config, as the input of
+/// a fuzz target or of a proptest test. It is debugged as its IR, which is
+/// what the failures show. The fuzz targets using it are in `fuzz`, run
+/// with `cargo fuzz run difftest` or `cargo fuzz run codegen` from there.
+#[derive(Clone, PartialEq)]
+pub struct GeneratedModule {
+    pub stmts: Vec<Statement>,
+}
+
+impl std::fmt::Debug for GeneratedModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for stmt in &self.stmts {
+            writeln!(f, "{}", stmt.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Arbitrary<'a> for GeneratedModule {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self {
+            stmts: generate(u, &Config::default())?,
+        })
+    }
+}
@@ -0,0 +1,291 @@
+//! The shrinker: it simplifies a module one step at a time, removing a
+//! statement, a label or an instruction, or making a value zero, as long as
+//! the module still verifies and still fails.
+
+use mirage_backend_opti::ir::{
+    for_each_read_operand_mut, for_each_register_mut, for_each_use, is_phi,
+};
+use mirage_backend_opti::verify::verify_module;
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::RegisterType;
+
+use crate::gen::Scalar;
+
+/// One step simpler
+#[derive(Debug, Clone, Copy)]
+enum Reduction {
+    /// Remove a statement
+    Statement(usize),
+    /// Remove a label of a function other than its entry, with the jumps
+    /// to it and the values its phis get from it
+    Label(usize, usize),
+    /// Remove an instruction
+    Instr(usize, usize, usize),
+    /// Assign zero instead of what an instruction computes
+    Zero(usize, usize, usize),
+    /// Read zero instead of an operand of an instruction
+    Operand(usize, usize, usize, usize),
+}
+
+fn function(stmts: &[Statement], index: usize) -> Option<&FunctionValue> {
+    match stmts.get(index) {
+        Some(Statement::Function(f)) => Some(f),
+        _ => None,
+    }
+}
+
+fn function_mut(stmts: &mut [Statement], index: usize) -> Option<&mut FunctionValue> {
+    match stmts.get_mut(index) {
+        Some(Statement::Function(f)) => Some(f),
+        _ => None,
+    }
+}
+
+fn instructions(stmts: &[Statement]) -> impl Iterator<Item = &LabelBodyInstr> {
+    stmts
+        .iter()
+        .filter_map(|s| match s {
+            Statement::Function(f) => Some(f),
+            _ => None,
+        })
+        .flat_map(|f| f.get_labels().iter().flat_map(|l| l.body.iter()))
+}
+
+/// The zero of the type of `value`, unless it already is a zero or isn't
+/// a scalar
+fn zero_of(value: &Value) -> Option<Value> {
+    if let Value::List(_) = value {
+        return None;
+    }
+    let zero = Value::ConstValue(Scalar::of(&value.get_type())?.constant(0));
+    (*value != zero).then_some(zero)
+}
+
+fn operand_count(instr: &LabelBodyInstr) -> usize {
+    let mut count = 0;
+    for_each_read_operand_mut(&mut instr.clone(), &mut |_| count += 1);
+    count
+}
+
+/// Every reduction of `stmts`, the largest first
+fn reductions(stmts: &[Statement]) -> Vec<Reduction> {
+    let mut reductions: Vec<_> = (0..stmts.len()).map(Reduction::Statement).collect();
+    let functions: Vec<_> = (0..stmts.len())
+        .filter_map(|s| function(stmts, s).map(|f| (s, f)))
+        .collect();
+    for (s, f) in &functions {
+        reductions.extend((1..f.len_labels()).map(|l| Reduction::Label(*s, l)));
+    }
+    for (s, f) in &functions {
+        for (l, label) in f.get_labels().iter().enumerate() {
+            reductions.extend((0..label.body.len()).map(|i| Reduction::Instr(*s, l, i)));
+        }
+    }
+    for (s, f) in &functions {
+        for (l, label) in f.get_labels().iter().enumerate() {
+            for (i, instr) in label.body.iter().enumerate() {
+                if matches!(instr, LabelBodyInstr::Assign(..)) {
+                    reductions.push(Reduction::Zero(*s, l, i));
+                }
+                reductions
+                    .extend((0..operand_count(instr)).map(|k| Reduction::Operand(*s, l, i, k)));
+            }
+        }
+    }
+    reductions
+}
+
+/// `stmts` with the reduction made, if it applies and the module still
+/// verifies
+fn apply(stmts: &[Statement], reduction: Reduction) -> Option<Vec<Statement>> {
+    let mut stmts = stmts.to_vec();
+    match reduction {
+        Reduction::Statement(s) => remove_statement(&mut stmts, s)?,
+        Reduction::Label(s, l) => {
+            let f = function_mut(&mut stmts, s)?;
+            let name = f.get_labels_mut().remove(l).name;
+            for label in f.get_labels_mut() {
+                label.body.retain(|instr| {
+                    !matches!(
+                        instr,
+                        LabelBodyInstr::Command(Command::Jump(target) | Command::Jeq(target, _, _))
+                            if *target == name
+                    )
+                });
+                for instr in &mut label.body {
+                    if let LabelBodyInstr::Assign(_, phi) = instr {
+                        if let LabelBodyInstr::Command(Command::Phi(incoming)) = &mut **phi {
+                            incoming.retain(|(from, _)| *from != name);
+                            if incoming.is_empty() {
+                                return None;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Reduction::Instr(s, l, i) => {
+            function_mut(&mut stmts, s)?.get_labels_mut()[l]
+                .body
+                .remove(i);
+        }
+        Reduction::Zero(s, l, i) => {
+            let body = &mut function_mut(&mut stmts, s)?.get_labels_mut()[l].body;
+            let LabelBodyInstr::Assign(reg, instr) = &body[i] else {
+                return None;
+            };
+            let zero = Scalar::of(&reg.ty)?.constant(0);
+            if **instr == LabelBodyInstr::Command(Command::Const(zero.clone())) {
+                return None;
+            }
+            let phi = is_phi(&body[i]);
+            let reg = reg.clone();
+            body.remove(i);
+            let zero = LabelBodyInstr::Assign(
+                reg,
+                Box::new(LabelBodyInstr::Command(Command::Const(zero))),
+            );
+            // The phis stay at the start of the label
+            let at = match phi {
+                true => body.iter().take_while(|i| is_phi(i)).count(),
+                false => i,
+            };
+            body.insert(at, zero);
+        }
+        Reduction::Operand(s, l, i, k) => {
+            let instr = &mut function_mut(&mut stmts, s)?.get_labels_mut()[l].body[i];
+            let mut n = 0;
+            let mut changed = false;
+            for_each_read_operand_mut(instr, &mut |value| {
+                if n == k {
+                    if let Some(zero) = zero_of(value) {
+                        *value = zero;
+                        changed = true;
+                    }
+                }
+                n += 1;
+            });
+            if !changed {
+                return None;
+            }
+        }
+    }
+    verify_module(&stmts).ok()?;
+    Some(stmts)
+}
+
+/// Remove a statement. A global is only removed when nothing reads it, and
+/// the next globals are renumbered. A typedef is only removed when no
+/// `new` makes it.
+fn remove_statement(stmts: &mut Vec<Statement>, s: usize) -> Option<()> {
+    match &stmts[s] {
+        Statement::Global(_) => {
+            let index = stmts[..s].iter().filter(|s| s.is_global()).count();
+            let mut used = false;
+            for instr in instructions(stmts) {
+                for_each_use(instr, &mut |reg, _| {
+                    used |= reg.register_type == RegisterType::Global && reg.index == index;
+                });
+            }
+            if used {
+                return None;
+            }
+            for stmt in stmts.iter_mut() {
+                let Statement::Function(f) = stmt else {
+                    continue;
+                };
+                for instr in f
+                    .get_labels_mut()
+                    .iter_mut()
+                    .flat_map(|l| l.body.iter_mut())
+                {
+                    for_each_register_mut(instr, &mut |reg| {
+                        if reg.register_type == RegisterType::Global && reg.index > index {
+                            reg.index -= 1;
+                        }
+                    });
+                }
+            }
+        }
+        Statement::Typedef(typedef) => {
+            let made = instructions(stmts).any(|instr| match instr {
+                LabelBodyInstr::Assign(_, instr) => matches!(
+                    &**instr,
+                    LabelBodyInstr::Command(Command::New(name, _)) if *name == typedef.name
+                ),
+                _ => false,
+            });
+            if made {
+                return None;
+            }
+        }
+        _ => {}
+    }
+    stmts.remove(s);
+    Some(())
+}
+
+/// The modules one step simpler than a module which verify, the largest
+/// reductions first
+pub struct Candidates {
+    base: Vec<Statement>,
+    reductions: Vec<Reduction>,
+    next: usize,
+}
+
+impl Candidates {
+    pub fn new(base: Vec<Statement>) -> Self {
+        Self::starting_at(base, 0)
+    }
+
+    /// The candidates after the first `skip` reductions
+    fn starting_at(base: Vec<Statement>, skip: usize) -> Self {
+        Self {
+            reductions: reductions(&base),
+            base,
+            next: skip,
+        }
+    }
+
+    /// The module the candidates simplify
+    pub fn base(&self) -> &[Statement] {
+        &self.base
+    }
+}
+
+impl Iterator for Candidates {
+    type Item = Vec<Statement>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(reduction) = self.reductions.get(self.next) {
+            self.next += 1;
+            if let Some(candidate) = apply(&self.base, *reduction) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+}
+
+/// Simplify `stmts` as long as `still_fails` holds, until no candidate
+/// does. Each pass resumes where the last reduction was made, and the
+/// shrinking ends with a pass which finds nothing from the start.
+pub fn shrink(
+    mut stmts: Vec<Statement>,
+    mut still_fails: impl FnMut(&[Statement]) -> bool,
+) -> Vec<Statement> {
+    let mut from = 0;
+    loop {
+        let mut candidates = Candidates::starting_at(stmts.clone(), from);
+        match candidates.find(|candidate| still_fails(candidate)) {
+            Some(smaller) => {
+                from = candidates.next - 1;
+                stmts = smaller;
+            }
+            None if from == 0 => return stmts,
+            None => from = 0,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! The proptest strategy of the generated modules, shrunk with the
+//! shrinker of the crate
+
+use arbitrary::Unstructured;
+use mirage_frontend::object::statements::Statement;
+use proptest::prelude::Rng;
+use proptest::strategy::{NewTree, Strategy, ValueTree};
+use proptest::test_runner::TestRunner;
+
+use crate::gen::{generate, Config};
+use crate::shrink::Candidates;
+use crate::GeneratedModule;
+
+/// How many random bytes a module is generated from
+const BYTES: usize = 4096;
+
+/// Generates modules with the limits of a config
+#[derive(Debug, Clone)]
+pub struct ModuleStrategy {
+    config: Config,
+}
+
+/// The strategy of the modules generated with `config`
+pub fn modules(config: Config) -> ModuleStrategy {
+    ModuleStrategy { config }
+}
+
+impl Strategy for ModuleStrategy {
+    type Tree = ModuleTree;
+    type Value = GeneratedModule;
+
+    fn new_tree(&self, runner: &mut TestRunner) -> NewTree<Self> {
+        let mut bytes = vec![0; BYTES];
+        runner.rng().fill_bytes(&mut bytes);
+        let stmts = generate(&mut Unstructured::new(&bytes), &self.config)
+            .map_err(|e| format!("the generator failed: {}", e))?;
+        Ok(ModuleTree {
+            candidates: Candidates::new(stmts.clone()),
+            current: stmts,
+        })
+    }
+}
+
+/// A generated module and its candidates. Simplifying moves to the first
+/// candidate, and complicating, once a candidate passes, moves to the next
+/// one of the last module which failed.
+pub struct ModuleTree {
+    current: Vec<Statement>,
+    candidates: Candidates,
+}
+
+impl ModuleTree {
+    fn advance(&mut self) -> bool {
+        match self.candidates.next() {
+            Some(candidate) => {
+                self.current = candidate;
+                true
+            }
+            None => {
+                self.current = self.candidates.base().to_vec();
+                false
+            }
+        }
+    }
+}
+
+impl ValueTree for ModuleTree {
+    type Value = GeneratedModule;
+
+    fn current(&self) -> GeneratedModule {
+        GeneratedModule {
+            stmts: self.current.clone(),
+        }
+    }
+
+    fn simplify(&mut self) -> bool {
+        self.candidates = Candidates::new(self.current.clone());
+        self.advance()
+    }
+
+    fn complicate(&mut self) -> bool {
+        self.advance()
+    }
+}
+
+impl proptest::arbitrary::Arbitrary for GeneratedModule {
+    type Parameters = Config;
+    type Strategy = ModuleStrategy;
+
+    fn arbitrary_with(config: Config) -> ModuleStrategy {
+        modules(config)
+    }
+}
@@ -0,0 +1,141 @@
+use mirage_backend_difftest::{register_stdio, Case, Harness, Interpreted, Wasm};
+use mirage_backend_interpreter::Interpreter;
+use mirage_backend_opti::verify::verify_module;
+use mirage_frontend::object::label::{LabelBodyInstr, Value};
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::parser::parse;
+use proptest::prelude::*;
+use proptest::strategy::ValueTree;
+use proptest::test_runner::{Config as RunnerConfig, TestError, TestRunner};
+
+use crate::{modules, shrink, Config, GeneratedModule, ENTRY};
+
+fn instructions(stmts: &[Statement]) -> impl Iterator<Item = &LabelBodyInstr> {
+    stmts
+        .iter()
+        .filter_map(|s| match s {
+            Statement::Function(f) => Some(f),
+            _ => None,
+        })
+        .flat_map(|f| f.get_labels().iter().flat_map(|l| l.body.iter()))
+}
+
+fn prints(stmts: &[Statement]) -> bool {
+    instructions(stmts)
+        .any(|instr| matches!(instr, LabelBodyInstr::Call(name, _) if name == "printf"))
+}
+
+/// The modules of a deterministic runner
+fn sample(count: usize, config: Config) -> Vec<GeneratedModule> {
+    let mut runner = TestRunner::deterministic();
+    let strategy = modules(config);
+    (0..count)
+        .map(|_| strategy.new_tree(&mut runner).unwrap().current())
+        .collect()
+}
+
+proptest! {
+    #![proptest_config(RunnerConfig::with_cases(64))]
+
+    #[test]
+    fn test_generated_modules_run(module in any::<GeneratedModule>()) {
+        prop_assert!(verify_module(&module.stmts).is_ok());
+        let mut interpreter = Interpreter::new(module.stmts.clone());
+        interpreter.set_fuel(Some(1_000_000));
+        register_stdio(&mut interpreter);
+        let result = interpreter.call(ENTRY, &[]);
+        prop_assert!(result.is_ok(), "{}", result.unwrap_err());
+    }
+
+    #[test]
+    fn test_parse_printed_modules(module in any::<GeneratedModule>()) {
+        let text: Vec<String> = module.stmts.iter().map(|stmt| stmt.to_string()).collect();
+        let parsed = parse(&text.join("\n"));
+        prop_assert!(parsed.is_ok(), "{}", parsed.unwrap_err());
+        // The registers only compare by name, their types are checked
+        // through `Debug`
+        prop_assert_eq!(format!("{:?}", parsed.unwrap()), format!("{:?}", module.stmts));
+    }
+}
+
+#[test]
+fn test_differential() {
+    let harness = Harness::with_backends(vec![Box::new(Interpreted), Box::new(Wasm)]);
+    let cases: Vec<_> = sample(24, Config::default())
+        .into_iter()
+        .enumerate()
+        .map(|(i, module)| Case::new(&format!("generated_{}", i), module.stmts, ENTRY))
+        .collect();
+    let report = harness.check_all(&cases);
+    assert!(report.is_success(), "{}", report);
+}
+
+#[test]
+fn test_config() {
+    let config = Config {
+        max_typedefs: 0,
+        max_globals: 0,
+        max_functions: 0,
+        printf: false,
+        ..Config::default()
+    };
+    for module in sample(16, config) {
+        match module.stmts.as_slice() {
+            [Statement::Function(f)] => assert_eq!(f.get_name(), ENTRY),
+            _ => panic!("{:?}", module),
+        }
+    }
+}
+
+/// What the entry of a module prints when it is interpreted
+fn output(stmts: &[Statement]) -> Vec<u8> {
+    let mut interpreter = Interpreter::new(stmts.to_vec());
+    interpreter.set_fuel(Some(1_000_000));
+    let stdout = register_stdio(&mut interpreter);
+    match interpreter.call(ENTRY, &[]) {
+        Ok(_) => stdout.take(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[test]
+fn test_shrink() {
+    let module = sample(32, Config::default())
+        .into_iter()
+        .find(|module| module.stmts.len() > 4 && !output(&module.stmts).is_empty())
+        .expect("a module prints");
+    let shrunk = shrink(module.stmts, |stmts| !output(stmts).is_empty());
+    assert!(verify_module(&shrunk).is_ok());
+    assert!(!output(&shrunk).is_empty());
+    // The printf extern, the entry, and at most a function between them,
+    // calling printf with constants
+    assert!(shrunk.len() <= 3, "{:?}", GeneratedModule { stmts: shrunk });
+    assert!(matches!(&shrunk[0], Statement::External(printf) if printf.name == "printf"));
+    for instr in instructions(&shrunk) {
+        match instr {
+            LabelBodyInstr::Call(_, args) => {
+                assert!(args.iter().all(|arg| matches!(arg, Value::ConstValue(_))))
+            }
+            LabelBodyInstr::Command(_) => {}
+            instr => panic!("{:?}", instr),
+        }
+    }
+}
+
+#[test]
+fn test_strategy_shrinks() {
+    let mut runner = TestRunner::deterministic();
+    let result = runner.run(&modules(Config::default()), |module| {
+        prop_assert!(!prints(&module.stmts));
+        Ok(())
+    });
+    match result {
+        Err(TestError::Fail(_, module)) => {
+            assert!(prints(&module.stmts));
+            assert_eq!(module.stmts.len(), 2, "{:?}", module);
+            assert!(instructions(&module.stmts).count() <= 2, "{:?}", module);
+        }
+        result => panic!("{:?}", result),
+    }
+}
@@ -0,0 +1,53 @@
+use mirage_frontend::object::util::to_string_with_special_char;
+use mirage_frontend::object::MirageValueEnum;
+
+use crate::layout::{size_of, struct_layout};
+
+/// The bytes of a constant string, with its escapes replaced and NUL
+/// terminated
+pub fn string_bytes(s: &str) -> Vec<u8> {
+    let mut bytes = to_string_with_special_char(s).into_bytes();
+    bytes.push(0);
+    bytes
+}
+
+/// The initial bytes of a global holding `value`. A string global is NUL
+/// terminated, like a string constant. A pointer is null, and so is a
+/// register, which has no value before the program runs.
+pub fn global_data(value: &MirageValueEnum) -> Vec<u8> {
+    if let Some(s) = value.try_to_rust_string() {
+        return string_bytes(&s);
+    }
+    let mut bytes = vec![0; size_of(&value.get_type()) as usize];
+    write(&mut bytes, 0, value);
+    bytes
+}
+
+fn write(bytes: &mut [u8], offset: usize, value: &MirageValueEnum) {
+    let mut put = |b: &[u8]| bytes[offset..offset + b.len()].copy_from_slice(b);
+    match value {
+        MirageValueEnum::Int8(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Int16(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Int32(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Int64(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::UInt8(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::UInt16(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::UInt32(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::UInt64(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Float32(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Float64(v) => put(&v.value.to_le_bytes()),
+        MirageValueEnum::Array(a) => {
+            let size = size_of(&a.ty.element_ty()) as usize;
+            for (i, v) in a.values.iter().enumerate() {
+                write(bytes, offset + i * size, v);
+            }
+        }
+        MirageValueEnum::Struct(s) => {
+            let (_, offsets) = struct_layout(&s.ty.fields);
+            for (v, field) in s.values.iter().zip(offsets) {
+                write(bytes, offset + field as usize, v);
+            }
+        }
+        MirageValueEnum::Pointer(_) | MirageValueEnum::Register(_) => {}
+    }
+}
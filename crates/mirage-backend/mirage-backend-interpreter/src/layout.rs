@@ -0,0 +1,23 @@
+pub use mirage_backend_opti::layout::is_aggregate;
+use mirage_backend_opti::layout::Layout;
+use mirage_frontend::object::MirageTypeEnum;
+
+/// The size of an address. Memory is laid out as on a 64-bit machine.
+pub const POINTER_SIZE: u32 = 8;
+
+/// The layout of the values in memory
+const LAYOUT: Layout = Layout::new(POINTER_SIZE as u64);
+
+/// The number of bytes taken by a value of `ty` in memory
+pub fn size_of(ty: &MirageTypeEnum) -> u32 {
+    LAYOUT.size_of(ty) as u32
+}
+
+/// The size of a struct with `fields` and the offset of every field
+pub fn struct_layout(fields: &[MirageTypeEnum]) -> (u32, Vec<u32>) {
+    let (size, offsets) = LAYOUT.struct_layout(fields);
+    (
+        size as u32,
+        offsets.into_iter().map(|offset| offset as u32).collect(),
+    )
+}
@@ -0,0 +1,332 @@
+#[cfg(test)]
+mod test;
+
+mod data;
+mod layout;
+mod machine;
+mod memory;
+mod value;
+
+pub use memory::{Memory, Region, NULL_PAGE};
+pub use value::{Kind, Val};
+
+use data::global_data;
+use machine::Machine;
+use mirage_backend_opti::ir::{for_each_use, reg_key, value_register, RegKey};
+use mirage_frontend::object::function::{FunctionType, FunctionValue};
+use mirage_frontend::object::label::{Command, LabelBodyInstr};
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::{MirageTypeEnum, RegisterType};
+use std::collections::HashMap;
+
+/// The number of nested calls after which a program is taken to recurse
+/// forever
+pub const DEFAULT_MAX_DEPTH: usize = 1 << 16;
+
+/// Why a program stopped
+/// # Variants
+/// * `NullDeref` - An access to the null pointer, or to an address just past
+///   it
+/// * `OutOfBounds` - An access to bytes which aren't all in one allocation
+/// * `Dangling` - An access to memory given back, by `free` or by the return
+///   of the function it belongs to
+/// * `ReadOnly` - A write to a `#const` global or to a string constant
+/// * `InvalidFree` - A `free` of an address `new` didn't give, or of a struct
+///   already freed
+/// * `MissingReturn` - The function ended without a `ret`
+/// * `UndefinedRegister` - A register read before being assigned
+/// * `UnknownFunction` - A call to a function which isn't declared
+/// * `UnknownExtern` - A call to an extern no host function is registered
+///   for
+/// * `UnknownLabel` - A jump to a label which isn't in the function
+/// * `UnknownType` - A `new` of a type which isn't defined
+/// * `UnknownGlobal` - A global register without its global
+/// * `ArgumentCount` - A call with too few arguments
+/// * `Unsupported` - An instruction which has no meaning, such as a `ret`
+///   assigned to a register
+/// * `Extern` - The host function of an extern failed
+/// * `StackOverflow` - Too many nested calls
+/// * `OutOfFuel` - The program ran more instructions than it was allowed
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrapKind {
+    NullDeref(u64),
+    OutOfBounds { address: u64, size: u64 },
+    Dangling(u64),
+    ReadOnly(u64),
+    InvalidFree(u64),
+    MissingReturn,
+    UndefinedRegister(String),
+    UnknownFunction(String),
+    UnknownExtern(String),
+    UnknownLabel(String),
+    UnknownType(String),
+    UnknownGlobal(usize),
+    ArgumentCount(String),
+    Unsupported(String),
+    Extern { name: String, message: String },
+    StackOverflow,
+    OutOfFuel,
+}
+
+impl std::fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrapKind::NullDeref(address) => write!(f, "null pointer access at {:#x}", address),
+            TrapKind::OutOfBounds { address, size } => {
+                write!(
+                    f,
+                    "out of bounds access of {} bytes at {:#x}",
+                    size, address
+                )
+            }
+            TrapKind::Dangling(address) => write!(f, "access to freed memory at {:#x}", address),
+            TrapKind::ReadOnly(address) => write!(f, "write to read-only memory at {:#x}", address),
+            TrapKind::InvalidFree(address) => write!(f, "invalid free of {:#x}", address),
+            TrapKind::MissingReturn => write!(f, "missing return"),
+            TrapKind::UndefinedRegister(reg) => {
+                write!(f, "`{}` is read before being assigned", reg)
+            }
+            TrapKind::UnknownFunction(name) => write!(f, "call to unknown function `{}`", name),
+            TrapKind::UnknownExtern(name) => {
+                write!(f, "no host function is registered for `{}`", name)
+            }
+            TrapKind::UnknownLabel(label) => write!(f, "unknown label `{}`", label),
+            TrapKind::UnknownType(name) => write!(f, "unknown type `{}`", name),
+            TrapKind::UnknownGlobal(index) => write!(f, "unknown global g{}", index),
+            TrapKind::ArgumentCount(name) => write!(f, "too few arguments to `{}`", name),
+            TrapKind::Unsupported(instr) => write!(f, "unsupported instruction `{}`", instr),
+            TrapKind::Extern { name, message } => write!(f, "`{}` failed: {}", name, message),
+            TrapKind::StackOverflow => write!(f, "stack overflow"),
+            TrapKind::OutOfFuel => write!(f, "out of fuel"),
+        }
+    }
+}
+
+/// A trap, and where it happened: the function and the label running
+/// when the program stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trap {
+    pub kind: TrapKind,
+    pub function: String,
+    pub label: String,
+}
+
+impl std::fmt::Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, label `{}`: {}",
+            self.function, self.label, self.kind
+        )
+    }
+}
+
+impl std::error::Error for Trap {}
+
+pub type TrapResult<T> = Result<T, Trap>;
+
+/// A host function an extern resolves to. It is given the memory of the
+/// program, to read the strings and the structs it is passed, and the
+/// arguments, the variadic ones promoted as in C. An error stops the
+/// program with a `TrapKind::Extern`.
+pub type Extern = Box<dyn FnMut(&mut Memory, &[Val]) -> Result<Val, String>>;
+
+/// A function of the program, and what running it needs to know
+#[derive(Debug, Clone)]
+struct FunctionInfo {
+    func: FunctionValue,
+    /// The index of every label
+    labels: HashMap<String, usize>,
+    /// What every register holds. It is the kind of its first assignment,
+    /// an address for a `getelementptr`, a `ref` or a `new`.
+    kinds: HashMap<RegKey, Kind>,
+    /// The registers whose address is taken, which live in memory
+    referenced: Vec<RegKey>,
+}
+
+impl FunctionInfo {
+    fn new(func: FunctionValue) -> Self {
+        let labels = func
+            .get_labels()
+            .iter()
+            .enumerate()
+            .map(|(i, l)| (l.name.clone(), i))
+            .collect();
+        let mut kinds = HashMap::new();
+        for (i, ty) in func.get_type().get_args().iter().enumerate() {
+            kinds.insert((RegisterType::Argument, i), Kind::of(ty));
+        }
+        let mut referenced = Vec::new();
+        for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+            if let LabelBodyInstr::Assign(reg, value) = instr {
+                let kind = match **value {
+                    LabelBodyInstr::Command(
+                        Command::GetElementPtr(..) | Command::Ref(_) | Command::New(..),
+                    ) => Kind::Ptr,
+                    _ => Kind::of(&reg.ty),
+                };
+                kinds.entry(reg_key(reg)).or_insert(kind);
+            }
+            for_each_use(instr, &mut |reg, _| {
+                kinds.entry(reg_key(reg)).or_insert(Kind::of(&reg.ty));
+            });
+            let value = match instr {
+                LabelBodyInstr::Assign(_, value) => value,
+                _ => instr,
+            };
+            if let LabelBodyInstr::Command(Command::Ref(value)) = value {
+                if let Some(reg) = value_register(value) {
+                    let key = reg_key(reg);
+                    if reg.register_type != RegisterType::Global
+                        && !layout::is_aggregate(&reg.ty)
+                        && !referenced.contains(&key)
+                    {
+                        referenced.push(key);
+                    }
+                }
+            }
+        }
+        Self {
+            func,
+            labels,
+            kinds,
+            referenced,
+        }
+    }
+}
+
+/// The program an interpreter runs
+#[derive(Debug, Clone, Default)]
+struct Program {
+    functions: HashMap<String, FunctionInfo>,
+    externs: HashMap<String, FunctionType>,
+    structs: HashMap<String, Vec<MirageTypeEnum>>,
+    /// The address and the type of every global, by index
+    globals: Vec<(u64, MirageTypeEnum)>,
+    global_names: HashMap<String, usize>,
+}
+
+/// The reference interpreter, which runs a program without compiling it.
+///
+/// It is the semantic oracle of the backends and of the optimizations: a
+/// program computes what the interpreter computes. Registers hold values of
+/// the kind of their first assignment, and integers wrap around at their
+/// width. Memory is laid out as C lays it out on a 64-bit machine, and
+/// every access is checked, so that what a backend would leave undefined
+/// traps. The structs and arrays a function creates belong to it, and are
+/// given back when it returns, like its stack frame.
+///
+/// Calls don't recurse on the Rust stack, so a deep recursion is only
+/// limited by `set_max_depth`. The globals keep their values from one call
+/// to the next.
+pub struct Interpreter {
+    program: Program,
+    memory: Memory,
+    externs: HashMap<String, Extern>,
+    /// The address of the string constants, which are made once
+    strings: HashMap<String, u64>,
+    fuel: Option<u64>,
+    max_depth: usize,
+}
+
+impl Interpreter {
+    pub fn new(stmts: Vec<Statement>) -> Self {
+        let mut program = Program::default();
+        let mut memory = Memory::new();
+        for stmt in stmts {
+            match stmt {
+                Statement::Typedef(t) => {
+                    program.structs.insert(t.name, t.ty.into_vec());
+                }
+                Statement::Global(global) => {
+                    let region = match global.is_const() {
+                        true => Region::Constant,
+                        false => Region::Global,
+                    };
+                    let value = global.value.get_value_ref();
+                    let address = memory.allocate_with(global_data(value), region);
+                    program
+                        .global_names
+                        .insert(global.name.clone(), program.globals.len());
+                    program.globals.push((address, global.value.get_type()));
+                }
+                Statement::External(e) => {
+                    program.externs.insert(e.name, e.ty);
+                }
+                Statement::Function(f) => {
+                    program
+                        .functions
+                        .insert(f.get_name().clone(), FunctionInfo::new(f));
+                }
+                _ => {}
+            }
+        }
+        Self {
+            program,
+            memory,
+            externs: HashMap::new(),
+            strings: HashMap::new(),
+            fuel: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    /// Resolve the extern `name` to the host function `f`
+    pub fn register_extern<F>(&mut self, name: &str, f: F)
+    where
+        F: FnMut(&mut Memory, &[Val]) -> Result<Val, String> + 'static,
+    {
+        self.externs.insert(name.to_string(), Box::new(f));
+    }
+
+    /// Stop every call after `fuel` instructions, or never with `None`
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Stop a call when more than `depth` calls are nested
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Whether the program defines a function named `name`
+    pub fn contains_function(&self, name: &str) -> bool {
+        self.program.functions.contains_key(name)
+    }
+
+    /// The Mirage type of the function named `name`
+    pub fn function_type(&self, name: &str) -> Option<&FunctionType> {
+        self.program
+            .functions
+            .get(name)
+            .map(|info| info.func.get_type())
+    }
+
+    /// The address of the global named `name`
+    pub fn global_address(&self, name: &str) -> Option<u64> {
+        let index = *self.program.global_names.get(name)?;
+        Some(self.program.globals[index].0)
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Run the function `name` with `args`, which are converted to the
+    /// types of its arguments, and return its value
+    pub fn call(&mut self, name: &str, args: &[Val]) -> TrapResult<Val> {
+        Machine::new(
+            &self.program,
+            &mut self.memory,
+            &mut self.externs,
+            &mut self.strings,
+            self.fuel,
+            self.max_depth,
+        )
+        .run(name, args)
+    }
+}
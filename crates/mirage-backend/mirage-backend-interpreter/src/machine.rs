@@ -0,0 +1,686 @@
+use std::collections::HashMap;
+
+use mirage_backend_opti::ir::{is_phi, reg_key, value_register, RegKey};
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::object::{MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue};
+
+use crate::data::string_bytes;
+use crate::layout::{is_aggregate, size_of, struct_layout};
+use crate::memory::{Memory, Region};
+use crate::value::{Kind, Val};
+use crate::{Extern, FunctionInfo, Program, Trap, TrapKind, TrapResult};
+
+type StepResult<T> = Result<T, TrapKind>;
+
+/// The constant integer `value` holds, if it is one
+fn const_index(value: &Value) -> Option<i64> {
+    let Value::ConstValue(obj) = value else {
+        return None;
+    };
+    match obj.get_value_ref() {
+        MirageValueEnum::Int8(v) => Some(v.value as i64),
+        MirageValueEnum::Int16(v) => Some(v.value as i64),
+        MirageValueEnum::Int32(v) => Some(v.value as i64),
+        MirageValueEnum::Int64(v) => Some(v.value),
+        MirageValueEnum::UInt8(v) => Some(v.value as i64),
+        MirageValueEnum::UInt16(v) => Some(v.value as i64),
+        MirageValueEnum::UInt32(v) => Some(v.value as i64),
+        MirageValueEnum::UInt64(v) => Some(v.value as i64),
+        _ => None,
+    }
+}
+
+/// A function being run
+struct Frame<'a> {
+    info: &'a FunctionInfo,
+    /// The label running, and the instruction to run next in it
+    label: usize,
+    instr: usize,
+    registers: HashMap<RegKey, Val>,
+    /// The address of the registers whose address is taken
+    slots: HashMap<RegKey, u64>,
+    /// The memory every instruction making a struct or an array got, by
+    /// label, instruction and rank in the instruction. Running the
+    /// instruction again reuses it, as a stack slot of a backend is.
+    sites: HashMap<(usize, usize, usize), u64>,
+    /// The rank of the next memory the running instruction makes
+    site: usize,
+    /// The memory given back when the function returns
+    allocations: Vec<u64>,
+    /// The register of the caller the return value is assigned to
+    dest: Option<&'a RegisterValue>,
+}
+
+/// Runs one call of the host into the program, on a stack of frames
+pub struct Machine<'a> {
+    program: &'a Program,
+    memory: &'a mut Memory,
+    externs: &'a mut HashMap<String, Extern>,
+    strings: &'a mut HashMap<String, u64>,
+    fuel: Option<u64>,
+    max_depth: usize,
+    frames: Vec<Frame<'a>>,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(
+        program: &'a Program,
+        memory: &'a mut Memory,
+        externs: &'a mut HashMap<String, Extern>,
+        strings: &'a mut HashMap<String, u64>,
+        fuel: Option<u64>,
+        max_depth: usize,
+    ) -> Self {
+        Self {
+            program,
+            memory,
+            externs,
+            strings,
+            fuel,
+            max_depth,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn run(mut self, name: &str, args: &[Val]) -> TrapResult<Val> {
+        let program = self.program;
+        let entry = |kind| Trap {
+            kind,
+            function: name.to_string(),
+            label: String::new(),
+        };
+        let info = program
+            .functions
+            .get(name)
+            .ok_or_else(|| entry(TrapKind::UnknownFunction(name.to_string())))?;
+        if args.len() < info.func.get_type().get_args().len() {
+            return Err(entry(TrapKind::ArgumentCount(name.to_string())));
+        }
+        let result = self.enter(info, args.to_vec(), None).and_then(|_| loop {
+            if let Some(value) = self.step()? {
+                break Ok(value);
+            }
+        });
+        result.map_err(|kind| self.trap(kind))
+    }
+
+    /// The trap `kind` happening where the program is, which gives back the
+    /// memory of every frame
+    fn trap(&mut self, kind: TrapKind) -> Trap {
+        let trap = match self.frames.last() {
+            Some(frame) => Trap {
+                kind,
+                function: frame.info.func.get_name().clone(),
+                label: frame
+                    .info
+                    .func
+                    .get_labels()
+                    .get(frame.label)
+                    .map(|l| l.name.clone())
+                    .unwrap_or_default(),
+            },
+            None => Trap {
+                kind,
+                function: String::new(),
+                label: String::new(),
+            },
+        };
+        while let Some(frame) = self.frames.pop() {
+            for address in frame.allocations {
+                self.memory.release(address);
+            }
+        }
+        trap
+    }
+
+    fn frame(&mut self) -> &mut Frame<'a> {
+        self.frames.last_mut().expect("a function is running")
+    }
+
+    /// Start running `info` with `args`, the variadic ones included
+    fn enter(
+        &mut self,
+        info: &'a FunctionInfo,
+        args: Vec<Val>,
+        dest: Option<&'a RegisterValue>,
+    ) -> StepResult<()> {
+        if self.frames.len() >= self.max_depth {
+            return Err(TrapKind::StackOverflow);
+        }
+        let mut frame = Frame {
+            info,
+            label: 0,
+            instr: 0,
+            registers: HashMap::new(),
+            slots: HashMap::new(),
+            sites: HashMap::new(),
+            site: 0,
+            allocations: Vec::new(),
+            dest,
+        };
+        for key in &info.referenced {
+            let size = info.kinds[key].size();
+            let address = self.memory.allocate(size, Region::Stack);
+            frame.slots.insert(*key, address);
+            frame.allocations.push(address);
+        }
+        self.frames.push(frame);
+        let types = info.func.get_type().get_args();
+        for (i, (arg, ty)) in args.into_iter().zip(types).enumerate() {
+            let reg = RegisterValue::new(i, RegisterType::Argument, ty.clone());
+            self.write(&reg, arg)?;
+        }
+        Ok(())
+    }
+
+    /// Run the next instruction, and return the value of the called
+    /// function once it returns
+    fn step(&mut self) -> StepResult<Option<Val>> {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(TrapKind::OutOfFuel);
+            }
+            *fuel -= 1;
+        }
+        let frame = self.frame();
+        let info = frame.info;
+        let labels = info.func.get_labels();
+        let Some(label) = labels.get(frame.label) else {
+            return Err(TrapKind::MissingReturn);
+        };
+        frame.site = 0;
+        let Some(instr) = label.body.get(frame.instr) else {
+            // Running past the end of a label goes on with the next one
+            return match labels.get(frame.label + 1) {
+                Some(next) => self.jump(&next.name).map(|_| None),
+                None => Err(TrapKind::MissingReturn),
+            };
+        };
+        frame.instr += 1;
+        if is_phi(instr) {
+            // The phis are assigned by the jump to their label
+            return Ok(None);
+        }
+        self.instruction(instr)
+    }
+
+    fn instruction(&mut self, instr: &'a LabelBodyInstr) -> StepResult<Option<Val>> {
+        match instr {
+            LabelBodyInstr::Assign(reg, value) => match &**value {
+                LabelBodyInstr::Call(name, args) => self.call(name, args, Some(reg)),
+                LabelBodyInstr::Command(cmd) => {
+                    let value = self.command(cmd)?;
+                    self.write(reg, value)?;
+                    Ok(None)
+                }
+                LabelBodyInstr::Assign(..) => Err(TrapKind::Unsupported(instr.to_string())),
+            },
+            LabelBodyInstr::Call(name, args) => self.call(name, args, None),
+            LabelBodyInstr::Command(cmd) => match cmd {
+                Command::Store(reg, value) => {
+                    let ty = match value {
+                        Value::List(_) => return Err(TrapKind::Unsupported(value.to_string())),
+                        _ => value.get_type(),
+                    };
+                    let address = self.read(reg)?.address();
+                    let value = self.value(value)?;
+                    self.store(address, &ty, value)?;
+                    Ok(None)
+                }
+                Command::Ret(value) => {
+                    let value = self.value(value)?;
+                    self.ret(value)
+                }
+                Command::Jump(label) => self.jump(label).map(|_| None),
+                Command::Jeq(label, lhs, rhs) => {
+                    let lhs = self.value(lhs)?;
+                    let rhs = self.value(rhs)?;
+                    if lhs.compare_eq(&rhs) {
+                        self.jump(label)?;
+                    }
+                    Ok(None)
+                }
+                Command::Free(regs) => {
+                    for reg in regs {
+                        let address = self.read(reg)?.address();
+                        if address != 0 {
+                            self.memory.free(address)?;
+                        }
+                    }
+                    Ok(None)
+                }
+                Command::Phi(_) => Ok(None),
+                _ => self.command(cmd).map(|_| None),
+            },
+        }
+    }
+
+    /// Give back the frame of the running function, and assign `value` to
+    /// the register of the caller waiting for it. The value is returned
+    /// when the host called the function.
+    fn ret(&mut self, value: Val) -> StepResult<Option<Val>> {
+        let frame = self.frames.pop().expect("a function is running");
+        for address in &frame.allocations {
+            self.memory.release(*address);
+        }
+        let value = value.convert(Kind::of(frame.info.func.get_type().get_ret()));
+        if self.frames.is_empty() {
+            return Ok(Some(value));
+        }
+        if let Some(dest) = frame.dest {
+            self.write(dest, value)?;
+        }
+        Ok(None)
+    }
+
+    /// Assign the phis of `label` the values coming from the running label,
+    /// and go on with it. The phis read their operands before any of them is
+    /// assigned.
+    fn jump(&mut self, label: &str) -> StepResult<()> {
+        let frame = self.frame();
+        let info = frame.info;
+        let labels = info.func.get_labels();
+        let current = &labels[frame.label].name;
+        let target = *info
+            .labels
+            .get(label)
+            .ok_or_else(|| TrapKind::UnknownLabel(label.to_string()))?;
+        let copies: Vec<(&RegisterValue, &Value)> = labels[target]
+            .body
+            .iter()
+            .map_while(|instr| match instr {
+                LabelBodyInstr::Assign(reg, value) => match &**value {
+                    LabelBodyInstr::Command(Command::Phi(incoming)) => Some((reg, incoming)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .filter_map(|(reg, incoming)| {
+                incoming
+                    .iter()
+                    .find(|(label, _)| label == current)
+                    .map(|(_, value)| (reg, value))
+            })
+            .collect();
+        let mut values = Vec::with_capacity(copies.len());
+        for (_, value) in &copies {
+            values.push(self.value(value)?);
+        }
+        for ((reg, _), value) in copies.into_iter().zip(values) {
+            self.write(reg, value)?;
+        }
+        let frame = self.frame();
+        frame.label = target;
+        frame.instr = 0;
+        Ok(())
+    }
+
+    /// Call `name` with `args`. A function of the program starts running,
+    /// and an extern runs its host function at once.
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        dest: Option<&'a RegisterValue>,
+    ) -> StepResult<Option<Val>> {
+        let program = self.program;
+        let ty = match program.functions.get(name) {
+            Some(info) => info.func.get_type(),
+            None => program
+                .externs
+                .get(name)
+                .ok_or_else(|| TrapKind::UnknownFunction(name.to_string()))?,
+        };
+        let fixed = ty.get_args();
+        if args.len() < fixed.len() {
+            return Err(TrapKind::ArgumentCount(name.to_string()));
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for (i, arg) in args.iter().enumerate() {
+            let value = self.value(arg)?;
+            values.push(match fixed.get(i) {
+                Some(ty) => value.convert(Kind::of(ty)),
+                None => value.convert(value.kind().promoted()),
+            });
+        }
+
+        if let Some(info) = program.functions.get(name) {
+            self.enter(info, values, dest)?;
+            return Ok(None);
+        }
+        let f = self
+            .externs
+            .get_mut(name)
+            .ok_or_else(|| TrapKind::UnknownExtern(name.to_string()))?;
+        let value = f(self.memory, &values).map_err(|message| TrapKind::Extern {
+            name: name.to_string(),
+            message,
+        })?;
+        if let Some(dest) = dest {
+            self.write(dest, value.convert(Kind::of(ty.get_ret())))?;
+        }
+        Ok(None)
+    }
+
+    /// The value of a command which makes one
+    fn command(&mut self, cmd: &Command) -> StepResult<Val> {
+        match cmd {
+            Command::New(name, args) => {
+                let program = self.program;
+                let fields = program
+                    .structs
+                    .get(name)
+                    .ok_or_else(|| TrapKind::UnknownType(name.clone()))?;
+                let (size, offsets) = struct_layout(fields);
+                let address = self.memory.allocate(size as usize, Region::Heap);
+                self.frame().allocations.push(address);
+                for ((arg, field), offset) in args.iter().zip(fields).zip(offsets) {
+                    let value = self.value(arg)?;
+                    self.store(address + offset as u64, field, value)?;
+                }
+                Ok(Val::Ptr(address))
+            }
+            Command::Get(reg, index) => {
+                let fields = match &reg.ty {
+                    MirageTypeEnum::Struct(s) => &s.fields,
+                    MirageTypeEnum::Pointer(p) => match &*p.element_ty {
+                        MirageTypeEnum::Struct(s) => &s.fields,
+                        _ => return Err(TrapKind::Unsupported(cmd.to_string())),
+                    },
+                    _ => return Err(TrapKind::Unsupported(cmd.to_string())),
+                };
+                let (_, offsets) = struct_layout(fields);
+                let Some((ty, offset)) = fields.get(*index).zip(offsets.get(*index)) else {
+                    return Err(TrapKind::Unsupported(cmd.to_string()));
+                };
+                let address = self.read(reg)?.address();
+                self.load(address.wrapping_add(*offset as u64), ty)
+            }
+            Command::Const(obj) => self.object(obj.get_value_ref()),
+            Command::Ref(value) => self.address_of(value),
+            Command::Load(ty, value) => {
+                let address = self.value(value)?.address();
+                if is_aggregate(ty) {
+                    // Loading a struct or an array copies it
+                    let copy = self.site(size_of(ty) as usize);
+                    self.memory.copy(copy, address, size_of(ty) as usize)?;
+                    return Ok(Val::Ptr(copy));
+                }
+                self.load(address, ty)
+            }
+            Command::GetElementPtr(ty, base, indices) => self.element_ptr(ty, base, indices),
+            Command::IncrInt8(reg) => self.incr(reg, Kind::I8),
+            Command::IncrInt16(reg) => self.incr(reg, Kind::I16),
+            Command::IncrInt32(reg) => self.incr(reg, Kind::I32),
+            Command::IncrInt64(reg) => self.incr(reg, Kind::I64),
+            Command::IncrFloat32(reg) => self.incr(reg, Kind::F32),
+            Command::IncrFloat64(reg) => self.incr(reg, Kind::F64),
+            Command::AddInt8(lhs, rhs) => self.arith(lhs, rhs, Kind::I8, false),
+            Command::AddInt16(lhs, rhs) => self.arith(lhs, rhs, Kind::I16, false),
+            Command::AddInt32(lhs, rhs) => self.arith(lhs, rhs, Kind::I32, false),
+            Command::AddInt64(lhs, rhs) => self.arith(lhs, rhs, Kind::I64, false),
+            Command::AddFloat32(lhs, rhs) => self.arith(lhs, rhs, Kind::F32, false),
+            Command::AddFloat64(lhs, rhs) => self.arith(lhs, rhs, Kind::F64, false),
+            Command::SubInt8(lhs, rhs) => self.arith(lhs, rhs, Kind::I8, true),
+            Command::SubInt16(lhs, rhs) => self.arith(lhs, rhs, Kind::I16, true),
+            Command::SubInt32(lhs, rhs) => self.arith(lhs, rhs, Kind::I32, true),
+            Command::SubInt64(lhs, rhs) => self.arith(lhs, rhs, Kind::I64, true),
+            Command::SubFloat32(lhs, rhs) => self.arith(lhs, rhs, Kind::F32, true),
+            Command::SubFloat64(lhs, rhs) => self.arith(lhs, rhs, Kind::F64, true),
+            Command::Store(..)
+            | Command::Free(_)
+            | Command::Ret(_)
+            | Command::Jump(_)
+            | Command::Jeq(..)
+            | Command::Phi(_) => Err(TrapKind::Unsupported(cmd.to_string())),
+        }
+    }
+
+    /// `reg + 1`, which wraps around at the width of `kind`
+    fn incr(&mut self, reg: &RegisterValue, kind: Kind) -> StepResult<Val> {
+        let value = self.read(reg)?.convert(kind);
+        Ok(value.arith(&Val::I64(1), false))
+    }
+
+    /// `lhs + rhs`, or `lhs - rhs`, which wraps around at the width of
+    /// `kind`
+    fn arith(&mut self, lhs: &Value, rhs: &Value, kind: Kind, sub: bool) -> StepResult<Val> {
+        let lhs = self.value(lhs)?.convert(kind);
+        let rhs = self.value(rhs)?;
+        Ok(lhs.arith(&rhs, sub))
+    }
+
+    /// The address `getelementptr` gives: the first index steps over whole
+    /// values of `ty`, the next ones go into it. It isn't checked, only the
+    /// accesses through it are.
+    fn element_ptr(
+        &mut self,
+        ty: &MirageTypeEnum,
+        base: &Value,
+        indices: &[Value],
+    ) -> StepResult<Val> {
+        let mut address = self.value(base)?.address();
+        let mut ty = ty.clone();
+        for (i, index) in indices.iter().enumerate() {
+            if i > 0 {
+                match ty {
+                    MirageTypeEnum::Struct(s) => {
+                        let field = const_index(index)
+                            .and_then(|f| usize::try_from(f).ok())
+                            .filter(|f| *f < s.fields.len())
+                            .ok_or_else(|| TrapKind::Unsupported(index.to_string()))?;
+                        let (_, offsets) = struct_layout(&s.fields);
+                        address = address.wrapping_add(offsets[field] as u64);
+                        ty = s.fields[field].clone();
+                        continue;
+                    }
+                    MirageTypeEnum::Array(a) => ty = a.element_ty(),
+                    _ => {
+                        return Err(TrapKind::Unsupported(format!(
+                            "getelementptr into {}",
+                            ty.print_to_string()
+                        )))
+                    }
+                }
+            }
+            let index = self.value(index)?;
+            let kind = if index.kind().is_signed() {
+                Kind::I64
+            } else {
+                Kind::U64
+            };
+            let Val::I64(index) = index.convert(kind).convert(Kind::I64) else {
+                unreachable!("the index is converted to an int64");
+            };
+            let offset = index.wrapping_mul(size_of(&ty) as i64);
+            address = address.wrapping_add(offset as u64);
+        }
+        Ok(Val::Ptr(address))
+    }
+
+    /// Memory of `size` bytes for the running instruction, the same every
+    /// time it runs
+    fn site(&mut self, size: usize) -> u64 {
+        let frame = self.frames.last_mut().expect("a function is running");
+        let key = (frame.label, frame.instr, frame.site);
+        frame.site += 1;
+        if let Some(address) = frame.sites.get(&key) {
+            return *address;
+        }
+        let address = self.memory.allocate(size, Region::Stack);
+        frame.sites.insert(key, address);
+        frame.allocations.push(address);
+        address
+    }
+
+    /// Load a value of `ty` from `address`. A struct or an array isn't
+    /// loaded: its address is the value.
+    fn load(&mut self, address: u64, ty: &MirageTypeEnum) -> StepResult<Val> {
+        if is_aggregate(ty) {
+            return Ok(Val::Ptr(address));
+        }
+        self.memory.load(address, Kind::of(ty))
+    }
+
+    /// Store `value`, of type `ty`, at `address`. A struct or an array is
+    /// copied from the address `value` holds.
+    fn store(&mut self, address: u64, ty: &MirageTypeEnum, value: Val) -> StepResult<()> {
+        if is_aggregate(ty) {
+            return self
+                .memory
+                .copy(address, value.address(), size_of(ty) as usize);
+        }
+        self.memory.store(address, value.convert(Kind::of(ty)))
+    }
+
+    fn global(&self, index: usize) -> StepResult<(u64, &'a MirageTypeEnum)> {
+        let program = self.program;
+        program
+            .globals
+            .get(index)
+            .map(|(address, ty)| (*address, ty))
+            .ok_or(TrapKind::UnknownGlobal(index))
+    }
+
+    fn reg_kind(&mut self, reg: &RegisterValue) -> Kind {
+        self.frame()
+            .info
+            .kinds
+            .get(&reg_key(reg))
+            .copied()
+            .unwrap_or_else(|| Kind::of(&reg.ty))
+    }
+
+    fn read(&mut self, reg: &RegisterValue) -> StepResult<Val> {
+        if reg.register_type == RegisterType::Global {
+            let (address, ty) = self.global(reg.index)?;
+            return self.load(address, ty);
+        }
+        let kind = self.reg_kind(reg);
+        let frame = self.frame();
+        let key = reg_key(reg);
+        if let Some(address) = frame.slots.get(&key).copied() {
+            return self.memory.load(address, kind);
+        }
+        frame
+            .registers
+            .get(&key)
+            .copied()
+            .ok_or_else(|| TrapKind::UndefinedRegister(reg.print_to_string()))
+    }
+
+    fn write(&mut self, reg: &RegisterValue, value: Val) -> StepResult<()> {
+        if reg.register_type == RegisterType::Global {
+            let (address, ty) = self.global(reg.index)?;
+            return self.store(address, ty, value);
+        }
+        let value = value.convert(self.reg_kind(reg));
+        let frame = self.frame();
+        let key = reg_key(reg);
+        if let Some(address) = frame.slots.get(&key).copied() {
+            return self.memory.store(address, value);
+        }
+        frame.registers.insert(key, value);
+        Ok(())
+    }
+
+    /// The address `ref value` gives. A constant gets memory of its own.
+    fn address_of(&mut self, value: &Value) -> StepResult<Val> {
+        if let Some(reg) = value_register(value) {
+            if reg.register_type == RegisterType::Global {
+                let (address, _) = self.global(reg.index)?;
+                return Ok(Val::Ptr(address));
+            }
+            if is_aggregate(&reg.ty) {
+                return self.read(reg);
+            }
+            return match self.frame().slots.get(&reg_key(reg)) {
+                Some(address) => Ok(Val::Ptr(*address)),
+                None => Err(TrapKind::Unsupported(value.to_string())),
+            };
+        }
+        let ty = match value {
+            Value::List(_) => return Err(TrapKind::Unsupported(value.to_string())),
+            _ => value.get_type(),
+        };
+        let value = self.value(value)?;
+        if is_aggregate(&ty) {
+            return Ok(value);
+        }
+        let address = self.site(size_of(&ty) as usize);
+        self.store(address, &ty, value)?;
+        Ok(Val::Ptr(address))
+    }
+
+    fn value(&mut self, value: &Value) -> StepResult<Val> {
+        match value {
+            Value::ConstValue(obj) => self.object(obj.get_value_ref()),
+            Value::Register(reg) => self.read(reg),
+            Value::List(_) => Err(TrapKind::Unsupported(value.to_string())),
+        }
+    }
+
+    /// The value of a constant. A string is the address of its memory,
+    /// made once, and another struct or array is written to memory of the
+    /// running instruction. A pointer is null.
+    fn object(&mut self, value: &MirageValueEnum) -> StepResult<Val> {
+        Ok(match value {
+            MirageValueEnum::Register(reg) => return self.read(reg),
+            MirageValueEnum::Int8(v) => Val::I8(v.value),
+            MirageValueEnum::Int16(v) => Val::I16(v.value),
+            MirageValueEnum::Int32(v) => Val::I32(v.value),
+            MirageValueEnum::Int64(v) => Val::I64(v.value),
+            MirageValueEnum::UInt8(v) => Val::U8(v.value),
+            MirageValueEnum::UInt16(v) => Val::U16(v.value),
+            MirageValueEnum::UInt32(v) => Val::U32(v.value),
+            MirageValueEnum::UInt64(v) => Val::U64(v.value),
+            MirageValueEnum::Float32(v) => Val::F32(v.value),
+            MirageValueEnum::Float64(v) => Val::F64(v.value),
+            MirageValueEnum::Pointer(_) => Val::Ptr(0),
+            MirageValueEnum::Array(_) | MirageValueEnum::Struct(_) => {
+                if let Some(s) = value.try_to_rust_string() {
+                    return Ok(Val::Ptr(self.string(&s)));
+                }
+                let address = self.site(size_of(&value.get_type()) as usize);
+                self.fill(address, value)?;
+                Val::Ptr(address)
+            }
+        })
+    }
+
+    /// The address of a constant string, NUL terminated and with its
+    /// escapes replaced. Equal strings share their memory.
+    fn string(&mut self, s: &str) -> u64 {
+        if let Some(address) = self.strings.get(s) {
+            return *address;
+        }
+        let address = self.memory.allocate_with(string_bytes(s), Region::Constant);
+        self.strings.insert(s.to_string(), address);
+        address
+    }
+
+    /// Write a constant struct or array at `address`
+    fn fill(&mut self, address: u64, value: &MirageValueEnum) -> StepResult<()> {
+        match value {
+            MirageValueEnum::Array(a) => {
+                let size = size_of(&a.ty.element_ty()) as u64;
+                for (i, v) in a.values.iter().enumerate() {
+                    self.fill(address + i as u64 * size, v)?;
+                }
+                Ok(())
+            }
+            MirageValueEnum::Struct(s) => {
+                let (_, offsets) = struct_layout(&s.ty.fields);
+                for (v, field) in s.values.iter().zip(offsets) {
+                    self.fill(address + field as u64, v)?;
+                }
+                Ok(())
+            }
+            _ => {
+                let v = self.object(value)?;
+                self.store(address, &value.get_type(), v)
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use crate::value::{Kind, Val};
+use crate::TrapKind;
+
+/// The addresses below this one are all taken as a null pointer, plus an
+/// offset, such as the address of a field of a null struct
+pub const NULL_PAGE: u64 = 0x1000;
+
+/// The address of the first allocation
+const FIRST_ADDRESS: u64 = 0x10000;
+
+/// The unmapped bytes between two allocations, so that going just past the
+/// end of one doesn't land in the next
+const GUARD: u64 = 16;
+
+/// Where an allocation lives, and so how long
+/// # Variants
+/// * `Global` - A global, alive as long as the interpreter
+/// * `Constant` - A `#const` global or a string constant, which can't be
+///   written
+/// * `Stack` - Memory of a function, given back when it returns
+/// * `Heap` - A struct made by `new`, given back by `free` or when the
+///   function which made it returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Global,
+    Constant,
+    Stack,
+    Heap,
+}
+
+#[derive(Debug, Clone)]
+struct Allocation {
+    size: u64,
+    region: Region,
+    /// The content, dropped when the allocation is given back
+    bytes: Vec<u8>,
+    live: bool,
+}
+
+/// The memory of a program: a flat address space where every allocation
+/// gets addresses of its own, never reused. An access is checked against
+/// the allocation it falls in, so that reading a null pointer, past the end
+/// of an allocation or memory given back traps instead of reading garbage.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    allocations: BTreeMap<u64, Allocation>,
+    next: u64,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self {
+            allocations: BTreeMap::new(),
+            next: FIRST_ADDRESS,
+        }
+    }
+
+    /// Allocate `size` zeroed bytes in `region`, and return their address.
+    /// Addresses are aligned to 16 bytes.
+    pub fn allocate(&mut self, size: usize, region: Region) -> u64 {
+        self.allocate_with(vec![0; size], region)
+    }
+
+    /// Allocate memory holding `bytes` in `region`, and return its address
+    pub fn allocate_with(&mut self, bytes: Vec<u8>, region: Region) -> u64 {
+        let address = self.next;
+        let size = bytes.len() as u64;
+        self.next = (address + size + GUARD).div_ceil(16) * 16;
+        self.allocations.insert(
+            address,
+            Allocation {
+                size,
+                region,
+                bytes,
+                live: true,
+            },
+        );
+        address
+    }
+
+    /// Give back the allocation at `address`. Its addresses stay known, so
+    /// that an access through a dangling pointer traps.
+    pub fn release(&mut self, address: u64) {
+        if let Some(allocation) = self.allocations.get_mut(&address) {
+            allocation.live = false;
+            allocation.bytes = Vec::new();
+        }
+    }
+
+    /// Give back the struct `new` made at `address`, as `free` does
+    pub fn free(&mut self, address: u64) -> Result<(), TrapKind> {
+        match self.allocations.get(&address) {
+            Some(allocation) if allocation.live && allocation.region == Region::Heap => {
+                self.release(address);
+                Ok(())
+            }
+            _ => Err(TrapKind::InvalidFree(address)),
+        }
+    }
+
+    /// The region of the live allocation `address` falls in
+    pub fn region(&self, address: u64) -> Option<Region> {
+        self.allocations
+            .range(..=address)
+            .next_back()
+            .filter(|(base, a)| a.live && address < *base + a.size.max(1))
+            .map(|(_, a)| a.region)
+    }
+
+    /// The allocation holding the `len` bytes at `address`, and the offset
+    /// of `address` in it
+    fn find(&self, address: u64, len: usize) -> Result<(u64, usize), TrapKind> {
+        if address < NULL_PAGE {
+            return Err(TrapKind::NullDeref(address));
+        }
+        let out_of_bounds = TrapKind::OutOfBounds {
+            address,
+            size: len as u64,
+        };
+        let (base, allocation) = self
+            .allocations
+            .range(..=address)
+            .next_back()
+            .ok_or(out_of_bounds.clone())?;
+        let offset = address - base;
+        if offset
+            .checked_add(len as u64)
+            .is_none_or(|end| end > allocation.size)
+        {
+            // An allocation given back is dangling even past its end
+            if !allocation.live && offset < allocation.size {
+                return Err(TrapKind::Dangling(address));
+            }
+            return Err(out_of_bounds);
+        }
+        if !allocation.live {
+            return Err(TrapKind::Dangling(address));
+        }
+        Ok((*base, offset as usize))
+    }
+
+    /// The `len` bytes at `address`
+    pub fn read(&self, address: u64, len: usize) -> Result<&[u8], TrapKind> {
+        let (base, offset) = self.find(address, len)?;
+        Ok(&self.allocations[&base].bytes[offset..offset + len])
+    }
+
+    /// Write `bytes` at `address`
+    pub fn write(&mut self, address: u64, bytes: &[u8]) -> Result<(), TrapKind> {
+        let (base, offset) = self.find(address, bytes.len())?;
+        let allocation = self.allocations.get_mut(&base).unwrap();
+        if allocation.region == Region::Constant {
+            return Err(TrapKind::ReadOnly(address));
+        }
+        allocation.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Copy `len` bytes from `from` to `to`
+    pub fn copy(&mut self, to: u64, from: u64, len: usize) -> Result<(), TrapKind> {
+        let bytes = self.read(from, len)?.to_vec();
+        self.write(to, &bytes)
+    }
+
+    /// The value of `kind` at `address`
+    pub fn load(&self, address: u64, kind: Kind) -> Result<Val, TrapKind> {
+        Ok(Val::from_bytes(kind, self.read(address, kind.size())?))
+    }
+
+    /// Write `value` at `address`
+    pub fn store(&mut self, address: u64, value: Val) -> Result<(), TrapKind> {
+        self.write(address, &value.to_bytes())
+    }
+
+    /// The bytes of the NUL terminated string at `address`, without the NUL
+    pub fn read_c_string(&self, address: u64) -> Result<Vec<u8>, TrapKind> {
+        let (base, offset) = self.find(address, 0)?;
+        let bytes = &self.allocations[&base].bytes[offset..];
+        match bytes.iter().position(|b| *b == 0) {
+            Some(end) => Ok(bytes[..end].to_vec()),
+            None => Err(TrapKind::OutOfBounds {
+                address,
+                size: bytes.len() as u64 + 1,
+            }),
+        }
+    }
+}
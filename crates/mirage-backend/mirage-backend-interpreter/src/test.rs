@@ -0,0 +1,785 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::{Flag, Flags};
+use mirage_frontend::object::statements::{External, Global, Statement, TypeDef};
+use mirage_frontend::object::util::List;
+use mirage_frontend::object::{
+    MirageObject, MirageTypeEnum, MirageValueEnum, RegisterType, RegisterValue,
+};
+
+fn i8_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int8().into()
+}
+
+fn u8_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_uint8().into()
+}
+
+fn i32_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int32().into()
+}
+
+fn i64_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int64().into()
+}
+
+fn reg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Register, ty)
+}
+
+fn arg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Argument, ty)
+}
+
+fn global(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Global, ty)
+}
+
+fn val(reg: &RegisterValue) -> Value {
+    Value::Register(reg.clone())
+}
+
+fn obj(value: MirageValueEnum) -> MirageObject {
+    MirageObject::from(value)
+}
+
+fn i32_obj(v: i32) -> MirageObject {
+    obj(MirageTypeEnum::type_int32().const_value(v).to_value_enum())
+}
+
+fn i64_obj(v: i64) -> MirageObject {
+    obj(MirageTypeEnum::type_int64().const_value(v).to_value_enum())
+}
+
+fn i32_val(v: i32) -> Value {
+    Value::ConstValue(i32_obj(v))
+}
+
+fn i8_val(v: i8) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_int8()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn u8_val(v: u8) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_uint8()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn i64_val(v: i64) -> Value {
+    Value::ConstValue(obj(MirageTypeEnum::type_int64()
+        .const_value(v)
+        .to_value_enum()))
+}
+
+fn assign(reg: &RegisterValue, cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(reg.clone(), Box::new(LabelBodyInstr::Command(cmd)))
+}
+
+fn assign_call(reg: &RegisterValue, name: &str, args: Vec<Value>) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(
+        reg.clone(),
+        Box::new(LabelBodyInstr::Call(name.to_string(), args)),
+    )
+}
+
+fn cmd(cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Command(cmd)
+}
+
+fn label(name: &str, body: Vec<LabelBodyInstr>) -> Label {
+    Label::new(name.to_string(), Flags::new(vec![]), body)
+}
+
+fn function_returning(
+    name: &str,
+    args: Vec<MirageTypeEnum>,
+    ret: MirageTypeEnum,
+    labels: Vec<Label>,
+) -> Statement {
+    let mut f = FunctionType::new(args, ret, false).fn_value(name.to_string());
+    for l in labels {
+        f.add_label(l);
+    }
+    Statement::Function(f)
+}
+
+fn function(name: &str, args: Vec<MirageTypeEnum>, labels: Vec<Label>) -> Statement {
+    function_returning(name, args, i32_ty(), labels)
+}
+
+fn string(s: &str) -> MirageValueEnum {
+    let ty = MirageTypeEnum::type_array(i8_ty(), s.len());
+    MirageValueEnum::Array(
+        ty.const_value(
+            s.bytes()
+                .map(|c| {
+                    MirageTypeEnum::type_int8()
+                        .const_value(c as i8)
+                        .to_value_enum()
+                })
+                .collect(),
+        ),
+    )
+}
+
+fn printf() -> Statement {
+    let ty = FunctionType::new(
+        vec![MirageTypeEnum::type_ptr(i8_ty()).into()],
+        i32_ty(),
+        true,
+    );
+    Statement::External(External::new("printf".to_string(), ty))
+}
+
+fn counter_loop() -> Statement {
+    let a = arg(0, i32_ty());
+    let r = |i| reg(i, i32_ty());
+    function(
+        "count",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    assign(&r(0), Command::Const(i32_obj(0))),
+                    assign(&r(1), Command::Ref(val(&r(0)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(&r(2), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Jeq("end".to_string(), val(&r(2)), val(&a))),
+                    assign(&r(3), Command::AddInt32(val(&r(2)), i32_val(1))),
+                    cmd(Command::Store(r(1), val(&r(3)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "end",
+                vec![
+                    assign(&r(4), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    )
+}
+
+/// `sum(n)`, adding `n` down to 1 with phis, one of them on the edge
+/// taken by a `jeq`
+fn sum_loop() -> Statement {
+    let n = arg(0, i64_ty());
+    let r = |i| reg(i, i64_ty());
+    function_returning(
+        "sum",
+        vec![i64_ty()],
+        i64_ty(),
+        vec![
+            label(
+                "entry",
+                vec![cmd(Command::Jeq("done".to_string(), val(&n), i64_val(0)))],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(
+                        &r(0),
+                        Command::Phi(vec![
+                            ("entry".to_string(), val(&n)),
+                            ("loop".to_string(), val(&r(2))),
+                        ]),
+                    ),
+                    assign(
+                        &r(1),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(0)),
+                            ("loop".to_string(), val(&r(3))),
+                        ]),
+                    ),
+                    assign(&r(2), Command::SubInt64(val(&r(0)), i64_val(1))),
+                    assign(&r(3), Command::AddInt64(val(&r(1)), val(&r(0)))),
+                    cmd(Command::Jeq("done".to_string(), val(&r(2)), i64_val(0))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "done",
+                vec![
+                    assign(
+                        &r(4),
+                        Command::Phi(vec![
+                            ("entry".to_string(), i64_val(0)),
+                            ("loop".to_string(), val(&r(3))),
+                        ]),
+                    ),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    )
+}
+
+use crate::{Interpreter, Kind, Trap, TrapKind, Val};
+
+fn ptr_ty(ty: MirageTypeEnum) -> MirageTypeEnum {
+    MirageTypeEnum::type_ptr(ty).into()
+}
+
+fn trap(kind: TrapKind, function: &str, label: &str) -> Trap {
+    Trap {
+        kind,
+        function: function.to_string(),
+        label: label.to_string(),
+    }
+}
+
+/// `fib(n)`, recursively
+fn fib() -> Statement {
+    let n = arg(0, i64_ty());
+    let r = |i| reg(i, i64_ty());
+    function_returning(
+        "fib",
+        vec![i64_ty()],
+        i64_ty(),
+        vec![
+            label(
+                "entry",
+                vec![
+                    cmd(Command::Jeq("base".to_string(), val(&n), i64_val(0))),
+                    cmd(Command::Jeq("base".to_string(), val(&n), i64_val(1))),
+                    assign(&r(0), Command::SubInt64(val(&n), i64_val(1))),
+                    assign_call(&r(1), "fib", vec![val(&r(0))]),
+                    assign(&r(2), Command::SubInt64(val(&n), i64_val(2))),
+                    assign_call(&r(3), "fib", vec![val(&r(2))]),
+                    assign(&r(4), Command::AddInt64(val(&r(1)), val(&r(3)))),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+            label("base", vec![cmd(Command::Ret(val(&n)))]),
+        ],
+    )
+}
+
+/// `depth(n)`, calling itself `n` times
+fn depth() -> Statement {
+    let n = arg(0, i32_ty());
+    let r = |i| reg(i, i32_ty());
+    function(
+        "depth",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    cmd(Command::Jeq("base".to_string(), val(&n), i32_val(0))),
+                    assign(&r(0), Command::SubInt32(val(&n), i32_val(1))),
+                    assign_call(&r(1), "depth", vec![val(&r(0))]),
+                    assign(&r(2), Command::AddInt32(val(&r(1)), i32_val(1))),
+                    cmd(Command::Ret(val(&r(2)))),
+                ],
+            ),
+            label("base", vec![cmd(Command::Ret(i32_val(0)))]),
+        ],
+    )
+}
+
+#[test]
+fn test_loops_and_calls() {
+    let mut interpreter = Interpreter::new(vec![counter_loop(), sum_loop(), fib(), depth()]);
+    assert_eq!(interpreter.call("count", &[Val::I32(5)]), Ok(Val::I32(5)));
+    assert_eq!(interpreter.call("count", &[Val::I32(0)]), Ok(Val::I32(0)));
+    assert_eq!(interpreter.call("sum", &[Val::I64(0)]), Ok(Val::I64(0)));
+    assert_eq!(
+        interpreter.call("sum", &[Val::I64(100)]),
+        Ok(Val::I64(5050))
+    );
+    // The arguments are converted to the types of the function's
+    assert_eq!(interpreter.call("sum", &[Val::U8(10)]), Ok(Val::I64(55)));
+    assert_eq!(interpreter.call("fib", &[Val::I64(15)]), Ok(Val::I64(610)));
+    // Calls don't recurse on the Rust stack
+    assert_eq!(
+        interpreter.call("depth", &[Val::I32(50_000)]),
+        Ok(Val::I32(50_000))
+    );
+
+    interpreter.set_max_depth(100);
+    assert_eq!(
+        interpreter.call("depth", &[Val::I32(1000)]),
+        Err(trap(TrapKind::StackOverflow, "depth", "entry"))
+    );
+    assert_eq!(
+        interpreter.call("count", &[]),
+        Err(trap(
+            TrapKind::ArgumentCount("count".to_string()),
+            "count",
+            ""
+        ))
+    );
+    assert_eq!(
+        interpreter.call("main", &[]),
+        Err(trap(
+            TrapKind::UnknownFunction("main".to_string()),
+            "main",
+            ""
+        ))
+    );
+    assert!(interpreter.contains_function("fib"));
+    assert_eq!(
+        interpreter
+            .function_type("sum")
+            .map(|ty| ty.get_args().len()),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_wraparound() {
+    let one = |kind| Val::I64(1).convert(kind);
+    let stmts = vec![
+        function_returning(
+            "inc8",
+            vec![i8_ty()],
+            i8_ty(),
+            vec![label(
+                "entry",
+                vec![
+                    assign(&reg(0, i8_ty()), Command::IncrInt8(arg(0, i8_ty()))),
+                    cmd(Command::Ret(val(&reg(0, i8_ty())))),
+                ],
+            )],
+        ),
+        function_returning(
+            "sub8",
+            vec![],
+            u8_ty(),
+            vec![label(
+                "entry",
+                vec![
+                    assign(&reg(0, u8_ty()), Command::SubInt8(u8_val(0), u8_val(1))),
+                    cmd(Command::Ret(val(&reg(0, u8_ty())))),
+                ],
+            )],
+        ),
+        function_returning(
+            "widen",
+            vec![i8_ty()],
+            i64_ty(),
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &reg(0, i8_ty()),
+                        Command::AddInt8(val(&arg(0, i8_ty())), i8_val(1)),
+                    ),
+                    cmd(Command::Ret(val(&reg(0, i8_ty())))),
+                ],
+            )],
+        ),
+        function_returning(
+            "add64",
+            vec![i64_ty(), i64_ty()],
+            i64_ty(),
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &reg(0, i64_ty()),
+                        Command::AddInt64(val(&arg(0, i64_ty())), val(&arg(1, i64_ty()))),
+                    ),
+                    cmd(Command::Ret(val(&reg(0, i64_ty())))),
+                ],
+            )],
+        ),
+    ];
+    let mut interpreter = Interpreter::new(stmts);
+    assert_eq!(interpreter.call("inc8", &[Val::I8(127)]), Ok(Val::I8(-128)));
+    assert_eq!(interpreter.call("inc8", &[Val::I8(-1)]), Ok(Val::I8(0)));
+    assert_eq!(interpreter.call("sub8", &[]), Ok(Val::U8(255)));
+    assert_eq!(interpreter.call("widen", &[Val::I8(-3)]), Ok(Val::I64(-2)));
+    assert_eq!(
+        interpreter.call("widen", &[Val::I8(127)]),
+        Ok(Val::I64(-128))
+    );
+    assert_eq!(
+        interpreter.call("add64", &[Val::I64(i64::MAX), Val::I64(1)]),
+        Ok(Val::I64(i64::MIN))
+    );
+
+    assert_eq!(
+        Val::U16(u16::MAX).arith(&one(Kind::U16), false),
+        Val::U16(0)
+    );
+    assert_eq!(
+        Val::I16(i16::MIN).arith(&one(Kind::I16), true),
+        Val::I16(i16::MAX)
+    );
+    assert_eq!(Val::U32(0).arith(&one(Kind::U32), true), Val::U32(u32::MAX));
+    assert_eq!(
+        Val::I32(i32::MAX).arith(&one(Kind::I32), false),
+        Val::I32(i32::MIN)
+    );
+    assert_eq!(
+        Val::U64(u64::MAX).arith(&one(Kind::U64), false),
+        Val::U64(0)
+    );
+
+    // Conversions are C's, and a float converted to an integer saturates
+    assert_eq!(Val::I32(-1).convert(Kind::U8), Val::U8(255));
+    assert_eq!(Val::U8(200).convert(Kind::I64), Val::I64(200));
+    assert_eq!(Val::I8(-2).convert(Kind::U64), Val::U64(u64::MAX - 1));
+    assert_eq!(Val::F64(1e10).convert(Kind::I32), Val::I32(i32::MAX));
+    assert_eq!(Val::F64(300.0).convert(Kind::I8), Val::I8(44));
+    assert_eq!(Val::F32(f32::NAN).convert(Kind::I64), Val::I64(0));
+    assert_eq!(Val::F64(-2.5).convert(Kind::I16), Val::I16(-2));
+    assert_eq!(Val::F64(f64::NAN), Val::F64(f64::NAN));
+    assert_ne!(Val::F64(0.0), Val::F64(-0.0));
+    assert!(!Val::F64(f64::NAN).compare_eq(&Val::F64(f64::NAN)));
+}
+
+#[test]
+fn test_structs_and_arrays() {
+    let fields = vec![i32_ty(), i64_ty()];
+    let point: MirageTypeEnum = MirageTypeEnum::type_struct(fields.clone()).into();
+    let p = reg(0, point.clone());
+    let array: MirageTypeEnum = MirageTypeEnum::type_array(i32_ty(), 4).into();
+    let table = MirageValueEnum::Array(
+        MirageTypeEnum::type_array(i32_ty(), 4).const_value(
+            [10, 20, 30, 40]
+                .into_iter()
+                .map(|v| MirageTypeEnum::type_int32().const_value(v).to_value_enum())
+                .collect(),
+        ),
+    );
+    let ptr = |i| reg(i, ptr_ty(i32_ty()));
+    let mut interpreter = Interpreter::new(vec![
+        Statement::Typedef(TypeDef::new("point".to_string(), List::from_vec(fields))),
+        Statement::Global(Global::new("table".to_string(), obj(table))),
+        function(
+            "first",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &p,
+                        Command::New(
+                            "point".to_string(),
+                            List::from_vec(vec![val(&arg(0, i32_ty())), i64_val(0)]),
+                        ),
+                    ),
+                    assign(&reg(1, i32_ty()), Command::Get(p.clone(), 0)),
+                    cmd(Command::Ret(val(&reg(1, i32_ty())))),
+                ],
+            )],
+        ),
+        function(
+            "at",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &ptr(0),
+                        Command::GetElementPtr(
+                            array.clone(),
+                            val(&global(0, array.clone())),
+                            vec![i32_val(0), val(&arg(0, i32_ty()))],
+                        ),
+                    ),
+                    assign(&reg(1, i32_ty()), Command::Load(i32_ty(), val(&ptr(0)))),
+                    cmd(Command::Store(ptr(0), i32_val(0))),
+                    cmd(Command::Ret(val(&reg(1, i32_ty())))),
+                ],
+            )],
+        ),
+        function_returning(
+            "second",
+            vec![],
+            i64_ty(),
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &p,
+                        Command::New(
+                            "point".to_string(),
+                            List::from_vec(vec![i32_val(1), i64_val(-7)]),
+                        ),
+                    ),
+                    // A load of a struct copies it
+                    assign(
+                        &reg(1, point.clone()),
+                        Command::Load(point.clone(), val(&p)),
+                    ),
+                    assign(
+                        &reg(2, ptr_ty(i64_ty())),
+                        Command::GetElementPtr(
+                            point.clone(),
+                            val(&p),
+                            vec![i32_val(0), i32_val(1)],
+                        ),
+                    ),
+                    cmd(Command::Store(reg(2, ptr_ty(i64_ty())), i64_val(5))),
+                    assign(&reg(3, i64_ty()), Command::Get(reg(1, point.clone()), 1)),
+                    cmd(Command::Free(vec![p.clone()])),
+                    cmd(Command::Ret(val(&reg(3, i64_ty())))),
+                ],
+            )],
+        ),
+    ]);
+    assert_eq!(interpreter.call("first", &[Val::I32(9)]), Ok(Val::I32(9)));
+    assert_eq!(interpreter.call("at", &[Val::I32(2)]), Ok(Val::I32(30)));
+    // The globals keep their values from one call to the next
+    assert_eq!(interpreter.call("at", &[Val::I32(2)]), Ok(Val::I32(0)));
+    assert_eq!(interpreter.call("at", &[Val::I32(3)]), Ok(Val::I32(40)));
+    assert_eq!(interpreter.call("second", &[]), Ok(Val::I64(-7)));
+
+    let table = interpreter.global_address("table").unwrap();
+    assert_eq!(
+        interpreter.memory().load(table + 4, Kind::I32),
+        Ok(Val::I32(20))
+    );
+    assert_eq!(
+        interpreter.call("at", &[Val::I32(4)]),
+        Err(trap(
+            TrapKind::OutOfBounds {
+                address: table + 16,
+                size: 4
+            },
+            "at",
+            "entry"
+        ))
+    );
+}
+
+#[test]
+fn test_externs() {
+    let calls: Rc<RefCell<Vec<Vec<Val>>>> = Rc::default();
+    let output = Rc::new(RefCell::new(String::new()));
+    let g = global(0, string("%d\n").get_type());
+    let ty = FunctionType::new(vec![i32_ty(), i32_ty()], i32_ty(), false);
+    let mut interpreter = Interpreter::new(vec![
+        printf(),
+        Statement::External(External::new("host_mul".to_string(), ty)),
+        Statement::Global(Global::new("fmt".to_string(), obj(string("%d\\n")))),
+        function(
+            "main",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign_call(&reg(0, i32_ty()), "host_mul", vec![i8_val(6), i32_val(7)]),
+                    LabelBodyInstr::Call(
+                        "printf".to_string(),
+                        vec![val(&g), val(&reg(0, i32_ty())), i8_val(-1)],
+                    ),
+                    cmd(Command::Ret(i32_val(3))),
+                ],
+            )],
+        ),
+    ]);
+    assert_eq!(
+        interpreter.call("main", &[]),
+        Err(trap(
+            TrapKind::UnknownExtern("host_mul".to_string()),
+            "main",
+            "entry"
+        ))
+    );
+
+    interpreter.register_extern("host_mul", |_, args| match args {
+        [Val::I32(a), Val::I32(b)] => Ok(Val::I32(a * b)),
+        _ => Err(format!("bad arguments {:?}", args)),
+    });
+    let log = calls.clone();
+    let out = output.clone();
+    interpreter.register_extern("printf", move |memory, args| {
+        log.borrow_mut().push(args.to_vec());
+        let fmt = memory
+            .read_c_string(args[0].address())
+            .map_err(|err| err.to_string())?;
+        let fmt = String::from_utf8(fmt).unwrap();
+        out.borrow_mut()
+            .push_str(&fmt.replace("%d", &args[1].to_string()));
+        Ok(Val::I32(fmt.len() as i32))
+    });
+    assert_eq!(interpreter.call("main", &[]), Ok(Val::I32(3)));
+    let address = interpreter.global_address("fmt").unwrap();
+    // The variadic arguments are promoted
+    assert_eq!(
+        *calls.borrow(),
+        vec![vec![Val::Ptr(address), Val::I32(42), Val::I32(-1)]]
+    );
+    assert_eq!(*output.borrow(), "int32 42\n");
+
+    interpreter.register_extern("host_mul", |_, _| Err("overflow".to_string()));
+    assert_eq!(
+        interpreter.call("main", &[]).unwrap_err().kind,
+        TrapKind::Extern {
+            name: "host_mul".to_string(),
+            message: "overflow".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_traps() {
+    let p = reg(0, ptr_ty(i32_ty()));
+    let x = reg(1, i32_ty());
+    let r = reg(2, i32_ty());
+    let constant = Global::with_flags(
+        "answer".to_string(),
+        Flags::new(vec![Flag::constant()]),
+        i32_obj(42),
+    );
+    let mut interpreter = Interpreter::new(vec![
+        Statement::Global(constant),
+        function(
+            "null",
+            vec![],
+            vec![
+                label("entry", vec![assign(&p, Command::Const(i64_obj(8)))]),
+                label(
+                    "read",
+                    vec![
+                        assign(&x, Command::Load(i32_ty(), val(&p))),
+                        cmd(Command::Ret(val(&x))),
+                    ],
+                ),
+            ],
+        ),
+        function(
+            "missing",
+            vec![i32_ty()],
+            vec![
+                label(
+                    "entry",
+                    vec![cmd(Command::Jeq(
+                        "end".to_string(),
+                        val(&arg(0, i32_ty())),
+                        i32_val(0),
+                    ))],
+                ),
+                label("middle", vec![cmd(Command::Ret(i32_val(1)))]),
+                label("end", vec![]),
+            ],
+        ),
+        function_returning(
+            "local",
+            vec![],
+            ptr_ty(i32_ty()),
+            vec![label(
+                "entry",
+                vec![
+                    assign(&x, Command::Const(i32_obj(1))),
+                    assign(&p, Command::Ref(val(&x))),
+                    cmd(Command::Ret(val(&p))),
+                ],
+            )],
+        ),
+        function(
+            "dangling",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign_call(&p, "local", vec![]),
+                    assign(&x, Command::Load(i32_ty(), val(&p))),
+                    cmd(Command::Ret(val(&x))),
+                ],
+            )],
+        ),
+        function(
+            "write_const",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign(&p, Command::Ref(val(&global(0, i32_ty())))),
+                    cmd(Command::Store(p.clone(), i32_val(0))),
+                    cmd(Command::Ret(i32_val(0))),
+                ],
+            )],
+        ),
+        function(
+            "double_free",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign(&p, Command::Ref(val(&global(0, i32_ty())))),
+                    cmd(Command::Free(vec![p.clone()])),
+                    cmd(Command::Ret(i32_val(0))),
+                ],
+            )],
+        ),
+        function(
+            "undefined",
+            vec![],
+            vec![label("entry", vec![cmd(Command::Ret(val(&r)))])],
+        ),
+        function(
+            "spin",
+            vec![],
+            vec![label(
+                "entry",
+                vec![cmd(Command::Jump("entry".to_string()))],
+            )],
+        ),
+        function("empty", vec![], vec![]),
+    ]);
+    let answer = interpreter.global_address("answer").unwrap();
+    assert_eq!(
+        interpreter.call("null", &[]),
+        Err(trap(TrapKind::NullDeref(8), "null", "read"))
+    );
+    assert_eq!(interpreter.call("missing", &[Val::I32(1)]), Ok(Val::I32(1)));
+    assert_eq!(
+        interpreter.call("missing", &[Val::I32(0)]),
+        Err(trap(TrapKind::MissingReturn, "missing", "end"))
+    );
+    assert_eq!(
+        interpreter.call("empty", &[]),
+        Err(trap(TrapKind::MissingReturn, "empty", ""))
+    );
+    let Ok(Val::Ptr(local)) = interpreter.call("local", &[]) else {
+        panic!("`local` returns an address");
+    };
+    assert!(interpreter.memory().load(local, Kind::I32).is_err());
+    assert!(matches!(
+        interpreter.call("dangling", &[]),
+        Err(Trap {
+            kind: TrapKind::Dangling(_),
+            ..
+        })
+    ));
+    assert_eq!(
+        interpreter.call("write_const", &[]),
+        Err(trap(TrapKind::ReadOnly(answer), "write_const", "entry"))
+    );
+    assert_eq!(
+        interpreter.call("double_free", &[]).unwrap_err().kind,
+        TrapKind::InvalidFree(answer)
+    );
+    assert_eq!(
+        interpreter.call("undefined", &[]).unwrap_err().kind,
+        TrapKind::UndefinedRegister(r.print_to_string())
+    );
+
+    interpreter.set_fuel(Some(1000));
+    assert_eq!(
+        interpreter.call("spin", &[]),
+        Err(trap(TrapKind::OutOfFuel, "spin", "entry"))
+    );
+    assert_eq!(
+        trap(TrapKind::MissingReturn, "missing", "end").to_string(),
+        "missing, label `end`: missing return"
+    );
+}
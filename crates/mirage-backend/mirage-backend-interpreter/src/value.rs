@@ -0,0 +1,311 @@
+use mirage_frontend::object::MirageTypeEnum;
+
+/// What a value is held as: an integer of its width and signedness, a
+/// float, or an address. Structs and arrays are handled through their
+/// address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    Ptr,
+}
+
+impl Kind {
+    pub fn of(ty: &MirageTypeEnum) -> Self {
+        match ty {
+            MirageTypeEnum::Int8(_) => Kind::I8,
+            MirageTypeEnum::Int16(_) => Kind::I16,
+            MirageTypeEnum::Int32(_) => Kind::I32,
+            MirageTypeEnum::Int64(_) => Kind::I64,
+            MirageTypeEnum::UInt8(_) => Kind::U8,
+            MirageTypeEnum::UInt16(_) => Kind::U16,
+            MirageTypeEnum::UInt32(_) => Kind::U32,
+            MirageTypeEnum::UInt64(_) => Kind::U64,
+            MirageTypeEnum::Float32(_) => Kind::F32,
+            MirageTypeEnum::Float64(_) => Kind::F64,
+            _ => Kind::Ptr,
+        }
+    }
+
+    /// The number of bytes a value of this kind takes in memory
+    pub fn size(self) -> usize {
+        match self {
+            Kind::I8 | Kind::U8 => 1,
+            Kind::I16 | Kind::U16 => 2,
+            Kind::I32 | Kind::U32 | Kind::F32 => 4,
+            Kind::I64 | Kind::U64 | Kind::F64 | Kind::Ptr => 8,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, Kind::I8 | Kind::I16 | Kind::I32 | Kind::I64)
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Kind::F32 | Kind::F64)
+    }
+
+    /// The kind a variadic argument of this kind is passed as: floats are
+    /// promoted to `f64` and narrower integers to 32 bits, as in C
+    pub fn promoted(self) -> Self {
+        match self {
+            Kind::F32 => Kind::F64,
+            Kind::I8 | Kind::I16 => Kind::I32,
+            Kind::U8 | Kind::U16 => Kind::U32,
+            kind => kind,
+        }
+    }
+}
+
+/// A value held by a register, passed to a function or returned by one.
+///
+/// Integers wrap around at their width. An address is a 64-bit integer,
+/// 0 being the null pointer. Two floats are equal when they have the same
+/// bits, so that a NaN is equal to itself and `0.0` isn't equal to `-0.0`.
+#[derive(Debug, Clone, Copy)]
+pub enum Val {
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    Ptr(u64),
+}
+
+impl Val {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Val::I8(_) => Kind::I8,
+            Val::I16(_) => Kind::I16,
+            Val::I32(_) => Kind::I32,
+            Val::I64(_) => Kind::I64,
+            Val::U8(_) => Kind::U8,
+            Val::U16(_) => Kind::U16,
+            Val::U32(_) => Kind::U32,
+            Val::U64(_) => Kind::U64,
+            Val::F32(_) => Kind::F32,
+            Val::F64(_) => Kind::F64,
+            Val::Ptr(_) => Kind::Ptr,
+        }
+    }
+
+    /// The zero of `kind`
+    pub fn zero(kind: Kind) -> Self {
+        Val::I64(0).convert(kind)
+    }
+
+    /// The integer value, extended according to its signedness. A float is
+    /// truncated toward zero.
+    fn int(&self) -> i128 {
+        match *self {
+            Val::I8(v) => v as i128,
+            Val::I16(v) => v as i128,
+            Val::I32(v) => v as i128,
+            Val::I64(v) => v as i128,
+            Val::U8(v) => v as i128,
+            Val::U16(v) => v as i128,
+            Val::U32(v) => v as i128,
+            Val::U64(v) | Val::Ptr(v) => v as i128,
+            Val::F32(v) => v as i128,
+            Val::F64(v) => v as i128,
+        }
+    }
+
+    fn float(&self) -> f64 {
+        match *self {
+            Val::F32(v) => v as f64,
+            Val::F64(v) => v,
+            _ => self.int() as f64,
+        }
+    }
+
+    /// The value converted to `kind`, as C converts between arithmetic
+    /// types. An integer is truncated or extended, and an address converts
+    /// as an unsigned integer. A float converted to an integer saturates,
+    /// at 32 bits for the narrower integers, and a NaN becomes 0, which is
+    /// what the backends do.
+    pub fn convert(&self, kind: Kind) -> Val {
+        if self.kind() == kind {
+            return *self;
+        }
+        if let Val::F32(v) = *self {
+            match kind {
+                Kind::F64 => return Val::F64(v as f64),
+                Kind::I8 | Kind::I16 => return Val::I32(v as i32).convert(kind),
+                Kind::U8 | Kind::U16 => return Val::U32(v as u32).convert(kind),
+                _ => return Val::F64(v as f64).convert(kind),
+            }
+        }
+        if let Val::F64(v) = *self {
+            return match kind {
+                Kind::I8 | Kind::I16 => Val::I32(v as i32).convert(kind),
+                Kind::U8 | Kind::U16 => Val::U32(v as u32).convert(kind),
+                Kind::I32 => Val::I32(v as i32),
+                Kind::I64 => Val::I64(v as i64),
+                Kind::U32 => Val::U32(v as u32),
+                Kind::U64 => Val::U64(v as u64),
+                Kind::Ptr => Val::Ptr(v as u64),
+                Kind::F32 => Val::F32(v as f32),
+                Kind::F64 => Val::F64(v),
+            };
+        }
+        let v = self.int();
+        match kind {
+            Kind::I8 => Val::I8(v as i8),
+            Kind::I16 => Val::I16(v as i16),
+            Kind::I32 => Val::I32(v as i32),
+            Kind::I64 => Val::I64(v as i64),
+            Kind::U8 => Val::U8(v as u8),
+            Kind::U16 => Val::U16(v as u16),
+            Kind::U32 => Val::U32(v as u32),
+            Kind::U64 => Val::U64(v as u64),
+            Kind::Ptr => Val::Ptr(v as u64),
+            Kind::F32 => Val::F32(v as f32),
+            Kind::F64 => Val::F64(v as f64),
+        }
+    }
+
+    /// `self + rhs`, or `self - rhs`, both of the same kind. Integers wrap
+    /// around at their width.
+    pub fn arith(&self, rhs: &Val, sub: bool) -> Val {
+        macro_rules! op {
+            ($a:expr, $b:expr, $ctor:path) => {
+                match sub {
+                    false => $ctor($a.wrapping_add($b)),
+                    true => $ctor($a.wrapping_sub($b)),
+                }
+            };
+        }
+        match (*self, rhs.convert(self.kind())) {
+            (Val::I8(a), Val::I8(b)) => op!(a, b, Val::I8),
+            (Val::I16(a), Val::I16(b)) => op!(a, b, Val::I16),
+            (Val::I32(a), Val::I32(b)) => op!(a, b, Val::I32),
+            (Val::I64(a), Val::I64(b)) => op!(a, b, Val::I64),
+            (Val::U8(a), Val::U8(b)) => op!(a, b, Val::U8),
+            (Val::U16(a), Val::U16(b)) => op!(a, b, Val::U16),
+            (Val::U32(a), Val::U32(b)) => op!(a, b, Val::U32),
+            (Val::U64(a), Val::U64(b)) => op!(a, b, Val::U64),
+            (Val::Ptr(a), Val::Ptr(b)) => op!(a, b, Val::Ptr),
+            (Val::F32(a), Val::F32(b)) => Val::F32(if sub { a - b } else { a + b }),
+            (Val::F64(a), Val::F64(b)) => Val::F64(if sub { a - b } else { a + b }),
+            _ => unreachable!("`rhs` is converted to the kind of `self`"),
+        }
+    }
+
+    /// Whether `self == rhs` once `rhs` is converted to the kind of `self`,
+    /// with the float comparison: a NaN is equal to nothing
+    pub fn compare_eq(&self, rhs: &Val) -> bool {
+        let rhs = rhs.convert(self.kind());
+        match self.kind().is_float() {
+            true => self.float() == rhs.float(),
+            false => self.int() == rhs.int(),
+        }
+    }
+
+    /// The value as an address
+    pub fn address(&self) -> u64 {
+        match self.convert(Kind::Ptr) {
+            Val::Ptr(address) => address,
+            _ => unreachable!("the value is converted to an address"),
+        }
+    }
+
+    /// The bytes of the value in memory, little endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            Val::I8(v) => v.to_le_bytes().to_vec(),
+            Val::I16(v) => v.to_le_bytes().to_vec(),
+            Val::I32(v) => v.to_le_bytes().to_vec(),
+            Val::I64(v) => v.to_le_bytes().to_vec(),
+            Val::U8(v) => v.to_le_bytes().to_vec(),
+            Val::U16(v) => v.to_le_bytes().to_vec(),
+            Val::U32(v) => v.to_le_bytes().to_vec(),
+            Val::U64(v) | Val::Ptr(v) => v.to_le_bytes().to_vec(),
+            Val::F32(v) => v.to_le_bytes().to_vec(),
+            Val::F64(v) => v.to_le_bytes().to_vec(),
+        }
+    }
+
+    /// The value of `kind` in `bytes`, which are `kind.size()` bytes long
+    pub fn from_bytes(kind: Kind, bytes: &[u8]) -> Val {
+        let mut buf = [0; 8];
+        buf[..kind.size()].copy_from_slice(&bytes[..kind.size()]);
+        let v = u64::from_le_bytes(buf);
+        match kind {
+            Kind::I8 => Val::I8(v as i8),
+            Kind::I16 => Val::I16(v as i16),
+            Kind::I32 => Val::I32(v as i32),
+            Kind::I64 => Val::I64(v as i64),
+            Kind::U8 => Val::U8(v as u8),
+            Kind::U16 => Val::U16(v as u16),
+            Kind::U32 => Val::U32(v as u32),
+            Kind::U64 => Val::U64(v),
+            Kind::F32 => Val::F32(f32::from_bits(v as u32)),
+            Kind::F64 => Val::F64(f64::from_bits(v)),
+            Kind::Ptr => Val::Ptr(v),
+        }
+    }
+
+    fn bits(&self) -> u64 {
+        match *self {
+            Val::F32(v) => v.to_bits() as u64,
+            Val::F64(v) => v.to_bits(),
+            _ => self.int() as u64,
+        }
+    }
+}
+
+impl PartialEq for Val {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind() && self.bits() == other.bits()
+    }
+}
+
+impl std::fmt::Display for Val {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Val::I8(v) => write!(f, "int8 {}", v),
+            Val::I16(v) => write!(f, "int16 {}", v),
+            Val::I32(v) => write!(f, "int32 {}", v),
+            Val::I64(v) => write!(f, "int64 {}", v),
+            Val::U8(v) => write!(f, "uint8 {}", v),
+            Val::U16(v) => write!(f, "uint16 {}", v),
+            Val::U32(v) => write!(f, "uint32 {}", v),
+            Val::U64(v) => write!(f, "uint64 {}", v),
+            Val::F32(v) => write!(f, "float32 {}", v),
+            Val::F64(v) => write!(f, "float64 {}", v),
+            Val::Ptr(v) => write!(f, "ptr {:#x}", v),
+        }
+    }
+}
+
+macro_rules! from_rust {
+    ($($t:ty => $variant:ident),*) => {
+        $(
+            impl From<$t> for Val {
+                fn from(v: $t) -> Self {
+                    Val::$variant(v)
+                }
+            }
+        )*
+    };
+}
+
+from_rust!(
+    i8 => I8, i16 => I16, i32 => I32, i64 => I64,
+    u8 => U8, u16 => U16, u32 => U32, u64 => U64,
+    f32 => F32, f64 => F64
+);
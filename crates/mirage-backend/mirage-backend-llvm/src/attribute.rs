@@ -68,6 +68,17 @@ impl Attribute {
             LLVMIsTypeAttribute(self.attribute) == 1
         }
     }
+
+    /// The kind id of the enum attribute called `name`, 0 if there is none
+    pub fn kind_for_name(name: &str) -> u32 {
+        unsafe {
+            LLVMGetEnumAttributeKindForName(name.as_ptr() as *const _, name.len())
+        }
+    }
+
+    pub fn as_llvm_ref(&self) -> LLVMAttributeRef {
+        self.attribute
+    }
     
     
     
@@ -37,6 +37,12 @@ impl BasicBlock {
         Some(BasicBlock::new(prev))
     }
     
+    /// The branch or return ending the block, if it has one yet
+    pub fn get_terminator(&self) -> Option<RawValue> {
+        let terminator = unsafe { LLVMGetBasicBlockTerminator(self.basic_block) };
+        ptr_to_option(terminator).map(RawValue::new)
+    }
+
     pub fn as_value(&self) -> RawValue {
         let val = unsafe { LLVMBasicBlockAsValue(self.basic_block) };
         
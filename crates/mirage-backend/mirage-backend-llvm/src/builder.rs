@@ -3,14 +3,15 @@ use crate::context::Context;
 use crate::ffi::LLVMBuildGlobalStringWithModule;
 use crate::module::Module;
 use crate::types::{TypeEnum, TypeKind};
-use crate::util::to_c_str;
+use crate::util::{ptr_to_option, to_c_str};
 use crate::value::float_value::FloatValue;
 use crate::value::function_value::FunctionValue;
 use crate::value::int_value::IntValue;
 use crate::value::pointer_value::PointerValue;
 use crate::value::{RawValue, Value, ValueEnum};
 use llvm_sys::core::*;
-use llvm_sys::prelude::{LLVMBuilderRef, LLVMValueRef};
+use llvm_sys::prelude::{LLVMBasicBlockRef, LLVMBuilderRef, LLVMValueRef};
+use llvm_sys::{LLVMIntPredicate, LLVMRealPredicate};
 use std::ffi::CString;
 use crate::analysis::FailureAction;
 
@@ -369,10 +370,99 @@ impl Builder {
         }
     }
 
+    /// Build a phi node. Its incoming values are added with `add_incoming`
+    /// once the predecessors exist.
+    pub fn build_phi(&self, ty: TypeEnum, name: &str) -> ValueEnum {
+        let name = to_c_str(name);
+        let value =
+            unsafe { LLVMBuildPhi(self.builder, ty.as_raw().as_llvm_ref(), name.as_ptr()) };
+        value.into()
+    }
+
+    /// Add the values `phi` takes when control comes from each block
+    pub fn add_incoming(&self, phi: ValueEnum, incoming: &[(ValueEnum, BasicBlock)]) {
+        let mut values = incoming
+            .iter()
+            .map(|(value, _)| value.as_llvm_ref())
+            .collect::<Vec<LLVMValueRef>>();
+        let mut blocks = incoming
+            .iter()
+            .map(|(_, block)| block.basic_block)
+            .collect::<Vec<LLVMBasicBlockRef>>();
+        unsafe {
+            LLVMAddIncoming(
+                phi.as_llvm_ref(),
+                values.as_mut_ptr(),
+                blocks.as_mut_ptr(),
+                incoming.len() as u32,
+            )
+        }
+    }
+
+    pub fn build_br(&self, dest: BasicBlock) {
+        unsafe { LLVMBuildBr(self.builder, dest.basic_block) };
+    }
+
+    pub fn build_cond_br(&self, cond: ValueEnum, then_block: BasicBlock, else_block: BasicBlock) {
+        unsafe {
+            LLVMBuildCondBr(
+                self.builder,
+                cond.as_llvm_ref(),
+                then_block.basic_block,
+                else_block.basic_block,
+            )
+        };
+    }
+
+    pub fn build_unreachable(&self) {
+        unsafe { LLVMBuildUnreachable(self.builder) };
+    }
+
+    /// Build `lhs == rhs` for integers and pointers
+    pub fn build_int_eq(&self, lhs: ValueEnum, rhs: ValueEnum, name: &str) -> ValueEnum {
+        let name = to_c_str(name);
+        let value = unsafe {
+            LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntEQ,
+                lhs.as_llvm_ref(),
+                rhs.as_llvm_ref(),
+                name.as_ptr(),
+            )
+        };
+        value.into()
+    }
+
+    /// Build `lhs == rhs` for floats, false when one of them is NaN
+    pub fn build_float_eq(&self, lhs: ValueEnum, rhs: ValueEnum, name: &str) -> ValueEnum {
+        let name = to_c_str(name);
+        let value = unsafe {
+            LLVMBuildFCmp(
+                self.builder,
+                LLVMRealPredicate::LLVMRealOEQ,
+                lhs.as_llvm_ref(),
+                rhs.as_llvm_ref(),
+                name.as_ptr(),
+            )
+        };
+        value.into()
+    }
+
     pub fn position_at_end(&mut self, basic_block: BasicBlock) {
         unsafe { LLVMPositionBuilderAtEnd(self.builder, basic_block.basic_block) }
     }
 
+    /// Add the next instructions right before `instr`
+    pub fn position_before(&mut self, instr: RawValue) {
+        unsafe { LLVMPositionBuilderBefore(self.builder, instr.as_llvm_ref()) }
+    }
+
+    /// The block the next instructions are added to
+    pub fn get_insert_block(&self) -> Option<BasicBlock> {
+        let block = unsafe { LLVMGetInsertBlock(self.builder) };
+        ptr_to_option(block).map(BasicBlock::new)
+    }
+
     pub fn get_entry_block(&self) -> Option<BasicBlock> {
         self.entry_block
     }
@@ -69,6 +69,10 @@ impl Context {
         float_types::FloatType::new_with_context(*self)
     }
 
+    pub fn f32_type(&self) -> float_types::FloatType {
+        float_types::FloatType::new_f32_with_context(*self)
+    }
+
     pub fn const_string(&self, s: &str) -> ValueEnum {
         let s = std::ffi::CString::new(s).unwrap();
         unsafe {
@@ -142,6 +146,14 @@ impl Context {
         BasicBlock::new(block)
     }
     
+    /// Create a block of the function of `before`, placed right before it
+    pub fn insert_basic_block(&self, before: BasicBlock, name: &str) -> BasicBlock {
+        let name = std::ffi::CString::new(name).unwrap();
+        let block =
+            unsafe { LLVMInsertBasicBlockInContext(self.context, before.basic_block, name.as_ptr()) };
+        BasicBlock::new(block)
+    }
+
     pub fn should_discard_value_names(&self) -> bool {
         unsafe { LLVMContextShouldDiscardValueNames(self.context) == 1 }
     }
@@ -1,6 +1,6 @@
 use llvm_sys::execution_engine::{
-    LLVMCreateExecutionEngineForModule, LLVMDisposeExecutionEngine, LLVMExecutionEngineRef,
-    LLVMFindFunction, LLVMGetFunctionAddress, LLVMLinkInMCJIT,
+    LLVMAddGlobalMapping, LLVMCreateExecutionEngineForModule, LLVMDisposeExecutionEngine,
+    LLVMExecutionEngineRef, LLVMFindFunction, LLVMGetFunctionAddress, LLVMLinkInMCJIT,
 };
 
 use crate::module::Module;
@@ -60,6 +60,27 @@ impl ExecutionEngine {
         unsafe { LLVMGetFunctionAddress(self.execution_engine, name.as_ptr()) }
     }
 
+    /// Like `get_function_address`, but `None` if the symbol can't be resolved
+    pub fn try_get_function_address(&self, name: &str) -> Option<u64> {
+        match self.get_function_address(name) {
+            0 => None,
+            address => Some(address),
+        }
+    }
+
+    /// Resolve `function` to `address` instead of looking it up in the process.
+    /// Mappings must be added before the first address lookup, since MCJIT
+    /// emits the module on that lookup.
+    pub fn add_global_mapping(&self, function: &FunctionValue, address: u64) {
+        unsafe {
+            LLVMAddGlobalMapping(
+                self.execution_engine,
+                function.function_value.as_llvm_ref(),
+                address as usize as *mut std::ffi::c_void,
+            )
+        }
+    }
+
     pub fn get_function<T: Copy + Sized>(&self, name: &str) -> T {
         let _ = self
             .find_function(name)
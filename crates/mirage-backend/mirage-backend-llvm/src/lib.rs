@@ -9,6 +9,7 @@ pub mod basic_block;
 pub mod builder;
 pub mod context;
 pub mod execution_engine;
+pub mod orc;
 pub mod module;
 pub mod types;
 pub mod util;
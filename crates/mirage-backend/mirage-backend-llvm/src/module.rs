@@ -9,6 +9,7 @@ use std::ffi::CString;
 
 use crate::analysis::FailureAction;
 use crate::context::Context;
+use crate::target::TargetData;
 use crate::metadata::{Metadata, NamedMetadata};
 use crate::types::function_types::FunctionType;
 use crate::types::{Type, TypeEnum};
@@ -144,6 +145,15 @@ impl Module {
         }
     }
 
+    /// Deep copy of the module, in the same context
+    pub fn clone_module(&self) -> Module {
+        let module = unsafe { LLVMCloneModule(self.module) };
+        Self {
+            module,
+            context: self.context,
+        }
+    }
+
     pub fn get_context(&self) -> Context {
         self.context
     }
@@ -293,6 +303,10 @@ impl Module {
         }
     }
 
+    pub fn set_target_data(&self, target_data: &TargetData) {
+        unsafe { llvm_sys::target::LLVMSetModuleDataLayout(self.module, target_data.target_data) }
+    }
+
     pub fn get_target_triple(&self) -> String {
         unsafe {
             let c_str = LLVMGetTarget(self.module);
@@ -0,0 +1,415 @@
+use std::ffi::{c_void, CStr};
+use std::sync::mpsc;
+
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMErrorRef, LLVMGetErrorMessage};
+use llvm_sys::orc2::lljit::*;
+use llvm_sys::orc2::*;
+
+use crate::context::Context;
+use crate::module::Module;
+use crate::target::Target;
+use crate::util::to_c_str;
+
+/// Suffix given to function bodies of lazily added modules. The
+/// unsuffixed name is a stub which compiles the body on first call.
+const LAZY_BODY_SUFFIX: &str = "$body";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrcError(pub String);
+
+impl std::fmt::Display for OrcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for OrcError {}
+
+pub type OrcResult<T> = Result<T, OrcError>;
+
+fn check(err: LLVMErrorRef) -> OrcResult<()> {
+    if err.is_null() {
+        return Ok(());
+    }
+    unsafe {
+        let msg = LLVMGetErrorMessage(err);
+        let s = CStr::from_ptr(msg).to_string_lossy().into_owned();
+        LLVMDisposeErrorMessage(msg);
+        Err(OrcError(s))
+    }
+}
+
+fn callable_flags() -> LLVMJITSymbolFlags {
+    LLVMJITSymbolFlags {
+        GenericFlags: LLVMJITSymbolGenericFlags::LLVMJITSymbolGenericFlagsExported as u8
+            | LLVMJITSymbolGenericFlags::LLVMJITSymbolGenericFlagsCallable as u8,
+        TargetFlags: 0,
+    }
+}
+
+/// A LLVM context which can be shared with the JIT
+#[derive(Debug)]
+pub struct ThreadSafeContext {
+    pub(crate) context: LLVMOrcThreadSafeContextRef,
+}
+
+impl ThreadSafeContext {
+    pub fn new() -> Self {
+        Self {
+            context: unsafe { LLVMOrcCreateNewThreadSafeContext() },
+        }
+    }
+
+    /// The underlying context, in which modules added to the JIT must be created
+    pub fn get_context(&self) -> Context {
+        Context::new(unsafe { LLVMOrcThreadSafeContextGetContext(self.context) })
+    }
+}
+
+impl Default for ThreadSafeContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ThreadSafeContext {
+    fn drop(&mut self) {
+        unsafe { LLVMOrcDisposeThreadSafeContext(self.context) }
+    }
+}
+
+/// A symbol table of the JIT. Lookups fall back to the symbols of the host
+/// process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JitDylib {
+    pub(crate) dylib: LLVMOrcJITDylibRef,
+}
+
+/// Tracks what was added to a `JitDylib`, so it can be removed later
+#[derive(Debug)]
+pub struct ResourceTracker {
+    pub(crate) tracker: LLVMOrcResourceTrackerRef,
+}
+
+impl ResourceTracker {
+    /// Remove everything added with this tracker from the JIT.
+    /// Addresses previously looked up from it become dangling.
+    pub fn remove(self) -> OrcResult<()> {
+        check(unsafe { LLVMOrcResourceTrackerRemove(self.tracker) })
+    }
+}
+
+impl Drop for ResourceTracker {
+    fn drop(&mut self) {
+        unsafe { LLVMOrcReleaseResourceTracker(self.tracker) }
+    }
+}
+
+/// LLVM's ORC `LLJIT`
+pub struct LLJit {
+    pub(crate) jit: LLVMOrcLLJITRef,
+    lazy_call_through: Option<LLVMOrcLazyCallThroughManagerRef>,
+    stubs: Option<LLVMOrcIndirectStubsManagerRef>,
+}
+
+impl LLJit {
+    pub fn new() -> OrcResult<Self> {
+        Target::init();
+        let mut jit = std::ptr::null_mut();
+        unsafe {
+            let builder = LLVMOrcCreateLLJITBuilder();
+            check(LLVMOrcCreateLLJIT(&mut jit, builder))?;
+        }
+        let jit = Self {
+            jit,
+            lazy_call_through: None,
+            stubs: None,
+        };
+        jit.add_process_symbols(jit.main_dylib())?;
+        Ok(jit)
+    }
+
+    pub fn get_triple(&self) -> String {
+        unsafe {
+            CStr::from_ptr(LLVMOrcLLJITGetTripleString(self.jit))
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    pub fn get_data_layout_str(&self) -> String {
+        unsafe {
+            CStr::from_ptr(LLVMOrcLLJITGetDataLayoutStr(self.jit))
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    pub fn main_dylib(&self) -> JitDylib {
+        JitDylib {
+            dylib: unsafe { LLVMOrcLLJITGetMainJITDylib(self.jit) },
+        }
+    }
+
+    /// Create a new, empty `JitDylib`
+    pub fn create_dylib(&self, name: &str) -> OrcResult<JitDylib> {
+        let name = to_c_str(name);
+        let mut dylib = std::ptr::null_mut();
+        unsafe {
+            let session = LLVMOrcLLJITGetExecutionSession(self.jit);
+            check(LLVMOrcExecutionSessionCreateJITDylib(
+                session,
+                &mut dylib,
+                name.as_ptr(),
+            ))?;
+        }
+        let dylib = JitDylib { dylib };
+        self.add_process_symbols(dylib)?;
+        Ok(dylib)
+    }
+
+    pub fn get_dylib(&self, name: &str) -> Option<JitDylib> {
+        let name = to_c_str(name);
+        let dylib = unsafe {
+            let session = LLVMOrcLLJITGetExecutionSession(self.jit);
+            LLVMOrcExecutionSessionGetJITDylibByName(session, name.as_ptr())
+        };
+        if dylib.is_null() {
+            None
+        } else {
+            Some(JitDylib { dylib })
+        }
+    }
+
+    fn add_process_symbols(&self, dylib: JitDylib) -> OrcResult<()> {
+        let mut generator = std::ptr::null_mut();
+        unsafe {
+            check(LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess(
+                &mut generator,
+                LLVMOrcLLJITGetGlobalPrefix(self.jit),
+                None,
+                std::ptr::null_mut(),
+            ))?;
+            LLVMOrcJITDylibAddGenerator(dylib.dylib, generator);
+        }
+        Ok(())
+    }
+
+    pub fn create_tracker(&self, dylib: JitDylib) -> ResourceTracker {
+        ResourceTracker {
+            tracker: unsafe { LLVMOrcJITDylibCreateResourceTracker(dylib.dylib) },
+        }
+    }
+
+    /// Move what was defined through `dylib` without a tracker onto `tracker`
+    fn claim_default_resources(&self, dylib: JitDylib, tracker: &ResourceTracker) {
+        unsafe {
+            let default = LLVMOrcJITDylibGetDefaultResourceTracker(dylib.dylib);
+            LLVMOrcResourceTrackerTransferTo(default, tracker.tracker);
+        }
+    }
+
+    /// Add a module, compiled when one of its symbols is first looked up.
+    /// The module must live in `context` and is owned by the JIT afterwards.
+    pub fn add_module(
+        &self,
+        tracker: &ResourceTracker,
+        module: Module,
+        context: &ThreadSafeContext,
+    ) -> OrcResult<()> {
+        unsafe {
+            let tsm = LLVMOrcCreateNewThreadSafeModule(module.module, context.context);
+            let res = check(LLVMOrcLLJITAddLLVMIRModuleWithRT(
+                self.jit,
+                tracker.tracker,
+                tsm,
+            ));
+            if res.is_err() {
+                LLVMOrcDisposeThreadSafeModule(tsm);
+            }
+            res
+        }
+    }
+
+    fn init_lazy(&mut self) -> OrcResult<()> {
+        if self.lazy_call_through.is_some() {
+            return Ok(());
+        }
+        let triple = to_c_str(&self.get_triple()).into_owned();
+        let mut lctm = std::ptr::null_mut();
+        unsafe {
+            let session = LLVMOrcLLJITGetExecutionSession(self.jit);
+            check(LLVMOrcCreateLocalLazyCallThroughManager(
+                triple.as_ptr(),
+                session,
+                0,
+                &mut lctm,
+            ))?;
+            self.stubs = Some(LLVMOrcCreateLocalIndirectStubsManager(triple.as_ptr()));
+        }
+        self.lazy_call_through = Some(lctm);
+        Ok(())
+    }
+
+    /// Like `add_module`, but each function defined in `module` gets a stub
+    /// and is compiled on its first call rather than on first lookup.
+    pub fn add_module_lazy(
+        &mut self,
+        dylib: JitDylib,
+        tracker: &ResourceTracker,
+        module: Module,
+        context: &ThreadSafeContext,
+    ) -> OrcResult<()> {
+        self.init_lazy()?;
+
+        let mut names = Vec::new();
+        let mut function = module.get_first_function();
+        while let Some(f) = function {
+            function = module.get_next_function(f);
+            if f.is_declaration() {
+                continue;
+            }
+            let name = f.get_name().to_string();
+            f.set_name(&format!("{}{}", name, LAZY_BODY_SUFFIX));
+            names.push(name);
+        }
+
+        self.add_module(tracker, module, context)?;
+
+        let mut aliases = names
+            .iter()
+            .map(|name| {
+                let body = format!("{}{}", name, LAZY_BODY_SUFFIX);
+                LLVMOrcCSymbolAliasMapPair {
+                    Name: self.mangle_and_intern(name),
+                    Entry: LLVMOrcCSymbolAliasMapEntry {
+                        Name: self.mangle_and_intern(&body),
+                        Flags: callable_flags(),
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        unsafe {
+            let unit = LLVMOrcLazyReexports(
+                self.lazy_call_through.unwrap(),
+                self.stubs.unwrap(),
+                dylib.dylib,
+                aliases.as_mut_ptr(),
+                aliases.len(),
+            );
+            self.define(dylib, tracker, unit)
+        }
+    }
+
+    /// Define `name` in `dylib` as the host function at `address`
+    pub fn define_symbol(
+        &self,
+        dylib: JitDylib,
+        tracker: &ResourceTracker,
+        name: &str,
+        address: u64,
+    ) -> OrcResult<()> {
+        let mut symbols = [LLVMOrcCSymbolMapPair {
+            Name: self.mangle_and_intern(name),
+            Sym: LLVMJITEvaluatedSymbol {
+                Address: address,
+                Flags: callable_flags(),
+            },
+        }];
+        unsafe {
+            let unit = LLVMOrcAbsoluteSymbols(symbols.as_mut_ptr(), symbols.len());
+            self.define(dylib, tracker, unit)
+        }
+    }
+
+    unsafe fn define(
+        &self,
+        dylib: JitDylib,
+        tracker: &ResourceTracker,
+        unit: LLVMOrcMaterializationUnitRef,
+    ) -> OrcResult<()> {
+        let res = check(LLVMOrcJITDylibDefine(dylib.dylib, unit));
+        if res.is_err() {
+            LLVMOrcDisposeMaterializationUnit(unit);
+        } else {
+            self.claim_default_resources(dylib, tracker);
+        }
+        res
+    }
+
+    fn mangle_and_intern(&self, name: &str) -> LLVMOrcSymbolStringPoolEntryRef {
+        let name = to_c_str(name);
+        unsafe { LLVMOrcLLJITMangleAndIntern(self.jit, name.as_ptr()) }
+    }
+
+    /// Address of `name` in the main `JitDylib`, compiling it if needed
+    pub fn lookup(&self, name: &str) -> OrcResult<u64> {
+        let name = to_c_str(name);
+        let mut address = 0;
+        check(unsafe { LLVMOrcLLJITLookup(self.jit, &mut address, name.as_ptr()) })?;
+        Ok(address)
+    }
+
+    /// Address of `name` in `dylib`, compiling it if needed
+    pub fn lookup_in(&self, dylib: JitDylib, name: &str) -> OrcResult<u64> {
+        extern "C" fn handle_result(
+            err: LLVMErrorRef,
+            result: LLVMOrcCSymbolMapPairs,
+            len: usize,
+            ctx: *mut c_void,
+        ) {
+            let sender = unsafe { &*(ctx as *const mpsc::Sender<OrcResult<u64>>) };
+            let res = check(err).and_then(|_| {
+                if len == 0 {
+                    Err(OrcError("symbol not found".to_string()))
+                } else {
+                    Ok(unsafe { (*result).Sym.Address })
+                }
+            });
+            let _ = sender.send(res);
+        }
+
+        let symbol = self.mangle_and_intern(name);
+        let mut search_order = [LLVMOrcCJITDylibSearchOrderElement {
+            JD: dylib.dylib,
+            JDLookupFlags: LLVMOrcJITDylibLookupFlags::LLVMOrcJITDylibLookupFlagsMatchAllSymbols,
+        }];
+        let mut symbols = [LLVMOrcCLookupSetElement {
+            Name: symbol,
+            LookupFlags: LLVMOrcSymbolLookupFlags::LLVMOrcSymbolLookupFlagsRequiredSymbol,
+        }];
+        let (sender, receiver) = mpsc::channel();
+        unsafe {
+            LLVMOrcExecutionSessionLookup(
+                LLVMOrcLLJITGetExecutionSession(self.jit),
+                LLVMOrcLookupKind::LLVMOrcLookupKindStatic,
+                search_order.as_mut_ptr(),
+                search_order.len(),
+                symbols.as_mut_ptr(),
+                symbols.len(),
+                handle_result,
+                &sender as *const _ as *mut c_void,
+            );
+        }
+        let res = receiver
+            .recv()
+            .unwrap_or_else(|_| Err(OrcError("lookup was dropped".to_string())));
+        unsafe { LLVMOrcReleaseSymbolStringPoolEntry(symbol) };
+        res
+    }
+}
+
+impl Drop for LLJit {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(stubs) = self.stubs {
+                LLVMOrcDisposeIndirectStubsManager(stubs);
+            }
+            if let Some(lctm) = self.lazy_call_through {
+                LLVMOrcDisposeLazyCallThroughManager(lctm);
+            }
+            let _ = check(LLVMOrcDisposeLLJIT(self.jit));
+        }
+    }
+}
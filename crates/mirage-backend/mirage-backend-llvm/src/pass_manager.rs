@@ -1,27 +1,106 @@
-use llvm_sys::prelude::*;
-use llvm_sys::core::*;
+use crate::module::Module;
+use crate::target::TargetMachine;
+use crate::util::to_c_str;
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMGetErrorMessage};
+use llvm_sys::transforms::pass_builder::*;
+use std::ffi::CStr;
 
-#[derive(Debug, Copy, Clone)]
+/// A pipeline for LLVM's new pass manager, run with `LLVMRunPasses`.
+///
+/// Passes are given in the textual pipeline syntax of `opt -passes=...`,
+/// e.g. `default<O2>`, `instcombine` or `function(sroa,early-cse)`.
+#[derive(Debug)]
 pub struct PassManager {
-    pass_manager: LLVMPassManagerRef
+    passes: Vec<String>,
+    options: LLVMPassBuilderOptionsRef,
 }
 
 impl PassManager {
-    
+    /// An empty pipeline
     pub fn create() -> Self {
-        let pass_manager = unsafe { LLVMCreatePassManager() };
-        Self { 
-            pass_manager
+        Self {
+            passes: Vec::new(),
+            options: unsafe { LLVMCreatePassBuilderOptions() },
         }
     }
-    
-    pub fn new(pass_manager: LLVMPassManagerRef) -> Self {
-        Self {
-            pass_manager
+
+    /// The `default<level>` pipeline, where `level` is one of
+    /// `O0`, `O1`, `O2`, `O3`, `Os` or `Oz`
+    pub fn default_pipeline(level: &str) -> Self {
+        let mut pm = Self::create();
+        pm.add_pass(&format!("default<{}>", level));
+        pm
+    }
+
+    /// Append a pass, or a comma separated list of passes, to the pipeline
+    pub fn add_pass(&mut self, pass: &str) {
+        self.passes.push(pass.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// The textual pipeline given to LLVM
+    pub fn pipeline(&self) -> String {
+        self.passes.join(",")
+    }
+
+    pub fn set_verify_each(&self, verify_each: bool) {
+        unsafe { LLVMPassBuilderOptionsSetVerifyEach(self.options, verify_each as i32) }
+    }
+
+    pub fn set_debug_logging(&self, debug_logging: bool) {
+        unsafe { LLVMPassBuilderOptionsSetDebugLogging(self.options, debug_logging as i32) }
+    }
+
+    pub fn set_loop_vectorization(&self, enabled: bool) {
+        unsafe { LLVMPassBuilderOptionsSetLoopVectorization(self.options, enabled as i32) }
+    }
+
+    pub fn set_slp_vectorization(&self, enabled: bool) {
+        unsafe { LLVMPassBuilderOptionsSetSLPVectorization(self.options, enabled as i32) }
+    }
+
+    pub fn set_loop_unrolling(&self, enabled: bool) {
+        unsafe { LLVMPassBuilderOptionsSetLoopUnrolling(self.options, enabled as i32) }
+    }
+
+    pub fn set_merge_functions(&self, enabled: bool) {
+        unsafe { LLVMPassBuilderOptionsSetMergeFunctions(self.options, enabled as i32) }
+    }
+
+    /// Run the pipeline over `module`. Target specific passes are only
+    /// available when a target machine is given.
+    pub fn run(&self, module: &Module, target_machine: Option<&TargetMachine>) -> Result<(), String> {
+        if self.passes.is_empty() {
+            return Ok(());
+        }
+        let pipeline = to_c_str(&self.pipeline()).into_owned();
+        let tm = target_machine
+            .map(|tm| tm.get_target_machine_ref())
+            .unwrap_or(std::ptr::null_mut());
+        unsafe {
+            let err = LLVMRunPasses(module.module, pipeline.as_ptr(), tm, self.options);
+            if err.is_null() {
+                return Ok(());
+            }
+            let msg = LLVMGetErrorMessage(err);
+            let s = CStr::from_ptr(msg).to_string_lossy().into_owned();
+            LLVMDisposeErrorMessage(msg);
+            Err(s)
         }
     }
-    
-    pub fn as_llvm_ref(&self) -> LLVMPassManagerRef {
-        self.pass_manager
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::create()
     }
-}
\ No newline at end of file
+}
+
+impl Drop for PassManager {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposePassBuilderOptions(self.options) }
+    }
+}
@@ -1,11 +1,8 @@
 use crate::module::Module;
-use crate::pass_manager::PassManager;
 use crate::util::to_c_str;
-use llvm_sys::prelude::LLVMPassManagerRef;
 use llvm_sys::target::*;
 use llvm_sys::target_machine::*;
 use std::ffi::{CStr, CString};
-use std::io::Write;
 
 #[derive(Debug, Copy, Clone)]
 pub enum OptimizationLevel {
@@ -163,23 +160,32 @@ impl Target {
     }
 
     pub fn create_from_default_target_triple() -> Self {
+        match Self::from_triple(&Self::get_default_target_triple()) {
+            Ok(target) => target,
+            Err(err_msg) => panic!("A error happnened: {}", err_msg),
+        }
+    }
+
+    /// The target of `triple`, or LLVM's message when it has none
+    pub fn from_triple(triple: &str) -> Result<Self, String> {
         unsafe {
-            let triple = Self::get_default_target_triple();
-            let triple = to_c_str(&triple);
+            let triple = to_c_str(triple);
             let mut target = std::mem::MaybeUninit::uninit();
             let mut err_msg = std::mem::MaybeUninit::uninit();
             let res =
                 LLVMGetTargetFromTriple(triple.as_ptr(), target.as_mut_ptr(), err_msg.as_mut_ptr());
 
-            if res != 1 {
+            if res != 0 {
                 let err_msg = err_msg.assume_init();
-                let err_msg = CStr::from_ptr(err_msg as *const _).to_str().unwrap();
-
-                panic!("A error happnened: {}", err_msg);
+                let message = CStr::from_ptr(err_msg as *const _)
+                    .to_string_lossy()
+                    .into_owned();
+                llvm_sys::core::LLVMDisposeMessage(err_msg);
+                return Err(message);
             }
 
             let target = target.assume_init();
-            Target::new(target)
+            Ok(Target::new(target))
         }
     }
     pub fn new(target: LLVMTargetRef) -> Self {
@@ -283,7 +289,7 @@ impl TargetMachine {
 
 #[derive(Debug, Copy, Clone)]
 pub struct TargetData {
-    target_data: LLVMTargetDataRef,
+    pub(crate) target_data: LLVMTargetDataRef,
 }
 
 impl TargetData {
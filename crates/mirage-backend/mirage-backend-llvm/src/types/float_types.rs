@@ -2,7 +2,7 @@ use crate::context::Context;
 
 use crate::types::{Type, TypeEnum};
 use crate::value::float_value::FloatValue;
-use llvm_sys::core::LLVMDoubleTypeInContext;
+use llvm_sys::core::{LLVMDoubleTypeInContext, LLVMFloatTypeInContext};
 use llvm_sys::prelude::LLVMTypeRef;
 
 
@@ -22,6 +22,11 @@ impl FloatType {
         Self { float_type: RawType::new(float_type) }
     }
 
+    pub fn new_f32_with_context(context: Context) -> Self {
+        let float_type = unsafe { LLVMFloatTypeInContext(context.context) };
+        Self { float_type: RawType::new(float_type) }
+    }
+
     pub fn float(&self, value: f64) -> FloatValue {
         FloatValue::new_const(value, *self)
     }
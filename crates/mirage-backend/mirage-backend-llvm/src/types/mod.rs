@@ -205,6 +205,16 @@ impl TypeEnum {
         }
     }
 
+    /// An undefined value of this type
+    pub fn get_undef(&self) -> crate::value::ValueEnum {
+        unsafe { LLVMGetUndef(self.as_raw().as_llvm_ref()) }.into()
+    }
+
+    /// The size of the type in bytes, as an `i64` constant
+    pub fn size_of(&self) -> crate::value::ValueEnum {
+        unsafe { LLVMSizeOf(self.as_raw().as_llvm_ref()) }.into()
+    }
+
     pub fn into_function_type(self) -> function_types::FunctionType {
         match self {
             TypeEnum::FunctionType(t) => t,
@@ -1,4 +1,5 @@
 use std::mem::forget;
+use crate::attribute::Attribute;
 use crate::module::Module;
 use llvm_sys::core::*;
 use llvm_sys::prelude::{LLVMTypeRef, LLVMValueRef};
@@ -60,6 +61,21 @@ impl FunctionValue {
         }
     }
 
+    pub fn set_name(&self, name: &str) {
+        unsafe {
+            LLVMSetValueName2(
+                self.function_value.as_llvm_ref(),
+                name.as_ptr() as *const _,
+                name.len(),
+            )
+        }
+    }
+
+    /// Whether the function has no body in its module
+    pub fn is_declaration(&self) -> bool {
+        unsafe { LLVMIsDeclaration(self.function_value.as_llvm_ref()) != 0 }
+    }
+
     pub fn get_nth_param(&self, index: u32) -> Option<ValueEnum> {
         let param = unsafe { LLVMGetParam(self.function_value.as_llvm_ref(), index) };
         if param.is_null() {
@@ -101,6 +117,17 @@ impl FunctionValue {
         self.function_type.map(|x| x.function_type.as_llvm_ref())
     }
 
+    /// Add an attribute to the function itself
+    pub fn add_attribute(&self, attribute: Attribute) {
+        unsafe {
+            LLVMAddAttributeAtIndex(
+                self.function_value.as_llvm_ref(),
+                llvm_sys::LLVMAttributeFunctionIndex,
+                attribute.as_llvm_ref(),
+            )
+        }
+    }
+
 }
 
 impl Value for FunctionValue {
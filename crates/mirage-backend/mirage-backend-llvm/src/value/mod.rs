@@ -1,10 +1,11 @@
 use llvm_sys::core::{
     LLVMDumpValue, LLVMGetAlignment, LLVMGetValueName2 as LLVMGetValueName,
     LLVMInstructionEraseFromParent, LLVMPrintValueToString, LLVMReplaceAllUsesWith,
-    LLVMSetAlignment, LLVMSetValueName2 as LLVMSetValueName, LLVMTypeOf, LLVMValueAsBasicBlock,
+    LLVMSetAlignment, LLVMSetLinkage, LLVMSetValueName2 as LLVMSetValueName, LLVMTypeOf,
+    LLVMValueAsBasicBlock,
 };
 use llvm_sys::prelude::LLVMValueRef;
-use llvm_sys::LLVMValue;
+use llvm_sys::{LLVMLinkage, LLVMValue};
 use std::ptr::NonNull;
 
 trait UnsignedInt: Sized + Into<u32> {}
@@ -415,4 +416,27 @@ impl RawValue {
     pub fn set_alignment(&self, align: u32) {
         unsafe { LLVMSetAlignment(self.as_llvm_ref(), align) }
     }
+
+    /// Set the linkage of a global value
+    pub fn set_linkage(&self, linkage: Linkage) {
+        unsafe { LLVMSetLinkage(self.as_llvm_ref(), linkage.into()) }
+    }
+}
+
+/// The linkage of a global value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Linkage {
+    External,
+    Internal,
+    Private,
+}
+
+impl From<Linkage> for LLVMLinkage {
+    fn from(linkage: Linkage) -> Self {
+        match linkage {
+            Linkage::External => LLVMLinkage::LLVMExternalLinkage,
+            Linkage::Internal => LLVMLinkage::LLVMInternalLinkage,
+            Linkage::Private => LLVMLinkage::LLVMPrivateLinkage,
+        }
+    }
 }
@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr};
+use mirage_frontend::object::meta::Flag;
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::RegisterType;
+
+use crate::analysis::{AnalysisManager, ModuleAnalysis};
+use crate::ir::{for_each_use, has_attribute, reg_key, value_register, RegKey};
+
+/// What a function may do besides computing its result: read or write
+/// memory it doesn't own, or free memory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Effects {
+    pub reads: bool,
+    pub writes: bool,
+    pub frees: bool,
+}
+
+impl Effects {
+    /// The effects of code we know nothing about
+    pub fn unknown() -> Self {
+        Self {
+            reads: true,
+            writes: true,
+            frees: true,
+        }
+    }
+
+    /// Neither touches memory nor frees it
+    pub fn is_pure(&self) -> bool {
+        !self.reads && !self.writes && !self.frees
+    }
+
+    /// Reads memory at most
+    pub fn is_readonly(&self) -> bool {
+        !self.writes && !self.frees
+    }
+
+    pub fn may_free(&self) -> bool {
+        self.frees
+    }
+
+    fn union(self, other: Effects) -> Effects {
+        Effects {
+            reads: self.reads || other.reads,
+            writes: self.writes || other.writes,
+            frees: self.frees || other.frees,
+        }
+    }
+}
+
+/// The callee of `instr`, if it is a call
+fn callee(instr: &LabelBodyInstr) -> Option<&String> {
+    match instr {
+        LabelBodyInstr::Call(name, _) => Some(name),
+        LabelBodyInstr::Assign(_, instr) => callee(instr),
+        LabelBodyInstr::Command(_) => None,
+    }
+}
+
+/// The registers only holding addresses inside the function's own
+/// registers and structs
+fn local_pointers(func: &FunctionValue) -> HashSet<RegKey> {
+    let is_local = |key: &RegKey| matches!(key.0, RegisterType::Register | RegisterType::Variable);
+    let assigns: Vec<(RegKey, &LabelBodyInstr)> = func
+        .get_labels()
+        .iter()
+        .flat_map(|l| l.body.iter())
+        .filter_map(|instr| match instr {
+            LabelBodyInstr::Assign(reg, value) => Some((reg_key(reg), &**value)),
+            _ => None,
+        })
+        .collect();
+    let mut local: HashSet<RegKey> = assigns.iter().map(|(key, _)| *key).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (key, value) in &assigns {
+            let ok = match value {
+                LabelBodyInstr::Command(Command::New(..)) => true,
+                LabelBodyInstr::Command(Command::Ref(v)) => {
+                    value_register(v).is_some_and(|r| is_local(&reg_key(r)))
+                }
+                LabelBodyInstr::Command(Command::GetElementPtr(_, v, _)) => {
+                    value_register(v).is_some_and(|r| local.contains(&reg_key(r)))
+                }
+                _ => false,
+            };
+            if !ok && local.remove(key) {
+                changed = true;
+            }
+        }
+    }
+    local
+}
+
+/// The effects of the instructions of `func`, calls left aside
+fn local_effects(func: &FunctionValue, const_globals: &HashSet<usize>) -> Effects {
+    let local = local_pointers(func);
+    let is_local = |reg: &_| local.contains(&reg_key(reg));
+    let mut effects = Effects::default();
+    for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+        let cmd = match instr {
+            LabelBodyInstr::Assign(_, value) => match &**value {
+                LabelBodyInstr::Command(cmd) => Some(cmd),
+                _ => None,
+            },
+            LabelBodyInstr::Command(cmd) => Some(cmd),
+            LabelBodyInstr::Call(..) => None,
+        };
+        match cmd {
+            Some(Command::Store(p, _)) => effects.writes |= !is_local(p),
+            Some(Command::Load(_, p)) => {
+                effects.reads |= !value_register(p).is_some_and(is_local);
+            }
+            Some(Command::Get(reg, _)) => {
+                effects.reads |= matches!(
+                    reg.register_type,
+                    RegisterType::Argument | RegisterType::Global
+                );
+            }
+            Some(Command::Free(_)) => effects.frees = true,
+            _ => {}
+        }
+        for_each_use(instr, &mut |reg, _| {
+            if reg.register_type == RegisterType::Global && !const_globals.contains(&reg.index) {
+                effects.reads = true;
+            }
+        });
+    }
+    effects
+}
+
+/// The calls between the functions and externs of a module.
+///
+/// Calls are always direct in Mirage, so a call to a name the module
+/// doesn't declare is treated as an indirect call: it may call back any
+/// function which isn't `#internal`, and may have any effect. Externs are
+/// assumed not to call back into the module, and to have any effect
+/// unless they are `#pure`.
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    names: Vec<String>,
+    index: HashMap<String, usize>,
+    callees: Vec<Vec<usize>>,
+    callers: Vec<Vec<usize>>,
+    /// Whether each function calls a name the module doesn't declare
+    unknown_calls: Vec<bool>,
+    /// The strongly connected components, callees before their callers
+    sccs: Vec<Vec<usize>>,
+    recursive: Vec<bool>,
+    effects: Vec<Effects>,
+}
+
+impl CallGraph {
+    pub fn new(stmts: &[Statement]) -> Self {
+        let mut graph = CallGraph::default();
+        let mut functions: Vec<Option<&FunctionValue>> = Vec::new();
+        let mut declared = Vec::new();
+        let mut const_globals = HashSet::new();
+        let mut globals = 0;
+        for stmt in stmts {
+            let (name, func, effects) = match stmt {
+                Statement::Function(f) => (f.get_name(), Some(f), Effects::default()),
+                Statement::External(e) => (
+                    &e.name,
+                    None,
+                    if e.is_pure() {
+                        Effects::default()
+                    } else {
+                        Effects::unknown()
+                    },
+                ),
+                Statement::Global(g) => {
+                    if g.is_const() {
+                        const_globals.insert(globals);
+                    }
+                    globals += 1;
+                    continue;
+                }
+                _ => continue,
+            };
+            if graph.index.contains_key(name) {
+                continue;
+            }
+            graph.index.insert(name.clone(), graph.names.len());
+            graph.names.push(name.clone());
+            functions.push(func);
+            declared.push(effects);
+        }
+
+        let n = graph.names.len();
+        let visible: Vec<usize> = (0..n)
+            .filter(|i| functions[*i].is_some_and(|f| !has_attribute(f, &Flag::internal())))
+            .collect();
+        graph.callees = vec![Vec::new(); n];
+        graph.callers = vec![Vec::new(); n];
+        graph.unknown_calls = vec![false; n];
+        for (i, func) in functions.iter().enumerate() {
+            let Some(func) = func else {
+                continue;
+            };
+            let mut callees = Vec::new();
+            for name in func
+                .get_labels()
+                .iter()
+                .flat_map(|l| l.body.iter())
+                .filter_map(callee)
+            {
+                match graph.index.get(name) {
+                    Some(c) => callees.push(*c),
+                    None => {
+                        graph.unknown_calls[i] = true;
+                        callees.extend(visible.iter().copied());
+                    }
+                }
+            }
+            callees.sort_unstable();
+            callees.dedup();
+            for c in &callees {
+                graph.callers[*c].push(i);
+            }
+            graph.callees[i] = callees;
+        }
+
+        graph.sccs = graph.tarjan();
+        graph.recursive = vec![false; n];
+        for scc in &graph.sccs {
+            let recursive = scc.len() > 1 || graph.callees[scc[0]].contains(&scc[0]);
+            for f in scc {
+                graph.recursive[*f] = recursive;
+            }
+        }
+
+        // Callees come first, so only the calls inside a component need a
+        // fixpoint
+        graph.effects = declared;
+        let local: Vec<Effects> = functions
+            .iter()
+            .map(|f| f.map_or(Effects::default(), |f| local_effects(f, &const_globals)))
+            .collect();
+        for scc in graph.sccs.clone() {
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for f in &scc {
+                    // Externs and `#pure` functions keep their declared effects
+                    if functions[*f].is_none_or(|func| has_attribute(func, &Flag::pure())) {
+                        continue;
+                    }
+                    let mut effects = graph.callees[*f]
+                        .iter()
+                        .fold(local[*f], |e, c| e.union(graph.effects[*c]));
+                    if graph.unknown_calls[*f] {
+                        effects = Effects::unknown();
+                    }
+                    if effects != graph.effects[*f] {
+                        graph.effects[*f] = effects;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        graph
+    }
+
+    /// Tarjan's algorithm. The components come out callees first.
+    fn tarjan(&self) -> Vec<Vec<usize>> {
+        struct State {
+            index: Vec<Option<usize>>,
+            low: Vec<usize>,
+            on_stack: Vec<bool>,
+            stack: Vec<usize>,
+            next: usize,
+            sccs: Vec<Vec<usize>>,
+        }
+
+        fn visit(graph: &CallGraph, v: usize, s: &mut State) {
+            s.index[v] = Some(s.next);
+            s.low[v] = s.next;
+            s.next += 1;
+            s.stack.push(v);
+            s.on_stack[v] = true;
+            for w in &graph.callees[v] {
+                match s.index[*w] {
+                    None => {
+                        visit(graph, *w, s);
+                        s.low[v] = s.low[v].min(s.low[*w]);
+                    }
+                    Some(i) if s.on_stack[*w] => s.low[v] = s.low[v].min(i),
+                    Some(_) => {}
+                }
+            }
+            if Some(s.low[v]) == s.index[v] {
+                let mut scc = Vec::new();
+                loop {
+                    let w = s.stack.pop().unwrap();
+                    s.on_stack[w] = false;
+                    scc.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                scc.sort_unstable();
+                s.sccs.push(scc);
+            }
+        }
+
+        let n = self.names.len();
+        let mut state = State {
+            index: vec![None; n],
+            low: vec![0; n],
+            on_stack: vec![false; n],
+            stack: Vec::new(),
+            next: 0,
+            sccs: Vec::new(),
+        };
+        for v in 0..n {
+            if state.index[v].is_none() {
+                visit(self, v, &mut state);
+            }
+        }
+        state.sccs
+    }
+
+    fn names(&self, nodes: &[usize]) -> Vec<&str> {
+        nodes.iter().map(|n| self.names[*n].as_str()).collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    /// The functions and externs `name` may call
+    pub fn callees(&self, name: &str) -> Vec<&str> {
+        self.index
+            .get(name)
+            .map_or(Vec::new(), |i| self.names(&self.callees[*i]))
+    }
+
+    /// The functions which may call `name`
+    pub fn callers(&self, name: &str) -> Vec<&str> {
+        self.index
+            .get(name)
+            .map_or(Vec::new(), |i| self.names(&self.callers[*i]))
+    }
+
+    /// The strongly connected components of the graph, callees before
+    /// their callers
+    pub fn sccs(&self) -> Vec<Vec<&str>> {
+        self.sccs.iter().map(|scc| self.names(scc)).collect()
+    }
+
+    /// Every function and extern, callees before their callers when the
+    /// calls aren't recursive
+    pub fn post_order(&self) -> Vec<&str> {
+        self.sccs.iter().flat_map(|scc| self.names(scc)).collect()
+    }
+
+    /// Whether `name` can call itself, directly or not
+    pub fn is_recursive(&self, name: &str) -> bool {
+        self.index.get(name).is_some_and(|i| self.recursive[*i])
+    }
+
+    /// Whether `name` calls a function the module doesn't declare
+    pub fn calls_unknown(&self, name: &str) -> bool {
+        self.index.get(name).is_some_and(|i| self.unknown_calls[*i])
+    }
+
+    /// The effects of calling `name`, unknown for the names the module
+    /// doesn't declare. A function marked `#pure` is trusted.
+    pub fn effects(&self, name: &str) -> Effects {
+        self.index
+            .get(name)
+            .map_or(Effects::unknown(), |i| self.effects[*i])
+    }
+
+    /// The functions reachable from `roots` through calls
+    pub fn reachable<'a>(&'a self, roots: impl IntoIterator<Item = &'a str>) -> HashSet<&'a str> {
+        let mut seen = HashSet::new();
+        let mut worklist: Vec<usize> = roots
+            .into_iter()
+            .filter_map(|name| self.index.get(name).copied())
+            .collect();
+        while let Some(f) = worklist.pop() {
+            if seen.insert(self.names[f].as_str()) {
+                worklist.extend(self.callees[f].iter().copied());
+            }
+        }
+        seen
+    }
+}
+
+impl ModuleAnalysis for CallGraph {
+    type Result = CallGraph;
+
+    fn name() -> &'static str {
+        "call-graph"
+    }
+
+    fn run(stmts: &[Statement], _am: &mut AnalysisManager) -> Self::Result {
+        CallGraph::new(stmts)
+    }
+}
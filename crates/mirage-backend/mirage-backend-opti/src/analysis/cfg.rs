@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr};
+
+use crate::analysis::{AnalysisManager, FunctionAnalysis};
+use crate::ir::is_terminator;
+
+/// A straight run of instructions inside a label, `start..end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub label: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The control flow graph of a function.
+///
+/// A label is split after every `jump`, `jeq` and `ret`. A `jeq` branches
+/// to its target or falls through to the next instruction, and a label
+/// which doesn't end with a terminator falls through to the next label.
+/// The entry block is always block 0.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub succs: Vec<Vec<usize>>,
+    pub preds: Vec<Vec<usize>>,
+    labels: HashMap<String, usize>,
+}
+
+impl Cfg {
+    pub fn new(func: &FunctionValue) -> Self {
+        let mut cfg = Cfg::default();
+        let labels = func.get_labels();
+        for (l, label) in labels.iter().enumerate() {
+            cfg.labels
+                .entry(label.name.clone())
+                .or_insert(cfg.blocks.len());
+            let mut start = 0;
+            for (i, instr) in label.body.iter().enumerate() {
+                if is_terminator(instr) && i + 1 < label.body.len() {
+                    cfg.blocks.push(Block {
+                        label: l,
+                        start,
+                        end: i + 1,
+                    });
+                    start = i + 1;
+                }
+            }
+            cfg.blocks.push(Block {
+                label: l,
+                start,
+                end: label.body.len(),
+            });
+        }
+
+        cfg.succs = vec![Vec::new(); cfg.blocks.len()];
+        cfg.preds = vec![Vec::new(); cfg.blocks.len()];
+        for b in 0..cfg.blocks.len() {
+            let block = cfg.blocks[b];
+            let next = (b + 1 < cfg.blocks.len()).then_some(b + 1);
+            let last = block
+                .end
+                .checked_sub(1)
+                .filter(|i| *i >= block.start)
+                .map(|i| &labels[block.label].body[i]);
+            let succs = match last {
+                Some(LabelBodyInstr::Command(Command::Ret(_))) => vec![],
+                Some(LabelBodyInstr::Command(Command::Jump(target))) => {
+                    cfg.labels.get(target).copied().into_iter().collect()
+                }
+                Some(LabelBodyInstr::Command(Command::Jeq(target, _, _))) => {
+                    let mut succs: Vec<_> = cfg.labels.get(target).copied().into_iter().collect();
+                    succs.extend(next.filter(|n| !succs.contains(n)));
+                    succs
+                }
+                _ => next.into_iter().collect(),
+            };
+            for s in &succs {
+                cfg.preds[*s].push(b);
+            }
+            cfg.succs[b] = succs;
+        }
+        cfg
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// The first block of the label `name`
+    pub fn label_block(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).copied()
+    }
+
+    /// The instructions of block `b`
+    pub fn instrs<'a>(&self, func: &'a FunctionValue, b: usize) -> &'a [LabelBodyInstr] {
+        let block = self.blocks[b];
+        &func.get_labels()[block.label].body[block.start..block.end]
+    }
+
+    /// The blocks reachable from the entry, in reverse post-order
+    pub fn reverse_post_order(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        if self.blocks.is_empty() {
+            return order;
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((b, i)) = stack.pop() {
+            if let Some(&s) = self.succs[b].get(i) {
+                stack.push((b, i + 1));
+                if !visited[s] {
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            } else {
+                order.push(b);
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Whether each block is reachable from the entry
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        for b in self.reverse_post_order() {
+            reachable[b] = true;
+        }
+        reachable
+    }
+}
+
+impl FunctionAnalysis for Cfg {
+    type Result = Cfg;
+
+    fn name() -> &'static str {
+        "cfg"
+    }
+
+    fn run(func: &FunctionValue, _am: &mut AnalysisManager) -> Self::Result {
+        Cfg::new(func)
+    }
+}
@@ -0,0 +1,294 @@
+use mirage_frontend::object::function::FunctionValue;
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::{AnalysisManager, FunctionAnalysis};
+
+/// The dominator tree of a control flow graph.
+///
+/// Block `a` dominates block `b` when every path from the entry to `b`
+/// goes through `a`. Blocks which can't be reached from the entry have no
+/// dominator and dominate nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dominators {
+    idom: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    /// The position of each block in reverse post-order
+    order: Vec<usize>,
+}
+
+impl Dominators {
+    /// Compute the dominators with the algorithm of Cooper, Harvey and
+    /// Kennedy
+    pub fn new(cfg: &Cfg) -> Self {
+        if cfg.is_empty() {
+            return Self::default();
+        }
+        Self::from_graph(&cfg.succs, &cfg.preds, 0)
+    }
+
+    /// The dominators of the graph given by `succs` and `preds`, rooted
+    /// at `root`
+    fn from_graph(succs: &[Vec<usize>], preds: &[Vec<usize>], root: usize) -> Self {
+        let rpo = reverse_post_order(succs, root);
+        let mut order = vec![usize::MAX; succs.len()];
+        for (i, b) in rpo.iter().enumerate() {
+            order[*b] = i;
+        }
+        let mut idom: Vec<Option<usize>> = vec![None; succs.len()];
+        idom[root] = Some(root);
+
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while order[a] > order[b] {
+                    a = idom[a].unwrap();
+                }
+                while order[b] > order[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in rpo.iter().skip(1) {
+                let new = preds[*b]
+                    .iter()
+                    .filter(|p| idom[**p].is_some())
+                    .fold(None, |acc, p| match acc {
+                        None => Some(*p),
+                        Some(acc) => Some(intersect(&idom, acc, *p)),
+                    });
+                if new != idom[*b] {
+                    idom[*b] = new;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); succs.len()];
+        for b in rpo.iter().skip(1) {
+            children[idom[*b].unwrap()].push(*b);
+        }
+        idom[root] = None;
+        Self {
+            idom,
+            children,
+            order,
+        }
+    }
+
+    /// The immediate dominator of `b`, `None` for the entry and the
+    /// unreachable blocks
+    pub fn idom(&self, b: usize) -> Option<usize> {
+        self.idom[b]
+    }
+
+    /// The blocks `b` immediately dominates
+    pub fn children(&self, b: usize) -> &[usize] {
+        &self.children[b]
+    }
+
+    pub fn is_reachable(&self, b: usize) -> bool {
+        self.order[b] != usize::MAX
+    }
+
+    /// Whether `a` dominates `b`. A block dominates itself.
+    pub fn dominates(&self, a: usize, mut b: usize) -> bool {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom[b] {
+                Some(d) => b = d,
+                None => return false,
+            }
+        }
+    }
+
+    /// The reachable blocks, each one before the blocks it dominates
+    pub fn pre_order(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut stack: Vec<usize> = (0..self.idom.len())
+            .filter(|b| self.is_reachable(*b) && self.idom[*b].is_none())
+            .collect();
+        while let Some(b) = stack.pop() {
+            order.push(b);
+            stack.extend(self.children[b].iter().rev());
+        }
+        order
+    }
+}
+
+/// The nodes reachable from `root`, in reverse post-order
+fn reverse_post_order(succs: &[Vec<usize>], root: usize) -> Vec<usize> {
+    let mut order = Vec::new();
+    let mut visited = vec![false; succs.len()];
+    let mut stack = vec![(root, 0)];
+    visited[root] = true;
+    while let Some((b, i)) = stack.pop() {
+        if let Some(&s) = succs[b].get(i) {
+            stack.push((b, i + 1));
+            if !visited[s] {
+                visited[s] = true;
+                stack.push((s, 0));
+            }
+        } else {
+            order.push(b);
+        }
+    }
+    order.reverse();
+    order
+}
+
+impl FunctionAnalysis for Dominators {
+    type Result = Dominators;
+
+    fn name() -> &'static str {
+        "dominators"
+    }
+
+    fn run(func: &FunctionValue, am: &mut AnalysisManager) -> Self::Result {
+        Dominators::new(&am.get::<Cfg>(func))
+    }
+}
+
+/// The post-dominator tree of a control flow graph.
+///
+/// Block `a` post-dominates block `b` when every path from `b` to the
+/// exit of the function goes through `a`. The blocks which return, or
+/// fall off the end of the function, are the predecessors of a virtual
+/// exit. Blocks from which the exit can't be reached, such as the blocks
+/// of an infinite loop, have no post-dominator.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PostDominators {
+    /// The dominators of the reversed graph, whose root is the virtual
+    /// exit at index `exit`
+    tree: Dominators,
+    exit: usize,
+}
+
+impl PostDominators {
+    pub fn new(cfg: &Cfg) -> Self {
+        let exit = cfg.len();
+        let mut succs = cfg.preds.clone();
+        let mut preds = cfg.succs.clone();
+        succs.push(Vec::new());
+        preds.push(Vec::new());
+        for (b, s) in cfg.succs.iter().enumerate() {
+            if s.is_empty() {
+                succs[exit].push(b);
+                preds[b].push(exit);
+            }
+        }
+        Self {
+            tree: Dominators::from_graph(&succs, &preds, exit),
+            exit,
+        }
+    }
+
+    /// The immediate post-dominator of `b`, `None` for the blocks which
+    /// leave the function and the ones which can't reach its exit
+    pub fn ipdom(&self, b: usize) -> Option<usize> {
+        self.tree.idom(b).filter(|d| *d != self.exit)
+    }
+
+    /// The blocks `b` immediately post-dominates
+    pub fn children(&self, b: usize) -> &[usize] {
+        self.tree.children(b)
+    }
+
+    /// Whether the exit of the function can be reached from `b`
+    pub fn reaches_exit(&self, b: usize) -> bool {
+        self.tree.is_reachable(b)
+    }
+
+    /// Whether `a` post-dominates `b`. A block post-dominates itself.
+    pub fn post_dominates(&self, a: usize, b: usize) -> bool {
+        self.tree.dominates(a, b)
+    }
+}
+
+impl FunctionAnalysis for PostDominators {
+    type Result = PostDominators;
+
+    fn name() -> &'static str {
+        "post-dominators"
+    }
+
+    fn run(func: &FunctionValue, am: &mut AnalysisManager) -> Self::Result {
+        PostDominators::new(&am.get::<Cfg>(func))
+    }
+}
+
+/// The dominance frontier of every block: the blocks where its
+/// dominance stops, which is where the values it defines meet the
+/// values of other paths
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DominanceFrontiers {
+    frontiers: Vec<Vec<usize>>,
+}
+
+impl DominanceFrontiers {
+    pub fn new(cfg: &Cfg, dom: &Dominators) -> Self {
+        let mut frontiers = vec![Vec::new(); cfg.len()];
+        for b in 0..cfg.len() {
+            let preds: Vec<usize> = cfg.preds[b]
+                .iter()
+                .copied()
+                .filter(|p| dom.is_reachable(*p))
+                .collect();
+            if preds.len() < 2 || !dom.is_reachable(b) {
+                continue;
+            }
+            for p in preds {
+                let mut runner = Some(p);
+                while let Some(r) = runner.filter(|r| Some(*r) != dom.idom(b)) {
+                    if !frontiers[r].contains(&b) {
+                        frontiers[r].push(b);
+                    }
+                    runner = dom.idom(r);
+                }
+            }
+        }
+        Self { frontiers }
+    }
+
+    pub fn frontier(&self, b: usize) -> &[usize] {
+        &self.frontiers[b]
+    }
+
+    /// The iterated dominance frontier of `blocks`, where phis are needed
+    /// for a value defined in each of them
+    pub fn iterated(&self, blocks: impl IntoIterator<Item = usize>) -> Vec<usize> {
+        let mut result: Vec<usize> = Vec::new();
+        let mut worklist: Vec<usize> = blocks.into_iter().collect();
+        while let Some(b) = worklist.pop() {
+            for f in &self.frontiers[b] {
+                if !result.contains(f) {
+                    result.push(*f);
+                    worklist.push(*f);
+                }
+            }
+        }
+        result.sort_unstable();
+        result
+    }
+}
+
+impl FunctionAnalysis for DominanceFrontiers {
+    type Result = DominanceFrontiers;
+
+    fn name() -> &'static str {
+        "dominance-frontiers"
+    }
+
+    fn run(func: &FunctionValue, am: &mut AnalysisManager) -> Self::Result {
+        let cfg = am.get::<Cfg>(func);
+        let dom = am.get::<Dominators>(func);
+        DominanceFrontiers::new(&cfg, &dom)
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashSet;
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::LabelBodyInstr;
+use mirage_frontend::object::RegisterType;
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::{AnalysisManager, FunctionAnalysis};
+use crate::ir::{for_each_use, is_phi, reg_key, RegKey};
+
+/// Whether the register belongs to the function, as opposed to its
+/// arguments and the globals of the module
+pub(crate) fn is_local(key: &RegKey) -> bool {
+    matches!(key.0, RegisterType::Register | RegisterType::Variable)
+}
+
+/// Add the local registers `instr` reads to `live`
+pub(crate) fn uses(live: &mut HashSet<RegKey>, instr: &LabelBodyInstr) {
+    for_each_use(instr, &mut |reg, _| {
+        let key = reg_key(reg);
+        if is_local(&key) {
+            live.insert(key);
+        }
+    });
+}
+
+/// Turn the registers live after `instr` into the ones live before it
+pub(crate) fn step(live: &mut HashSet<RegKey>, instr: &LabelBodyInstr) {
+    if let LabelBodyInstr::Assign(reg, _) = instr {
+        live.remove(&reg_key(reg));
+    }
+    uses(live, instr);
+}
+
+/// The local registers live at the boundaries of every block and label.
+///
+/// A register is live at a point when a path from that point reads it
+/// before assigning it. The phis of a block all read their operands when
+/// entering it, so their operands are live at the start of the block.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Liveness {
+    live_in: Vec<HashSet<RegKey>>,
+    live_out: Vec<HashSet<RegKey>>,
+    /// The first and last block of every label
+    labels: Vec<(usize, usize)>,
+}
+
+impl Liveness {
+    pub fn new(func: &FunctionValue, cfg: &Cfg) -> Self {
+        let mut live_in: Vec<HashSet<RegKey>> = vec![HashSet::new(); cfg.len()];
+        let mut live_out: Vec<HashSet<RegKey>> = vec![HashSet::new(); cfg.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in (0..cfg.len()).rev() {
+                let out: HashSet<RegKey> = cfg.succs[b]
+                    .iter()
+                    .flat_map(|s| live_in[*s].iter().copied())
+                    .collect();
+                let mut live = out.clone();
+                let instrs = cfg.instrs(func, b);
+                let phis = instrs.iter().take_while(|i| is_phi(i)).count();
+                for instr in instrs[phis..].iter().rev() {
+                    step(&mut live, instr);
+                }
+                for instr in &instrs[..phis] {
+                    if let LabelBodyInstr::Assign(reg, _) = instr {
+                        live.remove(&reg_key(reg));
+                    }
+                }
+                for instr in &instrs[..phis] {
+                    uses(&mut live, instr);
+                }
+                live_out[b] = out;
+                if live != live_in[b] {
+                    live_in[b] = live;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut labels = vec![(usize::MAX, 0); func.len_labels()];
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let (first, last) = &mut labels[block.label];
+            *first = (*first).min(b);
+            *last = b;
+        }
+        Self {
+            live_in,
+            live_out,
+            labels,
+        }
+    }
+
+    /// The registers live at the start of block `b`
+    pub fn live_in(&self, b: usize) -> &HashSet<RegKey> {
+        &self.live_in[b]
+    }
+
+    /// The registers live at the end of block `b`
+    pub fn live_out(&self, b: usize) -> &HashSet<RegKey> {
+        &self.live_out[b]
+    }
+
+    /// The registers live at the start of the label at index `l`
+    pub fn label_live_in(&self, l: usize) -> &HashSet<RegKey> {
+        &self.live_in[self.labels[l].0]
+    }
+
+    /// The registers live at the end of the label at index `l`
+    pub fn label_live_out(&self, l: usize) -> &HashSet<RegKey> {
+        &self.live_out[self.labels[l].1]
+    }
+}
+
+impl FunctionAnalysis for Liveness {
+    type Result = Liveness;
+
+    fn name() -> &'static str {
+        "liveness"
+    }
+
+    fn run(func: &FunctionValue, am: &mut AnalysisManager) -> Self::Result {
+        Liveness::new(func, &am.get::<Cfg>(func))
+    }
+}
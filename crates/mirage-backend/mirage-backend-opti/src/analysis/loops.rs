@@ -0,0 +1,123 @@
+use mirage_frontend::object::function::FunctionValue;
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::dominators::Dominators;
+use crate::analysis::{AnalysisManager, FunctionAnalysis};
+
+/// A natural loop: the blocks which can reach one of the `latches`
+/// without going through the `header`, which dominates all of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub header: usize,
+    /// The blocks with a back edge to the header
+    pub latches: Vec<usize>,
+    /// The blocks of the loop, header and nested loops included, sorted
+    pub blocks: Vec<usize>,
+    /// The innermost loop containing this one
+    pub parent: Option<usize>,
+    /// 1 for an outermost loop
+    pub depth: usize,
+}
+
+impl Loop {
+    pub fn contains(&self, b: usize) -> bool {
+        self.blocks.binary_search(&b).is_ok()
+    }
+}
+
+/// The natural loops of a function. Loops sharing a header are merged,
+/// and parents always come before the loops they contain.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Loops {
+    loops: Vec<Loop>,
+    /// The innermost loop of every block
+    innermost: Vec<Option<usize>>,
+}
+
+impl Loops {
+    pub fn new(cfg: &Cfg, dom: &Dominators) -> Self {
+        let mut loops: Vec<Loop> = Vec::new();
+        for header in dom.pre_order() {
+            let latches: Vec<usize> = cfg.preds[header]
+                .iter()
+                .copied()
+                .filter(|p| dom.dominates(header, *p))
+                .collect();
+            if latches.is_empty() {
+                continue;
+            }
+            let mut blocks = vec![header];
+            let mut worklist = latches.clone();
+            while let Some(b) = worklist.pop() {
+                if blocks.contains(&b) {
+                    continue;
+                }
+                blocks.push(b);
+                worklist.extend(
+                    cfg.preds[b]
+                        .iter()
+                        .copied()
+                        .filter(|p| dom.is_reachable(*p)),
+                );
+            }
+            blocks.sort_unstable();
+            loops.push(Loop {
+                header,
+                latches,
+                blocks,
+                parent: None,
+                depth: 1,
+            });
+        }
+
+        // Headers are visited in dominator pre-order, so an enclosing loop
+        // is always found before the loops it contains
+        let mut innermost: Vec<Option<usize>> = vec![None; cfg.len()];
+        for l in 0..loops.len() {
+            let parent = (0..l)
+                .filter(|p| loops[*p].contains(loops[l].header))
+                .min_by_key(|p| loops[*p].blocks.len());
+            loops[l].parent = parent;
+            loops[l].depth = parent.map_or(1, |p| loops[p].depth + 1);
+            for b in &loops[l].blocks {
+                innermost[*b] = match innermost[*b] {
+                    Some(i) if loops[i].blocks.len() <= loops[l].blocks.len() => Some(i),
+                    _ => Some(l),
+                };
+            }
+        }
+        Self { loops, innermost }
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    /// The innermost loop containing `b`
+    pub fn loop_of(&self, b: usize) -> Option<&Loop> {
+        self.innermost[b].map(|l| &self.loops[l])
+    }
+
+    /// The number of loops containing `b`
+    pub fn depth(&self, b: usize) -> usize {
+        self.loop_of(b).map_or(0, |l| l.depth)
+    }
+
+    pub fn is_header(&self, b: usize) -> bool {
+        self.loop_of(b).is_some_and(|l| l.header == b)
+    }
+}
+
+impl FunctionAnalysis for Loops {
+    type Result = Loops;
+
+    fn name() -> &'static str {
+        "loops"
+    }
+
+    fn run(func: &FunctionValue, am: &mut AnalysisManager) -> Self::Result {
+        let cfg = am.get::<Cfg>(func);
+        let dom = am.get::<Dominators>(func);
+        Loops::new(&cfg, &dom)
+    }
+}
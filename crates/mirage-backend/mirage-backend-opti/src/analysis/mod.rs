@@ -0,0 +1,246 @@
+pub mod call_graph;
+pub mod cfg;
+pub mod dominators;
+pub mod liveness;
+pub mod loops;
+pub mod ssa;
+pub mod use_def;
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::statements::Statement;
+
+/// An analysis computed over a single function
+pub trait FunctionAnalysis: 'static {
+    type Result: 'static;
+
+    fn name() -> &'static str;
+
+    /// Compute the analysis. Other analyses can be requested from `am`.
+    fn run(func: &FunctionValue, am: &mut AnalysisManager) -> Self::Result;
+}
+
+/// An analysis computed over the whole module
+pub trait ModuleAnalysis: 'static {
+    type Result: 'static;
+
+    fn name() -> &'static str;
+
+    /// Compute the analysis. Other analyses can be requested from `am`.
+    fn run(stmts: &[Statement], am: &mut AnalysisManager) -> Self::Result;
+}
+
+#[derive(Clone, Copy)]
+enum AnalysisKind {
+    Function(fn(&FunctionValue, &mut AnalysisManager)),
+    Module(fn(&[Statement], &mut AnalysisManager)),
+}
+
+/// A type-erased handle to an analysis, used by passes to declare what
+/// they require and what they preserve
+#[derive(Clone, Copy)]
+pub struct AnalysisId {
+    type_id: TypeId,
+    name: &'static str,
+    kind: AnalysisKind,
+}
+
+impl AnalysisId {
+    pub fn function<A: FunctionAnalysis>() -> Self {
+        fn compute<A: FunctionAnalysis>(func: &FunctionValue, am: &mut AnalysisManager) {
+            am.get::<A>(func);
+        }
+        Self {
+            type_id: TypeId::of::<A>(),
+            name: A::name(),
+            kind: AnalysisKind::Function(compute::<A>),
+        }
+    }
+
+    pub fn module<A: ModuleAnalysis>() -> Self {
+        fn compute<A: ModuleAnalysis>(stmts: &[Statement], am: &mut AnalysisManager) {
+            am.get_module::<A>(stmts);
+        }
+        Self {
+            type_id: TypeId::of::<A>(),
+            name: A::name(),
+            kind: AnalysisKind::Module(compute::<A>),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_module(&self) -> bool {
+        matches!(self.kind, AnalysisKind::Module(_))
+    }
+
+    /// Make sure the analysis is cached for `func`
+    pub(crate) fn compute_function(&self, func: &FunctionValue, am: &mut AnalysisManager) {
+        if let AnalysisKind::Function(f) = self.kind {
+            f(func, am)
+        }
+    }
+
+    /// Make sure the analysis is cached for the module
+    pub(crate) fn compute_module(&self, stmts: &[Statement], am: &mut AnalysisManager) {
+        if let AnalysisKind::Module(f) = self.kind {
+            f(stmts, am)
+        }
+    }
+}
+
+impl PartialEq for AnalysisId {
+    fn eq(&self, other: &Self) -> bool {
+        self.type_id == other.type_id
+    }
+}
+
+impl std::fmt::Debug for AnalysisId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AnalysisId({})", self.name)
+    }
+}
+
+/// What a pass left intact
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Preserved {
+    /// The pass didn't change the IR
+    #[default]
+    All,
+    /// The pass changed the IR; every analysis is stale
+    None,
+    /// The pass changed the IR but these analyses are still valid
+    Only(Vec<AnalysisId>),
+}
+
+impl Preserved {
+    pub fn changed(&self) -> bool {
+        !matches!(self, Preserved::All)
+    }
+
+    pub fn preserves(&self, id: &AnalysisId) -> bool {
+        match self {
+            Preserved::All => true,
+            Preserved::None => false,
+            Preserved::Only(ids) => ids.contains(id),
+        }
+    }
+
+    /// Combine the results of two passes run one after the other
+    pub fn intersect(self, other: Preserved) -> Preserved {
+        match (self, other) {
+            (Preserved::All, p) | (p, Preserved::All) => p,
+            (Preserved::Only(a), Preserved::Only(b)) => {
+                Preserved::Only(a.into_iter().filter(|id| b.contains(id)).collect())
+            }
+            _ => Preserved::None,
+        }
+    }
+}
+
+/// Number of analysis computations and cache hits
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AnalysisCounters {
+    pub computed: usize,
+    pub cached: usize,
+}
+
+/// Caches analysis results per function and per module until a pass
+/// invalidates them
+#[derive(Default)]
+pub struct AnalysisManager {
+    function_results: HashMap<(String, TypeId), Rc<dyn Any>>,
+    module_results: HashMap<TypeId, Rc<dyn Any>>,
+    counters: HashMap<&'static str, AnalysisCounters>,
+}
+
+impl AnalysisManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The result of `A` for `func`, computed if not cached
+    pub fn get<A: FunctionAnalysis>(&mut self, func: &FunctionValue) -> Rc<A::Result> {
+        let key = (func.get_name().clone(), TypeId::of::<A>());
+        if let Some(res) = self.function_results.get(&key) {
+            self.counters.entry(A::name()).or_default().cached += 1;
+            return res.clone().downcast::<A::Result>().unwrap();
+        }
+        let res = Rc::new(A::run(func, self));
+        self.counters.entry(A::name()).or_default().computed += 1;
+        self.function_results.insert(key, res.clone());
+        res
+    }
+
+    /// The result of `A` for `func`, only if cached
+    pub fn get_cached<A: FunctionAnalysis>(&self, func: &str) -> Option<Rc<A::Result>> {
+        self.function_results
+            .get(&(func.to_string(), TypeId::of::<A>()))
+            .map(|res| res.clone().downcast::<A::Result>().unwrap())
+    }
+
+    /// The result of `A` for the module, computed if not cached
+    pub fn get_module<A: ModuleAnalysis>(&mut self, stmts: &[Statement]) -> Rc<A::Result> {
+        if let Some(res) = self.get_module_cached::<A>() {
+            self.counters.entry(A::name()).or_default().cached += 1;
+            return res;
+        }
+        let res = Rc::new(A::run(stmts, self));
+        self.counters.entry(A::name()).or_default().computed += 1;
+        self.module_results.insert(TypeId::of::<A>(), res.clone());
+        res
+    }
+
+    /// The result of `A` for the module, only if cached
+    pub fn get_module_cached<A: ModuleAnalysis>(&self) -> Option<Rc<A::Result>> {
+        self.module_results
+            .get(&TypeId::of::<A>())
+            .map(|res| res.clone().downcast::<A::Result>().unwrap())
+    }
+
+    /// Drop the results for `func` which `preserved` doesn't cover
+    pub fn invalidate(&mut self, func: &str, preserved: &Preserved) {
+        if !preserved.changed() {
+            return;
+        }
+        self.function_results
+            .retain(|(name, id), _| name != func || Self::is_preserved(preserved, id));
+    }
+
+    /// Drop the module results which `preserved` doesn't cover
+    pub fn invalidate_module(&mut self, preserved: &Preserved) {
+        if !preserved.changed() {
+            return;
+        }
+        self.module_results
+            .retain(|id, _| Self::is_preserved(preserved, id));
+    }
+
+    /// Drop every cached result, for every function and the module
+    pub fn invalidate_all(&mut self, preserved: &Preserved) {
+        if !preserved.changed() {
+            return;
+        }
+        self.function_results
+            .retain(|(_, id), _| Self::is_preserved(preserved, id));
+        self.invalidate_module(preserved);
+    }
+
+    fn is_preserved(preserved: &Preserved, id: &TypeId) -> bool {
+        match preserved {
+            Preserved::All => true,
+            Preserved::None => false,
+            Preserved::Only(ids) => ids.iter().any(|x| &x.type_id == id),
+        }
+    }
+
+    /// How often each analysis was computed or served from the cache
+    pub fn counters(&self) -> &HashMap<&'static str, AnalysisCounters> {
+        &self.counters
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::LabelBodyInstr;
+use mirage_frontend::object::RegisterType;
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::dominators::Dominators;
+use crate::analysis::{AnalysisManager, FunctionAnalysis};
+use crate::ir::{for_each_use, phi_incoming, reg_key, value_register, RegKey, Use};
+
+/// The registers which are already SSA values: assigned exactly once,
+/// never used as memory, and read only where their assignment dominates.
+/// A backend can keep them as plain values instead of stack slots.
+pub struct SsaRegisters;
+
+impl FunctionAnalysis for SsaRegisters {
+    type Result = HashSet<(RegisterType, usize)>;
+
+    fn name() -> &'static str {
+        "ssa-registers"
+    }
+
+    fn run(func: &FunctionValue, am: &mut AnalysisManager) -> Self::Result {
+        let cfg = am.get::<Cfg>(func);
+        let dom = am.get::<Dominators>(func);
+
+        // Where each register is assigned, as (block, index)
+        let mut defs: HashMap<RegKey, Vec<(usize, usize)>> = HashMap::new();
+        let mut memory = HashSet::new();
+        for b in 0..cfg.len() {
+            for (i, instr) in cfg.instrs(func, b).iter().enumerate() {
+                if let LabelBodyInstr::Assign(reg, _) = instr {
+                    defs.entry(reg_key(reg)).or_default().push((b, i));
+                }
+                for_each_use(instr, &mut |reg, kind| {
+                    if kind == Use::Address {
+                        memory.insert(reg_key(reg));
+                    }
+                });
+            }
+        }
+        let mut ssa: HashSet<RegKey> = defs
+            .iter()
+            .filter(|(key, defs)| {
+                matches!(key.0, RegisterType::Register | RegisterType::Variable)
+                    && defs.len() == 1
+                    && dom.is_reachable(defs[0].0)
+                    && !memory.contains(*key)
+            })
+            .map(|(key, _)| *key)
+            .collect();
+
+        // The uses of each register, as the points which must be dominated
+        let mut uses: Vec<(RegKey, (usize, usize))> = Vec::new();
+        for b in 0..cfg.len() {
+            for (i, instr) in cfg.instrs(func, b).iter().enumerate() {
+                let incoming = match phi_incoming(instr) {
+                    Some(incoming) => incoming,
+                    None => {
+                        for_each_use(instr, &mut |reg, _| uses.push((reg_key(reg), (b, i))));
+                        continue;
+                    }
+                };
+                // A phi reads its operand at the end of the predecessor
+                for (label, value) in incoming {
+                    let (reg, first) = match (value_register(value), cfg.label_block(label)) {
+                        (Some(reg), Some(first)) => (reg, first),
+                        _ => continue,
+                    };
+                    for p in (first..cfg.len())
+                        .take_while(|p| cfg.blocks[*p].label == cfg.blocks[first].label)
+                        .filter(|p| cfg.succs[*p].contains(&b))
+                    {
+                        uses.push((reg_key(reg), (p, usize::MAX)));
+                    }
+                }
+            }
+        }
+        for (key, (b, i)) in uses {
+            let (db, di) = match defs.get(&key) {
+                Some(defs) => defs[0],
+                None => continue,
+            };
+            let dominated = if db == b {
+                di < i
+            } else {
+                dom.dominates(db, b)
+            };
+            if !dominated {
+                ssa.remove(&key);
+            }
+        }
+        ssa
+    }
+}
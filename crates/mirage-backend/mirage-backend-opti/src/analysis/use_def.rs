@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::LabelBodyInstr;
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::{AnalysisManager, FunctionAnalysis};
+use crate::ir::{for_each_use, phi_incoming, reg_key, value_register, RegKey};
+
+/// An instruction of a function: the index of its label, and its index
+/// in the body of the label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Location {
+    pub label: usize,
+    pub index: usize,
+}
+
+/// The assignments of every register, the instructions reading it, and
+/// the chains between them.
+///
+/// Registers can be assigned more than once, so a read is linked to every
+/// assignment which reaches it on some path. Arguments and globals have
+/// no assignment in the function.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UseDef {
+    defs: HashMap<RegKey, Vec<Location>>,
+    uses: HashMap<RegKey, Vec<Location>>,
+    use_defs: HashMap<(Location, RegKey), Vec<Location>>,
+    def_uses: HashMap<Location, Vec<Location>>,
+}
+
+/// The definitions reaching a point, by register
+type Reaching = HashMap<RegKey, HashSet<Location>>;
+
+fn merge(into: &mut Reaching, from: &Reaching) {
+    for (key, defs) in from {
+        into.entry(*key).or_default().extend(defs.iter().copied());
+    }
+}
+
+impl UseDef {
+    pub fn new(func: &FunctionValue, cfg: &Cfg) -> Self {
+        let location = |b: usize, i: usize| Location {
+            label: cfg.blocks[b].label,
+            index: cfg.blocks[b].start + i,
+        };
+
+        // Reaching definitions at the end of every block
+        let rpo = cfg.reverse_post_order();
+        let mut reach_out: Vec<Reaching> = vec![HashMap::new(); cfg.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in &rpo {
+                let mut reach = Reaching::new();
+                for p in &cfg.preds[*b] {
+                    merge(&mut reach, &reach_out[*p]);
+                }
+                for (i, instr) in cfg.instrs(func, *b).iter().enumerate() {
+                    if let LabelBodyInstr::Assign(reg, _) = instr {
+                        reach.insert(reg_key(reg), HashSet::from([location(*b, i)]));
+                    }
+                }
+                if reach != reach_out[*b] {
+                    reach_out[*b] = reach;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut chains = UseDef::default();
+        for b in rpo {
+            let mut reach = Reaching::new();
+            for p in &cfg.preds[b] {
+                merge(&mut reach, &reach_out[*p]);
+            }
+            for (i, instr) in cfg.instrs(func, b).iter().enumerate() {
+                let here = location(b, i);
+                match phi_incoming(instr) {
+                    // A phi reads each operand at the end of the
+                    // predecessors of its label
+                    Some(incoming) => {
+                        for (label, value) in incoming {
+                            let Some(reg) = value_register(value) else {
+                                continue;
+                            };
+                            let key = reg_key(reg);
+                            let defs: HashSet<Location> = cfg.preds[b]
+                                .iter()
+                                .filter(|p| {
+                                    cfg.label_block(label).is_some_and(|l| {
+                                        cfg.blocks[**p].label == cfg.blocks[l].label
+                                    })
+                                })
+                                .flat_map(|p| reach_out[*p].get(&key).into_iter().flatten())
+                                .copied()
+                                .collect();
+                            chains.add_use(key, here, defs);
+                        }
+                    }
+                    None => for_each_use(instr, &mut |reg, _| {
+                        let key = reg_key(reg);
+                        let defs = reach.get(&key).cloned().unwrap_or_default();
+                        chains.add_use(key, here, defs);
+                    }),
+                }
+                if let LabelBodyInstr::Assign(reg, _) = instr {
+                    chains.defs.entry(reg_key(reg)).or_default().push(here);
+                    reach.insert(reg_key(reg), HashSet::from([here]));
+                }
+            }
+        }
+        for list in chains
+            .defs
+            .values_mut()
+            .chain(chains.uses.values_mut())
+            .chain(chains.use_defs.values_mut())
+            .chain(chains.def_uses.values_mut())
+        {
+            list.sort_unstable();
+            list.dedup();
+        }
+        chains
+    }
+
+    fn add_use(&mut self, key: RegKey, at: Location, defs: HashSet<Location>) {
+        self.uses.entry(key).or_default().push(at);
+        for def in &defs {
+            self.def_uses.entry(*def).or_default().push(at);
+        }
+        self.use_defs.entry((at, key)).or_default().extend(defs);
+    }
+
+    /// The assignments of `reg` in the reachable labels
+    pub fn defs(&self, reg: &RegKey) -> &[Location] {
+        self.defs.get(reg).map_or(&[], |d| d)
+    }
+
+    /// The reachable instructions reading `reg`
+    pub fn uses(&self, reg: &RegKey) -> &[Location] {
+        self.uses.get(reg).map_or(&[], |u| u)
+    }
+
+    /// The assignments of `reg` whose value can be read at `at`
+    pub fn reaching_defs(&self, at: Location, reg: &RegKey) -> &[Location] {
+        self.use_defs.get(&(at, *reg)).map_or(&[], |d| d)
+    }
+
+    /// The instructions which can read the value assigned at `def`
+    pub fn def_uses(&self, def: Location) -> &[Location] {
+        self.def_uses.get(&def).map_or(&[], |u| u)
+    }
+}
+
+impl FunctionAnalysis for UseDef {
+    type Result = UseDef;
+
+    fn name() -> &'static str {
+        "use-def"
+    }
+
+    fn run(func: &FunctionValue, am: &mut AnalysisManager) -> Self::Result {
+        UseDef::new(func, &am.get::<Cfg>(func))
+    }
+}
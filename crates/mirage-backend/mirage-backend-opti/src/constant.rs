@@ -0,0 +1,147 @@
+use mirage_frontend::object::{
+    Float32Value, Float64Value, Int16Value, Int32Value, Int64Value, Int8Value, MirageValueEnum,
+    UInt16Value, UInt32Value, UInt64Value, UInt8Value,
+};
+
+/// A scalar constant the optimizer can compute with.
+///
+/// Integer arithmetic wraps around like the generated machine code does.
+/// Two constants are equal when they have the same type and the same bits,
+/// so `0.0` and `-0.0` are different constants.
+#[derive(Debug, Clone, Copy)]
+pub enum Constant {
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Float32(f32),
+    Float64(f64),
+}
+
+macro_rules! int_op {
+    ($lhs:expr, $rhs:expr, $op:ident, $fop:tt) => {
+        match ($lhs, $rhs) {
+            (Constant::Int8(a), Constant::Int8(b)) => Some(Constant::Int8(a.$op(b))),
+            (Constant::Int16(a), Constant::Int16(b)) => Some(Constant::Int16(a.$op(b))),
+            (Constant::Int32(a), Constant::Int32(b)) => Some(Constant::Int32(a.$op(b))),
+            (Constant::Int64(a), Constant::Int64(b)) => Some(Constant::Int64(a.$op(b))),
+            (Constant::UInt8(a), Constant::UInt8(b)) => Some(Constant::UInt8(a.$op(b))),
+            (Constant::UInt16(a), Constant::UInt16(b)) => Some(Constant::UInt16(a.$op(b))),
+            (Constant::UInt32(a), Constant::UInt32(b)) => Some(Constant::UInt32(a.$op(b))),
+            (Constant::UInt64(a), Constant::UInt64(b)) => Some(Constant::UInt64(a.$op(b))),
+            (Constant::Float32(a), Constant::Float32(b)) => Some(Constant::Float32(a $fop b)),
+            (Constant::Float64(a), Constant::Float64(b)) => Some(Constant::Float64(a $fop b)),
+            _ => None,
+        }
+    };
+}
+
+impl Constant {
+    /// The constant held by `value`, if it is a scalar
+    pub fn from_value(value: &MirageValueEnum) -> Option<Self> {
+        Some(match value {
+            MirageValueEnum::Int8(v) => Constant::Int8(v.value),
+            MirageValueEnum::Int16(v) => Constant::Int16(v.value),
+            MirageValueEnum::Int32(v) => Constant::Int32(v.value),
+            MirageValueEnum::Int64(v) => Constant::Int64(v.value),
+            MirageValueEnum::UInt8(v) => Constant::UInt8(v.value),
+            MirageValueEnum::UInt16(v) => Constant::UInt16(v.value),
+            MirageValueEnum::UInt32(v) => Constant::UInt32(v.value),
+            MirageValueEnum::UInt64(v) => Constant::UInt64(v.value),
+            MirageValueEnum::Float32(v) => Constant::Float32(v.value),
+            MirageValueEnum::Float64(v) => Constant::Float64(v.value),
+            _ => return None,
+        })
+    }
+
+    pub fn to_value(self) -> MirageValueEnum {
+        match self {
+            Constant::Int8(v) => Int8Value::new(v).to_value_enum(),
+            Constant::Int16(v) => Int16Value::new(v).to_value_enum(),
+            Constant::Int32(v) => Int32Value::new(v).to_value_enum(),
+            Constant::Int64(v) => Int64Value::new(v).to_value_enum(),
+            Constant::UInt8(v) => UInt8Value::new(v).to_value_enum(),
+            Constant::UInt16(v) => UInt16Value::new(v).to_value_enum(),
+            Constant::UInt32(v) => UInt32Value::new(v).to_value_enum(),
+            Constant::UInt64(v) => UInt64Value::new(v).to_value_enum(),
+            Constant::Float32(v) => Float32Value::new(v).to_value_enum(),
+            Constant::Float64(v) => Float64Value::new(v).to_value_enum(),
+        }
+    }
+
+    pub fn bits(&self) -> usize {
+        match self {
+            Constant::Int8(_) | Constant::UInt8(_) => 8,
+            Constant::Int16(_) | Constant::UInt16(_) => 16,
+            Constant::Int32(_) | Constant::UInt32(_) | Constant::Float32(_) => 32,
+            Constant::Int64(_) | Constant::UInt64(_) | Constant::Float64(_) => 64,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Constant::Float32(_) | Constant::Float64(_))
+    }
+
+    /// `self + rhs`, or `None` if the types differ
+    pub fn wrapping_add(self, rhs: Constant) -> Option<Constant> {
+        int_op!(self, rhs, wrapping_add, +)
+    }
+
+    /// `self - rhs`, or `None` if the types differ
+    pub fn wrapping_sub(self, rhs: Constant) -> Option<Constant> {
+        int_op!(self, rhs, wrapping_sub, -)
+    }
+
+    /// `self + 1`
+    pub fn incr(self) -> Constant {
+        let one = match self {
+            Constant::Int8(_) => Constant::Int8(1),
+            Constant::Int16(_) => Constant::Int16(1),
+            Constant::Int32(_) => Constant::Int32(1),
+            Constant::Int64(_) => Constant::Int64(1),
+            Constant::UInt8(_) => Constant::UInt8(1),
+            Constant::UInt16(_) => Constant::UInt16(1),
+            Constant::UInt32(_) => Constant::UInt32(1),
+            Constant::UInt64(_) => Constant::UInt64(1),
+            Constant::Float32(_) => Constant::Float32(1.0),
+            Constant::Float64(_) => Constant::Float64(1.0),
+        };
+        self.wrapping_add(one).unwrap()
+    }
+
+    /// Whether `jeq` would branch on these operands, or `None` if the
+    /// types differ. Floats compare like IEEE 754 `==`.
+    pub fn jeq(self, rhs: Constant) -> Option<bool> {
+        Some(match (self, rhs) {
+            (Constant::Float32(a), Constant::Float32(b)) => a == b,
+            (Constant::Float64(a), Constant::Float64(b)) => a == b,
+            (a, b) if a.is_float() || b.is_float() => return None,
+            (a, b) if std::mem::discriminant(&a) != std::mem::discriminant(&b) => return None,
+            (a, b) => a == b,
+        })
+    }
+}
+
+impl PartialEq for Constant {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Constant::Float32(a), Constant::Float32(b)) => a.to_bits() == b.to_bits(),
+            (Constant::Float64(a), Constant::Float64(b)) => a.to_bits() == b.to_bits(),
+            (Constant::Int8(a), Constant::Int8(b)) => a == b,
+            (Constant::Int16(a), Constant::Int16(b)) => a == b,
+            (Constant::Int32(a), Constant::Int32(b)) => a == b,
+            (Constant::Int64(a), Constant::Int64(b)) => a == b,
+            (Constant::UInt8(a), Constant::UInt8(b)) => a == b,
+            (Constant::UInt16(a), Constant::UInt16(b)) => a == b,
+            (Constant::UInt32(a), Constant::UInt32(b)) => a == b,
+            (Constant::UInt64(a), Constant::UInt64(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Constant {}
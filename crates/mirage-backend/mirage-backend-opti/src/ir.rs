@@ -0,0 +1,334 @@
+//! Helpers to walk and rewrite the operands of Mirage instructions
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::Flag;
+use mirage_frontend::object::util::List;
+use mirage_frontend::object::{MirageObject, MirageValueEnum, RegisterType, RegisterValue};
+
+/// Identifies a register independently of its type and flags
+pub type RegKey = (RegisterType, usize);
+
+pub fn reg_key(reg: &RegisterValue) -> RegKey {
+    (reg.register_type, reg.index)
+}
+
+/// The register a value reads, if any. Builder generated call arguments
+/// wrap their registers in a `ConstValue`, so both forms are accepted.
+pub fn value_register(value: &Value) -> Option<&RegisterValue> {
+    match value {
+        Value::Register(reg) => Some(reg),
+        Value::ConstValue(obj) => match obj.get_value_ref() {
+            MirageValueEnum::Register(reg) => Some(reg),
+            _ => None,
+        },
+        Value::List(_) => None,
+    }
+}
+
+/// How an instruction uses a register operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Use {
+    /// The value of the register is read
+    Read,
+    /// The register holds a pointer which is loaded from, stored to or
+    /// offset
+    Pointer,
+    /// The register is used as memory: its address is taken or it is freed
+    Address,
+}
+
+/// Call `f` on every register the instruction reads or addresses
+pub fn for_each_use(instr: &LabelBodyInstr, f: &mut impl FnMut(&RegisterValue, Use)) {
+    match instr {
+        LabelBodyInstr::Assign(_, instr) => for_each_use(instr, f),
+        LabelBodyInstr::Call(_, args) => {
+            for arg in args {
+                value_uses(arg, Use::Read, f);
+            }
+        }
+        LabelBodyInstr::Command(cmd) => command_uses(cmd, f),
+    }
+}
+
+fn value_uses(value: &Value, kind: Use, f: &mut impl FnMut(&RegisterValue, Use)) {
+    match value {
+        Value::List(list) => {
+            for v in list.iter() {
+                value_uses(v, kind, f);
+            }
+        }
+        v => {
+            if let Some(reg) = value_register(v) {
+                f(reg, kind)
+            }
+        }
+    }
+}
+
+fn command_uses(cmd: &Command, f: &mut impl FnMut(&RegisterValue, Use)) {
+    match cmd {
+        Command::Store(reg, value) => {
+            f(reg, Use::Pointer);
+            value_uses(value, Use::Read, f);
+        }
+        Command::New(_, args) => {
+            for v in args.iter() {
+                value_uses(v, Use::Read, f);
+            }
+        }
+        Command::Get(reg, _) => f(reg, Use::Address),
+        Command::Const(obj) => {
+            if let MirageValueEnum::Register(reg) = obj.get_value_ref() {
+                f(reg, Use::Read)
+            }
+        }
+        Command::Free(regs) => {
+            for reg in regs {
+                f(reg, Use::Address)
+            }
+        }
+        Command::Ret(v) => value_uses(v, Use::Read, f),
+        Command::Jeq(_, lhs, rhs)
+        | Command::AddInt8(lhs, rhs)
+        | Command::AddInt16(lhs, rhs)
+        | Command::AddInt32(lhs, rhs)
+        | Command::AddInt64(lhs, rhs)
+        | Command::AddFloat32(lhs, rhs)
+        | Command::AddFloat64(lhs, rhs)
+        | Command::SubInt8(lhs, rhs)
+        | Command::SubInt16(lhs, rhs)
+        | Command::SubInt32(lhs, rhs)
+        | Command::SubInt64(lhs, rhs)
+        | Command::SubFloat32(lhs, rhs)
+        | Command::SubFloat64(lhs, rhs) => {
+            value_uses(lhs, Use::Read, f);
+            value_uses(rhs, Use::Read, f);
+        }
+        Command::IncrInt8(reg)
+        | Command::IncrInt16(reg)
+        | Command::IncrInt32(reg)
+        | Command::IncrInt64(reg)
+        | Command::IncrFloat32(reg)
+        | Command::IncrFloat64(reg) => f(reg, Use::Read),
+        Command::Ref(v) => value_uses(v, Use::Address, f),
+        Command::Load(_, v) => value_uses(v, Use::Pointer, f),
+        Command::GetElementPtr(_, base, indices) => {
+            value_uses(base, Use::Pointer, f);
+            for v in indices {
+                value_uses(v, Use::Read, f);
+            }
+        }
+        Command::Phi(incoming) => {
+            for (_, v) in incoming {
+                value_uses(v, Use::Read, f);
+            }
+        }
+        Command::Jump(_) => {}
+    }
+}
+
+/// Call `f` on every operand which is read by value and could be replaced
+/// by a constant. Operands used as memory are skipped.
+pub fn for_each_read_operand_mut(instr: &mut LabelBodyInstr, f: &mut impl FnMut(&mut Value)) {
+    match instr {
+        LabelBodyInstr::Assign(_, instr) => for_each_read_operand_mut(instr, f),
+        LabelBodyInstr::Call(_, args) => {
+            for v in args {
+                f(v)
+            }
+        }
+        LabelBodyInstr::Command(cmd) => match cmd {
+            Command::Store(_, v) | Command::Ret(v) => f(v),
+            Command::Jeq(_, lhs, rhs)
+            | Command::AddInt8(lhs, rhs)
+            | Command::AddInt16(lhs, rhs)
+            | Command::AddInt32(lhs, rhs)
+            | Command::AddInt64(lhs, rhs)
+            | Command::AddFloat32(lhs, rhs)
+            | Command::AddFloat64(lhs, rhs)
+            | Command::SubInt8(lhs, rhs)
+            | Command::SubInt16(lhs, rhs)
+            | Command::SubInt32(lhs, rhs)
+            | Command::SubInt64(lhs, rhs)
+            | Command::SubFloat32(lhs, rhs)
+            | Command::SubFloat64(lhs, rhs) => {
+                f(lhs);
+                f(rhs);
+            }
+            Command::GetElementPtr(_, _, indices) => {
+                for v in indices {
+                    f(v)
+                }
+            }
+            Command::Phi(incoming) => {
+                for (_, v) in incoming {
+                    f(v)
+                }
+            }
+            _ => {}
+        },
+    }
+}
+
+/// Whether the instruction ends its block
+pub fn is_terminator(instr: &LabelBodyInstr) -> bool {
+    matches!(
+        instr,
+        LabelBodyInstr::Command(Command::Jump(_) | Command::Jeq(..) | Command::Ret(_))
+    )
+}
+
+/// The predecessors and values of `r = phi ...`. Phis are at the start
+/// of a label and all read their operands when entering the label.
+pub fn phi_incoming(instr: &LabelBodyInstr) -> Option<&Vec<(String, Value)>> {
+    match instr {
+        LabelBodyInstr::Assign(_, instr) => match &**instr {
+            LabelBodyInstr::Command(Command::Phi(incoming)) => Some(incoming),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn is_phi(instr: &LabelBodyInstr) -> bool {
+    phi_incoming(instr).is_some()
+}
+
+/// The first index not used by the registers of type `ty` in `func`
+pub fn next_index(func: &FunctionValue, ty: RegisterType) -> usize {
+    let mut next = 0;
+    let mut see = |reg: &RegisterValue| {
+        if reg.register_type == ty {
+            next = next.max(reg.index + 1);
+        }
+    };
+    for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+        if let LabelBodyInstr::Assign(reg, _) = instr {
+            see(reg);
+        }
+        for_each_use(instr, &mut |reg, _| see(reg));
+    }
+    next
+}
+
+/// `reg = value`
+pub fn copy(reg: RegisterValue, value: &Value) -> LabelBodyInstr {
+    let obj = match value {
+        Value::ConstValue(obj) => obj.clone(),
+        Value::Register(r) => MirageObject::new(MirageValueEnum::Register(r.clone()), r.get_type()),
+        Value::List(_) => panic!("a list can't be copied in a register"),
+    };
+    LabelBodyInstr::Assign(reg, Box::new(LabelBodyInstr::Command(Command::Const(obj))))
+}
+
+/// Whether the entry label of `func` carries `flag`. Function attributes
+/// such as `#internal` are written on the entry label.
+pub fn has_attribute(func: &FunctionValue, flag: &Flag) -> bool {
+    func.get_labels()
+        .first()
+        .is_some_and(|l| l.flags.contains(flag))
+}
+
+/// Call `f` on every register of the instruction, defined or used
+pub fn for_each_register_mut(instr: &mut LabelBodyInstr, f: &mut impl FnMut(&mut RegisterValue)) {
+    if let LabelBodyInstr::Assign(reg, _) = instr {
+        f(reg);
+    }
+    for_each_use_mut(instr, &mut |reg, _| f(reg));
+}
+
+/// Like `for_each_use`, but the registers can be rewritten
+pub fn for_each_use_mut(instr: &mut LabelBodyInstr, f: &mut impl FnMut(&mut RegisterValue, Use)) {
+    match instr {
+        LabelBodyInstr::Assign(_, instr) => for_each_use_mut(instr, f),
+        LabelBodyInstr::Call(_, args) => {
+            for v in args {
+                value_uses_mut(v, Use::Read, f);
+            }
+        }
+        LabelBodyInstr::Command(cmd) => match cmd {
+            Command::Store(reg, v) => {
+                f(reg, Use::Pointer);
+                value_uses_mut(v, Use::Read, f);
+            }
+            Command::New(_, args) => {
+                let mut values = args.clone().into_vec();
+                for v in &mut values {
+                    value_uses_mut(v, Use::Read, f);
+                }
+                *args = List::from_vec(values);
+            }
+            Command::Get(reg, _) => f(reg, Use::Address),
+            Command::Const(obj) => {
+                if let MirageValueEnum::Register(reg) = obj.get_value_ref() {
+                    let mut reg = reg.clone();
+                    f(&mut reg, Use::Read);
+                    *obj = MirageObject::new(MirageValueEnum::Register(reg), obj.get_type());
+                }
+            }
+            Command::Free(regs) => {
+                for reg in regs {
+                    f(reg, Use::Address);
+                }
+            }
+            Command::Ret(v) => value_uses_mut(v, Use::Read, f),
+            Command::Ref(v) => value_uses_mut(v, Use::Address, f),
+            Command::Load(_, v) => value_uses_mut(v, Use::Pointer, f),
+            Command::Jeq(_, lhs, rhs)
+            | Command::AddInt8(lhs, rhs)
+            | Command::AddInt16(lhs, rhs)
+            | Command::AddInt32(lhs, rhs)
+            | Command::AddInt64(lhs, rhs)
+            | Command::AddFloat32(lhs, rhs)
+            | Command::AddFloat64(lhs, rhs)
+            | Command::SubInt8(lhs, rhs)
+            | Command::SubInt16(lhs, rhs)
+            | Command::SubInt32(lhs, rhs)
+            | Command::SubInt64(lhs, rhs)
+            | Command::SubFloat32(lhs, rhs)
+            | Command::SubFloat64(lhs, rhs) => {
+                value_uses_mut(lhs, Use::Read, f);
+                value_uses_mut(rhs, Use::Read, f);
+            }
+            Command::IncrInt8(reg)
+            | Command::IncrInt16(reg)
+            | Command::IncrInt32(reg)
+            | Command::IncrInt64(reg)
+            | Command::IncrFloat32(reg)
+            | Command::IncrFloat64(reg) => f(reg, Use::Read),
+            Command::GetElementPtr(_, base, indices) => {
+                value_uses_mut(base, Use::Pointer, f);
+                for v in indices {
+                    value_uses_mut(v, Use::Read, f);
+                }
+            }
+            Command::Phi(incoming) => {
+                for (_, v) in incoming {
+                    value_uses_mut(v, Use::Read, f);
+                }
+            }
+            Command::Jump(_) => {}
+        },
+    }
+}
+
+fn value_uses_mut(value: &mut Value, kind: Use, f: &mut impl FnMut(&mut RegisterValue, Use)) {
+    match value {
+        Value::Register(reg) => f(reg, kind),
+        Value::ConstValue(obj) => {
+            if let MirageValueEnum::Register(reg) = obj.get_value_ref() {
+                let mut reg = reg.clone();
+                f(&mut reg, kind);
+                *obj = MirageObject::new(MirageValueEnum::Register(reg), obj.get_type());
+            }
+        }
+        Value::List(list) => {
+            let mut values = list.clone().into_vec();
+            for v in &mut values {
+                value_uses_mut(v, kind, f);
+            }
+            *list = List::from_vec(values);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use mirage_frontend::object::MirageTypeEnum;
+
+/// How values of Mirage types are laid out in memory on a target, which
+/// only depends on the size of an address. Structs are laid out as C does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pointer_size: u64,
+}
+
+impl Layout {
+    /// Create a new layout
+    /// # Arguments
+    /// * `pointer_size` - The size of an address in bytes
+    pub const fn new(pointer_size: u64) -> Self {
+        Self { pointer_size }
+    }
+
+    pub fn pointer_size(&self) -> u64 {
+        self.pointer_size
+    }
+
+    /// The number of bytes taken by a value of `ty` in memory
+    pub fn size_of(&self, ty: &MirageTypeEnum) -> u64 {
+        match ty {
+            MirageTypeEnum::Int8(_) | MirageTypeEnum::UInt8(_) => 1,
+            MirageTypeEnum::Int16(_) | MirageTypeEnum::UInt16(_) => 2,
+            MirageTypeEnum::Int32(_) | MirageTypeEnum::UInt32(_) | MirageTypeEnum::Float32(_) => 4,
+            MirageTypeEnum::Int64(_) | MirageTypeEnum::UInt64(_) | MirageTypeEnum::Float64(_) => 8,
+            MirageTypeEnum::Pointer(_) => self.pointer_size,
+            MirageTypeEnum::Array(a) => self.size_of(&a.element_ty()) * a.length() as u64,
+            MirageTypeEnum::Struct(s) => self.struct_layout(&s.fields).0,
+        }
+    }
+
+    pub fn align_of(&self, ty: &MirageTypeEnum) -> u64 {
+        match ty {
+            MirageTypeEnum::Array(a) => self.align_of(&a.element_ty()),
+            MirageTypeEnum::Struct(s) => s
+                .fields
+                .iter()
+                .map(|field| self.align_of(field))
+                .max()
+                .unwrap_or(1),
+            _ => self.size_of(ty),
+        }
+    }
+
+    /// The size of a struct with `fields` and the offset of every field
+    pub fn struct_layout(&self, fields: &[MirageTypeEnum]) -> (u64, Vec<u64>) {
+        let mut offsets = Vec::with_capacity(fields.len());
+        let mut size = 0;
+        let mut align = 1;
+        for field in fields {
+            size = align_to(size, self.align_of(field));
+            offsets.push(size);
+            size += self.size_of(field);
+            align = align.max(self.align_of(field));
+        }
+        (align_to(size, align), offsets)
+    }
+}
+
+/// Round `offset` up to a multiple of `align`
+pub fn align_to(offset: u64, align: u64) -> u64 {
+    offset.div_ceil(align) * align
+}
+
+/// Whether values of `ty` live in memory and are handled through their
+/// address: arrays, strings included, and structs
+pub fn is_aggregate(ty: &MirageTypeEnum) -> bool {
+    matches!(ty, MirageTypeEnum::Array(_) | MirageTypeEnum::Struct(_))
+}
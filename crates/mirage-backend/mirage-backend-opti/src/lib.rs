@@ -1,20 +1,29 @@
-// use mirage_frontend::object::statements::Statement;
-//
-// mod opti;
-// mod ozero;
-// mod oone;
-//
-// use opti::GlobalOptimizer;
-// pub use opti::OptiLevel;
-// use log::warn;
-//
-// pub fn optimize(level: OptiLevel, stmts: Vec<Statement>) -> Vec<Statement> {
-//     match level {
-//         OptiLevel::O0 => stmts,
-//         OptiLevel::O1 => oone::OptiOne::new(stmts.clone()).optimize_statements(stmts),
-//         _ => {
-//             warn!("Optimization level {} is not supported yet, falling back to O0", level.as_str());
-//             stmts
-//         }
-//     }
-// }
+use mirage_frontend::object::statements::Statement;
+
+#[cfg(test)]
+mod test;
+
+pub mod analysis;
+pub mod constant;
+pub mod ir;
+pub mod layout;
+mod opti;
+pub mod pass;
+pub mod pass_manager;
+pub mod passes;
+pub mod verify;
+
+pub use opti::OptiLevel;
+pub use pass_manager::{PassManager, PassStatistics};
+
+/// Maximum number of times the pipeline is rerun while it keeps changing the IR
+pub const MAX_ITERATIONS: usize = 4;
+
+/// Optimize `stmts` with the pipeline of `level`
+pub fn optimize(level: OptiLevel, mut stmts: Vec<Statement>) -> Vec<Statement> {
+    let mut pm = PassManager::for_level(level);
+    if !pm.is_empty() {
+        pm.run_to_fixpoint(&mut stmts, MAX_ITERATIONS);
+    }
+    stmts
+}
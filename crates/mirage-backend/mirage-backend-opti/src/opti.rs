@@ -1,11 +1,9 @@
-use mirage_frontend::object::{function::FunctionValue, label::{Command, Label, LabelBodyInstr}, statements::{Global, Statement}};
+use mirage_frontend::config::OptimizationLevel;
 
-
-
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum OptiLevel {
     /// No optimizations, also the default
+    #[default]
     O0,
     /// Optimize for size instead of speed
     O1,
@@ -32,48 +30,15 @@ impl OptiLevel {
     }
 }
 
-pub trait Optimize: Sized {
-    type Output;
-    fn optimize_level() -> OptiLevel;
-    fn optimize(&mut self, o: Self::Output) -> Self::Output;
-}
-
-
-pub trait GlobalOptimizer: Sized {
-    fn optimize_level() -> OptiLevel;
-    fn optimize_statements(&mut self, stmts: Vec<Statement>) -> Vec<Statement> {
-        stmts
-            .into_iter()
-            .map(|x| self.optimize_statement(x))
-            .collect()
-    }
-    fn optimize_statement(&mut self, stmt: Statement) -> Statement;
-    fn optimize_function(&mut self, func: FunctionValue) -> FunctionValue;
-    fn optimize_global(&mut self, global: Global) -> Global;
-    fn optimize_labels(&mut self, labels: Label) -> Label {
-        Label::new(
-            labels.name,
-            labels.flags,
-            self.optimize_label_instrs(labels.body)
-        )
-    }
-
-    fn optimize_label_instrs(&mut self, instrs: Vec<LabelBodyInstr>) -> Vec<LabelBodyInstr> {
-        instrs
-            .into_iter()
-            .map(|x| self.optimize_label_instr(x))
-            .collect()
-    }
-    fn optimize_label_instr(&mut self, instr: LabelBodyInstr) -> LabelBodyInstr;
-
-}
-
-impl<T: GlobalOptimizer> Optimize for T {
-    type Output = Vec<Statement>;
-    fn optimize_level() -> OptiLevel {
-        T::optimize_level()
-    }
-    fn optimize(&mut self, o: Self::Output) -> Self::Output {
-        self.optimize_statements(o)
+impl From<OptimizationLevel> for OptiLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::None => OptiLevel::O0,
+            OptimizationLevel::Less => OptiLevel::O1,
+            OptimizationLevel::Default => OptiLevel::O2,
+            OptimizationLevel::Medium | OptimizationLevel::Big => OptiLevel::O3,
+            OptimizationLevel::Size => OptiLevel::Os,
+            OptimizationLevel::MinSize => OptiLevel::Oz,
+        }
     }
 }
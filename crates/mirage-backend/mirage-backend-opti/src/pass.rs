@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::statements::Statement;
+
+use crate::analysis::{AnalysisId, AnalysisManager, FunctionAnalysis, ModuleAnalysis, Preserved};
+
+/// A pass transforming one function at a time
+pub trait FunctionPass {
+    fn name(&self) -> &'static str;
+
+    /// Analyses computed before the pass runs. Module analyses listed here
+    /// are the only ones available through `FunctionContext::module`.
+    fn requires(&self) -> Vec<AnalysisId> {
+        Vec::new()
+    }
+
+    fn run(&mut self, func: &mut FunctionValue, cx: &mut FunctionContext) -> Preserved;
+}
+
+/// A pass transforming the whole module
+pub trait ModulePass {
+    fn name(&self) -> &'static str;
+
+    /// Analyses computed before the pass runs. Function analyses listed
+    /// here are computed for every function of the module.
+    fn requires(&self) -> Vec<AnalysisId> {
+        Vec::new()
+    }
+
+    fn run(&mut self, stmts: &mut Vec<Statement>, cx: &mut ModuleContext) -> Preserved;
+}
+
+/// What a function pass can reach while it runs
+pub struct FunctionContext<'a> {
+    pub(crate) am: &'a mut AnalysisManager,
+    pub(crate) counters: &'a mut BTreeMap<&'static str, u64>,
+    pub(crate) pass: &'static str,
+}
+
+impl FunctionContext<'_> {
+    /// The result of `A` for `func`, computed if not cached
+    pub fn get<A: FunctionAnalysis>(&mut self, func: &FunctionValue) -> Rc<A::Result> {
+        self.am.get::<A>(func)
+    }
+
+    /// The result of the module analysis `A`.
+    /// # Panics
+    /// If the pass didn't list `A` in `requires`
+    pub fn module<A: ModuleAnalysis>(&self) -> Rc<A::Result> {
+        self.am.get_module_cached::<A>().unwrap_or_else(|| {
+            panic!(
+                "pass `{}` uses the module analysis `{}` without requiring it",
+                self.pass,
+                A::name()
+            )
+        })
+    }
+
+    /// Add `n` to the statistic `name` of the running pass
+    pub fn count(&mut self, name: &'static str, n: u64) {
+        *self.counters.entry(name).or_default() += n;
+    }
+}
+
+/// What a module pass can reach while it runs
+pub struct ModuleContext<'a> {
+    pub(crate) am: &'a mut AnalysisManager,
+    pub(crate) counters: &'a mut BTreeMap<&'static str, u64>,
+}
+
+impl ModuleContext<'_> {
+    pub fn analyses(&mut self) -> &mut AnalysisManager {
+        self.am
+    }
+
+    /// Add `n` to the statistic `name` of the running pass
+    pub fn count(&mut self, name: &'static str, n: u64) {
+        *self.counters.entry(name).or_default() += n;
+    }
+
+    /// Run a function pass over every function of `stmts` from inside a
+    /// module pass, invalidating the analyses of the functions it changes
+    pub fn run_function_pass<P: FunctionPass + ?Sized>(
+        &mut self,
+        pass: &mut P,
+        stmts: &mut [Statement],
+    ) -> Preserved {
+        let mut preserved = Preserved::All;
+        for stmt in stmts.iter_mut() {
+            if let Statement::Function(func) = stmt {
+                let mut cx = FunctionContext {
+                    am: self.am,
+                    counters: self.counters,
+                    pass: pass.name(),
+                };
+                let res = pass.run(func, &mut cx);
+                self.am.invalidate(func.get_name(), &res);
+                self.am.invalidate_module(&res);
+                preserved = preserved.intersect(res);
+            }
+        }
+        preserved
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use mirage_frontend::object::statements::Statement;
+
+use crate::analysis::{AnalysisManager, Preserved};
+use crate::opti::OptiLevel;
+use crate::pass::{FunctionContext, FunctionPass, ModuleContext, ModulePass};
+use crate::passes::{Dce, GlobalDce, Gvn, Inliner, Mem2Reg, Sccp};
+use crate::verify::verify_module;
+
+enum PassEntry {
+    Function(Box<dyn FunctionPass>),
+    Module(Box<dyn ModulePass>),
+}
+
+impl PassEntry {
+    fn name(&self) -> &'static str {
+        match self {
+            PassEntry::Function(p) => p.name(),
+            PassEntry::Module(p) => p.name(),
+        }
+    }
+}
+
+/// What a pass did over all of its runs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PassStatistics {
+    pub name: &'static str,
+    /// How many times the pass was run
+    pub runs: usize,
+    /// How many of those runs changed the IR
+    pub changes: usize,
+    pub time: Duration,
+    /// Counters reported by the pass itself
+    pub counters: BTreeMap<&'static str, u64>,
+}
+
+/// Runs a pipeline of function and module passes over Mirage IR, caching
+/// analyses between passes until they are invalidated
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<PassEntry>,
+    stats: Vec<PassStatistics>,
+    analyses: AnalysisManager,
+    verify_each: bool,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The pipeline used for `level`:
+    /// * `O0` runs nothing
+    /// * `O1` promotes memory to registers, folds constants and removes
+    ///   dead code, without inlining nor GVN
+    /// * `O2` adds inlining and GVN
+    /// * `O3` inlines bigger callees and folds constants again after GVN
+    /// * `Os` and `Oz` are `O2` inlining only the callees marked `#inline`
+    pub fn for_level(level: OptiLevel) -> Self {
+        let mut pm = Self::new();
+        match level {
+            OptiLevel::O0 => return pm,
+            OptiLevel::O1 => {}
+            OptiLevel::O2 | OptiLevel::O3 | OptiLevel::Os | OptiLevel::Oz => {
+                pm.add_module_pass(Inliner::for_level(level))
+            }
+        }
+        pm.add_function_pass(Mem2Reg::new());
+        pm.add_function_pass(Sccp::new());
+        if level != OptiLevel::O1 {
+            pm.add_function_pass(Gvn::new());
+        }
+        if level == OptiLevel::O3 {
+            pm.add_function_pass(Sccp::new());
+        }
+        pm.add_function_pass(Dce::new());
+        pm.add_module_pass(GlobalDce::new());
+        pm
+    }
+
+    pub fn add_function_pass<P: FunctionPass + 'static>(&mut self, pass: P) {
+        self.push(PassEntry::Function(Box::new(pass)));
+    }
+
+    pub fn add_module_pass<P: ModulePass + 'static>(&mut self, pass: P) {
+        self.push(PassEntry::Module(Box::new(pass)));
+    }
+
+    fn push(&mut self, entry: PassEntry) {
+        self.stats.push(PassStatistics {
+            name: entry.name(),
+            ..Default::default()
+        });
+        self.passes.push(entry);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// Verify the module after every pass which changed it
+    /// # Panics
+    /// `run` panics with the name of the pass which produced invalid IR
+    pub fn set_verify_each(&mut self, verify_each: bool) {
+        self.verify_each = verify_each;
+    }
+
+    /// The names of the passes, in the order they run
+    pub fn pipeline(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    /// Run the pipeline once over `stmts`
+    /// # Arguments
+    /// * `stmts` - The module to optimize
+    /// # Returns
+    /// What the pipeline as a whole preserved
+    pub fn run(&mut self, stmts: &mut Vec<Statement>) -> Preserved {
+        let mut preserved = Preserved::All;
+        for (pass, stats) in self.passes.iter_mut().zip(self.stats.iter_mut()) {
+            let start = Instant::now();
+            let res = match pass {
+                PassEntry::Function(pass) => {
+                    Self::run_function_pass(pass.as_mut(), stmts, &mut self.analyses, stats)
+                }
+                PassEntry::Module(pass) => {
+                    Self::run_module_pass(pass.as_mut(), stmts, &mut self.analyses, stats)
+                }
+            };
+            let elapsed = start.elapsed();
+            debug!(
+                "pass {}: {} in {:?}",
+                stats.name,
+                if res.changed() {
+                    "changed"
+                } else {
+                    "unchanged"
+                },
+                elapsed
+            );
+            stats.runs += 1;
+            stats.time += elapsed;
+            if res.changed() {
+                stats.changes += 1;
+                if self.verify_each {
+                    if let Err(errors) = verify_module(stmts) {
+                        let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+                        panic!(
+                            "pass `{}` produced invalid IR:\n{}",
+                            stats.name,
+                            errors.join("\n")
+                        );
+                    }
+                }
+            }
+            preserved = preserved.intersect(res);
+        }
+        preserved
+    }
+
+    /// Run the pipeline until it stops changing `stmts`, at most `max` times
+    /// # Returns
+    /// The number of iterations run
+    pub fn run_to_fixpoint(&mut self, stmts: &mut Vec<Statement>, max: usize) -> usize {
+        for i in 0..max {
+            if !self.run(stmts).changed() {
+                return i + 1;
+            }
+        }
+        max
+    }
+
+    fn run_function_pass(
+        pass: &mut dyn FunctionPass,
+        stmts: &mut [Statement],
+        am: &mut AnalysisManager,
+        stats: &mut PassStatistics,
+    ) -> Preserved {
+        let requires = pass.requires();
+        let mut preserved = Preserved::All;
+        for i in 0..stmts.len() {
+            if !matches!(stmts[i], Statement::Function(_)) {
+                continue;
+            }
+            // A function changed before this one may have invalidated
+            // the module analyses
+            for id in requires.iter().filter(|id| id.is_module()) {
+                id.compute_module(stmts, am);
+            }
+            let Statement::Function(func) = &mut stmts[i] else {
+                unreachable!()
+            };
+            for id in requires.iter().filter(|id| !id.is_module()) {
+                id.compute_function(func, am);
+            }
+            let mut cx = FunctionContext {
+                am,
+                counters: &mut stats.counters,
+                pass: pass.name(),
+            };
+            let res = pass.run(func, &mut cx);
+            am.invalidate(func.get_name(), &res);
+            am.invalidate_module(&res);
+            preserved = preserved.intersect(res);
+        }
+        preserved
+    }
+
+    fn run_module_pass(
+        pass: &mut dyn ModulePass,
+        stmts: &mut Vec<Statement>,
+        am: &mut AnalysisManager,
+        stats: &mut PassStatistics,
+    ) -> Preserved {
+        for id in pass.requires() {
+            if id.is_module() {
+                id.compute_module(stmts, am);
+            } else {
+                for stmt in stmts.iter() {
+                    if let Statement::Function(func) = stmt {
+                        id.compute_function(func, am);
+                    }
+                }
+            }
+        }
+        let mut cx = ModuleContext {
+            am,
+            counters: &mut stats.counters,
+        };
+        let res = pass.run(stmts, &mut cx);
+        am.invalidate_all(&res);
+        res
+    }
+
+    pub fn statistics(&self) -> &[PassStatistics] {
+        &self.stats
+    }
+
+    pub fn analyses(&self) -> &AnalysisManager {
+        &self.analyses
+    }
+
+    /// A human readable table of the statistics of every pass
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for stats in &self.stats {
+            out.push_str(&format!(
+                "{:<20} runs: {:<4} changes: {:<4} time: {:?}\n",
+                stats.name, stats.runs, stats.changes, stats.time
+            ));
+            for (name, value) in &stats.counters {
+                out.push_str(&format!("    {:<24} {}\n", name, value));
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr};
+use mirage_frontend::object::meta::Flag;
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::RegisterType;
+
+use crate::analysis::call_graph::CallGraph;
+use crate::analysis::cfg::Cfg;
+use crate::analysis::liveness::{is_local, step, Liveness};
+use crate::analysis::{AnalysisId, AnalysisManager, ModuleAnalysis, Preserved};
+use crate::ir::{for_each_register_mut, for_each_use, has_attribute, is_phi, reg_key, RegKey, Use};
+use crate::pass::{FunctionContext, FunctionPass, ModuleContext, ModulePass};
+
+/// The functions and externs marked `#pure`
+pub struct PureFunctions;
+
+impl ModuleAnalysis for PureFunctions {
+    type Result = HashSet<String>;
+
+    fn name() -> &'static str {
+        "pure-functions"
+    }
+
+    fn run(stmts: &[Statement], _am: &mut AnalysisManager) -> Self::Result {
+        stmts
+            .iter()
+            .filter_map(|s| match s {
+                Statement::External(e) if e.is_pure() => Some(e.name.clone()),
+                Statement::Function(f) if has_attribute(f, &Flag::pure()) => {
+                    Some(f.get_name().clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Whether `instr` can be dropped when the register it defines is unused
+fn is_removable(instr: &LabelBodyInstr, pure: &HashSet<String>) -> bool {
+    match instr {
+        LabelBodyInstr::Call(name, _) => pure.contains(name),
+        LabelBodyInstr::Command(cmd) => !matches!(
+            cmd,
+            Command::Store(..)
+                | Command::Free(_)
+                | Command::Ret(_)
+                | Command::Jump(_)
+                | Command::Jeq(..)
+        ),
+        LabelBodyInstr::Assign(..) => false,
+    }
+}
+
+/// Drop the blocks of `func` which can't be reached from the entry
+/// # Returns
+/// The number of labels and instructions removed
+pub(crate) fn remove_unreachable(func: &mut FunctionValue, cfg: &Cfg) -> (u64, u64) {
+    let reachable = cfg.reachable();
+    if reachable.iter().all(|r| *r) {
+        return (0, 0);
+    }
+    let mut bodies = vec![Vec::new(); func.len_labels()];
+    let mut kept = vec![false; func.len_labels()];
+    let mut instrs = 0;
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        if reachable[b] {
+            kept[block.label] = true;
+            bodies[block.label].extend(cfg.instrs(func, b).iter().cloned());
+        } else {
+            instrs += (block.end - block.start) as u64;
+        }
+    }
+    let labels = std::mem::take(func.get_labels_mut());
+    let mut removed = HashSet::new();
+    for ((mut label, body), kept) in labels.into_iter().zip(bodies).zip(kept) {
+        if kept {
+            label.body = body;
+            func.add_label(label);
+        } else {
+            removed.insert(label.name);
+        }
+    }
+    if !removed.is_empty() {
+        for instr in func
+            .get_labels_mut()
+            .iter_mut()
+            .flat_map(|l| l.body.iter_mut())
+        {
+            if let LabelBodyInstr::Assign(_, value) = instr {
+                if let LabelBodyInstr::Command(Command::Phi(incoming)) = &mut **value {
+                    incoming.retain(|(label, _)| !removed.contains(label));
+                }
+            }
+        }
+    }
+    (removed.len() as u64, instrs)
+}
+
+/// Drop the assignments whose register is never read afterwards
+/// # Returns
+/// The number of instructions removed
+fn remove_dead_assigns(func: &mut FunctionValue, cfg: &Cfg, pure: &HashSet<String>) -> u64 {
+    // Registers used as memory can be read through a pointer, so their
+    // definitions are always kept
+    let mut escaped = HashSet::new();
+    for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+        for_each_use(instr, &mut |reg, kind| {
+            if kind == Use::Address {
+                escaped.insert(reg_key(reg));
+            }
+        });
+    }
+
+    let liveness = Liveness::new(func, cfg);
+    let is_dead = |live: &HashSet<RegKey>, instr: &LabelBodyInstr| match instr {
+        LabelBodyInstr::Assign(reg, value) => {
+            let key = reg_key(reg);
+            is_local(&key)
+                && !escaped.contains(&key)
+                && !live.contains(&key)
+                && is_removable(value, pure)
+        }
+        _ => false,
+    };
+    let mut dead: HashMap<usize, HashSet<usize>> = HashMap::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let mut live = liveness.live_out(b).clone();
+        let instrs = cfg.instrs(func, b);
+        let phis = instrs.iter().take_while(|i| is_phi(i)).count();
+        for (i, instr) in instrs.iter().enumerate().skip(phis).rev() {
+            if is_dead(&live, instr) {
+                dead.entry(block.label).or_default().insert(block.start + i);
+            } else {
+                step(&mut live, instr);
+            }
+        }
+        for (i, instr) in instrs[..phis].iter().enumerate() {
+            if is_dead(&live, instr) {
+                dead.entry(block.label).or_default().insert(block.start + i);
+            }
+        }
+    }
+
+    let mut removed = 0;
+    for (l, label) in func.get_labels_mut().iter_mut().enumerate() {
+        if let Some(dead) = dead.get(&l) {
+            removed += dead.len() as u64;
+            label.body = std::mem::take(&mut label.body)
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| !dead.contains(i))
+                .map(|(_, instr)| instr)
+                .collect();
+        }
+    }
+    removed
+}
+
+/// Dead code elimination inside functions.
+///
+/// Removes the blocks which can't be reached from the entry label, then
+/// the assignments whose register is never read and whose value has no
+/// side effect. Calls are only removed when their callee is `#pure`.
+#[derive(Debug, Default)]
+pub struct Dce;
+
+impl Dce {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl FunctionPass for Dce {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn requires(&self) -> Vec<AnalysisId> {
+        vec![
+            AnalysisId::function::<Cfg>(),
+            AnalysisId::module::<PureFunctions>(),
+        ]
+    }
+
+    fn run(&mut self, func: &mut FunctionValue, cx: &mut FunctionContext) -> Preserved {
+        let pure = cx.module::<PureFunctions>();
+        let cfg = cx.get::<Cfg>(func);
+        let (labels, mut instrs) = remove_unreachable(func, &cfg);
+        let mut cfg = if labels + instrs > 0 {
+            Cfg::new(func)
+        } else {
+            (*cfg).clone()
+        };
+        loop {
+            let removed = remove_dead_assigns(func, &cfg, &pure);
+            if removed == 0 {
+                break;
+            }
+            instrs += removed;
+            cfg = Cfg::new(func);
+        }
+        if labels + instrs == 0 {
+            return Preserved::All;
+        }
+        cx.count("labels removed", labels);
+        cx.count("instructions removed", instrs);
+        Preserved::None
+    }
+}
+
+/// Dead code elimination at module scope.
+///
+/// Removes the functions marked `#internal` which no kept function calls,
+/// and the globals marked `#internal` which no function reads. The other
+/// functions and globals can be used from outside the module, so they are
+/// kept. The remaining globals are renumbered; only the internal ones are
+/// renamed after their new number.
+#[derive(Debug, Default)]
+pub struct GlobalDce;
+
+impl GlobalDce {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl ModulePass for GlobalDce {
+    fn name(&self) -> &'static str {
+        "global-dce"
+    }
+
+    fn run(&mut self, stmts: &mut Vec<Statement>, cx: &mut ModuleContext) -> Preserved {
+        let graph = cx.analyses().get_module::<CallGraph>(stmts);
+        let live: HashSet<String> = graph
+            .reachable(stmts.iter().filter_map(|s| match s {
+                Statement::Function(f) if !has_attribute(f, &Flag::internal()) => {
+                    Some(f.get_name().as_str())
+                }
+                _ => None,
+            }))
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let before = stmts.len();
+        stmts.retain(|s| match s {
+            Statement::Function(f) => live.contains(f.get_name()),
+            _ => true,
+        });
+        let functions_removed = (before - stmts.len()) as u64;
+
+        let mut used = HashSet::new();
+        for stmt in stmts.iter() {
+            if let Statement::Function(f) = stmt {
+                for instr in f.get_labels().iter().flat_map(|l| l.body.iter()) {
+                    for_each_use(instr, &mut |reg, _| {
+                        if reg.register_type == RegisterType::Global {
+                            used.insert(reg.index);
+                        }
+                    });
+                }
+            }
+        }
+        // The names of the exported globals can't change, nor be taken
+        let exported: HashSet<String> = stmts
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Global(g) if !g.flags.contains(&Flag::internal()) => {
+                    Some(g.name.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        let mut renumber = HashMap::new();
+        let mut index = 0;
+        let mut globals_removed = 0;
+        stmts.retain_mut(|s| {
+            let g = match s {
+                Statement::Global(g) => g,
+                _ => return true,
+            };
+            let old = index;
+            index += 1;
+            let internal = g.flags.contains(&Flag::internal());
+            if internal && !used.contains(&old) {
+                globals_removed += 1;
+                return false;
+            }
+            let new = renumber.len();
+            renumber.insert(old, new);
+            let name = format!("g{}", new);
+            if internal && g.name == format!("g{}", old) && !exported.contains(&name) {
+                g.name = name;
+            }
+            true
+        });
+        if globals_removed > 0 {
+            for stmt in stmts.iter_mut() {
+                if let Statement::Function(f) = stmt {
+                    for label in f.get_labels_mut() {
+                        for instr in &mut label.body {
+                            for_each_register_mut(instr, &mut |reg| {
+                                if reg.register_type == RegisterType::Global {
+                                    reg.index = renumber[&reg.index];
+                                }
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        if functions_removed + globals_removed == 0 {
+            return Preserved::All;
+        }
+        cx.count("functions removed", functions_removed);
+        cx.count("globals removed", globals_removed);
+        Preserved::None
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr};
+use mirage_frontend::object::{MirageTypeEnum, RegisterType, RegisterValue};
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::liveness::{step, Liveness};
+use crate::analysis::{AnalysisId, Preserved};
+use crate::ir::{for_each_use, is_phi, is_terminator, reg_key, RegKey, Use};
+use crate::pass::{FunctionContext, FunctionPass};
+use crate::passes::PureFunctions;
+
+/// The registers whose value may still be reachable once `instr` is done:
+/// returned, stored, copied, put in a struct, pointed to, or passed to a
+/// function which isn't `#pure` and may keep it
+fn escaping(instr: &LabelBodyInstr, pure: &HashSet<String>) -> Vec<RegKey> {
+    let cmd = match instr {
+        LabelBodyInstr::Assign(reg, value) => {
+            let mut regs = escaping(value, pure);
+            // A field or a loaded value which is an aggregate or a pointer
+            // points into the memory it was read from
+            let derived = matches!(
+                value.as_ref(),
+                LabelBodyInstr::Command(Command::Get(..) | Command::Load(..))
+            ) && (reg.ty.is_string()
+                || matches!(
+                    reg.ty,
+                    MirageTypeEnum::Array(_)
+                        | MirageTypeEnum::Struct(_)
+                        | MirageTypeEnum::Pointer(_)
+                ));
+            if derived {
+                for_each_use(value, &mut |reg, _| regs.push(reg_key(reg)));
+            }
+            return regs;
+        }
+        LabelBodyInstr::Call(name, _) if pure.contains(name) => return Vec::new(),
+        LabelBodyInstr::Call(..) => {
+            let mut regs = Vec::new();
+            for_each_use(instr, &mut |reg, _| regs.push(reg_key(reg)));
+            return regs;
+        }
+        LabelBodyInstr::Command(cmd) => cmd,
+    };
+    let mut regs = Vec::new();
+    match cmd {
+        Command::Store(..) => for_each_use(instr, &mut |reg, kind| {
+            if kind == Use::Read {
+                regs.push(reg_key(reg));
+            }
+        }),
+        Command::Const(_)
+        | Command::Ret(_)
+        | Command::Phi(_)
+        | Command::New(..)
+        | Command::Ref(_)
+        | Command::GetElementPtr(..)
+        | Command::Free(_) => for_each_use(instr, &mut |reg, _| regs.push(reg_key(reg))),
+        _ => {}
+    }
+    regs
+}
+
+/// Frees the variables (`v` registers) after their last use.
+///
+/// A `free` is added after the last instruction reading a variable, or
+/// after its assignment when it is never read. A variable still live at
+/// the end of a block but not in one of its successors is freed at the
+/// start of that successor, when the block is its only predecessor.
+///
+/// Variables which may outlive the instructions reading them are never
+/// freed: the ones returned, stored, copied, put in a struct, whose
+/// address is taken, passed to a function which isn't `#pure`, which a
+/// `get` or a `load` points into, or which the function already frees
+/// itself.
+#[derive(Debug, Default)]
+pub struct InsertFree;
+
+impl InsertFree {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl FunctionPass for InsertFree {
+    fn name(&self) -> &'static str {
+        "insert-free"
+    }
+
+    fn requires(&self) -> Vec<AnalysisId> {
+        vec![
+            AnalysisId::function::<Cfg>(),
+            AnalysisId::function::<Liveness>(),
+            AnalysisId::module::<PureFunctions>(),
+        ]
+    }
+
+    fn run(&mut self, func: &mut FunctionValue, cx: &mut FunctionContext) -> Preserved {
+        let cfg = cx.get::<Cfg>(func);
+        let liveness = cx.get::<Liveness>(func);
+        let pure = cx.module::<PureFunctions>();
+
+        let mut variables: HashMap<RegKey, RegisterValue> = HashMap::new();
+        let mut excluded: HashSet<RegKey> = HashSet::new();
+        for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+            if let LabelBodyInstr::Assign(reg, _) = instr {
+                if reg.register_type == RegisterType::Variable {
+                    variables.entry(reg_key(reg)).or_insert_with(|| reg.clone());
+                }
+            }
+            excluded.extend(escaping(instr, &pure));
+        }
+        variables.retain(|key, _| !excluded.contains(key));
+        if variables.is_empty() {
+            return Preserved::All;
+        }
+
+        // The variables to free before the instruction at each location,
+        // by label
+        let mut frees: Vec<BTreeMap<usize, Vec<RegKey>>> = vec![BTreeMap::new(); func.len_labels()];
+        let reachable = cfg.reachable();
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            if !reachable[b] {
+                continue;
+            }
+            let instrs = cfg.instrs(func, b);
+            let phis = instrs.iter().take_while(|i| is_phi(i)).count();
+            let mut live = liveness.live_out(b).clone();
+            let mut at_end = None;
+            for (i, instr) in instrs.iter().enumerate().skip(phis).rev() {
+                let after = live.clone();
+                step(&mut live, instr);
+                if is_terminator(instr) {
+                    at_end = Some(live.clone());
+                    continue;
+                }
+                let mut dead: Vec<RegKey> = Vec::new();
+                if let LabelBodyInstr::Assign(reg, _) = instr {
+                    dead.push(reg_key(reg));
+                }
+                for_each_use(instr, &mut |reg, _| dead.push(reg_key(reg)));
+                dead.retain(|key| variables.contains_key(key) && !after.contains(key));
+                if !dead.is_empty() {
+                    frees[block.label]
+                        .entry(block.start + i + 1)
+                        .or_default()
+                        .extend(dead);
+                }
+            }
+
+            let at_end = at_end.unwrap_or_else(|| liveness.live_out(b).clone());
+            for s in &cfg.succs[b] {
+                if cfg.preds[*s] != [b] {
+                    continue;
+                }
+                let dead: Vec<RegKey> = at_end
+                    .iter()
+                    .filter(|key| {
+                        variables.contains_key(key) && !liveness.live_in(*s).contains(key)
+                    })
+                    .copied()
+                    .collect();
+                if !dead.is_empty() {
+                    let target = cfg.blocks[*s];
+                    let phis = cfg
+                        .instrs(func, *s)
+                        .iter()
+                        .take_while(|i| is_phi(i))
+                        .count();
+                    frees[target.label]
+                        .entry(target.start + phis)
+                        .or_default()
+                        .extend(dead);
+                }
+            }
+        }
+
+        let mut freed = 0;
+        for (label, frees) in func.get_labels_mut().iter_mut().zip(frees) {
+            for (index, mut keys) in frees.into_iter().rev() {
+                keys.sort_unstable_by_key(|key| key.1);
+                keys.dedup();
+                freed += keys.len() as u64;
+                let regs = keys.iter().map(|key| variables[key].clone()).collect();
+                label
+                    .body
+                    .insert(index, LabelBodyInstr::Command(Command::Free(regs)));
+            }
+        }
+        if freed == 0 {
+            return Preserved::All;
+        }
+        cx.count("variables freed", freed);
+        Preserved::None
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr};
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::object::{MirageValueEnum, RegisterType, RegisterValue};
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::dominators::Dominators;
+use crate::analysis::ssa::SsaRegisters;
+use crate::analysis::{AnalysisId, Preserved};
+use crate::ir::{for_each_use, for_each_use_mut, reg_key, value_register, RegKey, Use};
+use crate::pass::{FunctionContext, FunctionPass};
+use crate::passes::PureFunctions;
+
+/// Where a pointer points, as far as it is known
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Base {
+    /// Into the register whose address was taken
+    Slot(RegKey),
+    Unknown,
+}
+
+/// The commands whose result only depends on their operands
+fn is_pure(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::AddInt8(..)
+            | Command::AddInt16(..)
+            | Command::AddInt32(..)
+            | Command::AddInt64(..)
+            | Command::AddFloat32(..)
+            | Command::AddFloat64(..)
+            | Command::SubInt8(..)
+            | Command::SubInt16(..)
+            | Command::SubInt32(..)
+            | Command::SubInt64(..)
+            | Command::SubFloat32(..)
+            | Command::SubFloat64(..)
+            | Command::IncrInt8(_)
+            | Command::IncrInt16(_)
+            | Command::IncrInt32(_)
+            | Command::IncrInt64(_)
+            | Command::IncrFloat32(_)
+            | Command::IncrFloat64(_)
+            | Command::GetElementPtr(..)
+    )
+}
+
+/// Pointers into two different slots can't alias, anything else may
+fn may_alias(a: Base, b: Base) -> bool {
+    match (a, b) {
+        (Base::Slot(a), Base::Slot(b)) => a == b,
+        _ => true,
+    }
+}
+
+/// Whether `instr` calls a function which isn't known to be `#pure`
+fn is_impure_call(instr: &LabelBodyInstr, pure: &HashSet<String>) -> bool {
+    match instr {
+        LabelBodyInstr::Assign(_, instr) => is_impure_call(instr, pure),
+        LabelBodyInstr::Call(name, _) => !pure.contains(name),
+        LabelBodyInstr::Command(_) => false,
+    }
+}
+
+struct Numbering<'a> {
+    func: &'a FunctionValue,
+    cfg: &'a Cfg,
+    dom: &'a Dominators,
+    pure: &'a HashSet<String>,
+    /// The registers which keep one value wherever they are read
+    stable: HashSet<RegKey>,
+    /// The registers whose address is taken
+    memory: HashSet<RegKey>,
+    /// The commands defining the stable registers
+    defs: HashMap<RegKey, &'a Command>,
+    /// The expressions computed in the dominators of the current block,
+    /// by their text
+    available: HashMap<String, RegisterValue>,
+    /// The registers to read instead of the redundant ones
+    replace: HashMap<RegKey, RegisterValue>,
+    merged: u64,
+    copies: u64,
+}
+
+impl<'a> Numbering<'a> {
+    fn new(
+        func: &'a FunctionValue,
+        cfg: &'a Cfg,
+        dom: &'a Dominators,
+        ssa: &HashSet<RegKey>,
+        pure: &'a HashSet<String>,
+    ) -> Self {
+        let mut stable = ssa.clone();
+        let mut memory = HashSet::new();
+        let mut assigned_args = HashSet::new();
+        let mut defs = HashMap::new();
+        for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+            if let LabelBodyInstr::Assign(reg, value) = instr {
+                if reg.register_type == RegisterType::Argument {
+                    assigned_args.insert(reg.index);
+                }
+                if let LabelBodyInstr::Command(cmd) = &**value {
+                    if ssa.contains(&reg_key(reg)) {
+                        defs.insert(reg_key(reg), cmd);
+                    }
+                }
+            }
+            for_each_use(instr, &mut |reg, kind| {
+                if kind == Use::Address {
+                    memory.insert(reg_key(reg));
+                }
+            });
+        }
+        // The arguments are stable unless the function writes them
+        stable.extend(
+            (0..func.get_type().get_args().len())
+                .filter(|i| !assigned_args.contains(i))
+                .map(|i| (RegisterType::Argument, i))
+                .filter(|key| !memory.contains(key)),
+        );
+        Self {
+            func,
+            cfg,
+            dom,
+            pure,
+            stable,
+            memory,
+            defs,
+            available: HashMap::new(),
+            replace: HashMap::new(),
+            merged: 0,
+            copies: 0,
+        }
+    }
+
+    /// Whether every register `instr` reads is stable
+    fn has_stable_operands(&self, instr: &LabelBodyInstr) -> bool {
+        let mut stable = true;
+        for_each_use(instr, &mut |reg, _| {
+            stable &= self.stable.contains(&reg_key(reg))
+        });
+        stable
+    }
+
+    fn base(&self, pointer: RegKey) -> Base {
+        match self.defs.get(&pointer) {
+            Some(Command::Ref(v)) => {
+                value_register(v).map_or(Base::Unknown, |a| Base::Slot(reg_key(a)))
+            }
+            Some(Command::GetElementPtr(_, v, _)) => {
+                value_register(v).map_or(Base::Unknown, |p| self.base(reg_key(p)))
+            }
+            _ => Base::Unknown,
+        }
+    }
+
+    /// Number the expressions of `b` and of the blocks it dominates
+    fn visit(&mut self, b: usize) {
+        let mut added = Vec::new();
+        // The loads of the block which no write may have changed since
+        let mut loads: Vec<(String, Base, RegisterValue)> = Vec::new();
+        for instr in self.cfg.instrs(self.func, b) {
+            let mut instr = instr.clone();
+            for_each_use_mut(&mut instr, &mut |reg, _| {
+                if let Some(r) = self.replace.get(&reg_key(reg)) {
+                    *reg = r.clone();
+                }
+            });
+
+            match &instr {
+                _ if is_impure_call(&instr, self.pure) => loads.clear(),
+                LabelBodyInstr::Command(Command::Store(p, _)) => {
+                    let base = self.base(reg_key(p));
+                    loads.retain(|(_, b, _)| !may_alias(base, *b));
+                }
+                LabelBodyInstr::Command(Command::Free(_)) => loads.clear(),
+                LabelBodyInstr::Assign(reg, _) if self.memory.contains(&reg_key(reg)) => {
+                    let base = Base::Slot(reg_key(reg));
+                    loads.retain(|(_, b, _)| !may_alias(base, *b));
+                }
+                _ => {}
+            }
+
+            let (reg, cmd) = match &instr {
+                LabelBodyInstr::Assign(reg, value) if self.stable.contains(&reg_key(reg)) => {
+                    match &**value {
+                        LabelBodyInstr::Command(cmd) => (reg, cmd),
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+            if !self.has_stable_operands(&instr) {
+                continue;
+            }
+            match cmd {
+                Command::Const(obj) => {
+                    if let MirageValueEnum::Register(src) = obj.get_value_ref() {
+                        self.replace.insert(reg_key(reg), src.clone());
+                        self.copies += 1;
+                    }
+                }
+                Command::Load(_, p) => {
+                    let text = cmd.to_string();
+                    match loads.iter().find(|(t, _, _)| *t == text) {
+                        Some((_, _, r)) => {
+                            self.replace.insert(reg_key(reg), r.clone());
+                            self.merged += 1;
+                        }
+                        None => {
+                            let base =
+                                value_register(p).map_or(Base::Unknown, |p| self.base(reg_key(p)));
+                            loads.push((text, base, reg.clone()));
+                        }
+                    }
+                }
+                cmd if is_pure(cmd) => {
+                    let text = cmd.to_string();
+                    match self.available.get(&text) {
+                        Some(r) => {
+                            self.replace.insert(reg_key(reg), r.clone());
+                            self.merged += 1;
+                        }
+                        None => {
+                            self.available.insert(text.clone(), reg.clone());
+                            added.push(text);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for child in self.dom.children(b).to_vec() {
+            self.visit(child);
+        }
+        for text in added {
+            self.available.remove(&text);
+        }
+    }
+}
+
+/// Global value numbering.
+///
+/// Replaces the reads of a register by the reads of an earlier register
+/// holding the same value: the copies of another register, and the pure
+/// computations (arithmetic, increments, `getelementptr`) already done by
+/// a dominating instruction with the same operands. Loads are only merged
+/// inside a block, when no store to a pointer which may alias, no call to
+/// a function not marked `#pure` and no `free` comes between them. The
+/// redundant assignments are removed.
+#[derive(Debug, Default)]
+pub struct Gvn;
+
+impl Gvn {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl FunctionPass for Gvn {
+    fn name(&self) -> &'static str {
+        "gvn"
+    }
+
+    fn requires(&self) -> Vec<AnalysisId> {
+        vec![
+            AnalysisId::function::<Cfg>(),
+            AnalysisId::function::<Dominators>(),
+            AnalysisId::function::<SsaRegisters>(),
+            AnalysisId::module::<PureFunctions>(),
+        ]
+    }
+
+    fn run(&mut self, func: &mut FunctionValue, cx: &mut FunctionContext) -> Preserved {
+        let pure = cx.module::<PureFunctions>();
+        let cfg = cx.get::<Cfg>(func);
+        let dom = cx.get::<Dominators>(func);
+        let ssa = cx.get::<SsaRegisters>(func);
+        if cfg.is_empty() {
+            return Preserved::All;
+        }
+
+        let mut numbering = Numbering::new(func, &cfg, &dom, &ssa, &pure);
+        numbering.visit(0);
+        let Numbering {
+            replace,
+            merged,
+            copies,
+            ..
+        } = numbering;
+        if replace.is_empty() {
+            return Preserved::All;
+        }
+
+        for label in func.get_labels_mut() {
+            label.body.retain(|instr| match instr {
+                LabelBodyInstr::Assign(reg, _) => !replace.contains_key(&reg_key(reg)),
+                _ => true,
+            });
+            for instr in &mut label.body {
+                for_each_use_mut(instr, &mut |reg, _| {
+                    if let Some(r) = replace.get(&reg_key(reg)) {
+                        *reg = r.clone();
+                    }
+                });
+            }
+        }
+        cx.count("expressions merged", merged);
+        cx.count("copies propagated", copies);
+        Preserved::None
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::{Flag, Flags};
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::{RegisterType, RegisterValue};
+
+use crate::analysis::call_graph::CallGraph;
+use crate::analysis::Preserved;
+use crate::ir::{
+    copy, for_each_register_mut, has_attribute, is_terminator, next_index, phi_incoming,
+};
+use crate::pass::{ModuleContext, ModulePass};
+use crate::OptiLevel;
+
+/// Inlines calls to the functions of the module.
+///
+/// A call is inlined when the callee is marked `#inline`, or when its
+/// body has at most `threshold` instructions. Callees marked `#noinline`
+/// and recursive functions are never inlined. Functions are visited
+/// callees first, so a callee is inlined with its own calls already
+/// inlined.
+#[derive(Debug, Clone)]
+pub struct Inliner {
+    threshold: usize,
+}
+
+impl Inliner {
+    pub fn new(threshold: usize) -> Self {
+        Self { threshold }
+    }
+
+    /// The inliner used for `level`. Size levels only inline the callees
+    /// marked `#inline`, since any body is bigger than a call.
+    pub fn for_level(level: OptiLevel) -> Self {
+        Self::new(match level {
+            OptiLevel::O0 | OptiLevel::Os | OptiLevel::Oz => 0,
+            OptiLevel::O1 => 4,
+            OptiLevel::O2 => 32,
+            OptiLevel::O3 => 128,
+        })
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    fn should_inline(&self, callee: &FunctionValue, graph: &CallGraph) -> bool {
+        if graph.is_recursive(callee.get_name())
+            || callee.len_labels() == 0
+            || has_attribute(callee, &Flag::noinline())
+            || !can_inline(callee)
+        {
+            return false;
+        }
+        has_attribute(callee, &Flag::inline()) || cost(callee) <= self.threshold
+    }
+}
+
+impl Default for Inliner {
+    fn default() -> Self {
+        Self::for_level(OptiLevel::O2)
+    }
+}
+
+fn cost(func: &FunctionValue) -> usize {
+    func.get_labels().iter().map(|l| l.body.len()).sum()
+}
+
+/// Whether every value the function returns can be copied in a register
+fn can_inline(func: &FunctionValue) -> bool {
+    func.get_labels()
+        .iter()
+        .flat_map(|l| l.body.iter())
+        .all(|instr| !matches!(instr, LabelBodyInstr::Command(Command::Ret(Value::List(_)))))
+}
+
+/// The callee of `instr`, and the register its result goes to
+fn call_site(instr: &LabelBodyInstr) -> Option<(&String, &Vec<Value>, Option<&RegisterValue>)> {
+    match instr {
+        LabelBodyInstr::Call(name, args) => Some((name, args, None)),
+        LabelBodyInstr::Assign(reg, instr) => match &**instr {
+            LabelBodyInstr::Call(name, args) => Some((name, args, Some(reg))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn fresh_label(used: &mut HashSet<String>, base: String) -> String {
+    let mut name = base.clone();
+    let mut n = 0;
+    while used.contains(&name) {
+        n += 1;
+        name = format!("{}.{}", base, n);
+    }
+    used.insert(name.clone());
+    name
+}
+
+/// Inline the call at `caller.labels[label][index]`
+fn inline_call(caller: &mut FunctionValue, label: usize, index: usize, callee: &FunctionValue) {
+    let r_base = next_index(caller, RegisterType::Register);
+    let v_base = next_index(caller, RegisterType::Variable);
+    let arg_base = v_base + next_index(callee, RegisterType::Variable);
+
+    let mut used: HashSet<String> = caller.get_labels().iter().map(|l| l.name.clone()).collect();
+    let renamed: HashMap<&String, String> = callee
+        .get_labels()
+        .iter()
+        .map(|l| {
+            let name = fresh_label(&mut used, format!("{}.{}", callee.get_name(), l.name));
+            (&l.name, name)
+        })
+        .collect();
+    let cont = fresh_label(
+        &mut used,
+        format!("{}.cont", caller.get_labels()[label].name),
+    );
+
+    let labels = caller.get_labels_mut();
+    let mut suffix = labels[label].body.split_off(index);
+    let call = suffix.remove(0);
+    let (_, args, result) = call_site(&call).unwrap();
+    let arg_types = callee.get_type().get_args();
+    for (i, (arg, ty)) in args.iter().zip(arg_types).enumerate() {
+        let reg = RegisterValue::new(arg_base + i, RegisterType::Variable, ty.clone());
+        labels[label].body.push(copy(reg, arg));
+    }
+    let entry = &renamed[&callee.get_labels()[0].name];
+    labels[label]
+        .body
+        .push(LabelBodyInstr::Command(Command::Jump(entry.clone())));
+
+    let mut inlined = Vec::with_capacity(callee.len_labels() + 1);
+    for (i, l) in callee.get_labels().iter().enumerate() {
+        let mut body = Vec::with_capacity(l.body.len());
+        for instr in &l.body {
+            let mut instr = instr.clone();
+            for_each_register_mut(&mut instr, &mut |reg| match reg.register_type {
+                RegisterType::Register => reg.index += r_base,
+                RegisterType::Variable => reg.index += v_base,
+                RegisterType::Argument => {
+                    reg.register_type = RegisterType::Variable;
+                    reg.index += arg_base;
+                }
+                RegisterType::Global => {}
+            });
+            match &mut instr {
+                LabelBodyInstr::Command(Command::Ret(value)) => {
+                    if let Some(result) = result {
+                        body.push(copy(result.clone(), value));
+                    }
+                    body.push(LabelBodyInstr::Command(Command::Jump(cont.clone())));
+                    break;
+                }
+                LabelBodyInstr::Command(Command::Jump(target) | Command::Jeq(target, _, _)) => {
+                    *target = renamed[target].clone();
+                }
+                _ => rename_phi_labels(&mut instr, |l| renamed.get(l).cloned()),
+            }
+            body.push(instr);
+        }
+        // The function attributes stay on the callee
+        let flags = if i == 0 {
+            Flags::new(vec![])
+        } else {
+            l.flags.clone()
+        };
+        inlined.push(Label::new(renamed[&l.name].clone(), flags, body));
+    }
+    inlined.push(Label::new(cont.clone(), Flags::new(vec![]), suffix));
+    let caller_label = labels[label].name.clone();
+    for instr in labels.iter_mut().flat_map(|l| l.body.iter_mut()) {
+        rename_phi_labels(instr, |l| (*l == caller_label).then(|| cont.clone()));
+    }
+    labels.splice(label + 1..label + 1, inlined);
+}
+
+/// Rename the predecessors of a phi with `f`, when it returns a new name
+fn rename_phi_labels(instr: &mut LabelBodyInstr, f: impl Fn(&String) -> Option<String>) {
+    if let LabelBodyInstr::Assign(_, value) = instr {
+        if let LabelBodyInstr::Command(Command::Phi(incoming)) = &mut **value {
+            for (label, _) in incoming {
+                if let Some(name) = f(label) {
+                    *label = name;
+                }
+            }
+        }
+    }
+}
+
+/// Whether a phi of `func` has `label` as predecessor
+fn is_phi_predecessor(func: &FunctionValue, label: &str) -> bool {
+    func.get_labels()
+        .iter()
+        .flat_map(|l| l.body.iter())
+        .filter_map(phi_incoming)
+        .any(|incoming| incoming.iter().any(|(l, _)| l == label))
+}
+
+impl ModulePass for Inliner {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn run(&mut self, stmts: &mut Vec<Statement>, cx: &mut ModuleContext) -> Preserved {
+        let mut functions: HashMap<String, FunctionValue> = stmts
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Function(f) => Some((f.get_name().clone(), f.clone())),
+                _ => None,
+            })
+            .collect();
+        let graph = cx.analyses().get_module::<CallGraph>(stmts);
+
+        let mut inlined = 0;
+        for name in graph.post_order() {
+            let Some(func) = functions.get(name) else {
+                continue;
+            };
+            let mut func = func.clone();
+            let mut changed = false;
+            let mut label = 0;
+            while label < func.len_labels() {
+                let body = &func.get_labels()[label].body;
+                // The branches after the call move to the continuation, so
+                // phis couldn't tell them apart from the ones before it
+                let phi_predecessor = is_phi_predecessor(&func, &func.get_labels()[label].name);
+                let site = body.iter().enumerate().find_map(|(i, instr)| {
+                    if phi_predecessor && body[..i].iter().any(is_terminator) {
+                        return None;
+                    }
+                    let (callee, args, _) = call_site(instr)?;
+                    let callee = functions.get(callee)?;
+                    (args.len() == callee.get_type().get_args().len()
+                        && self.should_inline(callee, &graph))
+                    .then_some((i, callee))
+                });
+                match site {
+                    Some((index, callee)) => {
+                        let skip = callee.len_labels();
+                        inline_call(&mut func, label, index, callee);
+                        // Resume at the continuation, the calls left in the
+                        // callee were already rejected
+                        label += skip + 1;
+                        inlined += 1;
+                        changed = true;
+                    }
+                    None => label += 1,
+                }
+            }
+            if changed {
+                functions.insert(name.to_string(), func);
+            }
+        }
+        if inlined == 0 {
+            return Preserved::All;
+        }
+
+        for stmt in stmts.iter_mut() {
+            if let Statement::Function(f) = stmt {
+                if let Some(func) = functions.get(f.get_name()) {
+                    *f = func.clone();
+                }
+            }
+        }
+        cx.count("calls inlined", inlined);
+        Preserved::None
+    }
+}
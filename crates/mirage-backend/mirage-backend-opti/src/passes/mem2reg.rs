@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::Flags;
+use mirage_frontend::object::{MirageTypeEnum, RegisterType, RegisterValue};
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::dominators::{DominanceFrontiers, Dominators};
+use crate::analysis::{AnalysisId, Preserved};
+use crate::ir::{
+    copy, for_each_use, for_each_use_mut, is_terminator, next_index, phi_incoming, reg_key,
+    value_register, RegKey, Use,
+};
+use crate::pass::{FunctionContext, FunctionPass};
+use crate::passes::dce::remove_unreachable;
+
+/// A register whose address is taken, and the registers holding that
+/// address
+#[derive(Debug, Clone)]
+struct Slot {
+    reg: RegKey,
+    ty: MirageTypeEnum,
+    pointers: HashSet<RegKey>,
+}
+
+/// How an instruction touches memory
+enum Access<'a> {
+    /// `p = ref a`
+    Ref(RegKey, RegKey),
+    /// `store p, v`
+    Store(RegKey),
+    /// `x = load ty, p`
+    Load(&'a RegisterValue, &'a MirageTypeEnum, RegKey),
+    Other,
+}
+
+fn access(instr: &LabelBodyInstr) -> Access<'_> {
+    match instr {
+        LabelBodyInstr::Assign(reg, value) => match &**value {
+            LabelBodyInstr::Command(Command::Ref(v)) => match value_register(v) {
+                Some(a) => Access::Ref(reg_key(reg), reg_key(a)),
+                None => Access::Other,
+            },
+            LabelBodyInstr::Command(Command::Load(ty, v)) => match value_register(v) {
+                Some(p) => Access::Load(reg, ty, reg_key(p)),
+                None => Access::Other,
+            },
+            _ => Access::Other,
+        },
+        LabelBodyInstr::Command(Command::Store(p, _)) => Access::Store(reg_key(p)),
+        _ => Access::Other,
+    }
+}
+
+fn is_local(key: &RegKey) -> bool {
+    matches!(key.0, RegisterType::Register | RegisterType::Variable)
+}
+
+/// The slots which are only read and written, directly or through
+/// pointers which don't escape
+fn find_slots(func: &FunctionValue) -> Vec<Slot> {
+    let instrs = || func.get_labels().iter().flat_map(|l| l.body.iter());
+
+    // The slots each register is assigned the address of, and the
+    // registers which are also assigned something else
+    let mut refs: HashMap<RegKey, HashSet<RegKey>> = HashMap::new();
+    let mut other = HashSet::new();
+    let mut types: Vec<(RegKey, MirageTypeEnum)> = Vec::new();
+    for instr in instrs() {
+        if let LabelBodyInstr::Assign(reg, value) = instr {
+            match &**value {
+                LabelBodyInstr::Command(Command::Ref(v)) => match value_register(v) {
+                    Some(a) if is_local(&reg_key(a)) => {
+                        refs.entry(reg_key(reg)).or_default().insert(reg_key(a));
+                        if !types.iter().any(|(k, _)| *k == reg_key(a)) {
+                            types.push((reg_key(a), a.get_type()));
+                        }
+                    }
+                    _ => {
+                        other.insert(reg_key(reg));
+                    }
+                },
+                _ => {
+                    other.insert(reg_key(reg));
+                }
+            }
+        }
+    }
+
+    let mut rejected: HashSet<RegKey> = HashSet::new();
+    let mut slots: Vec<Slot> = types
+        .into_iter()
+        .map(|(reg, ty)| Slot {
+            reg,
+            ty,
+            pointers: HashSet::new(),
+        })
+        .collect();
+    for (p, targets) in &refs {
+        if targets.len() > 1 || other.contains(p) {
+            // A pointer which can point somewhere else
+            rejected.extend(targets.iter().copied());
+        } else if let Some(slot) = slots.iter_mut().find(|s| targets.contains(&s.reg)) {
+            slot.pointers.insert(*p);
+        }
+    }
+
+    let slot_of = |key: &RegKey| slots.iter().find(|s| s.pointers.contains(key));
+    for instr in instrs() {
+        let access = access(instr);
+        let in_phi = phi_incoming(instr).is_some();
+        for_each_use(instr, &mut |reg, kind| {
+            let key = reg_key(reg);
+            if let Some(slot) = slot_of(&key) {
+                let ok = kind == Use::Pointer
+                    && match &access {
+                        Access::Store(p) => *p == key,
+                        Access::Load(_, ty, p) => *p == key && **ty == slot.ty,
+                        _ => false,
+                    };
+                if !ok {
+                    rejected.insert(slot.reg);
+                }
+            }
+            if let Some(slot) = slots.iter().find(|s| s.reg == key) {
+                let ok = match kind {
+                    Use::Read => !in_phi,
+                    Use::Pointer => false,
+                    Use::Address => {
+                        matches!(access, Access::Ref(p, a) if a == key && slot.pointers.contains(&p))
+                    }
+                };
+                if !ok {
+                    rejected.insert(slot.reg);
+                }
+            }
+        });
+    }
+    // A slot holding the address of another one
+    for slot in &slots {
+        if slots.iter().any(|s| s.pointers.contains(&slot.reg)) {
+            rejected.insert(slot.reg);
+        }
+    }
+    slots.retain(|s| !rejected.contains(&s.reg));
+    slots
+}
+
+/// Give every block its own label, so a phi can name each predecessor
+fn split_blocks(func: &mut FunctionValue) {
+    let mut used: HashSet<String> = func.get_labels().iter().map(|l| l.name.clone()).collect();
+    let mut segments: HashMap<String, Vec<String>> = HashMap::new();
+    let mut labels = Vec::new();
+    for label in std::mem::take(func.get_labels_mut()) {
+        let mut body = Vec::new();
+        let mut names = vec![label.name.clone()];
+        let mut current = Label::new(label.name.clone(), label.flags.clone(), vec![]);
+        let len = label.body.len();
+        for (i, instr) in label.body.into_iter().enumerate() {
+            let split = is_terminator(&instr) && i + 1 < len;
+            body.push(instr);
+            if split {
+                current.body = std::mem::take(&mut body);
+                labels.push(current);
+                let mut n = names.len();
+                let mut name = format!("{}.{}", label.name, n);
+                while used.contains(&name) {
+                    n += 1;
+                    name = format!("{}.{}", label.name, n);
+                }
+                used.insert(name.clone());
+                names.push(name.clone());
+                current = Label::new(name, Flags::new(vec![]), vec![]);
+            }
+        }
+        current.body = body;
+        labels.push(current);
+        if names.len() > 1 {
+            segments.insert(label.name, names);
+        }
+    }
+    for label in labels {
+        func.add_label(label);
+    }
+    if segments.is_empty() {
+        return;
+    }
+
+    // A phi coming from a split label now comes from the parts of it
+    // which branch to the phi
+    let cfg = Cfg::new(func);
+    for b in 0..func.len_labels() {
+        for instr in &mut func.get_labels_mut()[b].body {
+            if let LabelBodyInstr::Assign(_, value) = instr {
+                if let LabelBodyInstr::Command(Command::Phi(incoming)) = &mut **value {
+                    let mut new = Vec::new();
+                    for (label, value) in incoming.drain(..) {
+                        let parts: Vec<&String> = segments
+                            .get(&label)
+                            .map(|names| {
+                                names
+                                    .iter()
+                                    .filter(|n| {
+                                        cfg.label_block(n)
+                                            .is_some_and(|p| cfg.succs[p].contains(&b))
+                                    })
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        if parts.is_empty() {
+                            new.push((label, value));
+                        } else {
+                            new.extend(parts.into_iter().map(|n| (n.clone(), value.clone())));
+                        }
+                    }
+                    *incoming = new;
+                }
+            }
+        }
+    }
+}
+
+/// Rewrite `func` with `slot` promoted to registers. Every block must be
+/// a label of its own and reachable.
+/// # Returns
+/// The number of phis inserted, or `None` if the slot may be read before
+/// being written
+fn promote(func: &mut FunctionValue, slot: &Slot) -> Option<usize> {
+    let cfg = Cfg::new(func);
+    let dom = Dominators::new(&cfg);
+    let df = DominanceFrontiers::new(&cfg, &dom);
+    let is_pointer = |key: &RegKey| slot.pointers.contains(key);
+
+    // Where the slot is written, and where it is read before being written
+    let mut writes = vec![false; cfg.len()];
+    let mut exposed = vec![false; cfg.len()];
+    for (b, label) in func.get_labels().iter().enumerate() {
+        for instr in &label.body {
+            let mut reads = matches!(access(instr), Access::Load(_, _, p) if is_pointer(&p));
+            for_each_use(instr, &mut |reg, kind| {
+                reads |= kind == Use::Read && reg_key(reg) == slot.reg;
+            });
+            if reads && !writes[b] {
+                exposed[b] = true;
+            }
+            writes[b] |= match (access(instr), instr) {
+                (Access::Store(p), _) => is_pointer(&p),
+                (_, LabelBodyInstr::Assign(reg, _)) => reg_key(reg) == slot.reg,
+                _ => false,
+            };
+        }
+    }
+    let mut live_in = exposed.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..cfg.len()).rev() {
+            let live = exposed[b] || (!writes[b] && cfg.succs[b].iter().any(|s| live_in[*s]));
+            if live != live_in[b] {
+                live_in[b] = live;
+                changed = true;
+            }
+        }
+    }
+    let phis: Vec<usize> = df
+        .iterated((0..cfg.len()).filter(|b| writes[*b]))
+        .into_iter()
+        .filter(|b| live_in[*b])
+        .collect();
+
+    let mut next = next_index(func, RegisterType::Register);
+    let mut fresh = || {
+        let reg = RegisterValue::new(next, RegisterType::Register, slot.ty.clone());
+        next += 1;
+        reg
+    };
+    let phi_regs: HashMap<usize, RegisterValue> = phis.iter().map(|b| (*b, fresh())).collect();
+
+    let mut bodies: Vec<Vec<LabelBodyInstr>> = vec![Vec::new(); cfg.len()];
+    let mut end: Vec<Option<RegisterValue>> = vec![None; cfg.len()];
+    for b in dom.pre_order() {
+        let mut current = match (phi_regs.get(&b), dom.idom(b)) {
+            (Some(reg), _) => Some(reg.clone()),
+            (None, Some(idom)) => end[idom].clone(),
+            (None, None) => None,
+        };
+        let body = &mut bodies[b];
+        if let Some(reg) = phi_regs.get(&b) {
+            body.push(LabelBodyInstr::Assign(
+                reg.clone(),
+                Box::new(LabelBodyInstr::Command(Command::Phi(vec![]))),
+            ));
+        }
+        for instr in &func.get_labels()[b].body {
+            match access(instr) {
+                Access::Ref(p, _) if is_pointer(&p) => continue,
+                _ => {}
+            }
+            let mut new = match access(instr) {
+                Access::Load(x, _, p) if is_pointer(&p) => {
+                    copy(x.clone(), &Value::Register(current.clone()?))
+                }
+                _ => instr.clone(),
+            };
+            let mut undefined = false;
+            for_each_use_mut(&mut new, &mut |reg, kind| {
+                if kind == Use::Read && reg_key(reg) == slot.reg {
+                    match &current {
+                        Some(c) => *reg = c.clone(),
+                        None => undefined = true,
+                    }
+                }
+            });
+            if undefined {
+                return None;
+            }
+            match &mut new {
+                LabelBodyInstr::Command(Command::Store(p, value)) if is_pointer(&reg_key(p)) => {
+                    let reg = fresh();
+                    body.push(copy(reg.clone(), value));
+                    current = Some(reg);
+                }
+                LabelBodyInstr::Assign(reg, _) if reg_key(reg) == slot.reg => {
+                    *reg = fresh();
+                    current = Some(reg.clone());
+                    body.push(new);
+                }
+                _ => body.push(new),
+            }
+        }
+        end[b] = current;
+    }
+
+    for (b, reg) in &phi_regs {
+        let mut incoming = Vec::new();
+        for p in &cfg.preds[*b] {
+            let value = end[*p].clone()?;
+            let label = func.get_labels()[*p].name.clone();
+            incoming.push((label, Value::Register(value)));
+        }
+        bodies[*b][0] = LabelBodyInstr::Assign(
+            reg.clone(),
+            Box::new(LabelBodyInstr::Command(Command::Phi(incoming))),
+        );
+    }
+    for (label, body) in func.get_labels_mut().iter_mut().zip(bodies) {
+        label.body = body;
+    }
+    Some(phis.len())
+}
+
+/// Promotes stack slots to registers.
+///
+/// A slot is a register whose address is taken with `ref`, and which is
+/// then only accessed with `store` and `load` through that address, or
+/// directly. Each write becomes a new register, each read uses the
+/// register holding the last write, and phis join the writes where
+/// control flow meets, at the dominance frontiers. Slots which may be
+/// read before being written are left alone.
+#[derive(Debug, Default)]
+pub struct Mem2Reg;
+
+impl Mem2Reg {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl FunctionPass for Mem2Reg {
+    fn name(&self) -> &'static str {
+        "mem2reg"
+    }
+
+    fn requires(&self) -> Vec<AnalysisId> {
+        vec![AnalysisId::function::<Cfg>()]
+    }
+
+    fn run(&mut self, func: &mut FunctionValue, cx: &mut FunctionContext) -> Preserved {
+        let slots = find_slots(func);
+        if slots.is_empty() {
+            return Preserved::All;
+        }
+        let mut work = func.clone();
+        remove_unreachable(&mut work, &cx.get::<Cfg>(func));
+        split_blocks(&mut work);
+
+        let mut promoted = 0;
+        let mut phis = 0;
+        for slot in &slots {
+            let mut attempt = work.clone();
+            if let Some(n) = promote(&mut attempt, slot) {
+                work = attempt;
+                promoted += 1;
+                phis += n;
+            }
+        }
+        if promoted == 0 {
+            return Preserved::All;
+        }
+        *func = work;
+        cx.count("slots promoted", promoted);
+        cx.count("phis inserted", phis as u64);
+        Preserved::None
+    }
+}
@@ -0,0 +1,13 @@
+mod dce;
+mod free;
+mod gvn;
+mod inline;
+mod mem2reg;
+mod sccp;
+
+pub use dce::{Dce, GlobalDce, PureFunctions};
+pub use free::InsertFree;
+pub use gvn::Gvn;
+pub use inline::Inliner;
+pub use mem2reg::Mem2Reg;
+pub use sccp::{ConstGlobals, Sccp};
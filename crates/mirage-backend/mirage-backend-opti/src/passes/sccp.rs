@@ -0,0 +1,394 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_frontend::object::function::FunctionValue;
+use mirage_frontend::object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::{MirageObject, RegisterType, RegisterValue};
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::{AnalysisId, AnalysisManager, ModuleAnalysis, Preserved};
+use crate::constant::Constant;
+use crate::ir::{
+    for_each_read_operand_mut, for_each_use, is_phi, reg_key, value_register, RegKey, Use,
+};
+use crate::pass::{FunctionContext, FunctionPass};
+
+/// The values of the globals marked `#const`, by global register index
+pub struct ConstGlobals;
+
+impl ModuleAnalysis for ConstGlobals {
+    type Result = HashMap<usize, Constant>;
+
+    fn name() -> &'static str {
+        "const-globals"
+    }
+
+    fn run(stmts: &[Statement], _am: &mut AnalysisManager) -> Self::Result {
+        stmts
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Global(g) => Some(g),
+                _ => None,
+            })
+            .enumerate()
+            .filter(|(_, g)| g.is_const())
+            .filter_map(|(i, g)| Constant::from_value(g.value.get_value_ref()).map(|c| (i, c)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lattice {
+    /// No definition has been seen yet
+    Undef,
+    Const(Constant),
+    /// The register can hold more than one value
+    Over,
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Undef, x) | (x, Lattice::Undef) => x,
+            (Lattice::Const(a), Lattice::Const(b)) if a == b => self,
+            _ => Lattice::Over,
+        }
+    }
+}
+
+type State = HashMap<RegKey, Lattice>;
+
+struct Solver<'a> {
+    globals: &'a HashMap<usize, Constant>,
+    /// Registers used as memory, whose value can change behind our back
+    escaped: HashSet<RegKey>,
+}
+
+impl Solver<'_> {
+    fn lookup(&self, state: &State, reg: &RegisterValue) -> Lattice {
+        match reg.register_type {
+            RegisterType::Argument => Lattice::Over,
+            RegisterType::Global => self
+                .globals
+                .get(&reg.index)
+                .map_or(Lattice::Over, |c| Lattice::Const(*c)),
+            _ if self.escaped.contains(&reg_key(reg)) => Lattice::Over,
+            _ => state.get(&reg_key(reg)).copied().unwrap_or(Lattice::Undef),
+        }
+    }
+
+    fn eval_value(&self, state: &State, value: &Value) -> Lattice {
+        if let Some(reg) = value_register(value) {
+            return self.lookup(state, reg);
+        }
+        match value {
+            Value::ConstValue(obj) => {
+                Constant::from_value(obj.get_value_ref()).map_or(Lattice::Over, Lattice::Const)
+            }
+            _ => Lattice::Over,
+        }
+    }
+
+    fn eval_binary(
+        &self,
+        state: &State,
+        lhs: &Value,
+        rhs: &Value,
+        bits: usize,
+        float: bool,
+        op: fn(Constant, Constant) -> Option<Constant>,
+    ) -> Lattice {
+        match (self.eval_value(state, lhs), self.eval_value(state, rhs)) {
+            (Lattice::Over, _) | (_, Lattice::Over) => Lattice::Over,
+            (Lattice::Const(a), Lattice::Const(b)) => {
+                if a.bits() != bits || a.is_float() != float {
+                    return Lattice::Over;
+                }
+                op(a, b).map_or(Lattice::Over, Lattice::Const)
+            }
+            _ => Lattice::Undef,
+        }
+    }
+
+    fn eval_incr(&self, state: &State, reg: &RegisterValue) -> Lattice {
+        match self.lookup(state, reg) {
+            Lattice::Const(c) => Lattice::Const(c.incr()),
+            l => l,
+        }
+    }
+
+    fn eval(&self, state: &State, instr: &LabelBodyInstr) -> Lattice {
+        let cmd = match instr {
+            LabelBodyInstr::Command(cmd) => cmd,
+            _ => return Lattice::Over,
+        };
+        match cmd {
+            Command::Const(obj) => self.eval_value(state, &Value::ConstValue(obj.clone())),
+            Command::AddInt8(a, b) => {
+                self.eval_binary(state, a, b, 8, false, Constant::wrapping_add)
+            }
+            Command::AddInt16(a, b) => {
+                self.eval_binary(state, a, b, 16, false, Constant::wrapping_add)
+            }
+            Command::AddInt32(a, b) => {
+                self.eval_binary(state, a, b, 32, false, Constant::wrapping_add)
+            }
+            Command::AddInt64(a, b) => {
+                self.eval_binary(state, a, b, 64, false, Constant::wrapping_add)
+            }
+            Command::AddFloat32(a, b) => {
+                self.eval_binary(state, a, b, 32, true, Constant::wrapping_add)
+            }
+            Command::AddFloat64(a, b) => {
+                self.eval_binary(state, a, b, 64, true, Constant::wrapping_add)
+            }
+            Command::SubInt8(a, b) => {
+                self.eval_binary(state, a, b, 8, false, Constant::wrapping_sub)
+            }
+            Command::SubInt16(a, b) => {
+                self.eval_binary(state, a, b, 16, false, Constant::wrapping_sub)
+            }
+            Command::SubInt32(a, b) => {
+                self.eval_binary(state, a, b, 32, false, Constant::wrapping_sub)
+            }
+            Command::SubInt64(a, b) => {
+                self.eval_binary(state, a, b, 64, false, Constant::wrapping_sub)
+            }
+            Command::SubFloat32(a, b) => {
+                self.eval_binary(state, a, b, 32, true, Constant::wrapping_sub)
+            }
+            Command::SubFloat64(a, b) => {
+                self.eval_binary(state, a, b, 64, true, Constant::wrapping_sub)
+            }
+            Command::IncrInt8(r)
+            | Command::IncrInt16(r)
+            | Command::IncrInt32(r)
+            | Command::IncrInt64(r)
+            | Command::IncrFloat32(r)
+            | Command::IncrFloat64(r) => self.eval_incr(state, r),
+            Command::Phi(incoming) => incoming.iter().fold(Lattice::Undef, |l, (_, v)| {
+                l.meet(self.eval_value(state, v))
+            }),
+            _ => Lattice::Over,
+        }
+    }
+
+    /// Apply `instr` to `state`. The phis of a block all read `entry`, the
+    /// state the block starts with.
+    fn transfer(&self, state: &mut State, entry: &State, instr: &LabelBodyInstr) {
+        if let LabelBodyInstr::Assign(reg, value) = instr {
+            let value = if is_phi(instr) {
+                self.eval(entry, value)
+            } else {
+                self.eval(state, value)
+            };
+            state.insert(reg_key(reg), value);
+        }
+    }
+
+    /// Whether a `jeq` is taken: `Some(true)` if always, `Some(false)` if
+    /// never, `None` if it can go both ways
+    fn branch(&self, state: &State, lhs: &Value, rhs: &Value) -> Option<Option<bool>> {
+        match (self.eval_value(state, lhs), self.eval_value(state, rhs)) {
+            (Lattice::Const(a), Lattice::Const(b)) => Some(a.jeq(b)),
+            (Lattice::Over, _) | (_, Lattice::Over) => Some(None),
+            _ => None,
+        }
+    }
+
+    /// The successors of `b` which can be taken from `state`
+    fn feasible(&self, cfg: &Cfg, func: &FunctionValue, b: usize, state: &State) -> Vec<usize> {
+        let succs = &cfg.succs[b];
+        match cfg.instrs(func, b).last() {
+            Some(LabelBodyInstr::Command(Command::Jeq(target, lhs, rhs))) => {
+                let target = cfg.label_block(target);
+                let next = Some(b + 1).filter(|n| succs.contains(n));
+                match self.branch(state, lhs, rhs) {
+                    None => vec![],
+                    Some(Some(true)) => target.into_iter().collect(),
+                    Some(Some(false)) => next.into_iter().collect(),
+                    Some(None) => succs.clone(),
+                }
+            }
+            _ => succs.clone(),
+        }
+    }
+
+    /// The state at the start of every executable block
+    fn solve(&self, cfg: &Cfg, func: &FunctionValue) -> Vec<Option<State>> {
+        let mut input: Vec<Option<State>> = vec![None; cfg.len()];
+        let mut output: Vec<Option<State>> = vec![None; cfg.len()];
+        let mut edges = HashSet::new();
+        let mut worklist = vec![0];
+        while let Some(b) = worklist.pop() {
+            let mut state = State::new();
+            for p in &cfg.preds[b] {
+                if !edges.contains(&(*p, b)) {
+                    continue;
+                }
+                for (reg, value) in output[*p].iter().flatten() {
+                    let old = state.get(reg).copied().unwrap_or(Lattice::Undef);
+                    state.insert(*reg, old.meet(*value));
+                }
+            }
+            input[b] = Some(state.clone());
+            let entry = state.clone();
+            for instr in cfg.instrs(func, b) {
+                self.transfer(&mut state, &entry, instr);
+            }
+            let changed = output[b].as_ref() != Some(&state);
+            let feasible = self.feasible(cfg, func, b, &state);
+            output[b] = Some(state);
+            for s in feasible {
+                if edges.insert((b, s)) || changed {
+                    worklist.push(s);
+                }
+            }
+        }
+        input
+    }
+}
+
+/// What the rewrite of one block turned its terminator into
+enum Branch {
+    Kept,
+    Taken(String),
+    Removed,
+}
+
+/// Sparse conditional constant propagation.
+///
+/// Registers whose value is known are replaced by constants where they
+/// are read, arithmetic on constants is folded with wraparound, `jeq` on
+/// constants becomes a `jump` (or disappears), and globals marked
+/// `#const` are replaced by their value. Blocks found unreachable are
+/// left for dead code elimination.
+#[derive(Debug, Default)]
+pub struct Sccp;
+
+impl Sccp {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl FunctionPass for Sccp {
+    fn name(&self) -> &'static str {
+        "sccp"
+    }
+
+    fn requires(&self) -> Vec<AnalysisId> {
+        vec![
+            AnalysisId::function::<Cfg>(),
+            AnalysisId::module::<ConstGlobals>(),
+        ]
+    }
+
+    fn run(&mut self, func: &mut FunctionValue, cx: &mut FunctionContext) -> Preserved {
+        let cfg = cx.get::<Cfg>(func);
+        let globals = cx.module::<ConstGlobals>();
+        if cfg.is_empty() {
+            return Preserved::All;
+        }
+        let mut escaped = HashSet::new();
+        for label in func.get_labels() {
+            for instr in &label.body {
+                for_each_use(instr, &mut |reg, kind| {
+                    if kind == Use::Address {
+                        escaped.insert(reg_key(reg));
+                    }
+                });
+            }
+        }
+        let solver = Solver {
+            globals: &globals,
+            escaped,
+        };
+        let input = solver.solve(&cfg, func);
+
+        let mut replaced = 0;
+        let mut folded = 0;
+        let mut branches = 0;
+        let mut bodies: Vec<Vec<LabelBodyInstr>> = vec![Vec::new(); func.len_labels()];
+        let mut dropped_label = None;
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            if dropped_label == Some(block.label) {
+                continue;
+            }
+            dropped_label = None;
+            let instrs = cfg.instrs(func, b);
+            let mut state = match &input[b] {
+                Some(state) => state.clone(),
+                None => {
+                    bodies[block.label].extend(instrs.iter().cloned());
+                    continue;
+                }
+            };
+            let entry = state.clone();
+            let mut branch = Branch::Kept;
+            for instr in instrs {
+                let mut new = instr.clone();
+                let operands = if is_phi(instr) { &entry } else { &state };
+                for_each_read_operand_mut(&mut new, &mut |value| {
+                    let reg = match value_register(value) {
+                        Some(reg) => reg,
+                        None => return,
+                    };
+                    if let Lattice::Const(c) = solver.lookup(operands, reg) {
+                        let c = c.to_value();
+                        if c.get_type() == reg.get_type() {
+                            *value = Value::ConstValue(MirageObject::from(c));
+                            replaced += 1;
+                        }
+                    }
+                });
+                solver.transfer(&mut state, &entry, instr);
+                if let LabelBodyInstr::Assign(reg, value) = &mut new {
+                    let is_call = matches!(**value, LabelBodyInstr::Call(..));
+                    if let (false, Some(Lattice::Const(c))) = (is_call, state.get(&reg_key(reg))) {
+                        let c = MirageObject::from(c.to_value());
+                        let folded_value = LabelBodyInstr::Command(Command::Const(c.clone()));
+                        if c.get_type() == reg.get_type() && **value != folded_value {
+                            **value = folded_value;
+                            folded += 1;
+                        }
+                    }
+                }
+                if let LabelBodyInstr::Command(Command::Jeq(target, lhs, rhs)) = &new {
+                    match solver.branch(&state, lhs, rhs) {
+                        Some(Some(true)) => branch = Branch::Taken(target.clone()),
+                        Some(Some(false)) => branch = Branch::Removed,
+                        _ => {}
+                    }
+                }
+                match branch {
+                    Branch::Kept => bodies[block.label].push(new),
+                    Branch::Taken(ref target) => {
+                        bodies[block.label]
+                            .push(LabelBodyInstr::Command(Command::Jump(target.clone())));
+                        // The rest of the label was only reachable by
+                        // falling through the `jeq`
+                        dropped_label = Some(block.label);
+                        branches += 1;
+                    }
+                    Branch::Removed => branches += 1,
+                }
+            }
+        }
+
+        if replaced + folded + branches == 0 {
+            return Preserved::All;
+        }
+        for (label, body) in func.get_labels_mut().iter_mut().zip(bodies) {
+            label.body = body;
+        }
+        cx.count("operands replaced", replaced);
+        cx.count("instructions folded", folded);
+        cx.count("branches folded", branches);
+        if branches == 0 {
+            Preserved::Only(vec![AnalysisId::function::<Cfg>()])
+        } else {
+            Preserved::None
+        }
+    }
+}
@@ -0,0 +1,1641 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use mirage_frontend::builder::Builder;
+use mirage_frontend::module::Module;
+use mirage_frontend::object::function::{FunctionType, FunctionValue};
+use mirage_frontend::object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend::object::meta::{Flag, Flags};
+use mirage_frontend::object::statements::{External, Statement};
+use mirage_frontend::object::stringify::Stringify;
+use mirage_frontend::object::{
+    MirageObject, MirageTypeEnum, MirageValueEnum, PointerValue, RegisterType, RegisterValue,
+};
+use mirage_frontend::parser::parse;
+
+use crate::analysis::call_graph::{CallGraph, Effects};
+use crate::analysis::cfg::Cfg;
+use crate::analysis::dominators::{DominanceFrontiers, Dominators, PostDominators};
+use crate::analysis::liveness::Liveness;
+use crate::analysis::loops::Loops;
+use crate::analysis::ssa::SsaRegisters;
+use crate::analysis::use_def::{Location, UseDef};
+use crate::analysis::{AnalysisId, AnalysisManager, FunctionAnalysis, ModuleAnalysis, Preserved};
+use crate::constant::Constant;
+use crate::layout::Layout;
+use crate::pass::{FunctionContext, FunctionPass, ModuleContext, ModulePass};
+use crate::passes::{Dce, GlobalDce, Gvn, Inliner, InsertFree, Mem2Reg, Sccp};
+use crate::verify::{verify_module, VerifyError};
+use crate::{optimize, OptiLevel, PassManager};
+
+fn add_module(names: &[&str]) -> Vec<Statement> {
+    let mut builder = Builder::new(Module::new("test".to_string()));
+    for name in names {
+        let mut f = FunctionType::new(
+            vec![
+                MirageTypeEnum::type_int32().into(),
+                MirageTypeEnum::type_int32().into(),
+            ],
+            MirageTypeEnum::type_int32().into(),
+            false,
+        )
+        .fn_value(name.to_string());
+        let mut entry = builder.new_basic_block("entry");
+        let a = f.get_nth_arg(0).unwrap().expect_int_value().unwrap();
+        let b = f.get_nth_arg(1).unwrap().expect_int_value().unwrap();
+        let r = entry.build_int_add(a, b).unwrap();
+        entry.build_ret(r).unwrap();
+        f.add_label(entry.build());
+        builder.build_function(f);
+    }
+    builder.asts
+}
+
+thread_local! {
+    static LABEL_RUNS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The names of the labels of a function
+struct LabelNames;
+
+impl FunctionAnalysis for LabelNames {
+    type Result = Vec<String>;
+
+    fn name() -> &'static str {
+        "label-names"
+    }
+
+    fn run(func: &FunctionValue, _am: &mut AnalysisManager) -> Self::Result {
+        LABEL_RUNS.with(|c| c.set(c.get() + 1));
+        func.get_labels().iter().map(|l| l.name.clone()).collect()
+    }
+}
+
+/// The number of functions of the module
+struct FunctionCount;
+
+impl ModuleAnalysis for FunctionCount {
+    type Result = usize;
+
+    fn name() -> &'static str {
+        "function-count"
+    }
+
+    fn run(stmts: &[Statement], _am: &mut AnalysisManager) -> Self::Result {
+        stmts.iter().filter(|s| s.is_function()).count()
+    }
+}
+
+/// Renames the labels it sees to `<name>.r`, once
+struct Rename {
+    preserve_names: bool,
+}
+
+impl FunctionPass for Rename {
+    fn name(&self) -> &'static str {
+        "rename"
+    }
+
+    fn requires(&self) -> Vec<AnalysisId> {
+        vec![AnalysisId::function::<LabelNames>()]
+    }
+
+    fn run(&mut self, func: &mut FunctionValue, cx: &mut FunctionContext) -> Preserved {
+        let names = cx.get::<LabelNames>(func);
+        if names.iter().all(|n| n.ends_with(".r")) {
+            return Preserved::All;
+        }
+        for label in func.get_labels_mut() {
+            label.name.push_str(".r");
+            cx.count("renamed", 1);
+        }
+        if self.preserve_names {
+            Preserved::Only(vec![])
+        } else {
+            Preserved::None
+        }
+    }
+}
+
+/// Reads the module analysis it requires
+struct UsesCount {
+    seen: Rc<Cell<usize>>,
+    declare: bool,
+}
+
+impl FunctionPass for UsesCount {
+    fn name(&self) -> &'static str {
+        "uses-count"
+    }
+
+    fn requires(&self) -> Vec<AnalysisId> {
+        if self.declare {
+            vec![AnalysisId::module::<FunctionCount>()]
+        } else {
+            vec![]
+        }
+    }
+
+    fn run(&mut self, _func: &mut FunctionValue, cx: &mut FunctionContext) -> Preserved {
+        self.seen.set(*cx.module::<FunctionCount>());
+        Preserved::All
+    }
+}
+
+/// The names of the labels of every function of the module
+struct ModuleLabels;
+
+impl ModuleAnalysis for ModuleLabels {
+    type Result = Vec<String>;
+
+    fn name() -> &'static str {
+        "module-labels"
+    }
+
+    fn run(stmts: &[Statement], _am: &mut AnalysisManager) -> Self::Result {
+        stmts
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Function(f) => Some(f.get_labels()),
+                _ => None,
+            })
+            .flatten()
+            .map(|l| l.name.clone())
+            .collect()
+    }
+}
+
+/// Records the labels of the module, then renames those of the function
+struct RenameAfterModule {
+    seen: Rc<RefCell<Vec<Vec<String>>>>,
+}
+
+impl FunctionPass for RenameAfterModule {
+    fn name(&self) -> &'static str {
+        "rename-after-module"
+    }
+
+    fn requires(&self) -> Vec<AnalysisId> {
+        vec![AnalysisId::module::<ModuleLabels>()]
+    }
+
+    fn run(&mut self, func: &mut FunctionValue, cx: &mut FunctionContext) -> Preserved {
+        self.seen
+            .borrow_mut()
+            .push(cx.module::<ModuleLabels>().to_vec());
+        for label in func.get_labels_mut() {
+            label.name.push_str(".r");
+        }
+        Preserved::None
+    }
+}
+
+/// Drops every function but the first
+struct KeepFirst;
+
+impl ModulePass for KeepFirst {
+    fn name(&self) -> &'static str {
+        "keep-first"
+    }
+
+    fn run(&mut self, stmts: &mut Vec<Statement>, cx: &mut ModuleContext) -> Preserved {
+        let mut seen = false;
+        let before = stmts.len();
+        stmts.retain(|s| {
+            if !s.is_function() {
+                return true;
+            }
+            let keep = !seen;
+            seen = true;
+            keep
+        });
+        cx.count("removed", (before - stmts.len()) as u64);
+        if before == stmts.len() {
+            Preserved::All
+        } else {
+            Preserved::None
+        }
+    }
+}
+
+#[test]
+fn test_analysis_cache_and_invalidation() {
+    LABEL_RUNS.with(|c| c.set(0));
+    let mut stmts = add_module(&["add"]);
+    let mut pm = PassManager::new();
+    pm.add_function_pass(Rename {
+        preserve_names: false,
+    });
+    assert!(pm.run(&mut stmts).changed());
+    // Computed by `requires`, then served from the cache inside the pass
+    assert_eq!(LABEL_RUNS.with(|c| c.get()), 1);
+    let counters = pm.analyses().counters()["label-names"];
+    assert_eq!((counters.computed, counters.cached), (1, 1));
+
+    // The rename invalidated the result, so it is recomputed
+    assert!(!pm.run(&mut stmts).changed());
+    assert_eq!(LABEL_RUNS.with(|c| c.get()), 2);
+
+    // Nothing changed, so the result stays cached
+    assert!(!pm.run(&mut stmts).changed());
+    assert_eq!(LABEL_RUNS.with(|c| c.get()), 2);
+}
+
+#[test]
+fn test_preserved() {
+    let id = AnalysisId::function::<LabelNames>();
+    assert!(Preserved::All.preserves(&id));
+    assert!(!Preserved::None.preserves(&id));
+    assert!(Preserved::Only(vec![id]).preserves(&id));
+    assert_eq!(
+        Preserved::All.intersect(Preserved::Only(vec![id])),
+        Preserved::Only(vec![id])
+    );
+    assert_eq!(
+        Preserved::Only(vec![id]).intersect(Preserved::Only(vec![])),
+        Preserved::Only(vec![])
+    );
+    assert_eq!(Preserved::None.intersect(Preserved::All), Preserved::None);
+}
+
+#[test]
+fn test_statistics() {
+    let mut stmts = add_module(&["a", "b", "c"]);
+    let mut pm = PassManager::new();
+    pm.add_function_pass(Rename {
+        preserve_names: true,
+    });
+    pm.add_module_pass(KeepFirst);
+    assert_eq!(pm.pipeline(), vec!["rename", "keep-first"]);
+    assert_eq!(pm.run_to_fixpoint(&mut stmts, 10), 2);
+
+    let stats = pm.statistics();
+    assert_eq!((stats[0].runs, stats[0].changes), (2, 1));
+    assert_eq!(stats[0].counters["renamed"], 3);
+    assert_eq!((stats[1].runs, stats[1].changes), (2, 1));
+    assert_eq!(stats[1].counters["removed"], 2);
+    assert!(pm.report().contains("keep-first"));
+    assert_eq!(stmts.iter().filter(|s| s.is_function()).count(), 1);
+}
+
+#[test]
+fn test_module_analysis_in_function_pass() {
+    let seen = Rc::new(Cell::new(0));
+    let mut stmts = add_module(&["a", "b"]);
+    let mut pm = PassManager::new();
+    pm.add_function_pass(UsesCount {
+        seen: seen.clone(),
+        declare: true,
+    });
+    pm.run(&mut stmts);
+    assert_eq!(seen.get(), 2);
+}
+
+#[test]
+fn test_module_analysis_invalidated_per_function() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut stmts = add_module(&["a", "b"]);
+    let mut pm = PassManager::new();
+    pm.add_function_pass(RenameAfterModule { seen: seen.clone() });
+    pm.run(&mut stmts);
+    // `b` sees the labels `a` renamed
+    assert_eq!(
+        *seen.borrow(),
+        vec![
+            vec!["entry".to_string(), "entry".to_string()],
+            vec!["entry.r".to_string(), "entry".to_string()],
+        ]
+    );
+    assert_eq!(pm.analyses().counters()["module-labels"].computed, 2);
+}
+
+#[test]
+#[should_panic(expected = "without requiring it")]
+fn test_undeclared_module_analysis() {
+    let mut stmts = add_module(&["a"]);
+    let mut pm = PassManager::new();
+    pm.add_function_pass(UsesCount {
+        seen: Rc::new(Cell::new(0)),
+        declare: false,
+    });
+    pm.run(&mut stmts);
+}
+
+#[test]
+fn test_level_pipelines() {
+    assert!(PassManager::for_level(OptiLevel::O0).is_empty());
+    let stmts = add_module(&["add"]);
+    assert_eq!(optimize(OptiLevel::O0, stmts.clone()), stmts);
+}
+
+fn print(stmts: &[Statement]) -> String {
+    stmts
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn i32_ty() -> MirageTypeEnum {
+    MirageTypeEnum::type_int32().into()
+}
+
+fn reg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Register, ty)
+}
+
+fn arg(index: usize, ty: MirageTypeEnum) -> RegisterValue {
+    RegisterValue::new(index, RegisterType::Argument, ty)
+}
+
+fn val(reg: &RegisterValue) -> Value {
+    Value::Register(reg.clone())
+}
+
+fn cst(value: MirageValueEnum) -> Value {
+    Value::ConstValue(MirageObject::from(value))
+}
+
+fn i32_val(v: i32) -> Value {
+    cst(MirageTypeEnum::type_int32().const_value(v).to_value_enum())
+}
+
+fn assign(reg: &RegisterValue, cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(reg.clone(), Box::new(LabelBodyInstr::Command(cmd)))
+}
+
+fn cmd(cmd: Command) -> LabelBodyInstr {
+    LabelBodyInstr::Command(cmd)
+}
+
+fn label(name: &str, body: Vec<LabelBodyInstr>) -> Label {
+    Label::new(name.to_string(), Flags::new(vec![]), body)
+}
+
+fn function(name: &str, args: Vec<MirageTypeEnum>, labels: Vec<Label>) -> Statement {
+    let mut f = FunctionType::new(args, i32_ty(), false).fn_value(name.to_string());
+    for l in labels {
+        f.add_label(l);
+    }
+    Statement::Function(f)
+}
+
+fn run_sccp(stmts: &mut Vec<Statement>) -> Preserved {
+    let mut pm = PassManager::new();
+    pm.add_function_pass(Sccp::new());
+    pm.run(stmts)
+}
+
+#[test]
+fn test_constant_arithmetic() {
+    assert_eq!(
+        Constant::Int8(127).wrapping_add(Constant::Int8(1)),
+        Some(Constant::Int8(-128))
+    );
+    assert_eq!(
+        Constant::UInt8(0).wrapping_sub(Constant::UInt8(1)),
+        Some(Constant::UInt8(255))
+    );
+    assert_eq!(Constant::UInt64(u64::MAX).incr(), Constant::UInt64(0));
+    assert_eq!(
+        Constant::Float32(0.5).wrapping_add(Constant::Float32(0.25)),
+        Some(Constant::Float32(0.75))
+    );
+    assert_eq!(Constant::Int32(1).wrapping_add(Constant::Int64(1)), None);
+    assert_ne!(Constant::Float64(0.0), Constant::Float64(-0.0));
+    assert_eq!(
+        Constant::Float64(0.0).jeq(Constant::Float64(-0.0)),
+        Some(true)
+    );
+    assert_eq!(
+        Constant::Float64(f64::NAN).jeq(Constant::Float64(f64::NAN)),
+        Some(false)
+    );
+    assert_eq!(Constant::Int8(1).jeq(Constant::UInt8(1)), None);
+}
+
+#[test]
+fn test_cfg() {
+    let a = arg(0, i32_ty());
+    let stmt = function(
+        "f",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    cmd(Command::Jeq("exit".to_string(), val(&a), i32_val(0))),
+                    cmd(Command::Jump("loop".to_string())),
+                    cmd(Command::Ret(i32_val(2))),
+                ],
+            ),
+            label(
+                "loop",
+                vec![cmd(Command::Jeq("loop".to_string(), val(&a), i32_val(1)))],
+            ),
+            label("exit", vec![cmd(Command::Ret(i32_val(0)))]),
+        ],
+    );
+    let f = match &stmt {
+        Statement::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let cfg = Cfg::new(f);
+    // entry is split after its jeq and its jump
+    assert_eq!(cfg.len(), 5);
+    assert_eq!(
+        cfg.succs,
+        vec![vec![4, 1], vec![3], vec![], vec![3, 4], vec![]]
+    );
+    assert_eq!(cfg.preds[3], vec![1, 3]);
+    assert_eq!(cfg.reachable(), vec![true, true, false, true, true]);
+    assert_eq!(cfg.reverse_post_order()[0], 0);
+
+    let dom = Dominators::new(&cfg);
+    assert_eq!(
+        (0..5).map(|b| dom.idom(b)).collect::<Vec<_>>(),
+        vec![None, Some(0), None, Some(1), Some(0)]
+    );
+    assert!(dom.dominates(1, 3) && !dom.dominates(3, 4) && !dom.dominates(0, 2));
+    assert_eq!(dom.pre_order(), vec![0, 1, 3, 4]);
+    let df = DominanceFrontiers::new(&cfg, &dom);
+    assert_eq!(df.frontier(1), &[4]);
+    assert_eq!(df.frontier(3), &[3, 4]);
+    assert_eq!(df.iterated([1]), vec![4]);
+    assert_eq!(df.iterated([3]), vec![3, 4]);
+
+    let pdom = PostDominators::new(&cfg);
+    assert_eq!(
+        (0..5).map(|b| pdom.ipdom(b)).collect::<Vec<_>>(),
+        vec![Some(4), Some(3), None, Some(4), None]
+    );
+    let loops = Loops::new(&cfg, &dom);
+    assert_eq!(loops.loops().len(), 1);
+    assert_eq!(loops.loops()[0].blocks, vec![3]);
+    assert!(loops.is_header(3) && loops.depth(1) == 0);
+}
+
+#[test]
+fn test_loops() {
+    let a = arg(0, i32_ty());
+    let jeq = |target: &str, v| cmd(Command::Jeq(target.to_string(), val(&a), i32_val(v)));
+    let jump = |target: &str| cmd(Command::Jump(target.to_string()));
+    let stmt = function(
+        "f",
+        vec![i32_ty()],
+        vec![
+            label("entry", vec![jump("outer")]),
+            label("outer", vec![jeq("exit", 0)]),
+            label("inner", vec![jeq("inner", 1)]),
+            label("latch", vec![jump("outer")]),
+            label("exit", vec![jeq("spin", 2), cmd(Command::Ret(i32_val(0)))]),
+            label("spin", vec![jump("spin")]),
+        ],
+    );
+    let f = match &stmt {
+        Statement::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let mut am = AnalysisManager::new();
+    let loops = am.get::<Loops>(f);
+    assert_eq!(
+        (0..7).map(|b| loops.depth(b)).collect::<Vec<_>>(),
+        vec![0, 1, 2, 1, 0, 0, 1]
+    );
+    let inner = loops.loop_of(2).unwrap();
+    let outer = &loops.loops()[inner.parent.unwrap()];
+    assert_eq!((inner.header, outer.header), (2, 1));
+    assert_eq!(outer.blocks, vec![1, 2, 3]);
+    assert_eq!(outer.latches, vec![3]);
+    assert!(loops.loop_of(6).unwrap().parent.is_none());
+
+    // spin never reaches the exit
+    let pdom = am.get::<PostDominators>(f);
+    assert!(!pdom.reaches_exit(6) && pdom.ipdom(6).is_none());
+    assert_eq!(
+        (0..6).map(|b| pdom.ipdom(b)).collect::<Vec<_>>(),
+        vec![Some(1), Some(4), Some(3), Some(1), Some(5), None]
+    );
+    assert!(pdom.post_dominates(4, 0) && !pdom.post_dominates(2, 1));
+}
+
+#[test]
+fn test_sccp_folds_arithmetic() {
+    let r0 = reg(0, i32_ty());
+    let r1 = reg(1, i32_ty());
+    let r2 = reg(2, i32_ty());
+    let mut stmts = vec![function(
+        "f",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign(
+                    &r0,
+                    Command::Const(MirageObject::from(
+                        MirageTypeEnum::type_int32()
+                            .const_value(i32::MAX)
+                            .to_value_enum(),
+                    )),
+                ),
+                assign(&r1, Command::AddInt32(val(&r0), i32_val(1))),
+                assign(&r2, Command::IncrInt32(r1.clone())),
+                cmd(Command::Ret(val(&r2))),
+            ],
+        )],
+    )];
+    assert_eq!(
+        print(&stmts),
+        "f() -> @int32\n\
+         entry: \n\
+         \tr0 = @int32 2147483647\n\
+         \tr1 = add_i32 r0, @int32 1\n\
+         \tr2 = incr_i32 r1\n\
+         \tret r2"
+    );
+    assert!(run_sccp(&mut stmts).changed());
+    assert_eq!(
+        print(&stmts),
+        "f() -> @int32\n\
+         entry: \n\
+         \tr0 = @int32 2147483647\n\
+         \tr1 = @int32 -2147483648\n\
+         \tr2 = @int32 -2147483647\n\
+         \tret @int32 -2147483647"
+    );
+    assert!(!run_sccp(&mut stmts).changed());
+}
+
+#[test]
+fn test_sccp_folds_branches() {
+    let a = arg(0, i32_ty());
+    let r0 = reg(0, i32_ty());
+    let r1 = reg(1, i32_ty());
+    let mut stmts = vec![function(
+        "f",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    assign(&r0, Command::SubInt32(i32_val(3), i32_val(1))),
+                    cmd(Command::Jeq("two".to_string(), val(&r0), i32_val(2))),
+                    cmd(Command::Ret(val(&a))),
+                ],
+            ),
+            label(
+                "two",
+                vec![
+                    cmd(Command::Jeq("other".to_string(), val(&r0), i32_val(1))),
+                    assign(&r1, Command::AddInt32(val(&r0), val(&a))),
+                    cmd(Command::Ret(val(&r1))),
+                ],
+            ),
+            label("other", vec![cmd(Command::Ret(val(&r0)))]),
+        ],
+    )];
+    assert!(run_sccp(&mut stmts).changed());
+    // `other` is unreachable, so its use of r0 is left alone
+    assert_eq!(
+        print(&stmts),
+        "f(@int32) -> @int32\n\
+         entry: \n\
+         \tr0 = @int32 2\n\
+         \tjump two\n\
+         two: \n\
+         \tr1 = add_i32 @int32 2, arg0\n\
+         \tret r1\n\
+         other: \n\
+         \tret r0"
+    );
+}
+
+#[test]
+fn test_sccp_loop() {
+    // r0 = 0; loop: r0 = incr r0; jeq exit, r0, 10; jump loop
+    let r0 = reg(0, i32_ty());
+    let r1 = reg(1, i32_ty());
+    let mut stmts = vec![function(
+        "f",
+        vec![],
+        vec![
+            label(
+                "entry",
+                vec![assign(
+                    &r0,
+                    Command::Const(MirageObject::from(
+                        MirageTypeEnum::type_int32().const_value(0).to_value_enum(),
+                    )),
+                )],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(
+                        &r1,
+                        Command::Const(MirageObject::from(
+                            MirageTypeEnum::type_int32().const_value(5).to_value_enum(),
+                        )),
+                    ),
+                    assign(&r0, Command::IncrInt32(r0.clone())),
+                    cmd(Command::Jeq("exit".to_string(), val(&r0), i32_val(10))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "exit",
+                vec![
+                    assign(&r0, Command::AddInt32(val(&r0), val(&r1))),
+                    cmd(Command::Ret(val(&r0))),
+                ],
+            ),
+        ],
+    )];
+    let before = print(&stmts);
+    assert!(run_sccp(&mut stmts).changed());
+    // r0 varies around the loop; only r1 is known in `exit`
+    assert_eq!(
+        print(&stmts),
+        before.replace("add_i32 r0, r1", "add_i32 r0, @int32 5")
+    );
+}
+
+#[test]
+fn test_sccp_const_globals() {
+    let mut builder = Builder::new(Module::new("test".to_string()));
+    let g0 = builder.build_const_global(MirageObject::from(
+        MirageTypeEnum::type_int64().const_value(40).to_value_enum(),
+    ));
+    let g1 = builder.build_global(MirageObject::from(
+        MirageTypeEnum::type_int64().const_value(1).to_value_enum(),
+    ));
+    let g0 = g0.expect_register_value().unwrap();
+    let g1 = g1.expect_register_value().unwrap();
+    let i64_ty: MirageTypeEnum = MirageTypeEnum::type_int64().into();
+    let r0 = reg(0, i64_ty.clone());
+    let r1 = reg(1, i64_ty.clone());
+    let mut f = FunctionType::new(vec![], i64_ty, false).fn_value("f".to_string());
+    f.add_label(label(
+        "entry",
+        vec![
+            assign(&r0, Command::IncrInt64(g0)),
+            assign(&r1, Command::AddInt64(val(&r0), val(&g1))),
+            cmd(Command::Ret(val(&r1))),
+        ],
+    ));
+    builder.build_function(f);
+    let mut stmts = builder.asts;
+    assert!(print(&stmts).contains("global g0 #const = @int64 40\nglobal g1 = @int64 1"));
+    assert!(run_sccp(&mut stmts).changed());
+    assert!(print(&stmts).ends_with(
+        "entry: \n\
+         \tr0 = @int64 41\n\
+         \tr1 = add_i64 @int64 41, g1\n\
+         \tret r1"
+    ));
+}
+
+#[test]
+fn test_sccp_in_pipeline() {
+    let stmts = add_module(&["add"]);
+    assert_eq!(optimize(OptiLevel::O2, stmts.clone()), stmts);
+    assert_eq!(
+        PassManager::for_level(OptiLevel::O1).pipeline(),
+        vec!["mem2reg", "sccp", "dce", "global-dce"]
+    );
+    assert_eq!(
+        PassManager::for_level(OptiLevel::O2).pipeline(),
+        vec!["inline", "mem2reg", "sccp", "gvn", "dce", "global-dce"]
+    );
+    assert_eq!(
+        PassManager::for_level(OptiLevel::O3).pipeline(),
+        vec![
+            "inline",
+            "mem2reg",
+            "sccp",
+            "gvn",
+            "sccp",
+            "dce",
+            "global-dce"
+        ]
+    );
+}
+
+fn extern_fn(name: &str, flags: Vec<Flag>) -> Statement {
+    Statement::External(External::with_flags(
+        name.to_string(),
+        Flags::new(flags),
+        FunctionType::new(vec![i32_ty()], i32_ty(), false),
+    ))
+}
+
+fn call(reg: &RegisterValue, name: &str, args: Vec<Value>) -> LabelBodyInstr {
+    LabelBodyInstr::Assign(
+        reg.clone(),
+        Box::new(LabelBodyInstr::Call(name.to_string(), args)),
+    )
+}
+
+#[test]
+fn test_dce() {
+    let a = arg(0, i32_ty());
+    let r0 = reg(0, i32_ty());
+    let r1 = reg(1, i32_ty());
+    let r2 = reg(2, i32_ty());
+    let r3 = reg(3, i32_ty());
+    let mut stmts = vec![
+        extern_fn("log", vec![]),
+        extern_fn("abs", vec![Flag::pure()]),
+        function(
+            "f",
+            vec![i32_ty()],
+            vec![
+                label(
+                    "entry",
+                    vec![
+                        assign(&r0, Command::AddInt32(val(&a), i32_val(1))),
+                        assign(&r1, Command::AddInt32(val(&r0), i32_val(2))),
+                        call(&r2, "abs", vec![val(&a)]),
+                        call(&r3, "log", vec![val(&a)]),
+                        assign(&r0, Command::SubInt32(val(&a), i32_val(1))),
+                        cmd(Command::Jump("end".to_string())),
+                        assign(&r1, Command::AddInt32(val(&a), val(&a))),
+                    ],
+                ),
+                label("dead", vec![cmd(Command::Ret(val(&r1)))]),
+                label("end", vec![cmd(Command::Ret(val(&r0)))]),
+            ],
+        ),
+    ];
+    let mut pm = PassManager::new();
+    pm.set_verify_each(true);
+    pm.add_function_pass(Dce::new());
+    assert!(pm.run(&mut stmts).changed());
+    // The first r0 is overwritten before being read, and r1 only feeds it
+    assert!(print(&stmts).ends_with(
+        "entry: \n\
+         \tr3 = log { arg0 }\n\
+         \tr0 = sub_i32 arg0, @int32 1\n\
+         \tjump end\n\
+         end: \n\
+         \tret r0"
+    ));
+    let stats = &pm.statistics()[0];
+    assert_eq!(stats.counters["labels removed"], 1);
+    assert_eq!(stats.counters["instructions removed"], 5);
+    assert!(!pm.run(&mut stmts).changed());
+}
+
+#[test]
+fn test_global_dce() {
+    let mut builder = Builder::new(Module::new("test".to_string()));
+    for v in [1, 2, 3, 4] {
+        builder.build_global(MirageObject::from(
+            MirageTypeEnum::type_int32().const_value(v).to_value_enum(),
+        ));
+    }
+    let mut stmts = builder.asts;
+    // g1 is exported, the others are internal
+    for stmt in &mut stmts {
+        if let Statement::Global(g) = stmt {
+            if g.name != "g1" {
+                g.flags = Flags::new(vec![Flag::internal()]);
+            }
+        }
+    }
+    let g2 = RegisterValue::new(2, RegisterType::Global, i32_ty());
+    let r0 = reg(0, i32_ty());
+    let internal = |name: &str, body| {
+        let mut f = function(name, vec![], vec![label("entry", body)]);
+        if let Statement::Function(f) = &mut f {
+            f.get_labels_mut()[0].flags = Flags::new(vec![Flag::internal()]);
+        }
+        f
+    };
+    stmts.push(function(
+        "main",
+        vec![],
+        vec![label(
+            "entry",
+            vec![call(&r0, "used", vec![]), cmd(Command::Ret(val(&r0)))],
+        )],
+    ));
+    stmts.push(internal(
+        "used",
+        vec![
+            assign(&r0, Command::IncrInt32(g2.clone())),
+            cmd(Command::Ret(val(&r0))),
+        ],
+    ));
+    stmts.push(internal(
+        "unused",
+        vec![call(&r0, "unused", vec![]), cmd(Command::Ret(val(&r0)))],
+    ));
+    let mut pm = PassManager::new();
+    pm.set_verify_each(true);
+    pm.add_module_pass(GlobalDce::new());
+    assert!(pm.run(&mut stmts).changed());
+    // The unused internal g0 and g3 are removed. g1 keeps its name although
+    // it is now the first global, and g2 can't take it
+    let out = print(&stmts);
+    assert!(
+        out.starts_with("module test;\nglobal g1 = @int32 2\nglobal g2 #internal = @int32 3\nmain")
+    );
+    assert!(out.contains("r0 = incr_i32 g1"));
+    assert!(!out.contains("unused"));
+    assert_eq!(pm.statistics()[0].counters["globals removed"], 2);
+    assert_eq!(verify_module(&stmts), Ok(()));
+}
+
+#[test]
+fn test_verify() {
+    let r0 = reg(0, i32_ty());
+    let stmts = vec![
+        extern_fn("log", vec![]),
+        function(
+            "f",
+            vec![],
+            vec![
+                label(
+                    "entry",
+                    vec![
+                        call(&r0, "log", vec![]),
+                        cmd(Command::Jeq("nowhere".to_string(), val(&r0), i32_val(0))),
+                        cmd(Command::Ret(val(&reg(1, i32_ty())))),
+                    ],
+                ),
+                label("entry", vec![cmd(Command::Ret(val(&arg(0, i32_ty()))))]),
+            ],
+        ),
+        function("f", vec![], vec![]),
+    ];
+    assert_eq!(
+        verify_module(&stmts),
+        Err(vec![
+            VerifyError::DuplicateFunction("f".to_string()),
+            VerifyError::DuplicateLabel {
+                function: "f".to_string(),
+                label: "entry".to_string(),
+            },
+            VerifyError::ArgumentCount {
+                function: "f".to_string(),
+                callee: "log".to_string(),
+                expected: 1,
+                found: 0,
+            },
+            VerifyError::UnknownLabel {
+                function: "f".to_string(),
+                label: "nowhere".to_string(),
+            },
+            VerifyError::UndefinedRegister {
+                function: "f".to_string(),
+                register: "r1".to_string(),
+            },
+            VerifyError::UnknownArgument {
+                function: "f".to_string(),
+                index: 0,
+            },
+            VerifyError::EmptyFunction("f".to_string()),
+        ])
+    );
+    assert_eq!(verify_module(&add_module(&["a", "b"])), Ok(()));
+}
+
+fn with_attributes(mut stmt: Statement, flags: Vec<Flag>) -> Statement {
+    if let Statement::Function(f) = &mut stmt {
+        f.get_labels_mut()[0].flags = Flags::new(flags);
+    }
+    stmt
+}
+
+/// `inc(arg0) = arg0 + 1` and `main(arg0) = inc(arg0) + inc(2)`
+fn inline_module(inc_attributes: Vec<Flag>) -> Vec<Statement> {
+    let a = arg(0, i32_ty());
+    let r0 = reg(0, i32_ty());
+    let r1 = reg(1, i32_ty());
+    vec![
+        with_attributes(
+            function(
+                "inc",
+                vec![i32_ty()],
+                vec![label(
+                    "entry",
+                    vec![
+                        assign(&r0, Command::AddInt32(val(&a), i32_val(1))),
+                        cmd(Command::Ret(val(&r0))),
+                    ],
+                )],
+            ),
+            inc_attributes,
+        ),
+        function(
+            "main",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    call(&r0, "inc", vec![val(&a)]),
+                    call(&r1, "inc", vec![i32_val(2)]),
+                    assign(&r0, Command::AddInt32(val(&r0), val(&r1))),
+                    cmd(Command::Ret(val(&r0))),
+                ],
+            )],
+        ),
+    ]
+}
+
+fn run_inliner(inliner: Inliner, stmts: &mut Vec<Statement>) -> Preserved {
+    let mut pm = PassManager::new();
+    pm.set_verify_each(true);
+    pm.add_module_pass(inliner);
+    pm.run(stmts)
+}
+
+#[test]
+fn test_inline() {
+    let mut stmts = inline_module(vec![]);
+    assert!(run_inliner(Inliner::new(2), &mut stmts).changed());
+    assert!(print(&stmts).ends_with(
+        "main(@int32) -> @int32\n\
+         entry: \n\
+         \tv0 = arg0\n\
+         \tjump inc.entry\n\
+         inc.entry: \n\
+         \tr2 = add_i32 v0, @int32 1\n\
+         \tr0 = r2\n\
+         \tjump entry.cont\n\
+         entry.cont: \n\
+         \tv1 = @int32 2\n\
+         \tjump inc.entry.1\n\
+         inc.entry.1: \n\
+         \tr3 = add_i32 v1, @int32 1\n\
+         \tr1 = r3\n\
+         \tjump entry.cont.cont\n\
+         entry.cont.cont: \n\
+         \tr0 = add_i32 r0, r1\n\
+         \tret r0"
+    ));
+}
+
+#[test]
+fn test_inline_heuristics() {
+    // Too big for the threshold, unless asked for
+    let mut stmts = inline_module(vec![]);
+    assert!(!run_inliner(Inliner::new(1), &mut stmts).changed());
+    let mut stmts = inline_module(vec![Flag::inline()]);
+    assert!(run_inliner(Inliner::new(0), &mut stmts).changed());
+    let mut stmts = inline_module(vec![Flag::noinline()]);
+    assert!(!run_inliner(Inliner::new(100), &mut stmts).changed());
+
+    // Recursive functions are kept as calls
+    let r0 = reg(0, i32_ty());
+    let mut stmts = vec![
+        with_attributes(
+            function(
+                "even",
+                vec![i32_ty()],
+                vec![label(
+                    "entry",
+                    vec![
+                        call(&r0, "odd", vec![val(&arg(0, i32_ty()))]),
+                        cmd(Command::Ret(val(&r0))),
+                    ],
+                )],
+            ),
+            vec![Flag::inline()],
+        ),
+        function(
+            "odd",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    call(&r0, "even", vec![val(&arg(0, i32_ty()))]),
+                    cmd(Command::Ret(val(&r0))),
+                ],
+            )],
+        ),
+    ];
+    let before = stmts.clone();
+    assert!(!run_inliner(Inliner::new(100), &mut stmts).changed());
+    assert_eq!(stmts, before);
+}
+
+#[test]
+fn test_inline_pipeline() {
+    let stmts = inline_module(vec![Flag::internal()]);
+    let out = print(&optimize(OptiLevel::O2, stmts));
+    assert!(!out.contains("inc("));
+    assert!(out.ends_with("r0 = add_i32 r0, @int32 3\n\tret r0"));
+}
+
+#[test]
+fn test_size_levels_only_inline_marked_callees() {
+    assert_eq!(Inliner::for_level(OptiLevel::Os).threshold(), 0);
+    assert_eq!(Inliner::for_level(OptiLevel::Oz).threshold(), 0);
+    let out = print(&optimize(
+        OptiLevel::Os,
+        inline_module(vec![Flag::internal()]),
+    ));
+    assert!(out.contains("inc("));
+    let out = print(&optimize(
+        OptiLevel::Os,
+        inline_module(vec![Flag::internal(), Flag::inline()]),
+    ));
+    assert!(!out.contains("inc("));
+}
+
+/// A counter kept in memory: `i = 0; while i != arg0 { i += 1 }; ret i`
+fn counter_loop() -> Vec<Statement> {
+    let a = arg(0, i32_ty());
+    let r = |i| reg(i, i32_ty());
+    vec![function(
+        "f",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    assign(
+                        &r(0),
+                        Command::Const(MirageObject::from(
+                            MirageTypeEnum::type_int32().const_value(0).to_value_enum(),
+                        )),
+                    ),
+                    assign(&r(1), Command::Ref(val(&r(0)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "loop",
+                vec![
+                    assign(&r(2), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Jeq("end".to_string(), val(&r(2)), val(&a))),
+                    assign(&r(3), Command::AddInt32(val(&r(2)), i32_val(1))),
+                    cmd(Command::Store(r(1), val(&r(3)))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "end",
+                vec![
+                    assign(&r(4), Command::Load(i32_ty(), val(&r(1)))),
+                    cmd(Command::Ret(val(&r(4)))),
+                ],
+            ),
+        ],
+    )]
+}
+
+fn ssa_registers(stmts: &[Statement]) -> Vec<usize> {
+    let f = match &stmts[0] {
+        Statement::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let mut ssa: Vec<usize> = AnalysisManager::new()
+        .get::<SsaRegisters>(f)
+        .iter()
+        .map(|(_, index)| *index)
+        .collect();
+    ssa.sort_unstable();
+    ssa
+}
+
+#[test]
+fn test_mem2reg() {
+    let mut stmts = counter_loop();
+    // r0 is memory, r1 only holds its address
+    assert_eq!(ssa_registers(&stmts), vec![1, 2, 3, 4]);
+
+    let mut pm = PassManager::new();
+    pm.set_verify_each(true);
+    pm.add_function_pass(Mem2Reg::new());
+    assert!(pm.run(&mut stmts).changed());
+    assert_eq!(
+        print(&stmts),
+        "f(@int32) -> @int32\n\
+         entry: \n\
+         \tr6 = @int32 0\n\
+         \tjump loop\n\
+         loop: \n\
+         \tr5 = phi [r6, entry], [r7, loop.1]\n\
+         \tr2 = r5\n\
+         \tjeq end, r2, arg0\n\
+         loop.1: \n\
+         \tr3 = add_i32 r2, @int32 1\n\
+         \tr7 = r3\n\
+         \tjump loop\n\
+         end: \n\
+         \tr4 = r5\n\
+         \tret r4"
+    );
+    assert_eq!(pm.statistics()[0].counters["phis inserted"], 1);
+    assert_eq!(ssa_registers(&stmts), vec![2, 3, 4, 5, 6, 7]);
+}
+
+#[test]
+fn test_mem2reg_keeps_escaping_slots() {
+    let r0 = reg(0, i32_ty());
+    let r1 = reg(1, i32_ty());
+    let r2 = reg(2, i32_ty());
+    let mut pm = PassManager::new();
+    pm.add_function_pass(Mem2Reg::new());
+
+    // The address is passed to a function
+    let mut stmts = vec![
+        extern_fn("log", vec![]),
+        function(
+            "f",
+            vec![],
+            vec![label(
+                "entry",
+                vec![
+                    assign(
+                        &r0,
+                        Command::Const(MirageObject::from(
+                            MirageTypeEnum::type_int32().const_value(1).to_value_enum(),
+                        )),
+                    ),
+                    assign(&r1, Command::Ref(val(&r0))),
+                    call(&r2, "log", vec![val(&r1)]),
+                    cmd(Command::Ret(val(&r0))),
+                ],
+            )],
+        ),
+    ];
+    assert!(!pm.run(&mut stmts).changed());
+
+    // The slot is read before anything is stored in it
+    let mut stmts = vec![function(
+        "f",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign(&r1, Command::Ref(val(&r0))),
+                assign(&r2, Command::Load(i32_ty(), val(&r1))),
+                cmd(Command::Ret(val(&r2))),
+            ],
+        )],
+    )];
+    assert!(!pm.run(&mut stmts).changed());
+}
+
+#[test]
+fn test_mem2reg_pipeline() {
+    let out = print(&optimize(OptiLevel::O2, counter_loop()));
+    assert!(!out.contains("load") && !out.contains("store") && !out.contains("ref"));
+    assert_eq!(
+        verify_module(&optimize(OptiLevel::O2, counter_loop())),
+        Ok(())
+    );
+}
+
+fn run_gvn(stmts: &mut Vec<Statement>) -> PassManager {
+    let mut pm = PassManager::new();
+    pm.set_verify_each(true);
+    pm.add_function_pass(Gvn::new());
+    pm.run(stmts);
+    pm
+}
+
+#[test]
+fn test_gvn() {
+    let ptr: MirageTypeEnum = MirageTypeEnum::type_ptr(i32_ty()).into();
+    let (a, b, p) = (arg(0, i32_ty()), arg(1, i32_ty()), arg(2, ptr.clone()));
+    let r: Vec<RegisterValue> = (0..10).map(|i| reg(i, i32_ty())).collect();
+    let mut stmts = vec![
+        extern_fn("log", vec![]),
+        function(
+            "f",
+            vec![i32_ty(), i32_ty(), ptr],
+            vec![
+                label(
+                    "entry",
+                    vec![
+                        assign(&r[0], Command::AddInt32(val(&a), val(&b))),
+                        assign(&r[1], Command::Load(i32_ty(), val(&p))),
+                        assign(&r[2], Command::Load(i32_ty(), val(&p))),
+                        call(&r[3], "log", vec![val(&r[1])]),
+                        assign(&r[4], Command::Load(i32_ty(), val(&p))),
+                        cmd(Command::Jeq("then".to_string(), val(&r[0]), val(&r[4]))),
+                    ],
+                ),
+                label(
+                    "else",
+                    vec![
+                        assign(&r[5], Command::AddInt32(val(&a), val(&b))),
+                        assign(&r[6], Command::SubInt32(val(&r[5]), val(&b))),
+                        assign(
+                            &r[7],
+                            Command::Const(MirageObject::from(MirageValueEnum::Register(
+                                r[6].clone(),
+                            ))),
+                        ),
+                        cmd(Command::Ret(val(&r[7]))),
+                    ],
+                ),
+                label(
+                    "then",
+                    vec![
+                        assign(&r[8], Command::SubInt32(val(&r[0]), val(&b))),
+                        assign(&r[9], Command::AddInt32(val(&r[8]), val(&r[2]))),
+                        cmd(Command::Ret(val(&r[9]))),
+                    ],
+                ),
+            ],
+        ),
+    ];
+    let pm = run_gvn(&mut stmts);
+    // The `sub` of `then` isn't available in `else`, and the load after
+    // the call may read something else
+    assert_eq!(
+        print(&stmts[1..]),
+        "f(@int32, @int32, @int32*) -> @int32\n\
+         entry: \n\
+         \tr0 = add_i32 arg0, arg1\n\
+         \tr1 = load @int32, arg2\n\
+         \tr3 = log { r1 }\n\
+         \tr4 = load @int32, arg2\n\
+         \tjeq then, r0, r4\n\
+         else: \n\
+         \tr6 = sub_i32 r0, arg1\n\
+         \tret r6\n\
+         then: \n\
+         \tr8 = sub_i32 r0, arg1\n\
+         \tr9 = add_i32 r8, r1\n\
+         \tret r9"
+    );
+    assert_eq!(pm.statistics()[0].counters["expressions merged"], 2);
+    assert_eq!(pm.statistics()[0].counters["copies propagated"], 1);
+}
+
+#[test]
+fn test_gvn_loads_through_distinct_slots() {
+    let r: Vec<RegisterValue> = (0..7).map(|i| reg(i, i32_ty())).collect();
+    let mut stmts = vec![function(
+        "f",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign(
+                    &r[0],
+                    Command::Const(MirageObject::from(
+                        MirageTypeEnum::type_int32().const_value(0).to_value_enum(),
+                    )),
+                ),
+                assign(
+                    &r[1],
+                    Command::Const(MirageObject::from(
+                        MirageTypeEnum::type_int32().const_value(0).to_value_enum(),
+                    )),
+                ),
+                assign(&r[2], Command::Ref(val(&r[0]))),
+                assign(&r[3], Command::Ref(val(&r[1]))),
+                assign(&r[4], Command::Load(i32_ty(), val(&r[2]))),
+                cmd(Command::Store(r[3].clone(), i32_val(1))),
+                assign(&r[5], Command::Load(i32_ty(), val(&r[2]))),
+                cmd(Command::Store(r[2].clone(), i32_val(2))),
+                assign(&r[6], Command::Load(i32_ty(), val(&r[2]))),
+                cmd(Command::Ret(val(&r[6]))),
+            ],
+        )],
+    )];
+    let pm = run_gvn(&mut stmts);
+    // The store to r1 leaves r0 alone, the store to r0 doesn't
+    assert_eq!(pm.statistics()[0].counters["expressions merged"], 1);
+    let out = print(&stmts);
+    assert!(!out.contains("r5") && out.contains("r6 = load @int32, r2"));
+}
+
+#[test]
+fn test_liveness_and_use_def() {
+    let a = arg(0, i32_ty());
+    let r0 = reg(0, i32_ty());
+    let stmt = function(
+        "f",
+        vec![i32_ty()],
+        vec![
+            label(
+                "entry",
+                vec![
+                    assign(
+                        &r0,
+                        Command::Const(MirageObject::from(
+                            MirageTypeEnum::type_int32().const_value(0).to_value_enum(),
+                        )),
+                    ),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label(
+                "loop",
+                vec![
+                    cmd(Command::Jeq("end".to_string(), val(&r0), val(&a))),
+                    assign(&r0, Command::AddInt32(val(&r0), i32_val(1))),
+                    cmd(Command::Jump("loop".to_string())),
+                ],
+            ),
+            label("end", vec![cmd(Command::Ret(val(&r0)))]),
+        ],
+    );
+    let f = match &stmt {
+        Statement::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let mut am = AnalysisManager::new();
+    let key = (RegisterType::Register, 0);
+
+    let liveness = am.get::<Liveness>(f);
+    assert!(liveness.label_live_in(0).is_empty());
+    assert!((0..2).all(|l| liveness.label_live_out(l).contains(&key)));
+    assert!(liveness.label_live_out(2).is_empty());
+    assert!(liveness.label_live_in(1).contains(&key) && liveness.label_live_in(2).contains(&key));
+    // Between the jeq and the add, in the second block of loop
+    assert!(liveness.live_in(2).contains(&key));
+
+    let chains = am.get::<UseDef>(f);
+    let at = |label, index| Location { label, index };
+    assert_eq!(chains.defs(&key), &[at(0, 0), at(1, 1)]);
+    assert_eq!(chains.uses(&key), &[at(1, 0), at(1, 1), at(2, 0)]);
+    assert_eq!(chains.reaching_defs(at(1, 0), &key), &[at(0, 0), at(1, 1)]);
+    assert_eq!(chains.def_uses(at(1, 1)), &[at(1, 0), at(1, 1), at(2, 0)]);
+    assert_eq!(chains.def_uses(at(0, 0)), chains.def_uses(at(1, 1)));
+    assert!(chains.uses(&(RegisterType::Argument, 0)).len() == 1);
+    assert!(chains
+        .reaching_defs(at(1, 0), &(RegisterType::Argument, 0))
+        .is_empty());
+}
+
+#[test]
+fn test_insert_free() {
+    let a = arg(0, i32_ty());
+    let v = |i| RegisterValue::new(i, RegisterType::Variable, i32_ty());
+    let (r0, r1) = (reg(0, i32_ty()), reg(1, i32_ty()));
+    let mut stmts = vec![
+        extern_fn("log", vec![Flag::pure()]),
+        function(
+            "f",
+            vec![i32_ty()],
+            vec![
+                label(
+                    "entry",
+                    vec![
+                        assign(&v(0), Command::AddInt32(val(&a), i32_val(1))),
+                        assign(&v(1), Command::AddInt32(val(&a), i32_val(2))),
+                        call(&v(2), "log", vec![val(&a)]),
+                        call(&r0, "log", vec![val(&v(0))]),
+                        cmd(Command::Jeq("end".to_string(), val(&r0), val(&a))),
+                    ],
+                ),
+                label(
+                    "body",
+                    vec![
+                        call(&r1, "log", vec![val(&v(1))]),
+                        cmd(Command::Ret(val(&r1))),
+                    ],
+                ),
+                label(
+                    "end",
+                    vec![
+                        assign(&v(3), Command::AddInt32(val(&a), i32_val(3))),
+                        cmd(Command::Ret(val(&v(3)))),
+                    ],
+                ),
+            ],
+        ),
+    ];
+    let mut pm = PassManager::new();
+    pm.set_verify_each(true);
+    pm.add_function_pass(InsertFree::new());
+    assert!(pm.run(&mut stmts).changed());
+    // v1 dies on the edge to end, v3 is returned
+    assert_eq!(
+        print(&stmts[1..]),
+        "f(@int32) -> @int32\n\
+         entry: \n\
+         \tv0 = add_i32 arg0, @int32 1\n\
+         \tv1 = add_i32 arg0, @int32 2\n\
+         \tv2 = log { arg0 }\n\
+         \tfree v2\n\
+         \tr0 = log { v0 }\n\
+         \tfree v0\n\
+         \tjeq end, r0, arg0\n\
+         body: \n\
+         \tr1 = log { v1 }\n\
+         \tfree v1\n\
+         \tret r1\n\
+         end: \n\
+         \tfree v1\n\
+         \tv3 = add_i32 arg0, @int32 3\n\
+         \tret v3"
+    );
+    assert_eq!(pm.statistics()[0].counters["variables freed"], 4);
+    // Running it again finds nothing new to free
+    assert!(!pm.run(&mut stmts).changed());
+}
+
+#[test]
+fn test_insert_free_interior_pointer() {
+    // r1 points into v0, which must outlive the load through r2
+    let mut stmts = parse(
+        "module test;\n\
+         type outer = {[2 x @int64],@int64};\n\
+         f() -> @int64\n\
+         entry: \n\
+         \tv0 = new outer, {[2 x @int64] [@int64 1, @int64 2],@int64 3}\n\
+         \tr1 = get v0, 0\n\
+         \tr2 = getelementptr [2 x @int64], r1, @int64 0,@int64 1\n\
+         \tr3 = load @int64, r2\n\
+         \tret r3",
+    )
+    .unwrap();
+    let mut pm = PassManager::new();
+    pm.set_verify_each(true);
+    pm.add_function_pass(InsertFree::new());
+    assert!(!pm.run(&mut stmts).changed());
+    assert!(!print(&stmts).contains("free"));
+}
+
+#[test]
+fn test_insert_free_call_arguments() {
+    let ptr_ty: MirageTypeEnum = MirageTypeEnum::type_ptr(i32_ty()).into();
+    let (g0, g1) = (
+        RegisterValue::new(0, RegisterType::Global, ptr_ty.clone()),
+        RegisterValue::new(1, RegisterType::Global, i32_ty()),
+    );
+    let v = |i| RegisterValue::new(i, RegisterType::Variable, ptr_ty.clone());
+    let (r0, r1) = (reg(0, i32_ty()), reg(1, i32_ty()));
+    let mut builder = Builder::new(Module::new("test".to_string()));
+    builder.build_global(MirageObject::from(MirageValueEnum::Pointer(
+        PointerValue::new(MirageTypeEnum::type_ptr(i32_ty())),
+    )));
+    builder.build_global(MirageObject::from(
+        MirageTypeEnum::type_int32().const_value(0).to_value_enum(),
+    ));
+    let mut stmts = builder.asts;
+    stmts.push(extern_fn("len", vec![Flag::pure()]));
+    // `keep` stores the pointer it is given into g0, so it outlives the call
+    stmts.push(function(
+        "keep",
+        vec![ptr_ty.clone()],
+        vec![label(
+            "entry",
+            vec![
+                cmd(Command::Store(g0, val(&arg(0, ptr_ty.clone())))),
+                cmd(Command::Ret(i32_val(0))),
+            ],
+        )],
+    ));
+    stmts.push(function(
+        "f",
+        vec![],
+        vec![label(
+            "entry",
+            vec![
+                assign(&v(0), Command::Ref(val(&g1))),
+                assign(&v(1), Command::Ref(val(&g1))),
+                call(&r0, "keep", vec![val(&v(0))]),
+                call(&r1, "len", vec![val(&v(1))]),
+                cmd(Command::Ret(val(&r0))),
+            ],
+        )],
+    ));
+    let mut pm = PassManager::new();
+    pm.set_verify_each(true);
+    pm.add_function_pass(InsertFree::new());
+    assert!(pm.run(&mut stmts).changed());
+    // Only the variable given to the `#pure` len is freed
+    let out = print(&stmts);
+    assert!(out.ends_with(
+        "\tr0 = keep { v0 }\n\
+         \tr1 = len { v1 }\n\
+         \tfree v1\n\
+         \tret r0"
+    ));
+    assert_eq!(pm.statistics()[0].counters["variables freed"], 1);
+}
+
+#[test]
+fn test_call_graph() {
+    let a = arg(0, i32_ty());
+    let r0 = reg(0, i32_ty());
+    let r1 = reg(1, i32_ty());
+    let stmts = vec![
+        extern_fn("abs", vec![Flag::pure()]),
+        extern_fn("log", vec![]),
+        function(
+            "even",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    call(&r0, "abs", vec![val(&a)]),
+                    call(&r1, "odd", vec![val(&r0)]),
+                    cmd(Command::Ret(val(&r1))),
+                ],
+            )],
+        ),
+        function(
+            "odd",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    call(&r0, "even", vec![val(&a)]),
+                    cmd(Command::Ret(val(&r0))),
+                ],
+            )],
+        ),
+        function(
+            "peek",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    assign(&r0, Command::Load(i32_ty(), val(&a))),
+                    cmd(Command::Ret(val(&r0))),
+                ],
+            )],
+        ),
+        function(
+            "poke",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    cmd(Command::Store(a.clone(), i32_val(1))),
+                    call(&r0, "peek", vec![val(&a)]),
+                    cmd(Command::Free(vec![a.clone()])),
+                    cmd(Command::Ret(val(&r0))),
+                ],
+            )],
+        ),
+        function(
+            "main",
+            vec![i32_ty()],
+            vec![label(
+                "entry",
+                vec![
+                    call(&r0, "even", vec![val(&a)]),
+                    call(&r1, "mystery", vec![val(&r0)]),
+                    cmd(Command::Ret(val(&r1))),
+                ],
+            )],
+        ),
+    ];
+    let graph = CallGraph::new(&stmts);
+    assert!(graph.contains("log") && !graph.contains("mystery"));
+    assert_eq!(graph.callees("even"), vec!["abs", "odd"]);
+    assert_eq!(graph.callers("peek"), vec!["poke", "main"]);
+
+    // Callees come first, and mutually recursive functions share a
+    // component
+    let sccs = graph.sccs();
+    let position = |name| sccs.iter().position(|scc| scc.contains(&name)).unwrap();
+    assert_eq!(position("even"), position("odd"));
+    assert!(position("abs") < position("even"));
+    assert!(position("peek") < position("poke"));
+    assert!(graph.is_recursive("even") && graph.is_recursive("odd"));
+    assert!(!graph.is_recursive("peek"));
+
+    assert!(graph.effects("abs").is_pure());
+    assert!(graph.effects("even").is_pure());
+    assert!(graph.effects("peek").is_readonly() && !graph.effects("peek").is_pure());
+    let poke = graph.effects("poke");
+    assert!(poke.writes && poke.may_free());
+    assert_eq!(graph.effects("log"), Effects::unknown());
+
+    // The unknown callee may call back any function but is free to do
+    // anything
+    assert!(graph.calls_unknown("main") && !graph.calls_unknown("poke"));
+    assert_eq!(graph.effects("main"), Effects::unknown());
+    assert!(graph.callees("main").contains(&"poke"));
+    assert!(graph.is_recursive("main"));
+    assert_eq!(graph.reachable(["even"]).len(), 3);
+}
+
+#[test]
+fn test_layout() {
+    let fields: Vec<MirageTypeEnum> = vec![
+        MirageTypeEnum::type_int8().into(),
+        MirageTypeEnum::type_ptr(MirageTypeEnum::type_int8().into()).into(),
+        MirageTypeEnum::type_array(MirageTypeEnum::type_int16().into(), 3).into(),
+    ];
+    let ty: MirageTypeEnum = MirageTypeEnum::type_struct(fields.clone()).into();
+
+    let layout = Layout::new(8);
+    assert_eq!(layout.struct_layout(&fields), (24, vec![0, 8, 16]));
+    assert_eq!(layout.size_of(&ty), 24);
+    assert_eq!(layout.align_of(&ty), 8);
+
+    // wasm32 addresses take 4 bytes
+    let layout = Layout::new(4);
+    assert_eq!(layout.struct_layout(&fields), (16, vec![0, 4, 8]));
+    assert_eq!(layout.align_of(&ty), 4);
+    assert_eq!(
+        layout.size_of(&MirageTypeEnum::type_struct(vec![]).into()),
+        0
+    );
+}
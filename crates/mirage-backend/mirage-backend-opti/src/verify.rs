@@ -0,0 +1,241 @@
+use std::collections::{HashMap, HashSet};
+
+use mirage_frontend::object::function::{FunctionType, FunctionValue};
+use mirage_frontend::object::label::{Command, LabelBodyInstr};
+use mirage_frontend::object::statements::Statement;
+use mirage_frontend::object::RegisterType;
+
+use crate::ir::{for_each_use, is_phi, reg_key};
+
+/// A malformed part of a module
+/// # Variants
+/// * `DuplicateFunction` - Two functions or externs have the same name
+/// * `EmptyFunction` - A function has no label
+/// * `DuplicateLabel` - Two labels of a function have the same name
+/// * `UnknownLabel` - A `jump`, `jeq` or `phi` names a label which doesn't exist
+/// * `MisplacedPhi` - A `phi` comes after another kind of instruction
+/// * `UnknownFunction` - A call targets a function which isn't declared
+/// * `ArgumentCount` - A call passes the wrong number of arguments
+/// * `UnknownArgument` - An `arg` register past the function's arguments
+/// * `UnknownGlobal` - A `g` register past the module's globals
+/// * `UndefinedRegister` - A register is read but never assigned
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    DuplicateFunction(String),
+    EmptyFunction(String),
+    DuplicateLabel {
+        function: String,
+        label: String,
+    },
+    UnknownLabel {
+        function: String,
+        label: String,
+    },
+    MisplacedPhi {
+        function: String,
+        label: String,
+    },
+    UnknownFunction {
+        function: String,
+        callee: String,
+    },
+    ArgumentCount {
+        function: String,
+        callee: String,
+        expected: usize,
+        found: usize,
+    },
+    UnknownArgument {
+        function: String,
+        index: usize,
+    },
+    UnknownGlobal {
+        function: String,
+        index: usize,
+    },
+    UndefinedRegister {
+        function: String,
+        register: String,
+    },
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::DuplicateFunction(name) => write!(f, "`{}` is defined twice", name),
+            VerifyError::EmptyFunction(name) => write!(f, "`{}` has no label", name),
+            VerifyError::DuplicateLabel { function, label } => {
+                write!(f, "{}: label `{}` is defined twice", function, label)
+            }
+            VerifyError::UnknownLabel { function, label } => {
+                write!(f, "{}: unknown label `{}`", function, label)
+            }
+            VerifyError::MisplacedPhi { function, label } => {
+                write!(f, "{}: phi after the start of label `{}`", function, label)
+            }
+            VerifyError::UnknownFunction { function, callee } => {
+                write!(f, "{}: call to unknown function `{}`", function, callee)
+            }
+            VerifyError::ArgumentCount {
+                function,
+                callee,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}: `{}` expects {} arguments, found {}",
+                function, callee, expected, found
+            ),
+            VerifyError::UnknownArgument { function, index } => {
+                write!(f, "{}: unknown argument arg{}", function, index)
+            }
+            VerifyError::UnknownGlobal { function, index } => {
+                write!(f, "{}: unknown global g{}", function, index)
+            }
+            VerifyError::UndefinedRegister { function, register } => {
+                write!(f, "{}: `{}` is read but never assigned", function, register)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+pub type VerifyResult = Result<(), Vec<VerifyError>>;
+
+/// Check that `stmts` is well formed: functions, labels, call targets and
+/// registers all refer to something which exists
+pub fn verify_module(stmts: &[Statement]) -> VerifyResult {
+    let mut errors = Vec::new();
+    let mut signatures: HashMap<&str, &FunctionType> = HashMap::new();
+    let mut globals = 0;
+    for stmt in stmts {
+        let (name, ty) = match stmt {
+            Statement::Function(f) => (f.get_name().as_str(), f.get_type()),
+            Statement::External(e) => (e.name.as_str(), &e.ty),
+            Statement::Global(_) => {
+                globals += 1;
+                continue;
+            }
+            _ => continue,
+        };
+        if signatures.insert(name, ty).is_some() {
+            errors.push(VerifyError::DuplicateFunction(name.to_string()));
+        }
+    }
+    for stmt in stmts {
+        if let Statement::Function(f) = stmt {
+            verify_function(f, &signatures, globals, &mut errors);
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn verify_function(
+    func: &FunctionValue,
+    signatures: &HashMap<&str, &FunctionType>,
+    globals: usize,
+    errors: &mut Vec<VerifyError>,
+) {
+    let function = func.get_name().clone();
+    if func.len_labels() == 0 {
+        errors.push(VerifyError::EmptyFunction(function));
+        return;
+    }
+    let mut labels = HashSet::new();
+    for label in func.get_labels() {
+        if !labels.insert(label.name.as_str()) {
+            errors.push(VerifyError::DuplicateLabel {
+                function: function.clone(),
+                label: label.name.clone(),
+            });
+        }
+        let phis = label.body.iter().take_while(|i| is_phi(i)).count();
+        if label.body[phis..].iter().any(is_phi) {
+            errors.push(VerifyError::MisplacedPhi {
+                function: function.clone(),
+                label: label.name.clone(),
+            });
+        }
+    }
+
+    let defined: HashSet<_> = func
+        .get_labels()
+        .iter()
+        .flat_map(|l| l.body.iter())
+        .filter_map(|i| match i {
+            LabelBodyInstr::Assign(reg, _) => Some(reg_key(reg)),
+            _ => None,
+        })
+        .collect();
+    let args = func.get_type().get_args().len();
+
+    for instr in func.get_labels().iter().flat_map(|l| l.body.iter()) {
+        let (call, targets) = match instr {
+            LabelBodyInstr::Assign(_, instr) => match &**instr {
+                LabelBodyInstr::Call(name, args) => (Some((name, args.len())), vec![]),
+                LabelBodyInstr::Command(Command::Phi(incoming)) => {
+                    (None, incoming.iter().map(|(label, _)| label).collect())
+                }
+                _ => (None, vec![]),
+            },
+            LabelBodyInstr::Call(name, args) => (Some((name, args.len())), vec![]),
+            LabelBodyInstr::Command(Command::Jump(label) | Command::Jeq(label, _, _)) => {
+                (None, vec![label])
+            }
+            _ => (None, vec![]),
+        };
+        for label in targets {
+            if !labels.contains(label.as_str()) {
+                errors.push(VerifyError::UnknownLabel {
+                    function: function.clone(),
+                    label: label.clone(),
+                });
+            }
+        }
+        if let Some((callee, found)) = call {
+            match signatures.get(callee.as_str()) {
+                None => errors.push(VerifyError::UnknownFunction {
+                    function: function.clone(),
+                    callee: callee.clone(),
+                }),
+                Some(ty) => {
+                    let expected = ty.get_args().len();
+                    if found < expected || (found > expected && !ty.is_var_arg()) {
+                        errors.push(VerifyError::ArgumentCount {
+                            function: function.clone(),
+                            callee: callee.clone(),
+                            expected,
+                            found,
+                        });
+                    }
+                }
+            }
+        }
+        for_each_use(instr, &mut |reg, _| match reg.register_type {
+            RegisterType::Argument if reg.index >= args => {
+                errors.push(VerifyError::UnknownArgument {
+                    function: function.clone(),
+                    index: reg.index,
+                })
+            }
+            RegisterType::Global if reg.index >= globals => {
+                errors.push(VerifyError::UnknownGlobal {
+                    function: function.clone(),
+                    index: reg.index,
+                })
+            }
+            RegisterType::Register | RegisterType::Variable if !defined.contains(&reg_key(reg)) => {
+                errors.push(VerifyError::UndefinedRegister {
+                    function: function.clone(),
+                    register: reg.print_to_string(),
+                })
+            }
+            _ => {}
+        });
+    }
+}
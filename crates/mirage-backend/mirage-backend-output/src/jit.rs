@@ -0,0 +1,207 @@
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::MirageTypeEnum;
+
+/// A JIT error
+/// # Variants
+/// * `UnknownFunction` - No function with this name was compiled
+/// * `UnknownExtern` - No extern with this name was declared
+/// * `SignatureMismatch` - The Rust signature doesn't match the Mirage type
+/// * `AlreadyFinalized` - Symbols can't be registered once code has been emitted
+/// * `UnknownModule` - No module with this name was added
+/// * `AmbiguousSymbol` - Several dylibs define this name, none of them the
+///   main one
+/// * `Backend` - The backend failed to compile or link
+#[derive(Debug, Clone, PartialEq)]
+pub enum JitError {
+    UnknownFunction(String),
+    UnknownExtern(String),
+    SignatureMismatch {
+        name: String,
+        expected: String,
+        found: String,
+    },
+    AlreadyFinalized(String),
+    UnknownModule(String),
+    AmbiguousSymbol(String),
+    Backend(String),
+}
+
+impl std::fmt::Display for JitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JitError::UnknownFunction(name) => write!(f, "unknown function `{}`", name),
+            JitError::UnknownExtern(name) => write!(f, "unknown extern `{}`", name),
+            JitError::SignatureMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "signature mismatch for `{}`: expected {}, found {}",
+                name, expected, found
+            ),
+            JitError::AlreadyFinalized(name) => write!(
+                f,
+                "cannot register `{}`: the session has already emitted code",
+                name
+            ),
+            JitError::UnknownModule(name) => write!(f, "unknown module `{}`", name),
+            JitError::AmbiguousSymbol(name) => {
+                write!(f, "`{}` is defined in several dylibs", name)
+            }
+            JitError::Backend(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for JitError {}
+
+pub type JitResult<T> = Result<T, JitError>;
+
+/// A Rust type which can cross the JIT boundary
+pub trait JitType {
+    /// Whether values of this type can be passed where `ty` is expected
+    fn matches(ty: &MirageTypeEnum) -> bool;
+
+    /// The Rust spelling of the type, used in error messages
+    fn name() -> String;
+
+    /// Whether the type only stands for a return value which is discarded,
+    /// and can't be an argument
+    fn is_void() -> bool {
+        false
+    }
+}
+
+macro_rules! jit_type {
+    ($t:ty => $($variant:ident)|+) => {
+        impl JitType for $t {
+            fn matches(ty: &MirageTypeEnum) -> bool {
+                matches!(ty, $(MirageTypeEnum::$variant(_))|+)
+            }
+
+            fn name() -> String {
+                stringify!($t).to_string()
+            }
+        }
+    };
+}
+
+jit_type!(i8 => Int8);
+jit_type!(i16 => Int16);
+jit_type!(i32 => Int32);
+jit_type!(i64 => Int64);
+jit_type!(u8 => UInt8);
+jit_type!(u16 => UInt16);
+jit_type!(u32 => UInt32);
+jit_type!(u64 => UInt64);
+jit_type!(f32 => Float32);
+jit_type!(f64 => Float64);
+
+impl<T> JitType for *const T {
+    fn matches(ty: &MirageTypeEnum) -> bool {
+        matches!(ty, MirageTypeEnum::Pointer(_)) || ty.is_string()
+    }
+
+    fn name() -> String {
+        format!("*const {}", std::any::type_name::<T>())
+    }
+}
+
+impl<T> JitType for *mut T {
+    fn matches(ty: &MirageTypeEnum) -> bool {
+        matches!(ty, MirageTypeEnum::Pointer(_)) || ty.is_string()
+    }
+
+    fn name() -> String {
+        format!("*mut {}", std::any::type_name::<T>())
+    }
+}
+
+/// Mirage has no void type: a `()` return discards the value of a function
+/// returning a scalar
+impl JitType for () {
+    fn matches(ty: &MirageTypeEnum) -> bool {
+        !matches!(ty, MirageTypeEnum::Array(_) | MirageTypeEnum::Struct(_))
+    }
+
+    fn name() -> String {
+        "()".to_string()
+    }
+
+    fn is_void() -> bool {
+        true
+    }
+}
+
+/// A Rust `extern "C"` function pointer type which can be checked
+/// against a Mirage function type
+pub trait JitSignature: Copy + Sized {
+    /// Whether this signature can be used to call a function of type `ty`.
+    /// Variadic functions never match: Rust function pointers can't use the
+    /// variadic calling convention
+    fn matches(ty: &FunctionType) -> bool;
+
+    /// The Rust spelling of the signature, used in error messages
+    fn signature() -> String;
+
+    /// Build the function pointer from a code address
+    /// # Safety
+    /// `address` must point to a function with this signature
+    unsafe fn from_address(address: u64) -> Self;
+
+    /// The code address of the function pointer
+    fn address(self) -> u64;
+
+    /// Check `ty` and report a mismatch for `name`
+    fn check(name: &str, ty: &FunctionType) -> JitResult<()> {
+        if Self::matches(ty) {
+            Ok(())
+        } else {
+            Err(JitError::SignatureMismatch {
+                name: name.to_string(),
+                expected: ty.print_to_string(),
+                found: Self::signature(),
+            })
+        }
+    }
+}
+
+macro_rules! jit_signature {
+    ($($arg:ident),*) => {
+        impl<R: JitType, $($arg: JitType),*> JitSignature for extern "C" fn($($arg),*) -> R {
+            #[allow(unused_mut, unused_variables)]
+            fn matches(ty: &FunctionType) -> bool {
+                let args = ty.get_args();
+                let mut i = 0;
+                let mut ok = !ty.is_var_arg() && R::matches(ty.get_ret());
+                $(
+                    ok = ok && !$arg::is_void() && args.get(i).map_or(false, $arg::matches);
+                    i += 1;
+                )*
+                ok && i == args.len()
+            }
+
+            fn signature() -> String {
+                let args: Vec<String> = vec![$($arg::name()),*];
+                format!("extern \"C\" fn({}) -> {}", args.join(", "), R::name())
+            }
+
+            unsafe fn from_address(address: u64) -> Self {
+                std::mem::transmute_copy(&(address as usize))
+            }
+
+            fn address(self) -> u64 {
+                self as usize as u64
+            }
+        }
+    };
+}
+
+jit_signature!();
+jit_signature!(A);
+jit_signature!(A, B);
+jit_signature!(A, B, C);
+jit_signature!(A, B, C, D);
+jit_signature!(A, B, C, D, E);
+jit_signature!(A, B, C, D, E, F);
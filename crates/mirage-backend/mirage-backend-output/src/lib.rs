@@ -1,11 +1,15 @@
+#[cfg(test)]
+mod test;
+
+pub mod jit;
 
 pub mod object {
     pub use object::*;
 }
 
-use std::fmt::Debug;
 use std::sync::Arc;
 use object::*;
+use crate::jit::{JitResult, JitSignature};
 
 
 pub trait CompilerOutput {
@@ -16,7 +20,15 @@ pub trait CompilerOutput {
 }
 
 pub trait ExecutionEngineOutput {
-    fn get_function<T: Copy + Sized>(&mut self, name: &str) -> T;
+    /// Look up a compiled function, checking `T` against its Mirage type
+    /// # Safety
+    /// The returned pointer holds no borrow of the engine: it must not be
+    /// called once the engine is dropped, or once the code it points to is
+    /// removed or replaced
+    unsafe fn get_function<T: JitSignature>(&mut self, name: &str) -> JitResult<T>;
+
+    /// Provide the implementation of a Mirage `extern` from the host
+    fn register_symbol<T: JitSignature>(&mut self, name: &str, f: T) -> JitResult<()>;
 }
 
 pub struct ObjectOutput<'a> {
@@ -46,7 +58,7 @@ impl<'a> ObjectOutput<'a> {
         std::fs::write(file, self.to_bytes())
     }
 
-    pub fn get_sections(&self) -> Vec<Section> {
+    pub fn get_sections(&self) -> Vec<Section<'_, '_>> {
         self.file.sections().collect()
     }
     
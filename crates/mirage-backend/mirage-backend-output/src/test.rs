@@ -0,0 +1,61 @@
+use mirage_frontend::object::function::FunctionType;
+use mirage_frontend::object::{
+    Int32Type, Int64Type, Int8Type, MirageTypeEnum, PointerType, StructType,
+};
+
+use crate::jit::{JitError, JitSignature};
+
+fn int32() -> MirageTypeEnum {
+    MirageTypeEnum::Int32(Int32Type::new())
+}
+
+fn ptr() -> MirageTypeEnum {
+    MirageTypeEnum::Pointer(PointerType::new(MirageTypeEnum::Int8(Int8Type::new())))
+}
+
+#[test]
+fn test_signature_matches() {
+    let ty = FunctionType::new(vec![int32(), ptr()], int32(), false);
+    assert!(<extern "C" fn(i32, *const i8) -> i32>::matches(&ty));
+    assert!(!<extern "C" fn(i32) -> i32>::matches(&ty));
+    assert!(!<extern "C" fn(i32, *const i8, i32) -> i32>::matches(&ty));
+    assert!(!<extern "C" fn(i64, *const i8) -> i32>::matches(&ty));
+    assert!(!<extern "C" fn(i32, *const i8) -> i64>::matches(&ty));
+}
+
+#[test]
+fn test_var_arg_signature() {
+    let printf = FunctionType::new(vec![ptr()], int32(), true);
+    assert!(!<extern "C" fn(*const i8) -> i32>::matches(&printf));
+    assert!(!<extern "C" fn() -> i32>::matches(&printf));
+    assert!(!<extern "C" fn(i32) -> i32>::matches(&printf));
+}
+
+#[test]
+fn test_void_return() {
+    let ty = FunctionType::new(
+        vec![int32()],
+        MirageTypeEnum::Int64(Int64Type::new()),
+        false,
+    );
+    assert!(<extern "C" fn(i32)>::matches(&ty));
+    assert!(!<extern "C" fn(())>::matches(&FunctionType::new(
+        vec![int32()],
+        int32(),
+        false
+    )));
+
+    let aggregate = FunctionType::new(
+        vec![],
+        MirageTypeEnum::Struct(StructType::new(vec![int32()])),
+        false,
+    );
+    assert_eq!(
+        <extern "C" fn()>::check("f", &aggregate),
+        Err(JitError::SignatureMismatch {
+            name: "f".to_string(),
+            expected: "() -> { @int32 }".to_string(),
+            found: "extern \"C\" fn() -> ()".to_string(),
+        })
+    );
+}
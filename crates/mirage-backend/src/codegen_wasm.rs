@@ -0,0 +1 @@
+pub use mirage_backend_codegen_wasm::*;
\ No newline at end of file
@@ -0,0 +1 @@
+pub use mirage_backend_interpreter::*;
@@ -1,4 +1,11 @@
+pub mod asm;
+pub mod codegen_asm;
+pub mod codegen_c;
+#[cfg(feature = "cranelift")]
+pub mod codegen_cranelift;
 pub mod codegen_llvm;
+pub mod codegen_wasm;
+pub mod interpreter;
 pub mod llvm;
 pub mod opti;
 pub mod output;
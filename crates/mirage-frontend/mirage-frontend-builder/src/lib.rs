@@ -86,6 +86,21 @@ impl Builder {
         MirageValueEnum::Register(reg)
     }
 
+    /// Like `build_global`, but the global is marked `#const` so the
+    /// optimizer can replace its uses by its value
+    pub fn build_const_global(&mut self, obj: MirageObject) -> MirageValueEnum {
+        let reg = RegisterValue::new(self.index_g, RegisterType::Global, obj.get_type());
+        let global = Global::with_flags(
+            reg.print_to_string(),
+            Flags::new(vec![Flag::constant()]),
+            obj,
+        );
+        self.module.add_global(global.clone());
+        self.asts.push(Statement::Global(global));
+        self.index_g += 1;
+        MirageValueEnum::Register(reg)
+    }
+
     pub fn build_function(&mut self, func: FunctionValue) {
         self.module.add_function(func.clone());
         self.asts.push(Statement::Function(func));
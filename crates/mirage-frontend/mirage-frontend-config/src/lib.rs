@@ -1,6 +1,6 @@
 
 
-#[derive(Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum OptimizationLevel {
     None,
     Less,
@@ -9,10 +9,15 @@ pub enum OptimizationLevel {
     Default,
 
     Medium,
-    Big
+    Big,
+
+    /// Optimize for size
+    Size,
+    /// Optimize for size, even at the cost of speed
+    MinSize,
 }
 
-#[derive(Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Bits {
     B8,
     B16,
@@ -23,17 +28,43 @@ pub enum Bits {
     B64,
 }
 
+/// What a build produces
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Emit {
+    /// LLVM IR, as text
+    LlvmIr,
+    /// Assembly for the target
+    Asm,
+    /// A relocatable object file
+    Obj,
+
+    /// An executable, linked with the system C compiler
+    #[default]
+    Exe,
+
+    /// A WebAssembly module
+    Wasm,
+}
+
 pub struct Config {
     pub optimization_level: OptimizationLevel,
     pub bits: Bits,
-    
+    /// The target triple, `<os>-<arch>-<compiler>`, which replaces the
+    /// target of the module
+    pub target: Option<String>,
+    pub emit: Emit,
+    /// Where the output is written, `-` for the standard output
+    pub output: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             optimization_level: Default::default(),
-            bits: Bits::B32
+            bits: Bits::B32,
+            target: None,
+            emit: Emit::default(),
+            output: None,
         }
     }
-}
\ No newline at end of file
+}
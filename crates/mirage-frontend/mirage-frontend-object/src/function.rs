@@ -29,14 +29,17 @@ impl FunctionType {
     }
 
     pub fn print_to_string(&self) -> String {
+        let mut args = self
+            .args
+            .iter()
+            .map(|arg| arg.print_to_string())
+            .collect::<Vec<String>>();
+        if self.is_var_arg {
+            args.push("...".to_string());
+        }
         let mut s = String::new();
         s.push_str("(");
-        for arg in &self.args {
-            s.push_str(&arg.print_to_string());
-            s.push_str(", ");
-        }
-        s.pop();
-        s.pop();
+        s.push_str(&args.join(", "));
         s.push_str(") -> ");
         s.push_str(&self.ret.print_to_string());
         s
@@ -60,6 +60,8 @@ pub enum Command {
     Ref(Value),
     Load(MirageTypeEnum, Value),
     GetElementPtr(MirageTypeEnum, Value, Vec<Value>),
+    /// The value coming from the predecessor label it is paired with
+    Phi(Vec<(String, Value)>),
 }
 
 impl Stringify for Command {
@@ -136,6 +138,14 @@ impl Stringify for Command {
                     .collect::<Vec<String>>()
                     .join(",")
             ),
+            Command::Phi(incoming) => format!(
+                "phi {}",
+                incoming
+                    .iter()
+                    .map(|(label, val)| format!("[{}, {}]", val.to_string(), label))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
         }
     }
 }
@@ -1,3 +1,6 @@
+#[cfg(test)]
+mod test;
+
 mod values;
 mod types;
 pub mod label;
@@ -37,4 +40,8 @@ impl MirageObject {
     pub fn get_value(&self) -> MirageValueEnum {
         self.value.clone()
     }
+
+    pub fn get_value_ref(&self) -> &MirageValueEnum {
+        &self.value
+    }
 }
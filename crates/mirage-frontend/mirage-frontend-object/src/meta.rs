@@ -42,6 +42,31 @@ impl Flag {
             name: "not_loadable".to_string()
         }
     }
+    pub fn constant() -> Self {
+        Self {
+            name: "const".to_string()
+        }
+    }
+    pub fn pure() -> Self {
+        Self {
+            name: "pure".to_string()
+        }
+    }
+    pub fn internal() -> Self {
+        Self {
+            name: "internal".to_string()
+        }
+    }
+    pub fn inline() -> Self {
+        Self {
+            name: "inline".to_string()
+        }
+    }
+    pub fn noinline() -> Self {
+        Self {
+            name: "noinline".to_string()
+        }
+    }
     pub fn new(name: String) -> Self {
         Self {
             name
@@ -1,12 +1,14 @@
 use crate::function::FunctionType;
+use crate::meta::{Flag, Flags};
 use crate::stringify::Stringify;
 
 
 /// A extern declaration.
-/// Syntax: extern <name> : <type>;
+/// Syntax: extern <name> <flags>: <type>;
 #[derive(Debug, Clone, PartialEq)]
 pub struct External {
     pub name: String,
+    pub flags: Flags,
     pub ty: FunctionType
 }
 
@@ -14,13 +16,28 @@ impl External {
     pub fn new(name: String, ty: FunctionType) -> Self {
         Self {
             name,
+            flags: Flags::new(vec![]),
             ty
         }
     }
+
+    pub fn with_flags(name: String, flags: Flags, ty: FunctionType) -> Self {
+        Self {
+            name,
+            flags,
+            ty
+        }
+    }
+
+    /// Whether the extern is marked `#pure`: it has no side effects and
+    /// calls to it can be removed when their result is unused
+    pub fn is_pure(&self) -> bool {
+        self.flags.contains(&Flag::pure())
+    }
 }
 
 impl Stringify for External {
     fn to_string(&self) -> String {
-        format!("extern {} : {};", self.name, self.ty.print_to_string())
+        format!("extern {} {}: {};", self.name, self.flags.to_string(), self.ty.print_to_string())
     }
 }
@@ -1,12 +1,12 @@
-
-use crate::{MirageObject, stringify::Stringify};
+use crate::{MirageObject, meta::{Flag, Flags}, stringify::Stringify};
 
 
 /// A global variable.
-/// Syntax: global <name> = <value>
+/// Syntax: global <name> <flags>= <value>
 #[derive(Debug, Clone, PartialEq)]
 pub struct Global {
     pub name: String,
+    pub flags: Flags,
     pub value: MirageObject
 }
 
@@ -14,13 +14,27 @@ impl Global {
     pub fn new(name: String, value: MirageObject) -> Self {
         Self {
             name,
+            flags: Flags::new(vec![]),
             value
         }
     }
+
+    pub fn with_flags(name: String, flags: Flags, value: MirageObject) -> Self {
+        Self {
+            name,
+            flags,
+            value
+        }
+    }
+
+    /// Whether the global is marked `#const` and never written
+    pub fn is_const(&self) -> bool {
+        self.flags.contains(&Flag::constant())
+    }
 }
 
 impl Stringify for Global {
     fn to_string(&self) -> String {
-        format!("global {} = {}", self.name.to_string(), self.value.to_string())
+        format!("global {} {}= {}", self.name.to_string(), self.flags.to_string(), self.value.to_string())
     }
 }
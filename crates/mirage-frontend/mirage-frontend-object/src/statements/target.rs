@@ -119,6 +119,7 @@ pub enum Arch {
     X86_64,
     Arm,
     Arm64,
+    RiscV64,
     Unknown,
 }
 
@@ -128,7 +129,8 @@ impl Arch {
             "x86" => Self::X86,
             "x86_64" => Self::X86_64,
             "arm" => Self::Arm,
-            "arm64" => Self::Arm64,
+            "arm64" | "aarch64" => Self::Arm64,
+            "riscv64" => Self::RiscV64,
             _ => Self::Unknown,
         }
     }
@@ -139,6 +141,7 @@ impl Arch {
             Self::X86_64 => "x86_64",
             Self::Arm => "arm",
             Self::Arm64 => "arm64",
+            Self::RiscV64 => "riscv64",
             Self::Unknown => "unknown",
         }
     }
@@ -0,0 +1,11 @@
+use crate::util::to_string_with_special_char;
+
+#[test]
+fn test_special_char() {
+    assert_eq!(to_string_with_special_char("a\\tb\\n"), "a\tb\n");
+    assert_eq!(to_string_with_special_char("\\'\\\"\\0\\r"), "'\"\0\r");
+    // An escaped backslash doesn't start another escape
+    assert_eq!(to_string_with_special_char("a\\\\nb"), "a\\nb");
+    assert_eq!(to_string_with_special_char("\\\\\\n"), "\\\n");
+    assert_eq!(to_string_with_special_char("\\q\\"), "\\q\\");
+}
@@ -85,3 +85,31 @@ impl<T> PartialEq for List<T>
         self.inner == other.inner
     }
 }
+
+/// Replace the escape sequences of a string literal (`\n`, `\t`, `\r`, `\0`,
+/// `\'`, `\"` and `\\`) by the characters they stand for.
+/// The string is read from left to right, so `\\n` is a backslash followed by `n`.
+/// Unknown escape sequences are kept as they are.
+pub fn to_string_with_special_char(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some(c @ ('\'' | '"' | '\\')) => out.push(c),
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
@@ -0,0 +1,202 @@
+use crate::{ParseError, ParseResult};
+
+/// Where a token starts, from 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    /// A type name, after `@`
+    Type(String),
+    Number(String),
+    /// A flag name, after `#`
+    Flag(String),
+    /// The bytes between the quotes, escapes included
+    Str(Vec<u8>),
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Comma,
+    Colon,
+    Semi,
+    Eq,
+    Star,
+    Minus,
+    Arrow,
+    Ellipsis,
+    Eof,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let punct = match self {
+            Token::Ident(name) => return write!(f, "`{}`", name),
+            Token::Type(name) => return write!(f, "`@{}`", name),
+            Token::Number(n) => return write!(f, "`{}`", n),
+            Token::Flag(name) => return write!(f, "`#{}`", name),
+            Token::Str(_) => return write!(f, "a string"),
+            Token::Eof => return write!(f, "the end of the file"),
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Comma => ",",
+            Token::Colon => ":",
+            Token::Semi => ";",
+            Token::Eq => "=",
+            Token::Star => "*",
+            Token::Minus => "-",
+            Token::Arrow => "->",
+            Token::Ellipsis => "...",
+        };
+        write!(f, "`{}`", punct)
+    }
+}
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_' || c == b'.'
+}
+
+fn is_ident(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'.'
+}
+
+/// The tokens of `source`, ending with `Eof`
+pub fn lex(source: &str) -> ParseResult<Vec<(Token, Pos)>> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut line_start = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let pos = Pos {
+            line,
+            column: i - line_start + 1,
+        };
+        if c == b'\n' {
+            i += 1;
+            line += 1;
+            line_start = i;
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let word = |from: usize| {
+            let mut end = from;
+            while end < bytes.len() && is_ident(bytes[end]) {
+                end += 1;
+            }
+            (source[from..end].to_string(), end)
+        };
+        let token = match c {
+            b'(' => Token::LParen,
+            b')' => Token::RParen,
+            b'{' => Token::LBrace,
+            b'}' => Token::RBrace,
+            b'[' => Token::LBracket,
+            b']' => Token::RBracket,
+            b',' => Token::Comma,
+            b':' => Token::Colon,
+            b';' => Token::Semi,
+            b'=' => Token::Eq,
+            b'*' => Token::Star,
+            b'-' if bytes.get(i + 1) == Some(&b'>') => {
+                i += 1;
+                Token::Arrow
+            }
+            b'.' if bytes[i..].starts_with(b"...") => {
+                i += 2;
+                Token::Ellipsis
+            }
+            b'-' | b'0'..=b'9' => {
+                let mut end = i + 1;
+                while end < bytes.len() && bytes[end].is_ascii_digit() {
+                    end += 1;
+                }
+                if c == b'-' && end == i + 1 {
+                    Token::Minus
+                } else {
+                    if bytes.get(end) == Some(&b'.')
+                        && bytes.get(end + 1).is_some_and(u8::is_ascii_digit)
+                    {
+                        end += 1;
+                        while end < bytes.len() && bytes[end].is_ascii_digit() {
+                            end += 1;
+                        }
+                    }
+                    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+                        let mut exp = end + 1;
+                        if matches!(bytes.get(exp), Some(b'+' | b'-')) {
+                            exp += 1;
+                        }
+                        if bytes.get(exp).is_some_and(u8::is_ascii_digit) {
+                            end = exp;
+                            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                                end += 1;
+                            }
+                        }
+                    }
+                    i = end - 1;
+                    Token::Number(source[start..end].to_string())
+                }
+            }
+            b'@' | b'#' => {
+                let (name, end) = word(i + 1);
+                if name.is_empty() {
+                    return Err(ParseError::new(pos, "expected a name"));
+                }
+                i = end - 1;
+                match c {
+                    b'@' => Token::Type(name),
+                    _ => Token::Flag(name),
+                }
+            }
+            b'"' => {
+                let mut end = i + 1;
+                loop {
+                    match bytes.get(end) {
+                        Some(b'"') => break,
+                        Some(b'\\') if end + 1 < bytes.len() && bytes[end + 1] != b'\n' => end += 2,
+                        Some(b'\n') | None => {
+                            return Err(ParseError::new(pos, "unterminated string"))
+                        }
+                        Some(_) => end += 1,
+                    }
+                }
+                let string = bytes[i + 1..end].to_vec();
+                i = end;
+                Token::Str(string)
+            }
+            c if is_ident_start(c) => {
+                let (name, end) = word(i);
+                i = end - 1;
+                Token::Ident(name)
+            }
+            _ => {
+                let c = source[i..].chars().next().unwrap_or_default();
+                return Err(ParseError::new(pos, &format!("unexpected `{}`", c)));
+            }
+        };
+        tokens.push((token, pos));
+        i += 1;
+    }
+    let pos = Pos {
+        line,
+        column: bytes.len() - line_start + 1,
+    };
+    tokens.push((Token::Eof, pos));
+    Ok(tokens)
+}
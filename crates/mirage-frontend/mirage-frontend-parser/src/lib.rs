@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod test;
+
+mod lexer;
+mod parser;
+
+use lexer::Pos;
+use mirage_frontend_object::statements::Statement;
+
+/// An error in the text of a module, at a line and a column counted from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(pos: Pos, message: &str) -> Self {
+        Self {
+            line: pos.line,
+            column: pos.column,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+/// Parse a module from its text, as the statements print it.
+///
+/// The registers aren't typed in the text: an argument has the type of the
+/// function's argument, a global the type of its value, and another
+/// register the type of what its first assignment computes.
+pub fn parse(source: &str) -> ParseResult<Vec<Statement>> {
+    parser::Parser::new(lexer::lex(source)?).module()
+}
@@ -0,0 +1,785 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use mirage_frontend_object::function::{FunctionType, FunctionValue};
+use mirage_frontend_object::label::{Command, Label, LabelBodyInstr, Value};
+use mirage_frontend_object::meta::{Flag, Flags};
+use mirage_frontend_object::statements::{
+    External, Global, ModuleDecl, Statement, Target, TypeDef,
+};
+use mirage_frontend_object::util::List;
+use mirage_frontend_object::{
+    ArrayValue, MirageObject, MirageTypeEnum, MirageValueEnum, PointerValue, RegisterType,
+    RegisterValue, StructValue,
+};
+
+use crate::lexer::{Pos, Token};
+use crate::{ParseError, ParseResult};
+
+type Key = (RegisterType, usize);
+
+const STATEMENTS: [&str; 5] = ["module", "target", "extern", "type", "global"];
+
+/// The kind and the index of a register name, such as `r3` or `arg0`
+fn register_name(name: &str) -> Option<Key> {
+    let (kind, index) = if let Some(index) = name.strip_prefix("arg") {
+        (RegisterType::Argument, index)
+    } else {
+        let kind = RegisterType::try_from(name.get(..1)?).ok()?;
+        (kind, &name[1..])
+    };
+    if index.is_empty() || !index.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((kind, index.parse().ok()?))
+}
+
+/// The type a `getelementptr` of `ty` points to once it steps into it with
+/// the indices after the first
+fn element_type(ty: &MirageTypeEnum, indices: &[Value]) -> Option<MirageTypeEnum> {
+    let mut ty = ty.clone();
+    for index in indices.iter().skip(1) {
+        ty = match ty {
+            MirageTypeEnum::Array(a) => a.element_ty(),
+            MirageTypeEnum::Pointer(p) => *p.element_ty,
+            MirageTypeEnum::Struct(s) => {
+                let Value::ConstValue(index) = index else {
+                    return None;
+                };
+                let field = match index.get_value() {
+                    MirageValueEnum::Int32(v) => v.value as usize,
+                    MirageValueEnum::Int64(v) => v.value as usize,
+                    MirageValueEnum::UInt32(v) => v.value as usize,
+                    MirageValueEnum::UInt64(v) => v.value as usize,
+                    _ => return None,
+                };
+                s.fields.get(field)?.clone()
+            }
+            _ => return None,
+        };
+    }
+    Some(ty)
+}
+
+pub struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    at: usize,
+    typedefs: HashMap<String, Vec<MirageTypeEnum>>,
+    globals: Vec<MirageTypeEnum>,
+    /// The return types of the functions and the externs
+    functions: HashMap<String, MirageTypeEnum>,
+    /// The arguments of the function being parsed
+    args: Vec<MirageTypeEnum>,
+    /// The types of its registers found by the previous pass
+    known: HashMap<Key, MirageTypeEnum>,
+    /// The types of its registers found by this pass
+    found: HashMap<Key, MirageTypeEnum>,
+    assigned: HashSet<Key>,
+    /// Whether a register of unknown type is an error, in the last pass
+    strict: bool,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<(Token, Pos)>) -> Self {
+        Self {
+            tokens,
+            at: 0,
+            typedefs: HashMap::new(),
+            globals: Vec::new(),
+            functions: HashMap::new(),
+            args: Vec::new(),
+            known: HashMap::new(),
+            found: HashMap::new(),
+            assigned: HashSet::new(),
+            strict: false,
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.at].0
+    }
+
+    fn peek_at(&self, n: usize) -> &Token {
+        let at = (self.at + n).min(self.tokens.len() - 1);
+        &self.tokens[at].0
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens[self.at].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token != Token::Eof {
+            self.at += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: &str) -> ParseResult<T> {
+        Err(ParseError::new(self.pos(), message))
+    }
+
+    fn expected<T>(&self, what: &str) -> ParseResult<T> {
+        self.error(&format!("expected {}, found {}", what, self.peek()))
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> ParseResult<()> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            self.expected(&token.to_string())
+        }
+    }
+
+    fn ident(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Token::Ident(name) => {
+                let name = name.clone();
+                self.next();
+                Ok(name)
+            }
+            _ => self.expected("a name"),
+        }
+    }
+
+    fn flags(&mut self) -> Flags {
+        let mut flags = Flags::new(Vec::new());
+        while let Token::Flag(name) = self.peek() {
+            flags.push(Flag::new(name.clone()));
+            self.next();
+        }
+        flags
+    }
+
+    /// Items separated by commas, up to `close`
+    fn separated<T>(
+        &mut self,
+        close: Token,
+        mut item: impl FnMut(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<Vec<T>> {
+        let mut items = Vec::new();
+        if self.eat(&close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if !self.eat(&Token::Comma) {
+                self.expect(close)?;
+                return Ok(items);
+            }
+        }
+    }
+
+    /// Whether a statement starts here, which ends the function before
+    fn at_statement(&self) -> bool {
+        match self.peek() {
+            Token::Eof => true,
+            Token::Ident(name) => {
+                *self.peek_at(1) == Token::LParen
+                    || (STATEMENTS.contains(&name.as_str())
+                        && !matches!(self.peek_at(1), Token::Colon | Token::Flag(_)))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a label starts here: a name, its flags and a colon
+    fn at_label(&self) -> bool {
+        if !matches!(self.peek(), Token::Ident(_)) {
+            return false;
+        }
+        let mut n = 1;
+        while let Token::Flag(_) = self.peek_at(n) {
+            n += 1;
+        }
+        *self.peek_at(n) == Token::Colon
+    }
+
+    /// Whether a register is assigned here
+    fn at_assign(&self) -> bool {
+        let Token::Ident(name) = self.peek() else {
+            return false;
+        };
+        if register_name(name).is_none() {
+            return false;
+        }
+        let mut n = 1;
+        while let Token::Flag(_) = self.peek_at(n) {
+            n += 1;
+        }
+        *self.peek_at(n) == Token::Eq
+    }
+
+    pub fn module(mut self) -> ParseResult<Vec<Statement>> {
+        let mut stmts = Vec::new();
+        let mut bodies = Vec::new();
+        while *self.peek() != Token::Eof {
+            let stmt = self.statement()?;
+            if let Statement::Function(_) = stmt {
+                bodies.push((stmts.len(), self.at));
+                while !self.at_statement() {
+                    self.next();
+                }
+            }
+            stmts.push(stmt);
+        }
+        // The bodies are parsed once every function and global is known
+        for (index, start) in bodies {
+            self.at = start;
+            let Statement::Function(f) = &mut stmts[index] else {
+                unreachable!("only the functions have a body")
+            };
+            for label in self.body(f.get_type().get_args().clone())? {
+                f.add_label(label);
+            }
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> ParseResult<Statement> {
+        let name = match self.peek() {
+            Token::Ident(name) => name.clone(),
+            _ => return self.expected("a statement"),
+        };
+        if *self.peek_at(1) == Token::LParen {
+            self.next();
+            let ty = self.function_type()?;
+            self.functions.insert(name.clone(), ty.get_ret().clone());
+            return Ok(Statement::Function(FunctionValue::new(name, ty)));
+        }
+        self.next();
+        let stmt = match name.as_str() {
+            "module" => Statement::Module(ModuleDecl::new(self.ident()?)),
+            "target" => {
+                let mut target = String::new();
+                while !matches!(self.peek(), Token::Semi | Token::Eof) {
+                    match self.peek() {
+                        Token::Ident(s) | Token::Number(s) => target.push_str(s),
+                        Token::Minus => target.push('-'),
+                        _ => return self.expected("a target"),
+                    }
+                    self.next();
+                }
+                Statement::Target(Target::parse(&target))
+            }
+            "extern" => {
+                let name = self.ident()?;
+                let flags = self.flags();
+                self.expect(Token::Colon)?;
+                let ty = self.function_type()?;
+                self.functions.insert(name.clone(), ty.get_ret().clone());
+                Statement::External(External::with_flags(name, flags, ty))
+            }
+            "type" => {
+                let name = self.ident()?;
+                self.expect(Token::Eq)?;
+                self.expect(Token::LBrace)?;
+                let fields = self.separated(Token::RBrace, Self::ty)?;
+                self.typedefs.insert(name.clone(), fields.clone());
+                Statement::Typedef(TypeDef::new(name, List::from_vec(fields)))
+            }
+            "global" => {
+                let name = self.ident()?;
+                let flags = self.flags();
+                self.expect(Token::Eq)?;
+                let value = self.constant()?;
+                self.globals.push(value.get_type());
+                // A global has no `;`
+                return Ok(Statement::Global(Global::with_flags(name, flags, value)));
+            }
+            _ => {
+                self.at -= 1;
+                return self.expected("a statement");
+            }
+        };
+        self.expect(Token::Semi)?;
+        Ok(stmt)
+    }
+
+    /// `(args) -> ret`, the last argument being `...` if it has var args
+    fn function_type(&mut self) -> ParseResult<FunctionType> {
+        self.expect(Token::LParen)?;
+        let mut var_arg = false;
+        let args = self.separated(Token::RParen, |p| {
+            if var_arg {
+                return p.expected("`)` after `...`");
+            }
+            if p.eat(&Token::Ellipsis) {
+                var_arg = true;
+                return Ok(None);
+            }
+            p.ty().map(Some)
+        })?;
+        self.expect(Token::Arrow)?;
+        let ret = self.ty()?;
+        Ok(FunctionType::new(
+            args.into_iter().flatten().collect(),
+            ret,
+            var_arg,
+        ))
+    }
+
+    fn ty(&mut self) -> ParseResult<MirageTypeEnum> {
+        let mut ty = match self.peek().clone() {
+            Token::Type(name) => match MirageTypeEnum::from_str(&name) {
+                Some(ty) => {
+                    self.next();
+                    ty
+                }
+                None => return self.error(&format!("unknown type `@{}`", name)),
+            },
+            Token::LBracket => {
+                self.next();
+                let length = self.number::<usize>("a length")?;
+                if !matches!(self.peek(), Token::Ident(x) if x == "x") {
+                    return self.expected("`x`");
+                }
+                self.next();
+                let element = self.ty()?;
+                self.expect(Token::RBracket)?;
+                MirageTypeEnum::type_array(element, length).into()
+            }
+            Token::LBrace => {
+                self.next();
+                let fields = self.separated(Token::RBrace, Self::ty)?;
+                MirageTypeEnum::type_struct(fields).into()
+            }
+            _ => return self.expected("a type"),
+        };
+        while self.eat(&Token::Star) {
+            ty = MirageTypeEnum::type_ptr(ty).into();
+        }
+        Ok(ty)
+    }
+
+    /// A number token read as a `T`
+    fn number<T: FromStr>(&mut self, what: &str) -> ParseResult<T> {
+        let pos = self.pos();
+        let text = match self.peek().clone() {
+            Token::Number(text) => text,
+            // The floats which aren't finite
+            Token::Ident(name) if matches!(name.as_str(), "inf" | "NaN") => name,
+            Token::Minus if *self.peek_at(1) == Token::Ident("inf".to_string()) => {
+                self.next();
+                "-inf".to_string()
+            }
+            _ => return self.expected(what),
+        };
+        self.next();
+        text.parse()
+            .map_err(|_| ParseError::new(pos, &format!("`{}` isn't {}", text, what)))
+    }
+
+    /// A typed constant, such as `@int32 5` or `[2 x @int8] [@int8 1, @int8 2]`
+    fn constant(&mut self) -> ParseResult<MirageObject> {
+        if let Token::Str(bytes) = self.peek().clone() {
+            self.next();
+            let ty = MirageTypeEnum::type_array(MirageTypeEnum::type_int8().into(), bytes.len());
+            let chars = bytes
+                .iter()
+                .map(|c| {
+                    MirageTypeEnum::type_int8()
+                        .const_value(*c as i8)
+                        .to_value_enum()
+                })
+                .collect();
+            return Ok(MirageObject::from(ty.const_value(chars).to_mirage_value()));
+        }
+        let ty = self.ty()?;
+        let value = match &ty {
+            MirageTypeEnum::Int8(t) => t.const_value(self.number("an @int8")?).to_value_enum(),
+            MirageTypeEnum::Int16(t) => t.const_value(self.number("an @int16")?).to_value_enum(),
+            MirageTypeEnum::Int32(t) => t.const_value(self.number("an @int32")?).to_value_enum(),
+            MirageTypeEnum::Int64(t) => t.const_value(self.number("an @int64")?).to_value_enum(),
+            MirageTypeEnum::UInt8(t) => t.const_value(self.number("a @uint8")?).to_value_enum(),
+            MirageTypeEnum::UInt16(t) => t.const_value(self.number("a @uint16")?).to_value_enum(),
+            MirageTypeEnum::UInt32(t) => t.const_value(self.number("a @uint32")?).to_value_enum(),
+            MirageTypeEnum::UInt64(t) => t.const_value(self.number("a @uint64")?).to_value_enum(),
+            MirageTypeEnum::Float32(t) => t.const_value(self.number("a @float32")?).to_value_enum(),
+            MirageTypeEnum::Float64(t) => t.const_value(self.number("a @float64")?).to_value_enum(),
+            MirageTypeEnum::Array(a) => {
+                self.expect(Token::LBracket)?;
+                let element = a.element_ty();
+                let values = self.separated(Token::RBracket, |p| p.element(&element))?;
+                if values.len() != a.length() {
+                    return self.error(&format!(
+                        "expected {} elements, found {}",
+                        a.length(),
+                        values.len()
+                    ));
+                }
+                ArrayValue::new(a.clone(), values).into()
+            }
+            MirageTypeEnum::Struct(s) => {
+                self.expect(Token::LBrace)?;
+                let mut fields = s.fields.iter();
+                let values = self.separated(Token::RBrace, |p| match fields.next() {
+                    Some(field) => p.element(field),
+                    None => p.expected("`}`"),
+                })?;
+                if values.len() != s.fields.len() {
+                    return self.error(&format!(
+                        "expected {} fields, found {}",
+                        s.fields.len(),
+                        values.len()
+                    ));
+                }
+                MirageValueEnum::Struct(StructValue::new(s.clone(), values))
+            }
+            // A pointer constant prints as its pointer type and a `*`
+            MirageTypeEnum::Pointer(p) => match &*p.element_ty {
+                MirageTypeEnum::Pointer(inner) => PointerValue::new(inner.clone()).into(),
+                _ => return self.expected("a value"),
+            },
+        };
+        Ok(MirageObject::from(value))
+    }
+
+    /// A constant of an array or a struct, of type `ty`
+    fn element(&mut self, ty: &MirageTypeEnum) -> ParseResult<MirageValueEnum> {
+        let pos = self.pos();
+        let value = self.constant()?;
+        if value.get_type() != *ty {
+            let message = format!("expected a {}", ty.print_to_string());
+            return Err(ParseError::new(pos, &message));
+        }
+        Ok(value.get_value())
+    }
+
+    fn register(&mut self) -> ParseResult<RegisterValue> {
+        let pos = self.pos();
+        let name = self.ident()?;
+        let Some(key) = register_name(&name) else {
+            let message = format!("expected a register, found `{}`", name);
+            return Err(ParseError::new(pos, &message));
+        };
+        let ty = match key.0 {
+            RegisterType::Argument => self.args.get(key.1),
+            RegisterType::Global => self.globals.get(key.1),
+            _ => self.known.get(&key),
+        };
+        let ty = match (ty, key.0) {
+            (Some(ty), _) => ty.clone(),
+            (None, _) if !self.strict => MirageTypeEnum::type_int64().into(),
+            (None, RegisterType::Argument) => {
+                let message = format!("`{}` is past the arguments of the function", name);
+                return Err(ParseError::new(pos, &message));
+            }
+            (None, RegisterType::Global) => {
+                return Err(ParseError::new(pos, &format!("unknown global `{}`", name)))
+            }
+            (None, _) if self.assigned.contains(&key) => {
+                let message = format!("cannot infer the type of `{}`", name);
+                return Err(ParseError::new(pos, &message));
+            }
+            (None, _) => {
+                let message = format!("`{}` is never assigned", name);
+                return Err(ParseError::new(pos, &message));
+            }
+        };
+        let mut register = RegisterValue::new(key.1, key.0, ty);
+        register.flags = self.flags();
+        Ok(register)
+    }
+
+    fn value(&mut self) -> ParseResult<Value> {
+        match self.peek() {
+            Token::Ident(name) if register_name(name).is_some() => {
+                Ok(Value::Register(self.register()?))
+            }
+            _ => Ok(Value::ConstValue(self.constant()?)),
+        }
+    }
+
+    /// Whether a value starts here, rather than the next instruction
+    fn at_value(&self) -> bool {
+        match self.peek() {
+            Token::Ident(name) => register_name(name).is_some() && !self.at_assign(),
+            Token::Type(_) | Token::Str(_) | Token::LBracket | Token::LBrace => true,
+            _ => false,
+        }
+    }
+
+    /// The labels of a function whose arguments are `args`. The body is
+    /// parsed again as long as more registers get a type, then a last time
+    /// with every type known.
+    fn body(&mut self, args: Vec<MirageTypeEnum>) -> ParseResult<Vec<Label>> {
+        let start = self.at;
+        self.args = args;
+        self.known.clear();
+        self.assigned.clear();
+        self.strict = false;
+        loop {
+            self.at = start;
+            self.found.clear();
+            self.labels()?;
+            if self.found.len() == self.known.len() {
+                break;
+            }
+            self.known = std::mem::take(&mut self.found);
+        }
+        self.at = start;
+        self.strict = true;
+        self.labels()
+    }
+
+    fn labels(&mut self) -> ParseResult<Vec<Label>> {
+        let mut labels = Vec::new();
+        while !self.at_statement() {
+            if !self.at_label() {
+                return self.expected("a label");
+            }
+            let name = self.ident()?;
+            let flags = self.flags();
+            self.expect(Token::Colon)?;
+            let mut body = Vec::new();
+            while !self.at_statement() && !self.at_label() {
+                body.push(self.instr()?);
+            }
+            labels.push(Label::new(name, flags, body));
+        }
+        Ok(labels)
+    }
+
+    fn instr(&mut self) -> ParseResult<LabelBodyInstr> {
+        if !self.at_assign() {
+            return self.operation();
+        }
+        let pos = self.pos();
+        let Token::Ident(name) = self.peek().clone() else {
+            unreachable!("an assignment starts with its register")
+        };
+        let key = register_name(&name).expect("an assignment starts with its register");
+        self.assigned.insert(key);
+        let strict = std::mem::replace(&mut self.strict, false);
+        let register = self.register();
+        self.strict = strict;
+        let mut register = register?;
+        self.expect(Token::Eq)?;
+        let instr = self.operation()?;
+        if let Some(ty) = self.result_type(&instr) {
+            self.found.entry(key).or_insert(ty);
+        }
+        if let Some(ty) = self.known.get(&key) {
+            register.ty = ty.clone();
+        } else if self.strict {
+            let message = format!("cannot infer the type of `{}`", name);
+            return Err(ParseError::new(pos, &message));
+        }
+        Ok(LabelBodyInstr::Assign(register, Box::new(instr)))
+    }
+
+    fn operation(&mut self) -> ParseResult<LabelBodyInstr> {
+        let name = match self.peek() {
+            Token::Ident(name) if register_name(name).is_none() => name.clone(),
+            _ if !self.at_value() => return self.expected("an instruction"),
+            _ => {
+                let value = match self.value()? {
+                    Value::ConstValue(value) => value,
+                    Value::Register(r) => MirageObject::from(r.to_mirage_value()),
+                    Value::List(_) => unreachable!("a value isn't parsed as a list"),
+                };
+                return Ok(LabelBodyInstr::Command(Command::Const(value)));
+            }
+        };
+        if *self.peek_at(1) == Token::LBrace {
+            self.next();
+            self.next();
+            let args = self.separated(Token::RBrace, Self::value)?;
+            return Ok(LabelBodyInstr::Call(name, args));
+        }
+        let pos = self.pos();
+        self.next();
+        let binary = |p: &mut Self| -> ParseResult<(Value, Value)> {
+            let lhs = p.value()?;
+            p.expect(Token::Comma)?;
+            Ok((lhs, p.value()?))
+        };
+        let command = match name.as_str() {
+            "store" => {
+                let register = self.register()?;
+                self.expect(Token::Comma)?;
+                Command::Store(register, self.value()?)
+            }
+            "new" => {
+                let name = self.ident()?;
+                self.expect(Token::Comma)?;
+                self.expect(Token::LBrace)?;
+                let values = self.separated(Token::RBrace, Self::value)?;
+                Command::New(name, List::from_vec(values))
+            }
+            "get" => {
+                let register = self.register()?;
+                self.expect(Token::Comma)?;
+                Command::Get(register, self.number("an index")?)
+            }
+            "free" => {
+                let mut registers = Vec::new();
+                if self.at_value() {
+                    registers.push(self.register()?);
+                    while self.eat(&Token::Comma) {
+                        registers.push(self.register()?);
+                    }
+                }
+                Command::Free(registers)
+            }
+            "ret" => Command::Ret(self.value()?),
+            "jump" => Command::Jump(self.ident()?),
+            "jeq" => {
+                let label = self.ident()?;
+                self.expect(Token::Comma)?;
+                let (lhs, rhs) = binary(self)?;
+                Command::Jeq(label, lhs, rhs)
+            }
+            "incr_i8" => Command::IncrInt8(self.register()?),
+            "incr_i16" => Command::IncrInt16(self.register()?),
+            "incr_i32" => Command::IncrInt32(self.register()?),
+            "incr_i64" => Command::IncrInt64(self.register()?),
+            "incr_f32" => Command::IncrFloat32(self.register()?),
+            "incr_f64" => Command::IncrFloat64(self.register()?),
+            "add_i8" | "add_i16" | "add_i32" | "add_i64" | "add_f32" | "add_f64" | "sub_i8"
+            | "sub_i16" | "sub_i32" | "sub_i64" | "sub_f32" | "sub_f64" => {
+                let (lhs, rhs) = binary(self)?;
+                match name.as_str() {
+                    "add_i8" => Command::AddInt8(lhs, rhs),
+                    "add_i16" => Command::AddInt16(lhs, rhs),
+                    "add_i32" => Command::AddInt32(lhs, rhs),
+                    "add_i64" => Command::AddInt64(lhs, rhs),
+                    "add_f32" => Command::AddFloat32(lhs, rhs),
+                    "add_f64" => Command::AddFloat64(lhs, rhs),
+                    "sub_i8" => Command::SubInt8(lhs, rhs),
+                    "sub_i16" => Command::SubInt16(lhs, rhs),
+                    "sub_i32" => Command::SubInt32(lhs, rhs),
+                    "sub_i64" => Command::SubInt64(lhs, rhs),
+                    "sub_f32" => Command::SubFloat32(lhs, rhs),
+                    _ => Command::SubFloat64(lhs, rhs),
+                }
+            }
+            "ref" => Command::Ref(self.value()?),
+            "load" => {
+                let ty = self.ty()?;
+                self.expect(Token::Comma)?;
+                Command::Load(ty, self.value()?)
+            }
+            "getelementptr" => {
+                let ty = self.ty()?;
+                self.expect(Token::Comma)?;
+                let base = self.value()?;
+                self.expect(Token::Comma)?;
+                let mut indices = Vec::new();
+                if self.at_value() {
+                    indices.push(self.value()?);
+                    while self.eat(&Token::Comma) {
+                        indices.push(self.value()?);
+                    }
+                }
+                Command::GetElementPtr(ty, base, indices)
+            }
+            "phi" => {
+                let mut incoming = Vec::new();
+                loop {
+                    self.expect(Token::LBracket)?;
+                    let value = self.value()?;
+                    self.expect(Token::Comma)?;
+                    incoming.push((self.ident()?, value));
+                    self.expect(Token::RBracket)?;
+                    if !self.eat(&Token::Comma) {
+                        break;
+                    }
+                }
+                Command::Phi(incoming)
+            }
+            _ => {
+                let message = format!("unknown instruction `{}`", name);
+                return Err(ParseError::new(pos, &message));
+            }
+        };
+        Ok(LabelBodyInstr::Command(command))
+    }
+
+    /// The type of a value, unless it is a register whose type isn't known
+    /// yet
+    fn value_type(&self, value: &Value) -> Option<MirageTypeEnum> {
+        match value {
+            Value::Register(r) => self.register_type(r),
+            Value::ConstValue(c) => match c.get_value_ref() {
+                MirageValueEnum::Register(r) => self.register_type(r),
+                _ => Some(c.get_type()),
+            },
+            Value::List(_) => None,
+        }
+    }
+
+    fn register_type(&self, register: &RegisterValue) -> Option<MirageTypeEnum> {
+        match register.register_type {
+            RegisterType::Argument => self.args.get(register.index).cloned(),
+            RegisterType::Global => self.globals.get(register.index).cloned(),
+            kind => self.known.get(&(kind, register.index)).cloned(),
+        }
+    }
+
+    /// The type of what an instruction computes
+    fn result_type(&self, instr: &LabelBodyInstr) -> Option<MirageTypeEnum> {
+        let command = match instr {
+            LabelBodyInstr::Call(name, _) => return self.functions.get(name).cloned(),
+            LabelBodyInstr::Command(command) => command,
+            LabelBodyInstr::Assign(..) => return None,
+        };
+        match command {
+            Command::Const(value) => self.value_type(&Value::ConstValue(value.clone())),
+            Command::AddInt8(lhs, rhs)
+            | Command::AddInt16(lhs, rhs)
+            | Command::AddInt32(lhs, rhs)
+            | Command::AddInt64(lhs, rhs)
+            | Command::AddFloat32(lhs, rhs)
+            | Command::AddFloat64(lhs, rhs)
+            | Command::SubInt8(lhs, rhs)
+            | Command::SubInt16(lhs, rhs)
+            | Command::SubInt32(lhs, rhs)
+            | Command::SubInt64(lhs, rhs)
+            | Command::SubFloat32(lhs, rhs)
+            | Command::SubFloat64(lhs, rhs) => {
+                self.value_type(lhs).or_else(|| self.value_type(rhs))
+            }
+            Command::IncrInt8(r)
+            | Command::IncrInt16(r)
+            | Command::IncrInt32(r)
+            | Command::IncrInt64(r)
+            | Command::IncrFloat32(r)
+            | Command::IncrFloat64(r) => self.register_type(r),
+            Command::Ref(value) => Some(MirageTypeEnum::type_ptr(self.value_type(value)?).into()),
+            Command::Load(ty, _) => Some(ty.clone()),
+            Command::GetElementPtr(ty, _, indices) => {
+                Some(MirageTypeEnum::type_ptr(element_type(ty, indices)?).into())
+            }
+            Command::New(name, _) => {
+                let fields = self.typedefs.get(name)?.clone();
+                Some(MirageTypeEnum::type_struct(fields).into())
+            }
+            Command::Get(r, index) => match self.register_type(r)? {
+                MirageTypeEnum::Struct(s) => s.fields.get(*index).cloned(),
+                MirageTypeEnum::Pointer(p) => match *p.element_ty {
+                    MirageTypeEnum::Struct(s) => s.fields.get(*index).cloned(),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Command::Phi(incoming) => incoming.iter().find_map(|(_, v)| self.value_type(v)),
+            Command::Store(..)
+            | Command::Free(_)
+            | Command::Ret(_)
+            | Command::Jump(_)
+            | Command::Jeq(..) => None,
+        }
+    }
+}
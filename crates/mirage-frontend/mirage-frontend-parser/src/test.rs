@@ -0,0 +1,188 @@
+use mirage_frontend_object::label::{Command, LabelBodyInstr, Value};
+use mirage_frontend_object::statements::Statement;
+use mirage_frontend_object::stringify::Stringify;
+use mirage_frontend_object::{MirageTypeEnum, MirageValueEnum, RegisterValue};
+
+use crate::{parse, ParseError};
+
+const DEMO: &str = "module demo;\n\
+    target linux-x86_64-gcc;\n\
+    extern printf : (@int8*, ...) -> @int32;\n\
+    extern abs #pure : (@int32) -> @int32;\n\
+    type pair = {@int32,@float64};\n\
+    global g0 = @int64 -3\n\
+    global table #const = [3 x @uint16] [@uint16 1, @uint16 2, @uint16 65535]\n\
+    sum(@int32, @int32) -> @int32\n\
+    entry#inline : \n\
+    \tr0 = add_i32 arg0, arg1\n\
+    \tret r0\n\
+    main() -> @int32\n\
+    entry: \n\
+    \tr0 = new pair, {@int32 4,@float64 -1.5}\n\
+    \tr1 = get r0, 1\n\
+    \tr2 = sum { @int32 1, @int32 2 }\n\
+    \tr3 = ref g0\n\
+    \tstore r3, @int64 7\n\
+    \tr4 = load @int64, r3\n\
+    \tr5 = getelementptr [3 x @uint16], g1, @int64 0,@int64 2\n\
+    \tprintf { [4 x @int8] [@int8 37, @int8 100, @int8 92, @int8 110], r2 }\n\
+    \tjump head\n\
+    head: \n\
+    \tr6 = phi [@int32 0, entry], [r7, body]\n\
+    \tjeq exit, r6, @int32 3\n\
+    body: \n\
+    \tr7 = incr_i32 r6\n\
+    \tjump head\n\
+    exit: \n\
+    \tfree r0\n\
+    \tret r6";
+
+fn print(stmts: &[Statement]) -> String {
+    stmts
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn assigned(stmts: &[Statement], function: &str) -> Vec<RegisterValue> {
+    let f = stmts
+        .iter()
+        .find_map(|s| match s {
+            Statement::Function(f) if f.get_name() == function => Some(f),
+            _ => None,
+        })
+        .unwrap();
+    f.get_labels()
+        .iter()
+        .flat_map(|l| l.body.iter())
+        .filter_map(|instr| match instr {
+            LabelBodyInstr::Assign(r, _) => Some(r.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn error(source: &str) -> String {
+    parse(source).unwrap_err().to_string()
+}
+
+#[test]
+fn test_round_trip() {
+    let stmts = parse(DEMO).unwrap();
+    assert_eq!(stmts.len(), 9);
+    assert_eq!(print(&stmts), DEMO);
+    match &stmts[2] {
+        Statement::External(printf) => assert!(printf.ty.is_var_arg()),
+        stmt => panic!("{:?}", stmt),
+    }
+}
+
+#[test]
+fn test_register_types() {
+    let stmts = parse(DEMO).unwrap();
+    let int32: MirageTypeEnum = MirageTypeEnum::type_int32().into();
+    let int64: MirageTypeEnum = MirageTypeEnum::type_int64().into();
+    let types: Vec<_> = assigned(&stmts, "main").into_iter().map(|r| r.ty).collect();
+    assert_eq!(
+        types,
+        [
+            MirageTypeEnum::type_struct(vec![int32.clone(), MirageTypeEnum::type_float64().into()])
+                .into(),
+            MirageTypeEnum::type_float64().into(),
+            int32.clone(),
+            MirageTypeEnum::type_ptr(int64.clone()).into(),
+            int64,
+            MirageTypeEnum::type_ptr(MirageTypeEnum::type_uint16().into()).into(),
+            // The phi gets its type from its constant, then the increment
+            // from the phi
+            int32.clone(),
+            int32.clone(),
+        ]
+    );
+    assert_eq!(assigned(&stmts, "sum")[0].ty, int32);
+}
+
+#[test]
+fn test_string() {
+    let stmts = parse(
+        "extern puts : (@int8*) -> @int32;\n\
+         main() -> @int32\n\
+         entry:\n\
+         \tputs { \"hi\\n\" }\n\
+         \tret @int32 0",
+    )
+    .unwrap();
+    let Statement::Function(main) = &stmts[1] else {
+        panic!("{:?}", stmts)
+    };
+    match &main.get_labels()[0].body[0] {
+        LabelBodyInstr::Call(name, args) => {
+            assert_eq!(name, "puts");
+            match &args[..] {
+                [Value::ConstValue(s)] => assert_eq!(
+                    s.get_value(),
+                    MirageTypeEnum::type_array(MirageTypeEnum::type_int8().into(), 4)
+                        .const_value(
+                            b"hi\\n"
+                                .iter()
+                                .map(|c| MirageTypeEnum::type_int8()
+                                    .const_value(*c as i8)
+                                    .to_value_enum())
+                                .collect()
+                        )
+                        .to_mirage_value()
+                ),
+                args => panic!("{:?}", args),
+            }
+        }
+        instr => panic!("{:?}", instr),
+    }
+    assert!(matches!(
+        &main.get_labels()[0].body[1],
+        LabelBodyInstr::Command(Command::Ret(Value::ConstValue(v)))
+            if v.get_value() == MirageValueEnum::Int32(MirageTypeEnum::type_int32().const_value(0))
+    ));
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(
+        parse("main() -> @int32\nentry: \n\tret r3").unwrap_err(),
+        ParseError {
+            line: 3,
+            column: 6,
+            message: "`r3` is never assigned".to_string()
+        }
+    );
+    assert_eq!(
+        error("main() -> @int32\nentry: \n\tr0 = mul_i32 @int32 1, @int32 2"),
+        "3:7: unknown instruction `mul_i32`"
+    );
+    assert_eq!(
+        error("f(@int32) -> @int32\nentry: \n\tret arg1"),
+        "3:6: `arg1` is past the arguments of the function"
+    );
+    assert_eq!(
+        error("main() -> @int32\nentry: \n\tr0 = phi [r0, entry]\n\tret r0"),
+        "3:12: cannot infer the type of `r0`"
+    );
+    assert_eq!(
+        error("global g0 = @int128 1"),
+        "1:13: unknown type `@int128`"
+    );
+    assert_eq!(error("global g0 = @int8 300"), "1:19: `300` isn't an @int8");
+    assert_eq!(
+        error("global g0 = [2 x @int8] [@int8 1]"),
+        "1:34: expected 2 elements, found 1"
+    );
+    assert_eq!(
+        error("extern puts : (@int8*) -> @int32"),
+        "1:33: expected `;`, found the end of the file"
+    );
+    assert_eq!(
+        error("main() -> @int32\n\tret @int32 0"),
+        "2:2: expected a label, found `ret`"
+    );
+    assert_eq!(error("global s = \"abc"), "1:12: unterminated string");
+}
@@ -1,3 +1,5 @@
 pub mod builder;
+pub mod config;
 pub mod object;
 pub mod module;
+pub mod parser;
@@ -0,0 +1 @@
+pub use mirage_frontend_parser::*;
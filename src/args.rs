@@ -0,0 +1,189 @@
+use mirage::frontend::config::{Config, Emit, OptimizationLevel};
+use mirage::frontend::object::statements::{Arch, Os, TargetType};
+
+pub const USAGE: &str = "\
+Usage: mirage <command> [options] <file>
+
+Commands:
+    check <file>...     Parse and verify modules
+    build <file>        Compile a module
+    run <file> [-- <arg>...]
+                        Compile a module with the JIT and call its `main`
+    opt <file>          Print a module after the Mirage passes
+
+Options:
+    -o <path>           Write the output to <path>, `-` for the standard output
+    -O0 -O1 -O2 -O3 -Os -Oz
+                        The optimization level, -O2 by default
+    --target <os>-<arch>-<compiler>
+                        Replace the target of the module (build)
+    --emit <kind>       llvm-ir, asm, obj, exe or wasm, exe by default (build)
+    --stats             Print the statistics of the passes to the standard
+                        error (opt)
+    -h, --help          Print this message
+    -V, --version       Print the version
+
+A file named `-` is read from the standard input.
+
+Exit status: 0 on success, 1 when a module has errors or can't be
+compiled, 2 on a usage error. `run` exits with what `main` returns.";
+
+/// A command of the driver
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Parse and verify every file
+    Check(Vec<String>),
+    /// Compile a file to the `emit` of the config
+    Build(String),
+    /// Compile a file with the JIT and call its `main` with the arguments
+    Run(String, Vec<String>),
+    /// Print a file after the Mirage passes
+    Opt {
+        file: String,
+        stats: bool,
+    },
+    Help,
+    Version,
+}
+
+pub struct Args {
+    pub command: Command,
+    pub config: Config,
+}
+
+/// A command line which doesn't make sense
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageError(pub String);
+
+impl std::fmt::Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+fn usage<T>(message: String) -> Result<T, UsageError> {
+    Err(UsageError(message))
+}
+
+fn optimization_level(flag: &str) -> Result<OptimizationLevel, UsageError> {
+    Ok(match flag {
+        "-O0" => OptimizationLevel::None,
+        "-O1" => OptimizationLevel::Less,
+        "-O2" => OptimizationLevel::Default,
+        "-O3" => OptimizationLevel::Big,
+        "-Os" => OptimizationLevel::Size,
+        "-Oz" => OptimizationLevel::MinSize,
+        _ => return usage(format!("unknown optimization level `{}`", flag)),
+    })
+}
+
+fn emit(kind: &str) -> Result<Emit, UsageError> {
+    Ok(match kind {
+        "llvm-ir" => Emit::LlvmIr,
+        "asm" => Emit::Asm,
+        "obj" => Emit::Obj,
+        "exe" => Emit::Exe,
+        "wasm" => Emit::Wasm,
+        _ => return usage(format!("unknown output kind `{}`", kind)),
+    })
+}
+
+fn target(triple: &str) -> Result<String, UsageError> {
+    let target = TargetType::parse(triple);
+    if target.os == Os::Unknown || target.arch == Arch::Unknown {
+        return usage(format!("unknown target `{}`", triple));
+    }
+    Ok(triple.to_string())
+}
+
+/// Parse the arguments of the driver, without the name of the program
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, UsageError> {
+    let mut args = args.into_iter();
+    let mut config = Config::default();
+    let name = match args.next() {
+        Some(name) => name,
+        None => return usage("missing a command".to_string()),
+    };
+    match name.as_str() {
+        "-h" | "--help" | "help" => {
+            return Ok(Args {
+                command: Command::Help,
+                config,
+            })
+        }
+        "-V" | "--version" => {
+            return Ok(Args {
+                command: Command::Version,
+                config,
+            })
+        }
+        "check" | "build" | "run" | "opt" => {}
+        _ => return usage(format!("unknown command `{}`", name)),
+    }
+
+    let mut files = Vec::new();
+    let mut program_args = Vec::new();
+    let mut stats = false;
+    while let Some(arg) = args.next() {
+        let (option, inline) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option, Some(value)),
+            _ => (arg.as_str(), None),
+        };
+        let accepted = match option {
+            "-o" => ["build", "opt"].as_slice(),
+            "--target" | "--emit" => &["build"],
+            "--stats" => &["opt"],
+            "--" => &["run"],
+            _ if option.starts_with("-O") => &["build", "run", "opt"],
+            _ => &["check", "build", "run", "opt"],
+        };
+        if !accepted.contains(&name.as_str()) {
+            return usage(format!("`{}` doesn't take `{}`", name, option));
+        }
+        let mut value = || match inline {
+            Some(value) => Ok(value.to_string()),
+            None => args
+                .next()
+                .map_or_else(|| usage(format!("`{}` needs a value", option)), Ok),
+        };
+        match option {
+            "-o" => config.output = Some(value()?),
+            "--target" => config.target = Some(target(&value()?)?),
+            "--emit" => config.emit = emit(&value()?)?,
+            "--stats" => stats = true,
+            "--" => {
+                program_args.extend(args.by_ref());
+                break;
+            }
+            "-" => files.push(arg),
+            _ if option.starts_with("-O") => {
+                config.optimization_level = optimization_level(option)?
+            }
+            _ if option.starts_with('-') => return usage(format!("unknown option `{}`", option)),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        return usage(format!("`{}` needs a file", name));
+    }
+    if name != "check" && files.len() > 1 {
+        return usage(format!("`{}` takes a single file", name));
+    }
+    if config.output.as_deref() == Some("-") && config.emit == Emit::Exe && name == "build" {
+        return usage("cannot write an executable to the standard output".to_string());
+    }
+
+    let command = match name.as_str() {
+        "check" => Command::Check(files),
+        "build" => Command::Build(files.remove(0)),
+        "run" => Command::Run(files.remove(0), program_args),
+        _ => Command::Opt {
+            file: files.remove(0),
+            stats,
+        },
+    };
+    Ok(Args { command, config })
+}
@@ -0,0 +1,355 @@
+use std::ffi::CString;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::Command as Process;
+
+use mirage::backend::codegen_asm::{CodeGen, NativeProgram};
+use mirage::backend::codegen_llvm::{Compiler as LlvmCompiler, CompilerError, JitSession};
+use mirage::backend::codegen_wasm::Compiler as WasmCompiler;
+use mirage::backend::opti::verify::verify_module;
+use mirage::backend::opti::{optimize, OptiLevel, PassManager, MAX_ITERATIONS};
+use mirage::backend::output::ExecutionEngineOutput;
+use mirage::frontend::config::{Config, Emit};
+use mirage::frontend::module::Module;
+use mirage::frontend::object::statements::{ModuleDecl, Statement, Target, TargetType};
+use mirage::frontend::object::stringify::Stringify;
+use mirage::frontend::parser::{parse, ParseError};
+
+use crate::args::{Args, Command, USAGE};
+
+/// Why a command failed, printed as a diagnostic
+/// # Variants
+/// * `Io` - A file couldn't be read or written
+/// * `Parse` - The text of a file isn't a module
+/// * `Verify` - A module is malformed
+/// * `Compile` - A backend couldn't compile a module
+#[derive(Debug)]
+pub enum Error {
+    Io(String, std::io::Error),
+    Parse(String, ParseError),
+    Verify(String, String),
+    Compile(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(path, err) => write!(f, "mirage: error: {}: {}", path, err),
+            Error::Parse(path, err) => write!(
+                f,
+                "{}:{}:{}: error: {}",
+                path, err.line, err.column, err.message
+            ),
+            Error::Verify(path, message) => write!(f, "{}: error: {}", path, message),
+            Error::Compile(message) => write!(f, "mirage: error: {}", message),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The name of a file in diagnostics
+fn display(path: &str) -> &str {
+    if path == "-" {
+        "<stdin>"
+    } else {
+        path
+    }
+}
+
+fn read(path: &str) -> Result<String> {
+    let mut source = String::new();
+    let result = if path == "-" {
+        std::io::stdin().read_to_string(&mut source).map(|_| ())
+    } else {
+        std::fs::read_to_string(path).map(|text| source = text)
+    };
+    result.map_err(|err| Error::Io(display(path).to_string(), err))?;
+    Ok(source)
+}
+
+fn write(path: &str, bytes: &[u8]) -> Result<()> {
+    let result = if path == "-" {
+        std::io::stdout().write_all(bytes)
+    } else {
+        std::fs::write(path, bytes)
+    };
+    let name = if path == "-" { "<stdout>" } else { path };
+    result.map_err(|err| Error::Io(name.to_string(), err))
+}
+
+/// Parse and verify a file
+fn load(path: &str) -> Result<Vec<Statement>> {
+    let name = display(path);
+    let stmts = parse(&read(path)?).map_err(|err| Error::Parse(name.to_string(), err))?;
+    if let Err(errors) = verify_module(&stmts) {
+        let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        return Err(Error::Verify(
+            name.to_string(),
+            messages.join(&format!("\n{}: error: ", name)),
+        ));
+    }
+    Ok(stmts)
+}
+
+/// The text of a module, as `parse` reads it
+fn print(stmts: &[Statement]) -> String {
+    let mut text: Vec<String> = stmts.iter().map(|stmt| stmt.to_string()).collect();
+    text.push(String::new());
+    text.join("\n")
+}
+
+/// The name of the module, or of its file
+fn module_name(stmts: &[Statement], path: &str) -> String {
+    match stmts.first() {
+        Some(Statement::Module(module)) => module.name.clone(),
+        _ if path == "-" => "main".to_string(),
+        _ => Path::new(path)
+            .file_stem()
+            .map_or("main".to_string(), |stem| {
+                stem.to_string_lossy().into_owned()
+            }),
+    }
+}
+
+/// Replace the target of the module by `triple`
+fn retarget(stmts: &mut Vec<Statement>, triple: &str) {
+    stmts.retain(|stmt| !stmt.is_target());
+    let at = usize::from(matches!(stmts.first(), Some(Statement::Module(_))));
+    stmts.insert(at, Statement::Target(Target(TargetType::parse(triple))));
+}
+
+fn llvm_error(err: CompilerError) -> Error {
+    Error::Compile(match err {
+        CompilerError::InvalidStatement => "invalid statement".to_string(),
+        CompilerError::ModuleDeclMissing => "missing a module declaration".to_string(),
+        CompilerError::TargetMissing => "LLVM can't compile for the target".to_string(),
+        CompilerError::PassPipeline(message) => message,
+        CompilerError::Unsupported(what) => format!("`{}` can't be compiled with LLVM", what),
+    })
+}
+
+/// Compile a module with LLVM, which needs it to start with its declaration,
+/// for `target` or the host
+fn llvm(
+    mut stmts: Vec<Statement>,
+    name: &str,
+    level: OptiLevel,
+    target: Option<&str>,
+) -> Result<LlvmCompiler> {
+    if !matches!(stmts.first(), Some(Statement::Module(_))) {
+        stmts.insert(0, Statement::Module(ModuleDecl::new(name.to_string())));
+    }
+    let mut compiler = LlvmCompiler::new(stmts, false).map_err(llvm_error)?;
+    compiler.set_opti_level(level);
+    if let Some(triple) = target {
+        compiler.set_target(&TargetType::parse(triple));
+    }
+    compiler.compile().map_err(llvm_error)?;
+    Ok(compiler)
+}
+
+fn native(stmts: Vec<Statement>, name: &str) -> Result<CodeGen> {
+    let mut codegen = CodeGen::new(stmts, Module::new(name.to_string()));
+    codegen
+        .compile()
+        .map_err(|err| Error::Compile(err.to_string()))?;
+    Ok(codegen)
+}
+
+/// Link an object or an assembly file into an executable with the C
+/// compiler of `$CC`, `cc` by default
+fn link(input: &Path, output: &str) -> Result<()> {
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Process::new(&cc)
+        .arg(input)
+        .arg("-o")
+        .arg(output)
+        .status()
+        .map_err(|err| Error::Compile(format!("cannot run `{}`: {}", cc, err)))?;
+    if !status.success() {
+        return Err(Error::Compile(format!(
+            "`{}` failed to link {}",
+            cc, output
+        )));
+    }
+    Ok(())
+}
+
+fn build(file: &str, config: &Config) -> Result<()> {
+    let mut stmts = load(file)?;
+    if let Some(triple) = &config.target {
+        retarget(&mut stmts, triple);
+    }
+    let level = OptiLevel::from(config.optimization_level);
+    let stmts = optimize(level, stmts);
+    let name = module_name(&stmts, file);
+    let output = config.output.clone().unwrap_or_else(|| match config.emit {
+        Emit::LlvmIr => format!("{}.ll", name),
+        Emit::Asm => format!("{}.s", name),
+        Emit::Obj => format!("{}.o", name),
+        Emit::Exe => name.clone(),
+        Emit::Wasm => format!("{}.wasm", name),
+    });
+
+    match config.emit {
+        Emit::LlvmIr => write(
+            &output,
+            llvm(stmts, &name, level, config.target.as_deref())?
+                .print_to_string()
+                .as_bytes(),
+        ),
+        Emit::Asm => write(&output, native(stmts, &name)?.emit_asm().as_bytes()),
+        Emit::Obj => {
+            let object = native(stmts, &name)?
+                .emit_object()
+                .map_err(|err| Error::Compile(err.to_string()))?;
+            write(&output, &object)
+        }
+        Emit::Exe => {
+            let codegen = native(stmts, &name)?;
+            // x86-64 is encoded without an assembler, the other
+            // architectures are assembled by the C compiler
+            let (extension, bytes) = match codegen.program() {
+                NativeProgram::X86_64(_) => (
+                    "o",
+                    codegen
+                        .emit_object()
+                        .map_err(|err| Error::Compile(err.to_string()))?,
+                ),
+                _ => ("s", codegen.emit_asm().into_bytes()),
+            };
+            let input = std::env::temp_dir().join(format!(
+                "mirage-{}-{}.{}",
+                std::process::id(),
+                name,
+                extension
+            ));
+            write(&input.to_string_lossy(), &bytes)?;
+            let linked = link(&input, &output);
+            let _ = std::fs::remove_file(&input);
+            linked
+        }
+        Emit::Wasm => {
+            let mut compiler = WasmCompiler::new(stmts);
+            compiler
+                .compile()
+                .map_err(|err| Error::Compile(err.to_string()))?;
+            write(&output, &compiler.emit_wasm())
+        }
+    }
+}
+
+/// Call the `main` of a file with the JIT and return what it returns
+fn run(file: &str, args: &[String], config: &Config) -> Result<i32> {
+    let level = OptiLevel::from(config.optimization_level);
+    let stmts = optimize(level, load(file)?);
+    let name = module_name(&stmts, file);
+    let compiler = llvm(stmts, &name, level, None)?;
+    let mut session = JitSession::new(&compiler);
+    let jit = |err: mirage::backend::output::jit::JitError| Error::Compile(err.to_string());
+    // `session` outlives the calls to `main`
+    match session.function_type("main").map(|ty| ty.get_args().len()) {
+        None => Err(Error::Compile(format!("{} has no `main`", display(file)))),
+        Some(0) => {
+            let main =
+                unsafe { session.get_function::<extern "C" fn() -> i32>("main") }.map_err(jit)?;
+            Ok(main())
+        }
+        Some(_) => {
+            let main = unsafe {
+                session.get_function::<extern "C" fn(i32, *const *const i8) -> i32>("main")
+            }
+            .map_err(jit)?;
+            let args = std::iter::once(file)
+                .chain(args.iter().map(String::as_str))
+                .map(|arg| {
+                    CString::new(arg).map_err(|_| {
+                        Error::Compile(format!("argument `{}` contains a NUL byte", arg))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut argv: Vec<*const i8> = args.iter().map(|arg| arg.as_ptr()).collect();
+            argv.push(std::ptr::null());
+            Ok(main(args.len() as i32, argv.as_ptr()))
+        }
+    }
+}
+
+fn opt(file: &str, stats: bool, config: &Config) -> Result<()> {
+    let mut stmts = load(file)?;
+    let mut pm = PassManager::for_level(config.optimization_level.into());
+    if !pm.is_empty() {
+        pm.run_to_fixpoint(&mut stmts, MAX_ITERATIONS);
+    }
+    write(
+        config.output.as_deref().unwrap_or("-"),
+        print(&stmts).as_bytes(),
+    )?;
+    if stats {
+        eprint!("{}", pm.report());
+    }
+    Ok(())
+}
+
+/// The message of a panic, from its payload
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "unknown panic".to_string()),
+    }
+}
+
+/// Run a command, print its diagnostics and return the exit status. A
+/// panic of a backend is reported as an internal error, with the status of
+/// any other error.
+pub fn execute(args: Args) -> i32 {
+    std::panic::set_hook(Box::new(|_| {}));
+    let status = std::panic::catch_unwind(|| dispatch(args));
+    let _ = std::panic::take_hook();
+    status.unwrap_or_else(|payload| {
+        eprintln!(
+            "mirage: error: internal compiler error: {}",
+            panic_message(payload.as_ref())
+        );
+        1
+    })
+}
+
+fn dispatch(args: Args) -> i32 {
+    let Args { command, config } = args;
+    let result = match command {
+        Command::Help => write("-", format!("{}\n", USAGE).as_bytes()),
+        Command::Version => write(
+            "-",
+            format!("mirage {}\n", env!("CARGO_PKG_VERSION")).as_bytes(),
+        ),
+        Command::Check(files) => {
+            // Every file is checked, even after an error
+            let mut status = 0;
+            for file in files {
+                if let Err(err) = load(&file) {
+                    eprintln!("{}", err);
+                    status = 1;
+                }
+            }
+            return status;
+        }
+        Command::Build(file) => build(&file, &config),
+        Command::Run(file, args) => match run(&file, &args, &config) {
+            Ok(status) => return status,
+            Err(err) => Err(err),
+        },
+        Command::Opt { file, stats } => opt(&file, stats, &config),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
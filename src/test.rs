@@ -0,0 +1,72 @@
+use mirage::frontend::config::{Emit, OptimizationLevel};
+
+use crate::args::{parse, Args, Command, UsageError};
+
+fn args(line: &str) -> Result<Args, UsageError> {
+    parse(line.split_whitespace().map(String::from))
+}
+
+fn error(line: &str) -> String {
+    match args(line) {
+        Ok(args) => panic!("{:?}", args.command),
+        Err(err) => err.to_string(),
+    }
+}
+
+#[test]
+fn test_commands() {
+    assert_eq!(
+        args("check a.mir b.mir").unwrap().command,
+        Command::Check(vec!["a.mir".to_string(), "b.mir".to_string()])
+    );
+    assert_eq!(
+        args("run - -- x --y").unwrap().command,
+        Command::Run("-".to_string(), vec!["x".to_string(), "--y".to_string()])
+    );
+    assert_eq!(
+        args("opt --stats a.mir").unwrap().command,
+        Command::Opt {
+            file: "a.mir".to_string(),
+            stats: true
+        }
+    );
+    assert_eq!(args("--help").unwrap().command, Command::Help);
+    assert_eq!(args("-V").unwrap().command, Command::Version);
+}
+
+#[test]
+fn test_config() {
+    let args = args("build -o out --target linux-arm64-gcc -Oz --emit=asm a.mir").unwrap();
+    assert_eq!(args.command, Command::Build("a.mir".to_string()));
+    assert_eq!(args.config.output.as_deref(), Some("out"));
+    assert_eq!(args.config.target.as_deref(), Some("linux-arm64-gcc"));
+    assert_eq!(args.config.optimization_level, OptimizationLevel::MinSize);
+    assert_eq!(args.config.emit, Emit::Asm);
+
+    let args = self::args("build a.mir").unwrap();
+    assert_eq!(args.config.optimization_level, OptimizationLevel::Default);
+    assert_eq!(args.config.emit, Emit::Exe);
+    assert_eq!(args.config.output, None);
+}
+
+#[test]
+fn test_usage_errors() {
+    assert_eq!(error(""), "missing a command");
+    assert_eq!(error("compile a.mir"), "unknown command `compile`");
+    assert_eq!(error("check"), "`check` needs a file");
+    assert_eq!(error("build a.mir b.mir"), "`build` takes a single file");
+    assert_eq!(error("check -O2 a.mir"), "`check` doesn't take `-O2`");
+    assert_eq!(error("run --emit asm a.mir"), "`run` doesn't take `--emit`");
+    assert_eq!(error("build -O4 a.mir"), "unknown optimization level `-O4`");
+    assert_eq!(error("build --emit=bc a.mir"), "unknown output kind `bc`");
+    assert_eq!(
+        error("build --target sparc a.mir"),
+        "unknown target `sparc`"
+    );
+    assert_eq!(error("build a.mir -o"), "`-o` needs a value");
+    assert_eq!(error("opt --verbose a.mir"), "unknown option `--verbose`");
+    assert_eq!(
+        error("build -o - a.mir"),
+        "cannot write an executable to the standard output"
+    );
+}